@@ -17,9 +17,13 @@
 // limitations under the License.
 
 use crate::types::ConfigImpl;
-use crate::{Config, ConfigOption, ConfigOption::*, ConfigOptionName as CN};
+use crate::{ByteSizeVal, Config, ConfigDiffEntry, ConfigHistory, ConfigOption, ConfigOption::*};
+use crate::{ConfigOptionName as CN, ConfigSnapshot, DurationVal};
 use bmw_err::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 // macro to simplify the process of checking the parameters
 macro_rules! cc {
@@ -32,9 +36,9 @@ macro_rules! cc {
 }
 
 macro_rules! multi {
-	($opt:ident, $name:expr, $ret:expr, $config:expr) => {
+	($opt:ident $(| $alt:ident)?, $name:expr, $ret:expr, $config:expr) => {
 		match $config {
-			ConfigOption::$opt(_) => {
+			ConfigOption::$opt(_) $(| ConfigOption::$alt(_))? => {
 				if $name == &CN::$opt {
 					$ret.push($config.clone());
 				}
@@ -54,7 +58,7 @@ impl Config for ConfigImpl {
 		let mut ret = vec![];
 		for config in &self.configs {
 			multi!(HttpHeader, name, ret, config);
-			multi!(FileHeader, name, ret, config);
+			multi!(FileHeader | FileHeaderStr, name, ret, config);
 		}
 
 		ret
@@ -73,6 +77,8 @@ impl Config for ConfigImpl {
 				ConfigOption::DisplayStdout(v) => *v,
 				ConfigOption::DisplayTimestamp(v) => *v,
 				ConfigOption::GlobalSlabAllocator(v) => *v,
+				ConfigOption::OverflowToHeap(v) => *v,
+				ConfigOption::DeterministicHashing(v) => *v,
 				ConfigOption::IsCaseSensitive(v) => *v,
 				ConfigOption::IsTerminationPattern(v) => *v,
 				ConfigOption::IsMultiLine(v) => *v,
@@ -84,6 +90,10 @@ impl Config for ConfigImpl {
 				ConfigOption::Debug(v) => *v,
 				ConfigOption::IsSync(v) => *v,
 				ConfigOption::DebugLargeSlabCount(v) => *v,
+				ConfigOption::EvhProxyProtocol(v) => *v,
+				ConfigOption::EvhStrictSockOpts(v) => *v,
+				ConfigOption::EvhStrictOrdering(v) => *v,
+				ConfigOption::EvhStrictKeys(v) => *v,
 				_ => default,
 			},
 			None => default,
@@ -96,6 +106,10 @@ impl Config for ConfigImpl {
 				ConfigOption::MaxEntries(v) => *v,
 				ConfigOption::SlabSize(v) => *v,
 				ConfigOption::SlabCount(v) => *v,
+				ConfigOption::Shards(v) => *v,
+				ConfigOption::BloomBits(v) => *v,
+				ConfigOption::BloomHashes(v) => *v,
+				ConfigOption::ContiguousThresholdBytes(v) => *v,
 				ConfigOption::MinSize(v) => *v,
 				ConfigOption::MaxSize(v) => *v,
 				ConfigOption::SyncChannelSize(v) => *v,
@@ -108,8 +122,21 @@ impl Config for ConfigImpl {
 				ConfigOption::EvhThreads(v) => *v,
 				ConfigOption::EvhHouseKeeperFrequencyMillis(v) => *v,
 				ConfigOption::EvhStatsUpdateMillis(v) => *v,
+				ConfigOption::EvhProxyHeaderTimeoutMillis(v) => *v,
+				ConfigOption::EvhDeferAcceptSecs(v) => *v,
+				ConfigOption::EvhRequireDataWithinMillis(v) => *v,
+				ConfigOption::EvhMaxWriteBytesPerLoop(v) => *v,
+				ConfigOption::EvhFdWarnThresholdPercent(v) => *v,
+				ConfigOption::EvhCallbackTimeoutMillis(v) => *v,
+				ConfigOption::EvhRecvBufferSize(v) => *v,
+				ConfigOption::EvhSendBufferSize(v) => *v,
+				ConfigOption::EvhMaxSlabsPerConnection(v) => *v,
+				ConfigOption::EvhStartTimeoutMillis(v) => *v,
+				ConfigOption::EvhOnAcceptBudgetMicros(v) => *v,
+				ConfigOption::EvhMemoryBudgetBytes(v) => *v,
 				ConfigOption::EvhReadSlabSize(v) => *v,
 				ConfigOption::EvhReadSlabCount(v) => *v,
+				ConfigOption::EvhReadSlabCountPerThread(v) => *v,
 				ConfigOption::ListenQueueSize(v) => *v,
 				ConfigOption::MaxHeadersLen(v) => *v,
 				_ => default,
@@ -134,6 +161,8 @@ impl Config for ConfigImpl {
 			Some(v) => match v {
 				ConfigOption::MaxSizeBytes(v) => *v,
 				ConfigOption::MaxAgeMillis(v) => *v,
+				ConfigOption::MaxAge(v) => v.millis().unwrap_or(default),
+				ConfigOption::MaxFileSize(v) => v.bytes().unwrap_or(default),
 				ConfigOption::LineNumDataMaxLen(v) => *v,
 				ConfigOption::HttpTimeoutMillis(v) => *v,
 				_ => default,
@@ -146,19 +175,41 @@ impl Config for ConfigImpl {
 		match self.hash.get(name) {
 			Some(v) => match v {
 				ConfigOption::FileHeader(v) => v.to_string(),
+				ConfigOption::FileHeaderStr(v) => v.to_string(),
 				ConfigOption::Regex(v) => v.to_string(),
+				ConfigOption::RegexStr(v) => v.to_string(),
 				ConfigOption::EvhOutOfSlabsMessage(v) => v.to_string(),
+				ConfigOption::EvhOutOfSlabsMessageStr(v) => v.to_string(),
+				ConfigOption::EvhClientBindAddr(v) => v.to_string(),
+				ConfigOption::EvhClientBindAddrStr(v) => v.to_string(),
+				ConfigOption::EvhCaptureAll(v) => v.to_string(),
+				ConfigOption::EvhCaptureAllStr(v) => v.to_string(),
+				ConfigOption::EvhName(v) => v.to_string(),
+				ConfigOption::EvhNameStr(v) => v.to_string(),
+				ConfigOption::EvhSlabQuotaAction(v) => v.to_string(),
+				ConfigOption::EvhSlabQuotaActionStr(v) => v.to_string(),
 				ConfigOption::HttpAccept(v) => v.to_string(),
+				ConfigOption::HttpAcceptStr(v) => v.to_string(),
 				ConfigOption::HttpMeth(v) => v.to_string(),
+				ConfigOption::HttpMethStr(v) => v.to_string(),
 				ConfigOption::HttpVers(v) => v.to_string(),
+				ConfigOption::HttpVersStr(v) => v.to_string(),
 				ConfigOption::HttpConnection(v) => v.to_string(),
+				ConfigOption::HttpConnectionStr(v) => v.to_string(),
 				ConfigOption::HttpRequestUri(v) => v.to_string(),
+				ConfigOption::HttpRequestUriStr(v) => v.to_string(),
 				ConfigOption::HttpRequestUrl(v) => v.to_string(),
+				ConfigOption::HttpRequestUrlStr(v) => v.to_string(),
 				ConfigOption::HttpUserAgent(v) => v.to_string(),
+				ConfigOption::HttpUserAgentStr(v) => v.to_string(),
 				ConfigOption::Host(v) => v.to_string(),
+				ConfigOption::HostStr(v) => v.to_string(),
 				ConfigOption::Address(v) => v.to_string(),
+				ConfigOption::AddressStr(v) => v.to_string(),
 				ConfigOption::BaseDir(v) => v.to_string(),
+				ConfigOption::BaseDirStr(v) => v.to_string(),
 				ConfigOption::ServerName(v) => v.to_string(),
+				ConfigOption::ServerNameStr(v) => v.to_string(),
 				_ => default,
 			},
 			None => default,
@@ -175,6 +226,31 @@ impl Config for ConfigImpl {
 		}
 	}
 
+	fn options(&self) -> Vec<ConfigOption> {
+		self.configs.clone()
+	}
+
+	fn diff(&self, other: &dyn Config) -> Vec<ConfigDiffEntry> {
+		let other = ConfigImpl::new(other.options());
+		let mut names: Vec<&CN> = self.hash.keys().chain(other.hash.keys()).collect();
+		names.sort_by_key(|name| (*name).clone() as usize);
+		names.dedup();
+
+		let mut ret = vec![];
+		for name in names {
+			let old = self.hash.get(name).map(option_value);
+			let new = other.hash.get(name).map(option_value);
+			if old != new {
+				ret.push(ConfigDiffEntry {
+					name: name.clone(),
+					old,
+					new,
+				});
+			}
+		}
+		ret
+	}
+
 	fn check_config(&self, allowed: Vec<CN>, required: Vec<CN>) -> Result<(), Error> {
 		self.check_config_impl(allowed, required, vec![])
 	}
@@ -189,6 +265,313 @@ impl Config for ConfigImpl {
 	}
 }
 
+// the value of an option as a display string. The String and &'static str variants of an
+// option display the same way so they are not reported as a change.
+fn option_value(option: &ConfigOption) -> String {
+	let text = format!("{:?}", option);
+	match (text.find('('), text.rfind(')')) {
+		(Some(start), Some(end)) if start < end => text[start + 1..end].to_string(),
+		_ => text,
+	}
+}
+
+const DURATION_UNITS: &[(&str, u64)] = &[
+	("", 1),
+	("ms", 1),
+	("s", 1_000),
+	("m", 60_000),
+	("h", 3_600_000),
+	("d", 86_400_000),
+	("w", 604_800_000),
+];
+
+const BYTE_SIZE_UNITS: &[(&str, u64)] = &[
+	("", 1),
+	("b", 1),
+	("kb", 1_000),
+	("mb", 1_000_000),
+	("gb", 1_000_000_000),
+	("tb", 1_000_000_000_000),
+	("kib", 1 << 10),
+	("mib", 1 << 20),
+	("gib", 1 << 30),
+	("tib", 1 << 40),
+];
+
+// parse a number with an optional fractional part followed by one of `units`. On failure, the
+// reason is returned so that the caller can name the option in its error.
+fn parse_human(text: &str, units: &[(&str, u64)], kind: &str) -> Result<u64, String> {
+	let trimmed = text.trim();
+	let split = trimmed
+		.find(|c: char| !c.is_ascii_digit() && c != '.')
+		.unwrap_or(trimmed.len());
+	let (number, unit) = trimmed.split_at(split);
+	let (whole, fraction) = match number.split_once('.') {
+		Some((whole, fraction)) => (whole, fraction),
+		None => (number, ""),
+	};
+	if (whole.is_empty() && fraction.is_empty()) || fraction.contains('.') {
+		return Err(format!(
+			"'{}' is not a valid {}: expected a number",
+			text, kind
+		));
+	}
+	let unit = unit.trim().to_lowercase();
+	let multiplier = match units.iter().find(|(name, _)| *name == unit) {
+		Some((_, multiplier)) => *multiplier,
+		None => return Err(format!("'{}' is not a valid {}: unknown unit", text, kind)),
+	};
+
+	let overflow = || format!("'{}' is not a valid {}: the value is too large", text, kind);
+	let whole = if whole.is_empty() {
+		0
+	} else {
+		u64::from_str(whole).map_err(|_| overflow())?
+	};
+	let mut value = whole.checked_mul(multiplier).ok_or_else(overflow)?;
+	// digits beyond the precision of a u64 can't change the truncated result
+	let fraction = &fraction[..fraction.len().min(18)];
+	if !fraction.is_empty() {
+		let numerator = u128::from(u64::from_str(fraction).map_err(|_| overflow())?);
+		let scale = 10u128.pow(fraction.len() as u32);
+		let part = numerator * u128::from(multiplier) / scale;
+		value = value
+			.checked_add(u64::try_from(part).map_err(|_| overflow())?)
+			.ok_or_else(overflow)?;
+	}
+	Ok(value)
+}
+
+impl DurationVal {
+	/// Parse a human readable duration. See [`crate::DurationVal`] for the accepted formats.
+	///
+	/// # Errors
+	/// * [`bmw_err::ErrorKind::Configuration`] - If `text` is not a valid duration or if the
+	///   number of milliseconds does not fit in a [`u64`].
+	pub fn parse(text: &str) -> Result<Self, Error> {
+		match parse_human(text, DURATION_UNITS, "duration") {
+			Ok(value) => Ok(Self { value: Ok(value) }),
+			Err(text) => Err(err!(ErrKind::Configuration, text)),
+		}
+	}
+
+	/// The duration in milliseconds.
+	///
+	/// # Errors
+	/// * [`bmw_err::ErrorKind::Configuration`] - If the value was built from a string that is
+	///   not a valid duration.
+	pub fn millis(&self) -> Result<u64, Error> {
+		match &self.value {
+			Ok(millis) => Ok(*millis),
+			Err(text) => match parse_human(text, DURATION_UNITS, "duration") {
+				Ok(value) => Ok(value),
+				Err(text) => Err(err!(ErrKind::Configuration, text)),
+			},
+		}
+	}
+
+	/// The duration in milliseconds. If the value is not valid, the error names the option
+	/// `name` that it was specified for.
+	///
+	/// # Errors
+	/// * [`bmw_err::ErrorKind::Configuration`] - If the value was built from a string that is
+	///   not a valid duration.
+	pub fn check(&self, name: &str) -> Result<u64, Error> {
+		match &self.value {
+			Ok(value) => Ok(*value),
+			Err(text) => match parse_human(text, DURATION_UNITS, "duration") {
+				Ok(value) => Ok(value),
+				Err(reason) => {
+					let text = format!("invalid value for {}: {}", name, reason);
+					Err(err!(ErrKind::Configuration, text))
+				}
+			},
+		}
+	}
+}
+
+impl From<u64> for DurationVal {
+	fn from(millis: u64) -> Self {
+		Self { value: Ok(millis) }
+	}
+}
+
+impl From<&str> for DurationVal {
+	fn from(text: &str) -> Self {
+		match parse_human(text, DURATION_UNITS, "duration") {
+			Ok(millis) => Self { value: Ok(millis) },
+			Err(_) => Self {
+				value: Err(text.to_string()),
+			},
+		}
+	}
+}
+
+impl FromStr for DurationVal {
+	type Err = Error;
+	fn from_str(text: &str) -> Result<Self, Error> {
+		Self::parse(text)
+	}
+}
+
+// a valid value is displayed as its number of milliseconds so that it compares equal to the
+// numeric option in Config::diff
+impl Debug for DurationVal {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match &self.value {
+			Ok(millis) => write!(f, "{}", millis),
+			Err(text) => write!(f, "{:?}", text),
+		}
+	}
+}
+
+impl ByteSizeVal {
+	/// Parse a human readable number of bytes. See [`crate::ByteSizeVal`] for the accepted
+	/// formats.
+	///
+	/// # Errors
+	/// * [`bmw_err::ErrorKind::Configuration`] - If `text` is not a valid size or if the number
+	///   of bytes does not fit in a [`u64`].
+	pub fn parse(text: &str) -> Result<Self, Error> {
+		match parse_human(text, BYTE_SIZE_UNITS, "byte size") {
+			Ok(value) => Ok(Self { value: Ok(value) }),
+			Err(text) => Err(err!(ErrKind::Configuration, text)),
+		}
+	}
+
+	/// The size in bytes.
+	///
+	/// # Errors
+	/// * [`bmw_err::ErrorKind::Configuration`] - If the value was built from a string that is
+	///   not a valid size.
+	pub fn bytes(&self) -> Result<u64, Error> {
+		match &self.value {
+			Ok(bytes) => Ok(*bytes),
+			Err(text) => match parse_human(text, BYTE_SIZE_UNITS, "byte size") {
+				Ok(value) => Ok(value),
+				Err(text) => Err(err!(ErrKind::Configuration, text)),
+			},
+		}
+	}
+
+	/// The size in bytes. If the value is not valid, the error names the option `name` that it
+	/// was specified for.
+	///
+	/// # Errors
+	/// * [`bmw_err::ErrorKind::Configuration`] - If the value was built from a string that is
+	///   not a valid size.
+	pub fn check(&self, name: &str) -> Result<u64, Error> {
+		match &self.value {
+			Ok(value) => Ok(*value),
+			Err(text) => match parse_human(text, BYTE_SIZE_UNITS, "byte size") {
+				Ok(value) => Ok(value),
+				Err(reason) => {
+					let text = format!("invalid value for {}: {}", name, reason);
+					Err(err!(ErrKind::Configuration, text))
+				}
+			},
+		}
+	}
+}
+
+impl From<u64> for ByteSizeVal {
+	fn from(bytes: u64) -> Self {
+		Self { value: Ok(bytes) }
+	}
+}
+
+impl From<&str> for ByteSizeVal {
+	fn from(text: &str) -> Self {
+		match parse_human(text, BYTE_SIZE_UNITS, "byte size") {
+			Ok(bytes) => Self { value: Ok(bytes) },
+			Err(_) => Self {
+				value: Err(text.to_string()),
+			},
+		}
+	}
+}
+
+impl FromStr for ByteSizeVal {
+	type Err = Error;
+	fn from_str(text: &str) -> Result<Self, Error> {
+		Self::parse(text)
+	}
+}
+
+// a valid value is displayed as its number of bytes. See DurationVal.
+impl Debug for ByteSizeVal {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match &self.value {
+			Ok(bytes) => write!(f, "{}", bytes),
+			Err(text) => write!(f, "{:?}", text),
+		}
+	}
+}
+
+impl ConfigHistory {
+	/// Create a [`crate::ConfigHistory`] that keeps the last `capacity` snapshots. The space
+	/// for the snapshots is allocated up front.
+	///
+	/// # Errors
+	/// * [`bmw_err::ErrorKind::Configuration`] - If `capacity` is 0.
+	pub fn new(capacity: usize) -> Result<Self, Error> {
+		if capacity == 0 {
+			let text = "a ConfigHistory must have a capacity of at least 1";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+		Ok(Self {
+			capacity,
+			snapshots: VecDeque::with_capacity(capacity),
+		})
+	}
+
+	/// Record a snapshot of `config`. If the history is full, the oldest snapshot is evicted.
+	///
+	/// # Returns
+	/// `Vec<ConfigDiffEntry>` - The differences from the previous snapshot. For the first
+	/// snapshot, each specified option is returned with an old value of
+	/// [`std::option::Option::None`].
+	pub fn record(&mut self, config: &dyn Config) -> Result<Vec<ConfigDiffEntry>, Error> {
+		let timestamp_millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+		let config = ConfigImpl::new(config.options());
+		let changes = match self.snapshots.back() {
+			Some(last) => last.config.diff(&config),
+			None => ConfigImpl::new(vec![]).diff(&config),
+		};
+		if self.snapshots.len() == self.capacity {
+			self.snapshots.pop_front();
+		}
+		self.snapshots.push_back(ConfigSnapshot {
+			timestamp_millis,
+			config,
+			changes: changes.clone(),
+		});
+		Ok(changes)
+	}
+
+	/// Returns an iterator over the recorded snapshots from the oldest to the newest.
+	pub fn entries(&self) -> std::collections::vec_deque::Iter<'_, ConfigSnapshot> {
+		self.snapshots.iter()
+	}
+}
+
+impl ConfigSnapshot {
+	/// The time that the snapshot was recorded in milliseconds since the epoch.
+	pub fn timestamp_millis(&self) -> u128 {
+		self.timestamp_millis
+	}
+
+	/// The configuration at the time of the snapshot.
+	pub fn config(&self) -> &dyn Config {
+		&self.config
+	}
+
+	/// The differences from the previous snapshot. See [`crate::ConfigHistory::record`].
+	pub fn changes(&self) -> &[ConfigDiffEntry] {
+		&self.changes
+	}
+}
+
 impl ConfigImpl {
 	// create a new config based on the specified input.
 	pub fn new(configs: Vec<ConfigOption>) -> Self {
@@ -196,8 +579,8 @@ impl ConfigImpl {
 		let mut hash = HashMap::new();
 		for config in &configs {
 			let _ = match config {
-				MaxSizeBytes(_) => hash.insert(CN::MaxSizeBytes, config.clone()),
-				MaxAgeMillis(_) => hash.insert(CN::MaxAgeMillis, config.clone()),
+				MaxSizeBytes(_) | MaxFileSize(_) => hash.insert(CN::MaxSizeBytes, config.clone()),
+				MaxAgeMillis(_) | MaxAge(_) => hash.insert(CN::MaxAgeMillis, config.clone()),
 				DisplayColors(_) => hash.insert(CN::DisplayColors, config.clone()),
 				DisplayStdout(_) => hash.insert(CN::DisplayStdout, config.clone()),
 				DisplayTimestamp(_) => hash.insert(CN::DisplayTimestamp, config.clone()),
@@ -209,19 +592,27 @@ impl ConfigImpl {
 				DisplayBackTrace(_) => hash.insert(CN::DisplayBackTrace, config.clone()),
 				LineNumDataMaxLen(_) => hash.insert(CN::LineNumDataMaxLen, config.clone()),
 				DeleteRotation(_) => hash.insert(CN::DeleteRotation, config.clone()),
-				FileHeader(_) => hash.insert(CN::FileHeader, config.clone()),
+				FileHeader(_) | FileHeaderStr(_) => hash.insert(CN::FileHeader, config.clone()),
 				MaxEntries(_) => hash.insert(CN::MaxEntries, config.clone()),
 				MaxLoadFactor(_) => hash.insert(CN::MaxLoadFactor, config.clone()),
 				SlabSize(_) => hash.insert(CN::SlabSize, config.clone()),
 				SlabCount(_) => hash.insert(CN::SlabCount, config.clone()),
+				Shards(_) => hash.insert(CN::Shards, config.clone()),
+				BloomBits(_) => hash.insert(CN::BloomBits, config.clone()),
+				BloomHashes(_) => hash.insert(CN::BloomHashes, config.clone()),
+				ContiguousThresholdBytes(_) => {
+					hash.insert(CN::ContiguousThresholdBytes, config.clone())
+				}
 				MinSize(_) => hash.insert(CN::MinSize, config.clone()),
 				MaxSize(_) => hash.insert(CN::MaxSize, config.clone()),
 				SyncChannelSize(_) => hash.insert(CN::SyncChannelSize, config.clone()),
 				GlobalSlabAllocator(_) => hash.insert(CN::GlobalSlabAllocator, config.clone()),
+				OverflowToHeap(_) => hash.insert(CN::OverflowToHeap, config.clone()),
+				DeterministicHashing(_) => hash.insert(CN::DeterministicHashing, config.clone()),
 				Start(_) => hash.insert(CN::Start, config.clone()),
 				End(_) => hash.insert(CN::End, config.clone()),
 				MatchId(_) => hash.insert(CN::MatchId, config.clone()),
-				Regex(_) => hash.insert(CN::Regex, config.clone()),
+				Regex(_) | RegexStr(_) => hash.insert(CN::Regex, config.clone()),
 				IsCaseSensitive(_) => hash.insert(CN::IsCaseSensitive, config.clone()),
 				IsTerminationPattern(_) => hash.insert(CN::IsTerminationPattern, config.clone()),
 				IsMultiLine(_) => hash.insert(CN::IsMultiLine, config.clone()),
@@ -240,23 +631,77 @@ impl ConfigImpl {
 				EvhTimeout(_) => hash.insert(CN::EvhTimeout, config.clone()),
 				EvhReadSlabSize(_) => hash.insert(CN::EvhReadSlabSize, config.clone()),
 				EvhReadSlabCount(_) => hash.insert(CN::EvhReadSlabCount, config.clone()),
-				EvhOutOfSlabsMessage(_) => hash.insert(CN::EvhOutOfSlabsMessage, config.clone()),
+				EvhReadSlabCountPerThread(_) => {
+					hash.insert(CN::EvhReadSlabCountPerThread, config.clone())
+				}
+				EvhOutOfSlabsMessage(_) | EvhOutOfSlabsMessageStr(_) => {
+					hash.insert(CN::EvhOutOfSlabsMessage, config.clone())
+				}
+				EvhClientBindAddr(_) | EvhClientBindAddrStr(_) => {
+					hash.insert(CN::EvhClientBindAddr, config.clone())
+				}
+				EvhProxyProtocol(_) => hash.insert(CN::EvhProxyProtocol, config.clone()),
+				EvhProxyHeaderTimeoutMillis(_) => {
+					hash.insert(CN::EvhProxyHeaderTimeoutMillis, config.clone())
+				}
+				EvhDeferAcceptSecs(_) => hash.insert(CN::EvhDeferAcceptSecs, config.clone()),
+				EvhRequireDataWithinMillis(_) => {
+					hash.insert(CN::EvhRequireDataWithinMillis, config.clone())
+				}
+				EvhMaxWriteBytesPerLoop(_) => {
+					hash.insert(CN::EvhMaxWriteBytesPerLoop, config.clone())
+				}
+				EvhFdWarnThresholdPercent(_) => {
+					hash.insert(CN::EvhFdWarnThresholdPercent, config.clone())
+				}
+				EvhCallbackTimeoutMillis(_) => {
+					hash.insert(CN::EvhCallbackTimeoutMillis, config.clone())
+				}
+				EvhWatchdogAction(_) => hash.insert(CN::EvhWatchdogAction, config.clone()),
+				EvhRecvBufferSize(_) => hash.insert(CN::EvhRecvBufferSize, config.clone()),
+				EvhSendBufferSize(_) => hash.insert(CN::EvhSendBufferSize, config.clone()),
+				EvhStrictSockOpts(_) => hash.insert(CN::EvhStrictSockOpts, config.clone()),
+				EvhCaptureAll(_) | EvhCaptureAllStr(_) => {
+					hash.insert(CN::EvhCaptureAll, config.clone())
+				}
+				EvhName(_) | EvhNameStr(_) => hash.insert(CN::EvhName, config.clone()),
+				EvhStrictOrdering(_) => hash.insert(CN::EvhStrictOrdering, config.clone()),
+				EvhMaxSlabsPerConnection(_) => {
+					hash.insert(CN::EvhMaxSlabsPerConnection, config.clone())
+				}
+				EvhSlabQuotaAction(_) | EvhSlabQuotaActionStr(_) => {
+					hash.insert(CN::EvhSlabQuotaAction, config.clone())
+				}
+				EvhStrictKeys(_) => hash.insert(CN::EvhStrictKeys, config.clone()),
+				EvhStartTimeoutMillis(_) => hash.insert(CN::EvhStartTimeoutMillis, config.clone()),
+				EvhOnAcceptBudgetMicros(_) => {
+					hash.insert(CN::EvhOnAcceptBudgetMicros, config.clone())
+				}
+				EvhMemoryBudgetBytes(_) => hash.insert(CN::EvhMemoryBudgetBytes, config.clone()),
 				Port(_) => hash.insert(CN::Port, config.clone()),
-				Host(_) => hash.insert(CN::Host, config.clone()),
-				Address(_) => hash.insert(CN::Address, config.clone()),
-				BaseDir(_) => hash.insert(CN::BaseDir, config.clone()),
-				ServerName(_) => hash.insert(CN::ServerName, config.clone()),
+				Host(_) | HostStr(_) => hash.insert(CN::Host, config.clone()),
+				Address(_) | AddressStr(_) => hash.insert(CN::Address, config.clone()),
+				BaseDir(_) | BaseDirStr(_) => hash.insert(CN::BaseDir, config.clone()),
+				ServerName(_) | ServerNameStr(_) => hash.insert(CN::ServerName, config.clone()),
 				HttpContentFile(_) => hash.insert(CN::HttpContentFile, config.clone()),
 				HttpContentData(_) => hash.insert(CN::HttpContentData, config.clone()),
-				HttpAccept(_) => hash.insert(CN::HttpAccept, config.clone()),
+				HttpAccept(_) | HttpAcceptStr(_) => hash.insert(CN::HttpAccept, config.clone()),
 				HttpHeader(_) => hash.insert(CN::HttpHeader, config.clone()),
 				HttpTimeoutMillis(_) => hash.insert(CN::HttpTimeoutMillis, config.clone()),
-				HttpMeth(_) => hash.insert(CN::HttpMeth, config.clone()),
-				HttpVers(_) => hash.insert(CN::HttpVers, config.clone()),
-				HttpConnection(_) => hash.insert(CN::HttpConnection, config.clone()),
-				HttpRequestUri(_) => hash.insert(CN::HttpRequestUri, config.clone()),
-				HttpRequestUrl(_) => hash.insert(CN::HttpRequestUrl, config.clone()),
-				HttpUserAgent(_) => hash.insert(CN::HttpUserAgent, config.clone()),
+				HttpMeth(_) | HttpMethStr(_) => hash.insert(CN::HttpMeth, config.clone()),
+				HttpVers(_) | HttpVersStr(_) => hash.insert(CN::HttpVers, config.clone()),
+				HttpConnection(_) | HttpConnectionStr(_) => {
+					hash.insert(CN::HttpConnection, config.clone())
+				}
+				HttpRequestUri(_) | HttpRequestUriStr(_) => {
+					hash.insert(CN::HttpRequestUri, config.clone())
+				}
+				HttpRequestUrl(_) | HttpRequestUrlStr(_) => {
+					hash.insert(CN::HttpRequestUrl, config.clone())
+				}
+				HttpUserAgent(_) | HttpUserAgentStr(_) => {
+					hash.insert(CN::HttpUserAgent, config.clone())
+				}
 				HttpMimeMap(_) => hash.insert(CN::HttpMimeMap, config.clone()),
 				HttpShowRequest(_) => hash.insert(CN::HttpShowRequest, config.clone()),
 				MaxHeadersLen(_) => hash.insert(CN::MaxHeadersLen, config.clone()),
@@ -292,6 +737,14 @@ impl ConfigImpl {
 			match v {
 				MaxSizeBytes(_) => cc!(self, t, &mut s, CN::MaxSizeBytes, d),
 				MaxAgeMillis(_) => cc!(self, t, &mut s, CN::MaxAgeMillis, d),
+				MaxFileSize(v) => {
+					cc!(self, t, &mut s, CN::MaxSizeBytes, d);
+					v.check("MaxFileSize")?;
+				}
+				MaxAge(v) => {
+					cc!(self, t, &mut s, CN::MaxAgeMillis, d);
+					v.check("MaxAge")?;
+				}
 				DisplayColors(_) => cc!(self, t, &mut s, CN::DisplayColors, d),
 				DisplayStdout(_) => cc!(self, t, &mut s, CN::DisplayStdout, d),
 				DisplayTimestamp(_) => cc!(self, t, &mut s, CN::DisplayTimestamp, d),
@@ -303,19 +756,27 @@ impl ConfigImpl {
 				DisplayBackTrace(_) => cc!(self, t, &mut s, CN::DisplayBackTrace, d),
 				LineNumDataMaxLen(_) => cc!(self, t, &mut s, CN::LineNumDataMaxLen, d),
 				DeleteRotation(_) => cc!(self, t, &mut s, CN::DeleteRotation, d),
-				FileHeader(_) => cc!(self, t, &mut s, CN::FileHeader, d),
+				FileHeader(_) | FileHeaderStr(_) => cc!(self, t, &mut s, CN::FileHeader, d),
 				MaxEntries(_) => cc!(self, t, &mut s, CN::MaxEntries, d),
 				MaxLoadFactor(_) => cc!(self, t, &mut s, CN::MaxLoadFactor, d),
 				SlabSize(_) => cc!(self, t, &mut s, CN::SlabSize, d),
 				SlabCount(_) => cc!(self, t, &mut s, CN::SlabCount, d),
+				Shards(_) => cc!(self, t, &mut s, CN::Shards, d),
+				BloomBits(_) => cc!(self, t, &mut s, CN::BloomBits, d),
+				BloomHashes(_) => cc!(self, t, &mut s, CN::BloomHashes, d),
+				ContiguousThresholdBytes(_) => {
+					cc!(self, t, &mut s, CN::ContiguousThresholdBytes, d)
+				}
 				MinSize(_) => cc!(self, t, &mut s, CN::MinSize, d),
 				MaxSize(_) => cc!(self, t, &mut s, CN::MaxSize, d),
 				SyncChannelSize(_) => cc!(self, t, &mut s, CN::SyncChannelSize, d),
 				GlobalSlabAllocator(_) => cc!(self, t, &mut s, CN::GlobalSlabAllocator, d),
+				DeterministicHashing(_) => cc!(self, t, &mut s, CN::DeterministicHashing, d),
+				OverflowToHeap(_) => cc!(self, t, &mut s, CN::OverflowToHeap, d),
 				Start(_) => cc!(self, t, &mut s, CN::Start, d),
 				End(_) => cc!(self, t, &mut s, CN::End, d),
 				MatchId(_) => cc!(self, t, &mut s, CN::MatchId, d),
-				Regex(_) => cc!(self, t, &mut s, CN::Regex, d),
+				Regex(_) | RegexStr(_) => cc!(self, t, &mut s, CN::Regex, d),
 				IsCaseSensitive(_) => cc!(self, t, &mut s, CN::IsCaseSensitive, d),
 				IsTerminationPattern(_) => cc!(self, t, &mut s, CN::IsTerminationPattern, d),
 				IsMultiLine(_) => cc!(self, t, &mut s, CN::IsMultiLine, d),
@@ -334,23 +795,75 @@ impl ConfigImpl {
 				EvhTimeout(_) => cc!(self, t, &mut s, CN::EvhTimeout, d),
 				EvhReadSlabSize(_) => cc!(self, t, &mut s, CN::EvhReadSlabSize, d),
 				EvhReadSlabCount(_) => cc!(self, t, &mut s, CN::EvhReadSlabCount, d),
-				EvhOutOfSlabsMessage(_) => cc!(self, t, &mut s, CN::EvhOutOfSlabsMessage, d),
+				EvhReadSlabCountPerThread(_) => {
+					cc!(self, t, &mut s, CN::EvhReadSlabCountPerThread, d)
+				}
+				EvhOutOfSlabsMessage(_) | EvhOutOfSlabsMessageStr(_) => {
+					cc!(self, t, &mut s, CN::EvhOutOfSlabsMessage, d)
+				}
+				EvhClientBindAddr(_) | EvhClientBindAddrStr(_) => {
+					cc!(self, t, &mut s, CN::EvhClientBindAddr, d)
+				}
+				EvhProxyProtocol(_) => cc!(self, t, &mut s, CN::EvhProxyProtocol, d),
+				EvhProxyHeaderTimeoutMillis(_) => {
+					cc!(self, t, &mut s, CN::EvhProxyHeaderTimeoutMillis, d)
+				}
+				EvhDeferAcceptSecs(_) => cc!(self, t, &mut s, CN::EvhDeferAcceptSecs, d),
+				EvhRequireDataWithinMillis(_) => {
+					cc!(self, t, &mut s, CN::EvhRequireDataWithinMillis, d)
+				}
+				EvhMaxWriteBytesPerLoop(_) => cc!(self, t, &mut s, CN::EvhMaxWriteBytesPerLoop, d),
+				EvhFdWarnThresholdPercent(_) => {
+					cc!(self, t, &mut s, CN::EvhFdWarnThresholdPercent, d)
+				}
+				EvhCallbackTimeoutMillis(_) => {
+					cc!(self, t, &mut s, CN::EvhCallbackTimeoutMillis, d)
+				}
+				EvhWatchdogAction(_) => {
+					cc!(self, t, &mut s, CN::EvhWatchdogAction, d)
+				}
+				EvhRecvBufferSize(_) => cc!(self, t, &mut s, CN::EvhRecvBufferSize, d),
+				EvhSendBufferSize(_) => cc!(self, t, &mut s, CN::EvhSendBufferSize, d),
+				EvhStrictSockOpts(_) => cc!(self, t, &mut s, CN::EvhStrictSockOpts, d),
+				EvhCaptureAll(_) | EvhCaptureAllStr(_) => {
+					cc!(self, t, &mut s, CN::EvhCaptureAll, d)
+				}
+				EvhName(_) | EvhNameStr(_) => cc!(self, t, &mut s, CN::EvhName, d),
+				EvhStrictOrdering(_) => cc!(self, t, &mut s, CN::EvhStrictOrdering, d),
+				EvhMaxSlabsPerConnection(_) => {
+					cc!(self, t, &mut s, CN::EvhMaxSlabsPerConnection, d)
+				}
+				EvhSlabQuotaAction(_) | EvhSlabQuotaActionStr(_) => {
+					cc!(self, t, &mut s, CN::EvhSlabQuotaAction, d)
+				}
+				EvhStrictKeys(_) => cc!(self, t, &mut s, CN::EvhStrictKeys, d),
+				EvhStartTimeoutMillis(_) => cc!(self, t, &mut s, CN::EvhStartTimeoutMillis, d),
+				EvhOnAcceptBudgetMicros(_) => cc!(self, t, &mut s, CN::EvhOnAcceptBudgetMicros, d),
+				EvhMemoryBudgetBytes(_) => cc!(self, t, &mut s, CN::EvhMemoryBudgetBytes, d),
 				Port(_) => cc!(self, t, &mut s, CN::Port, d),
-				Host(_) => cc!(self, t, &mut s, CN::Host, d),
-				Address(_) => cc!(self, t, &mut s, CN::Address, d),
-				BaseDir(_) => cc!(self, t, &mut s, CN::BaseDir, d),
-				ServerName(_) => cc!(self, t, &mut s, CN::ServerName, d),
+				Host(_) | HostStr(_) => cc!(self, t, &mut s, CN::Host, d),
+				Address(_) | AddressStr(_) => cc!(self, t, &mut s, CN::Address, d),
+				BaseDir(_) | BaseDirStr(_) => cc!(self, t, &mut s, CN::BaseDir, d),
+				ServerName(_) | ServerNameStr(_) => cc!(self, t, &mut s, CN::ServerName, d),
 				HttpContentFile(_) => cc!(self, t, &mut s, CN::HttpContentFile, d),
 				HttpContentData(_) => cc!(self, t, &mut s, CN::HttpContentData, d),
-				HttpAccept(_) => cc!(self, t, &mut s, CN::HttpAccept, d),
+				HttpAccept(_) | HttpAcceptStr(_) => cc!(self, t, &mut s, CN::HttpAccept, d),
 				HttpHeader(_) => cc!(self, t, &mut s, CN::HttpHeader, d),
 				HttpTimeoutMillis(_) => cc!(self, t, &mut s, CN::HttpTimeoutMillis, d),
-				HttpMeth(_) => cc!(self, t, &mut s, CN::HttpMeth, d),
-				HttpVers(_) => cc!(self, t, &mut s, CN::HttpVers, d),
-				HttpConnection(_) => cc!(self, t, &mut s, CN::HttpConnection, d),
-				HttpRequestUri(_) => cc!(self, t, &mut s, CN::HttpRequestUri, d),
-				HttpRequestUrl(_) => cc!(self, t, &mut s, CN::HttpRequestUrl, d),
-				HttpUserAgent(_) => cc!(self, t, &mut s, CN::HttpUserAgent, d),
+				HttpMeth(_) | HttpMethStr(_) => cc!(self, t, &mut s, CN::HttpMeth, d),
+				HttpVers(_) | HttpVersStr(_) => cc!(self, t, &mut s, CN::HttpVers, d),
+				HttpConnection(_) | HttpConnectionStr(_) => {
+					cc!(self, t, &mut s, CN::HttpConnection, d)
+				}
+				HttpRequestUri(_) | HttpRequestUriStr(_) => {
+					cc!(self, t, &mut s, CN::HttpRequestUri, d)
+				}
+				HttpRequestUrl(_) | HttpRequestUrlStr(_) => {
+					cc!(self, t, &mut s, CN::HttpRequestUrl, d)
+				}
+				HttpUserAgent(_) | HttpUserAgentStr(_) => {
+					cc!(self, t, &mut s, CN::HttpUserAgent, d)
+				}
 				HttpMimeMap(_) => cc!(self, t, &mut s, CN::HttpMimeMap, d),
 				HttpShowRequest(_) => cc!(self, t, &mut s, CN::HttpShowRequest, d),
 				MaxHeadersLen(_) => cc!(self, t, &mut s, CN::MaxHeadersLen, d),
@@ -74,4 +74,7 @@ mod macros;
 mod test;
 mod types;
 
-pub use crate::types::{Config, ConfigBuilder, ConfigOption, ConfigOptionName};
+pub use crate::types::{
+	ByteSizeVal, Config, ConfigBuilder, ConfigDiffEntry, ConfigHistory, ConfigOption,
+	ConfigOptionName, ConfigSnapshot, DurationVal, WatchdogAction,
+};
@@ -17,7 +17,12 @@
 // limitations under the License.
 
 /// The config macro allows for a configuration to be specified and checked conveniently. This
-/// macro is used throughout BMW.
+/// macro is used throughout BMW. String options may be specified with either an owned [`String`]
+/// or a string literal. A literal (e.g. `FileHeader("test")`) is stored without allocating by
+/// using the borrowed version of the option (e.g. [`crate::ConfigOption::FileHeaderStr`]).
+/// [`crate::ConfigOption::MaxAge`] and [`crate::ConfigOption::MaxFileSize`] accept either a
+/// number (of milliseconds or bytes) or a human readable string (e.g. `MaxAge("1h")`). See
+/// [`crate::DurationVal`] and [`crate::ByteSizeVal`].
 ///
 /// # Examples
 ///
@@ -29,7 +34,7 @@
 /// // create a config using the macro and check it
 /// fn main() -> Result<(), Error> {
 ///     // create a simple config
-///     let config = config!(FileHeader("test".to_string()), DeleteRotation(false));
+///     let config = config!(FileHeader("test"), DeleteRotation(false));
 ///
 ///     // check it
 ///     let res = config.check_config(
@@ -49,15 +54,104 @@
 ///
 #[macro_export]
 macro_rules! config {
-	( $( $config:expr ),* ) => {{
+	( $( $config:tt )* ) => {{
                 #[allow(unused_imports)]
                 use bmw_conf::{ConfigBuilder, ConfigOption, ConfigOption::*};
                 #[allow(unused_mut)]
                 let mut config_values: Vec<ConfigOption> = vec![];
-                $(
-                        config_values.push($config);
-                )*
+                bmw_conf::config_push!(config_values, $($config)*);
 
                 ConfigBuilder::build_config(config_values)
         }};
 }
+
+// push each option of the config! macro, mapping string literals to the borrowed options
+#[doc(hidden)]
+#[macro_export]
+macro_rules! config_push {
+	($v:ident $(,)?) => {};
+	($v:ident, FileHeader($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::FileHeaderStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, Regex($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::RegexStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, EvhOutOfSlabsMessage($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::EvhOutOfSlabsMessageStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, EvhClientBindAddr($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::EvhClientBindAddrStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, EvhCaptureAll($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::EvhCaptureAllStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, EvhSlabQuotaAction($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::EvhSlabQuotaActionStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, EvhName($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::EvhNameStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, HttpAccept($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::HttpAcceptStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, HttpMeth($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::HttpMethStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, HttpVers($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::HttpVersStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, HttpConnection($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::HttpConnectionStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, HttpRequestUri($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::HttpRequestUriStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, HttpRequestUrl($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::HttpRequestUrlStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, HttpUserAgent($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::HttpUserAgentStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, Host($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::HostStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, Address($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::AddressStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, BaseDir($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::BaseDirStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, ServerName($s:literal) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::ServerNameStr($s));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, MaxAge($value:expr) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::MaxAge(bmw_conf::DurationVal::from($value)));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, MaxFileSize($value:expr) $(, $($rest:tt)*)?) => {
+		$v.push(bmw_conf::ConfigOption::MaxFileSize(bmw_conf::ByteSizeVal::from($value)));
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+	($v:ident, $config:expr $(, $($rest:tt)*)?) => {
+		$v.push($config);
+		bmw_conf::config_push!($v $(, $($rest)*)?);
+	};
+}
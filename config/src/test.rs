@@ -20,6 +20,7 @@
 mod test {
 	use crate as bmw_conf;
 	use crate::{config, ConfigBuilder, ConfigOption, ConfigOption::*, ConfigOptionName as CN};
+	use crate::{ByteSizeVal, ConfigDiffEntry, ConfigHistory, DurationVal};
 	use bmw_err::*;
 
 	#[test]
@@ -267,6 +268,59 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_config_string_literals() -> Result<(), Error> {
+		// the literal is stored as the borrowed option
+		let config = config!(FileHeader("test"), DeleteRotation(false));
+		assert_eq!(config.get(&CN::FileHeader), Some(FileHeaderStr("test")));
+		assert!(config
+			.check_config(
+				vec![CN::FileHeader, CN::DeleteRotation],
+				vec![CN::FileHeader]
+			)
+			.is_ok());
+
+		// owned and literal values are retrieved the same way
+		let owned = "dynamic".to_string();
+		let config = config!(
+			Regex(owned.clone()),
+			Host("127.0.0.1"),
+			ServerName(format!("{}-server", owned)),
+			HttpUserAgent("agent"),
+		);
+		assert_eq!(config.get_or_string(&CN::Regex, "".to_string()), owned);
+		assert_eq!(config.get_or_string(&CN::Host, "".to_string()), "127.0.0.1");
+		assert_eq!(
+			config.get_or_string(&CN::ServerName, "".to_string()),
+			"dynamic-server"
+		);
+		assert_eq!(
+			config.get_or_string(&CN::HttpUserAgent, "".to_string()),
+			"agent"
+		);
+
+		let literal = config!(FileHeader("header"));
+		let owned = config!(FileHeader("header".to_string()));
+		assert_eq!(
+			literal.get_or_string(&CN::FileHeader, "".to_string()),
+			owned.get_or_string(&CN::FileHeader, "".to_string())
+		);
+
+		// both forms count as the same option
+		let config = config!(FileHeader("test1"), FileHeader("test2".to_string()));
+		assert!(config.check_config(vec![CN::FileHeader], vec![]).is_err());
+		assert_eq!(
+			config.get_multi(&CN::FileHeader),
+			vec![FileHeaderStr("test1"), FileHeader("test2".to_string())]
+		);
+
+		// other options are not affected
+		let config = config!(SlabSize(10), FileHeader("test"));
+		assert!(config.check_config(vec![CN::SlabSize], vec![]).is_err());
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_config_with_debug() -> Result<(), Error> {
 		let config = config!(Debug(true));
@@ -313,4 +367,236 @@ mod test {
 		);
 		Ok(())
 	}
+
+	#[test]
+	fn test_config_diff() -> Result<(), Error> {
+		let old = config!(
+			SlabSize(100),
+			SlabCount(10),
+			FileHeader("abc".to_string()),
+			EvhTimeout(10)
+		);
+		let new = config!(
+			EvhTimeout(20),
+			SlabCount(10),
+			FileHeaderStr("abc"),
+			MaxEntries(5)
+		);
+
+		// the entries are in the order of ConfigOptionName. FileHeader has the same value.
+		assert_eq!(
+			old.diff(&*new),
+			vec![
+				ConfigDiffEntry {
+					name: CN::MaxEntries,
+					old: None,
+					new: Some("5".to_string()),
+				},
+				ConfigDiffEntry {
+					name: CN::SlabSize,
+					old: Some("100".to_string()),
+					new: None,
+				},
+				ConfigDiffEntry {
+					name: CN::EvhTimeout,
+					old: Some("10".to_string()),
+					new: Some("20".to_string()),
+				},
+			]
+		);
+
+		// the reverse diff swaps old and new
+		let diff = new.diff(&*old);
+		assert_eq!(diff.len(), 3);
+		assert_eq!(diff[2].old, Some("20".to_string()));
+		assert_eq!(diff[2].new, Some("10".to_string()));
+
+		// identical configs have no differences
+		assert_eq!(old.diff(&*old), vec![]);
+		let same = config!(EvhTimeout(10), FileHeader("abc".to_string()));
+		let same2 = config!(FileHeader("abc".to_string()), EvhTimeout(10));
+		assert_eq!(same.diff(&*same2), vec![]);
+		assert_eq!(config!().diff(&*config!()), vec![]);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_config_history() -> Result<(), Error> {
+		assert!(ConfigHistory::new(0).is_err());
+
+		let mut history = ConfigHistory::new(3)?;
+		assert_eq!(history.entries().count(), 0);
+
+		// the first snapshot reports every option
+		let changes = history.record(&*config!(SlabCount(1), Debug(true)))?;
+		assert_eq!(changes.len(), 2);
+		assert!(changes.iter().all(|entry| entry.old.is_none()));
+
+		for i in 2..=5 {
+			let changes = history.record(&*config!(SlabCount(i), Debug(true)))?;
+			assert_eq!(
+				changes,
+				vec![ConfigDiffEntry {
+					name: CN::SlabCount,
+					old: Some(format!("{}", i - 1)),
+					new: Some(format!("{}", i)),
+				}]
+			);
+		}
+
+		// only the last 3 snapshots are kept, oldest first
+		let entries: Vec<_> = history.entries().collect();
+		assert_eq!(entries.len(), 3);
+		assert_eq!(history.snapshots.capacity(), 3);
+		for (i, entry) in entries.iter().enumerate() {
+			assert_eq!(entry.config().get_or_usize(&CN::SlabCount, 0), i + 3);
+			assert_eq!(entry.changes().len(), 1);
+			assert!(entry.timestamp_millis() > 0);
+		}
+		assert!(entries[0].timestamp_millis() <= entries[2].timestamp_millis());
+
+		// recording the same config again reports no changes
+		assert_eq!(
+			history.record(&*config!(SlabCount(5), Debug(true)))?,
+			vec![]
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_config_duration_val() -> Result<(), Error> {
+		for (text, millis) in [
+			("500", 500),
+			("500ms", 500),
+			("90s", 90_000),
+			("15m", 900_000),
+			("2h", 7_200_000),
+			("1d", 86_400_000),
+			("2w", 1_209_600_000),
+			("1.5h", 5_400_000),
+			("0.5s", 500),
+			(".25s", 250),
+			("1.0009s", 1_000),
+			("3MS", 3),
+			("90S", 90_000),
+			("2H", 7_200_000),
+			(" 10 m ", 600_000),
+		] {
+			assert_eq!(DurationVal::parse(text)?.millis()?, millis, "{}", text);
+			assert_eq!(text.parse::<DurationVal>()?, DurationVal::from(millis));
+		}
+
+		for text in ["", "h", ".", "1.2.3s", "-1s", "1x", "1 hour", "1mb", "s1"] {
+			assert!(DurationVal::parse(text).is_err(), "{}", text);
+			assert!(DurationVal::from(text).millis().is_err(), "{}", text);
+		}
+
+		// overflow is rejected
+		assert!(DurationVal::parse("18446744073709551615ms").is_ok());
+		assert!(DurationVal::parse("18446744073709551616ms").is_err());
+		assert!(DurationVal::parse("18446744073709551615s").is_err());
+		assert!(DurationVal::parse("30600000000w").is_err());
+		assert!(DurationVal::parse("18446744073709551615.5ms").is_ok());
+
+		// the error names the option and the bad string
+		let e = DurationVal::from("10 fortnights")
+			.check("MaxAge")
+			.unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
+		assert!(e.to_string().contains("MaxAge"));
+		assert!(e.to_string().contains("'10 fortnights'"));
+		Ok(())
+	}
+
+	#[test]
+	fn test_config_byte_size_val() -> Result<(), Error> {
+		for (text, bytes) in [
+			("100", 100),
+			("100b", 100),
+			("64kb", 64_000),
+			("1mb", 1_000_000),
+			("3gb", 3_000_000_000),
+			("2tb", 2_000_000_000_000),
+			("64kib", 65_536),
+			("1mib", 1_048_576),
+			("2gib", 2_147_483_648),
+			("1tib", 1_099_511_627_776),
+			("1.5kib", 1_536),
+			("0.1b", 0),
+			("64KB", 64_000),
+			("2GiB", 2_147_483_648),
+			("1 MiB", 1_048_576),
+		] {
+			assert_eq!(ByteSizeVal::parse(text)?.bytes()?, bytes, "{}", text);
+			assert_eq!(text.parse::<ByteSizeVal>()?, ByteSizeVal::from(bytes));
+		}
+
+		for text in ["", "kb", "1k", "1kbb", "1.1.1mb", "-5b", "1ms"] {
+			assert!(ByteSizeVal::parse(text).is_err(), "{}", text);
+			assert!(ByteSizeVal::from(text).bytes().is_err(), "{}", text);
+		}
+
+		// overflow is rejected
+		assert!(ByteSizeVal::parse("16777215tib").is_ok());
+		assert!(ByteSizeVal::parse("16777216tib").is_err());
+		assert!(ByteSizeVal::parse("99999999999999999999").is_err());
+
+		let e = ByteSizeVal::from("12 bits")
+			.check("MaxFileSize")
+			.unwrap_err();
+		assert!(e.to_string().contains("MaxFileSize"));
+		assert!(e.to_string().contains("'12 bits'"));
+		Ok(())
+	}
+
+	#[test]
+	fn test_config_human_options() -> Result<(), Error> {
+		// strings and numbers are normalized to the numeric options
+		let config = config!(MaxAge("1h"), MaxFileSize("1mib"));
+		assert_eq!(config.get_or_u64(&CN::MaxAgeMillis, 0), 3_600_000);
+		assert_eq!(config.get_or_u64(&CN::MaxSizeBytes, 0), 1_048_576);
+		assert!(config
+			.check_config(vec![CN::MaxAgeMillis, CN::MaxSizeBytes], vec![])
+			.is_ok());
+
+		let millis: u64 = 250;
+		let config = config!(MaxAge(millis), MaxFileSize(10));
+		assert_eq!(config.get_or_u64(&CN::MaxAgeMillis, 0), 250);
+		assert_eq!(config.get_or_u64(&CN::MaxSizeBytes, 0), 10);
+
+		// the human and numeric forms are the same option
+		let config = config!(MaxAge("1h"), MaxAgeMillis(10));
+		assert!(config.check_config(vec![CN::MaxAgeMillis], vec![]).is_err());
+		let config = config!(MaxFileSize("1kb"));
+		assert!(config.check_config(vec![CN::MaxAgeMillis], vec![]).is_err());
+		assert!(config
+			.check_config(vec![CN::MaxSizeBytes], vec![CN::MaxSizeBytes])
+			.is_ok());
+
+		// equal values are not reported as a change
+		assert_eq!(
+			config!(MaxAge("1s")).diff(&*config!(MaxAgeMillis(1_000))),
+			vec![]
+		);
+
+		// a bad string is reported by check_config with the option name
+		let config = config!(MaxAge("1 eon"));
+		assert_eq!(config.get_or_u64(&CN::MaxAgeMillis, 7), 7);
+		let e = config
+			.check_config(vec![CN::MaxAgeMillis], vec![])
+			.unwrap_err();
+		assert!(e.to_string().contains("MaxAge"));
+		assert!(e.to_string().contains("'1 eon'"));
+
+		let config = config!(MaxFileSize("lots"));
+		let e = config
+			.check_config(vec![CN::MaxSizeBytes], vec![])
+			.unwrap_err();
+		assert!(e.to_string().contains("MaxFileSize"));
+		assert!(e.to_string().contains("'lots'"));
+
+		Ok(())
+	}
 }
@@ -17,7 +17,7 @@
 // limitations under the License.
 
 use bmw_err::Error;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::path::PathBuf;
 
@@ -380,6 +380,102 @@ pub trait Config {
 	) -> Result<(), Error>;
 
 	fn get_multi(&self, name: &ConfigOptionName) -> Vec<ConfigOption>;
+
+	/// Returns the [`crate::ConfigOption`]s of this configuration in the order that they were
+	/// specified.
+	fn options(&self) -> Vec<ConfigOption>;
+
+	/// Compares this configuration with another one and returns the options that differ. This
+	/// can be used to log what changed when a configuration is updated at runtime.
+	///
+	/// # Input Parameters
+	/// * other (`&dyn Config`) - The configuration to compare this one with.
+	///
+	/// # Returns
+	/// `Vec<ConfigDiffEntry>` - One [`crate::ConfigDiffEntry`] for each option that is
+	/// specified in only one of the configurations or that has a different value in each. The
+	/// entries are in the order of [`crate::ConfigOptionName`]. If an option is specified more
+	/// than once, its last value is compared.
+	///
+	/// # Examples
+	///
+	///```
+	/// use bmw_conf::*;
+	/// use bmw_err::*;
+	///
+	/// fn main() -> Result<(), Error> {
+	///     let old = config!(SlabCount(10), SlabSize(100), AutoRotate(true));
+	///     let new = config!(SlabCount(20), SlabSize(100), Debug(true));
+	///
+	///     let diff = old.diff(&*new);
+	///     assert_eq!(diff.len(), 3);
+	///     assert_eq!(diff[0].name, ConfigOptionName::AutoRotate);
+	///     assert_eq!(diff[0].old, Some("true".to_string()));
+	///     assert_eq!(diff[0].new, None);
+	///     assert_eq!(diff[1].name, ConfigOptionName::SlabCount);
+	///     assert_eq!(diff[1].old, Some("10".to_string()));
+	///     assert_eq!(diff[1].new, Some("20".to_string()));
+	///     assert_eq!(diff[2].name, ConfigOptionName::Debug);
+	///     assert_eq!(diff[2].old, None);
+	///     assert_eq!(diff[2].new, Some("true".to_string()));
+	///
+	///     Ok(())
+	/// }
+	///```
+	fn diff(&self, other: &dyn Config) -> Vec<ConfigDiffEntry>;
+}
+
+/// A difference between two configurations. See [`crate::Config::diff`].
+#[derive(PartialEq, Clone, Debug)]
+pub struct ConfigDiffEntry {
+	/// The name of the option that differs.
+	pub name: ConfigOptionName,
+	/// The value of the option in the original configuration or [`std::option::Option::None`]
+	/// if it was not specified.
+	pub old: Option<String>,
+	/// The value of the option in the other configuration or [`std::option::Option::None`] if
+	/// it was not specified.
+	pub new: Option<String>,
+}
+
+/// A configuration recorded by [`crate::ConfigHistory::record`].
+#[derive(Clone, Debug)]
+pub struct ConfigSnapshot {
+	pub(crate) timestamp_millis: u128,
+	pub(crate) config: ConfigImpl,
+	pub(crate) changes: Vec<ConfigDiffEntry>,
+}
+
+/// A bounded history of the snapshots of a configuration. Each call to
+/// [`crate::ConfigHistory::record`] stores a snapshot along with the time it was recorded and
+/// the differences from the previous snapshot. Once the history is full, the oldest snapshot
+/// is evicted. This is used to keep an audit trail of options that are changed at runtime.
+///
+/// # Examples
+///
+///```
+/// use bmw_conf::*;
+/// use bmw_err::*;
+///
+/// fn main() -> Result<(), Error> {
+///     let mut history = ConfigHistory::new(2)?;
+///     history.record(&*config!(SlabCount(10)))?;
+///     let changes = history.record(&*config!(SlabCount(20)))?;
+///     assert_eq!(changes.len(), 1);
+///     assert_eq!(changes[0].old, Some("10".to_string()));
+///     assert_eq!(changes[0].new, Some("20".to_string()));
+///
+///     // the first snapshot is evicted
+///     history.record(&*config!(SlabCount(30)))?;
+///     assert_eq!(history.entries().count(), 2);
+///
+///     Ok(())
+/// }
+///```
+#[derive(Clone, Debug)]
+pub struct ConfigHistory {
+	pub(crate) capacity: usize,
+	pub(crate) snapshots: VecDeque<ConfigSnapshot>,
 }
 
 /// Names of configuration options used throughout BMW via macro. This correspondes to the values
@@ -404,10 +500,16 @@ pub enum ConfigOptionName {
 	MaxLoadFactor,
 	SlabSize,
 	SlabCount,
+	Shards,
+	BloomBits,
+	DeterministicHashing,
+	BloomHashes,
+	ContiguousThresholdBytes,
 	MinSize,
 	MaxSize,
 	SyncChannelSize,
 	GlobalSlabAllocator,
+	OverflowToHeap,
 	Start,
 	End,
 	MatchId,
@@ -428,7 +530,29 @@ pub enum ConfigOptionName {
 	EvhTimeout,
 	EvhReadSlabSize,
 	EvhReadSlabCount,
+	EvhReadSlabCountPerThread,
 	EvhOutOfSlabsMessage,
+	EvhClientBindAddr,
+	EvhProxyProtocol,
+	EvhProxyHeaderTimeoutMillis,
+	EvhDeferAcceptSecs,
+	EvhRequireDataWithinMillis,
+	EvhMaxWriteBytesPerLoop,
+	EvhFdWarnThresholdPercent,
+	EvhCallbackTimeoutMillis,
+	EvhWatchdogAction,
+	EvhRecvBufferSize,
+	EvhSendBufferSize,
+	EvhStrictSockOpts,
+	EvhCaptureAll,
+	EvhName,
+	EvhStrictOrdering,
+	EvhMaxSlabsPerConnection,
+	EvhSlabQuotaAction,
+	EvhStrictKeys,
+	EvhStartTimeoutMillis,
+	EvhOnAcceptBudgetMicros,
+	EvhMemoryBudgetBytes,
 	HttpContentFile,
 	HttpContentData,
 	HttpAccept,
@@ -475,10 +599,16 @@ pub enum ConfigOption {
 	MaxLoadFactor(f64),
 	SlabSize(usize),
 	SlabCount(usize),
+	Shards(usize),
+	BloomBits(usize),
+	DeterministicHashing(bool),
+	BloomHashes(usize),
+	ContiguousThresholdBytes(usize),
 	MinSize(usize),
 	MaxSize(usize),
 	SyncChannelSize(usize),
 	GlobalSlabAllocator(bool),
+	OverflowToHeap(bool),
 	Start(usize),
 	End(usize),
 	MatchId(usize),
@@ -499,7 +629,29 @@ pub enum ConfigOption {
 	EvhTimeout(u16),
 	EvhReadSlabSize(usize),
 	EvhReadSlabCount(usize),
+	EvhReadSlabCountPerThread(usize),
 	EvhOutOfSlabsMessage(String),
+	EvhClientBindAddr(String),
+	EvhProxyProtocol(bool),
+	EvhProxyHeaderTimeoutMillis(usize),
+	EvhDeferAcceptSecs(usize),
+	EvhRequireDataWithinMillis(usize),
+	EvhMaxWriteBytesPerLoop(usize),
+	EvhFdWarnThresholdPercent(usize),
+	EvhCallbackTimeoutMillis(usize),
+	EvhWatchdogAction(WatchdogAction),
+	EvhRecvBufferSize(usize),
+	EvhSendBufferSize(usize),
+	EvhStrictSockOpts(bool),
+	EvhCaptureAll(String),
+	EvhName(String),
+	EvhStrictOrdering(bool),
+	EvhMaxSlabsPerConnection(usize),
+	EvhSlabQuotaAction(String),
+	EvhStrictKeys(bool),
+	EvhStartTimeoutMillis(usize),
+	EvhOnAcceptBudgetMicros(usize),
+	EvhMemoryBudgetBytes(usize),
 	HttpContentFile(PathBuf),
 	HttpContentData(Vec<u8>),
 	HttpAccept(String),
@@ -523,6 +675,117 @@ pub enum ConfigOption {
 	DebugNoChunks(bool),
 	Debug(bool),
 	DebugLargeSlabCount(bool),
+	// human readable versions of MaxAgeMillis and MaxSizeBytes. They are normalized to the
+	// same option names so they are read with get_or_u64 like the numeric options.
+	MaxAge(DurationVal),
+	MaxFileSize(ByteSizeVal),
+	// borrowed versions of the String options above. These are equivalent to the owned
+	// options and are selected by the config! macro when a string literal is specified.
+	FileHeaderStr(&'static str),
+	RegexStr(&'static str),
+	EvhOutOfSlabsMessageStr(&'static str),
+	EvhClientBindAddrStr(&'static str),
+	EvhCaptureAllStr(&'static str),
+	EvhNameStr(&'static str),
+	EvhSlabQuotaActionStr(&'static str),
+	HttpAcceptStr(&'static str),
+	HttpMethStr(&'static str),
+	HttpVersStr(&'static str),
+	HttpConnectionStr(&'static str),
+	HttpRequestUriStr(&'static str),
+	HttpRequestUrlStr(&'static str),
+	HttpUserAgentStr(&'static str),
+	HostStr(&'static str),
+	AddressStr(&'static str),
+	BaseDirStr(&'static str),
+	ServerNameStr(&'static str),
+}
+
+/// A duration that is normalized to milliseconds. A [`crate::DurationVal`] can be built from a
+/// number of milliseconds or parsed from a human readable string such as "500ms", "90s", "15m",
+/// "2h", "1d" or "1w". The unit is case insensitive, may be separated from the number by spaces
+/// and defaults to milliseconds. The number may have a fractional part ("1.5h"), fractions of a
+/// millisecond are truncated.
+///
+/// A string that can't be parsed is kept as is so that [`crate::DurationVal::from`] never fails.
+/// The error is reported when the value is read with [`crate::DurationVal::millis`] or when the
+/// configuration is checked with [`crate::Config::check_config`].
+///
+/// # Examples
+///
+///```
+/// use bmw_conf::*;
+/// use bmw_err::*;
+///
+/// fn main() -> Result<(), Error> {
+///     assert_eq!(DurationVal::parse("1.5h")?.millis()?, 90 * 60 * 1_000);
+///     assert_eq!(DurationVal::from(250).millis()?, 250);
+///     assert!(DurationVal::from("15 parsecs").millis().is_err());
+///
+///     // the option is normalized to MaxAgeMillis
+///     let config = config!(MaxAge("1h"));
+///     assert_eq!(config.get_or_u64(&ConfigOptionName::MaxAgeMillis, 0), 3_600_000);
+///
+///     Ok(())
+/// }
+///```
+#[derive(PartialEq, Eq, Clone)]
+pub struct DurationVal {
+	// the value in milliseconds or the text that could not be parsed
+	pub(crate) value: Result<u64, String>,
+}
+
+/// A number of bytes that can be parsed from a human readable string such as "512b", "64kb",
+/// "1mb" or "2gib". The decimal units kb, mb, gb and tb are powers of 1000 and the binary units
+/// kib, mib, gib and tib are powers of 1024. The unit is case insensitive, may be separated from
+/// the number by spaces and defaults to bytes. The number may have a fractional part ("1.5kib"),
+/// fractions of a byte are truncated. Like [`crate::DurationVal`], a string that can't be parsed
+/// is reported when the value is read or the configuration is checked.
+///
+/// # Examples
+///
+///```
+/// use bmw_conf::*;
+/// use bmw_err::*;
+///
+/// fn main() -> Result<(), Error> {
+///     assert_eq!(ByteSizeVal::parse("64kb")?.bytes()?, 64_000);
+///     assert_eq!(ByteSizeVal::parse("2GiB")?.bytes()?, 2 * 1024 * 1024 * 1024);
+///
+///     // the option is normalized to MaxSizeBytes
+///     let config = config!(MaxFileSize("1mib"));
+///     assert_eq!(config.get_or_u64(&ConfigOptionName::MaxSizeBytes, 0), 1024 * 1024);
+///
+///     Ok(())
+/// }
+///```
+#[derive(PartialEq, Eq, Clone)]
+pub struct ByteSizeVal {
+	// the value in bytes or the text that could not be parsed
+	pub(crate) value: Result<u64, String>,
+}
+
+/// The action that the watchdog of an event handler takes when a callback has been running for
+/// longer than `EvhCallbackTimeoutMillis`. See the `EvhWatchdogAction` option of the bmw_evh
+/// crate.
+///
+/// # Examples
+///
+///```
+/// use bmw_conf::*;
+///
+/// let config = config!(EvhWatchdogAction(WatchdogAction::SpawnReplacement));
+/// assert_eq!(
+///     config.get(&ConfigOptionName::EvhWatchdogAction),
+///     Some(ConfigOption::EvhWatchdogAction(WatchdogAction::SpawnReplacement))
+/// );
+///```
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum WatchdogAction {
+	/// Only log the callback and count the trip.
+	Log,
+	/// Also start a thread that takes over the connections of the stuck thread.
+	SpawnReplacement,
 }
 
 /// A builder struct which can be used to build configs. This is typically done using the
@@ -21,3 +21,4 @@ mod public;
 mod test;
 
 pub use crate::public::*;
+pub use bmw_conf::{ByteSizeVal, DurationVal};
@@ -16,8 +16,29 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+/// Build a [`crate::Configurable`] struct from a list of its options. When the options are
+/// listed inline (`vec![Option1(value), ...]`), each value is converted to the type of its
+/// option with [`crate::OptionValue`] so that human readable fields accept a number or a
+/// string. An error is returned if an option is specified more than once (unless it is a
+/// Vec), if a required option is missing, if a human readable value can't be parsed or if
+/// [`crate::Configurable::normalize`] or [`crate::Configurable::validate`] fails.
 #[macro_export]
 macro_rules! config {
+	( $configurable:ident, $enum_name:ident, vec![$($name:ident($($value:tt)*)),* $(,)?] ) => {{
+		bmw_conf2::config_impl!(
+			$configurable,
+			$enum_name,
+			vec![$($name(bmw_conf2::OptionValue::option_value($($value)*))),*]
+		)
+	}};
+	( $configurable:ident, $enum_name:ident, $vec:expr ) => {{
+		bmw_conf2::config_impl!($configurable, $enum_name, $vec)
+	}};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! config_impl {
 	( $configurable:ident, $enum_name:ident, $vec:expr ) => {{
 		use bmw_conf2::Configurable;
 		use bmw_err::*;
@@ -37,6 +58,9 @@ macro_rules! config {
 				err = Some(Err(err!(ErrKind::Configuration, text)));
 			}
 			name_set.insert(name.to_string());
+			if let Err(e) = cfg.check_value() {
+				err = Some(Err(e));
+			}
 			match cfg.value_u8() {
 				Some(value) => ret.set_u8(name, value),
 				None => {}
@@ -84,7 +108,13 @@ macro_rules! config {
 
 		match err {
 			Some(e) => e,
-			None => Ok(ret),
+			None => match ret.normalize() {
+				Ok(_) => match ret.validate() {
+					Ok(_) => Ok(ret),
+					Err(e) => Err(e),
+				},
+				Err(e) => Err(e),
+			},
 		}
 	}};
 }
@@ -16,6 +16,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use bmw_conf::{ByteSizeVal, DurationVal};
+use bmw_err::Error;
 use std::collections::HashSet;
 
 pub trait Configurable {
@@ -29,4 +31,57 @@ pub trait Configurable {
 	fn set_bool(&mut self, name: &str, value: bool);
 	fn set_string_tuple(&mut self, name: &str, value: (String, String));
 	fn allow_dupes(&self) -> HashSet<String>;
+	/// Check constraints that involve more than one field. This is called by [`crate::config!`]
+	/// after all options have been applied and the duplicate and required checks have passed,
+	/// so it sees the fully merged struct including defaults. The default implementation
+	/// accepts any configuration.
+	fn validate(&self) -> Result<(), Error> {
+		Ok(())
+	}
+	/// Check or rearrange the values of Vec fields. This is called by [`crate::config!`] after
+	/// all options have been applied and before [`Configurable::validate`]. The derived
+	/// implementation applies the `#[unique]`, `#[unique_by_first]` and `#[sorted]` field
+	/// attributes. The default implementation leaves the struct unchanged.
+	fn normalize(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+}
+
+/// Converts the value passed to an option in [`crate::config!`] into the type of the option.
+/// Every type converts into itself. Fields annotated with `#[human_duration]` or
+/// `#[human_bytes]` take a [`crate::DurationVal`] or a [`crate::ByteSizeVal`], which can be
+/// specified with either a number or a human readable string (e.g. `MaxAge("1h")` or
+/// `MaxAge(3_600_000)`).
+pub trait OptionValue<T> {
+	fn option_value(self) -> T;
+}
+
+impl<T> OptionValue<T> for T {
+	fn option_value(self) -> T {
+		self
+	}
+}
+
+impl OptionValue<DurationVal> for u64 {
+	fn option_value(self) -> DurationVal {
+		DurationVal::from(self)
+	}
+}
+
+impl OptionValue<DurationVal> for &str {
+	fn option_value(self) -> DurationVal {
+		DurationVal::from(self)
+	}
+}
+
+impl OptionValue<ByteSizeVal> for u64 {
+	fn option_value(self) -> ByteSizeVal {
+		ByteSizeVal::from(self)
+	}
+}
+
+impl OptionValue<ByteSizeVal> for &str {
+	fn option_value(self) -> ByteSizeVal {
+		ByteSizeVal::from(self)
+	}
 }
@@ -248,6 +248,10 @@ mod test {
 				MyConfig_Options::v7(v) => Some(v.clone()),
 			}
 		}
+
+		fn check_value(&self) -> Result<(), Error> {
+			Ok(())
+		}
 	}
 
 	#[test]
@@ -417,6 +421,10 @@ mod test {
 		fn value_string_tuple(&self) -> Option<(String, String)> {
 			None
 		}
+
+		fn check_value(&self) -> Result<(), Error> {
+			Ok(())
+		}
 	}
 
 	#[test]
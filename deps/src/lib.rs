@@ -63,6 +63,7 @@ pub use rand_core;
 pub use random_string;
 pub use ring;
 pub use sha1;
+pub use siphasher;
 pub use substring;
 pub use url;
 pub use url_path;
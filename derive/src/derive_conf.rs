@@ -17,10 +17,11 @@
 // limitations under the License.
 
 use crate::types::ConfMacroState as MacroState;
+use crate::types::{ConfFieldKind, ConfHuman, ConfVecAttr};
 use bmw_deps::convert_case::{Case, Casing};
 use bmw_err::{err, Error};
 use proc_macro::TokenTree::*;
-use proc_macro::{Group, TokenStream, TokenTree};
+use proc_macro::{Delimiter, Group, TokenStream, TokenTree};
 
 const DEBUG: bool = false;
 
@@ -67,6 +68,41 @@ impl MacroState {
 			string_configs: vec![],
 			bool_configs: vec![],
 			string_tuple_configs: vec![],
+			fields: vec![],
+			validate: None,
+			human: vec![],
+			vec_attrs: vec![],
+		}
+	}
+
+	// the human readable form of a field if it has a #[human_duration] or #[human_bytes]
+	fn human(&self, field: &str) -> Option<ConfHuman> {
+		self.human
+			.iter()
+			.find(|(name, _)| name == field)
+			.map(|(_, human)| *human)
+	}
+
+	// the type of the option for a u64 or usize field
+	fn option_type(&self, field: &str, ty: &str) -> String {
+		match self.human(field) {
+			Some(ConfHuman::Duration) => "bmw_conf2::DurationVal".to_string(),
+			Some(ConfHuman::Bytes) => "bmw_conf2::ByteSizeVal".to_string(),
+			None => ty.to_string(),
+		}
+	}
+
+	// the expression that reads a u64 or usize value from the option `v`
+	fn option_number(&self, field: &str, ty: &str) -> String {
+		let value = match self.human(field) {
+			Some(ConfHuman::Duration) => "v.millis().ok()",
+			Some(ConfHuman::Bytes) => "v.bytes().ok()",
+			None => return "Some(*v)".to_string(),
+		};
+		if ty == "usize" {
+			format!("{}.and_then(|v| usize::try_from(v).ok())", value)
+		} else {
+			value.to_string()
 		}
 	}
 
@@ -450,10 +486,11 @@ impl MacroState {
 						ret = "\n\t\tmatch self {".to_string();
 					}
 					ret = format!(
-						"{}\n\t\t\t{}_Options::{}(v) => Some(*v),",
+						"{}\n\t\t\t{}_Options::{}(v) => {},",
 						ret,
 						name,
-						config.0.to_case(Case::Pascal)
+						config.0.to_case(Case::Pascal),
+						self.option_number(&config.0, "u64")
 					);
 				}
 				for config_vec in vec![
@@ -548,10 +585,11 @@ impl MacroState {
 						ret = "\n\t\tmatch self {".to_string();
 					}
 					ret = format!(
-						"{}\n\t\t\t{}_Options::{}(v) => Some(*v),",
+						"{}\n\t\t\t{}_Options::{}(v) => {},",
 						ret,
 						name,
-						config.0.to_case(Case::Pascal)
+						config.0.to_case(Case::Pascal),
+						self.option_number(&config.0, "usize")
 					);
 				}
 				for config_vec in vec![
@@ -757,7 +795,11 @@ impl MacroState {
 			ret = format!(
 				"{}{}",
 				ret,
-				format!("\n\t{}(u64),", config.0.to_case(Case::Pascal))
+				format!(
+					"\n\t{}({}),",
+					config.0.to_case(Case::Pascal),
+					self.option_type(&config.0, "u64")
+				)
 			);
 		}
 		for config in &self.u128_configs {
@@ -771,7 +813,11 @@ impl MacroState {
 			ret = format!(
 				"{}{}",
 				ret,
-				format!("\n\t{}(usize),", config.0.to_case(Case::Pascal))
+				format!(
+					"\n\t{}({}),",
+					config.0.to_case(Case::Pascal),
+					self.option_type(&config.0, "usize")
+				)
 			);
 		}
 		for config in &self.string_configs {
@@ -914,6 +960,150 @@ impl MacroState {
 		ret
 	}
 
+	fn build_to_json(&self) -> String {
+		let mut ret = "\n\t\t#[allow(dead_code)]\
+			\n\t\tfn json_string(value: &str) -> String {\
+			\n\t\t\tlet mut ret = \"\\\"\".to_string();\
+			\n\t\t\tfor c in value.chars() {\
+			\n\t\t\t\tmatch c {\
+			\n\t\t\t\t\t'\"' => ret.push_str(\"\\\\\\\"\"),\
+			\n\t\t\t\t\t'\\\\' => ret.push_str(\"\\\\\\\\\"),\
+			\n\t\t\t\t\t'\\n' => ret.push_str(\"\\\\n\"),\
+			\n\t\t\t\t\t'\\r' => ret.push_str(\"\\\\r\"),\
+			\n\t\t\t\t\t'\\t' => ret.push_str(\"\\\\t\"),\
+			\n\t\t\t\t\tc if (c as u32) < 0x20 => ret.push_str(&format!(\"\\\\u{:04x}\", c as u32)),\
+			\n\t\t\t\t\tc => ret.push(c),\
+			\n\t\t\t\t}\
+			\n\t\t\t}\
+			\n\t\t\tret.push('\"');\
+			\n\t\t\tret\
+			\n\t\t}\
+			\n\t\t#[allow(unused_mut)]\
+			\n\t\tlet mut fields: Vec<String> = vec![];"
+			.to_string();
+		for (name, kind, is_vec) in &self.fields {
+			let value = match kind {
+				ConfFieldKind::Number => "format!(\"{}\", v)",
+				ConfFieldKind::String => "json_string(v)",
+				ConfFieldKind::StringTuple => {
+					"format!(\"[{},{}]\", json_string(&v.0), json_string(&v.1))"
+				}
+			};
+			let value = if *is_vec {
+				format!(
+					"format!(\"[{{}}]\", self.{}.iter().map(|v| {}).collect::<Vec<String>>().join(\",\"))",
+					name, value
+				)
+			} else {
+				format!("{{ let v = &self.{}; {} }}", name, value)
+			};
+			ret = format!(
+				"{}\n\t\tfields.push(format!(\"\\\"{}\\\":{{}}\", {}));",
+				ret, name, value
+			);
+		}
+		ret = format!(
+			"{}\n\t\tformat!(\"{{{{{{}}}}}}\", fields.join(\",\"))\n",
+			ret
+		);
+		ret
+	}
+
+	fn build_field_names(&self) -> String {
+		let mut ret = "".to_string();
+		for (name, _, _) in &self.fields {
+			ret = format!("{}\n\t\t\t\"{}\",", ret, name);
+		}
+		ret
+	}
+
+	// report human readable values that can't be parsed, naming the option
+	fn build_check_value(&self) -> String {
+		let name = match &self.name {
+			Some(name) => name,
+			None => return "Ok(())".to_string(),
+		};
+		let mut ret = "".to_string();
+		for config in self.u64_configs.iter().chain(self.usize_configs.iter()) {
+			if self.human(&config.0).is_some() {
+				let option = config.0.to_case(Case::Pascal);
+				ret = format!(
+					"{}\n\t\tif let {}_Options::{}(v) = self {{ v.check(\"{}\")?; }}",
+					ret, name, option, option
+				);
+			}
+		}
+		format!("{}\n\t\tOk(())\n", ret)
+	}
+
+	fn build_validate(&self) -> String {
+		match &self.validate {
+			Some(f) => format!(
+				"\tfn validate(&self) -> Result<(), bmw_err::Error> {{ self.{}() }}\n",
+				f
+			),
+			None => "".to_string(),
+		}
+	}
+
+	// apply #[unique_by_first], #[unique] and #[sorted] (in that order) to each Vec field
+	fn build_normalize(&self) -> String {
+		if self.vec_attrs.is_empty() {
+			return "".to_string();
+		}
+		let mut ret = "".to_string();
+		for (name, kind, is_vec) in &self.fields {
+			let option = name.to_case(Case::Pascal);
+			for attr in [
+				ConfVecAttr::UniqueByFirst,
+				ConfVecAttr::Unique,
+				ConfVecAttr::Sorted,
+			] {
+				if !self.vec_attrs.contains(&(name.clone(), attr)) {
+					continue;
+				}
+				let code = match attr {
+					_ if !is_vec => format!(
+						"compile_error!(\"{} must be a Vec to be unique or sorted\");",
+						name
+					),
+					ConfVecAttr::UniqueByFirst => match kind {
+						ConfFieldKind::StringTuple => format!(
+							"let mut values: Vec<(String, String)> = vec![];\
+							\n\t\tfor v in std::mem::take(&mut self.{}) {{\
+							\n\t\t\tmatch values.iter_mut().find(|x| x.0 == v.0) {{\
+							\n\t\t\t\tSome(x) => *x = v,\
+							\n\t\t\t\tNone => values.push(v),\
+							\n\t\t\t}}\
+							\n\t\t}}\
+							\n\t\tself.{} = values;",
+							name, name
+						),
+						_ => format!(
+							"compile_error!(\"{} must be a Vec<(String, String)> to be unique_by_first\");",
+							name
+						),
+					},
+					ConfVecAttr::Unique => format!(
+						"for (i, v) in self.{}.iter().enumerate() {{\
+						\n\t\t\tif self.{}[..i].contains(v) {{\
+						\n\t\t\t\tlet text = format!(\"config option ({}) has a duplicate value: {{:?}}\", v);\
+						\n\t\t\t\treturn Err(bmw_err::err!(bmw_err::ErrKind::Configuration, text));\
+						\n\t\t\t}}\
+						\n\t\t}}",
+						name, name, option
+					),
+					ConfVecAttr::Sorted => format!("self.{}.sort();", name),
+				};
+				ret = format!("{}\n\t\t{}", ret, code);
+			}
+		}
+		format!(
+			"\tfn normalize(&mut self) -> Result<(), bmw_err::Error> {{ {}\n\t\tOk(())\n\t}}\n",
+			ret
+		)
+	}
+
 	fn anon_lifetime(&self) -> String {
 		if self.string_configs.len() > 0 || self.string_tuple_configs.len() > 0 {
 			"<'_>".to_string()
@@ -939,6 +1129,10 @@ impl MacroState {
 				\tpub fn required() -> Vec<String> {{\n\
 					\t\tvec![{}\n\t\t]\n\
 				\t}}\n\
+				\tpub fn to_json(&self) -> String {{ {}\t}}\n\
+				\tpub fn field_names() -> &'static [&'static str] {{\n\
+					\t\t&[{}\n\t\t]\n\
+				\t}}\n\
 			}}\n\
 			\n\
                         #[derive(PartialEq, Debug)]\n\
@@ -956,6 +1150,8 @@ impl MacroState {
 				\tfn set_string_tuple(&mut self, name: &str, value: (String, String)) {{ {}\t}}\n\
 				\tfn set_bool(&mut self, name: &str, value: bool) {{ {}\t}}\n\
 				\tfn allow_dupes(&self) -> std::collections::HashSet<String> {{ {}\t}}\n\
+				{}\
+				{}\
 			}}\n\
 			\n\
 		        impl {}_Options {} {{\n\
@@ -969,10 +1165,13 @@ impl MacroState {
                                 \tpub fn value_string(&self) -> Option<String> {{ {}\t}}\n\
                                 \tpub fn value_bool(&self) -> Option<bool> {{ {}\t}}\n\
                                 \tpub fn value_string_tuple(&self) -> Option<(String, String)> {{ {}\t}}\n\
+                                \tpub fn check_value(&self) -> Result<(), bmw_err::Error> {{ {}\t}}\n\
 			}}\n\
 			",
 				name,
                                 self.build_required(),
+                                self.build_to_json(),
+                                self.build_field_names(),
                                 name,
                                 self.named_lifetime(),
                                 self.build_options_enum(),
@@ -987,6 +1186,8 @@ impl MacroState {
                                 self.build_set_string_tuple(),
                                 self.build_set_bool(),
                                 self.build_allow_dupes(),
+                                self.build_validate(),
+                                self.build_normalize(),
 				name,
                                 self.anon_lifetime(),
                                 self.build_name_fn(),
@@ -999,6 +1200,7 @@ impl MacroState {
                                 self.build_value_string(),
                                 self.build_value_bool(),
                                 self.build_value_string_tuple(),
+                                self.build_check_value(),
 			),
 			None => "".to_string(),
 		}
@@ -1047,7 +1249,9 @@ fn process_token_tree(tree: TokenTree, state: &mut MacroState) -> Result<(), Err
 		}
 		Group(group) => {
 			debug!("group={}", group)?;
-			process_group(group, state)?;
+			if !process_validate(&group, state)? {
+				process_group(group, state)?;
+			}
 		}
 		Literal(literal) => {
 			debug!("literal={}", literal)?;
@@ -1061,9 +1265,28 @@ fn process_token_tree(tree: TokenTree, state: &mut MacroState) -> Result<(), Err
 	Ok(())
 }
 
+// check for a struct level #[validate(fn_name)] attribute. Returns true if the group was one.
+fn process_validate(group: &Group, state: &mut MacroState) -> Result<bool, Error> {
+	if group.delimiter() != Delimiter::Bracket {
+		return Ok(false);
+	}
+	let mut items = group.stream().into_iter();
+	match (items.next(), items.next()) {
+		(Some(Ident(ident)), Some(Group(args))) if ident.to_string() == "validate" => {
+			let f = args.stream().to_string();
+			debug!("validate fn = {}", f)?;
+			state.validate = Some(f);
+			Ok(true)
+		}
+		_ => Ok(false),
+	}
+}
+
 fn process_group(group: Group, state: &mut MacroState) -> Result<(), Error> {
 	let mut last_name: Option<(String, bool)> = None;
 	let mut required = false;
+	let mut human = None;
+	let mut vec_attrs = vec![];
 	let mut in_vec = false;
 	for item in group.stream() {
 		match item {
@@ -1071,7 +1294,8 @@ fn process_group(group: Group, state: &mut MacroState) -> Result<(), Error> {
 				let ident_str = ident.to_string();
 				debug!("ident: {}", ident_str)?;
 				if ident_str != "pub"
-					&& ident_str != "u8" && ident_str != "u16"
+					&& ident_str != "u8"
+					&& ident_str != "u16"
 					&& ident_str != "u32"
 					&& ident_str != "u64"
 					&& ident_str != "u128"
@@ -1083,53 +1307,99 @@ fn process_group(group: Group, state: &mut MacroState) -> Result<(), Error> {
 					debug!("name: {}", ident)?;
 					last_name = Some((ident_str.clone(), required));
 					required = false;
+					if let Some(human) = human.take() {
+						state.human.push((ident_str.clone(), human));
+					}
+					for attr in vec_attrs.drain(..) {
+						state.vec_attrs.push((ident_str.clone(), attr));
+					}
 				}
 
 				if ident_str == "u8" {
 					match last_name {
-						Some(ref v) => state.u8_configs.push((v.0.clone(), v.1, in_vec)),
+						Some(ref v) => {
+							state.u8_configs.push((v.0.clone(), v.1, in_vec));
+							state
+								.fields
+								.push((v.0.clone(), ConfFieldKind::Number, in_vec));
+						}
 						None => {}
 					}
 				}
 				if ident_str == "u16" {
 					match last_name {
-						Some(ref v) => state.u16_configs.push((v.0.clone(), v.1, in_vec)),
+						Some(ref v) => {
+							state.u16_configs.push((v.0.clone(), v.1, in_vec));
+							state
+								.fields
+								.push((v.0.clone(), ConfFieldKind::Number, in_vec));
+						}
 						None => {}
 					}
 				}
 				if ident_str == "u32" {
 					match last_name {
-						Some(ref v) => state.u32_configs.push((v.0.clone(), v.1, in_vec)),
+						Some(ref v) => {
+							state.u32_configs.push((v.0.clone(), v.1, in_vec));
+							state
+								.fields
+								.push((v.0.clone(), ConfFieldKind::Number, in_vec));
+						}
 						None => {}
 					}
 				}
 				if ident_str == "u64" {
 					match last_name {
-						Some(ref v) => state.u64_configs.push((v.0.clone(), v.1, in_vec)),
+						Some(ref v) => {
+							state.u64_configs.push((v.0.clone(), v.1, in_vec));
+							state
+								.fields
+								.push((v.0.clone(), ConfFieldKind::Number, in_vec));
+						}
 						None => {}
 					}
 				}
 				if ident_str == "u128" {
 					match last_name {
-						Some(ref v) => state.u128_configs.push((v.0.clone(), v.1, in_vec)),
+						Some(ref v) => {
+							state.u128_configs.push((v.0.clone(), v.1, in_vec));
+							state
+								.fields
+								.push((v.0.clone(), ConfFieldKind::Number, in_vec));
+						}
 						None => {}
 					}
 				}
 				if ident_str == "usize" {
 					match last_name {
-						Some(ref v) => state.usize_configs.push((v.0.clone(), v.1, in_vec)),
+						Some(ref v) => {
+							state.usize_configs.push((v.0.clone(), v.1, in_vec));
+							state
+								.fields
+								.push((v.0.clone(), ConfFieldKind::Number, in_vec));
+						}
 						None => {}
 					}
 				}
 				if ident_str == "String" {
 					match last_name {
-						Some(ref v) => state.string_configs.push((v.0.clone(), v.1, in_vec)),
+						Some(ref v) => {
+							state.string_configs.push((v.0.clone(), v.1, in_vec));
+							state
+								.fields
+								.push((v.0.clone(), ConfFieldKind::String, in_vec));
+						}
 						None => {}
 					}
 				}
 				if ident_str == "bool" {
 					match last_name {
-						Some(ref v) => state.bool_configs.push((v.0.clone(), v.1, in_vec)),
+						Some(ref v) => {
+							state.bool_configs.push((v.0.clone(), v.1, in_vec));
+							state
+								.fields
+								.push((v.0.clone(), ConfFieldKind::Number, in_vec));
+						}
 						None => {}
 					}
 				}
@@ -1144,12 +1414,32 @@ fn process_group(group: Group, state: &mut MacroState) -> Result<(), Error> {
 					debug!("found a required")?;
 					required = true;
 				}
+				if item_str == "[human_duration]" {
+					human = Some(ConfHuman::Duration);
+				}
+				if item_str == "[human_bytes]" {
+					human = Some(ConfHuman::Bytes);
+				}
+				if item_str == "[unique]" {
+					vec_attrs.push(ConfVecAttr::Unique);
+				}
+				if item_str == "[unique_by_first]" {
+					vec_attrs.push(ConfVecAttr::UniqueByFirst);
+				}
+				if item_str == "[sorted]" {
+					vec_attrs.push(ConfVecAttr::Sorted);
+				}
 				if item_str == ">" {
 					in_vec = false;
 				}
 				if item_str == "(String, String)" {
 					match last_name {
-						Some(ref v) => state.string_tuple_configs.push((v.0.clone(), v.1, in_vec)),
+						Some(ref v) => {
+							state.string_tuple_configs.push((v.0.clone(), v.1, in_vec));
+							state
+								.fields
+								.push((v.0.clone(), ConfFieldKind::StringTuple, in_vec));
+						}
 						None => {}
 					}
 				}
@@ -17,7 +17,9 @@
 // limitations under the License.
 
 use crate::types::SerMacroState as MacroState;
+use crate::types::SerVariant;
 use bmw_err::{err, Error};
+use proc_macro::Delimiter;
 use proc_macro::TokenStream;
 use proc_macro::TokenTree;
 use proc_macro::TokenTree::{Group, Ident, Literal, Punct};
@@ -66,21 +68,28 @@ impl MacroState {
 			name: "".to_string(),
 			field_names: vec![],
 			is_enum: false,
+			variants: vec![],
+			fingerprint: false,
+			fingerprint_fields: vec![],
 		}
 	}
 
 	pub(crate) fn ret(&self) -> String {
 		let ret = if self.is_enum {
+			let (read_disc, read_arms, write_arms) = self.enum_arms();
 			format!("impl bmw_ser::Serializable for {} {{ \n\
                                         fn read<R>(reader: &mut R) -> Result<Self, bmw_err::Error> where R: bmw_ser::Reader {{\n\
-                                            Ok(match reader.read_u16()? {{ {} _ => {{\n\
+                                            #[cold]\n\
+                                            fn unexpected_type() -> bmw_err::Error {{\n\
                                             let fmt = \"unexpected type returned in reader\";\n\
-                                            let e = bmw_err::err!(bmw_err::ErrKind::CorruptedData, fmt);\n\
-                                            return Err(e);\n\
+                                            bmw_err::err!(bmw_err::ErrKind::CorruptedData, fmt)\n\
+                                            }}\n\
+                                            Ok(match reader.{}()? {{ {} _ => {{\n\
+                                            return Err(unexpected_type());\n\
                                             }}\n\
                                         }}) }} \n\
                     fn write<W>(&self, writer: &mut W) -> Result<(), bmw_err::Error> where W: bmw_ser::Writer {{ match self {{ {} }} Ok(()) }}\n\
-                    }}", self.name, self.ret_read, self.ret_write)
+                    }}", self.name, read_disc, read_arms, write_arms)
 		} else {
 			let mut field_name_return = "Ok(Self {".to_string();
 			for x in &self.field_names {
@@ -93,11 +102,102 @@ impl MacroState {
                     }}", self.name, self.ret_read, field_name_return, self.ret_write)
 		};
 
+		let ret = if self.fingerprint {
+			format!("{}\n{}", ret, self.fingerprint_impl())
+		} else {
+			ret
+		};
+
 		let _ = debug!("ret='{}'", ret);
 
 		ret
 	}
 
+	// build the match arms for an enum. Unit only enums with no more than 256 variants use a u8
+	// discriminant, all others use a u16. Returns the discriminant's reader function along
+	// with the read and write match arms.
+	fn enum_arms(&self) -> (&str, String, String) {
+		let unit_only = self.variants.len() <= 256
+			&& self
+				.variants
+				.iter()
+				.all(|(_, variant)| matches!(variant, SerVariant::Unit));
+		let (read_disc, write_disc) = if unit_only {
+			("read_u8", "write_u8")
+		} else {
+			("read_u16", "write_u16")
+		};
+
+		let mut read_arms = "".to_string();
+		let mut write_arms = "".to_string();
+		for (i, (name, variant)) in self.variants.iter().enumerate() {
+			let (read, pattern, writes) = match variant {
+				SerVariant::Unit => ("".to_string(), "".to_string(), "".to_string()),
+				SerVariant::Tuple(count) => {
+					let mut reads = "".to_string();
+					let mut names = "".to_string();
+					let mut writes = "".to_string();
+					for j in 0..*count {
+						reads = format!("{}bmw_ser::Serializable::read(reader)?,", reads);
+						names = format!("{}x{},", names, j);
+						writes =
+							format!("{}bmw_ser::Serializable::write(x{}, writer)?;", writes, j);
+					}
+					(format!("({})", reads), format!("({})", names), writes)
+				}
+				SerVariant::Named(fields) => {
+					// bind the fields to generated names so that a field can't shadow
+					// the reader or writer. Fields are read in declaration order.
+					let mut reads = "".to_string();
+					let mut names = "".to_string();
+					let mut writes = "".to_string();
+					for (j, field) in fields.iter().enumerate() {
+						reads =
+							format!("{}{}: bmw_ser::Serializable::read(reader)?,", reads, field);
+						names = format!("{}{}: x{},", names, field, j);
+						writes =
+							format!("{}bmw_ser::Serializable::write(x{}, writer)?;", writes, j);
+					}
+					(
+						format!("{{ {} }}", reads),
+						format!("{{ {} }}", names),
+						writes,
+					)
+				}
+			};
+			read_arms = format!("{}{} => {}::{}{},\n", read_arms, i, self.name, name, read);
+			write_arms = format!(
+				"{}{}::{}{} => {{ writer.{}({})?; {} }},\n",
+				write_arms, self.name, name, pattern, write_disc, i, writes
+			);
+		}
+		(read_disc, read_arms, write_arms)
+	}
+
+	// the SCHEMA_FINGERPRINT constant and schema_fingerprint function generated by
+	// #[ser(fingerprint)]. The hash covers the kind and name of the type and the names and types
+	// of its fields (or variants) in declaration order.
+	fn fingerprint_impl(&self) -> String {
+		let kind = if self.is_enum { "enum" } else { "struct" };
+		let schema = format!(
+			"{} {} {{ {} }}",
+			kind,
+			self.name,
+			self.fingerprint_fields.join(" , ")
+		);
+		let _ = debug!("schema='{}'", schema);
+		format!(
+			"impl {} {{\n\
+			#[doc = \"The schema fingerprint of this type. See bmw_derive.\"]\n\
+			pub const SCHEMA_FINGERPRINT: u64 = {:#018x};\n\
+			#[doc = \"Returns [`Self::SCHEMA_FINGERPRINT`].\"]\n\
+			pub fn schema_fingerprint() -> u64 {{ Self::SCHEMA_FINGERPRINT }}\n\
+			}}",
+			self.name,
+			fnv1a(&schema)
+		)
+	}
+
 	fn append_read(&mut self, s: &str) {
 		self.ret_read = format!("{}{}", self.ret_read, s);
 	}
@@ -155,7 +255,17 @@ fn process_token_tree(tree: TokenTree, state: &mut MacroState) -> Result<(), Err
 			}
 		}
 		Group(group) => {
-			process_group(group, state)?;
+			if group.delimiter() == Delimiter::Bracket {
+				if !ser_attr(&group, state)?.is_empty() {
+					let e = err!(
+						ErrKind::IllegalArgument,
+						"ser(skip) is only supported on fields"
+					);
+					return Err(e);
+				}
+			} else {
+				process_group(group, state)?;
+			}
 		}
 		Literal(literal) => {
 			debug!("literal={}", literal)?;
@@ -173,35 +283,76 @@ fn process_group(group: proc_macro::Group, state: &mut MacroState) -> Result<(),
 
 	let mut expect_name = true;
 	let mut name = "".to_string();
-	let mut has_inner = false;
+	let mut inner_count = 0;
+	let mut named_fields = None;
+	// the tokens that follow the name (the type of a field or the fields of a variant) and
+	// whether the field has the #[ser(skip)] attribute
+	let mut rest = vec![];
+	let mut skip = false;
+	let mut expect_attr = false;
+	let mut angle_depth = 0;
 
 	for item in group.stream() {
+		if expect_attr {
+			expect_attr = false;
+			if let Group(group) = &item {
+				if group.delimiter() == Delimiter::Bracket {
+					skip |= ser_attr(group, state)?.iter().any(|arg| arg == "skip");
+					continue;
+				}
+			}
+		}
 		match item {
 			Ident(ident) => {
-				let ident = ident.to_string();
-				debug!("groupident={}", ident)?;
-				if expect_name && ident != "pub" && ident != "doc" && ident != "crate" {
+				let ident_str = ident.to_string();
+				debug!("groupident={}", ident_str)?;
+				if expect_name && ident_str != "pub" && ident_str != "doc" && ident_str != "crate" {
 					expect_name = false;
-					has_inner = false;
-					name = ident.clone();
+					inner_count = 0;
+					named_fields = None;
+					rest.clear();
+					name = ident_str;
+				} else if !expect_name {
+					rest.push(Ident(ident));
 				}
 			}
 			Group(group) => {
-				// we don't need to process the inner group because the read function
-				// only requires the name, we do use this to determine if there's
-				// an inner value
+				// we don't need to process the types in the inner group because the read
+				// function only requires the names, we do use this to determine how many
+				// inner values there are or the field names of struct-style variants
 				debug!("group={}", group)?;
-				has_inner = true;
+				if !expect_name {
+					match group.delimiter() {
+						Delimiter::Brace => named_fields = Some(field_names(&group)),
+						_ => inner_count = count_inner(&group),
+					}
+					rest.push(Group(group));
+				}
 			}
 			Literal(literal) => {
 				debug!("groupliteral={}", literal)?;
+				if !expect_name {
+					rest.push(Literal(literal));
+				}
 			}
 			Punct(punct) => {
 				debug!("grouppunct={}", punct)?;
-				if punct.to_string() == ",".to_string() {
+				match punct.as_char() {
+					'#' if expect_name => expect_attr = true,
+					'<' => angle_depth += 1,
+					'>' if angle_depth > 0 => angle_depth -= 1,
+					_ => {}
+				}
+				if punct.as_char() == ',' && angle_depth == 0 {
 					debug!("end a name: {}", name)?;
-					process_field(&name, &group, state, has_inner)?;
+					let variant = build_variant(inner_count, named_fields.take());
+					let fingerprint = format!("{} {}", name, canonical(rest.drain(..)));
+					let fingerprint = fingerprint.trim_end().to_string();
+					process_field(&name, &group, state, variant, skip, fingerprint)?;
 					expect_name = true;
+					skip = false;
+				} else if !expect_name {
+					rest.push(Punct(punct));
 				}
 			}
 		}
@@ -210,18 +361,181 @@ fn process_group(group: proc_macro::Group, state: &mut MacroState) -> Result<(),
 	// if there's no trailing comma.
 	if !expect_name {
 		debug!("end name end loop: {}", name)?;
-		process_field(&name, &group, state, has_inner)?;
+		let variant = build_variant(inner_count, named_fields.take());
+		let fingerprint = format!("{} {}", name, canonical(rest.drain(..)));
+		let fingerprint = fingerprint.trim_end().to_string();
+		process_field(&name, &group, state, variant, skip, fingerprint)?;
 	}
 
 	Ok(())
 }
 
+// process an attribute. Attributes other than #[ser(...)] are ignored. Struct level arguments
+// are applied to the state and the other arguments are returned.
+#[cfg(not(tarpaulin_include))]
+fn ser_attr(group: &proc_macro::Group, state: &mut MacroState) -> Result<Vec<String>, Error> {
+	let mut items = group.stream().into_iter();
+	match items.next() {
+		Some(Ident(ident)) if ident.to_string() == "ser" => {}
+		_ => return Ok(vec![]),
+	}
+	let args = match items.next() {
+		Some(Group(args)) if args.delimiter() == Delimiter::Parenthesis => args,
+		_ => {
+			let e = err!(ErrKind::IllegalArgument, "expected #[ser(...)]");
+			return Err(e);
+		}
+	};
+	let mut ret = vec![];
+	for arg in args.stream() {
+		match arg {
+			Ident(ident) => match ident.to_string().as_str() {
+				"fingerprint" => state.fingerprint = true,
+				"skip" => ret.push("skip".to_string()),
+				arg => {
+					let fmt = format!("unknown ser attribute argument: '{}'", arg);
+					return Err(err!(ErrKind::IllegalArgument, fmt));
+				}
+			},
+			Punct(punct) if punct.as_char() == ',' => {}
+			arg => {
+				let fmt = format!("unexpected ser attribute argument: '{}'", arg);
+				return Err(err!(ErrKind::IllegalArgument, fmt));
+			}
+		}
+	}
+	Ok(ret)
+}
+
+// render tokens with a single space between them so that the fingerprint does not depend on
+// how the source is formatted. Attributes (including doc comments) and visibility are left out
+// because they don't change what is written.
+#[cfg(not(tarpaulin_include))]
+fn canonical<I: IntoIterator<Item = TokenTree>>(tokens: I) -> String {
+	let mut ret = vec![];
+	let mut tokens = tokens.into_iter().peekable();
+	while let Some(token) = tokens.next() {
+		match token {
+			Punct(punct) if punct.as_char() == '#' => {
+				if let Some(Group(group)) = tokens.peek() {
+					if group.delimiter() == Delimiter::Bracket {
+						tokens.next();
+					}
+				}
+			}
+			Ident(ident) if ident.to_string() == "pub" => {
+				if let Some(Group(group)) = tokens.peek() {
+					if group.delimiter() == Delimiter::Parenthesis {
+						tokens.next();
+					}
+				}
+			}
+			Group(group) => {
+				let inner = canonical(group.stream());
+				match group.delimiter() {
+					Delimiter::Parenthesis => ret.push(format!("( {} )", inner)),
+					Delimiter::Brace => ret.push(format!("{{ {} }}", inner)),
+					Delimiter::Bracket => ret.push(format!("[ {} ]", inner)),
+					Delimiter::None => ret.push(inner),
+				}
+			}
+			token => ret.push(token.to_string()),
+		}
+	}
+	// a trailing comma is optional
+	if ret.last().map(|token| token == ",").unwrap_or(false) {
+		ret.pop();
+	}
+	ret.join(" ")
+}
+
+// FNV-1a (64 bit). It is evaluated here so the fingerprint has no runtime cost.
+#[cfg(not(tarpaulin_include))]
+fn fnv1a(data: &str) -> u64 {
+	let mut hash = 0xcbf29ce484222325u64;
+	for b in data.bytes() {
+		hash ^= u64::from(b);
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+	hash
+}
+
+// count the comma separated values in a tuple variant's group. Commas nested within generic
+// arguments (e.g. HashMap<K, V>) are not counted.
+#[cfg(not(tarpaulin_include))]
+fn count_inner(group: &proc_macro::Group) -> usize {
+	let mut count = 0;
+	let mut angle_depth = 0;
+	let mut expect_value = true;
+	for item in group.stream() {
+		match item {
+			Punct(punct) if punct.as_char() == '<' => angle_depth += 1,
+			Punct(punct) if punct.as_char() == '>' && angle_depth > 0 => angle_depth -= 1,
+			Punct(punct) if punct.as_char() == ',' && angle_depth == 0 => {
+				expect_value = true;
+				continue;
+			}
+			_ => {}
+		}
+		if expect_value {
+			count += 1;
+			expect_value = false;
+		}
+	}
+	count
+}
+
+// collect the field names of a struct-style variant's group in declaration order. Anything after
+// the ':' up to the next top level comma is the field's type.
+#[cfg(not(tarpaulin_include))]
+fn field_names(group: &proc_macro::Group) -> Vec<String> {
+	let mut names = vec![];
+	let mut angle_depth = 0;
+	let mut expect_name = true;
+	let mut last_ident = None;
+	for item in group.stream() {
+		match item {
+			Ident(ident) if expect_name && angle_depth == 0 => {
+				let ident = ident.to_string();
+				if ident != "pub" {
+					last_ident = Some(ident);
+				}
+			}
+			Punct(punct) if punct.as_char() == '<' => angle_depth += 1,
+			Punct(punct) if punct.as_char() == '>' && angle_depth > 0 => angle_depth -= 1,
+			Punct(punct) if punct.as_char() == ':' && expect_name && angle_depth == 0 => {
+				if let Some(name) = last_ident.take() {
+					names.push(name);
+					expect_name = false;
+				}
+			}
+			Punct(punct) if punct.as_char() == ',' && angle_depth == 0 => {
+				expect_name = true;
+				last_ident = None;
+			}
+			_ => {}
+		}
+	}
+	names
+}
+
+#[cfg(not(tarpaulin_include))]
+fn build_variant(inner_count: usize, named_fields: Option<Vec<String>>) -> SerVariant {
+	match named_fields {
+		Some(fields) => SerVariant::Named(fields),
+		None if inner_count > 0 => SerVariant::Tuple(inner_count),
+		None => SerVariant::Unit,
+	}
+}
+
 #[cfg(not(tarpaulin_include))]
 fn process_field(
 	name: &String,
 	group: &proc_macro::Group,
 	state: &mut MacroState,
-	has_inner: bool,
+	variant: SerVariant,
+	skip: bool,
+	fingerprint: String,
 ) -> Result<(), Error> {
 	if name.len() == 0 {
 		let fmt = format!("expected name for this group: {:?}", group);
@@ -229,44 +543,25 @@ fn process_field(
 		return Err(e);
 	}
 
-	debug!("state.is_enum={},has_inner={}", state.is_enum, has_inner)?;
+	debug!("state.is_enum={}", state.is_enum)?;
 	if state.is_enum {
-		debug!("do an append enum")?;
-		if has_inner {
-			state.append_read(
-				&format!(
-					"{} => {}::{}(Serializable::read(reader)?),\n",
-					state.field_names.len(),
-					state.name,
-					name,
-				)[..],
-			);
-			state.append_write(
-				&format!(
-					"{}::{}(x) => {{ writer.write_u16({})?; Serializable::write(x, writer)?; }},\n",
-					state.name,
-					name,
-					state.field_names.len()
-				)[..],
-			);
-		} else {
-			state.append_read(
-				&format!("{} => {}::{},\n", state.field_names.len(), state.name, name)[..],
-			);
-			state.append_write(
-				&format!(
-					"{}::{} => {{ writer.write_u16({})?; }},\n",
-					state.name,
-					name,
-					state.field_names.len()
-				)[..],
-			);
+		if skip {
+			let fmt = format!("ser(skip) is only supported on struct fields: '{}'", name);
+			return Err(err!(ErrKind::IllegalArgument, fmt));
 		}
+		debug!("do an append enum")?;
+		state.variants.push((name.clone(), variant));
+	} else if skip {
+		// skipped fields are not written and are set to their default value when read
+		state.append_read(&format!("let {} = Default::default();\n", name)[..]);
 	} else {
 		state.append_read(&format!("let {} = bmw_ser::Serializable::read(reader)?;\n", name)[..]);
 		state
 			.append_write(&format!("bmw_ser::Serializable::write(&self.{}, writer)?;\n", name)[..]);
 	}
+	if !skip {
+		state.fingerprint_fields.push(fingerprint);
+	}
 	state.field_names.push(name.clone());
 
 	Ok(())
@@ -38,6 +38,8 @@
 //! enum MyEnum {
 //!     Type1(String),
 //!     Type2(u64),
+//!     Type3 { id: u64, name: String },
+//!     Type4,
 //! }
 //!
 //! fn main() -> Result<(), Error> {
@@ -54,6 +56,42 @@
 //!
 //!```
 //!
+//! Enum variants are written as a discriminant followed by the variant's fields in declaration
+//! order. Enums that only have unit variants (and no more than 256 of them) use a single byte
+//! for the discriminant. All other enums use two bytes.
+//!
+//! Fields and variants may be gated with `#[cfg(...)]`. The compiler removes the ones that are
+//! disabled before the derive runs, so the generated code only mentions what exists on the
+//! target platform. Discriminants are assigned to the remaining variants, so a gated variant
+//! changes the discriminants of the variants that follow it on the platforms where it is absent.
+//! Both this macro and the Configurable macro handle gated fields this way.
+//!
+//! A struct field with the `#[ser(skip)]` attribute is not written. When the struct is read,
+//! the field is set to its [`Default`] value.
+//!
+//! # Schema fingerprints
+//! With the `#[ser(fingerprint)]` attribute, the macro also generates a
+//! `const SCHEMA_FINGERPRINT: u64` and a `fn schema_fingerprint() -> u64` for the type. The
+//! value is a hash, computed when the macro is expanded, of the type's name and the names,
+//! order and types of its fields (or variants). Renaming or reordering fields changes it, while
+//! doc comments, visibility and `#[ser(skip)]` fields do not. Peers can exchange fingerprints
+//! before any payloads to detect that they were built with different layouts.
+//!
+//!```
+//! use bmw_derive::Serializable;
+//!
+//! #[derive(Serializable)]
+//! #[ser(fingerprint)]
+//! struct Hello {
+//!     version: u32,
+//!     name: String,
+//!     #[ser(skip)]
+//!     local_only: Option<u64>,
+//! }
+//!
+//! assert_eq!(Hello::schema_fingerprint(), Hello::SCHEMA_FINGERPRINT);
+//!```
+//!
 //! This macro is used in the bmw_util and other crates within BMW. For additional examples, see the bmw_util documentation.
 //!
 //! # Generics
@@ -67,13 +105,39 @@ use proc_macro::TokenStream;
 
 /// This is a proc macro for implementing the bmw_ser::Serializable trait. See the [`crate`]
 /// documentation for examples.
-#[proc_macro_derive(Serializable)]
+#[proc_macro_derive(Serializable, attributes(ser))]
 #[cfg(not(tarpaulin_include))]
 pub fn derive_serialize(strm: TokenStream) -> TokenStream {
 	do_derive_serialize(strm)
 }
 
-#[proc_macro_derive(Configurable, attributes(required))]
+/// This is a proc macro for implementing the bmw_conf2::Configurable trait. In addition to the
+/// trait, `to_json` is generated which exports the struct's fields as a flat JSON object (numbers
+/// and bools unquoted, strings escaped, Vecs as arrays and (String, String) fields as two element
+/// arrays) along with `field_names` which lists the fields in declaration order. A
+/// `#[validate(fn_name)]` attribute on the struct makes the generated
+/// `Configurable::validate` call `fn_name(&self) -> Result<(), bmw_err::Error>`, which can be
+/// used to enforce constraints between fields. A u64 or usize field annotated with
+/// `#[human_duration]` or `#[human_bytes]` is specified with a `bmw_conf2::DurationVal` or a
+/// `bmw_conf2::ByteSizeVal`, so `config!` accepts either a number or a string such as "90s" or
+/// "64kb" for it. The field itself stores the number of milliseconds or bytes. Vec fields
+/// append each value by default. Once all options are applied, `#[unique_by_first]` makes a
+/// later `(String, String)` value replace the earlier value with the same first element,
+/// `#[unique]` returns an error naming the duplicate if a value appears more than once and
+/// `#[sorted]` sorts the values, which requires an element type that implements `Ord`. These
+/// apply to the values from `Default::default()` as well as the configured ones.
+#[proc_macro_derive(
+	Configurable,
+	attributes(
+		required,
+		validate,
+		human_duration,
+		human_bytes,
+		unique,
+		unique_by_first,
+		sorted
+	)
+)]
 #[cfg(not(tarpaulin_include))]
 pub fn derive_configurable(strm: TokenStream) -> TokenStream {
 	do_derive_configurable(strm)
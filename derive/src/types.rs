@@ -23,6 +23,18 @@ pub(crate) struct SerMacroState {
 	pub(crate) name: String,
 	pub(crate) field_names: Vec<String>,
 	pub(crate) is_enum: bool,
+	pub(crate) variants: Vec<(String, SerVariant)>,
+	// set by #[ser(fingerprint)]
+	pub(crate) fingerprint: bool,
+	// the name and canonical type of each field (or variant) that is part of the fingerprint
+	pub(crate) fingerprint_fields: Vec<String>,
+}
+
+// the shape of an enum variant
+pub(crate) enum SerVariant {
+	Unit,
+	Tuple(usize),
+	Named(Vec<String>),
 }
 
 pub(crate) struct ConfMacroState {
@@ -37,4 +49,35 @@ pub(crate) struct ConfMacroState {
 	pub(crate) string_configs: Vec<(String, bool, bool)>,
 	pub(crate) bool_configs: Vec<(String, bool, bool)>,
 	pub(crate) string_tuple_configs: Vec<(String, bool, bool)>,
+	// all fields in declaration order with their kind and whether they are a Vec
+	pub(crate) fields: Vec<(String, ConfFieldKind, bool)>,
+	// the function named by a #[validate(fn_name)] attribute on the struct
+	pub(crate) validate: Option<String>,
+	// the fields annotated with #[human_duration] or #[human_bytes]
+	pub(crate) human: Vec<(String, ConfHuman)>,
+	// the Vec fields annotated with #[unique], #[unique_by_first] or #[sorted]
+	pub(crate) vec_attrs: Vec<(String, ConfVecAttr)>,
+}
+
+// the human readable form accepted by a u64 or usize field
+#[derive(Clone, Copy)]
+pub(crate) enum ConfHuman {
+	Duration,
+	Bytes,
+}
+
+// how the values of a Vec field are checked or rearranged once all options are applied
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum ConfVecAttr {
+	Unique,
+	UniqueByFirst,
+	Sorted,
+}
+
+// how a field of a Configurable struct is rendered by to_json
+#[derive(Clone, Copy)]
+pub(crate) enum ConfFieldKind {
+	Number,
+	String,
+	StringTuple,
 }
@@ -19,7 +19,7 @@
 #[cfg(unix)]
 use bmw_deps::nix::errno::Errno as NixErrno;
 
-use crate::types::{Error, ErrorKind};
+use crate::types::{ConnectErrorKind, Error, ErrorKind};
 use bmw_deps::failure::{Backtrace, Context, Fail};
 use bmw_deps::url::ParseError;
 use std::alloc::LayoutError;
@@ -68,6 +68,39 @@ impl Error {
 	pub fn inner(&self) -> String {
 		self.inner.to_string()
 	}
+
+	/// get the [`crate::ConnectErrorKind`] of this error. This is only set for errors
+	/// returned by failed outbound connection attempts.
+	pub fn connect_kind(&self) -> Option<ConnectErrorKind> {
+		self.connect_kind
+	}
+
+	/// attach a [`crate::ConnectErrorKind`] to this error.
+	pub fn with_connect_kind(mut self, kind: ConnectErrorKind) -> Self {
+		self.connect_kind = Some(kind);
+		self
+	}
+}
+
+impl ConnectErrorKind {
+	/// classify a raw os error code (errno on unix, WSA error code on windows).
+	pub fn from_raw_os_error(code: i32) -> Self {
+		Self::from(&std::io::Error::from_raw_os_error(code))
+	}
+}
+
+impl From<&std::io::Error> for ConnectErrorKind {
+	fn from(e: &std::io::Error) -> ConnectErrorKind {
+		match e.kind() {
+			std::io::ErrorKind::ConnectionRefused => ConnectErrorKind::Refused,
+			std::io::ErrorKind::HostUnreachable | std::io::ErrorKind::NetworkUnreachable => {
+				ConnectErrorKind::Unreachable
+			}
+			std::io::ErrorKind::TimedOut => ConnectErrorKind::TimedOut,
+			std::io::ErrorKind::AddrInUse => ConnectErrorKind::AddrInUse,
+			_ => ConnectErrorKind::Other(e.raw_os_error().unwrap_or(-1)),
+		}
+	}
 }
 
 // Conversions from other errors to our base error struct are below.
@@ -76,6 +109,7 @@ impl From<ErrorKind> for Error {
 	fn from(kind: ErrorKind) -> Error {
 		Error {
 			inner: Context::new(kind),
+			connect_kind: None,
 		}
 	}
 }
@@ -84,6 +118,7 @@ impl From<std::io::Error> for Error {
 	fn from(e: std::io::Error) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::IO(format!("{}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -92,6 +127,7 @@ impl From<ParseError> for Error {
 	fn from(e: ParseError) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Misc(format!("url::ParseError: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -100,6 +136,7 @@ impl From<OsString> for Error {
 	fn from(e: OsString) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Misc(format!("{:?}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -108,6 +145,7 @@ impl From<TryFromIntError> for Error {
 	fn from(e: TryFromIntError) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Misc(format!("TryFromIntError: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -116,6 +154,7 @@ impl From<ParseIntError> for Error {
 	fn from(e: ParseIntError) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Misc(format!("ParseIntError: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -124,6 +163,7 @@ impl From<Utf8Error> for Error {
 	fn from(e: Utf8Error) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Utf8(format!("Utf8 error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -132,6 +172,7 @@ impl<T> From<PoisonError<RwLockWriteGuard<'_, T>>> for Error {
 	fn from(e: PoisonError<RwLockWriteGuard<'_, T>>) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Poison(format!("Poison error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -140,6 +181,7 @@ impl<T> From<PoisonError<RwLockReadGuard<'_, T>>> for Error {
 	fn from(e: PoisonError<RwLockReadGuard<'_, T>>) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Poison(format!("Poison error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -148,6 +190,7 @@ impl<T> From<PoisonError<MutexGuard<'_, T>>> for Error {
 	fn from(e: PoisonError<MutexGuard<'_, T>>) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Poison(format!("Poison error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -156,6 +199,7 @@ impl From<RecvError> for Error {
 	fn from(e: RecvError) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::IllegalState(format!("Recv error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -164,6 +208,7 @@ impl<T> From<SendError<T>> for Error {
 	fn from(e: SendError<T>) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::IllegalState(format!("Send error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -172,6 +217,7 @@ impl From<LayoutError> for Error {
 	fn from(e: LayoutError) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Alloc(format!("Layout error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -180,6 +226,7 @@ impl From<SystemTimeError> for Error {
 	fn from(e: SystemTimeError) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::SystemTime(format!("System Time error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -189,6 +236,7 @@ impl From<Infallible> for Error {
 	fn from(e: Infallible) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Misc(format!("Infallible: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -198,6 +246,7 @@ impl From<NixErrno> for Error {
 	fn from(e: NixErrno) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Errno(format!("Errno system error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -206,6 +255,7 @@ impl From<FromUtf8Error> for Error {
 	fn from(e: FromUtf8Error) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Misc(format!("utf8 error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -214,6 +264,7 @@ impl From<AddrParseError> for Error {
 	fn from(e: AddrParseError) -> Error {
 		Error {
 			inner: Context::new(ErrorKind::Misc(format!("addr parse error: {}", e))),
+			connect_kind: None,
 		}
 	}
 }
@@ -23,7 +23,9 @@
 //! macro can be used to conveniently map errors from 3rd party crates
 //! into [`crate::ErrorKind`] in this crate. The [`crate::err`] macro
 //! can be used to generate errors. In most cases errors should be created
-//! using one of these two macros.
+//! using one of these two macros. The [`crate::ensure`] and [`crate::try_opt`]
+//! macros are shorthand for returning an [`crate::err`] error when a condition
+//! is false or an option is `None`.
 //!
 //! # Examples
 //!```
@@ -108,6 +110,38 @@
 //!     Ok(())
 //! }
 //!```
+//!
+//! # Conversions
+//!
+//! [`crate::Error`] implements [`std::convert::From`] for the foreign error types that are
+//! commonly encountered in BMW, so the `?` operator can be used on them directly without
+//! [`crate::map_err`]. The display text of the source error becomes the message of the
+//! resulting [`crate::ErrorKind`]. [`std::io::Error`] maps to [`crate::ErrorKind::IO`],
+//! [`std::str::Utf8Error`] maps to [`crate::ErrorKind::Utf8`], lock poisoning maps to
+//! [`crate::ErrorKind::Poison`] and integer, address and utf8 string parsing errors map to
+//! [`crate::ErrorKind::Misc`]. Use
+//! [`crate::map_err`] when a different [`crate::ErrKind`] or an additional message is needed.
+//!
+//!```
+//! use bmw_err::{Error, ErrorKind};
+//! use std::fs::File;
+//! use std::io::Read;
+//! use std::str::from_utf8;
+//!
+//! fn read_config(path: &str) -> Result<String, Error> {
+//!     let mut buf = vec![];
+//!     // std::io::Error is converted into ErrorKind::IO
+//!     File::open(path)?.read_to_end(&mut buf)?;
+//!     // std::str::Utf8Error is converted into ErrorKind::Utf8
+//!     Ok(from_utf8(&buf)?.to_string())
+//! }
+//!
+//! fn main() -> Result<(), Error> {
+//!     let e = read_config("/path/does/not/exist").unwrap_err();
+//!     assert!(matches!(e.kind(), ErrorKind::IO(_)));
+//!     Ok(())
+//! }
+//!```
 
 use bmw_deps::failure;
 
@@ -116,4 +150,4 @@ mod macros;
 mod test;
 mod types;
 
-pub use crate::types::{ErrKind, Error, ErrorKind};
+pub use crate::types::{ConnectErrorKind, ErrKind, Error, ErrorKind};
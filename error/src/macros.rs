@@ -78,6 +78,13 @@ macro_rules! err {
                         Http403 => impl_err!(Http403, $m),
                         Http400 => impl_err!(Http400, $m),
                         Rustlet => impl_err!(Rustlet, $m),
+                        Checksum => impl_err!(Checksum, $m),
+                        ShuttingDown => impl_err!(ShuttingDown, $m),
+                        Cancelled => impl_err!(Cancelled, $m),
+                        AlreadyRegistered => impl_err!(AlreadyRegistered, $m),
+                        AlreadyInitialized => impl_err!(AlreadyInitialized, $m),
+                        ConnectionClosed => impl_err!(ConnectionClosed, $m),
+                        Fragmentation => impl_err!(Fragmentation, $m),
 		}
 	}};
 }
@@ -147,6 +154,13 @@ macro_rules! map_err {
 				Http403 => impl_map_err!(Http403, $m, e),
 				Http400 => impl_map_err!(Http400, $m, e),
 				Rustlet => impl_map_err!(Rustlet, $m, e),
+				Checksum => impl_map_err!(Checksum, $m, e),
+				ShuttingDown => impl_map_err!(ShuttingDown, $m, e),
+				Cancelled => impl_map_err!(Cancelled, $m, e),
+				AlreadyRegistered => impl_map_err!(AlreadyRegistered, $m, e),
+				AlreadyInitialized => impl_map_err!(AlreadyInitialized, $m, e),
+				ConnectionClosed => impl_map_err!(ConnectionClosed, $m, e),
+				Fragmentation => impl_map_err!(Fragmentation, $m, e),
 			}
 		})
 	}};
@@ -162,13 +176,89 @@ macro_rules! cbreak {
 	}};
 }
 
-/// Macro to map the try_from error into an appropriate error.
+/// Return an error built with the [`crate::err`] macro if the specified condition is false.
+/// The arguments after the condition are passed to [`crate::err`], so the message may be
+/// formatted just like [`std::format`]. The message is only formatted if the condition is
+/// false. This macro may only be used in functions that return `Result<_, Error>`.
+///
+/// Example:
+///
+///```
+/// use bmw_err::{Error, ErrorKind, ErrKind, ensure};
+///
+/// fn check_len(len: usize, max: usize) -> Result<usize, Error> {
+///     ensure!(len <= max, ErrKind::IllegalArgument, "len {} exceeds max {}", len, max);
+///     Ok(len)
+/// }
+///
+/// fn main() -> Result<(), Error> {
+///     assert_eq!(check_len(5, 10)?, 5);
+///     let e = check_len(11, 10).unwrap_err();
+///     assert!(matches!(e.kind(), ErrorKind::IllegalArgument(_)));
+///     Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! ensure {
+	($cond:expr, $($err:tt)*) => {{
+		if !($cond) {
+			use bmw_err::err;
+			return Err(err!($($err)*));
+		}
+	}};
+}
+
+/// Unwrap the specified [`std::option::Option`] or return an error built with the
+/// [`crate::err`] macro if it is `None`. The arguments after the option are passed to
+/// [`crate::err`], so the message may be formatted just like [`std::format`]. The message is
+/// only formatted if the option is `None`. This macro may only be used in functions that return
+/// `Result<_, Error>`.
+///
+/// Example:
+///
+///```
+/// use bmw_err::{Error, ErrorKind, ErrKind, try_opt};
+/// use std::collections::HashMap;
+///
+/// fn lookup(map: &HashMap<u32, String>, id: u32) -> Result<String, Error> {
+///     let value = try_opt!(map.get(&id), ErrKind::IllegalArgument, "id {} not found", id);
+///     Ok(value.clone())
+/// }
+///
+/// fn main() -> Result<(), Error> {
+///     let mut map = HashMap::new();
+///     map.insert(1, "one".to_string());
+///     assert_eq!(lookup(&map, 1)?, "one".to_string());
+///     let e = lookup(&map, 2).unwrap_err();
+///     assert!(matches!(e.kind(), ErrorKind::IllegalArgument(_)));
+///     Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! try_opt {
+	($opt:expr, $($err:tt)*) => {{
+		match $opt {
+			Some(v) => v,
+			None => {
+				use bmw_err::err;
+				return Err(err!($($err)*));
+			}
+		}
+	}};
+}
+
+/// Macro to map the try_from error into an appropriate error. The error message includes the
+/// expression that could not be converted.
 #[macro_export]
 macro_rules! try_into {
 	($v:expr) => {{
 		use bmw_err::{map_err, ErrKind};
 		use std::convert::TryInto;
-		map_err!($v.try_into(), ErrKind::Misc, "TryInto Error")
+		map_err!(
+			$v.try_into(),
+			ErrKind::Misc,
+			concat!("TryInto Error: ", stringify!($v))
+		)
 	}};
 }
 
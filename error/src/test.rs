@@ -22,7 +22,7 @@ mod test {
 	use bmw_deps::nix::errno::Errno;
 
 	use crate as bmw_err;
-	use crate::{err, map_err, ErrKind, Error, ErrorKind};
+	use crate::{ensure, err, map_err, try_opt, ConnectErrorKind, ErrKind, Error, ErrorKind};
 	use bmw_deps::substring::Substring;
 	use bmw_deps::url::{ParseError, Url};
 	use std::alloc::Layout;
@@ -117,6 +117,37 @@ mod test {
 			ErrorKind::Configuration(ss.clone()).into(),
 		)?;
 		test_kind(ErrKind::Rustlet, s, ErrorKind::Rustlet(ss.clone()).into())?;
+		test_kind(ErrKind::Checksum, s, ErrorKind::Checksum(ss.clone()).into())?;
+		test_kind(
+			ErrKind::ShuttingDown,
+			s,
+			ErrorKind::ShuttingDown(ss.clone()).into(),
+		)?;
+		test_kind(
+			ErrKind::Cancelled,
+			s,
+			ErrorKind::Cancelled(ss.clone()).into(),
+		)?;
+		test_kind(
+			ErrKind::AlreadyRegistered,
+			s,
+			ErrorKind::AlreadyRegistered(ss.clone()).into(),
+		)?;
+		test_kind(
+			ErrKind::AlreadyInitialized,
+			s,
+			ErrorKind::AlreadyInitialized(ss.clone()).into(),
+		)?;
+		test_kind(
+			ErrKind::ConnectionClosed,
+			s,
+			ErrorKind::ConnectionClosed(ss.clone()).into(),
+		)?;
+		test_kind(
+			ErrKind::Fragmentation,
+			s,
+			ErrorKind::Fragmentation(ss.clone()).into(),
+		)?;
 		test_kind(ErrKind::Http404, s, ErrorKind::Http404(ss.clone()).into())?;
 		test_kind(ErrKind::Http400, s, ErrorKind::Http400(ss.clone()).into())?;
 		test_kind(ErrKind::Http403, s, ErrorKind::Http403(ss.clone()).into())?;
@@ -215,6 +246,28 @@ mod test {
 		test_map(ErrKind::Rustls, ErrorKind::Rustls(s.clone()).into())?;
 		test_map(ErrKind::Crypt, ErrorKind::Crypt(s.clone()).into())?;
 		test_map(ErrKind::Rustlet, ErrorKind::Rustlet(s.clone()).into())?;
+		test_map(ErrKind::Checksum, ErrorKind::Checksum(s.clone()).into())?;
+		test_map(
+			ErrKind::ShuttingDown,
+			ErrorKind::ShuttingDown(s.clone()).into(),
+		)?;
+		test_map(ErrKind::Cancelled, ErrorKind::Cancelled(s.clone()).into())?;
+		test_map(
+			ErrKind::AlreadyRegistered,
+			ErrorKind::AlreadyRegistered(s.clone()).into(),
+		)?;
+		test_map(
+			ErrKind::AlreadyInitialized,
+			ErrorKind::AlreadyInitialized(s.clone()).into(),
+		)?;
+		test_map(
+			ErrKind::ConnectionClosed,
+			ErrorKind::ConnectionClosed(s.clone()).into(),
+		)?;
+		test_map(
+			ErrKind::Fragmentation,
+			ErrorKind::Fragmentation(s.clone()).into(),
+		)?;
 		test_map(ErrKind::Http400, ErrorKind::Http400(s.clone()).into())?;
 		test_map(ErrKind::Http403, ErrorKind::Http403(s.clone()).into())?;
 		test_map(ErrKind::Http404, ErrorKind::Http404(s.clone()).into())?;
@@ -384,4 +437,102 @@ mod test {
 		assert_eq!(&(e.to_string())[0..s.len()], &s.to_string()[0..s.len()]);
 		Ok(())
 	}
+
+	fn ensure_positive(v: i32) -> Result<i32, Error> {
+		ensure!(
+			v > 0,
+			ErrKind::IllegalArgument,
+			"value {} is not positive",
+			v
+		);
+		Ok(v)
+	}
+
+	fn ensure_no_args(v: bool) -> Result<(), Error> {
+		ensure!(v, ErrKind::IllegalState, "condition failed");
+		Ok(())
+	}
+
+	fn first_even(v: &[u32]) -> Result<u32, Error> {
+		let even = try_opt!(
+			v.iter().find(|x| *x % 2 == 0),
+			ErrKind::Misc,
+			"no even value in {} values",
+			v.len()
+		);
+		Ok(*even)
+	}
+
+	fn opt_no_args(v: Option<u8>) -> Result<u8, Error> {
+		Ok(try_opt!(v, ErrKind::CorruptedData, "missing"))
+	}
+
+	#[test]
+	fn test_ensure() -> Result<(), Error> {
+		assert_eq!(ensure_positive(1)?, 1);
+		let e = ensure_positive(-3).unwrap_err();
+		assert_eq!(
+			e.kind(),
+			ErrorKind::IllegalArgument("value -3 is not positive".to_string())
+		);
+
+		assert!(ensure_no_args(true).is_ok());
+		let e = ensure_no_args(false).unwrap_err();
+		assert_eq!(
+			e.kind(),
+			ErrorKind::IllegalState("condition failed".to_string())
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_try_opt() -> Result<(), Error> {
+		assert_eq!(first_even(&[1, 3, 4])?, 4);
+		let e = first_even(&[1, 3, 5]).unwrap_err();
+		assert_eq!(
+			e.kind(),
+			ErrorKind::Misc("no even value in 3 values".to_string())
+		);
+
+		assert_eq!(opt_no_args(Some(7))?, 7);
+		let e = opt_no_args(None).unwrap_err();
+		assert_eq!(e.kind(), ErrorKind::CorruptedData("missing".to_string()));
+		Ok(())
+	}
+
+	#[test]
+	fn test_connect_error_kind() -> Result<(), Error> {
+		let e = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+		assert_eq!(ConnectErrorKind::from(&e), ConnectErrorKind::Refused);
+		let e = std::io::Error::from(std::io::ErrorKind::HostUnreachable);
+		assert_eq!(ConnectErrorKind::from(&e), ConnectErrorKind::Unreachable);
+		let e = std::io::Error::from(std::io::ErrorKind::NetworkUnreachable);
+		assert_eq!(ConnectErrorKind::from(&e), ConnectErrorKind::Unreachable);
+		let e = std::io::Error::from(std::io::ErrorKind::TimedOut);
+		assert_eq!(ConnectErrorKind::from(&e), ConnectErrorKind::TimedOut);
+		let e = std::io::Error::from(std::io::ErrorKind::AddrInUse);
+		assert_eq!(ConnectErrorKind::from(&e), ConnectErrorKind::AddrInUse);
+		let e = std::io::Error::from(std::io::ErrorKind::Other);
+		assert_eq!(ConnectErrorKind::from(&e), ConnectErrorKind::Other(-1));
+
+		#[cfg(unix)]
+		{
+			let kind = ConnectErrorKind::from_raw_os_error(Errno::ECONNREFUSED as i32);
+			assert_eq!(kind, ConnectErrorKind::Refused);
+			let kind = ConnectErrorKind::from_raw_os_error(Errno::ETIMEDOUT as i32);
+			assert_eq!(kind, ConnectErrorKind::TimedOut);
+			let kind = ConnectErrorKind::from_raw_os_error(Errno::EHOSTUNREACH as i32);
+			assert_eq!(kind, ConnectErrorKind::Unreachable);
+			let kind = ConnectErrorKind::from_raw_os_error(Errno::EPERM as i32);
+			assert_eq!(kind, ConnectErrorKind::Other(Errno::EPERM as i32));
+		}
+
+		// the kind is only present when attached and does not affect the error kind
+		let e: Error = err!(ErrKind::IO, "connect failed");
+		assert_eq!(e.connect_kind(), None);
+		let e = e.with_connect_kind(ConnectErrorKind::TimedOut);
+		assert_eq!(e.connect_kind(), Some(ConnectErrorKind::TimedOut));
+		assert_eq!(e.kind(), ErrorKind::IO("connect failed".to_string()));
+		Ok(())
+	}
 }
@@ -22,6 +22,25 @@ use bmw_deps::failure::{Context, Fail};
 #[derive(Debug, Fail)]
 pub struct Error {
 	pub(crate) inner: Context<ErrorKind>,
+	pub(crate) connect_kind: Option<ConnectErrorKind>,
+}
+
+/// Classification of a failed outbound connection attempt. This is derived from the socket
+/// error (SO_ERROR / errno) reported by the operating system and is attached to the
+/// [`crate::Error`] returned by the connect functions. It can be retrieved using
+/// [`crate::Error::connect_kind`].
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ConnectErrorKind {
+	/// The remote host actively refused the connection (ECONNREFUSED).
+	Refused,
+	/// The remote host or network is unreachable (EHOSTUNREACH / ENETUNREACH).
+	Unreachable,
+	/// The connection attempt timed out (ETIMEDOUT).
+	TimedOut,
+	/// The local address is already in use (EADDRINUSE).
+	AddrInUse,
+	/// Any other socket error. The raw os error code is included (or -1 if not available).
+	Other(i32),
 }
 
 /// Kinds of errors that can occur.
@@ -108,6 +127,27 @@ pub enum ErrorKind {
 	/// Rustlet Error
 	#[fail(display = "rustlet_error: {}", _0)]
 	Rustlet(String),
+	/// Checksum Error
+	#[fail(display = "checksum error: {}", _0)]
+	Checksum(String),
+	/// Shutting Down Error
+	#[fail(display = "shutting down: {}", _0)]
+	ShuttingDown(String),
+	/// Cancelled Error
+	#[fail(display = "cancelled: {}", _0)]
+	Cancelled(String),
+	/// Already Registered Error
+	#[fail(display = "already registered: {}", _0)]
+	AlreadyRegistered(String),
+	/// Already Initialized Error
+	#[fail(display = "already initialized: {}", _0)]
+	AlreadyInitialized(String),
+	/// Connection Closed Error
+	#[fail(display = "connection closed: {}", _0)]
+	ConnectionClosed(String),
+	/// Fragmentation Error
+	#[fail(display = "fragmentation: {}", _0)]
+	Fragmentation(String),
 }
 
 /// The kinds of errors in this crate. This enum is used to map to error
@@ -168,4 +208,18 @@ pub enum ErrKind {
 	Http400,
 	/// Rustlet error
 	Rustlet,
+	/// A checksum did not match the data that it covers
+	Checksum,
+	/// The component is shutting down and does not accept new work
+	ShuttingDown,
+	/// The operation was cancelled before it completed
+	Cancelled,
+	/// The resource is already registered
+	AlreadyRegistered,
+	/// The resource is already initialized
+	AlreadyInitialized,
+	/// The connection has been closed
+	ConnectionClosed,
+	/// Enough free space exists but not in a single contiguous run
+	Fragmentation,
 }
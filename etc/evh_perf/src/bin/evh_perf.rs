@@ -145,7 +145,7 @@ fn run_eventhandler(
 
 	let mut evh = evh!(
 		EvhThreads(threads),
-		EvhReadSlabCount(read_slab_count),
+		EvhReadSlabCountPerThread(read_slab_count),
 		EvhReadSlabSize(512),
 		EvhHouseKeeperFrequencyMillis(10_000)
 	)?;
@@ -190,14 +190,14 @@ fn run_eventhandler(
 		debug!("accept a connection id = {}", conn_data.id(),)?;
 		Ok(())
 	})?;
-	evh.set_on_close(move |conn_data, _| {
+	evh.set_on_close(move |conn_data, reason, _| {
 		if debug {
-			info!("on close: {}", conn_data.id())?;
+			info!("on close: {}, reason: {:?}", conn_data.id(), reason)?;
 		}
 		Ok(())
 	})?;
 	evh.set_on_panic(move |_, _| Ok(()))?;
-	evh.set_on_housekeeper(move |_| Ok(()))?;
+	evh.set_on_housekeeper(move |_, _| Ok(()))?;
 
 	evh.start()?;
 	let sc = EvhBuilder::build_server_connection(addr, 10_000)?;
@@ -824,12 +824,12 @@ fn run_thread(
 		)?;
 		Ok(())
 	})?;
-	evh.set_on_close(move |conn_data, _thread_context| {
-		debug!("on close: {}", conn_data.id(),)?;
+	evh.set_on_close(move |conn_data, reason, _thread_context| {
+		debug!("on close: {}, reason: {:?}", conn_data.id(), reason)?;
 		Ok(())
 	})?;
 	evh.set_on_panic(move |_, _| Ok(()))?;
-	evh.set_on_housekeeper(move |_thread_context| Ok(()))?;
+	evh.set_on_housekeeper(move |_hk, _thread_context| Ok(()))?;
 	evh.start()?;
 
 	for _ in 0..reconns {
@@ -22,12 +22,22 @@ use crate::mac::*;
 #[cfg(target_os = "windows")]
 use crate::win::*;
 
-use crate::types::{ConnectionType, DebugInfo, EventHandlerImpl};
-use crate::{Connection, EventHandler, EvhBuilder, UserContext};
+use crate::constants::{
+	EVH_CLIENT_ORIGIN_ID, EVH_DEFAULT_LOOPBACK_BUFFER_SIZE, EVH_EXPORT_STATE_VERSION,
+};
+use crate::evh::BUILDER_IDS;
+use crate::types::{ConnectionType, DebugInfo, EventHandlerImpl, Loopback};
+use crate::{
+	CloseReason, Connection, EventHandler, EvhBuilder, ExportedHandle, HousekeeperContext,
+	UserContext,
+};
 use bmw_conf::ConfigOption;
 use bmw_err::*;
 use bmw_log::*;
+use bmw_ser::deserialize;
 use std::any::Any;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
 
 info!();
 
@@ -54,13 +64,17 @@ impl EvhBuilder {
 			+ Clone
 			+ Sync
 			+ Unpin,
-		OnClose: FnMut(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
+		OnClose: FnMut(
+				&mut Connection,
+				&CloseReason,
+				&mut Box<dyn UserContext + '_>,
+			) -> Result<(), Error>
 			+ Send
 			+ 'static
 			+ Clone
 			+ Sync
 			+ Unpin,
-		OnHousekeeper: FnMut(&mut Box<dyn UserContext + '_>) -> Result<(), Error>
+		OnHousekeeper: FnMut(&mut HousekeeperContext<'_>, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 			+ Send
 			+ 'static
 			+ Clone
@@ -96,13 +110,17 @@ impl EvhBuilder {
 			None,
 			ConnectionType::Server,
 			DebugInfo::default(),
+			BUILDER_IDS.next(),
 			None,
 		)?)
 	}
 
 	/// Builds a client side [`crate::Connection`] that can be added to the
 	/// [`crate::EventHandler`] via the [`crate::EventHandler::add_client_connection`]
-	/// function.
+	/// function. Note that this function blocks until the connection is established or the
+	/// operating system's connect timeout expires, which can take minutes for an address that
+	/// does not respond. Use [`crate::EvhBuilder::build_client_connection_timeout`] to bound
+	/// the wait.
 	/// # Input Parameters
 	/// host - The remote host to bind to.
 	/// port - The parameter passed to the [`bmw_deps::libc::listen`] as the backlog parameter. This
@@ -110,7 +128,8 @@ impl EvhBuilder {
 	/// # Returns
 	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
 	/// # Errors
-	/// [`bmw_err::ErrKind::IO`] if an i/o error occurs.
+	/// [`bmw_err::ErrKind::IO`] if an i/o error occurs. If the connection attempt fails, the
+	/// classified socket error is available via [`bmw_err::Error::connect_kind`].
 	pub fn build_client_connection(host: &str, port: u16) -> Result<Connection, Error> {
 		let handle = create_connection(host, port)?;
 		Ok(Connection::new(
@@ -119,7 +138,227 @@ impl EvhBuilder {
 			None,
 			ConnectionType::Client,
 			DebugInfo::default(),
+			BUILDER_IDS.next(),
 			None,
 		)?)
 	}
+
+	/// Builds a client side [`crate::Connection`] like
+	/// [`crate::EvhBuilder::build_client_connection`], but waits at most `timeout_millis`
+	/// milliseconds for the connection to be established. A non-blocking connect is started to
+	/// each address of `host` in turn and waited on for the time that remains, so the calling
+	/// thread is never blocked for longer than the timeout. On success, the connection is fully
+	/// established and non-blocking and can be added to the [`crate::EventHandler`] via the
+	/// [`crate::EventHandler::add_client_connection`] function.
+	/// # Input Parameters
+	/// host - The remote host to connect to.
+	/// port - The port of the remote host to connect to.
+	/// timeout_millis - The maximum time, in milliseconds, to wait for the connection.
+	/// # Returns
+	/// On success, the [`crate::Connection`] is returned and on failure, [`bmw_err::Error`] is
+	/// returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] if `timeout_millis` is 0.
+	/// [`bmw_err::ErrKind::IO`] if the host cannot be resolved or the connection fails. If the
+	/// timeout expires, [`bmw_err::Error::connect_kind`] returns
+	/// [`bmw_err::ConnectErrorKind::TimedOut`].
+	pub fn build_client_connection_timeout(
+		host: &str,
+		port: u16,
+		timeout_millis: u64,
+	) -> Result<Connection, Error> {
+		let timeout = Duration::from_millis(timeout_millis);
+		let handle = create_connection_timeout(host, port, timeout)?;
+		Connection::new(
+			handle,
+			None,
+			None,
+			ConnectionType::Client,
+			DebugInfo::default(),
+			BUILDER_IDS.next(),
+			None,
+		)
+	}
+
+	/// Builds a [`crate::Connection`] from a connection that was exported by an
+	/// [`crate::EventHandler`] with [`crate::Connection::export_handle`]. The returned
+	/// connection can be added to an [`crate::EventHandler`] via the
+	/// [`crate::EventHandler::add_imported_connection`] function. The peer address and the
+	/// attachment of the exported connection are restored. Since the
+	/// [`crate::EventHandler`] that it was originally accepted on may not be known, its
+	/// origin_id is 0, like client connections.
+	/// # Input Parameters
+	/// exported - The [`crate::ExportedHandle`] returned by
+	/// [`crate::Connection::export_handle`].
+	/// # Returns
+	/// On success, the [`crate::Connection`] is returned and on failure, [`bmw_err::Error`] is
+	/// returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::CorruptedData`] if the state of the exported handle is invalid or
+	/// was written by an incompatible version.
+	pub fn import_connection(exported: ExportedHandle) -> Result<Connection, Error> {
+		let (version, (peer_addr, attachment)): (u8, (Option<String>, Option<Vec<u8>>)) =
+			deserialize(&mut &exported.state[..])?;
+		ensure!(
+			version == EVH_EXPORT_STATE_VERSION,
+			ErrKind::CorruptedData,
+			"unsupported exported handle version: {}",
+			version
+		);
+		let peer_addr = match peer_addr {
+			Some(peer_addr) => {
+				let text = format!("invalid peer address: {}", peer_addr);
+				Some(map_err!(peer_addr.parse(), ErrKind::CorruptedData, text)?)
+			}
+			None => None,
+		};
+		let mut connection = Connection::new(
+			exported.handle,
+			None,
+			None,
+			ConnectionType::Connection,
+			DebugInfo::default(),
+			BUILDER_IDS.next(),
+			Some(EVH_CLIENT_ORIGIN_ID),
+		)?;
+		connection.peer_addr = peer_addr;
+		connection.attachment = attachment;
+		Ok(connection)
+	}
+
+	/// Builds a pair of [`crate::Connection`]s that are connected to each other in memory. This
+	/// is useful for components of the same process that communicate through
+	/// [`crate::EventHandler`]s since no sockets or system calls are involved. Data written to
+	/// one side is delivered to the on_read handler of the other side through the same slabs as
+	/// data read from a socket. [`crate::WriteHandle::write`], [`crate::WriteHandle::close`] and
+	/// the [`crate::CloseReason`]s behave like they do for TCP connections. Each direction buffers up
+	/// to 256 KiB. Once the peer's buffer is full, the rest of the written data is queued until
+	/// the peer has read from it. See
+	/// [`crate::EvhBuilder::build_loopback_pair_with_buffer_size`].
+	/// # Returns
+	/// On success, the client side and the server side connections are returned. The client side
+	/// can be added to an [`crate::EventHandler`] via the
+	/// [`crate::EventHandler::add_client_connection`] function and the server side via the
+	/// [`crate::EventHandler::add_imported_connection`] function, which calls on_accept like it
+	/// does for accepted connections. The two sides may be added to the same or to different
+	/// [`crate::EventHandler`]s. On failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// None currently. The [`std::result::Result`] is returned for consistency with the other
+	/// builder functions.
+	pub fn build_loopback_pair() -> Result<(Connection, Connection), Error> {
+		Self::build_loopback_pair_with_buffer_size(EVH_DEFAULT_LOOPBACK_BUFFER_SIZE)
+	}
+
+	/// Builds a pair of in-memory [`crate::Connection`]s like
+	/// [`crate::EvhBuilder::build_loopback_pair`] with the specified buffer size.
+	/// # Input Parameters
+	/// buffer_size - The maximum number of bytes that are buffered in each direction. A writer
+	/// is paused once the peer's buffer is full.
+	/// # Returns
+	/// On success, the client side and the server side connections are returned. On failure,
+	/// [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] if buffer_size is 0.
+	pub fn build_loopback_pair_with_buffer_size(
+		buffer_size: usize,
+	) -> Result<(Connection, Connection), Error> {
+		let (client, server) = Loopback::pair(buffer_size)?;
+		let mut client_conn = Connection::new(
+			Loopback::next_handle()?,
+			None,
+			None,
+			ConnectionType::Client,
+			DebugInfo::default(),
+			BUILDER_IDS.next(),
+			None,
+		)?;
+		client_conn.loopback = Some(client);
+		let mut server_conn = Connection::new(
+			Loopback::next_handle()?,
+			None,
+			None,
+			ConnectionType::Connection,
+			DebugInfo::default(),
+			BUILDER_IDS.next(),
+			Some(EVH_CLIENT_ORIGIN_ID),
+		)?;
+		server_conn.loopback = Some(server);
+		Ok((client_conn, server_conn))
+	}
+
+	/// Builds a client side [`crate::Connection`] whose socket is bound to the specified local
+	/// address before connecting. This is useful on multi-homed hosts where connections must
+	/// originate from a specific interface or source port. The returned connection can be added
+	/// to the [`crate::EventHandler`] via the [`crate::EventHandler::add_client_connection`]
+	/// function.
+	/// # Input Parameters
+	/// host - The remote host to connect to.
+	/// port - The remote port to connect to.
+	/// local_addr - The local address to bind to. A port of 0 lets the operating system choose
+	/// the source port. Only remote addresses in the same address family are tried.
+	/// # Returns
+	/// On success, the [`crate::Connection`] is returned and on failure, [`bmw_err::Error`] is
+	/// returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IO`] if an i/o error occurs. If binding fails (e.g. the address is
+	/// not available or the port is in use), the message includes the attempted local address.
+	/// If the connection attempt fails, the classified socket error is available via
+	/// [`bmw_err::Error::connect_kind`].
+	pub fn build_client_connection_bound(
+		host: &str,
+		port: u16,
+		local_addr: SocketAddr,
+	) -> Result<Connection, Error> {
+		let handle = create_connection_bound(host, port, local_addr)?;
+		Connection::new(
+			handle,
+			None,
+			None,
+			ConnectionType::Client,
+			DebugInfo::default(),
+			BUILDER_IDS.next(),
+			None,
+		)
+	}
+
+	/// Builds a client side [`crate::Connection`] to a host which may have both IPv4 and IPv6
+	/// addresses using the happy eyeballs algorithm (RFC 8305). All addresses of the host are
+	/// resolved and a connection attempt is started to the first one. If it has not completed
+	/// after `attempt_delay_millis`, an attempt to the next address (alternating between address
+	/// families) is raced against it and so on. The first attempt to connect is used and the
+	/// others are closed, so a broken address family only costs the attempt delay rather than the
+	/// operating system's connect timeout. Like [`crate::EvhBuilder::build_client_connection`],
+	/// this function blocks until a connection is established. The returned connection can be
+	/// added to the [`crate::EventHandler`] via the
+	/// [`crate::EventHandler::add_client_connection`] function.
+	/// # Input Parameters
+	/// hostname - The remote host to connect to.
+	/// port - The remote port to connect to.
+	/// attempt_delay_millis - The time in milliseconds to wait for an attempt before starting
+	/// the next one. RFC 8305 recommends 250 milliseconds.
+	/// # Returns
+	/// On success, the [`crate::Connection`] is returned and on failure, [`bmw_err::Error`] is
+	/// returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IO`] if the host cannot be resolved or if all connection attempts
+	/// fail. In the latter case, the classified socket error of the last attempt is available
+	/// via [`bmw_err::Error::connect_kind`].
+	pub fn build_client_connection_happy_eyeballs(
+		hostname: &str,
+		port: u16,
+		attempt_delay_millis: u64,
+	) -> Result<Connection, Error> {
+		let addrs: Vec<SocketAddr> = (hostname, port).to_socket_addrs()?.collect();
+		let attempt_delay = Duration::from_millis(attempt_delay_millis);
+		let handle = create_connection_happy_eyeballs(&addrs, attempt_delay)?;
+		Connection::new(
+			handle,
+			None,
+			None,
+			ConnectionType::Client,
+			DebugInfo::default(),
+			BUILDER_IDS.next(),
+			None,
+		)
+	}
 }
@@ -24,6 +24,26 @@ pub(crate) const EVH_DEFAULT_READ_SLAB_SIZE: usize = 512;
 pub(crate) const EVH_DEFAULT_HOUSEKEEPING_FREQUENCY_MILLIS: usize = 10_000; // 10 seconds
 pub(crate) const EVH_DEFAULT_STATS_UPDATE_MILLIS: usize = 5_000; // 5 seconds
 pub(crate) const EVH_DEFAULT_OUT_OF_SLABS_MESSAGE: &str = "";
+pub(crate) const EVH_DEFAULT_PROXY_HEADER_TIMEOUT_MILLIS: usize = 5_000; // 5 seconds
+pub(crate) const EVH_DEFAULT_FD_WARN_THRESHOLD_PERCENT: usize = 90;
+pub(crate) const EVH_DEFAULT_START_TIMEOUT_MILLIS: usize = 5_000; // 5 seconds
+																  // the number of snapshots of the reloadable options that are kept. See EventHandler::update_config.
+pub(crate) const EVH_CONFIG_HISTORY_SIZE: usize = 16;
+
+// the minimum time between warnings about approaching the file descriptor limit
+pub(crate) const EVH_FD_WARN_INTERVAL_MILLIS: usize = 60_000; // 1 minute
+
+// the longest time the watchdog sleeps between checks of the running callbacks
+pub(crate) const EVH_WATCHDOG_MAX_CHECK_MILLIS: usize = 1_000; // 1 second
+
+// the origin_id that stats for all client connections are accumulated under
+pub(crate) const EVH_CLIENT_ORIGIN_ID: u128 = 0;
+
+// PROXY protocol header constants
+pub(crate) const PROXY_V1_PREFIX: &[u8] = b"PROXY ";
+pub(crate) const PROXY_V1_MAX_LEN: usize = 107;
+pub(crate) const PROXY_V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+pub(crate) const PROXY_V2_HEADER_LEN: usize = 16;
 
 // slice max size for ret handles
 pub(crate) const MAX_RET_HANDLES: usize = 100;
@@ -31,7 +51,38 @@ pub(crate) const MAX_RET_HANDLES: usize = 100;
 // write state flags
 pub(crate) const WRITE_STATE_FLAG_PENDING: u8 = 0x1 << 0;
 pub(crate) const WRITE_STATE_FLAG_CLOSE: u8 = 0x1 << 1;
-pub(crate) const WRITE_STATE_FLAG_TRIGGER_ON_READ: u8 = 0x1 << 2;
+// set while a CompressedStream is open
+pub(crate) const WRITE_STATE_FLAG_COMPRESSING: u8 = 0x1 << 2;
+
+// the amount of input compressed (and output buffered) at a time by a CompressedStream
+pub(crate) const EVH_COMPRESSION_BLOCK_SIZE: usize = 64 * 1024;
+
+// the number of capture records that may be queued for the capture writer thread
+pub(crate) const EVH_CAPTURE_QUEUE_SIZE: usize = 1_024;
+
+// how long Connection::export_handle waits for queued data to be written
+pub(crate) const EVH_EXPORT_FLUSH_TIMEOUT_MILLIS: usize = 5_000;
+// the version of the state blob of an ExportedHandle
+pub(crate) const EVH_EXPORT_STATE_VERSION: u8 = 1;
+
+// how often a graceful shutdown checks whether the connections have been closed
+pub(crate) const EVH_DRAIN_POLL_MILLIS: u64 = 10;
+
+// how often start checks whether the threads have initialized and add_server_connection checks
+// whether the thread it is waiting on is still alive
+pub(crate) const EVH_LIVENESS_POLL_MILLIS: u64 = 10;
+
+// the interval at which a rate limited connection writes. The token bucket holds the bytes
+// that may be written in one interval. See Connection::set_write_rate_limit_bytes_per_sec.
+pub(crate) const EVH_RATE_LIMIT_INTERVAL_MILLIS: usize = 50;
+
+// the number of bytes buffered in each direction of a loopback pair built with
+// EvhBuilder::build_loopback_pair. A writer is paused once the peer's buffer is full.
+pub(crate) const EVH_DEFAULT_LOOPBACK_BUFFER_SIZE: usize = 256 * 1024;
+
+// signal masks. See Signal.
+pub(crate) const SIGNAL_MASK_TERM: usize = 0x1 << 0;
+pub(crate) const SIGNAL_MASK_INT: usize = 0x1 << 1;
 
 // errno().0 values
 pub(crate) const EAGAIN: i32 = 11;
@@ -24,60 +24,1348 @@ use crate::win::*;
 
 use crate::constants::*;
 use crate::types::{
-	Chunk, ConnectionType, ConnectionVariant, DebugInfo, Event, EventHandlerCallbacks,
+	CallbackWatch, Capture, CaptureRecord, CaptureWriter, Chunk, ChunkAction, ConnectionIds,
+	ConnectionType, ConnectionVariant, DebugInfo, Encoder, Event, EventHandlerCallbacks,
 	EventHandlerConfig, EventHandlerContext, EventHandlerImpl, EventHandlerState, EventIn,
-	EventType, EventTypeIn, EvhController, GlobalStats, UserContextImpl, Wakeup, WriteHandle,
-	WriteState,
+	EventType, EventTypeIn, EvhController, GlobalStats, KeyRegistry, Loopback, LoopbackBuffer,
+	LoopbackWaker, MemoryBudget, Metrics, OnDataChunk, OnPanicInfo, OnRawEvent, OnReadEof,
+	OnShutdown, OnSlabQuota, PendingFile, PendingResponse, RawRegistration, ReloadableConfig,
+	ResponseSlot, ResponseSlotState, SignalRegistration, UserContextImpl, Wakeup, Watchdog,
+	WatchedCallback, WriteHandle, WriteInterest, WriteState,
+};
+use crate::{
+	CloseReason, CloseReasonStats, CompressedStream, CompressionFormat, Connection, ConnectionId,
+	EventHandler, EvhBuilder, EvhMetric, EvhStats, ExportedHandle, HousekeeperContext, Interest,
+	MemoryPressure, MetricsRecorder, OriginStats, ProtocolHandler, ReadSlabStats,
+	ResponseExtractor, ResponseFuture, ShutdownMode, Signal, ThreadHealth, Transform,
+	UnmatchedResponseHandler, UserContext,
 };
-use crate::{Connection, EventHandler, EvhStats, UserContext};
 use bmw_conf::ConfigOptionName as CN;
-use bmw_conf::{ConfigBuilder, ConfigOption};
+use bmw_conf::{ConfigBuilder, ConfigHistory, ConfigOption, WatchdogAction};
 use bmw_deps::errno::{errno, set_errno, Errno};
+use bmw_deps::flate2::write::{GzEncoder, ZlibEncoder};
+use bmw_deps::flate2::Compression;
+use bmw_deps::lazy_static::lazy_static;
 use bmw_deps::rand::random;
 use bmw_err::*;
 use bmw_log::*;
+use bmw_ser::{deserialize, serialize, write_frame, BinWriter, FrameOptions, Serializable, Writer};
 use bmw_util::*;
 use std::any::Any;
-use std::collections::{HashMap, VecDeque};
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpStream, ToSocketAddrs};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::sync::mpsc::{sync_channel, SyncSender};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::str::FromStr;
+use std::sync::atomic::{compiler_fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{
+	channel, sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError,
+};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll};
+use std::thread::{current, sleep, spawn};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 info!();
 
+// log an internal warning or error of an EventHandler. See evh_log_impl.
+macro_rules! evh_log {
+	($config:expr, $level:ident, $line:expr) => {
+		evh_log_impl($config, LogLevel::$level, $line, BMW_GLOBAL_LOG_LEVEL)
+	};
+	($config:expr, $level:ident, $line:expr, $($values:tt)*) => {
+		evh_log!($config, $level, &format!($line, $($values)*)[..])
+	};
+}
+
+lazy_static! {
+	// instance ids that have been used in this process. Ids are never removed so that
+	// connection ids are not reused, even after an EventHandler is dropped.
+	static ref INSTANCE_IDS: Mutex<HashSet<u64>> = Mutex::new(HashSet::new());
+	// assigns the ids of the server and client connections built by EvhBuilder
+	pub(crate) static ref BUILDER_IDS: ConnectionIds = ConnectionIds::new();
+	// the EventHandlers that are shut down on a signal by instance id. See
+	// EventHandler::handle_signals.
+	static ref SIGNAL_REGISTRATIONS: Mutex<HashMap<u64, SignalRegistration>> =
+		Mutex::new(HashMap::new());
+	// the monotonic base of the start times recorded in CallbackWatch::started
+	static ref WATCH_EPOCH: Instant = Instant::now();
+}
+
+// set once the thread that dispatches the signals to the EventHandlers has been spawned
+static SIGNAL_THREAD_STARTED: AtomicBool = AtomicBool::new(false);
+// the number of loopback handles assigned. They count down from Handle::MAX so that they do not
+// collide with the handles of sockets.
+static LOOPBACK_HANDLES: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+	// the CallbackWatch of the evh thread running on this thread and the generation of its
+	// context. Set by execute_thread if EvhCallbackTimeoutMillis is configured.
+	static CALLBACK_WATCH: RefCell<Option<(CallbackWatch, usize)>> = const { RefCell::new(None) };
+	// the id of the connection whose callback returned after this thread was replaced
+	static ORPHANED_ID: Cell<Option<u128>> = const { Cell::new(None) };
+}
+
+// records that a callback is running until it is dropped. See CallbackWatch.
+struct CallbackTimer {
+	active: bool,
+	id: u128,
+}
+
+fn watch_callback(callback: WatchedCallback, id: u128) -> CallbackTimer {
+	let active = CALLBACK_WATCH.with(|watch| match &*watch.borrow() {
+		Some((watch, generation)) if watch.generation.load(Ordering::Relaxed) == *generation => {
+			watch.id_high.store((id >> 64) as u64, Ordering::Relaxed);
+			watch.id_low.store(id as u64, Ordering::Relaxed);
+			watch.callback.store(callback as usize, Ordering::Relaxed);
+			watch
+				.started
+				.store(watch_millis().max(1), Ordering::Release);
+			true
+		}
+		_ => false,
+	});
+	CallbackTimer { active, id }
+}
+
+// milliseconds since WATCH_EPOCH. Unlike the wall clock, this never goes back.
+pub(crate) fn watch_millis() -> usize {
+	WATCH_EPOCH
+		.elapsed()
+		.as_millis()
+		.try_into()
+		.unwrap_or(usize::MAX)
+}
+
+// whether a callback that started at `started` has been running for longer than `timeout` at
+// `now` and was not already reported at `tripped`. The callback records `started` on its own
+// thread so it may be later than `now`.
+pub(crate) fn watch_expired(started: usize, tripped: usize, now: usize, timeout: usize) -> bool {
+	started != 0 && started != tripped && now.saturating_sub(started) > timeout
+}
+
+impl Drop for CallbackTimer {
+	fn drop(&mut self) {
+		if self.active {
+			CALLBACK_WATCH.with(|watch| {
+				// a thread that was replaced must not clear the state of its replacement
+				if let Some((watch, generation)) = &*watch.borrow() {
+					if watch.generation.load(Ordering::Relaxed) == *generation {
+						watch.started.store(0, Ordering::Release);
+					} else if self.id != u128::MAX {
+						ORPHANED_ID.with(|orphaned| orphaned.set(Some(self.id)));
+					}
+				}
+			});
+		}
+	}
+}
+
+impl WatchedCallback {
+	fn name(callback: usize) -> &'static str {
+		match callback {
+			1 => "on_read",
+			2 => "on_accept",
+			3 => "on_close",
+			4 => "on_housekeeper",
+			5 => "on_data_chunk",
+			6 => "on_raw_event",
+			7 => "on_slab_quota",
+			8 => "on_read_eof",
+			9 => "handler",
+			_ => "unknown",
+		}
+	}
+}
+
+impl CallbackWatch {
+	pub(crate) fn new(track_connections: bool) -> Result<Self, Error> {
+		let connections = if track_connections {
+			Some(lock_box!(HashMap::new())?)
+		} else {
+			None
+		};
+		Ok(Self {
+			started: Arc::new(AtomicUsize::new(0)),
+			callback: Arc::new(AtomicUsize::new(0)),
+			id_high: Arc::new(AtomicU64::new(0)),
+			id_low: Arc::new(AtomicU64::new(0)),
+			generation: Arc::new(AtomicUsize::new(0)),
+			connections,
+		})
+	}
+
+	fn id(&self) -> u128 {
+		let high = self.id_high.load(Ordering::Relaxed) as u128;
+		(high << 64) | self.id_low.load(Ordering::Relaxed) as u128
+	}
+}
+
+/// Parses an EvhClientBindAddr value. The port is optional and IPv6 addresses may include a
+/// scope id, given either as an interface index or (on unix) an interface name.
+pub(crate) fn parse_bind_addr(addr: &str) -> Result<SocketAddr, Error> {
+	if let Ok(sock_addr) = SocketAddr::from_str(addr) {
+		return Ok(sock_addr);
+	}
+	if let Ok(ip) = IpAddr::from_str(addr) {
+		return Ok(SocketAddr::new(ip, 0));
+	}
+
+	let text = format!("EvhClientBindAddr '{}' is not a valid address", addr);
+	let (host, port) = match addr.strip_prefix('[') {
+		Some(rest) => match rest.split_once("]:") {
+			Some((host, port)) => match port.parse::<u16>() {
+				Ok(port) => (host, port),
+				Err(_) => return Err(err!(ErrKind::Configuration, text)),
+			},
+			None => (rest.strip_suffix(']').unwrap_or(rest), 0),
+		},
+		None => (addr, 0),
+	};
+
+	match host.split_once('%') {
+		Some((ip, scope)) => {
+			let ip = match Ipv6Addr::from_str(ip) {
+				Ok(ip) => ip,
+				Err(_) => return Err(err!(ErrKind::Configuration, text)),
+			};
+			let scope_id = match scope.parse::<u32>() {
+				Ok(scope_id) => scope_id,
+				Err(_) => match interface_index(scope) {
+					Ok(scope_id) => scope_id,
+					Err(e) => {
+						let text = format!("{}: unknown scope id: {}", text, e);
+						return Err(err!(ErrKind::Configuration, text));
+					}
+				},
+			};
+			Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+		}
+		None => Err(err!(ErrKind::Configuration, text)),
+	}
+}
+
+/// Builds the error returned when an outbound connection fails. The socket error reported by
+/// the operating system is classified and attached so that callers can retrieve it using
+/// [`bmw_err::Error::connect_kind`].
+pub(crate) fn connect_error(text: &str, kind: ConnectErrorKind) -> Error {
+	let e: Error = err!(ErrKind::IO, text);
+	e.with_connect_kind(kind)
+}
+
+/// Connects to one of `addrs` as described in RFC 8305 (happy eyeballs). The addresses are
+/// tried alternating between address families, starting with the family of the first address.
+/// Each attempt runs on its own thread and the next attempt is started when the previous one
+/// fails or `attempt_delay` passes without it completing. The first stream to connect is
+/// returned and the streams of any attempts which complete later are closed.
+pub(crate) fn connect_happy_eyeballs(
+	addrs: &[SocketAddr],
+	attempt_delay: Duration,
+) -> Result<TcpStream, Error> {
+	let first_is_ipv4 = match addrs.first() {
+		Some(addr) => addr.is_ipv4(),
+		None => return Err(err!(ErrKind::IO, "no addresses to connect to")),
+	};
+	let (mut first, mut second): (VecDeque<SocketAddr>, VecDeque<SocketAddr>) = addrs
+		.iter()
+		.partition(|addr| addr.is_ipv4() == first_is_ipv4);
+	let mut ordered = vec![];
+	while let Some(addr) = first.pop_front() {
+		ordered.push(addr);
+		if let Some(addr) = second.pop_front() {
+			ordered.push(addr);
+		}
+	}
+	ordered.extend(second);
+
+	let (tx, rx) = channel();
+	let mut next = 0;
+	let mut pending = 0;
+
+	loop {
+		if next < ordered.len() {
+			let addr = ordered[next];
+			let tx = tx.clone();
+			spawn(move || {
+				// if another attempt has already won, the stream is dropped and closed
+				let _ = tx.send((addr, TcpStream::connect(addr)));
+			});
+			next += 1;
+			pending += 1;
+		}
+
+		let res = if next < ordered.len() {
+			rx.recv_timeout(attempt_delay).ok()
+		} else {
+			rx.recv().ok()
+		};
+
+		match res {
+			Some((_, Ok(strm))) => return Ok(strm),
+			Some((addr, Err(e))) => {
+				debug!("happy eyeballs connect to {} failed: {}", addr, e)?;
+				pending -= 1;
+				if pending == 0 && next >= ordered.len() {
+					let text = format!("could not connect to any of {:?}: {}", ordered, e);
+					return Err(connect_error(&text, ConnectErrorKind::from(&e)));
+				}
+			}
+			// attempt delay passed, start the next attempt
+			None => {}
+		}
+	}
+}
+
+/// Connects to `host`:`port` waiting at most `timeout` in total. The addresses of the host are
+/// tried in order, each with a non-blocking connect that is waited on (poll on unix, select on
+/// windows) for the time that remains. If the time runs out, the error is classified as
+/// [`bmw_err::ConnectErrorKind::TimedOut`].
+pub(crate) fn connect_with_timeout(
+	host: &str,
+	port: u16,
+	timeout: Duration,
+) -> Result<TcpStream, Error> {
+	if timeout.is_zero() {
+		return Err(err!(
+			ErrKind::IllegalArgument,
+			"timeout must be greater than 0"
+		));
+	}
+	let deadline = Instant::now() + timeout;
+	let mut last_err = None;
+	for addr in (host, port).to_socket_addrs()? {
+		let now = Instant::now();
+		if now >= deadline {
+			last_err = Some(std::io::Error::from(std::io::ErrorKind::TimedOut));
+			break;
+		}
+		match TcpStream::connect_timeout(&addr, deadline - now) {
+			Ok(strm) => return Ok(strm),
+			Err(e) => {
+				debug!("connect to {} failed: {}", addr, e)?;
+				last_err = Some(e);
+			}
+		}
+	}
+	match last_err {
+		Some(e) => {
+			let text = format!(
+				"could not connect to {}:{} within {:?}: {}",
+				host, port, timeout, e
+			);
+			Err(connect_error(&text, ConnectErrorKind::from(&e)))
+		}
+		None => {
+			let text = format!("no addresses found for {}:{}", host, port);
+			Err(err!(ErrKind::IO, text))
+		}
+	}
+}
+
+/// Parses a PROXY protocol (v1 or v2) header at the start of `buf`. `None` is returned if more
+/// data is needed. Otherwise, the length of the header and the source address it advertises (if
+/// any) are returned.
+pub(crate) fn parse_proxy_header(buf: &[u8]) -> Result<Option<(usize, Option<SocketAddr>)>, Error> {
+	let len = buf.len().min(PROXY_V2_SIGNATURE.len());
+	if buf[..len] == PROXY_V2_SIGNATURE[..len] {
+		if buf.len() < PROXY_V2_HEADER_LEN {
+			return Ok(None);
+		}
+		return parse_proxy_v2(buf);
+	}
+
+	let len = buf.len().min(PROXY_V1_PREFIX.len());
+	if buf[..len] == PROXY_V1_PREFIX[..len] {
+		return parse_proxy_v1(buf);
+	}
+
+	Err(malformed_proxy_header("unknown signature"))
+}
+
+fn parse_proxy_v1(buf: &[u8]) -> Result<Option<(usize, Option<SocketAddr>)>, Error> {
+	let end = match buf
+		.windows(2)
+		.take(PROXY_V1_MAX_LEN - 1)
+		.position(|w| w == b"\r\n")
+	{
+		Some(end) => end,
+		None => {
+			if buf.len() >= PROXY_V1_MAX_LEN {
+				return Err(malformed_proxy_header("v1 header too long"));
+			}
+			return Ok(None);
+		}
+	};
+
+	let line = match std::str::from_utf8(&buf[..end]) {
+		Ok(line) => line,
+		Err(_) => return Err(malformed_proxy_header("v1 header is not valid utf8")),
+	};
+	let parts: Vec<&str> = line.split(' ').collect();
+	let addr = match parts[1..] {
+		["UNKNOWN", ..] => None,
+		[proto, src, dst, sport, dport] if proto == "TCP4" || proto == "TCP6" => {
+			let src = IpAddr::from_str(src);
+			let dst = IpAddr::from_str(dst);
+			let sport = u16::from_str(sport);
+			let dport = u16::from_str(dport);
+			match (src, dst, sport, dport) {
+				(Ok(src), Ok(dst), Ok(sport), Ok(_))
+					if src.is_ipv4() == (proto == "TCP4") && dst.is_ipv4() == src.is_ipv4() =>
+				{
+					Some(SocketAddr::new(src, sport))
+				}
+				_ => return Err(malformed_proxy_header("invalid v1 address")),
+			}
+		}
+		_ => return Err(malformed_proxy_header("invalid v1 header")),
+	};
+
+	Ok(Some((end + 2, addr)))
+}
+
+fn parse_proxy_v2(buf: &[u8]) -> Result<Option<(usize, Option<SocketAddr>)>, Error> {
+	let ver_cmd = buf[PROXY_V2_SIGNATURE.len()];
+	let family = buf[PROXY_V2_SIGNATURE.len() + 1] >> 4;
+	if ver_cmd >> 4 != 2 {
+		return Err(malformed_proxy_header("unsupported v2 version"));
+	}
+	let len: usize = u16::from_be_bytes([buf[14], buf[15]]).into();
+	let total = PROXY_V2_HEADER_LEN + len;
+	if buf.len() < total {
+		return Ok(None);
+	}
+
+	let addrs = &buf[PROXY_V2_HEADER_LEN..total];
+	let addr = match (ver_cmd & 0xF, family) {
+		// LOCAL command. The connection was made by the proxy itself.
+		(0x0, _) => None,
+		// PROXY command over AF_INET
+		(0x1, 0x1) if addrs.len() >= 12 => {
+			let mut ip = [0u8; 4];
+			ip.copy_from_slice(&addrs[0..4]);
+			let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+			Some(SocketAddr::new(IpAddr::from(ip), port))
+		}
+		// PROXY command over AF_INET6
+		(0x1, 0x2) if addrs.len() >= 36 => {
+			let mut ip = [0u8; 16];
+			ip.copy_from_slice(&addrs[0..16]);
+			let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+			Some(SocketAddr::new(IpAddr::from(ip), port))
+		}
+		// AF_UNSPEC and AF_UNIX do not carry an ip address
+		(0x1, 0x0) | (0x1, 0x3) => None,
+		_ => return Err(malformed_proxy_header("invalid v2 header")),
+	};
+
+	Ok(Some((total, addr)))
+}
+
+fn malformed_proxy_header(reason: &str) -> Error {
+	let text = format!("malformed PROXY protocol header: {}", reason);
+	err!(ErrKind::CorruptedData, text)
+}
+
+impl CaptureWriter {
+	pub(crate) fn new() -> Result<Self, Error> {
+		Ok(Self {
+			tx: lock_box!(None)?,
+			drops: Arc::new(AtomicUsize::new(0)),
+			queued_bytes: Arc::new(AtomicUsize::new(0)),
+		})
+	}
+
+	// returns the sender for the writer thread, starting the thread if needed
+	fn sender(&mut self) -> Result<SyncSender<CaptureRecord>, Error> {
+		let mut tx = self.tx.wlock()?;
+		let guard = tx.guard()?;
+		if let Some(tx) = &**guard {
+			return Ok(tx.clone());
+		}
+		let (tx, rx) = sync_channel(EVH_CAPTURE_QUEUE_SIZE);
+		let queued_bytes = self.queued_bytes.clone();
+		spawn(move || Self::run(rx, queued_bytes));
+		(**guard) = Some(tx.clone());
+		Ok(tx)
+	}
+
+	fn run(rx: Receiver<CaptureRecord>, queued_bytes: Arc<AtomicUsize>) {
+		let mut files = HashMap::new();
+		while let Ok(record) = rx.recv() {
+			Self::process(&mut files, record, &queued_bytes);
+			while let Ok(record) = rx.try_recv() {
+				Self::process(&mut files, record, &queued_bytes);
+			}
+			// flush whenever the queue is drained so the files are current while idle
+			for (id, (inbound, outbound)) in files.iter_mut() {
+				if let Err(e) = inbound.flush().and_then(|_| outbound.flush()) {
+					let _ = warn!("flushing capture of connection {} failed: {}", id, e);
+				}
+			}
+		}
+	}
+
+	fn process(
+		files: &mut HashMap<u128, (BufWriter<File>, BufWriter<File>)>,
+		record: CaptureRecord,
+		queued_bytes: &AtomicUsize,
+	) {
+		if let CaptureRecord::Data(_, _, _, data) = &record {
+			queued_bytes.fetch_sub(data.len(), Ordering::Relaxed);
+		}
+		let (id, res) = match record {
+			CaptureRecord::Open(id, dir) => match Self::open(&dir, id) {
+				Ok(f) => {
+					files.insert(id, f);
+					(id, Ok(()))
+				}
+				Err(e) => (id, Err(e.into())),
+			},
+			CaptureRecord::Data(id, inbound, millis, data) => match files.get_mut(&id) {
+				Some((i, o)) => {
+					let mut w = BinWriter::new(if inbound { i } else { o });
+					let options = FrameOptions {
+						max_len: u32::MAX as usize,
+						crc32: false,
+					};
+					let res = w
+						.write_u64(millis)
+						.and_then(|_| write_frame(&mut w, &data, &options));
+					(id, res)
+				}
+				None => (id, Ok(())),
+			},
+			CaptureRecord::Close(id) => match files.remove(&id) {
+				Some((mut i, mut o)) => {
+					(id, i.flush().and_then(|_| o.flush()).map_err(|e| e.into()))
+				}
+				None => (id, Ok(())),
+			},
+		};
+		if let Err(e) = res {
+			let _ = warn!("capture of connection {} failed: {}", id, e);
+		}
+	}
+
+	fn open(dir: &Path, id: u128) -> std::io::Result<(BufWriter<File>, BufWriter<File>)> {
+		let mut options = OpenOptions::new();
+		options.create(true).append(true);
+		let inbound = options.open(dir.join(format!("{}.in", id)))?;
+		let outbound = options.open(dir.join(format!("{}.out", id)))?;
+		Ok((BufWriter::new(inbound), BufWriter::new(outbound)))
+	}
+}
+
+impl Capture {
+	fn record(&self, inbound: bool, data: &[u8]) -> Result<(), Error> {
+		let millis = try_into!(now_millis()?)?;
+		// counted before it is sent so that the writer thread never subtracts it first
+		self.queued_bytes.fetch_add(data.len(), Ordering::Relaxed);
+		if !self.send(CaptureRecord::Data(self.id, inbound, millis, data.to_vec()))? {
+			self.queued_bytes.fetch_sub(data.len(), Ordering::Relaxed);
+		}
+		Ok(())
+	}
+
+	// never blocks. If the writer can't keep up, the record is dropped. Returns whether the
+	// record was queued.
+	fn send(&self, record: CaptureRecord) -> Result<bool, Error> {
+		match self.tx.try_send(record) {
+			Err(TrySendError::Full(_)) => {
+				if self.drops.fetch_add(1, Ordering::Relaxed) == 0 {
+					warn!("capture queue is full. Dropping records")?;
+				}
+				Ok(false)
+			}
+			Err(TrySendError::Disconnected(_)) => Ok(false),
+			Ok(_) => Ok(true),
+		}
+	}
+}
+
+// overwrite `buf` with zeros. The writes are volatile so that the optimizer does not remove them
+// even though the memory is not read again. See Connection::set_sensitive.
+pub(crate) fn zeroize(buf: &mut [u8]) {
+	for b in buf.iter_mut() {
+		// SAFETY: b is a valid, aligned and exclusive reference
+		unsafe { std::ptr::write_volatile(b, 0) };
+	}
+	compiler_fence(Ordering::SeqCst);
+}
+
+// remove the first `n` bytes of `buf` without leaving a copy of any of its data behind the
+// new length. Unlike Vec::drain followed by shrink_to_fit, the buffer is not reallocated.
+pub(crate) fn drain_zeroize(buf: &mut Vec<u8>, n: usize) {
+	let len = buf.len();
+	let n = n.min(len);
+	buf.copy_within(n..len, 0);
+	zeroize(&mut buf[len - n..]);
+	buf.truncate(len - n);
+}
+
+// append `data` to `buf`. If `buf` has to grow, its old allocation is zeroized before it is freed.
+pub(crate) fn extend_zeroize(buf: &mut Vec<u8>, data: &[u8]) {
+	if buf.capacity() - buf.len() < data.len() {
+		let capacity = (buf.len() + data.len()).max(buf.capacity() * 2);
+		let mut grown = Vec::with_capacity(capacity);
+		grown.extend_from_slice(buf);
+		zeroize(buf);
+		*buf = grown;
+	}
+	buf.extend_from_slice(data);
+}
+
+fn now_millis() -> Result<usize, Error> {
+	let now = SystemTime::now();
+	let now: usize = try_into!(now.duration_since(UNIX_EPOCH)?.as_millis())?;
+	Ok(now)
+}
+
+// register a server connection's origin_id so that it appears in EvhStats::per_origin
+fn add_origin(stats: &mut Box<dyn LockBox<GlobalStats>>, origin_id: u128) -> Result<(), Error> {
+	let mut stats = stats.wlock()?;
+	let guard = stats.guard()?;
+	guard.stats.origins.entry(origin_id).or_default();
+	Ok(())
+}
+
+// record the address a server connection listens on so it can be included in warnings
+fn add_listen_addr(stats: &mut Box<dyn LockBox<GlobalStats>>, handle: Handle) -> Result<(), Error> {
+	let addr = local_addr_impl(handle)?;
+	let mut stats = stats.wlock()?;
+	let guard = stats.guard()?;
+	if !guard.listen_addrs.contains(&addr) {
+		guard.listen_addrs.push(addr);
+	}
+	Ok(())
+}
+
+// the number of connections that may still be opened before reaching the fd limit
+fn fd_headroom(stats: &dyn LockBox<GlobalStats>, fd_limit: Option<usize>) -> Result<usize, Error> {
+	match fd_limit {
+		Some(fd_limit) => {
+			let stats = stats.rlock()?;
+			let guard = stats.guard()?;
+			let count: usize = guard.handle_counts.iter().sum();
+			Ok(fd_limit.saturating_sub(count))
+		}
+		None => Ok(usize::MAX),
+	}
+}
+
+// log a warning when the number of connections exceeds threshold_percent of the fd limit
+pub(crate) fn check_fd_limit(
+	global_stats: &mut GlobalStats,
+	fd_limit: usize,
+	threshold_percent: usize,
+	now: usize,
+) -> Result<(), Error> {
+	let count: usize = global_stats.handle_counts.iter().sum();
+	if count.saturating_mul(100) <= fd_limit.saturating_mul(threshold_percent) {
+		return Ok(());
+	}
+	// rate limit the warnings so a busy server does not flood the log
+	if let Some(last) = global_stats.last_fd_warning {
+		if now.saturating_sub(last) < EVH_FD_WARN_INTERVAL_MILLIS {
+			return Ok(());
+		}
+	}
+	global_stats.last_fd_warning = Some(now);
+	global_stats.fd_warnings += 1;
+	warn!(
+		"open connections ({}) exceed {}% of the file descriptor limit ({}). listening on: {:?}",
+		count, threshold_percent, fd_limit, global_stats.listen_addrs
+	)?;
+	Ok(())
+}
+
+impl Metrics {
+	pub(crate) fn new(
+		recorder: Box<dyn MetricsRecorder + Send + Sync>,
+		config: &EventHandlerConfig,
+	) -> Self {
+		Self {
+			recorder,
+			disabled: AtomicBool::new(false),
+			connections: (0..config.threads).map(|_| AtomicU64::new(0)).collect(),
+			pending_write_bytes: (0..config.threads).map(|_| AtomicU64::new(0)).collect(),
+			config: config.clone(),
+		}
+	}
+
+	pub(crate) fn incr(&self, metric: EvhMetric, by: u64) {
+		if by > 0 {
+			self.record(|recorder| recorder.incr_counter(metric, by));
+		}
+	}
+
+	// set the thread's share of the gauges and report the totals of all threads
+	pub(crate) fn set_gauges(&self, tid: usize, connections: u64, pending_write_bytes: u64) {
+		self.connections[tid].store(connections, Ordering::Relaxed);
+		self.pending_write_bytes[tid].store(pending_write_bytes, Ordering::Relaxed);
+		let sum = |gauges: &Vec<AtomicU64>| -> u64 {
+			gauges
+				.iter()
+				.map(|gauge| gauge.load(Ordering::Relaxed))
+				.sum()
+		};
+		let connections = sum(&self.connections);
+		let pending_write_bytes = sum(&self.pending_write_bytes);
+		self.record(|recorder| {
+			recorder.set_gauge(EvhMetric::CurrentConnections, connections);
+			recorder.set_gauge(EvhMetric::PendingWriteBytes, pending_write_bytes);
+		});
+	}
+
+	// call the recorder unless it has panicked before. It is disabled if it panics.
+	fn record<F>(&self, f: F)
+	where
+		F: FnOnce(&(dyn MetricsRecorder + Send + Sync)),
+	{
+		if self.disabled.load(Ordering::Relaxed) {
+			return;
+		}
+		let recorder = self.recorder.as_ref();
+		if catch_unwind(AssertUnwindSafe(|| f(recorder))).is_err()
+			&& !self.disabled.swap(true, Ordering::Relaxed)
+		{
+			let _ = evh_log!(
+				&self.config,
+				Warn,
+				"the metrics recorder panicked. No more metrics will be recorded"
+			);
+		}
+	}
+}
+
+// prefix the line with the EvhName, if configured, and log it to the logger set with
+// EventHandler::set_logger. If no logger was set, the global logger is used with the log level
+// of the calling module.
+fn evh_log_impl(
+	config: &EventHandlerConfig,
+	level: LogLevel,
+	line: &str,
+	global_level: LogLevel,
+) -> Result<(), Error> {
+	let line = match &config.name {
+		Some(name) => format!("[{}] {}", name, line),
+		None => line.to_string(),
+	};
+	match rlock!(config.logger).clone() {
+		Some(mut logger) => logger.log(level, &line),
+		None => GlobalLogContainer::log(level, &line, global_level, LoggingType::Standard),
+	}
+}
+
+// the options accepted when an EventHandler is built
+fn config_options() -> Vec<CN> {
+	vec![
+		CN::EvhReadSlabSize,
+		CN::EvhReadSlabCount,
+		CN::EvhReadSlabCountPerThread,
+		CN::EvhTimeout,
+		CN::EvhThreads,
+		CN::EvhHouseKeeperFrequencyMillis,
+		CN::EvhStatsUpdateMillis,
+		CN::EvhOutOfSlabsMessage,
+		CN::EvhClientBindAddr,
+		CN::EvhProxyProtocol,
+		CN::EvhProxyHeaderTimeoutMillis,
+		CN::EvhDeferAcceptSecs,
+		CN::EvhRequireDataWithinMillis,
+		CN::EvhMaxWriteBytesPerLoop,
+		CN::EvhFdWarnThresholdPercent,
+		CN::EvhCallbackTimeoutMillis,
+		CN::EvhWatchdogAction,
+		CN::EvhRecvBufferSize,
+		CN::EvhSendBufferSize,
+		CN::EvhStrictSockOpts,
+		CN::EvhCaptureAll,
+		CN::EvhName,
+		CN::EvhStrictOrdering,
+		CN::EvhMaxSlabsPerConnection,
+		CN::EvhSlabQuotaAction,
+		CN::EvhStrictKeys,
+		CN::EvhStartTimeoutMillis,
+		CN::EvhOnAcceptBudgetMicros,
+		CN::EvhMemoryBudgetBytes,
+		CN::Debug,
+	]
+}
+
+// the options that may be changed with EventHandler::update_config
+fn reloadable_options() -> Vec<CN> {
+	vec![
+		CN::EvhHouseKeeperFrequencyMillis,
+		CN::EvhStatsUpdateMillis,
+		CN::EvhTimeout,
+		CN::EvhMaxWriteBytesPerLoop,
+		CN::EvhOutOfSlabsMessage,
+	]
+}
+
+// validate the options with the rules used when building an EventHandler and publish them in
+// the shared snapshot. The threads pick them up on their next event loop iteration.
+fn update_config(config: &mut EventHandlerConfig, options: Vec<ConfigOption>) -> Result<(), Error> {
+	let reloadable = reloadable_options();
+	let specified = ConfigBuilder::build_config(options.clone());
+	let rejected: Vec<String> = config_options()
+		.iter()
+		.filter(|name| !reloadable.contains(name) && specified.get(name).is_some())
+		.map(|name| format!("{:?}", name))
+		.collect();
+	if !rejected.is_empty() {
+		let text = format!(
+			"options that cannot be changed after the EventHandler is built: {}",
+			rejected.join(", ")
+		);
+		return Err(err!(ErrKind::Configuration, text));
+	}
+	specified.check_config(reloadable, vec![])?;
+	let values = build_config(options)?;
+
+	let snapshot;
+	{
+		let mut reloadable = config.reloadable.wlock()?;
+		let guard = reloadable.guard()?;
+		if specified.get(&CN::EvhTimeout).is_some() {
+			guard.timeout = values.timeout;
+		}
+		if specified.get(&CN::EvhHouseKeeperFrequencyMillis).is_some() {
+			guard.housekeeping_frequency_millis = values.housekeeping_frequency_millis;
+		}
+		if specified.get(&CN::EvhStatsUpdateMillis).is_some() {
+			guard.stats_update_frequency_millis = values.stats_update_frequency_millis;
+		}
+		if specified.get(&CN::EvhOutOfSlabsMessage).is_some() {
+			guard.out_of_slabs_message = values.out_of_slabs_message;
+		}
+		if specified.get(&CN::EvhMaxWriteBytesPerLoop).is_some() {
+			guard.max_write_bytes_per_loop = values.max_write_bytes_per_loop;
+		}
+		snapshot = ConfigBuilder::build_config(guard.options());
+	}
+	config.reload_generation.fetch_add(1, Ordering::AcqRel);
+
+	// keep an audit trail of the changes
+	let changes = wlock!(config.config_history).record(&*snapshot)?;
+	for change in changes {
+		let old = change.old.unwrap_or_default();
+		let new = change.new.unwrap_or_default();
+		evh_log!(
+			config,
+			Info,
+			"config change: {:?}: {} -> {}",
+			change.name,
+			old,
+			new
+		)?;
+	}
+	Ok(())
+}
+
+// copy the options changed by update_config into this thread's config
+fn reload_config(config: &mut EventHandlerConfig, generation: &mut u64) -> Result<(), Error> {
+	let current = config.reload_generation.load(Ordering::Acquire);
+	if current != *generation {
+		let reloadable = rlock!(config.reloadable).clone();
+		config.timeout = reloadable.timeout;
+		config.housekeeping_frequency_millis = reloadable.housekeeping_frequency_millis;
+		config.stats_update_frequency_millis = reloadable.stats_update_frequency_millis;
+		config.out_of_slabs_message = reloadable.out_of_slabs_message;
+		config.max_write_bytes_per_loop = reloadable.max_write_bytes_per_loop;
+		*generation = current;
+	}
+	Ok(())
+}
+
+impl ReloadableConfig {
+	// the current values as options so that they can be recorded in the config history
+	pub(crate) fn options(&self) -> Vec<ConfigOption> {
+		vec![
+			ConfigOption::EvhHouseKeeperFrequencyMillis(self.housekeeping_frequency_millis),
+			ConfigOption::EvhStatsUpdateMillis(self.stats_update_frequency_millis),
+			ConfigOption::EvhTimeout(self.timeout),
+			ConfigOption::EvhMaxWriteBytesPerLoop(self.max_write_bytes_per_loop),
+			ConfigOption::EvhOutOfSlabsMessage(self.out_of_slabs_message.clone()),
+		]
+	}
+}
+
+fn build_config(configs: Vec<ConfigOption>) -> Result<EventHandlerConfig, Error> {
+	let config = ConfigBuilder::build_config(configs);
+	config.check_config(config_options(), vec![])?;
+
+	let threads = config.get_or_usize(&CN::EvhThreads, EVH_DEFAULT_THREADS);
+	let read_slab_count = config.get_or_usize(&CN::EvhReadSlabCount, usize::MAX);
+	let evhrlcpt = &CN::EvhReadSlabCountPerThread;
+	let read_slab_count_per_thread = config.get_or_usize(evhrlcpt, usize::MAX);
+	let read_slab_size = config.get_or_usize(&CN::EvhReadSlabSize, EVH_DEFAULT_READ_SLAB_SIZE);
+	let debug = config.get_or_bool(&CN::Debug, false);
+	let timeout = config.get_or_u16(&CN::EvhTimeout, EVH_DEFAULT_TIMEOUT);
+	let evhkfm = &CN::EvhHouseKeeperFrequencyMillis;
+	let default = EVH_DEFAULT_HOUSEKEEPING_FREQUENCY_MILLIS;
+	let housekeeping_frequency_millis = config.get_or_usize(evhkfm, default);
+	let evhsum = &CN::EvhStatsUpdateMillis;
+	let default = EVH_DEFAULT_STATS_UPDATE_MILLIS;
+	let stats_update_frequency_millis = config.get_or_usize(evhsum, default);
+	let default = EVH_DEFAULT_OUT_OF_SLABS_MESSAGE.to_string();
+	let out_of_slabs_message = config.get_or_string(&CN::EvhOutOfSlabsMessage, default);
+	let client_bind_addr = config.get_or_string(&CN::EvhClientBindAddr, "".to_string());
+	let client_bind_addr = if client_bind_addr.is_empty() {
+		None
+	} else {
+		Some(parse_bind_addr(&client_bind_addr)?)
+	};
+	let proxy_protocol = config.get_or_bool(&CN::EvhProxyProtocol, false);
+	let evhphtm = &CN::EvhProxyHeaderTimeoutMillis;
+	let default = EVH_DEFAULT_PROXY_HEADER_TIMEOUT_MILLIS;
+	let proxy_header_timeout_millis = config.get_or_usize(evhphtm, default);
+	let defer_accept_secs = config.get_or_usize(&CN::EvhDeferAcceptSecs, usize::MAX);
+	let evhrdwm = &CN::EvhRequireDataWithinMillis;
+	let require_data_within_millis = config.get_or_usize(evhrdwm, usize::MAX);
+	let max_write_bytes_per_loop = config.get_or_usize(&CN::EvhMaxWriteBytesPerLoop, 0);
+	let evhfwtp = &CN::EvhFdWarnThresholdPercent;
+	let default = EVH_DEFAULT_FD_WARN_THRESHOLD_PERCENT;
+	let fd_warn_threshold_percent = config.get_or_usize(evhfwtp, default);
+	let evhctm = &CN::EvhCallbackTimeoutMillis;
+	let callback_timeout_millis = config.get_or_usize(evhctm, usize::MAX);
+	let watchdog_action = match config.get(&CN::EvhWatchdogAction) {
+		Some(ConfigOption::EvhWatchdogAction(action)) => Some(action),
+		_ => None,
+	};
+	let recv_buffer_size = config.get_or_usize(&CN::EvhRecvBufferSize, 0);
+	let send_buffer_size = config.get_or_usize(&CN::EvhSendBufferSize, 0);
+	let strict_sock_opts = config.get_or_bool(&CN::EvhStrictSockOpts, false);
+	let capture_all = config.get_or_string(&CN::EvhCaptureAll, "".to_string());
+	let capture_all = if capture_all.is_empty() {
+		None
+	} else {
+		Some(PathBuf::from(capture_all))
+	};
+	let name = config.get_or_string(&CN::EvhName, "".to_string());
+	let name = (!name.is_empty()).then_some(name);
+	let strict_ordering = config.get_or_bool(&CN::EvhStrictOrdering, false);
+	let strict_keys = config.get_or_bool(&CN::EvhStrictKeys, false);
+	let evhstm = &CN::EvhStartTimeoutMillis;
+	let start_timeout_millis = config.get_or_usize(evhstm, EVH_DEFAULT_START_TIMEOUT_MILLIS);
+	let accept_budget_micros = config.get_or_usize(&CN::EvhOnAcceptBudgetMicros, 0);
+	let memory_budget = config.get_or_usize(&CN::EvhMemoryBudgetBytes, usize::MAX);
+	let max_slabs_per_connection = config.get_or_usize(&CN::EvhMaxSlabsPerConnection, usize::MAX);
+	let slab_quota_action = config.get_or_string(&CN::EvhSlabQuotaAction, "".to_string());
+
+	if callback_timeout_millis == 0 {
+		let text = "EvhCallbackTimeoutMillis must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if watchdog_action.is_some() && callback_timeout_millis == usize::MAX {
+		let text = "EvhWatchdogAction requires EvhCallbackTimeoutMillis";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+	let watchdog_action = watchdog_action.unwrap_or(WatchdogAction::Log);
+
+	let slab_quota_close = match slab_quota_action.as_str() {
+		"" | "Pause" => false,
+		"Close" => true,
+		_ => {
+			let text = format!(
+				"EvhSlabQuotaAction must be Pause or Close. Found: {}",
+				slab_quota_action
+			);
+			return Err(err!(ErrKind::Configuration, text));
+		}
+	};
+
+	if !slab_quota_action.is_empty() && max_slabs_per_connection == usize::MAX {
+		let text = "EvhSlabQuotaAction requires EvhMaxSlabsPerConnection";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if max_slabs_per_connection == 0 {
+		let text = "EvhMaxSlabsPerConnection must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if fd_warn_threshold_percent == 0 || fd_warn_threshold_percent > 100 {
+		let text = "EvhFdWarnThresholdPercent must be between 1 and 100";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if read_slab_count == 0 {
+		let text = "EvhReadSlabCount count must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if read_slab_count_per_thread == 0 {
+		let text = "EvhReadSlabCountPerThread count must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	// each thread has its own read slab allocator. EvhReadSlabCount is the total for all
+	// threads and EvhReadSlabCountPerThread is the count for each thread.
+	let read_slab_count = match (read_slab_count, read_slab_count_per_thread) {
+		(usize::MAX, usize::MAX) => EVH_DEFAULT_READ_SLAB_COUNT,
+		(count, usize::MAX) => count.div_ceil(threads.max(1)),
+		(usize::MAX, count) => count,
+		_ => {
+			let text = "EvhReadSlabCount and EvhReadSlabCountPerThread may not both be specified";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+	};
+
+	if read_slab_size < 25 {
+		let text = "EvhReadSlabSize must be at least 25";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if timeout == 0 {
+		let text = "EvhTimeout must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if housekeeping_frequency_millis == 0 {
+		let text = "EvhHouseKeeperFrequencyMillis must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if proxy_header_timeout_millis == 0 {
+		let text = "EvhProxyHeaderTimeoutMillis must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if defer_accept_secs == 0 {
+		let text = "EvhDeferAcceptSecs must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if require_data_within_millis == 0 {
+		let text = "EvhRequireDataWithinMillis must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if start_timeout_millis == 0 {
+		let text = "EvhStartTimeoutMillis must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	if memory_budget == 0 {
+		let text = "EvhMemoryBudgetBytes must not be 0";
+		return Err(err!(ErrKind::Configuration, text));
+	}
+
+	let reloadable = ReloadableConfig {
+		timeout,
+		housekeeping_frequency_millis,
+		stats_update_frequency_millis,
+		out_of_slabs_message: out_of_slabs_message.clone(),
+		max_write_bytes_per_loop,
+	};
+	// the initial values are the first snapshot so that updates are logged as changes to them
+	let mut config_history = ConfigHistory::new(EVH_CONFIG_HISTORY_SIZE)?;
+	config_history.record(&*ConfigBuilder::build_config(reloadable.options()))?;
+
+	let evhc = EventHandlerConfig {
+		threads,
+		debug,
+		timeout,
+		read_slab_size,
+		read_slab_count,
+		housekeeping_frequency_millis,
+		stats_update_frequency_millis,
+		out_of_slabs_message,
+		client_bind_addr,
+		proxy_protocol,
+		proxy_header_timeout_millis,
+		defer_accept_secs: (defer_accept_secs != usize::MAX).then_some(defer_accept_secs),
+		require_data_within_millis: (require_data_within_millis != usize::MAX)
+			.then_some(require_data_within_millis),
+		max_write_bytes_per_loop,
+		fd_limit: fd_limit_impl(),
+		fd_warn_threshold_percent,
+		callback_timeout_millis: (callback_timeout_millis != usize::MAX)
+			.then_some(callback_timeout_millis),
+		watchdog_action,
+		recv_buffer_size,
+		send_buffer_size,
+		strict_sock_opts,
+		capture_all,
+		reloadable: lock_box!(reloadable)?,
+		reload_generation: Arc::new(AtomicU64::new(0)),
+		config_history: lock_box!(config_history)?,
+		name,
+		strict_ordering,
+		strict_keys,
+		start_timeout_millis,
+		accept_budget_micros,
+		max_slabs_per_connection: (max_slabs_per_connection != usize::MAX)
+			.then_some(max_slabs_per_connection),
+		slab_quota_close,
+		memory: (memory_budget != usize::MAX).then(|| MemoryBudget::new(memory_budget)),
+		logger: lock_box!(None)?,
+	};
+	Ok(evhc)
+}
+
+// apply EvhRecvBufferSize and EvhSendBufferSize to a socket. Failures are only logged unless
+// EvhStrictSockOpts is set.
+fn apply_buffer_sizes(handle: Handle, config: &EventHandlerConfig) -> Result<(), Error> {
+	let mut res = Ok(());
+	if config.recv_buffer_size != 0 {
+		res = set_recv_buffer_size_impl(handle, config.recv_buffer_size);
+	}
+	if res.is_ok() && config.send_buffer_size != 0 {
+		res = set_send_buffer_size_impl(handle, config.send_buffer_size);
+	}
+	match res {
+		Err(e) if !config.strict_sock_opts => {
+			evh_log!(
+				config,
+				Warn,
+				"socket buffer sizes not applied to handle {}: {}",
+				handle,
+				e
+			)?;
+			Ok(())
+		}
+		res => res,
+	}
+}
+
 fn add_connection(
 	debug_info: &DebugInfo,
 	state: &mut Array<Box<dyn LockBox<EventHandlerState>>>,
 	wakeups: &mut Array<Wakeup>,
 	mut connection: Connection,
-	is_server: bool,
-	tid: usize,
-	handle: Handle,
+	is_server: bool,
+	tid: usize,
+	handle: Handle,
+) -> Result<(), Error> {
+	let (tx, rx) = sync_channel(1);
+	connection.set_tx(tx);
+	connection.debug_info = debug_info.clone();
+	debug!("adding server handle = {}, tid = {}", handle, tid)?;
+
+	{
+		let mut state = state[tid].wlock()?;
+		let guard = state.guard()?;
+		ensure_alive(tid, &(**guard).health)?;
+		let nv = if is_server {
+			ConnectionVariant::ServerConnection(connection)
+		} else {
+			ConnectionVariant::ClientConnection(connection)
+		};
+		(**guard).nconnections.push_back(nv);
+	}
+
+	debug!("about to wakeup")?;
+
+	wakeups[tid].wakeup()?;
+
+	// the thread may exit before it picks up the connection, so don't wait on it forever
+	let poll = Duration::from_millis(EVH_LIVENESS_POLL_MILLIS);
+	loop {
+		match rx.recv_timeout(poll) {
+			Ok(res) => return res,
+			Err(RecvTimeoutError::Timeout) => ensure_alive(tid, &rlock!(state[tid]).health)?,
+			Err(RecvTimeoutError::Disconnected) => {
+				let text = format!("evh thread {} dropped the connection", tid);
+				return Err(err!(ErrKind::IllegalState, text));
+			}
+		}
+	}
+}
+
+// returns an error if the thread has exited. A thread that is still starting is considered
+// alive since it picks up its new connections once it has initialized.
+fn ensure_alive(tid: usize, health: &ThreadHealth) -> Result<(), Error> {
+	match health {
+		ThreadHealth::Starting | ThreadHealth::Running => Ok(()),
+		ThreadHealth::Stopped => {
+			let text = format!("evh thread {} is not running: stopped", tid);
+			Err(err!(ErrKind::IllegalState, text))
+		}
+		ThreadHealth::Failed(e) => {
+			let text = format!("evh thread {} is not running: {}", tid, e);
+			Err(err!(ErrKind::IllegalState, text))
+		}
+	}
+}
+
+fn thread_health(
+	state: &Array<Box<dyn LockBox<EventHandlerState>>>,
+	threads: usize,
+) -> Result<Vec<ThreadHealth>, Error> {
+	let mut ret = vec![];
+	for i in 0..threads {
+		ret.push(rlock!(state[i]).health.clone());
+	}
+	Ok(ret)
+}
+
+// waits until every thread has initialized. An error naming the thread is returned if a thread
+// exits first or if EvhStartTimeoutMillis elapses.
+fn wait_for_threads(
+	config: &EventHandlerConfig,
+	state: &Array<Box<dyn LockBox<EventHandlerState>>>,
+) -> Result<(), Error> {
+	let deadline = now_millis()?.saturating_add(config.start_timeout_millis);
+	loop {
+		let health = thread_health(state, config.threads)?;
+		let mut starting = vec![];
+		for (tid, health) in health.iter().enumerate() {
+			match health {
+				ThreadHealth::Running => {}
+				ThreadHealth::Starting => starting.push(tid.to_string()),
+				ThreadHealth::Stopped => {
+					let text = format!("evh thread {} stopped while starting", tid);
+					return Err(err!(ErrKind::IllegalState, text));
+				}
+				ThreadHealth::Failed(e) => {
+					let text = format!("evh thread {} failed to start: {}", tid, e);
+					return Err(err!(ErrKind::IllegalState, text));
+				}
+			}
+		}
+		cbreak!(starting.is_empty());
+		if now_millis()? >= deadline {
+			let text = format!(
+				"evh threads did not start within {} ms. Still starting: {}",
+				config.start_timeout_millis,
+				starting.join(", ")
+			);
+			return Err(err!(ErrKind::Timeout, text));
+		}
+		sleep(Duration::from_millis(EVH_LIVENESS_POLL_MILLIS));
+	}
+	Ok(())
+}
+
+// add a connection built with EvhBuilder::import_connection. Like accepted connections, the
+// thread is only woken up, on_accept is called once it registers the connection.
+fn add_imported_connection(
+	config: &EventHandlerConfig,
+	debug_info: &DebugInfo,
+	ids: &ConnectionIds,
+	state: &mut Array<Box<dyn LockBox<EventHandlerState>>>,
+	wakeups: &mut Array<Wakeup>,
+	mut connection: Connection,
 ) -> Result<(), Error> {
-	let (tx, rx) = sync_channel(1);
-	connection.set_tx(tx);
+	ensure!(
+		connection.ctype == ConnectionType::Connection && connection.evh_instance_id == 0,
+		ErrKind::IllegalArgument,
+		"trying to add a connection that was not imported!"
+	);
+	connection.evh_instance_id = ids.instance_id;
 	connection.debug_info = debug_info.clone();
-	debug!("adding server handle = {}, tid = {}", handle, tid)?;
+	let handle = connection.handle();
+	if connection.loopback.is_none() {
+		apply_buffer_sizes(handle, config)?;
+	}
+	let tid: usize = try_into!(handle % config.threads as Handle)?;
+	connection.set_state(state[tid].clone())?;
+	connection.set_wakeup(wakeups[tid].clone())?;
+	debug!("adding imported handle = {}, tid = {}", handle, tid)?;
 
 	{
 		let mut state = state[tid].wlock()?;
 		let guard = state.guard()?;
-		let nv = if is_server {
-			ConnectionVariant::ServerConnection(connection)
-		} else {
-			ConnectionVariant::ClientConnection(connection)
-		};
-		(**guard).nconnections.push_back(nv);
+		let nv = ConnectionVariant::Connection(connection);
+		guard.nconnections.push_back(nv);
 	}
 
-	debug!("about to wakeup")?;
-
 	wakeups[tid].wakeup()?;
-	rx.recv()?;
-
 	Ok(())
 }
 
+impl KeyRegistry {
+	pub(crate) fn new() -> Self {
+		Self {
+			keys: HashMap::new(),
+			connections: HashMap::new(),
+		}
+	}
+
+	fn register(
+		&mut self,
+		config: &EventHandlerConfig,
+		connection_id: u128,
+		key: u64,
+	) -> Result<(), Error> {
+		ensure!(
+			self.connections.contains_key(&connection_id),
+			ErrKind::IllegalArgument,
+			"no open connection has the id {}",
+			connection_id
+		);
+		match self.keys.get(&key).copied() {
+			Some(id) if id == connection_id => return Ok(()),
+			Some(id) => {
+				ensure!(
+					!config.strict_keys,
+					ErrKind::IllegalState,
+					"key {} is already registered to connection {}",
+					key,
+					id
+				);
+				evh_log!(
+					config,
+					Warn,
+					"key {} moved from connection {} to connection {}",
+					key,
+					id,
+					connection_id
+				)?;
+				if let Some((_, keys)) = self.connections.get_mut(&id) {
+					keys.retain(|k| *k != key);
+				}
+			}
+			None => {}
+		}
+		self.keys.insert(key, connection_id);
+		if let Some((_, keys)) = self.connections.get_mut(&connection_id) {
+			keys.push(key);
+		}
+		Ok(())
+	}
+
+	fn unregister(&mut self, key: u64) {
+		if let Some(id) = self.keys.remove(&key) {
+			if let Some((_, keys)) = self.connections.get_mut(&id) {
+				keys.retain(|k| *k != key);
+			}
+		}
+	}
+
+	fn write_handle(&self, key: u64) -> Option<WriteHandle> {
+		let id = self.keys.get(&key)?;
+		self.connections.get(id).map(|(wh, _)| wh.clone())
+	}
+
+	// called when a connection is registered with a thread, before on_accept
+	fn track(&mut self, conn: &mut Connection) -> Result<(), Error> {
+		self.connections
+			.insert(conn.id(), (conn.write_handle()?, vec![]));
+		Ok(())
+	}
+
+	// called when a connection is closed or exported. Its keys are removed as well.
+	fn untrack(&mut self, id: u128) {
+		if let Some((_, keys)) = self.connections.remove(&id) {
+			for key in keys {
+				self.keys.remove(&key);
+			}
+		}
+	}
+}
+
 fn do_wakeup_read_impl(
 	handle: Handle,
 	buf: &mut [u8],
@@ -99,7 +1387,25 @@ fn do_read_impl(
 		let text = "simulated read error";
 		return Err(err!(ErrKind::Test, text));
 	}
-	read_impl(handle, buf, debug_info)
+	if debug_info.is_spurious_eagain() {
+		debug_info.fault(handle);
+		return Ok(None);
+	}
+	match debug_info.partial_read() {
+		Some(max) => {
+			let len = max.max(1).min(buf.len());
+			read_impl(handle, &mut buf[..len], debug_info)
+		}
+		None => read_impl(handle, buf, debug_info),
+	}
+}
+
+fn do_accept_impl(handle: Handle, debug_info: &DebugInfo) -> Result<Option<Handle>, Error> {
+	if debug_info.take_accept_eagain() {
+		debug_info.fault(handle);
+		return Ok(None);
+	}
+	accept_impl(handle, debug_info)
 }
 
 fn do_write_impl(handle: Handle, buf: &[u8], debug_info: &DebugInfo) -> Result<isize, Error> {
@@ -110,6 +1416,10 @@ fn do_write_impl(handle: Handle, buf: &[u8], debug_info: &DebugInfo) -> Result<i
 	if debug_info.is_write_err2() {
 		set_errno(Errno(1));
 		Ok(-1)
+	} else if debug_info.is_spurious_eagain() {
+		debug_info.fault(handle);
+		set_errno(Errno(EAGAIN));
+		Ok(-1)
 	} else {
 		write_impl(handle, buf)
 	}
@@ -141,6 +1451,15 @@ impl Default for DebugInfo {
 			internal_panic: lock_box!(false).unwrap(),
 			get_events_error: lock_box!(false).unwrap(),
 			os_error: lock_box!(false).unwrap(),
+			fd_limit: lock_box!(None).unwrap(),
+			partial_read: lock_box!(None).unwrap(),
+			eagain_probability: lock_box!(None).unwrap(),
+			accept_eagain_once: lock_box!(false).unwrap(),
+			hold_loop: lock_box!(false).unwrap(),
+			write_interest_registers: lock_box!(0).unwrap(),
+			write_interest_deregisters: lock_box!(0).unwrap(),
+			#[cfg(test)]
+			faults: lock_box!(HashSet::new()).unwrap(),
 		}
 	}
 }
@@ -246,6 +1565,16 @@ impl DebugInfo {
 			false
 		}
 	}
+	fn is_hold_loop(&self) -> bool {
+		#[cfg(test)]
+		{
+			**self.hold_loop.rlock().unwrap().guard().unwrap()
+		}
+		#[cfg(not(test))]
+		{
+			false
+		}
+	}
 	fn is_get_events_error(&self) -> bool {
 		#[cfg(test)]
 		{
@@ -266,6 +1595,95 @@ impl DebugInfo {
 			false
 		}
 	}
+	fn fd_limit_override(&self) -> Option<usize> {
+		#[cfg(test)]
+		{
+			**self.fd_limit.rlock().unwrap().guard().unwrap()
+		}
+		#[cfg(not(test))]
+		{
+			None
+		}
+	}
+	fn partial_read(&self) -> Option<usize> {
+		#[cfg(test)]
+		{
+			**self.partial_read.rlock().unwrap().guard().unwrap()
+		}
+		#[cfg(not(test))]
+		{
+			None
+		}
+	}
+	// draws from a splitmix64 sequence so that a given seed always produces the same faults
+	pub(crate) fn is_spurious_eagain(&self) -> bool {
+		#[cfg(test)]
+		{
+			let mut eagain_probability = self.eagain_probability.clone();
+			let mut eagain_probability = eagain_probability.wlock().unwrap();
+			let guard = eagain_probability.guard().unwrap();
+			match (**guard).as_mut() {
+				Some((probability, state)) => {
+					*state = state.wrapping_add(0x9e3779b97f4a7c15);
+					let mut z = *state;
+					z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+					z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+					z ^= z >> 31;
+					((z >> 11) as f64 / (1u64 << 53) as f64) < *probability
+				}
+				None => false,
+			}
+		}
+		#[cfg(not(test))]
+		{
+			false
+		}
+	}
+	pub(crate) fn take_accept_eagain(&self) -> bool {
+		#[cfg(test)]
+		{
+			let mut accept_eagain_once = self.accept_eagain_once.clone();
+			let mut accept_eagain_once = accept_eagain_once.wlock().unwrap();
+			let guard = accept_eagain_once.guard().unwrap();
+			std::mem::replace(&mut **guard, false)
+		}
+		#[cfg(not(test))]
+		{
+			false
+		}
+	}
+	fn fault(&self, _handle: Handle) {
+		#[cfg(test)]
+		{
+			let mut faults = self.faults.clone();
+			faults.wlock().unwrap().guard().unwrap().insert(_handle);
+		}
+	}
+	// count a write interest registration or deregistration
+	fn count_write_interest(&self, _interest: WriteInterest) {
+		#[cfg(test)]
+		{
+			let mut counter = match _interest {
+				WriteInterest::Registered => self.write_interest_registers.clone(),
+				WriteInterest::NotRegistered => self.write_interest_deregisters.clone(),
+			};
+			**counter.wlock().unwrap().guard().unwrap() += 1;
+		}
+	}
+	// whether an injected WouldBlock was returned for this handle since the last call
+	fn take_fault(&self, _handle: Handle) -> bool {
+		#[cfg(test)]
+		{
+			let mut faults = self.faults.clone();
+			let mut faults = faults.wlock().unwrap();
+			let guard = faults.guard().unwrap();
+			!guard.is_empty() && guard.remove(&_handle)
+		}
+		#[cfg(not(test))]
+		{
+			false
+		}
+	}
 	fn update(&mut self, debug_info: DebugInfo) -> Result<(), Error> {
 		wlock!(self.pending) = rlock!(debug_info.pending);
 		wlock!(self.write_err) = rlock!(debug_info.write_err);
@@ -279,56 +1697,97 @@ impl DebugInfo {
 		wlock!(self.internal_panic) = rlock!(debug_info.internal_panic);
 		wlock!(self.get_events_error) = rlock!(debug_info.get_events_error);
 		wlock!(self.os_error) = rlock!(debug_info.os_error);
+		wlock!(self.fd_limit) = rlock!(debug_info.fd_limit);
+		wlock!(self.partial_read) = rlock!(debug_info.partial_read);
+		wlock!(self.eagain_probability) = rlock!(debug_info.eagain_probability);
+		wlock!(self.accept_eagain_once) = rlock!(debug_info.accept_eagain_once);
+		wlock!(self.hold_loop) = rlock!(debug_info.hold_loop);
+		// the counters are shared rather than copied. The threads clone the DebugInfo when
+		// the EventHandler is started.
+		self.write_interest_registers = debug_info.write_interest_registers;
+		self.write_interest_deregisters = debug_info.write_interest_deregisters;
 		Ok(())
 	}
 }
 
+impl ConnectionIds {
+	pub(crate) fn new() -> Self {
+		// the set is only ever inserted into so it is usable even if poisoned
+		let mut instance_ids = INSTANCE_IDS.lock().unwrap_or_else(PoisonError::into_inner);
+		// zero is the instance id of connections that have not been added yet
+		let instance_id = loop {
+			let instance_id: u64 = random();
+			if instance_id != 0 && instance_ids.insert(instance_id) {
+				break instance_id;
+			}
+		};
+		Self {
+			instance_id,
+			counter: Arc::new(AtomicU64::new(0)),
+		}
+	}
+
+	pub(crate) fn next(&self) -> u128 {
+		let id = ConnectionId {
+			instance_id: self.instance_id,
+			counter: self.counter.fetch_add(1, Ordering::Relaxed),
+		};
+		id.into()
+	}
+}
+
+impl From<u128> for ConnectionId {
+	fn from(id: u128) -> Self {
+		Self {
+			instance_id: (id >> 64) as u64,
+			counter: id as u64,
+		}
+	}
+}
+
+impl From<ConnectionId> for u128 {
+	fn from(id: ConnectionId) -> Self {
+		((id.instance_id as u128) << 64) | id.counter as u128
+	}
+}
+
 impl Wakeup {
 	pub(crate) fn new() -> Result<Self, Error> {
 		set_errno(Errno(0));
 		let (reader, writer) = wakeup_impl()?;
-		let requested = lock_box!(false)?;
-		let needed = lock_box!(false)?;
 		let id = random();
 		Ok(Self {
 			id,
 			reader,
 			writer,
-			requested,
-			needed,
+			pending: Arc::new(AtomicBool::new(false)),
+			blocking: Arc::new(AtomicBool::new(false)),
+			writes: Arc::new(AtomicUsize::new(0)),
 		})
 	}
 
+	// only the transition from not pending to pending writes to the pipe and only if the thread
+	// is blocking. Otherwise it sees the pending flag in pre_block and does not block.
 	pub(crate) fn wakeup(&mut self) -> Result<(), Error> {
-		let mut requested = self.requested.wlock()?;
-		let needed = self.needed.rlock()?;
-		let need_wakeup = **needed.guard()? && !(**requested.guard()?);
-		**requested.guard()? = true;
-		if need_wakeup {
+		if !self.pending.swap(true, Ordering::SeqCst) && self.blocking.load(Ordering::SeqCst) {
 			debug!("wakeup writing to {}", self.writer)?;
 			let len = write_impl(self.writer, &[0u8; 1])?;
+			self.writes.fetch_add(1, Ordering::Relaxed);
 			debug!("len={},errno={}", len, errno())?;
 		}
 		Ok(())
 	}
 
-	pub(crate) fn pre_block(&mut self) -> Result<(bool, RwLockReadGuardWrapper<bool>), Error> {
-		let requested = self.requested.rlock()?;
-		{
-			let mut needed = self.needed.wlock()?;
-			**needed.guard()? = true;
-		}
-		let lock_guard = self.needed.rlock()?;
-		let is_requested = **requested.guard()?;
-		Ok((is_requested, lock_guard))
+	// returns true if a wakeup is already pending in which case the thread must not block
+	pub(crate) fn pre_block(&mut self) -> Result<bool, Error> {
+		self.blocking.store(true, Ordering::SeqCst);
+		Ok(self.pending.load(Ordering::SeqCst))
 	}
 
+	// called before the pending work is processed so that no notification is lost
 	pub(crate) fn post_block(&mut self) -> Result<(), Error> {
-		let mut requested = self.requested.wlock()?;
-		let mut needed = self.needed.wlock()?;
-
-		**requested.guard()? = false;
-		**needed.guard()? = false;
+		self.blocking.store(false, Ordering::SeqCst);
+		self.pending.store(false, Ordering::SeqCst);
 		Ok(())
 	}
 }
@@ -386,8 +1845,18 @@ impl UserContext for &mut UserContextImpl {
 			let len = slab.len();
 			let start = len.saturating_sub(4);
 			let next = u32::from_be_bytes(try_into!(&slab[start..start + 4])?) as usize;
+			let bytes = if cur == connection.get_last_slab() {
+				connection.get_slab_offset()
+			} else {
+				start
+			};
+			connection.unread_bytes = connection.unread_bytes.saturating_sub(bytes);
+			if connection.sensitive {
+				zeroize(self.read_slabs.get_mut(cur)?.get_mut());
+			}
 			debug!("free slab1 {}", cur)?;
 			self.read_slabs.free(cur)?;
+			connection.owned_slabs = connection.owned_slabs.saturating_sub(1);
 
 			connection.set_first_slab(next);
 			if connection.get_first_slab() >= u32::MAX as usize {
@@ -402,6 +1871,11 @@ impl UserContext for &mut UserContextImpl {
 			cur = next;
 		}
 
+		if connection.unread_paused {
+			connection.unread_paused = false;
+			self.resumed_reads.push(connection.id());
+		}
+
 		debug!(
 			"clear through complete first_slab={},last_slab={}",
 			connection.get_first_slab(),
@@ -417,6 +1891,101 @@ impl UserContext for &mut UserContextImpl {
 	fn set_user_data(&mut self, user_data: Box<dyn Any + Send + Sync>) {
 		self.user_data = Some(user_data);
 	}
+
+	fn is_synthetic(&self) -> bool {
+		self.synthetic
+	}
+
+	fn reassign_connection(
+		&mut self,
+		connection_id: u128,
+		thread_hint: usize,
+	) -> Result<(), Error> {
+		self.reassignments.push((connection_id, thread_hint));
+		Ok(())
+	}
+
+	#[cfg(test)]
+	fn raw_read_slab(&self, slab_id: usize) -> Result<Vec<u8>, Error> {
+		Ok(self.read_slabs.get(slab_id)?.get().to_vec())
+	}
+}
+
+impl MemoryPressure {
+	fn from_usize(level: usize) -> Self {
+		match level {
+			0 => MemoryPressure::None,
+			1 => MemoryPressure::PauseReads,
+			2 => MemoryPressure::RejectAccepts,
+			_ => MemoryPressure::CloseConnections,
+		}
+	}
+}
+
+impl MemoryBudget {
+	fn new(budget: usize) -> Self {
+		Self {
+			budget,
+			fixed: Arc::new(AtomicUsize::new(0)),
+			variable: Arc::new(AtomicUsize::new(0)),
+			capture: Arc::new(AtomicUsize::new(0)),
+			pressure: Arc::new(AtomicUsize::new(0)),
+			changed_at: Arc::new(AtomicUsize::new(0)),
+		}
+	}
+
+	// the memory in use
+	pub(crate) fn used(&self) -> usize {
+		self.fixed.load(Ordering::Relaxed)
+			+ self.variable.load(Ordering::Relaxed)
+			+ self.capture.load(Ordering::Relaxed)
+	}
+
+	pub(crate) fn pressure(&self) -> MemoryPressure {
+		MemoryPressure::from_usize(self.pressure.load(Ordering::SeqCst))
+	}
+
+	// change the bytes counted for a consumer from `old` to `new`
+	pub(crate) fn adjust(&self, old: usize, new: usize) {
+		if new > old {
+			self.variable.fetch_add(new - old, Ordering::Relaxed);
+		} else if old > new {
+			self.variable.fetch_sub(old - new, Ordering::Relaxed);
+		}
+	}
+
+	// called by each thread at each housekeeping interval. The pressure is raised by one step
+	// if the budget is exceeded and it was not changed within the last `interval` millis. It
+	// goes back to None as soon as the memory in use is within the budget. Returns the new
+	// pressure if this call changed it.
+	fn update(&self, now: usize, interval: usize) -> Option<MemoryPressure> {
+		let level = self.pressure.load(Ordering::SeqCst);
+		if self.used() <= self.budget {
+			let reset =
+				self.pressure
+					.compare_exchange(level, 0, Ordering::SeqCst, Ordering::SeqCst);
+			if level == 0 || reset.is_err() {
+				return None;
+			}
+			self.changed_at.store(now, Ordering::SeqCst);
+			return Some(MemoryPressure::None);
+		}
+		let changed_at = self.changed_at.load(Ordering::SeqCst);
+		if level >= MemoryPressure::CloseConnections as usize
+			|| (level > 0 && now.saturating_sub(changed_at) < interval)
+		{
+			return None;
+		}
+		// only one thread raises the pressure per interval
+		let cas =
+			self.changed_at
+				.compare_exchange(changed_at, now, Ordering::SeqCst, Ordering::SeqCst);
+		if cas.is_err() {
+			return None;
+		}
+		self.pressure.store(level + 1, Ordering::SeqCst);
+		Some(MemoryPressure::from_usize(level + 1))
+	}
 }
 
 impl WriteState {
@@ -424,6 +1993,171 @@ impl WriteState {
 		Self {
 			flags: 0,
 			write_buffer: vec![],
+			trigger_on_read_count: 0,
+			pending_files: VecDeque::new(),
+			deferred: vec![],
+			capture: None,
+			metrics: None,
+			write_delay_millis: 0,
+			rate_limit: 0,
+			hold_until: 0,
+			tokens: 0,
+			last_refill: 0,
+			next_write: None,
+			outbound_transform: None,
+			transform_error: None,
+			sensitive: false,
+			responses: HashMap::new(),
+			response_timers: vec![],
+			memory: None,
+			accounted: 0,
+			attachment_len: 0,
+			attachment_size_hint: None,
+			owner: None,
+		}
+	}
+
+	// the bytes that this connection counts against EvhMemoryBudgetBytes
+	pub(crate) fn memory_bytes(&self) -> usize {
+		let trailing: usize = self.pending_files.iter().map(|p| p.trailing.len()).sum();
+		let queued = self.write_buffer.len() + self.deferred.len() + trailing;
+		queued + self.attachment_size_hint.unwrap_or(self.attachment_len)
+	}
+
+	// update the memory budget after the queued data or the attachment changed
+	pub(crate) fn account(&mut self) {
+		if let Some(memory) = &self.memory {
+			let bytes = self.memory_bytes();
+			memory.adjust(self.accounted, bytes);
+			self.accounted = bytes;
+		}
+	}
+
+	// release the queued data of a connection that is closed
+	pub(crate) fn release(&mut self) {
+		self.write_buffer = vec![];
+		self.deferred = vec![];
+		self.pending_files.clear();
+		self.attachment_len = 0;
+		self.attachment_size_hint = None;
+		self.account();
+	}
+
+	// complete the requests of a connection that is closed that are still waiting for a
+	// response
+	pub(crate) fn fail_responses(&mut self) {
+		for (correlation_id, pending) in self.responses.drain() {
+			let text = format!(
+				"connection closed before response to request {}",
+				correlation_id
+			);
+			pending
+				.slot
+				.complete(Err(err!(ErrKind::ConnectionClosed, text)));
+		}
+		self.response_timers.clear();
+	}
+
+	// overwrite the queued data of a sensitive connection that is closed
+	pub(crate) fn zeroize_buffers(&mut self) {
+		if self.sensitive {
+			zeroize(&mut self.write_buffer);
+			zeroize(&mut self.deferred);
+			for pending in self.pending_files.iter_mut() {
+				zeroize(&mut pending.trailing);
+			}
+		}
+	}
+
+	// pass data through the outbound transform, if one is set, and queue the result. If the
+	// transform fails, the connection is marked for close with the transform's error.
+	pub(crate) fn queue_transformed(&mut self, data: &[u8]) -> Result<(), Error> {
+		let mut output = vec![];
+		let res = match self.outbound_transform.as_mut() {
+			Some(transform) => {
+				let transform = transform.get_mut().unwrap_or_else(PoisonError::into_inner);
+				transform(data, &mut output)
+			}
+			None => {
+				output.extend(data);
+				Ok(())
+			}
+		};
+		match res {
+			Ok(_) => {
+				if let Some(capture) = &self.capture {
+					capture.record(false, &output)?;
+				}
+				if !output.is_empty() {
+					self.queue(&output)?;
+				}
+				if self.sensitive {
+					zeroize(&mut output);
+				}
+				Ok(())
+			}
+			Err(e) => {
+				let text = format!("outbound transform failed: {}", e.kind());
+				self.set_flag(WRITE_STATE_FLAG_CLOSE);
+				self.transform_error = Some(e);
+				Err(err!(ErrKind::IO, text))
+			}
+		}
+	}
+
+	// queue data behind anything that has not been written yet
+	pub(crate) fn queue(&mut self, data: &[u8]) -> Result<(), Error> {
+		self.hold()?;
+		self.set_flag(WRITE_STATE_FLAG_PENDING);
+		// data must not overtake a queued file region
+		let buf = match self.pending_files.back_mut() {
+			Some(pending) => &mut pending.trailing,
+			None => &mut self.write_buffer,
+		};
+		if self.sensitive {
+			extend_zeroize(buf, data);
+		} else {
+			buf.extend(data);
+		}
+		self.account();
+		Ok(())
+	}
+
+	// if a write delay is set, hold a new flush until the delay has passed. Data queued while
+	// a flush is pending goes out with it.
+	pub(crate) fn hold(&mut self) -> Result<(), Error> {
+		if self.write_delay_millis > 0 && !self.is_set(WRITE_STATE_FLAG_PENDING) {
+			self.hold_until = now_millis()?.saturating_add(self.write_delay_millis);
+		}
+		Ok(())
+	}
+
+	// whether writes are delayed or rate limited so that they must go through write_loop
+	pub(crate) fn is_shaped(&self) -> bool {
+		self.write_delay_millis > 0 || self.rate_limit > 0
+	}
+
+	// the size of the token bucket for the configured rate limit
+	pub(crate) fn rate_limit_burst(&self) -> usize {
+		(self
+			.rate_limit
+			.saturating_mul(EVH_RATE_LIMIT_INTERVAL_MILLIS)
+			/ 1_000)
+			.max(1)
+	}
+
+	// add the tokens earned since the last refill
+	pub(crate) fn refill(&mut self, now: usize) {
+		let earned = self
+			.rate_limit
+			.saturating_mul(now.saturating_sub(self.last_refill))
+			/ 1_000;
+		if earned > 0 {
+			self.tokens = self
+				.tokens
+				.saturating_add(earned)
+				.min(self.rate_limit_burst());
+			self.last_refill = now;
 		}
 	}
 
@@ -440,6 +2174,14 @@ impl WriteState {
 	}
 }
 
+impl Drop for WriteState {
+	fn drop(&mut self) {
+		if let Some(memory) = &self.memory {
+			memory.adjust(self.accounted, 0);
+		}
+	}
+}
+
 impl WriteHandle {
 	/// Write data to the underlying connection for this [`crate::WriteHandle`].
 	/// # Input Parameters
@@ -448,27 +2190,158 @@ impl WriteHandle {
 	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
 	/// # Errors
 	/// [`bmw_err::ErrKind::IO`] - if an I/O error occurs while writing to the connection.
-	/// [`bmw_err::ErrKind::IO`] - if the connection is already closed.
+	/// [`bmw_err::ErrKind::ConnectionClosed`] - if the connection is already closed.
+	/// # Ordering
+	/// While a [`crate::CompressedStream`] is open for the connection, the data is held and
+	/// written after the compressed data when the stream is finished.
 	/// # See also
 	/// See the [`crate`] documentation as well for the background information and motivation
 	/// for this crate as well as examples.
 	pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+		if self.is_set(WRITE_STATE_FLAG_COMPRESSING)? {
+			let mut write_state = self.write_state.wlock()?;
+			let guard = write_state.guard()?;
+			// the stream may have been finished since the flag was checked
+			if guard.is_set(WRITE_STATE_FLAG_COMPRESSING) {
+				if guard.sensitive {
+					extend_zeroize(&mut guard.deferred, data);
+				} else {
+					guard.deferred.extend(data);
+				}
+				guard.account();
+				return Ok(());
+			}
+		}
+		self.write_direct(data)
+	}
+
+	/// Compress `data` and write it to the underlying connection for this
+	/// [`crate::WriteHandle`]. The data is compressed in blocks so that the compressed data is
+	/// not all held in memory at once. This is the same as calling
+	/// [`crate::WriteHandle::begin_compressed_stream`], writing the data to the stream and
+	/// finishing it.
+	/// # Input Parameters
+	/// data - the data to be compressed and written to the connection.
+	/// format - the [`crate::CompressionFormat`] to write.
+	/// level - the compression level from 0 (no compression) to 9 (best compression).
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] - if the level is greater than 9.
+	/// [`bmw_err::ErrKind::IllegalState`] - if a compressed stream is already open.
+	/// [`bmw_err::ErrKind::IO`] - if an I/O error occurs while writing to the connection.
+	/// [`bmw_err::ErrKind::ConnectionClosed`] - if the connection is already closed.
+	pub fn write_compressed(
+		&mut self,
+		data: &[u8],
+		format: CompressionFormat,
+		level: u32,
+	) -> Result<(), Error> {
+		let mut stream = self.begin_compressed_stream(format, level)?;
+		stream.write(data)?;
+		stream.finish()
+	}
+
+	/// Begin a compressed stream on the underlying connection for this [`crate::WriteHandle`].
+	/// Data written to the returned [`crate::CompressedStream`] is compressed incrementally and
+	/// the compressed data is written to the connection as it is produced.
+	/// # Input Parameters
+	/// format - the [`crate::CompressionFormat`] to write.
+	/// level - the compression level from 0 (no compression) to 9 (best compression).
+	/// # Returns
+	/// On success, the [`crate::CompressedStream`] is returned and on failure,
+	/// [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] - if the level is greater than 9.
+	/// [`bmw_err::ErrKind::IllegalState`] - if a compressed stream is already open.
+	/// [`bmw_err::ErrKind::ConnectionClosed`] - if the connection is already closed.
+	/// # Ordering
+	/// The compressed stream is atomic. Data passed to [`crate::WriteHandle::write`] (on this
+	/// or any other clone of the [`crate::WriteHandle`]) while the stream is open is written
+	/// after all of the compressed data once [`crate::CompressedStream::finish`] is called. Only
+	/// one compressed stream may be open for a connection at a time.
+	pub fn begin_compressed_stream(
+		&mut self,
+		format: CompressionFormat,
+		level: u32,
+	) -> Result<CompressedStream, Error> {
+		ensure!(
+			level <= 9,
+			ErrKind::IllegalArgument,
+			"compression level must be between 0 and 9. Found: {}",
+			level
+		);
+		{
+			let mut write_state = self.write_state.wlock()?;
+			let guard = write_state.guard()?;
+			ensure!(
+				!guard.is_set(WRITE_STATE_FLAG_CLOSE),
+				ErrKind::ConnectionClosed,
+				"begin_compressed_stream on a closed handle: {}",
+				self.handle
+			);
+			ensure!(
+				!guard.is_set(WRITE_STATE_FLAG_COMPRESSING),
+				ErrKind::IllegalState,
+				"a compressed stream is already open on handle: {}",
+				self.handle
+			);
+			guard.set_flag(WRITE_STATE_FLAG_COMPRESSING);
+		}
+
+		let level = Compression::new(level);
+		let encoder = match format {
+			CompressionFormat::Gzip => Encoder::Gzip(GzEncoder::new(vec![], level)),
+			CompressionFormat::Deflate => Encoder::Deflate(ZlibEncoder::new(vec![], level)),
+		};
+		Ok(CompressedStream {
+			wh: self.clone(),
+			encoder: Some(encoder),
+		})
+	}
+
+	fn write_direct(&mut self, data: &[u8]) -> Result<(), Error> {
 		let data_len = data.len();
 		let wlen = {
 			let write_state = self.write_state.rlock()?;
 			let guard = write_state.guard()?;
 
+			// the lock is held until the data is written so that the event loop cannot close
+			// the handle (and the os reuse it for a new connection) while it is being written
 			if (**guard).is_set(WRITE_STATE_FLAG_CLOSE) {
 				let text = format!("write on a closed handle: {}", self.handle);
-				return Err(err!(ErrKind::IO, text));
-			} else if (**guard).is_set(WRITE_STATE_FLAG_PENDING)
+				return Err(err!(ErrKind::ConnectionClosed, text));
+			}
+			if guard.outbound_transform.is_some() {
+				drop(write_state);
+				return self.write_transformed(data);
+			}
+			if let Some(capture) = &guard.capture {
+				capture.record(false, data)?;
+			}
+
+			if (**guard).is_set(WRITE_STATE_FLAG_PENDING)
+				|| guard.is_shaped()
 				|| self.debug_info.is_pending()
 				|| self.debug_info.is_write_handle_err()
+				|| self.debug_info.is_spurious_eagain()
 			{
 				0
+			} else if let Some(loopback) = &self.loopback {
+				// what does not fit in the peer's buffer is queued like a partial write
+				let len = loopback.write(data)?;
+				if let Some(metrics) = &guard.metrics {
+					metrics.incr(EvhMetric::BytesWritten, try_into!(len)?);
+				}
+				len
 			} else {
 				match write_impl(self.handle, data) {
-					Ok(len) => len,
+					Ok(len) => {
+						if let Some(metrics) = &guard.metrics {
+							metrics.incr(EvhMetric::BytesWritten, try_into!(len)?);
+						}
+						len
+					}
 					Err(e) => {
 						let err = errno().0;
 						if err == EAGAIN || err == ETEMPUNAVAILABLE || err == WINNONBLOCKING {
@@ -508,45 +2381,177 @@ impl WriteHandle {
 		}
 		Ok(())
 	}
-	/// Close the underlying connection for this [`crate::WriteHandle`].
+	/// Send a region of a file to the underlying connection for this [`crate::WriteHandle`].
+	/// The data is streamed by the kernel using sendfile on Linux and macOS. On Windows, it is
+	/// sent in chunks with non-blocking writes. The region is queued behind any data that has
+	/// not been written yet and data written after this call is queued behind the region, so
+	/// ordering is preserved. The file is streamed by the event handler thread as the
+	/// connection becomes writable, so this function does not block.
+	/// # Input Parameters
+	/// path - the path of the file to send.
+	/// offset - the offset, in bytes, of the first byte to send.
+	/// len - the number of bytes to send.
 	/// # Returns
 	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
 	/// # Errors
-	/// [`bmw_err::ErrKind::IO`] - if an I/O error occurs while closing the connection.
-	/// [`bmw_err::ErrKind::IO`] - if the connection is already closed.
+	/// [`bmw_err::ErrKind::IO`] - if the file cannot be opened.
+	/// [`bmw_err::ErrKind::ConnectionClosed`] - if the connection is already closed.
+	/// [`bmw_err::ErrKind::IllegalArgument`] - if the region extends past the end of the file.
 	/// # See also
 	/// See the [`crate`] documentation as well for the background information and motivation
 	/// for this crate as well as examples.
-	pub fn close(&mut self) -> Result<(), Error> {
+	pub fn send_file(&mut self, path: &str, offset: u64, len: u64) -> Result<(), Error> {
+		let text = format!("could not open file '{}'", path);
+		let file = map_err!(File::open(path), ErrKind::IO, text)?;
+		let size = file.metadata()?.len();
+		ensure!(
+			offset.checked_add(len).is_some_and(|end| end <= size),
+			ErrKind::IllegalArgument,
+			"region {}+{} is past the end of file '{}' ({} bytes)",
+			offset,
+			len,
+			path,
+			size
+		);
+
 		{
 			let mut write_state = self.write_state.wlock()?;
 			let guard = write_state.guard()?;
-			if (**guard).is_set(WRITE_STATE_FLAG_CLOSE) {
-				let text = format!(
-					"try to close a handle that is already closed: {}",
-					self.handle
-				);
-				return Err(err!(ErrKind::IO, text));
+			ensure!(
+				!guard.is_set(WRITE_STATE_FLAG_CLOSE),
+				ErrKind::ConnectionClosed,
+				"send_file on a closed handle: {}",
+				self.handle
+			);
+			if len == 0 {
+				return Ok(());
 			}
+			guard.hold()?;
+			guard.set_flag(WRITE_STATE_FLAG_PENDING);
+			guard.pending_files.push_back(PendingFile {
+				file,
+				offset,
+				remaining: len,
+				trailing: vec![],
+			});
+		}
 
-			(**guard).set_flag(WRITE_STATE_FLAG_CLOSE);
+		self.notify()?;
+		Ok(())
+	}
+
+	/// Write a request to the underlying connection for this [`crate::WriteHandle`] and
+	/// return a [`crate::ResponseFuture`] for its response. The connection must have a
+	/// [`crate::ResponseExtractor`] set with [`crate::Connection::set_response_extractor`]
+	/// which returns the response for the correlation_id. Many requests may be outstanding on
+	/// the same connection at once and the responses may arrive in any order.
+	/// # Input Parameters
+	/// data - the request to be written to the connection. It must contain the correlation_id
+	/// in whatever form the protocol uses. The data is written as is.
+	/// correlation_id - the id that the [`crate::ResponseExtractor`] returns with the response
+	/// to this request.
+	/// timeout_millis - the number of milliseconds to wait for the response. If no response
+	/// is read by then, the [`crate::ResponseFuture`] returns a
+	/// [`bmw_err::ErrKind::Timeout`] error.
+	/// # Returns
+	/// On success, the [`crate::ResponseFuture`] is returned and on failure,
+	/// [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::AlreadyRegistered`] - if a request with the same correlation_id is
+	/// already waiting for a response on this connection.
+	/// [`bmw_err::ErrKind::IO`] - if an I/O error occurs while writing to the connection.
+	/// [`bmw_err::ErrKind::ConnectionClosed`] - if the connection is already closed.
+	pub fn request(
+		&mut self,
+		data: &[u8],
+		correlation_id: u64,
+		timeout_millis: usize,
+	) -> Result<ResponseFuture, Error> {
+		let slot = Arc::new(ResponseSlot::new());
+		{
+			let mut write_state = self.write_state.wlock()?;
+			let guard = write_state.guard()?;
+			ensure!(
+				!guard.is_set(WRITE_STATE_FLAG_CLOSE),
+				ErrKind::ConnectionClosed,
+				"request on a closed handle: {}",
+				self.handle
+			);
+			ensure!(
+				!guard.responses.contains_key(&correlation_id),
+				ErrKind::AlreadyRegistered,
+				"a request with correlation_id {} is already pending",
+				correlation_id
+			);
+			let deadline = now_millis()?.saturating_add(timeout_millis);
+			let pending = PendingResponse {
+				deadline,
+				slot: slot.clone(),
+			};
+			guard.responses.insert(correlation_id, pending);
+			guard.response_timers.push((deadline, correlation_id));
+		}
+
+		// the request is registered first so that a fast response is not unmatched
+		if let Err(e) = self.write(data) {
+			wlock!(self.write_state).responses.remove(&correlation_id);
+			return Err(e);
 		}
 
+		// wake the thread so that it picks up the timeout
+		self.notify()?;
+
+		Ok(ResponseFuture {
+			correlation_id,
+			slot,
+		})
+	}
+
+	/// Close the underlying connection for this [`crate::WriteHandle`].
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IO`] - if an I/O error occurs while closing the connection.
+	/// [`bmw_err::ErrKind::ConnectionClosed`] - if the connection is already closed.
+	/// # See also
+	/// See the [`crate`] documentation as well for the background information and motivation
+	/// for this crate as well as examples.
+	pub fn close(&mut self) -> Result<(), Error> {
 		{
-			wlock!(self.state).write_queue.push_back(self.id);
+			let mut write_state = self.write_state.wlock()?;
+			let guard = write_state.guard()?;
+			ensure!(
+				!(**guard).is_set(WRITE_STATE_FLAG_CLOSE),
+				ErrKind::ConnectionClosed,
+				"try to close a handle that is already closed: {}",
+				self.handle
+			);
+
+			(**guard).set_flag(WRITE_STATE_FLAG_CLOSE);
 		}
 
-		self.wakeup.wakeup()?;
+		self.notify()?;
 		Ok(())
 	}
 
 	/// Trigger a callback of the handler specified by [`crate::EventHandler::set_on_read`].
 	/// This is useful in applications like pipelines where data is held up for later
 	/// processing so that asynchronous threads can be executed.
+	/// # Ordering
+	/// Each call enqueues one synthetic invocation of the on_read handler for this connection.
+	/// The synthetic invocation is processed on the connection's thread in the same event path
+	/// as data read from the connection. Before it is executed, the connection is read until no
+	/// more data is available, so all data received before this function was called is
+	/// delivered to on_read before the synthetic invocation. Data that arrives after this
+	/// function was called but before the synthetic invocation is processed is read by the
+	/// same drain and is therefore also delivered before it. Only data that arrives once the
+	/// synthetic invocation has started is guaranteed to be delivered after it. Within the
+	/// callback,
+	/// [`crate::UserContext::is_synthetic`] returns true for the synthetic invocation.
 	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
 	/// # Errors
 	/// [`bmw_err::ErrKind::IO`] - if an I/O error occurs.
-	/// [`bmw_err::ErrKind::IO`] - if the connection is already closed.
+	/// [`bmw_err::ErrKind::ConnectionClosed`] - if the connection is already closed.
 	/// # See also
 	/// See the [`crate`] documentation as well for the background information and motivation
 	/// for this crate as well as examples.
@@ -555,17 +2560,16 @@ impl WriteHandle {
 		{
 			let mut write_state = self.write_state.wlock()?;
 			let guard = write_state.guard()?;
-			if (**guard).is_set(WRITE_STATE_FLAG_CLOSE) {
-				let text = format!("trigger_on_read on a closed handle: {}", self.handle);
-				return Err(err!(ErrKind::IO, text));
-			}
+			ensure!(
+				!(**guard).is_set(WRITE_STATE_FLAG_CLOSE),
+				ErrKind::ConnectionClosed,
+				"trigger_on_read on a closed handle: {}",
+				self.handle
+			);
 
-			(**guard).set_flag(WRITE_STATE_FLAG_TRIGGER_ON_READ);
-		}
-		{
-			wlock!(self.state).write_queue.push_back(self.id);
+			guard.trigger_on_read_count += 1;
 		}
-		self.wakeup.wakeup()?;
+		self.notify()?;
 
 		Ok(())
 	}
@@ -577,15 +2581,10 @@ impl WriteHandle {
 			let mut write_state = self.write_state.wlock()?;
 			let guard = write_state.guard()?;
 			(**guard).set_flag(WRITE_STATE_FLAG_CLOSE);
-			(**guard).set_flag(WRITE_STATE_FLAG_PENDING);
-			(**guard).write_buffer.extend(data);
+			(**guard).queue(data)?;
 		}
 
-		{
-			wlock!(self.state).write_queue.push_back(self.id);
-		}
-
-		self.wakeup.wakeup()?;
+		self.notify()?;
 		Ok(())
 	}
 
@@ -600,84 +2599,727 @@ impl WriteHandle {
 		Ok((**guard).is_set(flag))
 	}
 
-	fn unset_flag(&mut self, flag: u8) -> Result<(), Error> {
-		let mut write_state = self.write_state.wlock()?;
-		let guard = write_state.guard()?;
-		(**guard).unset_flag(flag);
+	fn take_next_write(&mut self) -> Result<Option<usize>, Error> {
+		Ok(wlock!(self.write_state).next_write.take())
+	}
+
+	fn take_trigger_on_read_count(&mut self) -> Result<usize, Error> {
+		let mut write_state = self.write_state.wlock()?;
+		let guard = write_state.guard()?;
+		let count = guard.trigger_on_read_count;
+		guard.trigger_on_read_count = 0;
+		Ok(count)
+	}
+
+	fn take_transform_error(&mut self) -> Result<Option<Error>, Error> {
+		let mut write_state = self.write_state.wlock()?;
+		let guard = write_state.guard()?;
+		Ok(guard.transform_error.take())
+	}
+
+	fn write_state(&mut self) -> Result<&mut Box<dyn LockBox<WriteState>>, Error> {
+		Ok(&mut self.write_state)
+	}
+	// queue this connection for its thread and wake the thread. The thread is the one that the
+	// handle was created on unless the connection was moved by UserContext::reassign_connection.
+	pub(crate) fn notify(&mut self) -> Result<(), Error> {
+		let owner = rlock!(self.write_state).owner.clone();
+		let (mut wakeup, mut state) = match owner {
+			Some(owner) => owner,
+			None => (self.wakeup.clone(), self.state.clone()),
+		};
+		wlock!(state).write_queue.push_back(self.id);
+		wakeup.wakeup()
+	}
+
+	pub(crate) fn new(connection_impl: &Connection, debug_info: DebugInfo) -> Result<Self, Error> {
+		let wakeup = &connection_impl.wakeup;
+		let wakeup = try_opt!(wakeup, ErrKind::IllegalState, "connection has no Wakeup").clone();
+		let state = &connection_impl.state;
+		let state = try_opt!(state, ErrKind::IllegalState, "connection has no WriteState").clone();
+
+		Ok(Self {
+			handle: connection_impl.handle,
+			id: connection_impl.id,
+			write_state: connection_impl.write_state.clone(),
+			wakeup,
+			state,
+			debug_info,
+			loopback: connection_impl.loopback.clone(),
+		})
+	}
+	// transformed data is queued and written by the event handler thread. The transform is
+	// called with the write state locked so that the output is queued in the order that it
+	// was produced.
+	fn write_transformed(&mut self, data: &[u8]) -> Result<(), Error> {
+		let res = {
+			let mut write_state = self.write_state.wlock()?;
+			let guard = write_state.guard()?;
+			ensure!(
+				!guard.is_set(WRITE_STATE_FLAG_CLOSE),
+				ErrKind::ConnectionClosed,
+				"write on a closed handle: {}",
+				self.handle
+			);
+			guard.queue_transformed(data)
+		};
+
+		self.notify()?;
+		res
+	}
+
+	fn queue_data(&mut self, data: &[u8]) -> Result<(), Error> {
+		{
+			let mut write_state = self.write_state.wlock()?;
+			let guard = write_state.guard()?;
+			guard.queue(data)?;
+		}
+
+		self.notify()?;
+		Ok(())
+	}
+}
+
+impl CompressedStream {
+	/// Compress `data` and write any compressed data that is ready to the connection.
+	/// # Input Parameters
+	/// data - the data to be compressed.
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IO`] - if an I/O error occurs while writing to the connection.
+	/// [`bmw_err::ErrKind::ConnectionClosed`] - if the connection is already closed.
+	pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+		for block in data.chunks(EVH_COMPRESSION_BLOCK_SIZE) {
+			let output = match self.encoder.as_mut() {
+				Some(Encoder::Gzip(encoder)) => {
+					encoder.write_all(block)?;
+					encoder.get_mut()
+				}
+				Some(Encoder::Deflate(encoder)) => {
+					encoder.write_all(block)?;
+					encoder.get_mut()
+				}
+				None => return Err(err!(ErrKind::IllegalState, "stream already finished")),
+			};
+			if output.len() >= EVH_COMPRESSION_BLOCK_SIZE {
+				let output = std::mem::take(output);
+				self.wh.write_direct(&output)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Write the rest of the compressed data to the connection followed by any data that was
+	/// passed to [`crate::WriteHandle::write`] while the stream was open.
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IO`] - if an I/O error occurs while writing to the connection.
+	/// [`bmw_err::ErrKind::ConnectionClosed`] - if the connection is already closed.
+	pub fn finish(mut self) -> Result<(), Error> {
+		self.finish_impl()
+	}
+
+	fn finish_impl(&mut self) -> Result<(), Error> {
+		let res = match self.encoder.take() {
+			Some(Encoder::Gzip(encoder)) => encoder.finish(),
+			Some(Encoder::Deflate(encoder)) => encoder.finish(),
+			None => return Ok(()),
+		};
+		// the stream is closed even if the write fails so that the deferred data is not lost
+		let res = match res {
+			Ok(output) => self.wh.write_direct(&output),
+			Err(e) => Err(e.into()),
+		};
+
+		let queued = {
+			let mut write_state = self.wh.write_state.wlock()?;
+			let guard = write_state.guard()?;
+			guard.unset_flag(WRITE_STATE_FLAG_COMPRESSING);
+			let mut deferred = std::mem::take(&mut guard.deferred);
+			// queued so that writes made after this point can't overtake it
+			let queued = if !deferred.is_empty() && !guard.is_set(WRITE_STATE_FLAG_CLOSE) {
+				Some(guard.queue_transformed(&deferred))
+			} else {
+				None
+			};
+			if guard.sensitive {
+				zeroize(&mut deferred);
+			}
+			guard.account();
+			queued
+		};
+		match queued {
+			Some(queue_res) => {
+				self.wh.notify()?;
+				res.and(queue_res)
+			}
+			None => res,
+		}
+	}
+}
+
+impl Drop for CompressedStream {
+	fn drop(&mut self) {
+		let _ = self.finish_impl();
+	}
+}
+
+impl ResponseFuture {
+	/// Returns the correlation_id of the request that this [`crate::ResponseFuture`] is for.
+	pub fn correlation_id(&self) -> u64 {
+		self.correlation_id
+	}
+
+	/// Block until the response to the request is read, the request times out or the
+	/// connection is closed.
+	/// # Returns
+	/// On success, the response is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::Timeout`] - if no response was read before the request's timeout.
+	/// [`bmw_err::ErrKind::ConnectionClosed`] - if the connection was closed first.
+	pub fn wait(self) -> Result<Vec<u8>, Error> {
+		let mut state = self.slot.lock();
+		loop {
+			if let Some(result) = state.result.take() {
+				return result;
+			}
+			state = self
+				.slot
+				.cond
+				.wait(state)
+				.unwrap_or_else(PoisonError::into_inner);
+		}
+	}
+}
+
+impl Future for ResponseFuture {
+	type Output = Result<Vec<u8>, Error>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let mut state = self.slot.lock();
+		match state.result.take() {
+			Some(result) => Poll::Ready(result),
+			None => {
+				state.waker = Some(cx.waker().clone());
+				Poll::Pending
+			}
+		}
+	}
+}
+
+impl ResponseSlot {
+	pub(crate) fn new() -> Self {
+		Self {
+			state: Mutex::new(ResponseSlotState {
+				result: None,
+				waker: None,
+			}),
+			cond: Condvar::new(),
+		}
+	}
+
+	fn lock(&self) -> MutexGuard<'_, ResponseSlotState> {
+		self.state.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	// store the result and wake whoever is waiting for it
+	pub(crate) fn complete(&self, result: Result<Vec<u8>, Error>) {
+		let waker = {
+			let mut state = self.lock();
+			state.result = Some(result);
+			state.waker.take()
+		};
+		self.cond.notify_all();
+		if let Some(waker) = waker {
+			waker.wake();
+		}
+	}
+}
+
+impl ExportedHandle {
+	/// Rebuild an [`crate::ExportedHandle`] from a handle received from another process and
+	/// the bytes returned by [`crate::ExportedHandle::state`] in that process. The state is
+	/// validated by [`crate::EvhBuilder::import_connection`].
+	pub fn new(handle: Handle, state: Vec<u8>) -> Self {
+		Self { handle, state }
+	}
+
+	/// Returns the underlying handle of the exported connection.
+	pub fn handle(&self) -> Handle {
+		self.handle
+	}
+
+	/// Returns the serialized state of the exported connection.
+	pub fn state(&self) -> &[u8] {
+		&self.state
+	}
+}
+
+impl HousekeeperContext<'_> {
+	/// Returns an iterator over the ids of the connections of the thread that is executing the
+	/// OnHousekeeper handler. Both accepted connections and client connections are included.
+	pub fn connection_ids(&self) -> impl Iterator<Item = u128> + '_ {
+		self.id_hash
+			.iter()
+			.filter(|(_, conn)| Self::as_connection(conn).is_some())
+			.map(|(id, _)| *id)
+	}
+
+	/// Returns a [`crate::WriteHandle`] for the connection with the specified `id`. If the
+	/// connection does not belong to this thread, [`std::option::Option::None`] is returned.
+	pub fn write_handle(&self, id: u128) -> Result<Option<WriteHandle>, Error> {
+		match self.connection(id) {
+			Some(conn) => Ok(Some(conn.write_handle()?)),
+			None => Ok(None),
+		}
+	}
+
+	/// Returns the number of milliseconds since data was last read from the connection with
+	/// the specified `id`. If no data has been read yet, the time since the connection was
+	/// created is returned. Data written to the connection does not reset this value. If the
+	/// connection does not belong to this thread, [`std::option::Option::None`] is returned.
+	pub fn idle_millis(&self, id: u128) -> Option<usize> {
+		self.connection(id)
+			.map(|conn| self.now.saturating_sub(conn.last_read_millis))
+	}
+
+	/// Requests that the connection with the specified `id` be closed with the specified
+	/// [`crate::CloseReason`]. The connection is closed, and the OnClose handler is executed,
+	/// once the OnHousekeeper handler returns. Returns false if the connection does not belong
+	/// to this thread.
+	pub fn request_close(&mut self, id: u128, reason: CloseReason) -> bool {
+		if self.connection(id).is_none() {
+			return false;
+		}
+		self.close_requests.push((id, reason));
+		true
+	}
+
+	fn connection(&self, id: u128) -> Option<&Connection> {
+		self.id_hash.get(&id).and_then(Self::as_connection)
+	}
+
+	fn as_connection(conn: &ConnectionVariant) -> Option<&Connection> {
+		match conn {
+			ConnectionVariant::Connection(c) | ConnectionVariant::ClientConnection(c)
+				if c.accepted =>
+			{
+				Some(c)
+			}
+			_ => None,
+		}
+	}
+}
+
+impl Connection {
+	/// Retrieves the `id` for this Connection. The id is a u128 value that is never reused
+	/// within the process. See [`crate::ConnectionId`] for the format.
+	pub fn id(&self) -> u128 {
+		self.id
+	}
+
+	/// Returns the instance id of the [`crate::EventHandler`] that this connection was accepted
+	/// by or added to or 0 if it has not been added to one yet. See
+	/// [`crate::EventHandler::instance_id`].
+	pub fn evh_instance_id(&self) -> u64 {
+		self.evh_instance_id
+	}
+
+	/// Returns a [`crate::WriteHandle`] which can be used to write data, close the
+	/// connection, or trigger and on_read event for the underlying connection. See
+	/// [`crate::WriteHandle`].
+	pub fn write_handle(&self) -> Result<WriteHandle, Error> {
+		let wh = WriteHandle::new(self, self.debug_info.clone())?;
+		Ok(wh)
+	}
+
+	/// Returns the `origin_id` for a [`crate::Connection`]. The origin_id is the id of the
+	/// connection if it is a client or server connection that was added to this
+	/// [`crate::EventHandler`]. If it is an accepted connection, it is the id of the
+	/// [`crate::Connection`] that it was accepted on.
+	pub fn origin_id(&self) -> u128 {
+		self.origin_id
+	}
+
+	/// Disable the message that is sent by configuring EvhOutOfSlabsMessage for the
+	/// [`crate::EventHandler`] that this connection is associated with.
+	pub fn disable_write_final(&mut self) {
+		self.disable_write_final = true;
+	}
+
+	/// Returns true if the peer has shut down its write side. This is only set if the
+	/// OnReadEof handler was set with [`crate::EventHandler::set_on_read_eof`]. Otherwise the
+	/// connection is closed when end of file is read.
+	pub fn is_read_eof(&self) -> bool {
+		self.read_eof
+	}
+
+	/// Delay the data written to this [`crate::Connection`] by `millis` milliseconds. This is
+	/// intended for testing clients against a slow peer. Each flush (the data written while
+	/// nothing is pending) is held for the delay before it is sent and data written while a
+	/// flush is held goes out with it, so ordering is preserved. A close requested with
+	/// [`crate::WriteHandle::close`] takes effect once the delayed data is sent. A value of 0
+	/// (the default) turns the delay off. Only outbound data is affected.
+	pub fn set_write_delay_millis(&mut self, millis: usize) -> Result<(), Error> {
+		wlock!(self.write_state).write_delay_millis = millis;
+		Ok(())
+	}
+
+	/// Limit the rate at which data is written to this [`crate::Connection`] to
+	/// `bytes_per_sec` bytes per second. This is intended for testing clients against a slow
+	/// peer. Data beyond the limit is queued and sent as the limit allows. A close requested
+	/// with [`crate::WriteHandle::close`] takes effect once all of the queued data is sent. A
+	/// value of 0 (the default) turns the limit off. Only outbound data is affected.
+	pub fn set_write_rate_limit_bytes_per_sec(
+		&mut self,
+		bytes_per_sec: usize,
+	) -> Result<(), Error> {
+		let now = now_millis()?;
+		let mut write_state = self.write_state.wlock()?;
+		let guard = write_state.guard()?;
+		guard.rate_limit = bytes_per_sec;
+		guard.tokens = guard.rate_limit_burst();
+		guard.last_refill = now;
+		Ok(())
+	}
+
+	/// Stop reading from this [`crate::Connection`] once `bytes` bytes that were read have not
+	/// been cleared with [`crate::UserContext::clear_through`] or
+	/// [`crate::UserContext::clear_all`]. The rest of the data is left in the socket and
+	/// reading resumes automatically once the handler clears data. This bounds the memory used
+	/// by the connection and is intended for request/response protocols, where reading ahead
+	/// past the current request only has to be buffered by the handler. The limit applies to
+	/// the raw bytes read, so a request larger than the limit is never completed. A synthetic
+	/// on_read requested with [`crate::WriteHandle::trigger_on_read`] is still executed while
+	/// reading is stopped. This is normally called from on_accept. A value of 0 (the default)
+	/// turns the limit off.
+	pub fn set_max_unread_bytes(&mut self, bytes: usize) -> Result<(), Error> {
+		self.max_unread_bytes = bytes;
+		Ok(())
+	}
+
+	/// Returns the number of bytes that were read from this [`crate::Connection`] and are held
+	/// in its read slabs because they have not been cleared yet.
+	pub fn unread_bytes(&self) -> usize {
+		self.unread_bytes
+	}
+
+	/// Transform the data read from this [`crate::Connection`] before it is seen by the OnRead
+	/// handler. This can be used to decrypt or otherwise decode a stream without the handler
+	/// knowing about it. The transform is called once for each read with the data that was
+	/// read and appends its output to the [`std::vec::Vec`]. The output may be shorter or
+	/// longer than the input. If it is empty, the OnRead handler is not called for that read.
+	/// If the transform returns an error, the connection is closed with
+	/// [`crate::CloseReason::TransformError`]. Setting a new transform replaces the previous
+	/// one. The transform is not carried along by [`crate::Connection::export_handle`].
+	pub fn set_inbound_transform(&mut self, transform: Transform) -> Result<(), Error> {
+		self.inbound_transform = Some(Arc::new(Mutex::new(transform)));
+		Ok(())
+	}
+
+	/// Extract the responses to requests written with [`crate::WriteHandle::request`] from the
+	/// data read from this [`crate::Connection`]. The extractor is called once for each read
+	/// with the data that was read (after the inbound transform if one is set) and returns
+	/// the (correlation_id, response) pairs of the responses that it found. It must buffer
+	/// partial responses itself. Each response completes the [`crate::ResponseFuture`] of the
+	/// pending request with the same correlation_id. Responses that do not match a pending
+	/// request are passed to the handler set with
+	/// [`crate::Connection::set_unmatched_response_handler`] or dropped if none is set. Data
+	/// that the extractor appends to the [`std::vec::Vec`] is passed on to the OnRead handler
+	/// so that unsolicited messages may still be handled there. If it is empty, the OnRead
+	/// handler is not called for that read. If the extractor returns an error, the connection
+	/// is closed with [`crate::CloseReason::TransformError`]. Setting a new extractor replaces
+	/// the previous one. The extractor is not carried along by
+	/// [`crate::Connection::export_handle`].
+	pub fn set_response_extractor(&mut self, extractor: ResponseExtractor) -> Result<(), Error> {
+		self.response_extractor = Some(Arc::new(Mutex::new(extractor)));
+		Ok(())
+	}
+
+	/// Set the handler that is called with the responses returned by the
+	/// [`crate::ResponseExtractor`] of this [`crate::Connection`] that do not match a pending
+	/// request, i.e. the request already timed out or was never sent. The handler is called
+	/// on the [`crate::EventHandler`] thread. If it returns an error, the error is logged and
+	/// the connection stays open. See [`crate::Connection::set_response_extractor`].
+	pub fn set_unmatched_response_handler(
+		&mut self,
+		handler: UnmatchedResponseHandler,
+	) -> Result<(), Error> {
+		self.unmatched_response_handler = Some(Arc::new(Mutex::new(handler)));
+		Ok(())
+	}
+
+	/// Transform the data written to this [`crate::Connection`] before it is sent. This can be
+	/// used to encrypt or otherwise encode a stream without the writer knowing about it. The
+	/// transform is called once for each write with the data that was written and appends its
+	/// output to the [`std::vec::Vec`]. Each byte is transformed exactly once, even if it has
+	/// to be queued and retried. While a transform is set, written data is queued and sent by
+	/// the [`crate::EventHandler`] thread. File regions sent with
+	/// [`crate::WriteHandle::send_file`] are not transformed. If the transform returns an
+	/// error, the write returns an error and the connection is closed with
+	/// [`crate::CloseReason::TransformError`] once the data queued before it is sent. Setting a
+	/// new transform replaces the previous one. The transform is not carried along by
+	/// [`crate::Connection::export_handle`].
+	pub fn set_outbound_transform(&mut self, transform: Transform) -> Result<(), Error> {
+		wlock!(self.write_state).outbound_transform = Some(Mutex::new(transform));
 		Ok(())
 	}
 
-	fn write_state(&mut self) -> Result<&mut Box<dyn LockBox<WriteState>>, Error> {
-		Ok(&mut self.write_state)
-	}
-	pub(crate) fn new(connection_impl: &Connection, debug_info: DebugInfo) -> Result<Self, Error> {
-		let wakeup = match &connection_impl.wakeup {
-			Some(wakeup) => wakeup.clone(),
+	/// Record the raw traffic of this [`crate::Connection`]. Each chunk of data read from the
+	/// connection is appended to `<dir>/<id>.in` and each buffer written through a
+	/// [`crate::WriteHandle`] is appended to `<dir>/<id>.out` where id is
+	/// [`crate::Connection::id`]. Every record is the time it was captured in milliseconds since
+	/// the epoch (8 bytes, big endian) followed by the data as a [`bmw_ser::write_frame`] frame
+	/// without a checksum (the length of the data in 4 bytes, big endian, and the data) so that
+	/// a capture can be replayed with its original pacing.
+	///
+	/// The files are written by a separate thread which is fed through a bounded queue, so
+	/// capture never blocks the event loop. If the queue is full, records are dropped and
+	/// counted in [`crate::EvhStats::capture_drops`]. The files are flushed and closed when the
+	/// connection is closed. Data sent with [`crate::WriteHandle::send_file`] is not captured.
+	///
+	/// Capture can be enabled once the connection is managed by an [`crate::EventHandler`], for
+	/// instance in on_accept. To capture all connections, see `EvhCaptureAll`.
+	/// # Errors
+	/// * [`bmw_err::ErrKind::IllegalState`] - If the connection is not managed by an
+	///   [`crate::EventHandler`] yet or if it is sensitive. See
+	///   [`crate::Connection::set_sensitive`].
+	pub fn enable_capture(&mut self, dir: &str) -> Result<(), Error> {
+		if self.capture.is_some() {
+			return Ok(());
+		}
+		if self.sensitive {
+			let text = "capture is not available for a sensitive connection";
+			return Err(err!(ErrKind::IllegalState, text));
+		}
+		let writer = match self.capture_writer.as_mut() {
+			Some(writer) => writer,
 			None => {
-				let text = "connection has no Wakeup";
+				let text = "capture requires a connection managed by an EventHandler";
 				return Err(err!(ErrKind::IllegalState, text));
 			}
 		};
-		let state = match &connection_impl.state {
-			Some(state) => state.clone(),
-			None => {
-				let text = "connection has no WriteState";
-				return Err(err!(ErrKind::IllegalState, text));
-			}
+		let capture = Capture {
+			id: self.id,
+			tx: writer.sender()?,
+			drops: writer.drops.clone(),
+			queued_bytes: writer.queued_bytes.clone(),
 		};
+		capture.send(CaptureRecord::Open(self.id, PathBuf::from(dir)))?;
+		wlock!(self.write_state).capture = Some(capture.clone());
+		self.capture = Some(capture);
+		Ok(())
+	}
 
-		Ok(Self {
-			handle: connection_impl.handle,
-			id: connection_impl.id,
-			write_state: connection_impl.write_state.clone(),
-			wakeup,
-			state,
-			debug_info,
-		})
+	/// Mark this [`crate::Connection`] as carrying secrets (for instance key material). The
+	/// read slabs of a sensitive connection are overwritten with zeros when they are released
+	/// with [`crate::UserContext::clear_through`], [`crate::UserContext::clear_all`] or when the
+	/// connection is closed, and the write buffers are overwritten with zeros once their data
+	/// has been written. Capture is stopped and can no longer be enabled. The writes are
+	/// volatile so they are not optimized away.
+	///
+	/// Zeroizing has a cost, which is only paid by connections that are marked sensitive. The
+	/// flag can be set on the connection passed to on_accept or before the connection is passed
+	/// to [`crate::EventHandler::add_client_connection`].
+	/// # Input Parameters
+	/// * `sensitive` - whether the connection is sensitive.
+	/// # Returns
+	/// [`unit`] on success or an [`bmw_err::Error`] on failure.
+	pub fn set_sensitive(&mut self, sensitive: bool) -> Result<(), Error> {
+		if sensitive {
+			self.end_capture()?;
+		}
+		self.sensitive = sensitive;
+		wlock!(self.write_state).sensitive = sensitive;
+		Ok(())
 	}
-	fn queue_data(&mut self, data: &[u8]) -> Result<(), Error> {
-		{
-			let mut write_state = self.write_state.wlock()?;
-			let guard = write_state.guard()?;
-			(**guard).set_flag(WRITE_STATE_FLAG_PENDING);
-			(**guard).write_buffer.extend(data);
+
+	// overwrite the data of a sensitive connection that was read but not delivered yet
+	pub(crate) fn zeroize_pending(&mut self) {
+		if self.sensitive {
+			zeroize(&mut self.inbound_pending);
+			zeroize(&mut self.proxy_data);
+		}
+	}
+
+	// flush and close the capture files of this connection
+	pub(crate) fn end_capture(&mut self) -> Result<(), Error> {
+		if let Some(capture) = self.capture.take() {
+			wlock!(self.write_state).capture = None;
+			capture.send(CaptureRecord::Close(self.id))?;
 		}
+		Ok(())
+	}
 
+	/// Attach application state to this [`crate::Connection`]. The value is serialized and
+	/// stored with the connection. It is carried along by [`crate::Connection::export_handle`]
+	/// so that the [`crate::EventHandler`] (or process) that imports the connection can
+	/// resume where this one left off. Setting a new value replaces the previous one.
+	pub fn set_attachment<A: Serializable>(&mut self, attachment: &A) -> Result<(), Error> {
+		let mut data = vec![];
+		serialize(&mut data, attachment)?;
 		{
-			wlock!(self.state).write_queue.push_back(self.id);
+			let mut write_state = self.write_state.wlock()?;
+			let guard = write_state.guard()?;
+			guard.attachment_len = data.len();
+			guard.account();
 		}
+		self.attachment = Some(data);
+		Ok(())
+	}
 
-		self.wakeup.wakeup()?;
+	/// Set the number of bytes that the state of this [`crate::Connection`] is counted as
+	/// against `EvhMemoryBudgetBytes`. By default, the size of the value set with
+	/// [`crate::Connection::set_attachment`] is counted. The hint is meant for applications
+	/// that keep larger per-connection state, for instance in the user data of the
+	/// [`crate::UserContext`]. It is released when the connection is closed. Without a memory
+	/// budget, the hint has no effect. See [`crate::MemoryPressure`].
+	/// # Input Parameters
+	/// bytes - the size of the connection's state in bytes.
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	pub fn set_attachment_size_hint(&mut self, bytes: usize) -> Result<(), Error> {
+		let mut write_state = self.write_state.wlock()?;
+		let guard = write_state.guard()?;
+		guard.attachment_size_hint = Some(bytes);
+		guard.account();
 		Ok(())
 	}
-}
 
-impl Connection {
-	/// Retrieves the `id` for this Connection. The id is a unique random u128 value.
-	pub fn id(&self) -> u128 {
-		self.id
+	/// Returns the value set with [`crate::Connection::set_attachment`] or [`None`] if no value
+	/// was set.
+	/// # Errors
+	/// [`bmw_err::ErrKind::CorruptedData`] - If the attachment cannot be deserialized as `A`.
+	pub fn attachment<A: Serializable>(&self) -> Result<Option<A>, Error> {
+		match &self.attachment {
+			Some(data) => Ok(Some(deserialize(&mut &data[..])?)),
+			None => Ok(None),
+		}
 	}
 
-	/// Returns a [`crate::WriteHandle`] which can be used to write data, close the
-	/// connection, or trigger and on_read event for the underlying connection. See
-	/// [`crate::WriteHandle`].
-	pub fn write_handle(&self) -> Result<WriteHandle, Error> {
-		let wh = WriteHandle::new(self, self.debug_info.clone())?;
-		Ok(wh)
+	/// Remove this accepted [`crate::Connection`] from its [`crate::EventHandler`] without
+	/// closing it so that it can be handed to another [`crate::EventHandler`] or process. Any
+	/// data queued by [`crate::WriteHandle::write`] is written first and this function blocks
+	/// until that is done. Once the handle is exported, writes through existing
+	/// [`crate::WriteHandle`]s fail and the on_close handler is not called. The handle is
+	/// deregistered by the [`crate::EventHandler`] thread when the current callback returns,
+	/// so this should be the last thing that the callback does with the connection. Data
+	/// that was read but not consumed is dropped.
+	///
+	/// The returned [`crate::ExportedHandle`] holds the handle and the state of the
+	/// connection: the peer address advertised by the PROXY protocol header, if any, and the
+	/// value set with [`crate::Connection::set_attachment`].
+	/// # Errors
+	/// * [`bmw_err::ErrKind::IllegalState`] - If the connection is not an accepted connection
+	///   managed by an [`crate::EventHandler`], has already been exported or closed or is
+	///   still sending a file or a compressed stream.
+	/// * [`bmw_err::ErrKind::Timeout`] - If the queued data could not be written within 5
+	///   seconds. The connection is not exported in that case.
+	/// * [`bmw_err::ErrKind::IO`] - If an i/o error occurs while writing the queued data.
+	pub fn export_handle(&mut self) -> Result<ExportedHandle, Error> {
+		ensure!(
+			self.ctype == ConnectionType::Connection && self.state.is_some(),
+			ErrKind::IllegalState,
+			"only accepted connections managed by an EventHandler can be exported"
+		);
+		ensure!(
+			self.loopback.is_none(),
+			ErrKind::IllegalState,
+			"loopback connections cannot be exported"
+		);
+		ensure!(
+			!self.exported,
+			ErrKind::IllegalState,
+			"connection {} has already been exported",
+			self.id
+		);
+		let deadline = now_millis()? + EVH_EXPORT_FLUSH_TIMEOUT_MILLIS;
+		loop {
+			{
+				let mut write_state = self.write_state.wlock()?;
+				let guard = write_state.guard()?;
+				ensure!(
+					!guard.is_set(WRITE_STATE_FLAG_CLOSE),
+					ErrKind::IllegalState,
+					"connection {} is closed",
+					self.id
+				);
+				ensure!(
+					guard.pending_files.is_empty() && !guard.is_set(WRITE_STATE_FLAG_COMPRESSING),
+					ErrKind::IllegalState,
+					"connection {} is still sending a file or compressed stream",
+					self.id
+				);
+				if guard.write_buffer.is_empty() {
+					// writes through existing write handles fail from here on
+					guard.unset_flag(WRITE_STATE_FLAG_PENDING);
+					guard.set_flag(WRITE_STATE_FLAG_CLOSE);
+					break;
+				}
+				let wlen = write_impl(self.handle, &guard.write_buffer)?;
+				if wlen > 0 {
+					let wlen: usize = try_into!(wlen)?;
+					if guard.sensitive {
+						drain_zeroize(&mut guard.write_buffer, wlen);
+					} else {
+						guard.write_buffer.drain(..wlen);
+					}
+					guard.account();
+				} else {
+					let err = errno().0;
+					if err != EAGAIN && err != ETEMPUNAVAILABLE && err != WINNONBLOCKING {
+						let text = format!(
+							"write I/O error exporting handle {}: {}",
+							self.handle,
+							errno()
+						);
+						return Err(err!(ErrKind::IO, text));
+					}
+				}
+			}
+			ensure!(
+				now_millis()? < deadline,
+				ErrKind::Timeout,
+				"timed out writing the queued data of connection {}",
+				self.id
+			);
+			sleep(Duration::from_millis(1));
+		}
+		self.exported = true;
+		let peer_addr = self.peer_addr.map(|addr| addr.to_string());
+		let mut state = vec![];
+		let version = EVH_EXPORT_STATE_VERSION;
+		serialize(&mut state, &(version, (peer_addr, self.attachment.clone())))?;
+		Ok(ExportedHandle {
+			handle: self.handle,
+			state,
+		})
 	}
 
-	/// Returns the `origin_id` for a [`crate::Connection`]. The origin_id is the id of the
-	/// connection if it is a client or server connection that was added to this
-	/// [`crate::EventHandler`]. If it is an accepted connection, it is the id of the
-	/// [`crate::Connection`] that it was accepted on.
-	pub fn origin_id(&self) -> u128 {
-		self.origin_id
+	/// Returns the address of the remote peer of this [`crate::Connection`]. If
+	/// EvhProxyProtocol is configured and the PROXY protocol header advertised a source
+	/// address, that address is returned instead of the address of the proxy.
+	pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
+		match self.peer_addr {
+			Some(peer_addr) => Ok(peer_addr),
+			None => peer_addr_impl(self.handle),
+		}
 	}
 
-	/// Disable the message that is sent by configuring EvhOutOfSlabsMessage for the
-	/// [`crate::EventHandler`] that this connection is associated with.
-	pub fn disable_write_final(&mut self) {
-		self.disable_write_final = true;
+	/// Returns the effective size of the receive buffer (SO_RCVBUF) of the underlying socket.
+	/// The kernel may round the value configured with EvhRecvBufferSize. For instance, linux
+	/// doubles it to allow space for bookkeeping overhead.
+	pub fn recv_buffer_size(&self) -> Result<usize, Error> {
+		recv_buffer_size_impl(self.handle)
+	}
+
+	/// Returns the effective size of the send buffer (SO_SNDBUF) of the underlying socket.
+	/// As with [`crate::Connection::recv_buffer_size`], this may differ from the value
+	/// configured with EvhSendBufferSize.
+	pub fn send_buffer_size(&self) -> Result<usize, Error> {
+		send_buffer_size_impl(self.handle)
 	}
 
 	pub(crate) fn new(
@@ -686,14 +3328,16 @@ impl Connection {
 		state: Option<Box<dyn LockBox<EventHandlerState>>>,
 		ctype: ConnectionType,
 		debug_info: DebugInfo,
+		id: u128,
 		origin_id: Option<u128>,
 	) -> Result<Self, Error> {
-		let id = random();
 		// if it's None, it's either a server or client connection and this should be their id
 		let origin_id = match origin_id {
 			Some(origin_id) => origin_id,
 			None => id,
 		};
+		let accepted = ctype != ConnectionType::Connection;
+		let last_read_millis = now_millis()?;
 		Ok(Self {
 			handle,
 			id,
@@ -709,11 +3353,106 @@ impl Connection {
 			origin_id,
 			write_final: false,
 			disable_write_final: false,
+			proxy_header: None,
+			proxy_data: vec![],
+			peer_addr: None,
+			evh_instance_id: 0,
+			awaiting_data: false,
+			capture_writer: None,
+			capture: None,
+			attachment: None,
+			exported: false,
+			migrated: false,
+			accepted,
+			owned_slabs: 0,
+			slab_quota_paused: false,
+			memory_paused: false,
+			max_unread_bytes: 0,
+			unread_bytes: 0,
+			unread_paused: false,
+			read_eof: false,
+			last_read_millis,
+			write_interest: WriteInterest::NotRegistered,
+			inbound_transform: None,
+			inbound_pending: vec![],
+			loopback: None,
+			sensitive: false,
+			response_extractor: None,
+			unmatched_response_handler: None,
 		})
 	}
 	pub(crate) fn handle(&self) -> Handle {
 		self.handle
 	}
+	// whether the connection holds read state that a replica does not carry over
+	pub(crate) fn has_buffered_data(&self) -> bool {
+		self.owned_slabs > 0
+			|| self.proxy_header.is_some()
+			|| !self.proxy_data.is_empty()
+			|| !self.inbound_pending.is_empty()
+	}
+	// a copy of this connection for a replacement thread. The write state is shared so
+	// existing WriteHandles keep working. Read slabs and PROXY protocol state are not copied.
+	pub(crate) fn replica(&self) -> Connection {
+		Connection {
+			handle: self.handle,
+			id: self.id,
+			first_slab: usize::MAX,
+			last_slab: usize::MAX,
+			slab_offset: 0,
+			write_state: self.write_state.clone(),
+			wakeup: self.wakeup.clone(),
+			state: self.state.clone(),
+			tx: None,
+			ctype: match self.ctype {
+				ConnectionType::Server => ConnectionType::Server,
+				ConnectionType::Client => ConnectionType::Client,
+				ConnectionType::Connection => ConnectionType::Connection,
+			},
+			debug_info: self.debug_info.clone(),
+			origin_id: self.origin_id,
+			write_final: self.write_final,
+			disable_write_final: self.disable_write_final,
+			proxy_header: None,
+			proxy_data: vec![],
+			peer_addr: self.peer_addr,
+			evh_instance_id: self.evh_instance_id,
+			awaiting_data: false,
+			capture_writer: self.capture_writer.clone(),
+			capture: self.capture.clone(),
+			attachment: self.attachment.clone(),
+			exported: self.exported,
+			migrated: false,
+			accepted: self.accepted,
+			owned_slabs: 0,
+			slab_quota_paused: false,
+			memory_paused: false,
+			max_unread_bytes: self.max_unread_bytes,
+			unread_bytes: 0,
+			unread_paused: false,
+			read_eof: self.read_eof,
+			last_read_millis: self.last_read_millis,
+			write_interest: self.write_interest,
+			inbound_transform: self.inbound_transform.clone(),
+			inbound_pending: vec![],
+			loopback: self.loopback.clone(),
+			sensitive: self.sensitive,
+			response_extractor: self.response_extractor.clone(),
+			unmatched_response_handler: self.unmatched_response_handler.clone(),
+		}
+	}
+	// the reason for a close requested through the write handle. A connection that ran out of
+	// slabs is closed this way after EvhOutOfSlabsMessage is sent and a connection whose
+	// outbound transform failed is closed this way with the transform's error.
+	pub(crate) fn local_close_reason(&self, transform_error: Option<Error>) -> CloseReason {
+		if let Some(e) = transform_error {
+			CloseReason::TransformError(e)
+		} else if self.write_final {
+			CloseReason::OutOfSlabs
+		} else {
+			CloseReason::LocalClose
+		}
+	}
 	pub(crate) fn set_state(
 		&mut self,
 		state: Box<dyn LockBox<EventHandlerState>>,
@@ -727,10 +3466,10 @@ impl Connection {
 		Ok(())
 	}
 
-	fn set_tx(&mut self, tx: SyncSender<()>) {
+	fn set_tx(&mut self, tx: SyncSender<Result<(), Error>>) {
 		self.tx = Some(tx);
 	}
-	fn get_tx(&mut self) -> Option<&mut SyncSender<()>> {
+	fn get_tx(&mut self) -> Option<&mut SyncSender<Result<(), Error>>> {
 		self.tx.as_mut()
 	}
 	fn get_slab_offset(&self) -> usize {
@@ -753,12 +3492,186 @@ impl Connection {
 	}
 }
 
+impl Loopback {
+	// build both sides of a loopback pair
+	pub(crate) fn pair(capacity: usize) -> Result<(Self, Self), Error> {
+		ensure!(
+			capacity > 0,
+			ErrKind::IllegalArgument,
+			"the loopback buffer size must be greater than 0"
+		);
+		let a_to_b = Arc::new(Mutex::new(LoopbackBuffer::new(capacity)));
+		let b_to_a = Arc::new(Mutex::new(LoopbackBuffer::new(capacity)));
+		let a = Self {
+			inbound: b_to_a.clone(),
+			outbound: a_to_b.clone(),
+		};
+		let b = Self {
+			inbound: a_to_b,
+			outbound: b_to_a,
+		};
+		Ok((a, b))
+	}
+
+	// the synthetic handle of a side of a loopback pair
+	pub(crate) fn next_handle() -> Result<Handle, Error> {
+		let n: Handle = try_into!(LOOPBACK_HANDLES.fetch_add(1, Ordering::SeqCst))?;
+		Ok(Handle::MAX - n)
+	}
+
+	// called when the connection is registered with an EventHandler thread
+	pub(crate) fn register(&self, waker: LoopbackWaker) -> Result<(), Error> {
+		self.lock_inbound().reader = Some(waker.clone());
+		self.lock_outbound().writer = Some(waker);
+		Ok(())
+	}
+
+	// writes as much of data as fits in the peer's buffer. Like write_impl, the number of bytes
+	// written is returned.
+	pub(crate) fn write(&self, data: &[u8]) -> Result<isize, Error> {
+		let (len, reader) = {
+			let mut buffer = self.lock_outbound();
+			if buffer.read_closed || buffer.write_closed {
+				return Err(err!(ErrKind::IO, "loopback connection closed by peer"));
+			}
+			let len = data
+				.len()
+				.min(buffer.capacity.saturating_sub(buffer.data.len()));
+			buffer.data.extend(&data[..len]);
+			(len, buffer.reader.clone())
+		};
+		if len > 0 {
+			if let Some(reader) = reader {
+				reader.notify_read()?;
+			}
+		}
+		Ok(try_into!(len)?)
+	}
+
+	// writes the next part of a file region that fits in the peer's buffer. Like sendfile_impl,
+	// the number of bytes written is returned.
+	pub(crate) fn send_file(&self, mut file: &File, offset: u64, len: u64) -> Result<isize, Error> {
+		let space = {
+			let buffer = self.lock_outbound();
+			buffer.capacity.saturating_sub(buffer.data.len())
+		};
+		let len: usize = try_into!(len.min(try_into!(space)?))?;
+		let mut buf = vec![0u8; len];
+		file.seek(SeekFrom::Start(offset))?;
+		let rlen = file.read(&mut buf)?;
+		self.write(&buf[..rlen])
+	}
+
+	// true if the peer's buffer is full. The peer wakes the writer once it has read from it.
+	pub(crate) fn is_full(&self) -> bool {
+		let buffer = self.lock_outbound();
+		!buffer.read_closed && buffer.data.len() >= buffer.capacity
+	}
+
+	// reads buffered data into buf. Like do_read_impl, Some(0) is returned at EOF and None if
+	// there is no data to read.
+	pub(crate) fn read(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+		let (ret, writer) = {
+			let mut buffer = self.lock_inbound();
+			let was_full = buffer.data.len() >= buffer.capacity;
+			let len = buffer.data.len().min(buf.len());
+			for (dst, src) in buf.iter_mut().zip(buffer.data.drain(..len)) {
+				*dst = src;
+			}
+			let ret = if len > 0 {
+				Some(len)
+			} else if buffer.write_closed {
+				Some(0)
+			} else {
+				None
+			};
+			// the writer is only paused once the buffer is full
+			let writer = if was_full && len > 0 {
+				buffer.writer.clone()
+			} else {
+				None
+			};
+			(ret, writer)
+		};
+		if let Some(writer) = writer {
+			writer.notify_write()?;
+		}
+		Ok(ret)
+	}
+
+	// closes this side of the pair. The peer reads EOF once it has read the buffered data and
+	// its writes fail.
+	pub(crate) fn close(&self) -> Result<(), Error> {
+		let reader = {
+			let mut buffer = self.lock_outbound();
+			buffer.write_closed = true;
+			buffer.reader.take()
+		};
+		let writer = {
+			let mut buffer = self.lock_inbound();
+			buffer.read_closed = true;
+			buffer.data.clear();
+			buffer.writer.take()
+		};
+		if let Some(reader) = reader {
+			reader.notify_read()?;
+		}
+		if let Some(writer) = writer {
+			writer.notify_write()?;
+		}
+		Ok(())
+	}
+
+	fn lock_inbound(&self) -> MutexGuard<'_, LoopbackBuffer> {
+		self.inbound.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	fn lock_outbound(&self) -> MutexGuard<'_, LoopbackBuffer> {
+		self.outbound.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+impl LoopbackBuffer {
+	fn new(capacity: usize) -> Self {
+		Self {
+			data: VecDeque::new(),
+			capacity,
+			write_closed: false,
+			read_closed: false,
+			reader: None,
+			writer: None,
+		}
+	}
+}
+
+impl LoopbackWaker {
+	// the reading side has data to read or reached EOF
+	fn notify_read(&self) -> Result<(), Error> {
+		let mut state = self.state.clone();
+		wlock!(state).read_queue.push_back(self.id);
+		self.wakeup.clone().wakeup()
+	}
+
+	// the writing side may continue writing its pending data. A connection whose peer closed
+	// fails its next write.
+	fn notify_write(&self) -> Result<(), Error> {
+		let mut state = self.state.clone();
+		wlock!(state).write_queue.push_back(self.id);
+		self.wakeup.clone().wakeup()
+	}
+}
+
 impl EventHandlerState {
 	pub(crate) fn new() -> Result<Self, Error> {
 		Ok(Self {
 			nconnections: VecDeque::new(),
 			write_queue: VecDeque::new(),
+			read_queue: VecDeque::new(),
+			raw_deregister: vec![],
 			stop: false,
+			drain: false,
+			remaining: None,
+			health: ThreadHealth::Starting,
 		})
 	}
 }
@@ -778,13 +3691,13 @@ where
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnClose: FnMut(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnClose: FnMut(&mut Connection, &CloseReason, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnHousekeeper: FnMut(&mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnHousekeeper: FnMut(&mut HousekeeperContext<'_>, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
@@ -803,6 +3716,13 @@ where
 		if stop_res.is_err() {
 			let _ = error!("Error occurred while dropping: {}", stop_res.unwrap_err());
 		}
+		if self.has_controller {
+			// the event loops keep running until the controller stops them. Dropping the
+			// thread pool would shut it down and wait for them.
+			if let Some(thread_pool) = self.thread_pool.take() {
+				std::mem::forget(thread_pool);
+			}
+		}
 	}
 }
 
@@ -822,13 +3742,13 @@ where
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnClose: FnMut(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnClose: FnMut(&mut Connection, &CloseReason, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnHousekeeper: FnMut(&mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnHousekeeper: FnMut(&mut HousekeeperContext<'_>, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
@@ -842,96 +3762,326 @@ where
 		+ Unpin,
 {
 	fn start(&mut self) -> Result<(), Error> {
+		if self.callbacks.on_read.is_some() && self.callbacks.on_data_chunk.is_some() {
+			let text = "only one of on_read and on_data_chunk may be set";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+		let cb = &self.callbacks;
+		if cb.handler.is_some()
+			&& (cb.on_read.is_some()
+				|| cb.on_accept.is_some()
+				|| cb.on_close.is_some()
+				|| cb.on_housekeeper.is_some()
+				|| cb.on_panic.is_some()
+				|| cb.on_data_chunk.is_some())
+		{
+			let text = "a ProtocolHandler may not be set along with closure handlers";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+		let missing = self.missing_callbacks();
+		if !missing.is_empty() {
+			let text = format!("required callbacks not set: {}", missing.join(", "));
+			return Err(err!(ErrKind::Configuration, text));
+		}
+		self.init_memory_budget()?;
 		self.start_impl()
 	}
-	fn set_on_read(&mut self, on_read: OnRead) -> Result<(), Error> {
-		self.callbacks.on_read = Some(Box::pin(on_read));
+	fn set_on_read(&mut self, on_read: OnRead) -> Result<(), Error> {
+		self.check_callback_settable("on_read", false)?;
+		self.callbacks.on_read = Some(Box::pin(on_read));
+		Ok(())
+	}
+	fn set_on_accept(&mut self, on_accept: OnAccept) -> Result<(), Error> {
+		self.check_callback_settable("on_accept", true)?;
+		self.callbacks.on_accept = Some(Box::pin(on_accept));
+		Ok(())
+	}
+	fn set_on_close(&mut self, on_close: OnClose) -> Result<(), Error> {
+		self.check_callback_settable("on_close", true)?;
+		self.callbacks.on_close = Some(Box::pin(on_close));
+		Ok(())
+	}
+	fn set_on_housekeeper(&mut self, on_housekeeper: OnHousekeeper) -> Result<(), Error> {
+		self.check_callback_settable("on_housekeeper", true)?;
+		self.callbacks.on_housekeeper = Some(Box::pin(on_housekeeper));
+		Ok(())
+	}
+	fn set_on_panic(&mut self, on_panic: OnPanic) -> Result<(), Error> {
+		self.check_callback_settable("on_panic", true)?;
+		self.callbacks.on_panic = Some(Box::pin(on_panic));
+		Ok(())
+	}
+	fn set_on_read_only(&mut self) -> Result<(), Error> {
+		self.check_callback_settable("on_read_only", false)?;
+		self.on_read_only = true;
+		Ok(())
+	}
+	fn set_logger(&mut self, logger: DynLogger) -> Result<(), Error> {
+		wlock!(self.config.logger) = Some(SharedLogger::new(logger));
+		Ok(())
+	}
+	fn set_debug_info(&mut self, debug_info: DebugInfo) -> Result<(), Error> {
+		self.debug_info.update(debug_info)?;
+		Ok(())
+	}
+	fn add_server_connection(&mut self, mut connection: Connection) -> Result<(), Error> {
+		ensure!(
+			connection.ctype == ConnectionType::Server,
+			ErrKind::IllegalArgument,
+			"trying to add a non-server connection as a server!"
+		);
+		connection.evh_instance_id = self.ids.instance_id;
+		let handle = connection.handle();
+		if let Some(secs) = self.config.defer_accept_secs {
+			set_defer_accept_impl(handle, secs)?;
+		}
+		add_origin(&mut self.stats, connection.id())?;
+		add_listen_addr(&mut self.stats, handle)?;
+		let tid: usize = try_into!(handle % self.config.threads as Handle)?;
+		add_connection(
+			&self.debug_info,
+			&mut self.state,
+			&mut self.wakeups,
+			connection,
+			true,
+			tid,
+			handle,
+		)
+	}
+	fn add_client_connection(&mut self, mut connection: Connection) -> Result<WriteHandle, Error> {
+		ensure!(
+			connection.ctype == ConnectionType::Client,
+			ErrKind::IllegalArgument,
+			"trying to add a non-server connection as a server!"
+		);
+
+		connection.evh_instance_id = self.ids.instance_id;
+		let handle = connection.handle();
+		if connection.loopback.is_none() {
+			apply_buffer_sizes(handle, &self.config)?;
+		}
+		let tid: usize = try_into!(handle % self.config.threads as Handle)?;
+		connection.set_state(self.state[tid].clone())?;
+		connection.set_wakeup(self.wakeups[tid].clone())?;
+		let ret = connection.write_handle()?;
+		add_connection(
+			&self.debug_info,
+			&mut self.state,
+			&mut self.wakeups,
+			connection,
+			false,
+			tid,
+			handle,
+		)?;
+		Ok(ret)
+	}
+
+	fn add_imported_connection(&mut self, connection: Connection) -> Result<(), Error> {
+		add_imported_connection(
+			&self.config,
+			&self.debug_info,
+			&self.ids,
+			&mut self.state,
+			&mut self.wakeups,
+			connection,
+		)
+	}
+
+	fn build_client_connection(&self, host: &str, port: u16) -> Result<Connection, Error> {
+		match self.config.client_bind_addr {
+			Some(local_addr) => EvhBuilder::build_client_connection_bound(host, port, local_addr),
+			None => EvhBuilder::build_client_connection(host, port),
+		}
+	}
+
+	fn controller(&mut self) -> Result<EvhController, Error> {
+		self.has_controller = true;
+		Ok(self.new_controller())
+	}
+
+	fn instance_id(&self) -> u64 {
+		self.ids.instance_id
+	}
+
+	fn fd_limit(&self) -> Result<Option<usize>, Error> {
+		Ok(self.debug_info.fd_limit_override().or(self.config.fd_limit))
+	}
+
+	fn update_config(&mut self, options: Vec<ConfigOption>) -> Result<(), Error> {
+		update_config(&mut self.config, options)
+	}
+
+	fn fd_headroom(&self) -> Result<usize, Error> {
+		fd_headroom(self.stats.as_ref(), self.fd_limit()?)
+	}
+
+	fn thread_health(&self) -> Result<Vec<ThreadHealth>, Error> {
+		thread_health(&self.state, self.config.threads)
+	}
+
+	fn is_running(&self) -> Result<bool, Error> {
+		let health = thread_health(&self.state, self.config.threads)?;
+		Ok(health.iter().all(|h| h == &ThreadHealth::Running))
+	}
+
+	fn handle_signals(&mut self, signals: &[Signal], mode: ShutdownMode) -> Result<(), Error> {
+		ensure!(
+			self.stopper.is_some(),
+			ErrKind::IllegalState,
+			"handle_signals may only be called after start"
+		);
+		ensure!(
+			!signals.is_empty(),
+			ErrKind::IllegalArgument,
+			"at least one signal must be specified"
+		);
+		let registration = SignalRegistration {
+			signals: signals
+				.iter()
+				.fold(0, |mask, signal| mask | signal_mask(signal)),
+			mode,
+			controller: self.new_controller(),
+			on_shutdown: self.on_shutdown.clone(),
+		};
+		register_signals(self.ids.instance_id, signals, registration)
+	}
+
+	fn set_on_shutdown(&mut self, on_shutdown: OnShutdown) -> Result<(), Error> {
+		wlock!(self.on_shutdown) = Some(on_shutdown);
+		Ok(())
+	}
+
+	fn register_key(&mut self, connection_id: u128, key: u64) -> Result<(), Error> {
+		wlock!(self.keys).register(&self.config, connection_id, key)
+	}
+
+	fn unregister_key(&mut self, key: u64) -> Result<(), Error> {
+		wlock!(self.keys).unregister(key);
 		Ok(())
 	}
-	fn set_on_accept(&mut self, on_accept: OnAccept) -> Result<(), Error> {
-		self.callbacks.on_accept = Some(Box::pin(on_accept));
+
+	fn write_handle_for_key(&self, key: u64) -> Result<Option<WriteHandle>, Error> {
+		Ok(rlock!(self.keys).write_handle(key))
+	}
+
+	fn wait_for_stats(&mut self) -> Result<EvhStats, Error> {
+		self.wait_for_stats()
+	}
+
+	fn set_on_raw_event(&mut self, on_raw_event: OnRawEvent) -> Result<(), Error> {
+		self.check_callback_settable("on_raw_event", false)?;
+		self.callbacks.on_raw_event = Some(lock_box!(on_raw_event)?);
 		Ok(())
 	}
-	fn set_on_close(&mut self, on_close: OnClose) -> Result<(), Error> {
-		self.callbacks.on_close = Some(Box::pin(on_close));
+	fn set_on_data_chunk(&mut self, on_data_chunk: OnDataChunk) -> Result<(), Error> {
+		self.check_callback_settable("on_data_chunk", false)?;
+		self.callbacks.on_data_chunk = Some(lock_box!(on_data_chunk)?);
 		Ok(())
 	}
-	fn set_on_housekeeper(&mut self, on_housekeeper: OnHousekeeper) -> Result<(), Error> {
-		self.callbacks.on_housekeeper = Some(Box::pin(on_housekeeper));
+	fn set_on_slab_quota(&mut self, on_slab_quota: OnSlabQuota) -> Result<(), Error> {
+		self.check_callback_settable("on_slab_quota", false)?;
+		self.callbacks.on_slab_quota = Some(lock_box!(on_slab_quota)?);
 		Ok(())
 	}
-	fn set_on_panic(&mut self, on_panic: OnPanic) -> Result<(), Error> {
-		self.callbacks.on_panic = Some(Box::pin(on_panic));
+	fn set_on_read_eof(&mut self, on_read_eof: OnReadEof) -> Result<(), Error> {
+		self.check_callback_settable("on_read_eof", false)?;
+		self.callbacks.on_read_eof = Some(lock_box!(on_read_eof)?);
 		Ok(())
 	}
-	fn set_debug_info(&mut self, debug_info: DebugInfo) -> Result<(), Error> {
-		self.debug_info.update(debug_info)?;
+	fn set_on_panic_info(&mut self, on_panic_info: OnPanicInfo) -> Result<(), Error> {
+		self.check_callback_settable("on_panic_info", false)?;
+		self.callbacks.on_panic_info = Some(lock_box!(on_panic_info)?);
 		Ok(())
 	}
-	fn add_server_connection(&mut self, connection: Connection) -> Result<(), Error> {
-		if connection.ctype != ConnectionType::Server {
-			let text = "trying to add a non-server connection as a server!";
-			return Err(err!(ErrKind::IllegalArgument, text));
-		}
-		let handle = connection.handle();
-		let tid: usize = try_into!(handle % self.config.threads as Handle)?;
-		add_connection(
-			&self.debug_info,
-			&mut self.state,
-			&mut self.wakeups,
-			connection,
-			true,
-			tid,
-			handle,
-		)
+	fn set_handler(
+		&mut self,
+		handler: Box<dyn ProtocolHandler + Send + Sync>,
+	) -> Result<(), Error> {
+		self.check_callback_settable("handler", true)?;
+		self.callbacks.handler = Some(lock_box!(handler)?);
+		Ok(())
 	}
-	fn add_client_connection(&mut self, mut connection: Connection) -> Result<WriteHandle, Error> {
-		if connection.ctype != ConnectionType::Client {
-			let text = "trying to add a non-server connection as a server!";
-			return Err(err!(ErrKind::IllegalArgument, text));
-		}
 
-		let handle = connection.handle();
+	fn set_metrics_recorder(
+		&mut self,
+		recorder: Box<dyn MetricsRecorder + Send + Sync>,
+	) -> Result<(), Error> {
+		ensure!(
+			self.stopper.is_none(),
+			ErrKind::IllegalState,
+			"set_metrics_recorder must be called before start"
+		);
+		self.metrics = Some(Arc::new(Metrics::new(recorder, &self.config)));
+		Ok(())
+	}
+
+	fn register_raw(
+		&mut self,
+		handle: Handle,
+		interest: Interest,
+		token: u64,
+		close_on_stop: bool,
+	) -> Result<(), Error> {
+		ensure!(
+			!self.raw_tokens.contains_key(&token),
+			ErrKind::IllegalArgument,
+			"raw token {} is already registered",
+			token
+		);
 		let tid: usize = try_into!(handle % self.config.threads as Handle)?;
-		connection.set_state(self.state[tid].clone())?;
-		connection.set_wakeup(self.wakeups[tid].clone())?;
-		let ret = connection.write_handle()?;
-		add_connection(
-			&self.debug_info,
-			&mut self.state,
-			&mut self.wakeups,
-			connection,
-			false,
-			tid,
+		let (tx, rx) = sync_channel(1);
+		let raw = RawRegistration {
 			handle,
+			id: self.ids.next(),
+			token,
+			interest,
+			close_on_stop,
+			tx: Some(tx),
+		};
+		debug!(
+			"adding raw handle = {}, token = {}, tid = {}",
+			handle, token, tid
 		)?;
-		Ok(ret)
-	}
 
-	fn controller(&mut self) -> Result<EvhController, Error> {
-		self.has_controller = true;
-		Ok(EvhController {
-			state: self.state.clone(),
-			wakeups: self.wakeups.clone(),
-			stopper: self.stopper.clone(),
-			stats: self.stats.clone(),
-			debug_info: self.debug_info.clone(),
-			config: self.config.clone(),
-		})
+		wlock!(self.state[tid])
+			.nconnections
+			.push_back(ConnectionVariant::Raw(raw));
+		self.wakeups[tid].wakeup()?;
+		rx.recv()?;
+
+		self.raw_tokens.insert(token, tid);
+		Ok(())
 	}
 
-	fn wait_for_stats(&mut self) -> Result<EvhStats, Error> {
-		self.wait_for_stats()
+	fn deregister_raw(&mut self, token: u64) -> Result<(), Error> {
+		let tid = try_opt!(
+			self.raw_tokens.remove(&token),
+			ErrKind::IllegalArgument,
+			"raw token {} is not registered",
+			token
+		);
+		let (tx, rx) = sync_channel(1);
+		wlock!(self.state[tid]).raw_deregister.push((token, tx));
+		self.wakeups[tid].wakeup()?;
+		rx.recv()?;
+		Ok(())
 	}
 }
 
 impl EvhController {
-	pub fn add_server_connection(&mut self, connection: Connection) -> Result<(), Error> {
-		if connection.ctype != ConnectionType::Server {
-			let text = "trying to add a non-server connection as a server!";
-			return Err(err!(ErrKind::IllegalArgument, text));
-		}
+	pub fn add_server_connection(&mut self, mut connection: Connection) -> Result<(), Error> {
+		ensure!(
+			connection.ctype == ConnectionType::Server,
+			ErrKind::IllegalArgument,
+			"trying to add a non-server connection as a server!"
+		);
+		connection.evh_instance_id = self.ids.instance_id;
 		let handle = connection.handle();
+		if let Some(secs) = self.config.defer_accept_secs {
+			set_defer_accept_impl(handle, secs)?;
+		}
+		add_origin(&mut self.stats, connection.id())?;
+		add_listen_addr(&mut self.stats, handle)?;
 		let tid: usize = try_into!(handle % self.config.threads as Handle)?;
 		add_connection(
 			&self.debug_info,
@@ -948,12 +4098,17 @@ impl EvhController {
 		&mut self,
 		mut connection: Connection,
 	) -> Result<WriteHandle, Error> {
-		if connection.ctype != ConnectionType::Client {
-			let text = "trying to add a non-server connection as a server!";
-			return Err(err!(ErrKind::IllegalArgument, text));
-		}
+		ensure!(
+			connection.ctype == ConnectionType::Client,
+			ErrKind::IllegalArgument,
+			"trying to add a non-server connection as a server!"
+		);
 
+		connection.evh_instance_id = self.ids.instance_id;
 		let handle = connection.handle();
+		if connection.loopback.is_none() {
+			apply_buffer_sizes(handle, &self.config)?;
+		}
 		let tid: usize = try_into!(handle % self.config.threads as Handle)?;
 		connection.set_state(self.state[tid].clone())?;
 		connection.set_wakeup(self.wakeups[tid].clone())?;
@@ -970,6 +4125,48 @@ impl EvhController {
 		Ok(ret)
 	}
 
+	pub fn update_config(&mut self, options: Vec<ConfigOption>) -> Result<(), Error> {
+		update_config(&mut self.config, options)
+	}
+
+	/// See [`crate::EventHandler::register_key`].
+	pub fn register_key(&mut self, connection_id: u128, key: u64) -> Result<(), Error> {
+		wlock!(self.keys).register(&self.config, connection_id, key)
+	}
+
+	/// See [`crate::EventHandler::unregister_key`].
+	pub fn unregister_key(&mut self, key: u64) -> Result<(), Error> {
+		wlock!(self.keys).unregister(key);
+		Ok(())
+	}
+
+	/// See [`crate::EventHandler::write_handle_for_key`].
+	pub fn write_handle_for_key(&self, key: u64) -> Result<Option<WriteHandle>, Error> {
+		Ok(rlock!(self.keys).write_handle(key))
+	}
+
+	/// See [`crate::EventHandler::thread_health`].
+	pub fn thread_health(&self) -> Result<Vec<ThreadHealth>, Error> {
+		thread_health(&self.state, self.config.threads)
+	}
+
+	/// See [`crate::EventHandler::is_running`].
+	pub fn is_running(&self) -> Result<bool, Error> {
+		let health = thread_health(&self.state, self.config.threads)?;
+		Ok(health.iter().all(|h| h == &ThreadHealth::Running))
+	}
+
+	pub fn add_imported_connection(&mut self, connection: Connection) -> Result<(), Error> {
+		add_imported_connection(
+			&self.config,
+			&self.debug_info,
+			&self.ids,
+			&mut self.state,
+			&mut self.wakeups,
+			connection,
+		)
+	}
+
 	pub fn wait_for_stats(&mut self) -> Result<EvhStats, Error> {
 		let mut ret = EvhStats::new();
 		let (tx, rx) = sync_channel(1);
@@ -988,6 +4185,8 @@ impl EvhController {
 			let _ = std::mem::replace(&mut ret, (**guard).stats.clone());
 			(**guard).stats.reset();
 		}
+		ret.name.clone_from(&self.config.name);
+		ret.set_memory(self.config.memory.as_ref());
 
 		Ok(ret)
 	}
@@ -1009,6 +4208,131 @@ impl EvhController {
 
 		Ok(())
 	}
+
+	// stops accepting connections and waits until the open connections have been closed, the
+	// timeout elapses or the EventHandler is stopped
+	pub(crate) fn drain(&mut self, timeout_millis: usize) -> Result<(), Error> {
+		for i in 0..self.config.threads {
+			{
+				let mut state = self.state[i].wlock()?;
+				let guard = state.guard()?;
+				(**guard).drain = true;
+				(**guard).remaining = None;
+			}
+			self.wakeups[i].wakeup()?;
+		}
+
+		let deadline = now_millis()?.saturating_add(timeout_millis);
+		loop {
+			// None until every thread has reported
+			let mut remaining = Some(0);
+			for i in 0..self.config.threads {
+				let state = self.state[i].rlock()?;
+				let guard = state.guard()?;
+				if (**guard).stop {
+					return Ok(());
+				}
+				remaining = remaining.zip((**guard).remaining).map(|(a, b)| a + b);
+			}
+			cbreak!(remaining == Some(0));
+			if now_millis()? >= deadline {
+				evh_log!(
+					&self.config,
+					Warn,
+					"graceful shutdown timed out, closing the open connections"
+				)?;
+				break;
+			}
+			sleep(Duration::from_millis(EVH_DRAIN_POLL_MILLIS));
+		}
+		Ok(())
+	}
+}
+
+pub(crate) fn signal_mask(signal: &Signal) -> usize {
+	match signal {
+		Signal::Term => SIGNAL_MASK_TERM,
+		Signal::Int => SIGNAL_MASK_INT,
+	}
+}
+
+// installs the signal handlers, starts the thread that dispatches the signals if needed and
+// adds (or replaces) the registration of the EventHandler
+fn register_signals(
+	instance_id: u64,
+	signals: &[Signal],
+	registration: SignalRegistration,
+) -> Result<(), Error> {
+	let mut registrations = SIGNAL_REGISTRATIONS
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner);
+	for signal in signals {
+		install_signal_handler_impl(*signal)?;
+	}
+	if !SIGNAL_THREAD_STARTED.swap(true, Ordering::SeqCst) {
+		spawn(dispatch_signals);
+	}
+	registrations.insert(instance_id, registration);
+	Ok(())
+}
+
+fn unregister_signals(instance_id: u64) {
+	SIGNAL_REGISTRATIONS
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.remove(&instance_id);
+}
+
+// runs for the life of the process and dispatches each received signal
+fn dispatch_signals() {
+	loop {
+		let mask = match wait_signal_impl() {
+			Ok(mask) => mask,
+			Err(e) => {
+				let _ = error!("waiting for signals generated error: {}", e);
+				SIGNAL_THREAD_STARTED.store(false, Ordering::SeqCst);
+				return;
+			}
+		};
+		dispatch_signal_mask(mask);
+	}
+}
+
+// each EventHandler that registered for one of the signals in `mask` is shut down on a thread
+// of its own so that none of them waits for the others to drain. The tests call this directly
+// rather than raising signals in the shared test process.
+pub(crate) fn dispatch_signal_mask(mask: usize) {
+	let mut registrations = SIGNAL_REGISTRATIONS
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner);
+	let ids: Vec<u64> = registrations
+		.iter()
+		.filter(|(_, registration)| registration.signals & mask != 0)
+		.map(|(id, _)| *id)
+		.collect();
+	for id in ids {
+		if let Some(registration) = registrations.remove(&id) {
+			spawn(move || {
+				if let Err(e) = shutdown_on_signal(registration) {
+					let _ = error!("shutting down evh {} generated error: {}", id, e);
+				}
+			});
+		}
+	}
+}
+
+fn shutdown_on_signal(mut registration: SignalRegistration) -> Result<(), Error> {
+	let config = registration.controller.config.clone();
+	evh_log!(&config, Info, "received a signal, shutting down")?;
+	if let ShutdownMode::Graceful(timeout_millis) = registration.mode {
+		registration.controller.drain(timeout_millis)?;
+	}
+	if let Some(on_shutdown) = wlock!(registration.on_shutdown).as_mut() {
+		if let Err(e) = on_shutdown() {
+			evh_log!(&config, Warn, "on_shutdown callback generated error: {}", e)?;
+		}
+	}
+	registration.controller.stop()
 }
 
 impl<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>
@@ -1026,13 +4350,13 @@ where
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnClose: FnMut(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnClose: FnMut(&mut Connection, &CloseReason, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnHousekeeper: FnMut(&mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnHousekeeper: FnMut(&mut HousekeeperContext<'_>, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
@@ -1046,7 +4370,7 @@ where
 		+ Unpin,
 {
 	pub(crate) fn new(configs: Vec<ConfigOption>) -> Result<Self, Error> {
-		let config = Self::build_config(configs)?;
+		let mut config = build_config(configs)?;
 		let mut state = array!(config.threads, &lock_box!(EventHandlerState::new()?)?)?;
 
 		let w = Wakeup::new()?;
@@ -1054,15 +4378,22 @@ where
 		close_impl(w.writer)?;
 		let mut wakeups = array!(config.threads, &w)?;
 
+		let ids = ConnectionIds::new();
 		for i in 0..config.threads {
 			state[i] = lock_box!(EventHandlerState::new()?)?;
 			wakeups[i] = Wakeup::new()?;
+			wakeups[i].id = ids.next();
 		}
 
 		let global_stats = GlobalStats {
 			stats: EvhStats::new(),
 			update_counter: 0,
 			tx: None,
+			handle_counts: vec![0; config.threads],
+			listen_addrs: vec![],
+			last_fd_warning: None,
+			fd_warnings: 0,
+			stats_locks: 0,
 		};
 		let stats = lock_box!(global_stats)?;
 
@@ -1072,16 +4403,34 @@ where
 		let on_close = None;
 		let on_panic = None;
 		let on_housekeeper = None;
+		let on_raw_event = None;
 		let callbacks = EventHandlerCallbacks {
 			on_read,
 			on_accept,
 			on_close,
 			on_panic,
 			on_housekeeper,
+			on_raw_event,
+			on_data_chunk: None,
+			on_slab_quota: None,
+			on_read_eof: None,
+			on_panic_info: None,
+			handler: None,
 		};
 
+		let mut watches = vec![];
+		for _ in 0..config.threads {
+			watches.push(CallbackWatch::new(
+				config.watchdog_action == WatchdogAction::SpawnReplacement,
+			)?);
+		}
+
 		let stopper = None;
 		let has_controller = false;
+		let capture_writer = CaptureWriter::new()?;
+		if let Some(memory) = config.memory.as_mut() {
+			memory.capture = capture_writer.queued_bytes.clone();
+		}
 
 		let ret = Self {
 			callbacks,
@@ -1090,8 +4439,17 @@ where
 			wakeups,
 			stats,
 			stopper,
+			thread_pool: None,
 			debug_info,
 			has_controller,
+			on_read_only: false,
+			raw_tokens: HashMap::new(),
+			ids,
+			watches,
+			capture_writer,
+			on_shutdown: lock_box!(None)?,
+			keys: lock_box!(KeyRegistry::new())?,
+			metrics: None,
 		};
 
 		Ok(ret)
@@ -1115,10 +4473,90 @@ where
 			let _ = std::mem::replace(&mut ret, (**guard).stats.clone());
 			(**guard).stats.reset();
 		}
+		ret.name.clone_from(&self.config.name);
+		ret.set_memory(self.config.memory.as_ref());
 
 		Ok(ret)
 	}
 
+	fn new_controller(&self) -> EvhController {
+		EvhController {
+			state: self.state.clone(),
+			wakeups: self.wakeups.clone(),
+			stopper: self.stopper.clone(),
+			stats: self.stats.clone(),
+			debug_info: self.debug_info.clone(),
+			config: self.config.clone(),
+			ids: self.ids.clone(),
+			keys: self.keys.clone(),
+		}
+	}
+
+	// count the fixed cost of the read slab arenas and the per thread structures against
+	// EvhMemoryBudgetBytes. It must leave room for the connections.
+	fn init_memory_budget(&self) -> Result<(), Error> {
+		if let Some(memory) = &self.config.memory {
+			let slabs = self.config.read_slab_count * self.config.read_slab_size;
+			let thread = size_of::<EventHandlerContext>()
+				+ EVH_DEFAULT_IN_EVENTS_SIZE * size_of::<EventIn>()
+				+ size_of::<UserContextImpl>();
+			let fixed = (slabs + thread) * self.config.threads;
+			if fixed >= memory.budget {
+				let text = format!(
+					"EvhMemoryBudgetBytes ({}) must exceed the fixed cost of the read slabs and threads ({})",
+					memory.budget, fixed
+				);
+				return Err(err!(ErrKind::Configuration, text));
+			}
+			memory.fixed.store(fixed, Ordering::Relaxed);
+		}
+		Ok(())
+	}
+
+	// check that a callback may still be set. Callbacks are cloned into the event loop
+	// threads by start so they may not be changed afterwards. `read_only_forbidden`
+	// indicates that the callback is not used by an on_read_only (evh_oro!) EventHandler.
+	fn check_callback_settable(&self, name: &str, read_only_forbidden: bool) -> Result<(), Error> {
+		if self.stopper.is_some() {
+			let text = format!("{} may not be set after start has been called", name);
+			return Err(err!(ErrKind::IllegalState, text));
+		}
+		if read_only_forbidden && self.on_read_only {
+			let text = format!("{} is not supported in on_read_only mode", name);
+			return Err(err!(ErrKind::Configuration, text));
+		}
+		Ok(())
+	}
+
+	// the names of the callbacks that must be set before start but are not. A
+	// ProtocolHandler replaces all of the closures and on_data_chunk replaces on_read.
+	fn missing_callbacks(&self) -> Vec<&'static str> {
+		let cb = &self.callbacks;
+		let mut missing = vec![];
+		if cb.handler.is_some() {
+			return missing;
+		}
+		if cb.on_read.is_none() && cb.on_data_chunk.is_none() {
+			missing.push("on_read");
+		}
+		if self.on_read_only {
+			return missing;
+		}
+		if cb.on_accept.is_none() {
+			missing.push("on_accept");
+		}
+		if cb.on_close.is_none() {
+			missing.push("on_close");
+		}
+		if cb.on_panic.is_none() {
+			missing.push("on_panic");
+		}
+		if cb.on_housekeeper.is_none() {
+			missing.push("on_housekeeper");
+		}
+		missing
+	}
+
 	fn start_impl(&mut self) -> Result<(), Error> {
 		let mut tp = thread_pool!(MinSize(self.config.threads))?;
 		let mut executor = lock_box!(tp.executor()?)?;
@@ -1138,6 +4576,10 @@ where
 			read_slabs,
 			user_data: None,
 			slab_cur: usize::MAX,
+			synthetic: false,
+			read_slab_high_water: 0,
+			reassignments: vec![],
+			resumed_reads: vec![],
 		};
 
 		let wakeups_cl = wakeups.clone();
@@ -1149,6 +4591,13 @@ where
 
 		for i in 0..config.threads {
 			let mut evhc = EventHandlerContext::new(wakeups.clone(), i, self.stats.clone())?;
+			evhc.ids = self.ids.clone();
+			evhc.keys = self.keys.clone();
+			evhc.capture_writer = Some(self.capture_writer.clone());
+			evhc.metrics = self.metrics.clone();
+			if config.callback_timeout_millis.is_some() {
+				evhc.watch = Some(self.watches[i].clone());
+			}
 			let wakeup_reader = wakeups[i].reader;
 			let evt = EventIn::new(wakeup_reader, EventTypeIn::Read);
 			evhc.in_events.push(evt);
@@ -1162,6 +4611,10 @@ where
 				read_slabs,
 				user_data: None,
 				slab_cur: usize::MAX,
+				synthetic: false,
+				read_slab_high_water: 0,
+				reassignments: vec![],
+				resumed_reads: vec![],
 			};
 			user_context_arr[i] = lock_box!(user_context)?;
 
@@ -1173,12 +4626,24 @@ where
 		let debug_info_clone = self.debug_info.clone();
 		let mut user_context_arr_clone = user_context_arr.clone();
 
+		// the thread pool passes the panic info to on_panic_info just before on_panic
+		let mut panic_infos = lock_box!(HashMap::<u128, PanicInfoCapture>::new())?;
+		let mut panic_infos_clone = panic_infos.clone();
+		tp.set_on_panic_info(Box::new(move |id, info| -> Result<(), Error> {
+			wlock!(panic_infos_clone).insert(id, info.clone());
+			Ok(())
+		}))?;
+
 		tp.set_on_panic(move |id, e| -> Result<(), Error> {
 			{
+				let info = match wlock!(panic_infos).remove(&id) {
+					Some(info) => info,
+					None => PanicInfoCapture::from_payload(&*e, current().id()),
+				};
 				let id = try_into!(id)?;
 				let mut user_context = user_context_arr_clone[id].wlock_ignore_poison()?;
 				let guard = user_context.guard()?;
-				Self::call_on_panic(&mut callbacks.on_panic, &mut *guard, e)?;
+				Self::call_on_panic(&mut callbacks, &mut *guard, e, &info)?;
 			}
 			let config = config.clone();
 			let callbacks = callbacks.clone();
@@ -1190,7 +4655,7 @@ where
 			wlock!(executor).execute(
 				async move {
 					let i = try_into!(id)?;
-					let c = config;
+					let c = config.clone();
 					let d = callbacks;
 					let e = state;
 					let f = ctx_arr;
@@ -1201,7 +4666,12 @@ where
 
 					if r.is_err() {
 						let e = r.unwrap_err();
-						fatal!("Execute thread had an unexpected error: {}", e)?;
+						evh_log!(
+							&config,
+							Fatal,
+							"Execute thread had an unexpected error: {}",
+							e
+						)?;
 					}
 					Ok(())
 				},
@@ -1226,91 +4696,342 @@ where
 			let user_context_arr = user_context_arr.clone();
 			let debug_info = self.debug_info.clone();
 			execute!(tp, try_into!(i)?, {
-				let c = config;
+				let c = config.clone();
 				let a = callbacks;
 				let s = state;
 				let r = ctx_arr.clone();
 				let u = user_context_arr.clone();
-				let f = false;
+				let f = false;
+				let d = &debug_info;
+
+				let t = Self::execute_thread(c, a, s, r, u, i, f, d);
+				if t.is_err() {
+					let e = t.unwrap_err();
+					evh_log!(
+						&config,
+						Fatal,
+						"Execute thread had an unexpected error: {}",
+						e
+					)?;
+				}
+				Ok(())
+			})?;
+		}
+
+		self.thread_pool = Some(Box::new(tp));
+
+		if let Some(timeout) = self.config.callback_timeout_millis {
+			self.start_watchdog(timeout, ctx_arr, user_context_arr)?;
+		}
+
+		if let Err(e) = wait_for_threads(&self.config, &self.state) {
+			evh_log!(&self.config, Fatal, "{}", e)?;
+			// stop the threads that did start
+			self.new_controller().stop()?;
+			return Err(e);
+		}
+		Ok(())
+	}
+
+	// the watchdog runs on its own thread so that it works even if every evh thread is stuck
+	fn start_watchdog(
+		&self,
+		timeout: usize,
+		ctx_arr: Array<Box<dyn LockBox<EventHandlerContext>>>,
+		user_context_arr: Array<Box<dyn LockBox<UserContextImpl>>>,
+	) -> Result<(), Error> {
+		let callbacks = self.callbacks.clone();
+		let mut watchdog = Watchdog {
+			config: self.config.clone(),
+			state: self.state.clone(),
+			wakeups: self.wakeups.clone(),
+			stats: self.stats.clone(),
+			ids: self.ids.clone(),
+			keys: self.keys.clone(),
+			debug_info: self.debug_info.clone(),
+			watches: self.watches.clone(),
+			ctx_arr,
+			user_context_arr,
+			capture_writer: self.capture_writer.clone(),
+			metrics: self.metrics.clone(),
+		};
+		let interval: u64 = try_into!((timeout / 4).clamp(1, EVH_WATCHDOG_MAX_CHECK_MILLIS))?;
+
+		spawn(move || {
+			// the start time of the last callback that was reported for each thread
+			let mut tripped = vec![0; watchdog.config.threads];
+			loop {
+				sleep(Duration::from_millis(interval));
+				match Self::check_watches(timeout, &mut tripped, &mut watchdog, &callbacks) {
+					Ok(stop) => cbreak!(stop),
+					// an error only ends this check. The watchdog keeps running so that stuck
+					// callbacks are still detected.
+					Err(e) => {
+						let _ = evh_log!(&watchdog.config, Warn, "watchdog generated error: {}", e);
+					}
+				}
+			}
+		});
+		Ok(())
+	}
+
+	// report the callbacks that have been running for longer than `timeout` and replace their
+	// threads if configured. Returns true once the EventHandler has been stopped.
+	fn check_watches(
+		timeout: usize,
+		tripped: &mut [usize],
+		watchdog: &mut Watchdog,
+		callbacks: &EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
+	) -> Result<bool, Error> {
+		if rlock!(watchdog.state[0]).stop {
+			return Ok(true);
+		}
+		let now = watch_millis();
+		for (tid, tripped) in tripped.iter_mut().enumerate() {
+			let watch = &watchdog.watches[tid];
+			let started = watch.started.load(Ordering::Acquire);
+			if !watch_expired(started, *tripped, now, timeout) {
+				continue;
+			}
+			*tripped = started;
+			// the trip is counted before anything that may fail
+			wlock!(watchdog.stats).stats.watchdog_trips += 1;
+			let callback = watch.callback.load(Ordering::Relaxed);
+			evh_log!(
+				&watchdog.config,
+				Fatal,
+				"{} callback on thread {} has been running for {}ms (connection id = {})",
+				WatchedCallback::name(callback),
+				tid,
+				now.saturating_sub(started),
+				watch.id()
+			)?;
+
+			if watchdog.config.watchdog_action != WatchdogAction::SpawnReplacement {
+				continue;
+			}
+			// these callbacks are shared by the threads behind a lock that is held by the stuck
+			// thread so a replacement would block as well
+			if callback == WatchedCallback::DataChunk as usize
+				|| callback == WatchedCallback::RawEvent as usize
+				|| callback == WatchedCallback::SlabQuota as usize
+				|| callback == WatchedCallback::ReadEof as usize
+				|| callback == WatchedCallback::Handler as usize
+			{
+				evh_log!(
+					&watchdog.config,
+					Warn,
+					"thread {} cannot be replaced while in a shared callback",
+					tid
+				)?;
+				continue;
+			}
+			if let Err(e) = Self::spawn_replacement(tid, watchdog, callbacks) {
+				evh_log!(
+					&watchdog.config,
+					Fatal,
+					"could not replace thread {}: {}",
+					tid,
+					e
+				)?;
+			}
+		}
+		Ok(false)
+	}
+
+	// start a new thread that takes over the connections of the stuck thread `tid`. The stuck
+	// thread exits when (if) its callback returns.
+	fn spawn_replacement(
+		tid: usize,
+		watchdog: &mut Watchdog,
+		callbacks: &EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
+	) -> Result<(), Error> {
+		let watch = &watchdog.watches[tid];
+		let connections = try_opt!(
+			watch.connections.as_ref(),
+			ErrKind::IllegalState,
+			"connections are not tracked"
+		);
+		let stuck_id = watch.id();
+		let generation = watch.generation.fetch_add(1, Ordering::AcqRel) + 1;
+		evh_log!(
+			&watchdog.config,
+			Warn,
+			"starting replacement (generation {}) for thread {}",
+			generation,
+			tid
+		)?;
+
+		let wakeups = watchdog.wakeups.clone();
+		let stats = watchdog.stats.clone();
+		let mut ctx = EventHandlerContext::new(wakeups, tid, stats)?;
+		ctx.ids = watchdog.ids.clone();
+		ctx.keys = watchdog.keys.clone();
+		ctx.capture_writer = Some(watchdog.capture_writer.clone());
+		ctx.metrics = watchdog.metrics.clone();
+		ctx.watch = Some(watch.clone());
+		ctx.generation = generation;
+
+		let wakeup = &watchdog.wakeups[tid];
+		ctx.id_hash
+			.insert(wakeup.id, ConnectionVariant::Wakeup(wakeup.clone()));
+		ctx.handle_hash.insert(wakeup.reader, wakeup.id);
+		ctx.in_events
+			.push(EventIn::new(wakeup.reader, EventTypeIn::Read));
+
+		// the connection whose callback is stuck is left to the stuck thread
+		for (id, (conn, buffered)) in rlock!(connections).iter() {
+			if *id == stuck_id {
+				continue;
+			}
+			let handle = conn.handle();
+			let variant = match conn.ctype {
+				ConnectionType::Server => ConnectionVariant::ServerConnection(conn.replica()),
+				ConnectionType::Client => ConnectionVariant::ClientConnection(conn.replica()),
+				ConnectionType::Connection => ConnectionVariant::Connection(conn.replica()),
+			};
+			ctx.id_hash.insert(*id, variant);
+			ctx.handle_hash.insert(handle, *id);
+			// the read slabs of the connection belong to the stuck thread so the data can't
+			// be delivered by the replacement
+			if *buffered {
+				ctx.replacement_closes.push(handle);
+			} else {
+				ctx.in_events.push(EventIn::new(handle, EventTypeIn::Read));
+			}
+		}
+
+		let config = watchdog.config.clone();
+		let read_slabs = slab_allocator!(
+			SlabSize(config.read_slab_size),
+			SlabCount(config.read_slab_count)
+		)?;
+		let user_context = UserContextImpl {
+			read_slabs,
+			user_data: None,
+			slab_cur: usize::MAX,
+			synthetic: false,
+			read_slab_high_water: 0,
+			reassignments: vec![],
+			resumed_reads: vec![],
+		};
+		watchdog.ctx_arr[tid] = lock_box!(ctx)?;
+		watchdog.user_context_arr[tid] = lock_box!(user_context)?;
+
+		let callbacks = callbacks.clone();
+		let state = watchdog.state.clone();
+		let ctx_arr = watchdog.ctx_arr.clone();
+		let mut user_context_arr = watchdog.user_context_arr.clone();
+		let debug_info = watchdog.debug_info.clone();
+		spawn(move || {
+			PanicInfoCapture::enable();
+			let mut panic_recovery = false;
+			loop {
+				let c = config.clone();
+				let a = callbacks.clone();
+				let s = state.clone();
+				let r = ctx_arr.clone();
+				let u = user_context_arr.clone();
+				let p = panic_recovery;
 				let d = &debug_info;
+				let res = catch_unwind(AssertUnwindSafe(|| {
+					Self::execute_thread(c, a, s, r, u, tid, p, d)
+				}));
+				match res {
+					Ok(Ok(_)) => break,
+					Ok(Err(e)) => {
+						let _ = evh_log!(
+							&config,
+							Fatal,
+							"Execute thread had an unexpected error: {}",
+							e
+						);
+						break;
+					}
+					Err(e) => {
+						let mut callbacks = callbacks.clone();
+						let info = PanicInfoCapture::from_payload(&*e, current().id());
+						if let Ok(mut user_context) = user_context_arr[tid].wlock_ignore_poison() {
+							if let Ok(guard) = user_context.guard() {
+								let _ = Self::call_on_panic(&mut callbacks, guard, e, &info);
+							}
+						}
+						panic_recovery = true;
+					}
+				}
+			}
+		});
 
-				let t = Self::execute_thread(c, a, s, r, u, i, f, d);
-				if t.is_err() {
-					let e = t.unwrap_err();
-					fatal!("Execute thread had an unexpected error: {}", e)?;
+		Ok(())
+	}
+
+	// record a copy of the connection so that a replacement thread can take it over
+	fn track_connection(
+		ctx: &EventHandlerContext,
+		id: u128,
+		conn: &ConnectionVariant,
+	) -> Result<(), Error> {
+		if let Some(mut connections) = ctx.watch.as_ref().and_then(|w| w.connections.clone()) {
+			match conn {
+				ConnectionVariant::ServerConnection(conn)
+				| ConnectionVariant::ClientConnection(conn)
+				| ConnectionVariant::Connection(conn) => {
+					let buffered = conn.has_buffered_data();
+					wlock!(connections).insert(id, (conn.replica(), buffered));
 				}
-				Ok(())
-			})?;
+				_ => {}
+			}
 		}
 		Ok(())
 	}
 
-	fn build_config(configs: Vec<ConfigOption>) -> Result<EventHandlerConfig, Error> {
-		let config = ConfigBuilder::build_config(configs);
-		config.check_config(
-			vec![
-				CN::EvhReadSlabSize,
-				CN::EvhReadSlabCount,
-				CN::EvhTimeout,
-				CN::EvhThreads,
-				CN::EvhHouseKeeperFrequencyMillis,
-				CN::EvhStatsUpdateMillis,
-				CN::EvhOutOfSlabsMessage,
-				CN::Debug,
-			],
-			vec![],
-		)?;
-
-		let threads = config.get_or_usize(&CN::EvhThreads, EVH_DEFAULT_THREADS);
-		let evhrlc = &CN::EvhReadSlabCount;
-		let read_slab_count = config.get_or_usize(evhrlc, EVH_DEFAULT_READ_SLAB_COUNT);
-		let read_slab_size = config.get_or_usize(&CN::EvhReadSlabSize, EVH_DEFAULT_READ_SLAB_SIZE);
-		let debug = config.get_or_bool(&CN::Debug, false);
-		let timeout = config.get_or_u16(&CN::EvhTimeout, EVH_DEFAULT_TIMEOUT);
-		let evhkfm = &CN::EvhHouseKeeperFrequencyMillis;
-		let default = EVH_DEFAULT_HOUSEKEEPING_FREQUENCY_MILLIS;
-		let housekeeping_frequency_millis = config.get_or_usize(evhkfm, default);
-		let evhsum = &CN::EvhStatsUpdateMillis;
-		let default = EVH_DEFAULT_STATS_UPDATE_MILLIS;
-		let stats_update_frequency_millis = config.get_or_usize(evhsum, default);
-		let default = EVH_DEFAULT_OUT_OF_SLABS_MESSAGE.to_string();
-		let out_of_slabs_message = config.get_or_string(&CN::EvhOutOfSlabsMessage, default);
-
-		if read_slab_count == 0 {
-			let text = "EvhReadSlabCount count must not be 0";
-			return Err(err!(ErrKind::Configuration, text));
+	// record whether a tracked connection holds read data after it was processed
+	fn track_buffered(watch: &Option<CallbackWatch>, conn: &Connection) -> Result<(), Error> {
+		if let Some(mut connections) = watch.as_ref().and_then(|w| w.connections.clone()) {
+			if let Some((_, buffered)) = wlock!(connections).get_mut(&conn.id) {
+				*buffered = conn.has_buffered_data();
+			}
 		}
+		Ok(())
+	}
 
-		if read_slab_size < 25 {
-			let text = "EvhReadSlabSize must be at least 25";
-			return Err(err!(ErrKind::Configuration, text));
+	// true if a replacement thread has taken over this context's connections
+	fn is_replaced(ctx: &EventHandlerContext) -> bool {
+		match &ctx.watch {
+			Some(watch) => watch.generation.load(Ordering::Acquire) != ctx.generation,
+			None => false,
 		}
+	}
 
-		if timeout == 0 {
-			let text = "EvhTimeout must not be 0";
-			return Err(err!(ErrKind::Configuration, text));
+	// runs the event loop of the thread and records the error in its health if it fails
+	pub(crate) fn execute_thread(
+		config: EventHandlerConfig,
+		callbacks: EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
+		mut state: Array<Box<dyn LockBox<EventHandlerState>>>,
+		ctx_arr: Array<Box<dyn LockBox<EventHandlerContext>>>,
+		user_context_arr: Array<Box<dyn LockBox<UserContextImpl>>>,
+		tid: usize,
+		panic_recovery: bool,
+		debug_info: &DebugInfo,
+	) -> Result<(), Error> {
+		let s = state.clone();
+		let (u, p) = (user_context_arr, panic_recovery);
+		let mut ctx = ctx_arr[tid].clone();
+		let ret = Self::execute_thread_impl(config, callbacks, s, ctx_arr, u, tid, p, debug_info);
+		// not needed after a panic because the recovered thread continues with the same context
+		{
+			let mut ctx = ctx.wlock_ignore_poison()?;
+			let guard = ctx.guard()?;
+			Self::flush_thread_stats(guard)?;
 		}
-
-		if housekeeping_frequency_millis == 0 {
-			let text = "EvhHouseKeeperFrequencyMillis must not be 0";
-			return Err(err!(ErrKind::Configuration, text));
+		if let Err(e) = &ret {
+			wlock!(state[tid]).health = ThreadHealth::Failed(e.kind().to_string());
 		}
-
-		let evhc = EventHandlerConfig {
-			threads,
-			debug,
-			timeout,
-			read_slab_size,
-			read_slab_count,
-			housekeeping_frequency_millis,
-			stats_update_frequency_millis,
-			out_of_slabs_message,
-		};
-		Ok(evhc)
+		ret
 	}
 
-	pub(crate) fn execute_thread(
-		config: EventHandlerConfig,
+	fn execute_thread_impl(
+		mut config: EventHandlerConfig,
 		mut callbacks: EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
 		mut state: Array<Box<dyn LockBox<EventHandlerState>>>,
 		mut ctx_arr: Array<Box<dyn LockBox<EventHandlerContext>>>,
@@ -1332,7 +5053,23 @@ where
 		let ctx_guard = ctx.guard()?;
 		let user_context_guard = user_context.guard()?;
 
+		let watch = (**ctx_guard).watch.clone();
+		let generation = (**ctx_guard).generation;
+		CALLBACK_WATCH.with(|w| *w.borrow_mut() = watch.map(|watch| (watch, generation)));
+		if Self::is_replaced(&(**ctx_guard)) {
+			return Ok(());
+		}
+
+		// these connections were taken over from a replaced thread that kept their read data
+		for handle in std::mem::take(&mut ctx_guard.replacement_closes) {
+			let g = &mut (**ctx_guard);
+			let c = &mut callbacks;
+			let u = &mut (**user_context_guard);
+			Self::process_close(handle, g, c, u, CloseReason::ThreadReplaced)?;
+		}
+
 		let mut count = 0u128;
+		let mut generation = 0;
 
 		if panic_recovery {
 			let ret_event_itt = (**ctx_guard).ret_event_itt;
@@ -1340,7 +5077,9 @@ where
 
 			let trigger_itt = (**ctx_guard).trigger_itt;
 			let trigger_count = (**ctx_guard).trigger_on_read_list.len();
-			warn!("panic occurred, trying to recover")?;
+			let loopback_itt = (**ctx_guard).loopback_itt;
+			let loopback_count = (**ctx_guard).loopback_events.len();
+			evh_log!(&config, Warn, "panic occurred, trying to recover")?;
 
 			if trigger_itt < trigger_count && !debug_info.is_internal_panic() {
 				let handle = (**ctx_guard).trigger_on_read_list[trigger_itt];
@@ -1350,7 +5089,7 @@ where
 				let g = &mut (**ctx_guard);
 				let c = &mut callbacks;
 				let u = &mut (**user_context_guard);
-				Self::process_close(h, g, c, u)?;
+				Self::process_close(h, g, c, u, CloseReason::Panic)?;
 
 				// skip over errant event
 				(**ctx_guard).trigger_itt += 1;
@@ -1364,13 +5103,30 @@ where
 				let g = &mut (**ctx_guard);
 				let c = &mut callbacks;
 				let u = &mut (**user_context_guard);
-				Self::process_close(h, g, c, u)?;
+				Self::process_close(h, g, c, u, CloseReason::Panic)?;
 
 				// skip over errant event
 				(**ctx_guard).ret_event_itt += 1;
+			} else if loopback_itt < loopback_count && !debug_info.is_internal_panic() {
+				// the error was in the event of a loopback connection
+				let handle = (**ctx_guard).loopback_events[loopback_itt].handle;
+				debug!("handle to close (loopback) = {}", handle)?;
+
+				let h = handle;
+				let g = &mut (**ctx_guard);
+				let c = &mut callbacks;
+				let u = &mut (**user_context_guard);
+				Self::process_close(h, g, c, u, CloseReason::Panic)?;
+
+				// skip over errant event
+				(**ctx_guard).loopback_itt += 1;
 			} else {
 				// something's wrong
-				warn!("panic, but no pending events. Internal panic?")?;
+				evh_log!(
+					&config,
+					Warn,
+					"panic, but no pending events. Internal panic?"
+				)?;
 			}
 
 			let c = &config;
@@ -1382,7 +5138,12 @@ where
 
 			if r.is_err() {
 				let e = r.unwrap_err();
-				fatal!("Process events generated an unexpected error: {}", e)?;
+				evh_log!(
+					&config,
+					Fatal,
+					"Process events generated an unexpected error: {}",
+					e
+				)?;
 			}
 		}
 
@@ -1396,18 +5157,35 @@ where
 		let stop = match proc_state_res {
 			Ok(stop) => stop,
 			Err(e) => {
-				fatal!("Process events generated an unexpected error: {}", e)?;
+				evh_log!(
+					&config,
+					Fatal,
+					"Process events generated an unexpected error: {}",
+					e
+				)?;
 				!debug_info.is_internal_panic()
 			}
 		};
 
 		if !stop {
+			wlock!(state[tid]).health = ThreadHealth::Running;
 			loop {
+				reload_config(&mut config, &mut generation)?;
+				while debug_info.is_hold_loop() {
+					sleep(Duration::from_millis(1));
+				}
 				let r = do_get_events(&config, &mut (**ctx_guard), debug_info);
 				if r.is_err() {
 					let e = r.unwrap_err();
-					fatal!("get_events generated an unexpected error: {}", e,)?;
+					evh_log!(
+						&config,
+						Fatal,
+						"get_events generated an unexpected error: {}",
+						e,
+					)?;
 				}
+				// time spent waiting in get_events is idle time and is not counted
+				let loop_start = ctx_guard.metrics.as_ref().map(|_| Instant::now());
 
 				let cg = &mut **ctx_guard;
 				cg.thread_stats.event_loops += 1;
@@ -1426,22 +5204,96 @@ where
 				let r = Self::process_state(s, cg, c, u, co, debug_info);
 				match r {
 					Ok(stop) => cbreak!(stop),
-					Err(e) => fatal!("Process events generated an unexpected error: {}", e)?,
+					Err(e) => evh_log!(
+						&config,
+						Fatal,
+						"Process events generated an unexpected error: {}",
+						e
+					)?,
 				}
 
 				debug!("calling proc events")?;
 				// set iterator to 0 outside function in case of thread panic
 				cg.ret_event_itt = 0;
 				cg.trigger_itt = 0;
+				cg.loopback_itt = 0;
 				let s = &mut state;
 				let r = Self::process_events(co, cg, c, s, u, debug_info);
 				if r.is_err() {
 					let e = r.unwrap_err();
-					fatal!("Process events generated an unexpected error: {}", e)?;
+					evh_log!(
+						&config,
+						Fatal,
+						"Process events generated an unexpected error: {}",
+						e
+					)?;
+				}
+				if cg.draining {
+					Self::process_drain(&mut state[tid], cg)?;
+				}
+				if let (Some(metrics), Some(start)) = (&cg.metrics, loop_start) {
+					metrics.incr(
+						EvhMetric::EventLoopMicros,
+						try_into!(start.elapsed().as_micros())?,
+					);
 				}
 				count += 1;
 			}
 		}
+
+		// the connection whose callback was stuck was not taken over by the replacement
+		if let Some(id) = ORPHANED_ID.with(|orphaned| orphaned.take()) {
+			let cg = &mut **ctx_guard;
+			let handle = match cg.id_hash.get(&id) {
+				Some(ConnectionVariant::Connection(conn)) => Some(conn.handle()),
+				Some(ConnectionVariant::ClientConnection(conn)) => Some(conn.handle()),
+				_ => None,
+			};
+			if let Some(handle) = handle {
+				evh_log!(
+					&config,
+					Warn,
+					"closing connection {} after its callback returned",
+					id
+				)?;
+				let c = &mut callbacks;
+				let u = &mut (**user_context_guard);
+				Self::process_close(handle, cg, c, u, CloseReason::CallbackTimeout)?;
+			}
+		}
+
+		// a thread that was replaced by the watchdog leaves the health to its replacement
+		if !Self::is_replaced(&(**ctx_guard)) {
+			wlock!(state[tid]).health = ThreadHealth::Stopped;
+		}
+		Ok(())
+	}
+
+	// closes the server connections so no more connections are accepted and reports the
+	// number of connections that are still open for a graceful shutdown
+	fn process_drain(
+		state: &mut Box<dyn LockBox<EventHandlerState>>,
+		ctx: &mut EventHandlerContext,
+	) -> Result<(), Error> {
+		let mut listeners = vec![];
+		let mut remaining = 0;
+		for (id, conn) in &ctx.id_hash {
+			match conn {
+				ConnectionVariant::ServerConnection(conn) => listeners.push((conn.handle(), *id)),
+				ConnectionVariant::Connection(_) | ConnectionVariant::ClientConnection(_) => {
+					remaining += 1
+				}
+				_ => {}
+			}
+		}
+		for (handle, id) in listeners {
+			debug!("closing listener handle={},id={}", handle, id)?;
+			ctx.handle_hash.remove(&handle);
+			ctx.id_hash.remove(&id);
+			deregister_raw_impl(handle, ctx)?;
+			close_impl(handle)?;
+		}
+		wlock!(state).remaining = Some(remaining);
 		Ok(())
 	}
 
@@ -1455,12 +5307,27 @@ where
 		let writer = ctx.wakeups[ctx.tid].writer;
 		for (handle, id) in &ctx.handle_hash {
 			debug!("close handle = {}, id = {}", handle, id)?;
+			wlock!(ctx.keys).untrack(*id);
+			// raw handles are owned by the caller unless close_on_stop was specified
+			if let Some(ConnectionVariant::Raw(raw)) = ctx.id_hash.get(id) {
+				if !raw.close_on_stop {
+					continue;
+				}
+			}
+			if let Some(loopback) = Self::loopback(ctx.id_hash.get(id)) {
+				loopback.close()?;
+				continue;
+			}
 			if *handle != reader && *handle != writer {
 				close_impl(*handle)?;
 			}
 		}
 
 		for conn in nconnections {
+			if let Some(loopback) = Self::loopback(Some(conn)) {
+				loopback.close()?;
+				continue;
+			}
 			match conn {
 				ConnectionVariant::ServerConnection(c) => {
 					let handle = c.handle();
@@ -1480,123 +5347,661 @@ where
 						close_impl(handle)?;
 					}
 				}
-				ConnectionVariant::Wakeup(_w) => {}
+				ConnectionVariant::Wakeup(_w) => {}
+				ConnectionVariant::Raw(raw) => {
+					if raw.close_on_stop {
+						close_impl(raw.handle)?;
+					}
+				}
+			}
+		}
+
+		close_impl(reader)?;
+		close_impl(writer)?;
+
+		Ok(())
+	}
+
+	fn process_housekeeper(
+		ctx: &mut EventHandlerContext,
+		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
+		user_context: &mut UserContextImpl,
+		config: &EventHandlerConfig,
+		debug_info: &DebugInfo,
+	) -> Result<(), Error> {
+		let now = SystemTime::now();
+		let now: usize = try_into!(now.duration_since(UNIX_EPOCH)?.as_millis())?;
+		if now.saturating_sub(ctx.last_housekeeping) > config.housekeeping_frequency_millis {
+			Self::call_on_housekeeper(ctx, user_context, callbacks, now)?;
+			Self::process_memory_pressure(ctx, callbacks, user_context, config, now)?;
+			ctx.last_housekeeping = now;
+		}
+
+		if now.saturating_sub(ctx.last_stats_update) > config.stats_update_frequency_millis {
+			Self::update_stats(ctx, user_context, config, debug_info, now)?;
+			ctx.last_stats_update = now;
+		}
+		Ok(())
+	}
+
+	// apply the MemoryPressure of EvhMemoryBudgetBytes to the connections of this thread
+	fn process_memory_pressure(
+		ctx: &mut EventHandlerContext,
+		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
+		user_context: &mut UserContextImpl,
+		config: &EventHandlerConfig,
+		now: usize,
+	) -> Result<(), Error> {
+		let memory = match &config.memory {
+			Some(memory) => memory,
+			None => return Ok(()),
+		};
+		match memory.update(now, config.housekeeping_frequency_millis) {
+			Some(MemoryPressure::None) => {
+				evh_log!(
+					config,
+					Info,
+					"memory use is within EvhMemoryBudgetBytes again"
+				)?;
+				ctx.thread_stats.memory_recoveries += 1;
+			}
+			Some(pressure) => {
+				let used = memory.used();
+				let budget = memory.budget;
+				evh_log!(
+					config,
+					Warn,
+					"memory use {} > {}: {:?}",
+					used,
+					budget,
+					pressure
+				)?;
+				ctx.thread_stats.memory_pressure_events += 1;
+			}
+			None => {}
+		}
+		let pressure = memory.pressure();
+		if pressure == MemoryPressure::None {
+			return Self::resume_memory_paused(ctx);
+		}
+
+		// (bytes, id, handle) of the connections that use memory, the largest first
+		let mut consumers = vec![];
+		for (id, conn) in &ctx.id_hash {
+			let conn = match conn {
+				ConnectionVariant::Connection(conn) => conn,
+				ConnectionVariant::ClientConnection(conn) => conn,
+				_ => continue,
+			};
+			let bytes = rlock!(conn.write_state).accounted;
+			if bytes > 0 {
+				consumers.push((bytes, *id, conn.handle()));
+			}
+		}
+		consumers.sort_by(|a, b| b.cmp(a));
+
+		let over = memory.used().saturating_sub(memory.budget);
+		if pressure == MemoryPressure::CloseConnections && over > 0 && !consumers.is_empty() {
+			let (_, _, handle) = consumers.remove(0);
+			let reason = CloseReason::MemoryPressure;
+			Self::process_close(handle, ctx, callbacks, user_context, reason)?;
+		}
+
+		// pause the largest consumers until the memory they use covers the excess
+		let mut covered = 0;
+		for (bytes, id, _) in consumers {
+			cbreak!(covered >= over);
+			covered += bytes;
+			if let Some(ConnectionVariant::Connection(conn))
+			| Some(ConnectionVariant::ClientConnection(conn)) = ctx.id_hash.get_mut(&id)
+			{
+				if !conn.memory_paused {
+					conn.memory_paused = true;
+					ctx.memory_paused.push(id);
+					ctx.thread_stats.memory_reads_paused += 1;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	// read from the connections paused by MemoryPressure::PauseReads again. Their handles are
+	// registered again, which reports them if data arrived while they were paused.
+	fn resume_memory_paused(ctx: &mut EventHandlerContext) -> Result<(), Error> {
+		for id in std::mem::take(&mut ctx.memory_paused) {
+			let conn = match ctx.id_hash.get_mut(&id) {
+				Some(ConnectionVariant::Connection(conn)) => conn,
+				Some(ConnectionVariant::ClientConnection(conn)) => conn,
+				_ => continue,
+			};
+			conn.memory_paused = false;
+			let handle = conn.handle();
+			if conn.loopback.is_some() {
+				ctx.loopback_events
+					.push(Event::new(handle, EventType::Read));
+			} else {
+				let etype = match conn.write_interest {
+					WriteInterest::Registered => EventTypeIn::Write,
+					WriteInterest::NotRegistered => EventTypeIn::Read,
+				};
+				ctx.in_events.push(EventIn::new(handle, etype));
+			}
+		}
+		Ok(())
+	}
+
+	// count the queued data and attachment of a connection against EvhMemoryBudgetBytes
+	fn init_memory(conn: &mut Connection, config: &EventHandlerConfig) -> Result<(), Error> {
+		if let Some(memory) = &config.memory {
+			let mut write_state = conn.write_state.wlock()?;
+			let guard = write_state.guard()?;
+			guard.memory = Some(memory.clone());
+			guard.account();
+		}
+		Ok(())
+	}
+
+	// the number of accepted and client connections of this thread
+	fn connection_count(ctx: &EventHandlerContext) -> usize {
+		ctx.id_hash
+			.values()
+			.filter(|conn| {
+				matches!(
+					conn,
+					ConnectionVariant::Connection(_) | ConnectionVariant::ClientConnection(_)
+				)
+			})
+			.count()
+	}
+
+	fn update_stats(
+		ctx: &mut EventHandlerContext,
+		user_context: &mut UserContextImpl,
+		config: &EventHandlerConfig,
+		debug_info: &DebugInfo,
+		now: usize,
+	) -> Result<(), Error> {
+		Self::collect_shared_counters(ctx);
+		let connections = Self::connection_count(ctx);
+		let free = user_context.read_slabs.free_count()?;
+		let read_slab_stats = ReadSlabStats {
+			used: user_context.read_slabs.slab_count()?.saturating_sub(free),
+			free,
+			high_water: user_context.read_slab_high_water,
+		};
+		{
+			let mut global_stats = ctx.global_stats.wlock()?;
+			let guard = global_stats.guard()?;
+			(**guard).stats.incr_stats(&ctx.thread_stats);
+			guard.stats_locks += 1;
+			guard.stats.set_read_slabs(ctx.tid, read_slab_stats);
+			guard.stats.set_thread_connections(ctx.tid, connections);
+			guard.handle_counts[ctx.tid] = ctx.id_hash.len();
+			if let Some(fd_limit) = debug_info.fd_limit_override().or(config.fd_limit) {
+				check_fd_limit(guard, fd_limit, config.fd_warn_threshold_percent, now)?;
+			}
+			(**guard).update_counter += 1;
+			if (**guard).update_counter >= config.threads {
+				if (**guard).tx.is_some() {
+					let tx = &(**guard).tx.as_mut().unwrap();
+					tx.send(())?;
+				}
+
+				(**guard).update_counter = 0;
+			}
+		}
+
+		if let Some(metrics) = &ctx.metrics {
+			let mut connections = 0;
+			let mut pending_write_bytes = 0;
+			for conn in ctx.id_hash.values() {
+				let write_state = match conn {
+					ConnectionVariant::Connection(conn) => &conn.write_state,
+					ConnectionVariant::ClientConnection(conn) => &conn.write_state,
+					_ => continue,
+				};
+				connections += 1;
+				pending_write_bytes += rlock!(write_state).write_buffer.len();
+			}
+			metrics.set_gauges(ctx.tid, connections, try_into!(pending_write_bytes)?);
+		}
+
+		ctx.thread_stats.reset();
+		Ok(())
+	}
+
+	// move the counters that other threads update into the thread_stats of this thread
+	fn collect_shared_counters(ctx: &mut EventHandlerContext) {
+		let writes = &ctx.wakeups[ctx.tid].writes;
+		ctx.thread_stats.wakeup_writes += writes.swap(0, Ordering::Relaxed);
+		if let Some(capture_writer) = &ctx.capture_writer {
+			ctx.thread_stats.capture_drops += capture_writer.drops.swap(0, Ordering::Relaxed);
+		}
+	}
+
+	// aggregate the counts of a thread that is exiting. They would otherwise be lost because
+	// the thread does not reach its next stats update.
+	fn flush_thread_stats(ctx: &mut EventHandlerContext) -> Result<(), Error> {
+		Self::collect_shared_counters(ctx);
+		{
+			let mut global_stats = ctx.global_stats.wlock_ignore_poison()?;
+			let guard = global_stats.guard()?;
+			guard.stats.incr_stats(&ctx.thread_stats);
+			guard.stats_locks += 1;
+		}
+		ctx.thread_stats.reset();
+		Ok(())
+	}
+
+	fn record_reads(
+		ctx: &EventHandlerContext,
+		read_count: usize,
+		read_sum: u128,
+	) -> Result<(), Error> {
+		if let Some(metrics) = &ctx.metrics {
+			metrics.incr(EvhMetric::Reads, try_into!(read_count)?);
+			metrics.incr(EvhMetric::BytesRead, try_into!(read_sum)?);
+		}
+		Ok(())
+	}
+
+	// allow the connection to be captured and capture it if EvhCaptureAll is configured.
+	// Sensitive connections are never captured.
+	fn init_capture(
+		ctx: &EventHandlerContext,
+		conn: &mut Connection,
+		config: &EventHandlerConfig,
+	) -> Result<(), Error> {
+		conn.capture_writer = ctx.capture_writer.clone();
+		if conn.sensitive {
+			return Ok(());
+		}
+		if let Some(dir) = &config.capture_all {
+			let dir = dir.to_string_lossy().to_string();
+			if let Err(e) = conn.enable_capture(&dir) {
+				evh_log!(
+					config,
+					Warn,
+					"enabling capture of connection {} failed: {}",
+					conn.id(),
+					e
+				)?;
+			}
+		}
+		Ok(())
+	}
+
+	fn process_state(
+		state: &mut Box<dyn LockBox<EventHandlerState>>,
+		ctx: &mut EventHandlerContext,
+		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
+		user_context: &mut UserContextImpl,
+		config: &EventHandlerConfig,
+		debug_info: &DebugInfo,
+	) -> Result<bool, Error> {
+		if debug_info.is_internal_panic() {
+			return Err(err!(ErrKind::Test, "internal panic"));
+		}
+
+		debug!("in process state tid={}", ctx.tid)?;
+		// a thread that was replaced only waits for its callback to return and exits
+		if Self::is_replaced(ctx) {
+			return Ok(true);
+		}
+
+		Self::process_write_pending(ctx, callbacks, user_context, state, debug_info)?;
+		Self::process_housekeeper(ctx, callbacks, user_context, config, debug_info)?;
+		Self::process_proxy_timeouts(ctx, callbacks, user_context)?;
+		Self::process_data_timeouts(ctx, callbacks, user_context)?;
+		Self::process_write_timers(ctx, debug_info)?;
+		Self::process_response_timers(ctx)?;
+
+		{
+			let mut state = state.wlock()?;
+			let guard = state.guard()?;
+
+			for (token, tx) in guard.raw_deregister.drain(..) {
+				if let Some(handle) = ctx.raw_token_hash.get(&token).copied() {
+					Self::remove_raw(handle, ctx)?;
+				}
+				let _ = tx.send(());
+			}
+
+			debug!("guard.stop={}", (**guard).stop)?;
+			if (**guard).stop {
+				debug!("stopping thread")?;
+				(**guard)
+					.nconnections
+					.extend(ctx.pending_connections.drain(..));
+				Self::close_handles(ctx, &(**guard).nconnections, callbacks)?;
+				return Ok(true);
+			}
+			// the listeners are closed by process_drain once the pending events are processed
+			ctx.draining = (**guard).drain;
+			debug!("nconnections.size={}", (**guard).nconnections.len())?;
+			// the lock is not held while on_accept is called so that a stuck callback does
+			// not block the threads that add connections to this one
+			ctx.pending_connections
+				.extend((**guard).nconnections.drain(..));
+		}
+
+		let accept_start = (!ctx.pending_connections.is_empty()).then(Instant::now);
+		let mut registered = 0;
+		loop {
+			if let Some(accept_start) = accept_start {
+				let budget = config.accept_budget_micros;
+				let elapsed = accept_start.elapsed().as_micros();
+				if budget > 0
+					&& registered > 0
+					&& elapsed > try_into!(budget)?
+					&& !ctx.pending_connections.is_empty()
+				{
+					// the rest are registered in the next loop. See events_timeout.
+					ctx.thread_stats.accept_burst_deferrals += 1;
+					break;
+				}
+			}
+			if Self::is_replaced(ctx) {
+				// the replacement thread registers the rest of the connections
+				let mut state = state.wlock()?;
+				let guard = state.guard()?;
+				while let Some(next) = ctx.pending_connections.pop_back() {
+					(**guard).nconnections.push_front(next);
+				}
+				return Ok(true);
+			}
+			let next = ctx.pending_connections.pop_front();
+			cbreak!(next.is_none());
+			let mut next = next.unwrap();
+			registered += 1;
+			let mut exported = false;
+			if Self::reject_duplicate(ctx, &mut next) {
+				continue;
+			}
+			let (handle, id, etype) = match &mut next {
+				ConnectionVariant::ServerConnection(conn) => {
+					debug!("server in process state")?;
+					let mut tx = conn.get_tx();
+					if tx.is_some() {
+						let _ = tx.as_mut().unwrap().send(Ok(()));
+					}
+					(conn.handle(), conn.id(), EventTypeIn::Read)
+				}
+				ConnectionVariant::ClientConnection(conn) if conn.migrated => {
+					Self::register_migrated(ctx, conn)?
+				}
+				ConnectionVariant::Connection(conn) if conn.migrated => {
+					Self::register_migrated(ctx, conn)?
+				}
+				ConnectionVariant::ClientConnection(conn) => {
+					debug!("client in process state")?;
+					if let Some(metrics) = &ctx.metrics {
+						wlock!(conn.write_state).metrics = Some(metrics.clone());
+					}
+					Self::init_memory(conn, config)?;
+					Self::init_capture(ctx, conn, config)?;
+					wlock!(ctx.keys).track(conn)?;
+					Self::register_loopback(conn)?;
+					let mut tx = conn.get_tx();
+					if tx.is_some() {
+						let _ = tx.as_mut().unwrap().send(Ok(()));
+					}
+					(conn.handle(), conn.id(), EventTypeIn::Read)
+				}
+				ConnectionVariant::Connection(conn) => {
+					ctx.thread_stats.accepts += 1;
+					ctx.thread_stats.incr_origin(conn.origin_id, 1, 0, 0);
+					if let Some(metrics) = &ctx.metrics {
+						metrics.incr(EvhMetric::Accepts, 1);
+						wlock!(conn.write_state).metrics = Some(metrics.clone());
+					}
+					Self::init_memory(conn, config)?;
+					Self::init_capture(ctx, conn, config)?;
+					// tracked before on_accept so that keys can be registered in it
+					wlock!(ctx.keys).track(conn)?;
+					Self::register_loopback(conn)?;
+					if let Some(millis) = config.require_data_within_millis {
+						conn.awaiting_data = true;
+						ctx.data_deadlines
+							.push_back((conn.id(), now_millis()? + millis));
+					}
+					// imported connections have no server origin. The exporting
+					// EventHandler already consumed their PROXY protocol header.
+					if config.proxy_protocol && conn.origin_id != EVH_CLIENT_ORIGIN_ID {
+						// on_accept is called once the PROXY protocol header is consumed
+						conn.proxy_header = Some(vec![]);
+						let deadline = now_millis()? + config.proxy_header_timeout_millis;
+						ctx.proxy_deadlines.push_back((conn.id(), deadline));
+					} else {
+						Self::call_on_accept(user_context, conn, callbacks)?;
+						exported = conn.exported;
+						if exported {
+							// exported in on_accept before it was registered
+							conn.end_capture()?;
+							wlock!(ctx.keys).untrack(conn.id());
+						}
+					}
+					(conn.handle(), conn.id(), EventTypeIn::Read)
+				}
+				ConnectionVariant::Wakeup(wakeup) => (wakeup.reader, wakeup.id, EventTypeIn::Read),
+				ConnectionVariant::Raw(raw) => {
+					debug!("raw in process state")?;
+					if let Some(tx) = raw.tx.as_mut() {
+						let _ = tx.send(());
+					}
+					ctx.raw_token_hash.insert(raw.token, raw.handle);
+					(raw.handle, raw.id, EventTypeIn::Raw(raw.interest))
+				}
+			};
+
+			debug!("found handle = {}, id = {}", handle, id)?;
+			if exported {
+				continue;
+			}
+			let loopback = Self::loopback(Some(&next)).is_some();
+			Self::track_connection(ctx, id, &next)?;
+			ctx.id_hash.insert(id, next);
+			ctx.handle_hash.insert(handle, id);
+			if loopback {
+				// the peer may have written data before this side was registered
+				ctx.loopback_events
+					.push(Event::new(handle, EventType::Read));
+			} else {
+				let event_in = EventIn::new(handle, etype);
+				ctx.in_events.push(event_in);
 			}
 		}
 
-		close_impl(reader)?;
-		close_impl(writer)?;
+		if let Some(accept_start) = accept_start {
+			let micros = try_into!(accept_start.elapsed().as_micros())?;
+			let max = &mut ctx.thread_stats.max_accept_micros;
+			*max = (*max).max(micros);
+		}
 
-		Ok(())
+		Ok(false)
 	}
 
-	fn process_housekeeper(
+	// a connection moved from another thread by UserContext::reassign_connection. It was
+	// already accepted and set up by that thread. Returns its (handle, id, etype).
+	fn register_migrated(
+		ctx: &mut EventHandlerContext,
+		conn: &mut Connection,
+	) -> Result<(Handle, u128, EventTypeIn), Error> {
+		conn.migrated = false;
+		Self::take_response_timers(&mut ctx.response_timers, conn)?;
+		// data queued before the move is written once the handle is writable. The handle is
+		// registered with this thread's selector, so the write interest is registered again.
+		let (etype, write_interest) =
+			match rlock!(conn.write_state).is_set(WRITE_STATE_FLAG_PENDING) {
+				true => (EventTypeIn::Write, WriteInterest::Registered),
+				false => (EventTypeIn::Read, WriteInterest::NotRegistered),
+			};
+		conn.write_interest = write_interest;
+		Ok((conn.handle(), conn.id(), etype))
+	}
+
+	// a server or client connection whose handle is already registered with this thread is
+	// not registered again. The error is returned by add_server_connection or
+	// add_client_connection and the handle is left open since it belongs to the existing
+	// registration.
+	fn reject_duplicate(ctx: &EventHandlerContext, next: &mut ConnectionVariant) -> bool {
+		let conn = match next {
+			ConnectionVariant::ServerConnection(conn) => conn,
+			ConnectionVariant::ClientConnection(conn) => conn,
+			_ => return false,
+		};
+		let handle = conn.handle();
+		let existing = match ctx.handle_hash.get(&handle) {
+			Some(existing) => *existing,
+			None => return false,
+		};
+		let text = format!(
+			"handle {} is already registered with connection id {}",
+			handle, existing
+		);
+		if let Some(tx) = conn.get_tx() {
+			let _ = tx.send(Err(err!(ErrKind::AlreadyRegistered, text)));
+		}
+		true
+	}
+
+	// close any connections that have not sent a complete PROXY protocol header in time
+	fn process_proxy_timeouts(
 		ctx: &mut EventHandlerContext,
 		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
 		user_context: &mut UserContextImpl,
-		config: &EventHandlerConfig,
 	) -> Result<(), Error> {
-		let now = SystemTime::now();
-		let now: usize = try_into!(now.duration_since(UNIX_EPOCH)?.as_millis())?;
-		if now.saturating_sub(ctx.last_housekeeping) > config.housekeeping_frequency_millis {
-			Self::call_on_housekeeper(user_context, &mut callbacks.on_housekeeper)?;
-			ctx.last_housekeeping = now;
+		if ctx.proxy_deadlines.is_empty() {
+			return Ok(());
 		}
 
-		if now.saturating_sub(ctx.last_stats_update) > config.stats_update_frequency_millis {
-			Self::update_stats(ctx, config)?;
-			ctx.last_stats_update = now;
+		let now = now_millis()?;
+		while let Some((id, deadline)) = ctx.proxy_deadlines.front().copied() {
+			cbreak!(deadline > now);
+			ctx.proxy_deadlines.pop_front();
+			let handle = match ctx.id_hash.get(&id) {
+				Some(ConnectionVariant::Connection(conn)) if conn.proxy_header.is_some() => {
+					conn.handle()
+				}
+				// the header was received or the connection is already closed
+				_ => continue,
+			};
+			let text = "PROXY protocol header not received within EvhProxyHeaderTimeoutMillis";
+			let e = err!(ErrKind::Timeout, text);
+			warn!("closing connection {}: {}", id, e)?;
+			Self::process_close(
+				handle,
+				ctx,
+				callbacks,
+				user_context,
+				CloseReason::IdleTimeout,
+			)?;
 		}
 		Ok(())
 	}
 
-	fn update_stats(
+	// close any connections that have not sent any data within EvhRequireDataWithinMillis
+	fn process_data_timeouts(
 		ctx: &mut EventHandlerContext,
-		config: &EventHandlerConfig,
+		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
+		user_context: &mut UserContextImpl,
 	) -> Result<(), Error> {
-		{
-			let mut global_stats = ctx.global_stats.wlock()?;
-			let guard = global_stats.guard()?;
-			(**guard).stats.incr_stats(&ctx.thread_stats);
-			(**guard).update_counter += 1;
-			if (**guard).update_counter >= config.threads {
-				if (**guard).tx.is_some() {
-					let tx = &(**guard).tx.as_mut().unwrap();
-					tx.send(())?;
-				}
-
-				(**guard).update_counter = 0;
-			}
+		if ctx.data_deadlines.is_empty() {
+			return Ok(());
 		}
 
-		ctx.thread_stats.reset();
+		let now = now_millis()?;
+		while let Some((id, deadline)) = ctx.data_deadlines.front().copied() {
+			cbreak!(deadline > now);
+			ctx.data_deadlines.pop_front();
+			let handle = match ctx.id_hash.get(&id) {
+				Some(ConnectionVariant::Connection(conn)) if conn.awaiting_data => conn.handle(),
+				// data was received or the connection is already closed
+				_ => continue,
+			};
+			debug!(
+				"closing connection {}: no data within EvhRequireDataWithinMillis",
+				id
+			)?;
+			Self::process_close(
+				handle,
+				ctx,
+				callbacks,
+				user_context,
+				CloseReason::IdleTimeout,
+			)?;
+		}
 		Ok(())
 	}
 
-	fn process_state(
-		state: &mut Box<dyn LockBox<EventHandlerState>>,
+	// re-arm the write interest of the delayed or rate limited connections that are due
+	fn process_write_timers(
 		ctx: &mut EventHandlerContext,
-		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
-		user_context: &mut UserContextImpl,
-		config: &EventHandlerConfig,
 		debug_info: &DebugInfo,
-	) -> Result<bool, Error> {
-		if debug_info.is_internal_panic() {
-			return Err(err!(ErrKind::Test, "internal panic"));
+	) -> Result<(), Error> {
+		if ctx.write_timers.is_empty() {
+			return Ok(());
 		}
 
-		debug!("in process state tid={}", ctx.tid)?;
-
-		Self::process_write_pending(ctx, callbacks, user_context, state)?;
-		Self::process_housekeeper(ctx, callbacks, user_context, config)?;
+		let now = now_millis()?;
+		while let Some(Reverse((at, id))) = ctx.write_timers.peek().copied() {
+			cbreak!(at > now);
+			ctx.write_timers.pop();
+			let handle = match ctx.id_hash.get(&id) {
+				Some(ConnectionVariant::Connection(conn)) => conn.handle(),
+				Some(ConnectionVariant::ClientConnection(conn)) => conn.handle(),
+				// the connection is already closed
+				_ => continue,
+			};
+			Self::rearm_write_interest(ctx, handle, debug_info);
+		}
+		Ok(())
+	}
 
-		let mut state = state.wlock()?;
-		let guard = state.guard()?;
-		debug!("guard.stop={}", (**guard).stop)?;
-		if (**guard).stop {
-			debug!("stopping thread")?;
-			Self::close_handles(ctx, &(**guard).nconnections, callbacks)?;
-			Ok(true)
-		} else {
-			debug!("nconnections.size={}", (**guard).nconnections.len())?;
-			loop {
-				let next = (**guard).nconnections.pop_front();
-				cbreak!(next.is_none());
-				let mut next = next.unwrap();
-				let (handle, id) = match &mut next {
-					ConnectionVariant::ServerConnection(conn) => {
-						debug!("server in process state")?;
-						let mut tx = conn.get_tx();
-						if tx.is_some() {
-							let _ = tx.as_mut().unwrap().send(());
-						}
-						(conn.handle(), conn.id())
-					}
-					ConnectionVariant::ClientConnection(conn) => {
-						debug!("client in process state")?;
-						let mut tx = conn.get_tx();
-						if tx.is_some() {
-							let _ = tx.as_mut().unwrap().send(());
-						}
-						(conn.handle(), conn.id())
-					}
-					ConnectionVariant::Connection(conn) => {
-						ctx.thread_stats.accepts += 1;
-						Self::call_on_accept(user_context, conn, &mut callbacks.on_accept)?;
-						(conn.handle(), conn.id())
-					}
-					ConnectionVariant::Wakeup(wakeup) => (wakeup.reader, wakeup.id),
-				};
+	// fail the requests whose response did not arrive before their timeout
+	fn process_response_timers(ctx: &mut EventHandlerContext) -> Result<(), Error> {
+		if ctx.response_timers.is_empty() {
+			return Ok(());
+		}
 
-				debug!("found handle = {}, id = {}", handle, id)?;
-				ctx.id_hash.insert(id, next);
-				ctx.handle_hash.insert(handle, id);
-				let event_in = EventIn::new(handle, EventTypeIn::Read);
-				ctx.in_events.push(event_in);
+		let now = now_millis()?;
+		while let Some(Reverse((at, id, correlation_id))) = ctx.response_timers.peek().copied() {
+			cbreak!(at > now);
+			ctx.response_timers.pop();
+			let write_state = match ctx.id_hash.get_mut(&id) {
+				Some(ConnectionVariant::Connection(conn)) => &mut conn.write_state,
+				Some(ConnectionVariant::ClientConnection(conn)) => &mut conn.write_state,
+				// the connection is already closed
+				_ => continue,
+			};
+			let mut write_state = write_state.wlock()?;
+			let guard = write_state.guard()?;
+			// the correlation_id may have been reused by a later request
+			let expired = match guard.responses.get(&correlation_id) {
+				Some(pending) => pending.deadline <= now,
+				None => false,
+			};
+			if expired {
+				if let Some(pending) = guard.responses.remove(&correlation_id) {
+					let text = format!("no response for request {} before timeout", correlation_id);
+					pending.slot.complete(Err(err!(ErrKind::Timeout, text)));
+				}
 			}
+		}
+		Ok(())
+	}
 
-			Ok(false)
+	// move the timeouts of the requests written since the last loop into the context
+	fn take_response_timers(
+		response_timers: &mut BinaryHeap<Reverse<(usize, u128, u64)>>,
+		conn: &mut Connection,
+	) -> Result<(), Error> {
+		let mut write_state = conn.write_state.wlock()?;
+		let guard = write_state.guard()?;
+		for (at, correlation_id) in guard.response_timers.drain(..) {
+			response_timers.push(Reverse((at, conn.id, correlation_id)));
 		}
+		Ok(())
 	}
 
 	fn process_write_pending(
@@ -1604,9 +6009,11 @@ where
 		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
 		user_context: &mut UserContextImpl,
 		state: &mut Box<dyn LockBox<EventHandlerState>>,
+		debug_info: &DebugInfo,
 	) -> Result<(), Error> {
 		debug!("in process write pending")?;
 		let mut ids = vec![];
+		let mut read_ids = vec![];
 		{
 			let mut state = state.wlock()?;
 			let guard = state.guard()?;
@@ -1615,11 +6022,23 @@ where
 				let id = (**guard).write_queue.pop_front();
 				ids.push(id.unwrap());
 			}
+			read_ids.extend(guard.read_queue.drain(..));
 		}
 
 		ctx.trigger_on_read_list.clear();
+		ctx.loopback_events.clear();
+		for id in read_ids {
+			let handle = match ctx.id_hash.get(&id) {
+				Some(ConnectionVariant::Connection(conn)) => conn.handle(),
+				Some(ConnectionVariant::ClientConnection(conn)) => conn.handle(),
+				// the connection is already closed
+				_ => continue,
+			};
+			ctx.loopback_events
+				.push(Event::new(handle, EventType::Read));
+		}
 		for id in ids {
-			Self::process_write_id(ctx, id, callbacks, user_context)?;
+			Self::process_write_id(ctx, id, callbacks, user_context, debug_info)?;
 		}
 
 		Ok(())
@@ -1630,6 +6049,7 @@ where
 		id: u128,
 		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
 		user_context: &mut UserContextImpl,
+		debug_info: &DebugInfo,
 	) -> Result<(), Error> {
 		let mut close_list = vec![];
 		let mut conn = ctx.id_hash.get_mut(&id);
@@ -1639,56 +6059,162 @@ where
 				ConnectionVariant::ServerConnection(_conn) => {}
 				ConnectionVariant::ClientConnection(conn) => {
 					let handle = conn.handle();
+					Self::take_response_timers(&mut ctx.response_timers, conn)?;
 					let (close, trigger_on_read, pending) = Self::write_conn(conn)?;
 					// if data is pending complete the write first
 					if close && !pending {
-						close_list.push(conn.handle());
+						let e = conn.write_handle()?.take_transform_error()?;
+						close_list.push((conn.handle(), conn.local_close_reason(e)));
 					}
-					if trigger_on_read && !close {
-						ctx.trigger_on_read_list.push(conn.handle());
+					if !close {
+						for _ in 0..trigger_on_read {
+							ctx.trigger_on_read_list.push(handle);
+						}
 					}
 
 					if pending {
-						let evt = EventIn::new(handle, EventTypeIn::Write);
-						ctx.in_events.push(evt);
+						Self::register_write_interest(ctx, handle, debug_info);
 					}
 				}
 				ConnectionVariant::Connection(conn) => {
 					let handle = conn.handle();
+					Self::take_response_timers(&mut ctx.response_timers, conn)?;
 					let (close, trigger_on_read, pending) = Self::write_conn(conn)?;
 
 					// if data is pending complete the write first
 					if close && !pending {
-						close_list.push(conn.handle());
+						let e = conn.write_handle()?.take_transform_error()?;
+						close_list.push((conn.handle(), conn.local_close_reason(e)));
 					}
-					if trigger_on_read {
-						ctx.trigger_on_read_list.push(conn.handle());
+					for _ in 0..trigger_on_read {
+						ctx.trigger_on_read_list.push(handle);
 					}
 					if pending {
-						let evt = EventIn::new(handle, EventTypeIn::Write);
-						ctx.in_events.push(evt);
+						Self::register_write_interest(ctx, handle, debug_info);
 					}
 				}
 				ConnectionVariant::Wakeup(_wakeup) => {}
+				ConnectionVariant::Raw(_raw) => {}
 			}
 		} else {
 			debug!("none1 in process_write_id")?;
 		}
 
-		for handle in close_list {
-			Self::process_close(handle, ctx, callbacks, user_context)?;
+		for (handle, reason) in close_list {
+			Self::process_close(handle, ctx, callbacks, user_context, reason)?;
+		}
+		Ok(())
+	}
+
+	// loopback connections are not registered with the selector, their write events are
+	// processed after the other events of this loop
+	fn push_write_event(ctx: &mut EventHandlerContext, handle: Handle) {
+		let loopback = match ctx.handle_hash.get(&handle) {
+			Some(id) => Self::loopback(ctx.id_hash.get(id)).is_some(),
+			None => false,
+		};
+		if loopback {
+			ctx.loopback_events
+				.push(Event::new(handle, EventType::Write));
+		} else {
+			ctx.in_events.push(EventIn::new(handle, EventTypeIn::Write));
+		}
+	}
+
+	// write interest is only registered while data is pending, so a connection whose writes
+	// complete immediately never changes its registration. Returns whether an event was queued.
+	fn register_write_interest(
+		ctx: &mut EventHandlerContext,
+		handle: Handle,
+		debug_info: &DebugInfo,
+	) -> bool {
+		let prev = Self::write_interest_mut(ctx, handle)
+			.map(|interest| std::mem::replace(interest, WriteInterest::Registered));
+		match prev {
+			Some(WriteInterest::Registered) => false,
+			Some(WriteInterest::NotRegistered) => {
+				debug_info.count_write_interest(WriteInterest::Registered);
+				ctx.in_events.push(EventIn::new(handle, EventTypeIn::Write));
+				true
+			}
+			None => {
+				Self::push_write_event(ctx, handle);
+				true
+			}
+		}
+	}
+
+	// like register_write_interest, but an interest that is already registered is registered
+	// again so that the handle is reported even if it did not become writable since the last
+	// event
+	fn rearm_write_interest(ctx: &mut EventHandlerContext, handle: Handle, debug_info: &DebugInfo) {
+		if !Self::register_write_interest(ctx, handle, debug_info) {
+			Self::push_write_event(ctx, handle);
+		}
+	}
+
+	// drop the write interest once the pending data has been written. Returns whether an
+	// event was queued.
+	fn deregister_write_interest(
+		ctx: &mut EventHandlerContext,
+		handle: Handle,
+		debug_info: &DebugInfo,
+	) -> bool {
+		let prev = Self::write_interest_mut(ctx, handle)
+			.map(|interest| std::mem::replace(interest, WriteInterest::NotRegistered));
+		if prev != Some(WriteInterest::Registered) {
+			return false;
+		}
+		debug_info.count_write_interest(WriteInterest::NotRegistered);
+		ctx.in_events
+			.push(EventIn::new(handle, EventTypeIn::DeregisterWrite));
+		true
+	}
+
+	// the write interest of a connection that is registered with the selector
+	fn write_interest_mut(
+		ctx: &mut EventHandlerContext,
+		handle: Handle,
+	) -> Option<&mut WriteInterest> {
+		let conn = match ctx.id_hash.get_mut(ctx.handle_hash.get(&handle)?)? {
+			ConnectionVariant::Connection(conn) => conn,
+			ConnectionVariant::ClientConnection(conn) => conn,
+			_ => return None,
+		};
+		match conn.loopback {
+			Some(_) => None,
+			None => Some(&mut conn.write_interest),
+		}
+	}
+
+	fn loopback(conn: Option<&ConnectionVariant>) -> Option<&Loopback> {
+		match conn {
+			Some(ConnectionVariant::Connection(conn)) => conn.loopback.as_ref(),
+			Some(ConnectionVariant::ClientConnection(conn)) => conn.loopback.as_ref(),
+			_ => None,
+		}
+	}
+
+	// the peer wakes this thread through its read or write queue
+	fn register_loopback(conn: &Connection) -> Result<(), Error> {
+		if let (Some(loopback), Some(wakeup), Some(state)) =
+			(&conn.loopback, &conn.wakeup, &conn.state)
+		{
+			loopback.register(LoopbackWaker {
+				id: conn.id(),
+				wakeup: wakeup.clone(),
+				state: state.clone(),
+			})?;
 		}
 		Ok(())
 	}
 
-	fn write_conn(conn: &mut Connection) -> Result<(bool, bool, bool), Error> {
+	// returns (close, number of trigger_on_read calls, pending)
+	fn write_conn(conn: &mut Connection) -> Result<(bool, usize, bool), Error> {
 		let mut write_handle = conn.write_handle()?;
 		let ret1 = write_handle.is_set(WRITE_STATE_FLAG_CLOSE)?;
-		let ret2 = write_handle.is_set(WRITE_STATE_FLAG_TRIGGER_ON_READ)?;
+		let ret2 = write_handle.take_trigger_on_read_count()?;
 		let ret3 = write_handle.is_set(WRITE_STATE_FLAG_PENDING)?;
-		if ret2 {
-			write_handle.unset_flag(WRITE_STATE_FLAG_TRIGGER_ON_READ)?;
-		}
 		Ok((ret1, ret2, ret3))
 	}
 
@@ -1709,27 +6235,46 @@ where
 		let list_len = ctx.trigger_on_read_list.len();
 		loop {
 			cbreak!(ctx.trigger_itt == list_len);
+			cbreak!(Self::is_replaced(ctx));
 
 			let handle = ctx.trigger_on_read_list[ctx.trigger_itt];
-			let id = ctx.handle_hash.get(&handle);
-			if id.is_some() {
-				let id = id.unwrap();
-				let mut conn = ctx.id_hash.get_mut(&id);
-				if conn.is_some() {
-					match conn.as_mut().unwrap() {
-						ConnectionVariant::Connection(conn) => {
-							Self::call_on_read(u, conn, &mut callbacks.on_read)?
-						}
-						ConnectionVariant::ClientConnection(conn) => {
-							Self::call_on_read(u, conn, &mut callbacks.on_read)?
-						}
-						_ => warn!("unexpected Conection variant for trigger_on_read")?,
+			let (mut close, mut read_count, mut read_sum) = (None, 0, 0);
+			let mut origin_id = EVH_CLIENT_ORIGIN_ID;
+			let mut exported = false;
+			match ctx.handle_hash.get(&handle) {
+				Some(id) => match ctx.id_hash.get_mut(id) {
+					Some(ConnectionVariant::Connection(conn)) => {
+						origin_id = conn.origin_id;
+						(close, read_count, read_sum) =
+							Self::process_trigger_on_read(conn, config, callbacks, u, d)?;
+						Self::track_buffered(&ctx.watch, conn)?;
+						exported = conn.exported;
 					}
-				} else {
-					warn!("none in trigger_on_read1")?;
-				}
-			} else {
-				warn!("none in trigger_on_read2")?;
+					Some(ConnectionVariant::ClientConnection(conn)) => {
+						(close, read_count, read_sum) =
+							Self::process_trigger_on_read(conn, config, callbacks, u, d)?;
+						Self::track_buffered(&ctx.watch, conn)?;
+					}
+					Some(_) => evh_log!(
+						config,
+						Warn,
+						"unexpected Conection variant for trigger_on_read"
+					)?,
+					None => evh_log!(config, Warn, "none in trigger_on_read1")?,
+				},
+				None => evh_log!(config, Warn, "none in trigger_on_read2")?,
+			}
+			ctx.thread_stats.reads += read_count;
+			ctx.thread_stats.bytes_read += read_sum;
+			Self::record_reads(ctx, read_count, read_sum)?;
+			if read_count > 0 {
+				ctx.thread_stats
+					.incr_origin(origin_id, 0, read_count, read_sum);
+			}
+			if exported {
+				Self::process_export(handle, ctx, u)?;
+			} else if let Some(reason) = close {
+				Self::process_close(handle, ctx, callbacks, u, reason)?;
 			}
 			ctx.trigger_itt += 1;
 		}
@@ -1738,11 +6283,32 @@ where
 		debug!("events to process = {}", ctx.ret_event_count)?;
 		loop {
 			cbreak!(ctx.ret_event_itt == ctx.ret_event_count);
+			cbreak!(Self::is_replaced(ctx));
 
 			debug!("proc event = {:?}", ctx.ret_events[ctx.ret_event_itt])?;
 			let h = ctx.ret_events[ctx.ret_event_itt].handle;
+
+			let raw = match ctx.handle_hash.get(&h) {
+				Some(id) => match ctx.id_hash.get(id) {
+					Some(ConnectionVariant::Raw(raw)) => Some((raw.token, raw.interest)),
+					_ => None,
+				},
+				None => None,
+			};
+			if let Some((token, interest)) = raw {
+				let etype = ctx.ret_events[ctx.ret_event_itt].etype;
+				// only report the readiness that was requested for this handle
+				let readable = etype != EventType::Write && interest != Interest::Write;
+				let writable = etype != EventType::Read && interest != Interest::Read;
+				let cb = &mut callbacks.on_raw_event;
+				Self::call_on_raw_event(cb, token, readable, writable)?;
+				update_ctx(ctx, h, EventTypeIn::Raw(interest))?;
+				ctx.ret_event_itt += 1;
+				continue;
+			}
+
 			let mut need_read_update = false;
-			let mut need_write_update = false;
+			let mut deregistered = false;
 
 			if ctx.ret_events[ctx.ret_event_itt].etype == EventType::Read
 				|| ctx.ret_events[ctx.ret_event_itt].etype == EventType::ReadWrite
@@ -1754,17 +6320,150 @@ where
 			if ctx.ret_events[ctx.ret_event_itt].etype == EventType::Write
 				|| ctx.ret_events[ctx.ret_event_itt].etype == EventType::ReadWrite
 			{
-				need_write_update = Self::process_write_event(config, ctx, callbacks, h, u)?;
+				let (open, pending) = Self::process_write_event(config, ctx, callbacks, h, u, d)?;
+				// write interest is only kept while data is pending. Once the write
+				// completes, it is deregistered, which also re-arms the read interest.
+				if open && !pending {
+					deregistered = Self::deregister_write_interest(ctx, h, d);
+				}
+				need_read_update = need_read_update || open;
 			}
 
-			if need_write_update {
+			// update_ctx is a no-op on the edge triggered backends. The DeregisterWrite event
+			// already re-arms the read interest.
+			let write_registered = Self::write_interest_mut(ctx, h).map(|interest| *interest)
+				== Some(WriteInterest::Registered);
+			if write_registered {
 				update_ctx(ctx, h, EventTypeIn::Write)?;
-			} else if need_read_update {
+			} else if need_read_update && !deregistered {
 				update_ctx(ctx, h, EventTypeIn::Read)?;
 			}
+			// an injected WouldBlock is not followed by a new edge so the handle is
+			// re-registered, which reports it again if it is still ready
+			if d.take_fault(h) && ctx.handle_hash.contains_key(&h) {
+				let etype = if write_registered {
+					EventTypeIn::Write
+				} else {
+					EventTypeIn::Read
+				};
+				ctx.in_events.push(EventIn::new(h, etype));
+			}
 			ctx.ret_event_itt += 1;
 		}
 
+		// then the events of the loopback connections
+		loop {
+			cbreak!(ctx.loopback_itt >= ctx.loopback_events.len());
+			cbreak!(Self::is_replaced(ctx));
+
+			let event = ctx.loopback_events[ctx.loopback_itt];
+			// the connection may have been closed by an earlier event
+			if ctx.handle_hash.contains_key(&event.handle) {
+				let h = event.handle;
+				if event.etype == EventType::Read {
+					Self::process_read_event(config, ctx, callbacks, h, state, u, d)?;
+				} else {
+					Self::process_write_event(config, ctx, callbacks, h, u, d)?;
+				}
+			}
+			ctx.loopback_itt += 1;
+		}
+
+		Self::process_resumed_reads(ctx, state, u)?;
+		Self::process_reassignments(config, ctx, state, u)
+	}
+
+	// read from the connections that were paused by Connection::set_max_unread_bytes again.
+	// Like resume_memory_paused, their handles are registered again. The loopback events of
+	// this loop were already processed, so loopback connections are queued like a
+	// notification from their peer.
+	fn process_resumed_reads(
+		ctx: &mut EventHandlerContext,
+		state: &mut Array<Box<dyn LockBox<EventHandlerState>>>,
+		user_context: &mut UserContextImpl,
+	) -> Result<(), Error> {
+		for id in std::mem::take(&mut user_context.resumed_reads) {
+			let conn = match ctx.id_hash.get(&id) {
+				Some(ConnectionVariant::Connection(conn)) => conn,
+				Some(ConnectionVariant::ClientConnection(conn)) => conn,
+				_ => continue,
+			};
+			let handle = conn.handle();
+			if conn.loopback.is_some() {
+				wlock!(state[ctx.tid]).read_queue.push_back(id);
+				ctx.wakeups[ctx.tid].clone().wakeup()?;
+			} else {
+				let etype = match conn.write_interest {
+					WriteInterest::Registered => EventTypeIn::Write,
+					WriteInterest::NotRegistered => EventTypeIn::Read,
+				};
+				ctx.in_events.push(EventIn::new(handle, etype));
+			}
+		}
+		Ok(())
+	}
+
+	// move the connections requested with UserContext::reassign_connection to their new
+	// threads. Like process_export, the handle is deregistered but not closed. The receiving
+	// thread registers it in process_state.
+	fn process_reassignments(
+		config: &EventHandlerConfig,
+		ctx: &mut EventHandlerContext,
+		state: &mut Array<Box<dyn LockBox<EventHandlerState>>>,
+		mut user_context: &mut UserContextImpl,
+	) -> Result<(), Error> {
+		for (id, thread_hint) in std::mem::take(&mut user_context.reassignments) {
+			let tid = thread_hint % config.threads;
+			let movable = match ctx.id_hash.get(&id) {
+				Some(ConnectionVariant::Connection(conn))
+				| Some(ConnectionVariant::ClientConnection(conn)) => conn.loopback.is_none() && !conn.exported,
+				_ => false,
+			};
+			if tid == ctx.tid || !movable {
+				continue;
+			}
+			let mut next = ctx.id_hash.remove(&id).unwrap();
+			let conn = match &mut next {
+				ConnectionVariant::Connection(conn) => conn,
+				ConnectionVariant::ClientConnection(conn) => conn,
+				_ => continue,
+			};
+			let handle = conn.handle();
+			debug!("reassigning handle={},id={} to thread {}", handle, id, tid)?;
+			ctx.handle_hash.remove(&handle);
+			if let Some(mut connections) = ctx.watch.as_ref().and_then(|w| w.connections.clone()) {
+				wlock!(connections).remove(&id);
+			}
+			user_context.clear_through(conn.get_last_slab(), conn)?;
+			conn.slab_quota_paused = false;
+			conn.memory_paused = false;
+			ctx.in_events.retain(|event| event.handle != handle);
+			deregister_raw_impl(handle, ctx)?;
+
+			// the timeouts of pending responses are picked up again by the new thread
+			let mut timers = vec![];
+			ctx.response_timers
+				.retain(|Reverse((at, timer_id, correlation_id))| {
+					if *timer_id == id {
+						timers.push((*at, *correlation_id));
+					}
+					*timer_id != id
+				});
+			let wakeup = ctx.wakeups[tid].clone();
+			let owner = (wakeup.clone(), state[tid].clone());
+			{
+				let mut write_state = conn.write_state.wlock()?;
+				let guard = write_state.guard()?;
+				guard.response_timers.extend(timers);
+				guard.owner = Some(owner);
+			}
+			conn.wakeup = Some(wakeup);
+			conn.state = Some(state[tid].clone());
+			conn.migrated = true;
+			wlock!(state[tid]).nconnections.push_back(next);
+			ctx.wakeups[tid].wakeup()?;
+			ctx.thread_stats.reassignments += 1;
+		}
 		Ok(())
 	}
 
@@ -1779,23 +6478,22 @@ where
 	) -> Result<bool, Error> {
 		let mut ret = false;
 		let mut accepted = vec![];
-		let mut close = false;
+		let mut close = None;
 		let mut read_count = 0;
 		let mut read_sum = 0;
+		let mut origin_id = EVH_CLIENT_ORIGIN_ID;
+		let mut exported = false;
 		debug!("process read event= {}", handle)?;
-		let id = ctx.handle_hash.get(&handle);
-		if id.is_some() {
-			let id = id.unwrap();
-			let mut conn = ctx.id_hash.get_mut(&id);
-			if conn.is_some() {
-				let conn = conn.as_mut().unwrap();
-				match conn {
+		if let Some(id) = ctx.handle_hash.get(&handle) {
+			// a missing connection is logged and the event is skipped
+			match Self::read_event_conn(&mut ctx.id_hash, *id) {
+				Ok(conn) => match conn {
 					ConnectionVariant::ServerConnection(conn) => {
-						Self::process_accept(conn, &mut accepted, debug_info, callbacks)?;
+						Self::process_accept(config, conn, &mut accepted, debug_info, callbacks)?;
 						ret = true;
 					}
 					ConnectionVariant::ClientConnection(conn) => {
-						if !conn.write_final {
+						if !conn.write_final && !conn.read_eof {
 							(close, read_count, read_sum) = Self::process_read(
 								conn,
 								config,
@@ -1803,11 +6501,13 @@ where
 								user_context,
 								debug_info,
 							)?;
-							ret = !close;
+							Self::track_buffered(&ctx.watch, conn)?;
+							ret = close.is_none();
 						}
 					}
 					ConnectionVariant::Connection(conn) => {
-						if !conn.write_final {
+						origin_id = conn.origin_id;
+						if !conn.write_final && !conn.read_eof {
 							(close, read_count, read_sum) = Self::process_read(
 								conn,
 								config,
@@ -1815,8 +6515,10 @@ where
 								user_context,
 								debug_info,
 							)?;
-							ret = !close;
+							Self::track_buffered(&ctx.watch, conn)?;
+							ret = close.is_none();
 						}
+						exported = conn.exported;
 					}
 					ConnectionVariant::Wakeup(_wakeup) => {
 						let mut buf = [0u8; 1000];
@@ -1824,7 +6526,7 @@ where
 							let rlen = match do_wakeup_read_impl(handle, &mut buf, debug_info) {
 								Ok(rlen) => rlen,
 								Err(e) => {
-									warn!("wakeup read_impl err: {}", e)?;
+									evh_log!(config, Warn, "wakeup read_impl err: {}", e)?;
 									None
 								}
 							};
@@ -1833,50 +6535,98 @@ where
 						}
 						ret = true;
 					}
-				}
-			} else {
-				warn!("none1")?;
+					ConnectionVariant::Raw(_raw) => {}
+				},
+				Err(e) => evh_log!(config, Warn, "{}", e)?,
 			}
 		} else {
 			// connection has been removed from the hashes and shutdown, now a read occurs
 			// and we can close the underlying handle
 			close_impl(handle)?;
 		}
-		debug!("close was {}", close)?;
-		if close {
+		debug!("close was {:?}", close)?;
+		if exported {
+			Self::process_export(handle, ctx, user_context)?;
+			ret = false;
+		} else if let Some(reason) = close {
 			debug!("closing handle {}", handle)?;
-			Self::process_close(handle, ctx, callbacks, user_context)?;
+			Self::process_close(handle, ctx, callbacks, user_context, reason)?;
 		}
 		ctx.thread_stats.reads += read_count;
 		ctx.thread_stats.bytes_read += read_sum;
+		Self::record_reads(ctx, read_count, read_sum)?;
+		if read_count > 0 {
+			ctx.thread_stats
+				.incr_origin(origin_id, 0, read_count, read_sum);
+		}
 
-		Self::process_accepted_connections(accepted, config, state, &mut ctx.wakeups, debug_info)?;
+		ctx.thread_stats.memory_accepts_rejected += Self::process_accepted_connections(
+			accepted,
+			config,
+			state,
+			&mut ctx.wakeups,
+			&ctx.ids,
+			debug_info,
+		)?;
 		Ok(ret)
 	}
 
+	// the connection with the specified id, which a registered handle belongs to
+	fn read_event_conn(
+		id_hash: &mut HashMap<u128, ConnectionVariant>,
+		id: u128,
+	) -> Result<&mut ConnectionVariant, Error> {
+		let conn = try_opt!(
+			id_hash.get_mut(&id),
+			ErrKind::IllegalState,
+			"read event for id {} without a connection",
+			id
+		);
+		Ok(conn)
+	}
+
+	// returns the number of connections that were rejected due to MemoryPressure::RejectAccepts
 	fn process_accepted_connections(
 		accepted: Vec<(Handle, u128)>,
 		config: &EventHandlerConfig,
 		state: &mut Array<Box<dyn LockBox<EventHandlerState>>>,
 		wakeups: &mut Array<Wakeup>,
+		ids: &ConnectionIds,
 		debug_info: &DebugInfo,
-	) -> Result<(), Error> {
+	) -> Result<usize, Error> {
 		debug!("accepted connections = {:?}", accepted)?;
+		let reject = match &config.memory {
+			Some(memory) => memory.pressure() >= MemoryPressure::RejectAccepts,
+			None => false,
+		};
+		let mut rejected = 0;
 		for a in accepted {
+			if reject {
+				close_impl(a.0)?;
+				rejected += 1;
+				continue;
+			}
+			if let Err(e) = apply_buffer_sizes(a.0, config) {
+				evh_log!(config, Warn, "closing accepted handle {}: {}", a.0, e)?;
+				close_impl(a.0)?;
+				continue;
+			}
 			let accept_usize: usize = try_into!(a.0)?;
 			let tid = accept_usize % config.threads;
 			let wakeup = Some(wakeups[tid].clone());
 			let cstate = Some(state[tid].clone());
 			let ctype = ConnectionType::Connection;
 			let origin_id = a.1;
-			let connection = Connection::new(
+			let mut connection = Connection::new(
 				a.0,
 				wakeup,
 				cstate,
 				ctype,
 				debug_info.clone(),
+				ids.next(),
 				Some(origin_id),
 			)?;
+			connection.evh_instance_id = ids.instance_id;
 
 			{
 				let mut state = state[tid].wlock()?;
@@ -1887,7 +6637,43 @@ where
 
 			wakeups[tid].wakeup()?;
 		}
-		Ok(())
+		Ok(rejected)
+	}
+
+	fn allocate_read_slab(user_context: &mut UserContextImpl) -> Result<SlabMut<'_>, Error> {
+		let used = user_context.read_slabs.slab_count()? - user_context.read_slabs.free_count()?;
+		let slab = user_context.read_slabs.allocate()?;
+		if used >= user_context.read_slab_high_water {
+			user_context.read_slab_high_water = used + 1;
+		}
+		Ok(slab)
+	}
+
+	// send EvhOutOfSlabsMessage and return the reason to close the connection with, if it
+	// must be closed right away
+	fn out_of_slabs(
+		conn: &mut Connection,
+		config: &EventHandlerConfig,
+	) -> Result<Option<CloseReason>, Error> {
+		let close = match Self::write_final(conn, config) {
+			Ok(true) => Some(CloseReason::OutOfSlabs),
+			Ok(false) => None,
+			Err(e) => {
+				evh_log!(config, Warn, "write_final generated error: {}", e)?;
+				Some(CloseReason::OutOfSlabs)
+			}
+		};
+		conn.write_final = true;
+		Ok(close)
+	}
+
+	// true if the connection may not allocate another read slab due to
+	// EvhMaxSlabsPerConnection
+	fn slab_quota_exceeded(conn: &Connection, config: &EventHandlerConfig) -> bool {
+		match config.max_slabs_per_connection {
+			Some(max) => conn.owned_slabs >= max,
+			None => false,
+		}
 	}
 
 	fn write_final(conn: &mut Connection, config: &EventHandlerConfig) -> Result<bool, Error> {
@@ -1901,37 +6687,97 @@ where
 		}
 	}
 
+	// deliver any data that has already been received on this connection and then execute
+	// the synthetic on_read callback requested by trigger_on_read
+	fn process_trigger_on_read(
+		conn: &mut Connection,
+		config: &EventHandlerConfig,
+		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
+		user_context: &mut UserContextImpl,
+		debug_info: &DebugInfo,
+	) -> Result<(Option<CloseReason>, usize, u128), Error> {
+		let (close, read_count, read_sum) = if conn.write_final || conn.read_eof {
+			(None, 0, 0)
+		} else {
+			Self::process_read(conn, config, callbacks, user_context, debug_info)?
+		};
+		if close.is_none() && !conn.exported && conn.accepted {
+			Self::call_on_read(config, user_context, conn, callbacks, true)?;
+		}
+		Ok((close, read_count, read_sum))
+	}
+
 	fn process_read(
 		conn: &mut Connection,
 		config: &EventHandlerConfig,
 		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
 		user_context: &mut UserContextImpl,
 		debug_info: &DebugInfo,
-	) -> Result<(bool, usize, u128), Error> {
+	) -> Result<(Option<CloseReason>, usize, u128), Error> {
 		debug!("in process_read")?;
-		let mut close = false;
+		let mut close = None;
 		let mut read_count = 0;
 		let mut read_sum = 0u128;
 		let handle = conn.handle();
+
+		if conn.proxy_header.is_some() {
+			let reason = Self::process_proxy_header(conn, debug_info)?;
+			if reason.is_some() || conn.proxy_header.is_some() {
+				return Ok((reason, 0, 0));
+			}
+		}
+		if !conn.accepted {
+			// the data is left in the socket until on_accept has returned
+			Self::call_on_accept(user_context, conn, callbacks)?;
+		}
+		if conn.memory_paused {
+			// the data is left in the socket until the memory pressure is relieved
+			return Ok((None, 0, 0));
+		}
 		// loop through and read as many slabs as we can
 		while TRUE {
+			// a callback exported the connection. process_export removes it.
+			cbreak!(conn.exported);
+			// the rest of the data is left in the socket until the handler clears data
+			if conn.max_unread_bytes > 0 && conn.unread_bytes >= conn.max_unread_bytes {
+				conn.unread_paused = true;
+				cbreak!(true);
+			}
 			let last_slab = conn.get_last_slab();
 			let slab_offset = conn.get_slab_offset();
 			let len = config.read_slab_size;
 			let read_slab_next_offset = len.saturating_sub(4);
+			let need_slab = last_slab >= u32::MAX as usize || slab_offset == read_slab_next_offset;
+			if need_slab && Self::slab_quota_exceeded(conn, config) {
+				if config.slab_quota_close {
+					evh_log!(config, Warn, "slab quota exceeded by {}", conn.id())?;
+					close = Self::out_of_slabs(conn, config)?;
+					cbreak!(true);
+				}
+				if !conn.slab_quota_paused {
+					conn.slab_quota_paused = true;
+					let cb = &mut callbacks.on_slab_quota;
+					Self::call_on_slab_quota(user_context, conn, cb)?;
+				}
+				// the callback may have freed slabs, otherwise the rest of the data is left
+				// in the socket
+				cbreak!(Self::slab_quota_exceeded(conn, config));
+				continue;
+			}
+			if need_slab {
+				conn.slab_quota_paused = false;
+			}
 			let mut slab = if last_slab >= u32::MAX as usize {
-				let slab = match user_context.read_slabs.allocate() {
+				let slab = match Self::allocate_read_slab(user_context) {
 					Ok(slab) => Some(slab),
 					Err(e) => {
-						warn!("cannot allocate any more slabs1 due to: {}", e)?;
-						close = match Self::write_final(conn, config) {
-							Ok(close) => close,
-							Err(e) => {
-								warn!("write_final generated error: {}", e)?;
-								true
-							}
-						};
-						conn.write_final = true;
+						evh_log!(
+							config,
+							Warn,
+							"cannot allocate any more slabs1 due to: {}",
+							e
+						)?;
+						close = Self::out_of_slabs(conn, config)?;
 						None
 					}
 				};
@@ -1941,6 +6787,7 @@ where
 
 				let id = slab.id();
 				debug!("allocate a slab1 {}", id)?;
+				conn.owned_slabs += 1;
 				// initialize connection with values
 				conn.set_last_slab(id);
 				conn.set_first_slab(id);
@@ -1951,18 +6798,16 @@ where
 					.clone_from_slice(&u32::MAX.to_be_bytes());
 				slab
 			} else if slab_offset == read_slab_next_offset {
-				let slab = match user_context.read_slabs.allocate() {
+				let slab = match Self::allocate_read_slab(user_context) {
 					Ok(slab) => Some(slab),
 					Err(e) => {
-						warn!("cannot allocate any more slabs2 due to: {}", e)?;
-						close = match Self::write_final(conn, config) {
-							Ok(close) => close,
-							Err(e) => {
-								warn!("write_final generated error: {}", e)?;
-								true
-							}
-						};
-						conn.write_final = true;
+						evh_log!(
+							config,
+							Warn,
+							"cannot allocate any more slabs2 due to: {}",
+							e
+						)?;
+						close = Self::out_of_slabs(conn, config)?;
 						None
 					}
 				};
@@ -1972,6 +6817,7 @@ where
 
 				let slab_id = slab.id();
 				debug!("allocate a slab2 {}", slab_id)?;
+				conn.owned_slabs += 1;
 				user_context.read_slabs.get_mut(last_slab)?.get_mut()
 					[read_slab_next_offset..read_slab_next_offset + 4]
 					.clone_from_slice(&(slab_id as u32).to_be_bytes());
@@ -1989,27 +6835,98 @@ where
 
 			let slab_offset = conn.get_slab_offset();
 			let slab_id = slab.id();
-			let slab_bytes = &mut slab.get_mut()[slab_offset..read_slab_next_offset];
-			let rlen = match do_read_impl(handle, slab_bytes, debug_info) {
-				Ok(rlen) => rlen,
-				Err(_e) => {
-					// read error. Close the connection
-					// we don't log this because it pollutes the logs
-					close = true;
-					None
+			let end = match conn.max_unread_bytes {
+				0 => read_slab_next_offset,
+				max => read_slab_next_offset.min(slab_offset + max - conn.unread_bytes),
+			};
+			let slab_bytes = &mut slab.get_mut()[slab_offset..end];
+			let mut transformed = false;
+			let rlen = if !conn.inbound_pending.is_empty() {
+				// deliver transformed data that did not fit in the previous slab first
+				let len = conn.inbound_pending.len().min(slab_bytes.len());
+				slab_bytes[..len].clone_from_slice(&conn.inbound_pending[..len]);
+				if conn.sensitive {
+					drain_zeroize(&mut conn.inbound_pending, len);
+				} else {
+					conn.inbound_pending.drain(..len);
+				}
+				transformed = true;
+				Some(len)
+			} else if !conn.proxy_data.is_empty() {
+				// deliver data that was read along with the PROXY protocol header first
+				let len = conn.proxy_data.len().min(slab_bytes.len());
+				slab_bytes[..len].clone_from_slice(&conn.proxy_data[..len]);
+				if conn.sensitive {
+					drain_zeroize(&mut conn.proxy_data, len);
+				} else {
+					conn.proxy_data.drain(..len);
+				}
+				Some(len)
+			} else if let Some(loopback) = &conn.loopback {
+				match loopback.read(slab_bytes) {
+					Ok(rlen) => rlen,
+					Err(e) => {
+						close = Some(CloseReason::ReadError(e));
+						None
+					}
+				}
+			} else {
+				match do_read_impl(handle, slab_bytes, debug_info) {
+					Ok(rlen) => rlen,
+					Err(e) => {
+						// read error. Close the connection
+						// we don't log this because it pollutes the logs
+						close = Some(CloseReason::ReadError(e));
+						None
+					}
 				}
 			};
 			debug!("rlen={:?}, slab_offset={}", rlen, slab_offset)?;
 
-			cbreak!(close);
+			cbreak!(close.is_some());
 
 			if rlen.is_some() {
-				let rlen = rlen.unwrap();
+				let mut rlen = rlen.unwrap();
 				if rlen > 0 {
+					conn.awaiting_data = false;
+					if !transformed {
+						if let Some(capture) = &conn.capture {
+							capture.record(true, &slab.get()[slab_offset..slab_offset + rlen])?;
+						}
+						read_count += 1;
+						let rlen_u128: u128 = try_into!(rlen)?;
+						read_sum += rlen_u128;
+					}
+					if !transformed
+						&& (conn.inbound_transform.is_some() || conn.response_extractor.is_some())
+					{
+						let buf = &mut slab.get_mut()[slab_offset..read_slab_next_offset];
+						match Self::transform_inbound(conn, buf, rlen) {
+							Ok(len) => rlen = len,
+							Err(e) => close = Some(CloseReason::TransformError(e)),
+						}
+						cbreak!(close.is_some());
+						// the transform may hold data back without producing any output yet.
+						// A slab that was allocated for this read is freed again.
+						if rlen == 0 {
+							if slab_offset == 0 {
+								let next = read_slab_next_offset;
+								Self::free_empty_slab(
+									user_context,
+									conn,
+									slab_id,
+									last_slab,
+									next,
+								)?;
+							}
+							continue;
+						}
+					}
 					conn.set_slab_offset(slab_offset + rlen);
-					read_count += 1;
-					let rlen_u128: u128 = try_into!(rlen)?;
-					read_sum += rlen_u128;
+					conn.unread_bytes += rlen;
+					if rlen > 0 {
+						conn.last_read_millis = now_millis()?;
+					}
 				}
 
 				let cur = slab_offset + rlen;
@@ -2017,25 +6934,33 @@ where
 
 				if rlen == 0 {
 					debug!("connection closed")?;
-					close = true;
+					if callbacks.on_read_eof.is_some()
+						&& !conn.write_handle()?.is_set(WRITE_STATE_FLAG_CLOSE)?
+					{
+						// half-close. Keep the connection registered so the handler can
+						// still write to it. The handler is called once the slab is released.
+						conn.read_eof = true;
+					} else {
+						close = Some(CloseReason::PeerClosed);
+					}
 					cbreak!(true);
 				}
+
+				if callbacks.on_data_chunk.is_some() {
+					let data = &slab.get()[..cur];
+					let cb = &mut callbacks.on_data_chunk;
+					if Self::call_on_data_chunk(conn, data, cb)? == ChunkAction::Free {
+						let mut ctx = &mut *user_context;
+						ctx.clear_all(conn)?;
+					}
+					continue;
+				}
 			} else {
 				debug!("no more data to read for now")?;
 				// if the slab doesn't have any data, we free it
 				if slab_offset == 0 {
-					debug!("free slab2 {}", slab_id)?;
-					user_context.read_slabs.free(slab_id)?;
-					conn.set_last_slab(last_slab);
-
-					if last_slab < u32::MAX as usize {
-						let mut slab_mut = user_context.read_slabs.get_mut(last_slab)?;
-						let slab = slab_mut.get_mut();
-						slab[read_slab_next_offset..read_slab_next_offset + 4]
-							.clone_from_slice(&u32::MAX.to_be_bytes());
-					} else {
-						conn.set_first_slab(last_slab);
-					}
+					let next = read_slab_next_offset;
+					Self::free_empty_slab(user_context, conn, slab_id, last_slab, next)?;
 				}
 
 				// no more to read for now
@@ -2043,37 +6968,223 @@ where
 			}
 
 			debug!("call onread")?;
-			Self::call_on_read(user_context, conn, &mut callbacks.on_read)?;
+			Self::call_on_read(config, user_context, conn, callbacks, false)?;
+		}
+
+		if conn.read_eof {
+			Self::call_on_read_eof(user_context, conn, &mut callbacks.on_read_eof)?;
 		}
 
 		Ok((close, read_count, read_sum))
 	}
 
+	// free a slab that was allocated for a read that did not produce any data. last_slab is
+	// the end of the connection's chain before the slab was allocated.
+	fn free_empty_slab(
+		user_context: &mut UserContextImpl,
+		conn: &mut Connection,
+		slab_id: usize,
+		last_slab: usize,
+		read_slab_next_offset: usize,
+	) -> Result<(), Error> {
+		debug!("free slab2 {}", slab_id)?;
+		user_context.read_slabs.free(slab_id)?;
+		conn.owned_slabs = conn.owned_slabs.saturating_sub(1);
+		conn.set_last_slab(last_slab);
+
+		if last_slab < u32::MAX as usize {
+			let mut slab_mut = user_context.read_slabs.get_mut(last_slab)?;
+			let slab = slab_mut.get_mut();
+			slab[read_slab_next_offset..read_slab_next_offset + 4]
+				.clone_from_slice(&u32::MAX.to_be_bytes());
+		} else {
+			conn.set_first_slab(last_slab);
+		}
+		Ok(())
+	}
+
+	// run the data that was just read through the inbound transform and the response
+	// extractor. As much of the output as fits is written in place in the slab and the rest is
+	// kept in inbound_pending. Returns the number of bytes written to the slab.
+	fn transform_inbound(
+		conn: &mut Connection,
+		buf: &mut [u8],
+		len: usize,
+	) -> Result<usize, Error> {
+		let mut output = vec![];
+		match conn.inbound_transform.as_ref() {
+			Some(transform) => {
+				let mut transform = transform.lock().unwrap_or_else(PoisonError::into_inner);
+				(*transform)(&buf[..len], &mut output)?;
+			}
+			None => output.extend(&buf[..len]),
+		}
+		if let Some(extractor) = conn.response_extractor.clone() {
+			let mut rest = vec![];
+			let responses = {
+				let mut extractor = extractor.lock().unwrap_or_else(PoisonError::into_inner);
+				(*extractor)(&output, &mut rest)?
+			};
+			if conn.sensitive {
+				zeroize(&mut output);
+			}
+			output = rest;
+			Self::resolve_responses(conn, responses)?;
+		}
+		let fit = output.len().min(buf.len());
+		buf[..fit].clone_from_slice(&output[..fit]);
+		if conn.sensitive {
+			extend_zeroize(&mut conn.inbound_pending, &output[fit..]);
+			zeroize(&mut output);
+		} else {
+			conn.inbound_pending.extend(&output[fit..]);
+		}
+		Ok(fit)
+	}
+
+	// complete the pending requests of the responses returned by the response extractor.
+	// Responses that do not match a pending request go to the unmatched response handler.
+	fn resolve_responses(
+		conn: &mut Connection,
+		responses: Vec<(u64, Vec<u8>)>,
+	) -> Result<(), Error> {
+		if responses.is_empty() {
+			return Ok(());
+		}
+		let mut unmatched = vec![];
+		{
+			let mut write_state = conn.write_state.wlock()?;
+			let guard = write_state.guard()?;
+			for (correlation_id, response) in responses {
+				match guard.responses.remove(&correlation_id) {
+					Some(pending) => pending.slot.complete(Ok(response)),
+					None => unmatched.push((correlation_id, response)),
+				}
+			}
+		}
+
+		for (correlation_id, response) in unmatched {
+			match &conn.unmatched_response_handler {
+				Some(handler) => {
+					let mut handler = handler.lock().unwrap_or_else(PoisonError::into_inner);
+					if let Err(e) = (*handler)(correlation_id, response) {
+						warn!("unmatched response handler generated error: {}", e)?;
+					}
+				}
+				None => debug!(
+					"dropping unmatched response {} on connection {}",
+					correlation_id,
+					conn.id()
+				)?,
+			}
+		}
+		Ok(())
+	}
+
+	// read and parse the PROXY protocol header. The header has been consumed once
+	// proxy_header is None and any data read past the end of it is kept in proxy_data. If the
+	// connection must be closed, the reason is returned.
+	fn process_proxy_header(
+		conn: &mut Connection,
+		debug_info: &DebugInfo,
+	) -> Result<Option<CloseReason>, Error> {
+		let mut buf = [0u8; 512];
+		loop {
+			let rlen = match do_read_impl(conn.handle(), &mut buf, debug_info) {
+				Ok(Some(0)) => return Ok(Some(CloseReason::PeerClosed)),
+				Ok(Some(rlen)) => rlen,
+				Ok(None) => return Ok(None),
+				Err(e) => return Ok(Some(CloseReason::ReadError(e))),
+			};
+
+			let header = conn.proxy_header.get_or_insert_with(Vec::new);
+			header.extend_from_slice(&buf[..rlen]);
+			match parse_proxy_header(header) {
+				Ok(Some((len, peer_addr))) => {
+					conn.proxy_data = header.split_off(len);
+					conn.peer_addr = peer_addr;
+					conn.proxy_header = None;
+					return Ok(None);
+				}
+				Ok(None) => {}
+				Err(e) => {
+					warn!("closing connection {}: {}", conn.id(), e)?;
+					return Ok(Some(CloseReason::ReadError(e)));
+				}
+			}
+		}
+	}
+
 	fn call_on_housekeeper(
+		ctx: &mut EventHandlerContext,
 		user_context: &mut UserContextImpl,
-		callback: &mut Option<Pin<Box<OnHousekeeper>>>,
+		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
+		now: usize,
 	) -> Result<(), Error> {
 		user_context.slab_cur = usize::MAX;
-		if callback.is_some() {
-			let callback = callback.as_mut().unwrap();
-			let mut user_context: Box<dyn UserContext> = Box::new(user_context);
-			let res = callback(&mut user_context);
+		let mut hk = HousekeeperContext {
+			id_hash: &ctx.id_hash,
+			now,
+			close_requests: vec![],
+		};
+		if let Some(handler) = callbacks.handler.as_mut() {
+			let mut user_context: Box<dyn UserContext> = Box::new(&mut *user_context);
+			let mut handler = handler.wlock_ignore_poison()?;
+			let handler = handler.guard()?;
+			let _timer = watch_callback(WatchedCallback::Handler, u128::MAX);
+			if let Err(e) = handler.on_housekeeper(&mut hk, &mut user_context) {
+				warn!("on_housekeeper callback generated error: {}", e)?;
+			}
+		} else if let Some(callback) = callbacks.on_housekeeper.as_mut() {
+			let mut user_context: Box<dyn UserContext> = Box::new(&mut *user_context);
+			let _timer = watch_callback(WatchedCallback::Housekeeper, u128::MAX);
+			let res = callback(&mut hk, &mut user_context);
 			if res.is_err() {
 				let e = res.unwrap_err();
 				warn!("on_housekeeper callback generated error: {}", e)?;
 			}
 		}
+
+		// close the connections that the handler requested to be closed. The handler may
+		// have requested the same connection more than once.
+		let close_requests = hk.close_requests;
+		for (id, reason) in close_requests {
+			let handle = match ctx.id_hash.get(&id) {
+				Some(ConnectionVariant::Connection(c)) => c.handle(),
+				Some(ConnectionVariant::ClientConnection(c)) => c.handle(),
+				_ => continue,
+			};
+			Self::process_close(handle, ctx, callbacks, user_context, reason)?;
+		}
 		Ok(())
 	}
 
 	fn call_on_panic(
-		callback: &mut Option<Pin<Box<OnPanic>>>,
+		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
 		user_context: &mut UserContextImpl,
 		e: Box<dyn Any + Send>,
+		info: &PanicInfoCapture,
 	) -> Result<(), Error> {
-		if callback.is_some() {
+		if let Some(handler) = callbacks.handler.as_mut() {
 			let mut user_context: Box<dyn UserContext> = Box::new(user_context);
-			let callback = callback.as_mut().unwrap();
+			let mut handler = handler.wlock_ignore_poison()?;
+			let handler = handler.guard()?;
+			if let Err(e) = handler.on_panic_info(&mut user_context, info) {
+				warn!("on_panic_info callback generated error: {}", e)?;
+			}
+			if let Err(e) = handler.on_panic(&mut user_context, e) {
+				warn!("on_panic callback generated error: {}", e)?;
+			}
+			return Ok(());
+		}
+		let mut user_context: Box<dyn UserContext> = Box::new(user_context);
+		if let Some(callback) = callbacks.on_panic_info.as_mut() {
+			let mut callback = callback.wlock_ignore_poison()?;
+			if let Err(e) = (callback.guard()?)(&mut user_context, info) {
+				warn!("on_panic_info callback generated error: {}", e)?;
+			}
+		}
+		if let Some(callback) = callbacks.on_panic.as_mut() {
 			let res = callback(&mut user_context, e);
 			if res.is_err() {
 				let e = res.unwrap_err();
@@ -2084,15 +7195,35 @@ where
 	}
 
 	fn call_on_read(
+		config: &EventHandlerConfig,
 		user_context: &mut UserContextImpl,
 		conn: &mut Connection,
-		callback: &mut Option<Pin<Box<OnRead>>>,
+		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
+		synthetic: bool,
 	) -> Result<(), Error> {
+		if !conn.accepted {
+			// the callers only dispatch on_read once on_accept has returned
+			let text = format!("on_read dispatched before on_accept for {}", conn.id());
+			if config.strict_ordering {
+				panic!("EvhStrictOrdering: {}", text);
+			}
+			evh_log!(config, Error, "{}", text)?;
+			return Ok(());
+		}
 		if !conn.write_handle()?.is_set(WRITE_STATE_FLAG_CLOSE)? {
 			user_context.slab_cur = conn.get_first_slab();
-			if callback.is_some() {
+			user_context.synthetic = synthetic;
+			if let Some(handler) = callbacks.handler.as_mut() {
+				let mut user_context: Box<dyn UserContext> = Box::new(user_context);
+				let mut handler = handler.wlock_ignore_poison()?;
+				let handler = handler.guard()?;
+				let _timer = watch_callback(WatchedCallback::Handler, conn.id());
+				if let Err(e) = handler.on_read(conn, &mut user_context) {
+					warn!("on_read callback generated error: {}", e)?;
+				}
+			} else if let Some(callback) = callbacks.on_read.as_mut() {
 				let mut user_context: Box<dyn UserContext> = Box::new(user_context);
-				let callback = callback.as_mut().unwrap();
+				let _timer = watch_callback(WatchedCallback::Read, conn.id());
 				let res = callback(conn, &mut user_context);
 				if res.is_err() {
 					let e = res.unwrap_err();
@@ -2106,55 +7237,170 @@ where
 	fn call_on_accept(
 		user_context: &mut UserContextImpl,
 		conn: &mut Connection,
-		callback: &mut Option<Pin<Box<OnAccept>>>,
+		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
 	) -> Result<(), Error> {
 		user_context.slab_cur = usize::MAX;
-		if callback.is_some() {
+		if let Some(handler) = callbacks.handler.as_mut() {
+			let mut user_context: Box<dyn UserContext> = Box::new(user_context);
+			let mut handler = handler.wlock_ignore_poison()?;
+			let handler = handler.guard()?;
+			let _timer = watch_callback(WatchedCallback::Handler, conn.id());
+			if let Err(e) = handler.on_accept(conn, &mut user_context) {
+				warn!("on_accept callback generated error: {}", e)?;
+			}
+		} else if let Some(callback) = callbacks.on_accept.as_mut() {
 			let mut user_context: Box<dyn UserContext> = Box::new(user_context);
-			let callback = callback.as_mut().unwrap();
+			let _timer = watch_callback(WatchedCallback::Accept, conn.id());
 			let res = callback(conn, &mut user_context);
 			if res.is_err() {
 				let e = res.unwrap_err();
 				warn!("on_accept callback generated error: {}", e)?;
 			}
 		}
+		conn.accepted = true;
+		Ok(())
+	}
+
+	fn call_on_data_chunk(
+		conn: &mut Connection,
+		data: &[u8],
+		callback: &mut Option<Box<dyn LockBox<OnDataChunk>>>,
+	) -> Result<ChunkAction, Error> {
+		if conn.write_handle()?.is_set(WRITE_STATE_FLAG_CLOSE)? {
+			return Ok(ChunkAction::Free);
+		}
+		match callback.as_mut() {
+			Some(callback) => {
+				let mut callback = callback.wlock()?;
+				let callback = callback.guard()?;
+				let _timer = watch_callback(WatchedCallback::DataChunk, conn.id());
+				match (callback)(conn, data) {
+					Ok(action) => Ok(action),
+					Err(e) => {
+						warn!("on_data_chunk callback generated error: {}", e)?;
+						Ok(ChunkAction::Free)
+					}
+				}
+			}
+			None => Ok(ChunkAction::Free),
+		}
+	}
+
+	fn call_on_slab_quota(
+		user_context: &mut UserContextImpl,
+		conn: &mut Connection,
+		callback: &mut Option<Box<dyn LockBox<OnSlabQuota>>>,
+	) -> Result<(), Error> {
+		if let Some(callback) = callback.as_mut() {
+			user_context.slab_cur = conn.get_first_slab();
+			user_context.synthetic = false;
+			let mut callback = callback.wlock()?;
+			let callback = callback.guard()?;
+			let mut user_context: Box<dyn UserContext> = Box::new(user_context);
+			let _timer = watch_callback(WatchedCallback::SlabQuota, conn.id());
+			if let Err(e) = (callback)(conn, &mut user_context) {
+				warn!("on_slab_quota callback generated error: {}", e)?;
+			}
+		}
+		Ok(())
+	}
+
+	fn call_on_read_eof(
+		user_context: &mut UserContextImpl,
+		conn: &mut Connection,
+		callback: &mut Option<Box<dyn LockBox<OnReadEof>>>,
+	) -> Result<(), Error> {
+		if let Some(callback) = callback.as_mut() {
+			user_context.slab_cur = conn.get_first_slab();
+			user_context.synthetic = false;
+			let mut callback = callback.wlock()?;
+			let callback = callback.guard()?;
+			let mut user_context: Box<dyn UserContext> = Box::new(user_context);
+			let _timer = watch_callback(WatchedCallback::ReadEof, conn.id());
+			if let Err(e) = (callback)(conn, &mut user_context) {
+				warn!("on_read_eof callback generated error: {}", e)?;
+			}
+		}
+		Ok(())
+	}
+
+	fn call_on_raw_event(
+		callback: &mut Option<Box<dyn LockBox<OnRawEvent>>>,
+		token: u64,
+		readable: bool,
+		writable: bool,
+	) -> Result<(), Error> {
+		if let Some(callback) = callback.as_mut() {
+			let mut callback = callback.wlock()?;
+			let callback = callback.guard()?;
+			let _timer = watch_callback(WatchedCallback::RawEvent, u128::MAX);
+			if let Err(e) = (**callback)(token, readable, writable) {
+				warn!("on_raw_event callback generated error: {}", e)?;
+			}
+		}
 		Ok(())
 	}
 
+	fn remove_raw(handle: Handle, ctx: &mut EventHandlerContext) -> Result<(), Error> {
+		let id = ctx.handle_hash.remove(&handle).unwrap_or(u128::MAX);
+		debug!("removing raw handle={},id={}", handle, id)?;
+		match ctx.id_hash.remove(&id) {
+			Some(ConnectionVariant::Raw(raw)) => {
+				ctx.raw_token_hash.remove(&raw.token);
+			}
+			_ => warn!("expected a raw registration")?,
+		}
+		deregister_raw_impl(handle, ctx)
+	}
+
 	pub(crate) fn call_on_close(
 		user_context: &mut UserContextImpl,
 		handle: Handle,
+		reason: &CloseReason,
 		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
 		ctx: &mut EventHandlerContext,
 	) -> Result<(), Error> {
 		user_context.slab_cur = usize::MAX;
-		match callbacks.on_close {
-			Some(ref mut callback) => match ctx.handle_hash.get(&handle) {
-				Some(id) => match ctx.id_hash.get_mut(id) {
-					Some(conn) => match conn {
-						ConnectionVariant::Connection(conn) => {
-							let mut user_context: Box<dyn UserContext> = Box::new(user_context);
-							let res = callback(conn, &mut user_context);
-							if res.is_err() {
-								let e = res.unwrap_err();
-								warn!("on_close callback generated error: {}", e)?;
-							}
-						}
-						ConnectionVariant::ClientConnection(conn) => {
-							let mut user_context: Box<dyn UserContext> = Box::new(user_context);
-							let res = callback(conn, &mut user_context);
-							if res.is_err() {
-								let e = res.unwrap_err();
-								warn!("on_close callback generated error: {}", e)?;
-							}
-						}
-						_ => warn!("on_close called on unexpected type. tid = {}", ctx.tid)?,
-					},
-					None => warn!("onclose noneA")?,
-				},
-				None => warn!("onclose noneB")?,
+		if callbacks.handler.is_none() && callbacks.on_close.is_none() {
+			warn!("onclose noneC")?;
+			return Ok(());
+		}
+		let conn = match ctx.handle_hash.get(&handle) {
+			Some(id) => match ctx.id_hash.get_mut(id) {
+				Some(conn) => conn,
+				None => {
+					warn!("onclose noneA")?;
+					return Ok(());
+				}
 			},
-			None => warn!("onclose noneC")?,
+			None => {
+				warn!("onclose noneB")?;
+				return Ok(());
+			}
+		};
+		let conn = match conn {
+			// on_accept was never called for this connection
+			ConnectionVariant::Connection(conn) if !conn.accepted => return Ok(()),
+			ConnectionVariant::Connection(conn) => conn,
+			ConnectionVariant::ClientConnection(conn) => conn,
+			_ => {
+				warn!("on_close called on unexpected type. tid = {}", ctx.tid)?;
+				return Ok(());
+			}
+		};
+		let mut user_context: Box<dyn UserContext> = Box::new(user_context);
+		let res = if let Some(handler) = callbacks.handler.as_mut() {
+			let mut handler = handler.wlock_ignore_poison()?;
+			let handler = handler.guard()?;
+			let _timer = watch_callback(WatchedCallback::Handler, conn.id());
+			handler.on_close(conn, reason, &mut user_context)
+		} else {
+			let callback = callbacks.on_close.as_mut().unwrap();
+			let _timer = watch_callback(WatchedCallback::Close, conn.id());
+			callback(conn, reason, &mut user_context)
+		};
+		if let Err(e) = res {
+			warn!("on_close callback generated error: {}", e)?;
 		}
 		Ok(())
 	}
@@ -2164,30 +7410,101 @@ where
 		ctx: &mut EventHandlerContext,
 		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
 		mut user_context: &mut UserContextImpl,
+		reason: CloseReason,
 	) -> Result<(), Error> {
+		// raw handles are never closed by the evh, only deregistered
+		if let Some(id) = ctx.handle_hash.get(&handle) {
+			if let Some(ConnectionVariant::Raw(_)) = ctx.id_hash.get(id) {
+				return Self::remove_raw(handle, ctx);
+			}
+		}
 		ctx.thread_stats.closes += 1;
-		Self::call_on_close(user_context, handle, callbacks, ctx)?;
+		if let Some(metrics) = &ctx.metrics {
+			metrics.incr(EvhMetric::Closes, 1);
+		}
+		ctx.thread_stats.close_reasons.incr(&reason);
+		Self::call_on_close(user_context, handle, &reason, callbacks, ctx)?;
 
 		let id = ctx.handle_hash.remove(&handle).unwrap_or(u128::MAX);
 		debug!("removing handle={},id={}", handle, id)?;
+		wlock!(ctx.keys).untrack(id);
+		if let Some(mut connections) = ctx.watch.as_ref().and_then(|w| w.connections.clone()) {
+			wlock!(connections).remove(&id);
+		}
+		let mut loopback = None;
+		let mut write_state = None;
 		match ctx.id_hash.remove(&id) {
 			Some(conn) => match conn {
 				ConnectionVariant::Connection(mut conn) => {
+					conn.end_capture()?;
 					user_context.clear_through(conn.get_last_slab(), &mut conn)?;
+					conn.zeroize_pending();
+					loopback = conn.loopback.take();
+					write_state = Some(conn.write_state.clone());
 				}
 				ConnectionVariant::ClientConnection(mut conn) => {
+					conn.end_capture()?;
 					user_context.clear_through(conn.get_last_slab(), &mut conn)?;
+					conn.zeroize_pending();
+					loopback = conn.loopback.take();
+					write_state = Some(conn.write_state.clone());
 				}
 				_ => warn!("unexpected process_close server/wakeup tid = {}", ctx.tid)?,
 			},
 			None => warn!("expected a connection")?,
 		}
-		close_impl_ctx(handle, ctx)?;
+		// the connection is marked closed and its handle is closed under the write state lock.
+		// A WriteHandle holds the lock while it writes, so once the handle is closed (and may
+		// be reused by the os for a new connection) every WriteHandle of this connection
+		// returns ConnectionClosed rather than writing to the new connection.
+		let mut write_state = write_state;
+		let mut guard = match write_state.as_mut() {
+			Some(write_state) => Some(write_state.wlock()?),
+			None => None,
+		};
+		if let Some(guard) = guard.as_mut() {
+			let guard = guard.guard()?;
+			guard.set_flag(WRITE_STATE_FLAG_CLOSE);
+			guard.zeroize_buffers();
+			guard.fail_responses();
+			guard.release();
+		}
+		match loopback {
+			// there is no handle to close. The peer reads EOF and its writes fail.
+			Some(loopback) => loopback.close()?,
+			None => close_impl_ctx(handle, ctx)?,
+		}
+		drop(guard);
 		debug!("id hash rem")?;
 		Ok(())
 	}
 
+	// remove a connection that was exported with Connection::export_handle. Unlike
+	// process_close, on_close is not called and the handle is deregistered but not closed.
+	fn process_export(
+		handle: Handle,
+		ctx: &mut EventHandlerContext,
+		mut user_context: &mut UserContextImpl,
+	) -> Result<(), Error> {
+		let id = ctx.handle_hash.remove(&handle).unwrap_or(u128::MAX);
+		debug!("exporting handle={},id={}", handle, id)?;
+		wlock!(ctx.keys).untrack(id);
+		if let Some(mut connections) = ctx.watch.as_ref().and_then(|w| w.connections.clone()) {
+			wlock!(connections).remove(&id);
+		}
+		match ctx.id_hash.remove(&id) {
+			Some(ConnectionVariant::Connection(mut conn)) => {
+				conn.end_capture()?;
+				user_context.clear_through(conn.get_last_slab(), &mut conn)?;
+			}
+			_ => warn!("expected an exported connection")?,
+		}
+		ctx.in_events.retain(|event| event.handle != handle);
+		deregister_raw_impl(handle, ctx)
+	}
+
 	pub(crate) fn process_accept(
+		config: &EventHandlerConfig,
 		conn: &Connection,
 		accepted: &mut Vec<(Handle, u128)>,
 		debug_info: &DebugInfo,
@@ -2197,7 +7514,7 @@ where
 		let id = conn.id();
 		debug!("process read event on handle={},id={}", handle, id)?;
 		while TRUE {
-			let accept_res = accept_impl(handle, debug_info);
+			let accept_res = do_accept_impl(handle, debug_info);
 
 			if accept_res.is_ok() {
 				let next = accept_res.unwrap();
@@ -2205,7 +7522,7 @@ where
 				accepted.push((next.unwrap(), conn.id()));
 			} else {
 				let e = accept_res.unwrap_err();
-				warn!("accept generated error: {}", e)?;
+				evh_log!(config, Warn, "accept generated error: {}", e)?;
 				cbreak!(true);
 			}
 		}
@@ -2213,79 +7530,232 @@ where
 	}
 
 	pub(crate) fn process_write_event(
-		_config: &EventHandlerConfig,
+		config: &EventHandlerConfig,
 		ctx: &mut EventHandlerContext,
 		callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
 		handle: Handle,
 		user_context: &mut UserContextImpl,
-	) -> Result<bool, Error> {
-		let mut close = false;
+		debug_info: &DebugInfo,
+	) -> Result<(bool, bool), Error> {
+		let mut close = None;
 		let mut write_count = 0;
 		let mut write_sum = 0;
+		let mut more = false;
+		let mut pending = false;
+		let mut open = false;
+		let mut next_write = None;
+		let max = config.max_write_bytes_per_loop;
 		match ctx.handle_hash.get(&handle) {
 			Some(id) => match ctx.id_hash.get_mut(id) {
 				Some(conn) => match conn {
 					ConnectionVariant::Connection(conn) => {
-						(close, write_count, write_sum) = Self::write_loop(conn, callbacks)?;
+						(close, write_count, write_sum, more) =
+							Self::write_loop(conn, callbacks, max)?;
+						pending = conn.write_handle()?.is_set(WRITE_STATE_FLAG_PENDING)?;
+						next_write = conn.write_handle()?.take_next_write()?;
+						open = true;
 					}
 					ConnectionVariant::ClientConnection(conn) => {
-						(close, write_count, write_sum) = Self::write_loop(conn, callbacks)?;
+						(close, write_count, write_sum, more) =
+							Self::write_loop(conn, callbacks, max)?;
+						pending = conn.write_handle()?.is_set(WRITE_STATE_FLAG_PENDING)?;
+						next_write = conn.write_handle()?.take_next_write()?;
+						open = true;
 					}
-					_ => warn!("unexpected ConnectionVariant in process_write_event")?,
+					_ => evh_log!(
+						config,
+						Warn,
+						"unexpected ConnectionVariant in process_write_event"
+					)?,
 				},
-				None => warn!("id hash lookup failed for id: {}, handle: {}", id, handle)?,
+				None => evh_log!(
+					config,
+					Warn,
+					"id hash lookup failed for id: {}, handle: {}",
+					id,
+					handle
+				)?,
 			},
 			None => debug!("handle lookup failed for  handle: {}", handle)?,
 		}
 
-		let ret = if close {
-			Self::process_close(handle, ctx, callbacks, user_context)?;
-			false
+		// returns whether the connection is still open and whether data is still pending
+		let ret = if let Some(reason) = close {
+			Self::process_close(handle, ctx, callbacks, user_context, reason)?;
+			(false, false)
+		} else if let Some(at) = next_write {
+			// the write is delayed or rate limited. process_write_timers re-arms the write
+			// interest when it is due, so only read interest is kept until then.
+			if let Some(id) = ctx.handle_hash.get(&handle) {
+				ctx.write_timers.push(Reverse((at, *id)));
+			}
+			(open, false)
 		} else {
-			true
+			if more {
+				// the quota for this loop was used up, but the socket may still be writable.
+				// Re-arm the write interest so that we continue on the next loop after the
+				// other connections have had a chance to write.
+				Self::rearm_write_interest(ctx, handle, debug_info);
+			}
+			(open, pending || more)
 		};
 
 		ctx.thread_stats.delay_writes += write_count;
 		ctx.thread_stats.bytes_delay_write += write_sum;
+		if let Some(metrics) = &ctx.metrics {
+			metrics.incr(EvhMetric::BytesWritten, try_into!(write_sum)?);
+		}
 
 		Ok(ret)
 	}
 
+	// write pending data until the socket would block, everything is written or max_bytes (if
+	// not 0) have been written. The last value returned is true if data remains because
+	// max_bytes was reached.
 	pub(crate) fn write_loop(
 		conn: &mut Connection,
 		_callbacks: &mut EventHandlerCallbacks<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>,
-	) -> Result<(bool, usize, u128), Error> {
+		max_bytes: usize,
+	) -> Result<(Option<CloseReason>, usize, u128, bool), Error> {
 		let mut write_count = 0;
 		let mut write_sum = 0;
+		let mut more = false;
+		let max_bytes: u128 = try_into!(max_bytes)?;
 		let mut wh = conn.write_handle()?;
 		let write_state = wh.write_state()?;
 		let mut write_state = write_state.wlock()?;
 		let guard = write_state.guard()?;
-		let mut close = false;
+		let mut close = None;
 		let mut rem = true;
 
+		guard.next_write = None;
+		let now = if guard.is_shaped() || guard.hold_until != 0 {
+			now_millis()?
+		} else {
+			0
+		};
+		if guard.hold_until != 0 {
+			// now is truncated to the millisecond so the flush waits until it is past
+			// hold_until to be sure that the whole delay has passed
+			if now <= guard.hold_until {
+				// the flush is delayed, the event loop calls back when it is due
+				guard.next_write = Some(guard.hold_until + 1);
+				return Ok((None, 0, 0, false));
+			}
+			guard.hold_until = 0;
+		}
+		// the number of bytes that the rate limit allows in this loop
+		let budget = if guard.rate_limit > 0 {
+			guard.refill(now);
+			guard.tokens
+		} else {
+			usize::MAX
+		};
+
 		loop {
 			let len = (**guard).write_buffer.len();
-			if len == 0 && !conn.debug_info.is_write_err2() {
-				rem = false;
+			let done = len == 0 && guard.pending_files.is_empty();
+			if max_bytes != 0 && write_sum >= max_bytes && !done {
+				more = true;
 				cbreak!(true);
 			}
-			let wlen = match do_write_impl(conn.handle(), &(**guard).write_buffer, &conn.debug_info)
-			{
+			// the number of bytes that may still be written in this loop
+			let quota = if max_bytes == 0 {
+				usize::MAX
+			} else {
+				try_into!(max_bytes - write_sum)?
+			};
+			let written: usize = try_into!(write_sum)?;
+			let quota = quota.min(budget.saturating_sub(written));
+			if quota == 0 && !done {
+				// the rate limit is reached, continue once more tokens are available
+				let wait = 1_000_usize.div_ceil(guard.rate_limit.max(1));
+				let wait = wait.max(EVH_RATE_LIMIT_INTERVAL_MILLIS);
+				guard.next_write = Some(now.saturating_add(wait));
+				cbreak!(true);
+			}
+			if !done && conn.loopback.as_ref().map(|l| l.is_full()).unwrap_or(false) {
+				// the peer wakes this thread once it has read from its buffer
+				cbreak!(true);
+			}
+			if len == 0 && !conn.debug_info.is_write_err2() {
+				if guard.pending_files.is_empty() {
+					rem = false;
+					cbreak!(true);
+				}
+
+				// buffered data is written, continue with the next queued file region
+				let wlen = match guard.pending_files.front() {
+					Some(pending) => {
+						let (file, offset, remaining) =
+							(&pending.file, pending.offset, pending.remaining);
+						let remaining = remaining.min(try_into!(quota)?);
+						let res = match &conn.loopback {
+							Some(loopback) => loopback.send_file(file, offset, remaining),
+							None => sendfile_impl(conn.handle(), file, offset, remaining),
+						};
+						match res {
+							Ok(wlen) => wlen,
+							Err(e) => {
+								close = Some(CloseReason::WriteError(e));
+								0
+							}
+						}
+					}
+					None => 0,
+				};
+				cbreak!(close.is_some());
+
+				if wlen < 0 {
+					let err = errno().0;
+					if err != EAGAIN && err != ETEMPUNAVAILABLE && err != WINNONBLOCKING {
+						close = Some(Self::write_errno_reason(err));
+					}
+					cbreak!(true);
+				} else if wlen == 0 {
+					// the file was truncated after send_file was called
+					warn!("file region ended early on handle {}", conn.handle())?;
+					let text = "file region ended early";
+					close = Some(CloseReason::WriteError(err!(ErrKind::IO, text)));
+					cbreak!(true);
+				}
+
+				let wlen: u64 = try_into!(wlen)?;
+				write_count += 1;
+				write_sum += u128::from(wlen);
+				if let Some(mut pending) = guard.pending_files.pop_front() {
+					pending.offset += wlen;
+					pending.remaining = pending.remaining.saturating_sub(wlen);
+					if pending.remaining == 0 {
+						// the data written after this region is next
+						guard.write_buffer = pending.trailing;
+					} else {
+						guard.pending_files.push_front(pending);
+					}
+				}
+				continue;
+			}
+			let end = len.min(quota);
+			let buf = &guard.write_buffer[..end];
+			let res = match &conn.loopback {
+				Some(loopback) => loopback.write(buf),
+				None => do_write_impl(conn.handle(), buf, &conn.debug_info),
+			};
+			let wlen = match res {
 				Ok(wlen) => wlen,
-				Err(_e) => {
+				Err(e) => {
 					// write i/o error. Don't log these because they would pollute
 					// the logs
-					close = true;
+					close = Some(CloseReason::WriteError(e));
 					0
 				}
 			};
-			cbreak!(close);
+			cbreak!(close.is_some());
 
 			if wlen < 0 {
 				let err = errno().0;
 				if err != EAGAIN && err != ETEMPUNAVAILABLE && err != WINNONBLOCKING {
-					close = true;
+					close = Some(Self::write_errno_reason(err));
 				}
 				cbreak!(true);
 			} else {
@@ -2296,23 +7766,40 @@ where
 					write_sum += wlen_u128;
 				}
 
-				(**guard).write_buffer.drain(0..wlen);
-				(**guard).write_buffer.shrink_to_fit();
+				if guard.sensitive {
+					// shrink_to_fit would free a copy of the data without overwriting it
+					drain_zeroize(&mut guard.write_buffer, wlen);
+				} else {
+					(**guard).write_buffer.drain(0..wlen);
+					(**guard).write_buffer.shrink_to_fit();
+				}
 			}
 		}
 
+		if guard.rate_limit > 0 {
+			let written: usize = try_into!(write_sum)?;
+			guard.tokens = guard.tokens.saturating_sub(written);
+		}
+		guard.account();
+
 		if !rem {
 			(**guard).unset_flag(WRITE_STATE_FLAG_PENDING);
 
 			if (**guard).is_set(WRITE_STATE_FLAG_CLOSE) {
-				close = true;
+				close = Some(conn.local_close_reason(guard.transform_error.take()));
 			}
 		}
 
-		Ok((close, write_count, write_sum))
+		Ok((close, write_count, write_sum, more))
+	}
+
+	fn write_errno_reason(err: i32) -> CloseReason {
+		let text = format!("write failed with errno: {}", err);
+		CloseReason::WriteError(err!(ErrKind::IO, text))
 	}
 
 	fn stop(&mut self) -> Result<(), Error> {
+		unregister_signals(self.ids.instance_id);
 		if !self.has_controller {
 			if self.debug_info.is_stop_error() {
 				let text = "simulated stop error";
@@ -2327,6 +7814,8 @@ where
 				wlock!(self.state[i]).stop = true;
 				self.wakeups[i].wakeup()?;
 			}
+			wlock!(self.stats).stats.origins.clear();
+			wlock!(self.stats).listen_addrs.clear();
 		}
 
 		Ok(())
@@ -2373,12 +7862,29 @@ impl EventHandlerContext {
 			wakeups,
 			tid,
 			last_housekeeping: 0,
+			raw_token_hash: HashMap::new(),
 			trigger_on_read_list: vec![],
 			trigger_itt: 0,
+			loopback_events: vec![],
+			loopback_itt: 0,
 			ret_event_itt: 0,
 			thread_stats: EvhStats::new(),
 			global_stats,
 			last_stats_update: 0,
+			proxy_deadlines: VecDeque::new(),
+			data_deadlines: VecDeque::new(),
+			ids: ConnectionIds::new(),
+			pending_connections: VecDeque::new(),
+			watch: None,
+			generation: 0,
+			replacement_closes: vec![],
+			capture_writer: None,
+			metrics: None,
+			draining: false,
+			write_timers: BinaryHeap::new(),
+			response_timers: BinaryHeap::new(),
+			memory_paused: vec![],
+			keys: lock_box!(KeyRegistry::new())?,
 			#[cfg(target_os = "linux")]
 			linux_ctx: LinuxContext::new()?,
 			#[cfg(target_os = "macos")]
@@ -2387,6 +7893,24 @@ impl EventHandlerContext {
 			windows_ctx: WindowsContext::new()?,
 		})
 	}
+
+	// the number of milliseconds get_events may block for. It is shortened so that the
+	// next delayed or rate limited write or request timeout is not missed.
+	pub(crate) fn events_timeout(&self, timeout: u16) -> Result<u16, Error> {
+		// connections deferred by EvhOnAcceptBudgetMicros are registered in the next loop
+		if !self.pending_connections.is_empty() {
+			return Ok(0);
+		}
+		let write_at = self.write_timers.peek().map(|Reverse((at, _))| *at);
+		let response_at = self.response_timers.peek().map(|Reverse((at, _, _))| *at);
+		match write_at.into_iter().chain(response_at).min() {
+			Some(at) => {
+				let wait = at.saturating_sub(now_millis()?);
+				Ok(wait.min(timeout.into()).try_into().unwrap_or(timeout))
+			}
+			None => Ok(timeout),
+		}
+	}
 }
 
 impl EvhStats {
@@ -2399,7 +7923,72 @@ impl EvhStats {
 			event_loops: 0,
 			bytes_delay_write: 0,
 			bytes_read: 0,
+			wakeup_writes: 0,
+			watchdog_trips: 0,
+			capture_drops: 0,
+			accept_burst_deferrals: 0,
+			max_accept_micros: 0,
+			name: None,
+			memory_used: 0,
+			memory_budget: 0,
+			memory_pressure: MemoryPressure::None,
+			memory_pressure_events: 0,
+			memory_reads_paused: 0,
+			memory_accepts_rejected: 0,
+			memory_recoveries: 0,
+			close_reasons: CloseReasonStats::default(),
+			read_slabs: vec![],
+			thread_connections: vec![],
+			reassignments: 0,
+			origins: HashMap::new(),
+		}
+	}
+
+	/// Returns the accepts, reads and bytes read in the last statistical interval broken
+	/// down by origin_id (see [`crate::Connection::origin_id`]), sorted by origin_id. There
+	/// is an entry for each server connection that has been added to the
+	/// [`crate::EventHandler`]. Stats for all client connections are combined into a single
+	/// entry with an origin_id of 0.
+	pub fn per_origin(&self) -> Vec<(u128, OriginStats)> {
+		let mut ret: Vec<(u128, OriginStats)> =
+			self.origins.iter().map(|(k, v)| (*k, *v)).collect();
+		ret.sort_by_key(|(origin_id, _)| *origin_id);
+		ret
+	}
+
+	fn incr_origin(&mut self, origin_id: u128, accepts: usize, reads: usize, bytes_read: u128) {
+		let origin = self.origins.entry(origin_id).or_default();
+		origin.accepts += accepts;
+		origin.reads += reads;
+		origin.bytes_read += bytes_read;
+	}
+
+	// the memory use when the stats are retrieved
+	fn set_memory(&mut self, memory: Option<&MemoryBudget>) {
+		if let Some(memory) = memory {
+			self.memory_used = memory.used();
+			self.memory_budget = memory.budget;
+			self.memory_pressure = memory.pressure();
+		}
+	}
+
+	fn set_thread_connections(&mut self, tid: usize, connections: usize) {
+		if self.thread_connections.len() <= tid {
+			self.thread_connections.resize(tid + 1, 0);
+		}
+		self.thread_connections[tid] = connections;
+	}
+
+	fn set_read_slabs(&mut self, tid: usize, read_slab_stats: ReadSlabStats) {
+		if self.read_slabs.len() <= tid {
+			let empty = ReadSlabStats {
+				used: 0,
+				free: 0,
+				high_water: 0,
+			};
+			self.read_slabs.resize(tid + 1, empty);
 		}
+		self.read_slabs[tid] = read_slab_stats;
 	}
 
 	fn reset(&mut self) {
@@ -2410,6 +7999,21 @@ impl EvhStats {
 		self.event_loops = 0;
 		self.bytes_read = 0;
 		self.bytes_delay_write = 0;
+		self.wakeup_writes = 0;
+		self.watchdog_trips = 0;
+		self.capture_drops = 0;
+		self.accept_burst_deferrals = 0;
+		self.max_accept_micros = 0;
+		self.memory_pressure_events = 0;
+		self.memory_reads_paused = 0;
+		self.memory_accepts_rejected = 0;
+		self.memory_recoveries = 0;
+		self.reassignments = 0;
+		self.close_reasons = CloseReasonStats::default();
+		// keep the keys so that there is still an entry for each origin
+		for origin in self.origins.values_mut() {
+			*origin = OriginStats::default();
+		}
 	}
 
 	fn incr_stats(&mut self, stats: &EvhStats) {
@@ -2420,5 +8024,51 @@ impl EvhStats {
 		self.event_loops += stats.event_loops;
 		self.bytes_read += stats.bytes_read;
 		self.bytes_delay_write += stats.bytes_delay_write;
+		self.wakeup_writes += stats.wakeup_writes;
+		self.watchdog_trips += stats.watchdog_trips;
+		self.capture_drops += stats.capture_drops;
+		self.accept_burst_deferrals += stats.accept_burst_deferrals;
+		self.max_accept_micros = self.max_accept_micros.max(stats.max_accept_micros);
+		self.memory_pressure_events += stats.memory_pressure_events;
+		self.memory_reads_paused += stats.memory_reads_paused;
+		self.memory_accepts_rejected += stats.memory_accepts_rejected;
+		self.memory_recoveries += stats.memory_recoveries;
+		self.reassignments += stats.reassignments;
+		self.close_reasons.incr_stats(&stats.close_reasons);
+		for (origin_id, origin) in &stats.origins {
+			self.incr_origin(*origin_id, origin.accepts, origin.reads, origin.bytes_read);
+		}
+	}
+}
+
+impl CloseReasonStats {
+	fn incr(&mut self, reason: &CloseReason) {
+		match reason {
+			CloseReason::PeerClosed => self.peer_closed += 1,
+			CloseReason::ReadError(_) => self.read_error += 1,
+			CloseReason::WriteError(_) => self.write_error += 1,
+			CloseReason::OutOfSlabs => self.out_of_slabs += 1,
+			CloseReason::IdleTimeout => self.idle_timeout += 1,
+			CloseReason::LocalClose => self.local_close += 1,
+			CloseReason::Panic => self.panic += 1,
+			CloseReason::CallbackTimeout => self.callback_timeout += 1,
+			CloseReason::TransformError(_) => self.transform_error += 1,
+			CloseReason::MemoryPressure => self.memory_pressure += 1,
+			CloseReason::ThreadReplaced => self.thread_replaced += 1,
+		}
+	}
+
+	fn incr_stats(&mut self, stats: &CloseReasonStats) {
+		self.peer_closed += stats.peer_closed;
+		self.read_error += stats.read_error;
+		self.write_error += stats.write_error;
+		self.out_of_slabs += stats.out_of_slabs;
+		self.idle_timeout += stats.idle_timeout;
+		self.local_close += stats.local_close;
+		self.panic += stats.panic;
+		self.callback_timeout += stats.callback_timeout;
+		self.transform_error += stats.transform_error;
+		self.memory_pressure += stats.memory_pressure;
+		self.thread_replaced += stats.thread_replaced;
 	}
 }
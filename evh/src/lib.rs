@@ -156,5 +156,17 @@ mod types;
 mod win;
 
 pub use crate::types::{
-	Chunk, Connection, EventHandler, EvhBuilder, EvhController, EvhStats, UserContext, WriteHandle,
+	Chunk, ChunkAction, CloseReason, CloseReasonStats, CompressedStream, CompressionFormat,
+	Connection, ConnectionId, EventHandler, EvhBuilder, EvhController, EvhMetric, EvhStats,
+	ExportedHandle, HousekeeperContext, Interest, MemoryPressure, MetricsRecorder, NoOnAccept,
+	NoOnClose, NoOnHousekeeper, NoOnPanic, NoOnRead, OriginStats, ProtocolHandler, ReadSlabStats,
+	ResponseExtractor, ResponseFuture, ShutdownMode, Signal, ThreadHealth, Transform,
+	UnmatchedResponseHandler, UserContext, WriteHandle,
 };
+
+#[cfg(target_os = "linux")]
+pub use crate::linux::Handle;
+#[cfg(target_os = "macos")]
+pub use crate::mac::Handle;
+#[cfg(target_os = "windows")]
+pub use crate::win::Handle;
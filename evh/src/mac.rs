@@ -16,28 +16,38 @@
 // limitations under the License.
 
 use crate::constants::*;
+use crate::evh::{connect_error, connect_happy_eyeballs, connect_with_timeout};
 use crate::types::{
-	DebugInfo, Event, EventHandlerConfig, EventHandlerContext, EventType, EventTypeIn,
+	DebugInfo, Event, EventHandlerConfig, EventHandlerContext, EventType, EventTypeIn, Interest,
+	Signal,
 };
 use bmw_deps::errno::{errno, set_errno, Errno};
 use bmw_deps::kqueue_sys::{kevent, kqueue, EventFilter, EventFlag, FilterFlag};
 use bmw_deps::libc::{
-	self, accept, c_int, c_void, close, fcntl, listen, pipe, read, shutdown, sockaddr, socket,
-	timespec, write, F_SETFL, O_NONBLOCK,
+	self, accept, c_int, c_void, close, fcntl, listen, pipe, read, shutdown, sigemptyset, sockaddr,
+	socket, timespec, write, EINTR, F_SETFL, O_NONBLOCK, SA_RESTART, SIGINT, SIGTERM,
 };
-use bmw_deps::nix::sys::socket::{bind, SockaddrIn, SockaddrIn6};
+use bmw_deps::nix::net::if_::if_nametoindex;
+use bmw_deps::nix::sys::socket::{bind, connect, SockaddrIn, SockaddrIn6};
 use bmw_err::*;
 use bmw_log::*;
-use std::mem::{size_of, zeroed};
-use std::net::TcpStream;
-use std::os::fd::IntoRawFd;
+use std::fs::File;
+use std::mem::{size_of, zeroed, ManuallyDrop};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::os::fd::RawFd;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd};
+use std::ptr::null_mut;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
 use std::time::Duration;
 
 info!();
 
-pub(crate) type Handle = RawFd;
+// maximum number of bytes passed to a single sendfile call
+const SENDFILE_MAX_LEN: u64 = 0x7fff_f000;
+
+/// The underlying handle type used by the [`crate::EventHandler`] on this platform.
+pub type Handle = RawFd;
 
 pub(crate) struct MacosContext {
 	pub(crate) selector: Handle,
@@ -57,6 +67,33 @@ pub(crate) fn write_impl(handle: Handle, buf: &[u8]) -> Result<isize, Error> {
 	Ok(unsafe { write(handle, cbuf, buf.len().into()) })
 }
 
+pub(crate) fn sendfile_impl(
+	handle: Handle,
+	file: &File,
+	offset: u64,
+	len: u64,
+) -> Result<isize, Error> {
+	set_errno(Errno(0));
+	let offset: libc::off_t = try_into!(offset)?;
+	let mut sent: libc::off_t = try_into!(len.min(SENDFILE_MAX_LEN))?;
+	let res = unsafe {
+		libc::sendfile(
+			file.as_raw_fd(),
+			handle,
+			offset,
+			&mut sent,
+			std::ptr::null_mut(),
+			0,
+		)
+	};
+	// on a non-blocking socket, sendfile may fail with EAGAIN after a partial send
+	if res == 0 || (sent > 0 && errno().0 == libc::EAGAIN) {
+		Ok(try_into!(sent)?)
+	} else {
+		Ok(-1)
+	}
+}
+
 pub(crate) fn wakeup_impl() -> Result<(Handle, Handle), Error> {
 	set_errno(Errno(0));
 	let mut retfds = [0i32; 2];
@@ -67,6 +104,78 @@ pub(crate) fn wakeup_impl() -> Result<(Handle, Handle), Error> {
 	Ok((retfds[0], retfds[1]))
 }
 
+// the pipe that the signal handler writes to. See install_signal_handler_impl.
+static SIGNAL_PIPE: [AtomicI32; 2] = [AtomicI32::new(-1), AtomicI32::new(-1)];
+// the signals received since the last call to wait_signal_impl
+static SIGNALS_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+// only async-signal-safe operations may be used in here
+extern "C" fn signal_handler(signum: c_int) {
+	let mask = match signum {
+		SIGTERM => SIGNAL_MASK_TERM,
+		SIGINT => SIGNAL_MASK_INT,
+		_ => 0,
+	};
+	SIGNALS_PENDING.fetch_or(mask, Ordering::SeqCst);
+	let writer = SIGNAL_PIPE[1].load(Ordering::SeqCst);
+	if writer >= 0 {
+		let saved = errno();
+		unsafe { write(writer, [0u8; 1].as_ptr() as *const c_void, 1) };
+		set_errno(saved);
+	}
+}
+
+// calls must be serialized by the caller
+pub(crate) fn install_signal_handler_impl(signal: Signal) -> Result<(), Error> {
+	if SIGNAL_PIPE[0].load(Ordering::SeqCst) < 0 {
+		let mut retfds = [0i32; 2];
+		let fds: *mut c_int = &mut retfds as *mut _ as *mut c_int;
+		if unsafe { pipe(fds) } != 0 {
+			let text = format!("could not create the signal pipe: {}", errno());
+			return Err(err!(ErrKind::IO, text));
+		}
+		// the handler must never block, the reader blocks in wait_signal_impl
+		unsafe { fcntl(retfds[1], F_SETFL, O_NONBLOCK) };
+		SIGNAL_PIPE[0].store(retfds[0], Ordering::SeqCst);
+		SIGNAL_PIPE[1].store(retfds[1], Ordering::SeqCst);
+	}
+
+	let signum = match signal {
+		Signal::Term => SIGTERM,
+		Signal::Int => SIGINT,
+	};
+	let mut action: libc::sigaction = unsafe { zeroed() };
+	action.sa_sigaction = signal_handler as *const () as usize;
+	action.sa_flags = SA_RESTART;
+	unsafe { sigemptyset(&mut action.sa_mask) };
+	if unsafe { libc::sigaction(signum, &action, null_mut()) } != 0 {
+		let text = format!(
+			"could not install the handler for {:?}: {}",
+			signal,
+			errno()
+		);
+		return Err(err!(ErrKind::IO, text));
+	}
+	Ok(())
+}
+
+// blocks until a signal is received and returns the mask of the signals received
+pub(crate) fn wait_signal_impl() -> Result<usize, Error> {
+	let reader = SIGNAL_PIPE[0].load(Ordering::SeqCst);
+	loop {
+		let pending = SIGNALS_PENDING.swap(0, Ordering::SeqCst);
+		if pending != 0 {
+			return Ok(pending);
+		}
+		let mut buf = [0u8; 1];
+		let len = unsafe { read(reader, buf.as_mut_ptr() as *mut c_void, 1) };
+		if len < 0 && errno().0 != EINTR {
+			let text = format!("reading the signal pipe failed: {}", errno());
+			return Err(err!(ErrKind::IO, text));
+		}
+	}
+}
+
 pub(crate) fn shutdown_impl(handle: Handle) -> Result<(), Error> {
 	unsafe {
 		shutdown(handle, 1);
@@ -90,6 +199,36 @@ pub(crate) fn close_impl_ctx(handle: Handle, _ctx: &mut EventHandlerContext) ->
 	Ok(())
 }
 
+pub(crate) fn deregister_raw_impl(
+	handle: Handle,
+	ctx: &mut EventHandlerContext,
+) -> Result<(), Error> {
+	debug!("deregister_raw_impl handle = {}", handle)?;
+	let mut kevs = vec![];
+	for filter in [EventFilter::EVFILT_READ, EventFilter::EVFILT_WRITE] {
+		kevs.push(kevent::new(
+			handle.try_into()?,
+			filter,
+			EventFlag::EV_DELETE,
+			FilterFlag::empty(),
+		));
+	}
+	for kev in kevs {
+		// a filter that was not registered returns ENOENT which is ok to ignore
+		unsafe {
+			kevent(
+				ctx.macos_ctx.selector,
+				&kev,
+				1,
+				std::ptr::null_mut(),
+				0,
+				std::ptr::null(),
+			)
+		};
+	}
+	Ok(())
+}
+
 pub(crate) fn read_impl(
 	handle: Handle,
 	buf: &mut [u8],
@@ -144,14 +283,199 @@ pub(crate) fn accept_impl(fd: RawFd, debug_info: &DebugInfo) -> Result<Option<Ha
 	Ok(Some(handle))
 }
 
+// there is no equivalent of TCP_DEFER_ACCEPT on this platform
+pub(crate) fn set_defer_accept_impl(handle: Handle, _secs: usize) -> Result<(), Error> {
+	warn!(
+		"EvhDeferAcceptSecs is not supported on this platform. Ignoring for handle {}",
+		handle
+	)?;
+	Ok(())
+}
+
+pub(crate) fn set_recv_buffer_size_impl(handle: Handle, size: usize) -> Result<(), Error> {
+	set_buffer_size(handle, libc::SO_RCVBUF, size, "SO_RCVBUF")
+}
+
+pub(crate) fn set_send_buffer_size_impl(handle: Handle, size: usize) -> Result<(), Error> {
+	set_buffer_size(handle, libc::SO_SNDBUF, size, "SO_SNDBUF")
+}
+
+pub(crate) fn recv_buffer_size_impl(handle: Handle) -> Result<usize, Error> {
+	buffer_size(handle, libc::SO_RCVBUF, "SO_RCVBUF")
+}
+
+pub(crate) fn send_buffer_size_impl(handle: Handle) -> Result<usize, Error> {
+	buffer_size(handle, libc::SO_SNDBUF, "SO_SNDBUF")
+}
+
+fn set_buffer_size(handle: Handle, opt: c_int, size: usize, name: &str) -> Result<(), Error> {
+	let optval: c_int = try_into!(size)?;
+	let res = unsafe {
+		libc::setsockopt(
+			handle,
+			libc::SOL_SOCKET,
+			opt,
+			&optval as *const _ as *const c_void,
+			size_of::<c_int>() as libc::socklen_t,
+		)
+	};
+	if res != 0 {
+		let text = format!("setting {} failed: {}", name, errno());
+		return Err(err!(ErrKind::IO, text));
+	}
+	Ok(())
+}
+
+// the kernel may adjust the requested value so read back what is actually in effect
+fn buffer_size(handle: Handle, opt: c_int, name: &str) -> Result<usize, Error> {
+	let mut optval: c_int = 0;
+	let mut len = size_of::<c_int>() as libc::socklen_t;
+	let res = unsafe {
+		libc::getsockopt(
+			handle,
+			libc::SOL_SOCKET,
+			opt,
+			&mut optval as *mut _ as *mut c_void,
+			&mut len,
+		)
+	};
+	if res != 0 {
+		let text = format!("getting {} failed: {}", name, errno());
+		return Err(err!(ErrKind::IO, text));
+	}
+	Ok(try_into!(optval)?)
+}
+
+pub(crate) fn peer_addr_impl(handle: Handle) -> Result<SocketAddr, Error> {
+	// borrow the socket without taking ownership so it's not closed when dropped
+	let strm = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(handle) });
+	Ok(strm.peer_addr()?)
+}
+
+pub(crate) fn local_addr_impl(handle: Handle) -> Result<SocketAddr, Error> {
+	// borrow the socket without taking ownership so it's not closed when dropped
+	let strm = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(handle) });
+	Ok(strm.local_addr()?)
+}
+
+pub(crate) fn fd_limit_impl() -> Option<usize> {
+	let mut rlim = libc::rlimit {
+		rlim_cur: 0,
+		rlim_max: 0,
+	};
+	let res = unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) };
+	if res != 0 || rlim.rlim_cur == libc::RLIM_INFINITY {
+		None
+	} else {
+		rlim.rlim_cur.try_into().ok()
+	}
+}
+
 pub(crate) fn create_connection(host: &str, port: u16) -> Result<Handle, Error> {
-	let strm = TcpStream::connect(format!("{}:{}", host, port))?;
+	let strm = TcpStream::connect(format!("{}:{}", host, port)).map_err(|e| {
+		let text = format!("could not connect to {}:{}: {}", host, port, e);
+		connect_error(&text, ConnectErrorKind::from(&e))
+	})?;
+	strm.set_nonblocking(true)?;
+	let fd = strm.into_raw_fd();
+
+	Ok(fd)
+}
+
+pub(crate) fn create_connection_timeout(
+	host: &str,
+	port: u16,
+	timeout: Duration,
+) -> Result<Handle, Error> {
+	let strm = connect_with_timeout(host, port, timeout)?;
+	strm.set_nonblocking(true)?;
+	let fd = strm.into_raw_fd();
+
+	Ok(fd)
+}
+
+pub(crate) fn create_connection_happy_eyeballs(
+	addrs: &[SocketAddr],
+	attempt_delay: Duration,
+) -> Result<Handle, Error> {
+	let strm = connect_happy_eyeballs(addrs, attempt_delay)?;
 	strm.set_nonblocking(true)?;
 	let fd = strm.into_raw_fd();
 
 	Ok(fd)
 }
 
+pub(crate) fn create_connection_bound(
+	host: &str,
+	port: u16,
+	local_addr: SocketAddr,
+) -> Result<Handle, Error> {
+	let mut last_err = None;
+	for remote in (host, port).to_socket_addrs()? {
+		// only addresses in the same family as the local address can be used
+		if remote.is_ipv4() != local_addr.is_ipv4() {
+			continue;
+		}
+
+		let family = match local_addr {
+			SocketAddr::V4(_) => libc::AF_INET,
+			SocketAddr::V6(_) => libc::AF_INET6,
+		};
+		let fd = unsafe { socket(family, libc::SOCK_STREAM, 0) };
+		if fd < 0 {
+			let text = format!("could not create socket: {}", errno());
+			return Err(err!(ErrKind::IO, text));
+		}
+		// take ownership so the socket is closed if bind or connect fails
+		let strm = unsafe { TcpStream::from_raw_fd(fd) };
+
+		let res = match local_addr {
+			SocketAddr::V4(addr) => bind(fd, &SockaddrIn::from(addr)),
+			SocketAddr::V6(addr) => bind(fd, &SockaddrIn6::from(addr)),
+		};
+		if let Err(e) = res {
+			let text = format!("could not bind to local address {}: {}", local_addr, e);
+			return Err(err!(ErrKind::IO, text));
+		}
+
+		let res = match remote {
+			SocketAddr::V4(addr) => connect(fd, &SockaddrIn::from(addr)),
+			SocketAddr::V6(addr) => connect(fd, &SockaddrIn6::from(addr)),
+		};
+		match res {
+			Ok(_) => {
+				strm.set_nonblocking(true)?;
+				return Ok(strm.into_raw_fd());
+			}
+			Err(e) => last_err = Some(e),
+		}
+	}
+
+	match last_err {
+		Some(e) => {
+			let text = format!(
+				"could not connect to {}:{} from local address {}: {}",
+				host, port, local_addr, e
+			);
+			Err(connect_error(
+				&text,
+				ConnectErrorKind::from_raw_os_error(e as i32),
+			))
+		}
+		None => {
+			let text = format!(
+				"no address for {}:{} matches the family of local address {}",
+				host, port, local_addr
+			);
+			Err(err!(ErrKind::IO, text))
+		}
+	}
+}
+
+pub(crate) fn interface_index(name: &str) -> Result<u32, Error> {
+	Ok(if_nametoindex(name)?)
+}
+
 pub(crate) fn create_listener(
 	addr: &str,
 	size: usize,
@@ -246,6 +570,33 @@ pub(crate) fn get_events_in(
 					FilterFlag::empty(),
 				));
 			}
+			EventTypeIn::DeregisterWrite => {
+				// the read filter is independent of the write filter and stays registered
+				kevs.push(kevent::new(
+					evt.handle.try_into()?,
+					EventFilter::EVFILT_WRITE,
+					EventFlag::EV_DELETE,
+					FilterFlag::empty(),
+				));
+			}
+			EventTypeIn::Raw(interest) => {
+				if interest == Interest::Read || interest == Interest::ReadWrite {
+					kevs.push(kevent::new(
+						evt.handle.try_into()?,
+						EventFilter::EVFILT_READ,
+						EventFlag::EV_ADD | EventFlag::EV_CLEAR,
+						FilterFlag::empty(),
+					));
+				}
+				if interest == Interest::Write || interest == Interest::ReadWrite {
+					kevs.push(kevent::new(
+						evt.handle.try_into()?,
+						EventFilter::EVFILT_WRITE,
+						EventFlag::EV_ADD | EventFlag::EV_CLEAR,
+						FilterFlag::empty(),
+					));
+				}
+			}
 		}
 	}
 	Ok(kevs)
@@ -267,8 +618,9 @@ pub(crate) fn get_events_out(
 	}
 	let results = {
 		set_errno(Errno(0));
-		let (requested, _lock) = ctx.wakeups[ctx.tid].pre_block()?;
-		let timeout = Duration::from_millis(if requested { 0 } else { config.timeout.into() });
+		let requested = ctx.wakeups[ctx.tid].pre_block()?;
+		let timeout = ctx.events_timeout(config.timeout)?;
+		let timeout = Duration::from_millis(if requested { 0 } else { timeout.into() });
 		unsafe {
 			kevent(
 				ctx.macos_ctx.selector,
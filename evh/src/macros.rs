@@ -25,10 +25,16 @@
 /// * EvhReadSlabSize ([`prim@usize`]) (optional) - The size of the slabs, in bytes, in the read slab
 /// allocator. The read slab allocator is where request data is temporarily stored before it can be
 /// processed and cleared. Each thread has it's own dedicated slab allocator. The default value is 512.
-/// * EvhReadSlabCount ([`prim@usize`]) (optional) - The count of the slabs, in the read slab
-/// allocator. The read slab allocator is where request data is temporarily stored before it can be
-/// processed and cleared. Each thread has it's own dedicated slab allocator. The default value is
-/// 1_000.
+/// * EvhReadSlabCount ([`prim@usize`]) (optional) - The total count of the slabs, in the read
+/// slab allocators of all threads. The read slab allocator is where request data is temporarily
+/// stored before it can be processed and cleared. Each thread has it's own dedicated slab
+/// allocator and the count is divided evenly (rounding up) between the threads. This option may
+/// not be specified with EvhReadSlabCountPerThread.
+/// * EvhReadSlabCountPerThread ([`prim@usize`]) (optional) - The count of the slabs in each
+/// thread's read slab allocator. Since the allocators are independent, one thread running out of
+/// slabs does not affect the connections of the other threads. The utilization of each allocator
+/// is reported in [`crate::EvhStats::read_slabs`]. If neither this option nor EvhReadSlabCount is
+/// specified, each thread has 1_000 slabs.
 /// * EvhTimeout ([`prim@u16`]) (optional) - The time, in milliseconds that the event handler will
 /// wait to get events, if none occur. This value is important for things like the house keeping
 /// frequency and stats configurations because if no events occur and this value is too great, a
@@ -42,9 +48,109 @@
 /// * EvhStatsUpdateMillis ([`prim@usize`]) - The frequency, in milliseconds, at which the stats
 /// data is returned. The stats data may be retrieved by calling the
 /// [`crate::EventHandler::wait_for_stats`] function. The default value is 5_000 (5 seconds).
+/// * EvhClientBindAddr ([`String`]) (optional) - The local address that client connections built
+/// with [`crate::EventHandler::build_client_connection`] are bound to before connecting. Either an
+/// ip address (e.g. "10.0.0.2") or an ip address and port (e.g. "10.0.0.2:9000") may be
+/// specified. IPv6 link-local addresses may include a scope id (e.g. "`[fe80::1%2]:0`" or
+/// "fe80::1%eth0"). By default, client connections are not explicitly bound.
+/// * EvhProxyProtocol ([`bool`]) (optional) - If set to true, each accepted connection must start
+/// with a PROXY protocol (v1 or v2) header, as sent by load balancers such as HAProxy or an AWS
+/// NLB. The header is consumed before any data is passed to the OnRead handler, the OnAccept
+/// handler is called once it has been received, and [`crate::Connection::peer_addr`] returns the
+/// source address that it advertises. Connections with a malformed header are closed. The
+/// default value is false.
+/// * EvhProxyHeaderTimeoutMillis ([`prim@usize`]) (optional) - The time, in milliseconds, that an
+/// accepted connection has to send a complete PROXY protocol header before it is closed. Only
+/// used if EvhProxyProtocol is set to true. The default value is 5_000 (5 seconds).
+/// * EvhDeferAcceptSecs ([`prim@usize`]) (optional) - If set, accepted connections are kept in
+/// the kernel until they send data (or this many seconds pass) so that port scanners which
+/// connect and never send anything do not use any resources. This is done with TCP_DEFER_ACCEPT
+/// on Linux and is applied when a server connection is added. On other platforms a warning is
+/// logged and the option is ignored. By default, it is not set.
+/// * EvhRequireDataWithinMillis ([`prim@usize`]) (optional) - If set, accepted connections that
+/// have not sent any data within this many milliseconds are closed with
+/// [`crate::CloseReason::IdleTimeout`]. Unlike EvhDeferAcceptSecs, this works on all platforms,
+/// but the connection is accepted first. By default, it is not set.
+/// * EvhMaxWriteBytesPerLoop ([`prim@usize`]) (optional) - The maximum number of bytes that
+/// queued data is written to a single connection in each iteration of the event loop. The rest
+/// is written in later iterations so that a connection sending a large response does not delay
+/// the other connections that are handled by the same thread. Data written directly by
+/// [`crate::WriteHandle::write`] that does not need to be queued is not limited. The default
+/// value is 0 (unlimited).
+/// * EvhFdWarnThresholdPercent ([`prim@usize`]) (optional) - The percentage of the file
+/// descriptor limit (RLIMIT_NOFILE) that the number of open connections may reach before a
+/// warning is logged. Warnings are logged at most once per minute. Must be between 1 and 100.
+/// The default value is 90.
+/// * EvhCallbackTimeoutMillis ([`prim@usize`]) (optional) - If set, a watchdog thread checks
+/// that no callback has been running for longer than this many milliseconds. When one has, a
+/// fatal-level message with the callback and the connection id is logged and
+/// [`crate::EvhStats::watchdog_trips`] is incremented. Must not be 0. The default is no
+/// watchdog.
+/// * EvhWatchdogAction ([`bmw_conf::WatchdogAction`]) (optional) - What the watchdog does when
+/// a callback has been running for longer than EvhCallbackTimeoutMillis. `Log` only logs the
+/// callback. `SpawnReplacement` also starts a new thread that takes over the connections of
+/// the stuck thread so that they are served again. The connection whose callback is stuck stays
+/// with the stuck thread, which closes it with [`crate::CloseReason::CallbackTimeout`] and
+/// exits once the callback returns. The read slabs also stay with the stuck thread, so
+/// connections holding read data that was not cleared (or a partial PROXY protocol header) are
+/// closed by the replacement with [`crate::CloseReason::ThreadReplaced`] instead of being
+/// served. User data, raw registrations and EvhRequireDataWithinMillis deadlines are not
+/// carried over to the replacement thread and threads stuck in on_data_chunk or on_raw_event
+/// are not replaced. Requires EvhCallbackTimeoutMillis. The default value is `Log`.
+/// * EvhRecvBufferSize ([`prim@usize`]) (optional) - The size of the kernel receive buffer
+/// (SO_RCVBUF) to set on accepted and client connections. 0 leaves the kernel default in place.
+/// The effective value can be read back with [`crate::Connection::recv_buffer_size`]. The
+/// default value is 0.
+/// * EvhSendBufferSize ([`prim@usize`]) (optional) - The size of the kernel send buffer
+/// (SO_SNDBUF) to set on accepted and client connections. 0 leaves the kernel default in place.
+/// The effective value can be read back with [`crate::Connection::send_buffer_size`]. The
+/// default value is 0.
+/// * EvhStrictSockOpts ([`bool`]) (optional) - If set to true, a failure to apply
+/// EvhRecvBufferSize or EvhSendBufferSize is an error: accepted connections are closed and
+/// adding a client connection fails. Otherwise, a warning is logged and the connection is used
+/// with the kernel defaults. The default value is false.
+/// * EvhCaptureAll ([`prim@str`]) (optional) - If set, the raw traffic of every accepted and
+/// client connection is recorded in this directory as if
+/// [`crate::Connection::enable_capture`] had been called on it. The default is not to capture.
+/// * EvhName ([`prim@str`]) (optional) - The name of this evh. If set, it prefixes the lines
+/// that the evh logs (see [`crate::EventHandler::set_logger`]) and is returned in
+/// [`crate::EvhStats::name`]. The default is no name.
+/// * EvhStrictOrdering ([`bool`]) (optional) - If set to true, the evh panics if it ever
+/// dispatches OnRead for an accepted connection before its OnAccept callback has returned.
+/// This is meant for tests. The default value is false.
+/// * EvhMaxSlabsPerConnection ([`prim@usize`]) (optional) - The maximum number of read slabs
+/// that a single connection may hold. When a connection needs more, EvhSlabQuotaAction is
+/// taken. Must not be 0. The default is no quota.
+/// * EvhSlabQuotaAction ([`std::string::String`]) (optional) - What happens when a connection
+/// exceeds EvhMaxSlabsPerConnection. "Pause" stops reading from the connection and executes the
+/// callback set with [`crate::EventHandler::set_on_slab_quota`]. "Close" sends
+/// EvhOutOfSlabsMessage and closes the connection with [`crate::CloseReason::OutOfSlabs`].
+/// Requires EvhMaxSlabsPerConnection. The default value is "Pause".
+/// * EvhStrictKeys ([`bool`]) (optional) - If set to true, registering a key that is already
+/// registered to another connection with [`crate::EventHandler::register_key`] is an error.
+/// Otherwise, the key is moved to the new connection and a warning is logged. The default value
+/// is false.
+/// * EvhStartTimeoutMillis ([`prim@usize`]) (optional) - How long [`crate::EventHandler::start`]
+/// waits for every thread to finish initializing before returning an error. Must not be 0. The
+/// default value is 5,000 (5 seconds).
+/// * EvhOnAcceptBudgetMicros ([`prim@usize`]) (optional) - The time in microseconds that a thread
+/// may spend registering accepted connections and calling on_accept in one event loop. Once it
+/// is exceeded, the rest of the connections are deferred to the next loop so that reads on the
+/// existing connections are not delayed by a burst of connects. At least one connection is
+/// registered in each loop. The default value is 0 (no limit).
+/// * EvhMemoryBudgetBytes ([`prim@usize`]) (optional) - A cap on the memory used by the read
+/// slabs, the queued write data, the connection attachments and the capture queue together. When
+/// it is exceeded, reads on the largest consumers are paused first. If the use stays above the
+/// budget for another housekeeping interval, accepted connections are rejected, and after that
+/// the largest consumers are closed with [`crate::CloseReason::MemoryPressure`]. Must exceed the
+/// fixed cost of the read slabs and threads. The default value is no limit.
 /// * Debug ([`bool`]) - If this parameter is set to true, additional debugging information will be
 /// logged. This parameter must NOT be set in a production configuration.
 ///
+/// EvhHouseKeeperFrequencyMillis, EvhStatsUpdateMillis, EvhTimeout, EvhMaxWriteBytesPerLoop and
+/// EvhOutOfSlabsMessage may also be changed while the evh is running with
+/// [`crate::EventHandler::update_config`].
+///
 /// # Returns
 /// A `Ok(Box<dyn EventHandler<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic> + Send + Sync>)`
 /// is returned on success. A [`bmw_err::Error`] is returned on failure.
@@ -52,10 +158,15 @@
 /// # Errors
 /// * [`bmw_err::ErrKind::Configuration`] - If any values are specified other than the allowed
 /// values mentioned above or if there are any duplicate parameters specified.
-/// * [`bmw_err::ErrKind::Configuration`] - If EvhReadSlabCount is 0.
+/// * [`bmw_err::ErrKind::Configuration`] - If EvhReadSlabCount or EvhReadSlabCountPerThread is 0.
+/// * [`bmw_err::ErrKind::Configuration`] - If both EvhReadSlabCount and EvhReadSlabCountPerThread
+/// are specified.
 /// * [`bmw_err::ErrKind::Configuration`] - If EvhReadSlabSize is less than 25.
 /// * [`bmw_err::ErrKind::Configuration`] - If EvhTimeout is 0.
 /// * [`bmw_err::ErrKind::Configuration`] - If EvhHouseKeeperFrequencyMillis is 0.
+/// * [`bmw_err::ErrKind::Configuration`] - If EvhProxyHeaderTimeoutMillis is 0.
+/// * [`bmw_err::ErrKind::Configuration`] - If EvhDeferAcceptSecs or EvhRequireDataWithinMillis is 0.
+/// * [`bmw_err::ErrKind::Configuration`] - If EvhClientBindAddr is not a valid address.
 ///
 /// # See also
 /// See the [`crate`] documentation as well for the background information and motivation
@@ -123,13 +234,13 @@
 ///     })?;
 ///
 ///     // set the handler to be executed when a connection is closed
-///     evh.set_on_close(move |connection, ctx| -> Result<(), Error> {
+///     evh.set_on_close(move |connection, reason, ctx| -> Result<(), Error> {
 ///         Ok(())
 ///     })?;
 ///
 ///     // set a housekeeper handler to be executed on a per thread basis
 ///     // at the configured frequency
-///     evh.set_on_housekeeper(move |ctx| -> Result<(), Error> {
+///     evh.set_on_housekeeper(move |hk, ctx| -> Result<(), Error> {
 ///         Ok(())
 ///     })?;
 ///
@@ -167,6 +278,8 @@ macro_rules! evh {
 /// only). This macro is useful for testing, but also for simple use cases where only on read
 /// processing is necessary. It can allow the user to avoid specifying the unused boilerplate code
 /// that is required since all of the handlers must be specified with the [`crate::evh!`] macro.
+/// The other handlers are not supported by the returned [`crate::EventHandler`] and trying to set
+/// them results in a [`bmw_err::ErrKind::Configuration`] error.
 /// The result is returned as a
 /// `Box<dyn EventHandler<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic> + Send + Sync>`. If an
 /// error occurs, a [`bmw_err::Error`] is rerurned.
@@ -177,10 +290,16 @@ macro_rules! evh {
 /// * EvhReadSlabSize ([`prim@usize`]) (optional) - The size of the slabs, in bytes, in the read slab
 /// allocator. The read slab allocator is where request data is temporarily stored before it can be
 /// processed and cleared. Each thread has it's own dedicated slab allocator. The default value is 512.
-/// * EvhReadSlabCount ([`prim@usize`]) (optional) - The count of the slabs, in the read slab
-/// allocator. The read slab allocator is where request data is temporarily stored before it can be
-/// processed and cleared. Each thread has it's own dedicated slab allocator. The default value is
-/// 1_000.
+/// * EvhReadSlabCount ([`prim@usize`]) (optional) - The total count of the slabs, in the read
+/// slab allocators of all threads. The read slab allocator is where request data is temporarily
+/// stored before it can be processed and cleared. Each thread has it's own dedicated slab
+/// allocator and the count is divided evenly (rounding up) between the threads. This option may
+/// not be specified with EvhReadSlabCountPerThread.
+/// * EvhReadSlabCountPerThread ([`prim@usize`]) (optional) - The count of the slabs in each
+/// thread's read slab allocator. Since the allocators are independent, one thread running out of
+/// slabs does not affect the connections of the other threads. The utilization of each allocator
+/// is reported in [`crate::EvhStats::read_slabs`]. If neither this option nor EvhReadSlabCount is
+/// specified, each thread has 1_000 slabs.
 /// * EvhTimeout ([`prim@u16`]) (optional) - The time, in milliseconds that the event handler will
 /// wait to get events, if none occur. This value is important for things like the house keeping
 /// frequency and stats configurations because if no events occur and this value is too great, a
@@ -194,9 +313,109 @@ macro_rules! evh {
 /// * EvhStatsUpdateMillis ([`prim@usize`]) - The frequency, in milliseconds, at which the stats
 /// data is returned. The stats data may be retrieved by calling the
 /// [`crate::EventHandler::wait_for_stats`] function. The default value is 5_000 (5 seconds).
+/// * EvhClientBindAddr ([`String`]) (optional) - The local address that client connections built
+/// with [`crate::EventHandler::build_client_connection`] are bound to before connecting. Either an
+/// ip address (e.g. "10.0.0.2") or an ip address and port (e.g. "10.0.0.2:9000") may be
+/// specified. IPv6 link-local addresses may include a scope id (e.g. "`[fe80::1%2]:0`" or
+/// "fe80::1%eth0"). By default, client connections are not explicitly bound.
+/// * EvhProxyProtocol ([`bool`]) (optional) - If set to true, each accepted connection must start
+/// with a PROXY protocol (v1 or v2) header, as sent by load balancers such as HAProxy or an AWS
+/// NLB. The header is consumed before any data is passed to the OnRead handler, the OnAccept
+/// handler is called once it has been received, and [`crate::Connection::peer_addr`] returns the
+/// source address that it advertises. Connections with a malformed header are closed. The
+/// default value is false.
+/// * EvhProxyHeaderTimeoutMillis ([`prim@usize`]) (optional) - The time, in milliseconds, that an
+/// accepted connection has to send a complete PROXY protocol header before it is closed. Only
+/// used if EvhProxyProtocol is set to true. The default value is 5_000 (5 seconds).
+/// * EvhDeferAcceptSecs ([`prim@usize`]) (optional) - If set, accepted connections are kept in
+/// the kernel until they send data (or this many seconds pass) so that port scanners which
+/// connect and never send anything do not use any resources. This is done with TCP_DEFER_ACCEPT
+/// on Linux and is applied when a server connection is added. On other platforms a warning is
+/// logged and the option is ignored. By default, it is not set.
+/// * EvhRequireDataWithinMillis ([`prim@usize`]) (optional) - If set, accepted connections that
+/// have not sent any data within this many milliseconds are closed with
+/// [`crate::CloseReason::IdleTimeout`]. Unlike EvhDeferAcceptSecs, this works on all platforms,
+/// but the connection is accepted first. By default, it is not set.
+/// * EvhMaxWriteBytesPerLoop ([`prim@usize`]) (optional) - The maximum number of bytes that
+/// queued data is written to a single connection in each iteration of the event loop. The rest
+/// is written in later iterations so that a connection sending a large response does not delay
+/// the other connections that are handled by the same thread. Data written directly by
+/// [`crate::WriteHandle::write`] that does not need to be queued is not limited. The default
+/// value is 0 (unlimited).
+/// * EvhFdWarnThresholdPercent ([`prim@usize`]) (optional) - The percentage of the file
+/// descriptor limit (RLIMIT_NOFILE) that the number of open connections may reach before a
+/// warning is logged. Warnings are logged at most once per minute. Must be between 1 and 100.
+/// The default value is 90.
+/// * EvhCallbackTimeoutMillis ([`prim@usize`]) (optional) - If set, a watchdog thread checks
+/// that no callback has been running for longer than this many milliseconds. When one has, a
+/// fatal-level message with the callback and the connection id is logged and
+/// [`crate::EvhStats::watchdog_trips`] is incremented. Must not be 0. The default is no
+/// watchdog.
+/// * EvhWatchdogAction ([`bmw_conf::WatchdogAction`]) (optional) - What the watchdog does when
+/// a callback has been running for longer than EvhCallbackTimeoutMillis. `Log` only logs the
+/// callback. `SpawnReplacement` also starts a new thread that takes over the connections of
+/// the stuck thread so that they are served again. The connection whose callback is stuck stays
+/// with the stuck thread, which closes it with [`crate::CloseReason::CallbackTimeout`] and
+/// exits once the callback returns. The read slabs also stay with the stuck thread, so
+/// connections holding read data that was not cleared (or a partial PROXY protocol header) are
+/// closed by the replacement with [`crate::CloseReason::ThreadReplaced`] instead of being
+/// served. User data, raw registrations and EvhRequireDataWithinMillis deadlines are not
+/// carried over to the replacement thread and threads stuck in on_data_chunk or on_raw_event
+/// are not replaced. Requires EvhCallbackTimeoutMillis. The default value is `Log`.
+/// * EvhRecvBufferSize ([`prim@usize`]) (optional) - The size of the kernel receive buffer
+/// (SO_RCVBUF) to set on accepted and client connections. 0 leaves the kernel default in place.
+/// The effective value can be read back with [`crate::Connection::recv_buffer_size`]. The
+/// default value is 0.
+/// * EvhSendBufferSize ([`prim@usize`]) (optional) - The size of the kernel send buffer
+/// (SO_SNDBUF) to set on accepted and client connections. 0 leaves the kernel default in place.
+/// The effective value can be read back with [`crate::Connection::send_buffer_size`]. The
+/// default value is 0.
+/// * EvhStrictSockOpts ([`bool`]) (optional) - If set to true, a failure to apply
+/// EvhRecvBufferSize or EvhSendBufferSize is an error: accepted connections are closed and
+/// adding a client connection fails. Otherwise, a warning is logged and the connection is used
+/// with the kernel defaults. The default value is false.
+/// * EvhCaptureAll ([`prim@str`]) (optional) - If set, the raw traffic of every accepted and
+/// client connection is recorded in this directory as if
+/// [`crate::Connection::enable_capture`] had been called on it. The default is not to capture.
+/// * EvhName ([`prim@str`]) (optional) - The name of this evh. If set, it prefixes the lines
+/// that the evh logs (see [`crate::EventHandler::set_logger`]) and is returned in
+/// [`crate::EvhStats::name`]. The default is no name.
+/// * EvhStrictOrdering ([`bool`]) (optional) - If set to true, the evh panics if it ever
+/// dispatches OnRead for an accepted connection before its OnAccept callback has returned.
+/// This is meant for tests. The default value is false.
+/// * EvhMaxSlabsPerConnection ([`prim@usize`]) (optional) - The maximum number of read slabs
+/// that a single connection may hold. When a connection needs more, EvhSlabQuotaAction is
+/// taken. Must not be 0. The default is no quota.
+/// * EvhSlabQuotaAction ([`std::string::String`]) (optional) - What happens when a connection
+/// exceeds EvhMaxSlabsPerConnection. "Pause" stops reading from the connection and executes the
+/// callback set with [`crate::EventHandler::set_on_slab_quota`]. "Close" sends
+/// EvhOutOfSlabsMessage and closes the connection with [`crate::CloseReason::OutOfSlabs`].
+/// Requires EvhMaxSlabsPerConnection. The default value is "Pause".
+/// * EvhStrictKeys ([`bool`]) (optional) - If set to true, registering a key that is already
+/// registered to another connection with [`crate::EventHandler::register_key`] is an error.
+/// Otherwise, the key is moved to the new connection and a warning is logged. The default value
+/// is false.
+/// * EvhStartTimeoutMillis ([`prim@usize`]) (optional) - How long [`crate::EventHandler::start`]
+/// waits for every thread to finish initializing before returning an error. Must not be 0. The
+/// default value is 5,000 (5 seconds).
+/// * EvhOnAcceptBudgetMicros ([`prim@usize`]) (optional) - The time in microseconds that a thread
+/// may spend registering accepted connections and calling on_accept in one event loop. Once it
+/// is exceeded, the rest of the connections are deferred to the next loop so that reads on the
+/// existing connections are not delayed by a burst of connects. At least one connection is
+/// registered in each loop. The default value is 0 (no limit).
+/// * EvhMemoryBudgetBytes ([`prim@usize`]) (optional) - A cap on the memory used by the read
+/// slabs, the queued write data, the connection attachments and the capture queue together. When
+/// it is exceeded, reads on the largest consumers are paused first. If the use stays above the
+/// budget for another housekeeping interval, accepted connections are rejected, and after that
+/// the largest consumers are closed with [`crate::CloseReason::MemoryPressure`]. Must exceed the
+/// fixed cost of the read slabs and threads. The default value is no limit.
 /// * Debug ([`bool`]) - If this parameter is set to true, additional debugging information will be
 /// logged. This parameter must NOT be set in a production configuration.
 ///
+/// EvhHouseKeeperFrequencyMillis, EvhStatsUpdateMillis, EvhTimeout, EvhMaxWriteBytesPerLoop and
+/// EvhOutOfSlabsMessage may also be changed while the evh is running with
+/// [`crate::EventHandler::update_config`].
+///
 /// # Returns
 /// A `Ok(Box<dyn EventHandler<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic> + Send + Sync>)`
 /// is returned on success. A [`bmw_err::Error`] is returned on failure.
@@ -204,10 +423,15 @@ macro_rules! evh {
 /// # Errors
 /// * [`bmw_err::ErrKind::Configuration`] - If any values are specified other than the allowed
 /// values mentioned above or if there are any duplicate parameters specified.
-/// * [`bmw_err::ErrKind::Configuration`] - If EvhReadSlabCount is 0.
+/// * [`bmw_err::ErrKind::Configuration`] - If EvhReadSlabCount or EvhReadSlabCountPerThread is 0.
+/// * [`bmw_err::ErrKind::Configuration`] - If both EvhReadSlabCount and EvhReadSlabCountPerThread
+/// are specified.
 /// * [`bmw_err::ErrKind::Configuration`] - If EvhReadSlabSize is less than 25.
 /// * [`bmw_err::ErrKind::Configuration`] - If EvhTimeout is 0.
 /// * [`bmw_err::ErrKind::Configuration`] - If EvhHouseKeeperFrequencyMillis is 0.
+/// * [`bmw_err::ErrKind::Configuration`] - If EvhProxyHeaderTimeoutMillis is 0.
+/// * [`bmw_err::ErrKind::Configuration`] - If EvhDeferAcceptSecs or EvhRequireDataWithinMillis is 0.
+/// * [`bmw_err::ErrKind::Configuration`] - If EvhClientBindAddr is not a valid address.
 ///
 /// # See also
 /// See the [`crate`] documentation as well for the background information and motivation
@@ -288,11 +512,11 @@ macro_rules! evh_oro {
                                         Ok(())
                                 })?;
 
-                                evh.set_on_close(move |_connection, _ctx| -> Result<(), Error> {
+                                evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> {
                                         Ok(())
                                 })?;
 
-                                evh.set_on_housekeeper(move |_ctx| -> Result<(), Error> {
+                                evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> {
                                         Ok(())
                                 })?;
 
@@ -300,6 +524,8 @@ macro_rules! evh_oro {
                                         Ok(())
                                 })?;
 
+                                evh.set_on_read_only()?;
+
                                 Ok(evh)},
                         Err(e) => {
                                 let text = format!("build_evh resulted in error: {}", e);
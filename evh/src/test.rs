@@ -18,21 +18,46 @@
 #[cfg(test)]
 mod test {
 	use crate as bmw_evh;
+	use crate::evh::{
+		check_fd_limit, drain_zeroize, extend_zeroize, parse_bind_addr, parse_proxy_header,
+		watch_expired, watch_millis, zeroize,
+	};
 	use crate::types::{
 		ConnectionType, ConnectionVariant, DebugInfo, EventHandlerCallbacks, EventHandlerConfig,
 		EventHandlerContext, EventHandlerImpl, EventHandlerState, EvhStats, GlobalStats,
-		UserContextImpl, Wakeup, WriteHandle, WriteState,
+		ReloadableConfig, UserContextImpl, Wakeup, WriteHandle, WriteInterest, WriteState,
+	};
+	use crate::{
+		evh, evh_oro, ChunkAction, CloseReason, CloseReasonStats, CompressionFormat, Connection,
+		ConnectionId, EventHandler, EvhBuilder, EvhMetric, ExportedHandle, HousekeeperContext,
+		MemoryPressure, MetricsRecorder, NoOnAccept, NoOnClose, NoOnHousekeeper, NoOnPanic,
+		NoOnRead, OriginStats, ProtocolHandler, ResponseExtractor, ThreadHealth, UserContext,
 	};
-	use crate::{evh, evh_oro, Connection, EvhBuilder, UserContext};
+	use bmw_conf::{ConfigHistory, ConfigOption, WatchdogAction};
+	use bmw_deps::flate2::bufread::{GzDecoder, ZlibDecoder};
+	use bmw_deps::rand::random;
 	use bmw_err::*;
 	use bmw_log::*;
+	use bmw_ser::{read_frame, BinReader, FrameOptions, Reader};
 	use bmw_test::*;
 	use bmw_util::*;
-	use std::collections::{HashMap, VecDeque};
+	use std::collections::{HashMap, HashSet, VecDeque};
+	use std::fs::File;
+	use std::future::Future;
 	use std::io::{Read, Write};
-	use std::net::TcpStream;
+	use std::net::{
+		IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, SocketAddrV6, TcpListener, TcpStream,
+		UdpSocket,
+	};
+	use std::path::PathBuf;
+	use std::pin::Pin;
 	use std::str::from_utf8;
+	use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+	use std::sync::mpsc::sync_channel;
+	use std::sync::Arc;
+	use std::task::{Context, Poll, Wake, Waker};
 	use std::thread;
+	use std::time::{Duration, Instant};
 
 	#[cfg(target_os = "linux")]
 	use crate::linux::*;
@@ -142,7 +167,7 @@ mod test {
 		let (tx2, rx2) = test_info.sync_channel();
 		let mut counter = lock_box!(0)?;
 
-		evh.set_on_close(move |_connection, _ctx| -> Result<(), Error> {
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> {
 			info!("onClose")?;
 			if rlock!(counter) == 1 {
 				tx2.send(())?;
@@ -151,7 +176,7 @@ mod test {
 			Err(err!(ErrKind::Test, "simulated error"))
 		})?;
 
-		evh.set_on_housekeeper(move |_ctx| -> Result<(), Error> {
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> {
 			info!("onHousekeeper")?;
 			Ok(())
 		})?;
@@ -226,13 +251,13 @@ mod test {
 			Err(err!(ErrKind::Test, "on accept err"))
 		})?;
 
-		evh.set_on_close(move |_connection, _ctx| -> Result<(), Error> {
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> {
 			Err(err!(ErrKind::Test, "on close err"))
 		})?;
 
 		let mut tx2_sent = lock_box!(false)?;
 
-		evh.set_on_housekeeper(move |_ctx| -> Result<(), Error> {
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> {
 			if !rlock!(tx2_sent) {
 				tx2.send(())?;
 			}
@@ -363,12 +388,12 @@ mod test {
 			Ok(())
 		})?;
 
-		evh.set_on_close(move |_connection, _ctx| -> Result<(), Error> {
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> {
 			info!("onClose")?;
 			Ok(())
 		})?;
 
-		evh.set_on_housekeeper(move |_ctx| -> Result<(), Error> {
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> {
 			info!("onHousekeeper")?;
 			Ok(())
 		})?;
@@ -544,7 +569,7 @@ mod test {
 			Ok(())
 		})?;
 
-		evh.set_on_close(move |_connection, _ctx| -> Result<(), Error> {
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> {
 			info!("onClose")?;
 			Ok(())
 		})?;
@@ -557,7 +582,7 @@ mod test {
 
 		let (tx, rx) = test_info.sync_channel();
 
-		evh.set_on_housekeeper(move |_ctx| -> Result<(), Error> {
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> {
 			info!("onHousekeeper")?;
 			let id = thread::current().id();
 			let mut thread_hash = thread_hash.wlock()?;
@@ -658,6 +683,85 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_evh_panic_info() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			EvhThreads(1),
+			EvhTimeout(u16::MAX),
+			EvhHouseKeeperFrequencyMillis(usize::MAX)
+		)?;
+
+		let lines = lock_box!(vec![])?;
+		let lines_clone = lines.clone();
+		let mut infos = lock_box!(vec![])?;
+		let infos_clone = infos.clone();
+		let (tx, rx) = test_info.sync_channel();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			let mut lines = lines.clone();
+			match &data[..] {
+				b"str" => {
+					wlock!(lines).push(line!() + 1);
+					panic!("str payload");
+				}
+				b"string" => {
+					wlock!(lines).push(line!() + 1);
+					panic!("string payload {}", data.len());
+				}
+				b"any" => {
+					wlock!(lines).push(line!() + 1);
+					std::panic::panic_any(data.len());
+				}
+				_ => connection.write_handle()?.write(&data)?,
+			}
+			Ok(())
+		})?;
+		evh.set_on_panic_info(Box::new(move |_ctx, info| -> Result<(), Error> {
+			wlock!(infos).push(info.clone());
+			tx.send(())?;
+			Ok(())
+		}))?;
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let mut buf = [0u8; 100];
+		for payload in [&b"str"[..], b"string", b"any"] {
+			let mut strm = TcpStream::connect(addr.clone())?;
+			strm.write(payload)?;
+			rx.recv()?;
+			assert_eq!(strm.read(&mut buf)?, 0);
+		}
+
+		let mut strm = TcpStream::connect(addr)?;
+		strm.write(b"x")?;
+		assert_eq!(strm.read(&mut buf)?, 1);
+
+		let infos = rlock!(infos_clone).clone();
+		let lines = rlock!(lines_clone).clone();
+		assert_eq!(infos.len(), 3);
+		assert_eq!(infos[0].message(), "str payload");
+		assert_eq!(infos[1].message(), "string payload 6");
+		assert_eq!(infos[2].message(), "Box<dyn Any>");
+		for i in 0..3 {
+			assert!(infos[i].file().unwrap().ends_with("test.rs"));
+			assert_eq!(infos[i].line(), Some(lines[i]));
+		}
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_evh_panic_advanced() -> Result<(), Error> {
 		let test_info = test_info!()?;
@@ -931,135 +1035,127 @@ mod test {
 	}
 
 	#[test]
-	fn test_evh_invalid_configs() -> Result<(), Error> {
-		// timeout == 0
-		let error;
-		match evh_oro!(
+	fn test_evh_trigger_on_read_ordering() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
 			Debug(false),
-			EvhTimeout(0),
+			EvhTimeout(u16::MAX),
 			EvhThreads(1),
-			EvhReadSlabSize(100),
-			EvhStatsUpdateMillis(3_000)
-		) {
-			Ok(mut evh) => {
-				evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
-				error = false;
-			}
-			Err(_) => {
-				error = true;
-			}
-		}
-		assert!(error);
+			EvhReadSlabSize(100)
+		)?;
 
-		// read_slab_size < 25
-		let error;
-		match evh_oro!(
-			Debug(false),
-			EvhTimeout(1),
-			EvhThreads(1),
-			EvhReadSlabSize(10),
-			EvhStatsUpdateMillis(3_000)
-		) {
-			Ok(mut evh) => {
-				evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
-				error = false;
-			}
-			Err(_) => {
-				error = true;
-			}
-		}
-		assert!(error);
+		let payloads = 300;
+		let triggers = 100;
+
+		// number of complete payloads received so far
+		let mut received = lock_box!(0usize)?;
+		// for each synthetic call, the number of payloads received before it
+		let mut markers: Box<dyn LockBox<Vec<usize>>> = lock_box!(vec![])?;
+		// for each payload, the number of synthetic calls that occurred before it was received
+		let mut delivered: Box<dyn LockBox<Vec<usize>>> = lock_box!(vec![])?;
+		let mut wh: Box<dyn LockBox<Option<WriteHandle>>> = lock_box!(None)?;
+		let received_clone = received.clone();
+		let markers_clone = markers.clone();
+		let delivered_clone = delivered.clone();
+		let wh_clone = wh.clone();
 
-		// read_slab_count == 0
-		let error;
-		match evh_oro!(
-			Debug(false),
-			EvhTimeout(1),
-			EvhThreads(1),
-			EvhReadSlabSize(100),
-			EvhReadSlabCount(0),
-			EvhStatsUpdateMillis(3_000)
-		) {
-			Ok(mut evh) => {
-				evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
-				error = false;
-			}
-			Err(_) => {
-				error = true;
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			if ctx.is_synthetic() {
+				let count = rlock!(received);
+				wlock!(markers).push(count);
+				return Ok(());
 			}
-		}
-		assert!(error);
 
-		// housekeeping_frequency_millis == 0
-		let error;
-		match evh_oro!(
-			Debug(false),
-			EvhTimeout(1),
-			EvhThreads(1),
-			EvhReadSlabSize(100),
-			EvhHouseKeeperFrequencyMillis(0),
-			EvhStatsUpdateMillis(3_000)
-		) {
-			Ok(mut evh) => {
-				evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
-				error = false;
-			}
-			Err(_) => {
-				error = true;
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				let next_chunk = next_chunk.unwrap();
+				data.extend(next_chunk.data());
 			}
-		}
-		assert!(error);
-
-		Ok(())
-	}
+			ctx.clear_all(connection)?;
 
-	#[test]
-	fn test_evh_stats() -> Result<(), Error> {
-		let test_info = test_info!()?;
-		let mut evh = evh_oro!(
-			Debug(false),
-			EvhTimeout(10),
-			EvhThreads(1),
-			EvhReadSlabSize(100),
-			EvhStatsUpdateMillis(3_000)
-		)?;
+			let marker_count = rlock!(markers).len();
+			for _ in data.iter().filter(|b| **b == b';') {
+				wlock!(received) += 1;
+				wlock!(delivered).push(marker_count);
+			}
 
-		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			ctx.clear_all(connection)?;
+			if rlock!(wh).is_none() {
+				wlock!(wh) = Some(connection.write_handle()?);
+			}
 			Ok(())
 		})?;
 
 		evh.start()?;
 		let port = test_info.port();
 		let addr = format!("127.0.0.1:{}", port);
-		info!("addr={}", addr)?;
 		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 		evh.add_server_connection(conn)?;
 
-		{
-			let _strm = TcpStream::connect(addr.clone())?;
-			let _strm = TcpStream::connect(addr.clone())?;
-			let _strm = TcpStream::connect(addr.clone())?;
-			let _strm = TcpStream::connect(addr.clone())?;
-			let _strm = TcpStream::connect(addr.clone())?;
+		let mut strm = TcpStream::connect(addr)?;
+		strm.write(b"0;")?;
+
+		// number of payloads written by the client before each write / trigger
+		let mut written = lock_box!(1usize)?;
+		let written_clone = written.clone();
+		let markers_clone2 = markers_clone.clone();
+
+		// the writer records how many synthetic calls it had observed before each write
+		let writer = thread::spawn(move || -> Result<(Vec<usize>, TcpStream), Error> {
+			let mut observed = vec![0];
+			for i in 1..payloads {
+				observed.push(rlock!(markers_clone2).len());
+				strm.write(format!("{};", i).as_bytes())?;
+				wlock!(written) += 1;
+			}
+			// return the stream so the connection stays open until the test completes
+			Ok((observed, strm))
+		});
+
+		// the trigger thread records how many payloads were written before each trigger
+		let trigger = thread::spawn(move || -> Result<Vec<usize>, Error> {
+			let mut wh = loop {
+				if let Some(wh) = rlock!(wh_clone).clone() {
+					break wh;
+				}
+				sleep(Duration::from_millis(1));
+			};
+			let mut expected = vec![];
+			for _ in 0..triggers {
+				expected.push(rlock!(written_clone));
+				wh.trigger_on_read()?;
+			}
+			Ok(expected)
+		});
+
+		let (observed, _strm) = writer.join().unwrap()?;
+		let expected = trigger.join().unwrap()?;
+
+		loop {
+			if rlock!(received_clone) == payloads && rlock!(markers_clone).len() == triggers {
+				break;
+			}
+			sleep(Duration::from_millis(1));
 		}
-		let mut strm = TcpStream::connect(addr.clone())?;
-		strm.write(b"test")?;
 
-		let stats = evh.wait_for_stats()?;
-		info!("stats={:?}", stats)?;
+		// each synthetic call must be preceded by all data written before its trigger
+		let markers = rlock!(markers_clone).clone();
+		for i in 0..triggers {
+			assert!(markers[i] >= expected[i]);
+		}
 
-		// 1 left in scope has not disconnecte yet
-		assert_eq!(stats.accepts, 6);
-		assert_eq!(stats.closes, 5);
-		assert_eq!(stats.reads, 1);
-		assert!(stats.event_loops != 0);
+		// data written after a synthetic call must be delivered after it
+		let delivered = rlock!(delivered_clone).clone();
+		for i in 0..payloads {
+			assert!(delivered[i] >= observed[i]);
+		}
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_trigger_empty() -> Result<(), Error> {
+	fn test_evh_trigger_on_read_stress() -> Result<(), Error> {
 		let test_info = test_info!()?;
 		let mut evh = evh_oro!(
 			Debug(false),
@@ -1068,15 +1164,19 @@ mod test {
 			EvhReadSlabSize(100)
 		)?;
 
-		let mut recv_msg = lock_box!(false)?;
-		let recv_msg_clone = recv_msg.clone();
-		let (tx, rx) = test_info.sync_channel();
+		let triggers = 1_000;
+		let mut synthetic = lock_box!(0usize)?;
+		let mut wh: Box<dyn LockBox<Option<WriteHandle>>> = lock_box!(None)?;
+		let synthetic_clone = synthetic.clone();
+		let wh_clone = wh.clone();
 
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			info!("onRead")?;
+			if ctx.is_synthetic() {
+				wlock!(synthetic) += 1;
+			} else if rlock!(wh).is_none() {
+				wlock!(wh) = Some(connection.write_handle()?);
+			}
 			ctx.clear_all(connection)?;
-			wlock!(recv_msg) = true;
-			tx.send(())?;
 			Ok(())
 		})?;
 
@@ -1086,37 +1186,66 @@ mod test {
 		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 		evh.add_server_connection(conn)?;
 
-		let conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		let mut wh = evh.add_client_connection(conn2)?;
-		wh.trigger_on_read()?;
+		let mut strm = TcpStream::connect(addr)?;
+		strm.write(b"hi")?;
 
-		rx.recv()?;
-		assert!(rlock!(recv_msg_clone));
+		// issue all of the triggers from another thread without waiting for them
+		let trigger = thread::spawn(move || -> Result<(), Error> {
+			let mut wh = loop {
+				if let Some(wh) = rlock!(wh_clone).clone() {
+					break wh;
+				}
+				sleep(Duration::from_millis(1));
+			};
+			for _ in 0..triggers {
+				wh.trigger_on_read()?;
+			}
+			Ok(())
+		});
+		trigger.join().unwrap()?;
+
+		let start = Instant::now();
+		loop {
+			cbreak!(rlock!(synthetic_clone) >= triggers);
+			assert!(start.elapsed() < Duration::from_secs(30));
+			sleep(Duration::from_millis(1));
+		}
+
+		// every trigger results in exactly one synthetic on_read
+		sleep(Duration::from_millis(100));
+		assert_eq!(rlock!(synthetic_clone), triggers);
 
 		Ok(())
 	}
 
+	// on Windows, registrations are one shot and must be re-armed after each event. With
+	// pending set, every write completes on a write event after which read interest must be
+	// re-armed or the next message is never read.
+	#[cfg(target_os = "windows")]
 	#[test]
-	fn test_evh_short_buf() -> Result<(), Error> {
+	fn test_evh_windows_pending_rearm() -> Result<(), Error> {
 		let test_info = test_info!()?;
 		let mut evh = evh_oro!(
 			Debug(false),
-			EvhTimeout(u16::MAX),
+			EvhTimeout(100),
 			EvhThreads(1),
 			EvhReadSlabSize(100)
 		)?;
 
-		let mut recv_msg = lock_box!(false)?;
-		let recv_msg_clone = recv_msg.clone();
-		let (tx, rx) = test_info.sync_channel();
+		let debug_info = DebugInfo {
+			pending: lock_box!(true)?,
+			..Default::default()
+		};
+		evh.set_debug_info(debug_info)?;
 
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			info!("onRead")?;
-			let next_chunk = ctx.next_chunk(connection)?.unwrap();
-			assert_ne!(next_chunk.slab_id(), usize::MAX);
-
-			wlock!(recv_msg) = true;
-			tx.send(())?;
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			connection.write_handle()?.write(&data)?;
 			ctx.clear_all(connection)?;
 			Ok(())
 		})?;
@@ -1127,169 +1256,217 @@ mod test {
 		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 		evh.add_server_connection(conn)?;
 
-		let conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		let mut wh = evh.add_client_connection(conn2)?;
-		wh.write(b"01234567890123456789")?;
-
-		rx.recv()?;
-		assert!(rlock!(recv_msg_clone));
+		let mut strm = TcpStream::connect(addr)?;
+		let mut buf = [0u8; 100];
+		for i in 0..10 {
+			let msg = format!("msg{}", i);
+			strm.write(msg.as_bytes())?;
+			let mut len_sum = 0;
+			while len_sum < msg.len() {
+				let len = strm.read(&mut buf[len_sum..])?;
+				assert!(len != 0);
+				len_sum += len;
+			}
+			assert_eq!(&buf[0..len_sum], msg.as_bytes());
+		}
 
 		Ok(())
 	}
 
+	// same as test_evh_trigger_on_read, but repeated on one connection so that the wakeup
+	// and the connection are re-armed many times
+	#[cfg(target_os = "windows")]
 	#[test]
-	fn test_evh_multi_chunk() -> Result<(), Error> {
+	fn test_evh_windows_trigger_on_read() -> Result<(), Error> {
 		let test_info = test_info!()?;
 		let mut evh = evh_oro!(
 			Debug(false),
-			EvhTimeout(10),
+			EvhTimeout(u16::MAX),
 			EvhThreads(1),
-			EvhReadSlabSize(25),
+			EvhReadSlabSize(100)
 		)?;
 
-		let (tx, rx) = test_info.sync_channel();
-		let b = b"0123456789012345678901234567890123456789012345678901234567890123456789012345678901234567890123456789";
-		let mut found_full = lock_box!(false)?;
-		let found_full_read = found_full.clone();
-
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			info!("onRead")?;
 			let mut data: Vec<u8> = vec![];
-
 			loop {
 				let next_chunk = ctx.next_chunk(connection)?;
 				cbreak!(next_chunk.is_none());
-				let next_chunk = next_chunk.unwrap();
-				data.extend(next_chunk.data());
+				data.extend(next_chunk.unwrap().data());
 			}
 
 			let mut wh = connection.write_handle()?;
-
-			let dstring = from_utf8(&data)?;
-			info!("data[{}]='{}'", connection.id(), dstring,)?;
-
-			if dstring == "hi" {
+			if data == b"hi" {
 				wh.write(b"1111")?;
 				spawn(move || -> Result<(), Error> {
 					wh.trigger_on_read()?;
 					Ok(())
 				});
-			} else if dstring == "" {
+			} else if data.is_empty() {
 				wh.write(b"2222")?;
 			}
 
-			if data == b {
-				wlock!(found_full) = true;
-				tx.send(())?;
-			}
-
+			ctx.clear_all(connection)?;
 			Ok(())
 		})?;
 
 		evh.start()?;
 		let port = test_info.port();
 		let addr = format!("127.0.0.1:{}", port);
-		info!("addr={}", addr)?;
 		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 		evh.add_server_connection(conn)?;
 
-		let mut strm = TcpStream::connect(addr.clone())?;
-		strm.write(b"0123456789012345678901234567890123456789012345678901234567890123456789012345678901234567890123456789")?;
-
-		rx.recv()?;
-		assert!(rlock!(found_full_read));
+		let mut strm = TcpStream::connect(addr)?;
+		let mut buf = [0u8; 100];
+		for _ in 0..10 {
+			strm.write(b"hi")?;
+			let mut len_sum = 0;
+			while len_sum < 8 {
+				let len = strm.read(&mut buf[len_sum..])?;
+				assert!(len != 0);
+				len_sum += len;
+			}
+			assert_eq!(&buf[0..len_sum], b"11112222");
+		}
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_user_data() -> Result<(), Error> {
-		let test_info = test_info!()?;
-		let mut evh = evh_oro!(
+	fn test_evh_invalid_configs() -> Result<(), Error> {
+		// timeout == 0
+		let error;
+		match evh_oro!(
 			Debug(false),
-			EvhTimeout(u16::MAX),
+			EvhTimeout(0),
 			EvhThreads(1),
-			EvhReadSlabSize(100)
-		)?;
-
-		let mut recv_msg = lock_box!(false)?;
-		let mut recv_msg2 = lock_box!(false)?;
-		let recv_msg_clone = recv_msg.clone();
-		let recv_msg2_clone = recv_msg2.clone();
-		let (tx, rx) = test_info.sync_channel();
-
-		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			let x = ctx.get_user_data();
-			if !rlock!(recv_msg) {
-				info!("none")?;
-				assert!(x.is_none());
-			} else {
-				info!("some")?;
-				assert_eq!(
-					x.as_ref().unwrap().downcast_ref::<usize>().unwrap(),
-					&1usize
-				);
-				wlock!(recv_msg2) = true;
-				tx.send(())?;
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(3_000)
+		) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
+				error = false;
 			}
-			ctx.set_user_data(Box::new(1usize));
-			info!("onRead")?;
-
-			let next_chunk = ctx.next_chunk(connection)?.unwrap();
-			assert_ne!(next_chunk.slab_id(), usize::MAX);
+			Err(_) => {
+				error = true;
+			}
+		}
+		assert!(error);
 
-			wlock!(recv_msg) = true;
-			ctx.clear_all(connection)?;
-			Ok(())
-		})?;
+		// read_slab_size < 25
+		let error;
+		match evh_oro!(
+			Debug(false),
+			EvhTimeout(1),
+			EvhThreads(1),
+			EvhReadSlabSize(10),
+			EvhStatsUpdateMillis(3_000)
+		) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
+				error = false;
+			}
+			Err(_) => {
+				error = true;
+			}
+		}
+		assert!(error);
 
-		evh.start()?;
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
-		evh.add_server_connection(conn)?;
+		// read_slab_count == 0
+		let error;
+		match evh_oro!(
+			Debug(false),
+			EvhTimeout(1),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhReadSlabCount(0),
+			EvhStatsUpdateMillis(3_000)
+		) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
+				error = false;
+			}
+			Err(_) => {
+				error = true;
+			}
+		}
+		assert!(error);
 
-		let conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		let mut wh = evh.add_client_connection(conn2)?;
-		wh.write(b"01234567890123456789")?;
+		// read_slab_count_per_thread == 0
+		let error;
+		match evh_oro!(
+			Debug(false),
+			EvhTimeout(1),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhReadSlabCountPerThread(0),
+			EvhStatsUpdateMillis(3_000)
+		) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
+				error = false;
+			}
+			Err(_) => {
+				error = true;
+			}
+		}
+		assert!(error);
 
-		let conn3 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		let mut wh = evh.add_client_connection(conn3)?;
-		wh.write(b"01234567890123456789")?;
+		// both read slab counts specified
+		let error;
+		match evh_oro!(
+			Debug(false),
+			EvhTimeout(1),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhReadSlabCount(10),
+			EvhReadSlabCountPerThread(10),
+			EvhStatsUpdateMillis(3_000)
+		) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
+				error = false;
+			}
+			Err(_) => {
+				error = true;
+			}
+		}
+		assert!(error);
 
-		rx.recv()?;
-		assert!(rlock!(recv_msg_clone));
-		assert!(rlock!(recv_msg2_clone));
+		// housekeeping_frequency_millis == 0
+		let error;
+		match evh_oro!(
+			Debug(false),
+			EvhTimeout(1),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhHouseKeeperFrequencyMillis(0),
+			EvhStatsUpdateMillis(3_000)
+		) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
+				error = false;
+			}
+			Err(_) => {
+				error = true;
+			}
+		}
+		assert!(error);
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_pending1() -> Result<(), Error> {
+	fn test_evh_stats() -> Result<(), Error> {
 		let test_info = test_info!()?;
 		let mut evh = evh_oro!(
 			Debug(false),
-			EvhTimeout(u16::MAX),
+			EvhTimeout(10),
 			EvhThreads(1),
-			EvhReadSlabSize(100)
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(3_000)
 		)?;
 
-		let debug_info = DebugInfo {
-			pending: lock_box!(true)?,
-			..Default::default()
-		};
-		evh.set_debug_info(debug_info.clone())?;
-
-		let mut recv_msg = lock_box!(false)?;
-		let recv_msg_clone = recv_msg.clone();
-
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			let mut wh = connection.write_handle()?;
-			wh.write(b"test")?;
-			wh.close()?;
-			assert!(wh.write(b"test").is_err());
-			assert!(wh.close().is_err());
-			wlock!(recv_msg) = true;
 			ctx.clear_all(connection)?;
 			Ok(())
 		})?;
@@ -1297,197 +1474,532 @@ mod test {
 		evh.start()?;
 		let port = test_info.port();
 		let addr = format!("127.0.0.1:{}", port);
-		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		info!("addr={}", addr)?;
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 		evh.add_server_connection(conn)?;
 
-		let mut strm = TcpStream::connect(addr)?;
-		strm.write(b"01234567890123456789")?;
+		{
+			let _strm = TcpStream::connect(addr.clone())?;
+			let _strm = TcpStream::connect(addr.clone())?;
+			let _strm = TcpStream::connect(addr.clone())?;
+			let _strm = TcpStream::connect(addr.clone())?;
+			let _strm = TcpStream::connect(addr.clone())?;
+		}
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write(b"test")?;
 
-		let mut buf = [0u8; 100];
-		let len = strm.read(&mut buf)?;
-		assert_eq!(len, 4);
-		assert_eq!(&buf[0..4], b"test");
+		let stats = evh.wait_for_stats()?;
+		info!("stats={:?}", stats)?;
 
-		// closed connection
-		assert_eq!(strm.read(&mut buf)?, 0);
-		assert!(rlock!(recv_msg_clone));
+		// 1 left in scope has not disconnecte yet
+		assert_eq!(stats.accepts, 6);
+		assert_eq!(stats.closes, 5);
+		assert_eq!(stats.reads, 1);
+		assert!(stats.event_loops != 0);
 
 		Ok(())
 	}
 
-	#[test]
-	fn test_evh_read_error() -> Result<(), Error> {
-		let test_info = test_info!()?;
-		let mut evh = evh_oro!(
-			Debug(false),
-			EvhTimeout(u16::MAX),
-			EvhThreads(1),
-			EvhReadSlabSize(100)
-		)?;
-
-		let debug_info = DebugInfo {
-			read_err: lock_box!(true)?,
-			..Default::default()
-		};
-		evh.set_debug_info(debug_info.clone())?;
-
-		let mut recv_msg = lock_box!(false)?;
-		let recv_msg_clone = recv_msg.clone();
-
+	// each of the clients performs rounds request/response round trips so the EventHandler
+	// performs exactly clients * rounds reads. Returns the aggregated reads once the threads
+	// have stopped and the number of times they took the stats lock.
+	fn stats_round_trips(
+		addr: &str,
+		threads: usize,
+		clients: usize,
+		rounds: usize,
+		update_millis: usize,
+	) -> Result<(usize, usize), Error> {
+		let mut evh = EventHandlerImpl::new(vec![
+			ConfigOption::EvhThreads(threads),
+			ConfigOption::EvhTimeout(10),
+			ConfigOption::EvhReadSlabSize(100),
+			ConfigOption::EvhStatsUpdateMillis(update_millis),
+		])?;
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			let mut wh = connection.write_handle()?;
-			wlock!(recv_msg) = true;
-			wh.write(b"test")?;
+			let mut data = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			connection.write_handle()?.write(&data)?;
 			ctx.clear_all(connection)?;
 			Ok(())
 		})?;
-
+		evh.set_on_accept(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_, _, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_, _| -> Result<(), Error> { Ok(()) })?;
 		evh.start()?;
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		let conn = EvhBuilder::build_server_connection(addr, 10_000)?;
 		evh.add_server_connection(conn)?;
 
-		let mut strm = TcpStream::connect(addr.clone())?;
-		strm.write(b"01234567890123456789")?;
-
-		let mut buf = [0u8; 100];
-		// this will be an error because the connection is closed when there's a read error
-		//assert!(strm.read(&mut buf).is_err());
-		assert_eq!(strm.read(&mut buf)?, 0);
-		assert!(!rlock!(recv_msg_clone));
+		let mut jhs = vec![];
+		for _ in 0..clients {
+			let addr = addr.to_string();
+			jhs.push(thread::spawn(move || -> Result<TcpStream, Error> {
+				let mut strm = TcpStream::connect(addr)?;
+				let mut buf = [0u8; 1];
+				for _ in 0..rounds {
+					strm.write_all(b"x")?;
+					strm.read_exact(&mut buf)?;
+				}
+				Ok(strm)
+			}));
+		}
+		// keep the connections open so that only reads are counted
+		let mut strms = vec![];
+		for jh in jhs {
+			strms.push(jh.join().unwrap()?);
+		}
 
-		evh.set_debug_info(DebugInfo::default())?;
+		evh.controller()?.stop()?;
+		let expected = clients * rounds;
+		let mut count = 0;
+		loop {
+			let (reads, stats_locks) = {
+				let stats = evh.stats.rlock()?;
+				let guard = stats.guard()?;
+				(guard.stats.reads, guard.stats_locks)
+			};
+			if reads >= expected || count >= 1_000 {
+				return Ok((reads, stats_locks));
+			}
+			sleep(Duration::from_millis(10));
+			count += 1;
+		}
+	}
 
-		let mut strm = TcpStream::connect(addr)?;
-		strm.write(b"01234567890123456789")?;
+	#[test]
+	fn test_evh_stats_aggregation() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let (threads, clients, rounds) = (4, 8, 100);
 
-		let mut buf = [0u8; 100];
-		// this will be an error because the connection is closed when there's a read error
+		// counts are aggregated on the stats updates and when the threads exit
+		let (reads, _) = stats_round_trips(&addr, threads, clients, rounds, 10)?;
+		assert_eq!(reads, clients * rounds);
 
-		let len = strm.read(&mut buf)?;
-		assert_eq!(len, 4);
-		assert_eq!(&buf[0..len], b"test");
-		assert!(rlock!(recv_msg_clone));
+		// with no stats update after the first one, all of the reads are aggregated when the
+		// threads exit. Each thread took the lock once for the update and once to exit.
+		let addr = format!("127.0.0.1:{}", pick_free_port()?);
+		let (reads, stats_locks) = stats_round_trips(&addr, threads, clients, rounds, 3_600_000)?;
+		assert_eq!(reads, clients * rounds);
+		assert!(stats_locks <= 2 * threads);
 
 		Ok(())
 	}
 
+	// run with cargo test -p bmw_evh test_evh_stats_lock_bench -- --ignored --nocapture
 	#[test]
-	fn test_evh_write_error() -> Result<(), Error> {
+	#[ignore]
+	fn test_evh_stats_lock_bench() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let (threads, clients, rounds) = (4, 16, 10_000);
+
+		let start = Instant::now();
+		let (reads, stats_locks) = stats_round_trips(&addr, threads, clients, rounds, 100)?;
+		let elapsed = start.elapsed();
+		info!(
+			"reads={},stats_locks={},elapsed={:?},reads/sec={}",
+			reads,
+			stats_locks,
+			elapsed,
+			reads as f64 / elapsed.as_secs_f64()
+		)?;
+
+		// the lock is taken once per thread per stats update, independent of the reads
+		assert_eq!(reads, clients * rounds);
+		assert!(stats_locks * 100 < reads);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_fd_limit() -> Result<(), Error> {
 		let test_info = test_info!()?;
 		let mut evh = evh_oro!(
 			Debug(false),
-			EvhTimeout(u16::MAX),
+			EvhTimeout(10),
 			EvhThreads(1),
-			EvhReadSlabSize(100)
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(100)
 		)?;
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
 
-		let debug_info = DebugInfo {
-			write_err: lock_box!(true)?,
-			pending: lock_box!(true)?,
+		#[cfg(unix)]
+		assert_eq!(evh.fd_limit()?.is_some(), fd_limit_impl().is_some());
+		#[cfg(windows)]
+		assert_eq!(evh.fd_limit()?, None);
+
+		// mock a limit of 10 so that the listener and 9 connections exceed the 90% threshold
+		evh.set_debug_info(DebugInfo {
+			fd_limit: lock_box!(Some(10))?,
 			..Default::default()
+		})?;
+		assert_eq!(evh.fd_limit()?, Some(10));
+		assert_eq!(evh.fd_headroom()?, 10);
+
+		evh.start()?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let mut strms = vec![];
+		for _ in 0..9 {
+			strms.push(TcpStream::connect(addr.clone())?);
+		}
+
+		let mut count = 0;
+		while evh.fd_headroom()? != 0 {
+			evh.wait_for_stats()?;
+			count += 1;
+			assert!(count < 100);
+		}
+		assert_eq!(evh.fd_limit()?, Some(10));
+
+		// the threshold must be a percentage
+		for percent in [0, 101] {
+			match evh_oro!(EvhFdWarnThresholdPercent(percent)) {
+				Ok(mut evh) => {
+					evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+					return Err(err!(ErrKind::Test, "expected a configuration error"));
+				}
+				Err(e) => assert!(matches!(e.kind(), ErrorKind::Configuration(_))),
+			}
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_fd_limit_warning_rate_limit() -> Result<(), Error> {
+		let mut global_stats = GlobalStats {
+			stats: EvhStats::new(),
+			update_counter: 0,
+			tx: None,
+			handle_counts: vec![4, 4],
+			listen_addrs: vec![SocketAddr::from(([127, 0, 0, 1], 1234))],
+			last_fd_warning: None,
+			fd_warnings: 0,
+			stats_locks: 0,
 		};
-		evh.set_debug_info(debug_info.clone())?;
 
+		// 8 of 10 is below the 90% threshold
+		check_fd_limit(&mut global_stats, 10, 90, 1_000)?;
+		assert_eq!(global_stats.fd_warnings, 0);
+
+		// 10 of 10 exceeds it. The warning is only logged once per window.
+		global_stats.handle_counts[1] = 6;
+		check_fd_limit(&mut global_stats, 10, 90, 1_000)?;
+		assert_eq!(global_stats.fd_warnings, 1);
+		check_fd_limit(&mut global_stats, 10, 90, 2_000)?;
+		check_fd_limit(&mut global_stats, 10, 90, 60_999)?;
+		assert_eq!(global_stats.fd_warnings, 1);
+
+		// the next window
+		check_fd_limit(&mut global_stats, 10, 90, 61_000)?;
+		assert_eq!(global_stats.fd_warnings, 2);
+		check_fd_limit(&mut global_stats, 10, 90, 61_001)?;
+		assert_eq!(global_stats.fd_warnings, 2);
+
+		// exactly at the threshold does not warn
+		global_stats.last_fd_warning = None;
+		global_stats.handle_counts = vec![9, 0];
+		check_fd_limit(&mut global_stats, 10, 90, 200_000)?;
+		assert_eq!(global_stats.fd_warnings, 2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_callback_timeout() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(100),
+			EvhCallbackTimeoutMillis(100)
+		)?;
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			let mut wh = connection.write_handle()?;
-			wh.write(b"test")?;
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
 			ctx.clear_all(connection)?;
+			if data == b"sleep" {
+				sleep(Duration::from_millis(500));
+			}
+			connection.write_handle()?.write(&data)?;
 			Ok(())
 		})?;
-
 		evh.start()?;
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 		evh.add_server_connection(conn)?;
 
 		let mut strm = TcpStream::connect(addr.clone())?;
-		strm.write(b"01234567890123456789")?;
-
 		let mut buf = [0u8; 100];
+		strm.write(b"fast")?;
 		let len = strm.read(&mut buf)?;
-		// the len will be 0 because it will be closed by the server due to the write error
-		assert_eq!(len, 0);
+		assert_eq!(&buf[0..len], b"fast");
 
-		// now update debug info to avoid the error
+		// the callback only trips the watchdog once even though it runs for several checks
+		strm.write(b"sleep")?;
+		let len = strm.read(&mut buf)?;
+		assert_eq!(&buf[0..len], b"sleep");
 
-		evh.set_debug_info(DebugInfo::default())?;
+		let mut watchdog_trips = 0;
+		for _ in 0..10 {
+			watchdog_trips += evh.wait_for_stats()?.watchdog_trips;
+		}
+		assert_eq!(watchdog_trips, 1);
 
-		let mut strm = TcpStream::connect(addr)?;
-		strm.write(b"01234567890123456789")?;
+		// a timeout of 0 and an action other than Log or SpawnReplacement are errors
+		match evh_oro!(EvhCallbackTimeoutMillis(0)) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+				return Err(err!(ErrKind::Test, "expected a configuration error"));
+			}
+			Err(e) => assert!(matches!(e.kind(), ErrorKind::Configuration(_))),
+		}
 
-		let mut buf = [0u8; 100];
-		let len = strm.read(&mut buf)?;
-		// now it's corrected
-		assert_eq!(len, 4);
+		// the action requires a timeout
+		match evh_oro!(EvhWatchdogAction(WatchdogAction::Log)) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+				return Err(err!(ErrKind::Test, "expected a configuration error"));
+			}
+			Err(e) => assert!(matches!(e.kind(), ErrorKind::Configuration(_))),
+		}
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_write_handle_error() -> Result<(), Error> {
+	fn test_evh_watchdog_spawn_replacement() -> Result<(), Error> {
 		let test_info = test_info!()?;
-		let mut evh = evh_oro!(
-			Debug(false),
-			EvhTimeout(u16::MAX),
+		let mut evh = evh!(
+			EvhTimeout(10),
 			EvhThreads(1),
-			EvhReadSlabSize(100)
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(100),
+			EvhCallbackTimeoutMillis(100),
+			EvhWatchdogAction(WatchdogAction::SpawnReplacement)
 		)?;
 
-		let debug_info = DebugInfo {
-			write_handle_err: lock_box!(true)?,
-			..Default::default()
-		};
-		evh.set_debug_info(debug_info.clone())?;
-
+		let mut release = lock_box!(false)?;
+		let release_clone = release.clone();
+		let closes = lock_box!(vec![])?;
+		let mut closes_clone = closes.clone();
 		let (tx, rx) = test_info.sync_channel();
 
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			let mut wh = connection.write_handle()?;
-			match wh.write(b"test") {
-				Ok(_) => {
-					info!("write ok")?;
-				}
-				Err(e) => {
-					info!("write err: {}", e)?;
-					tx.send(())?;
-				}
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			if data == b"keep" {
+				// the data stays in the read slabs of the thread
+				connection.write_handle()?.write(b"kept")?;
+				return Ok(());
 			}
 			ctx.clear_all(connection)?;
+			if data == b"block" {
+				tx.send(())?;
+				while !rlock!(release_clone) {
+					sleep(Duration::from_millis(10));
+				}
+			} else {
+				connection.write_handle()?.write(&data)?;
+			}
 			Ok(())
 		})?;
-
+		evh.set_on_close(move |_connection, reason, _ctx| -> Result<(), Error> {
+			wlock!(closes_clone).push(close_reason_name(reason));
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
 		evh.start()?;
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 		evh.add_server_connection(conn)?;
 
-		let mut strm = TcpStream::connect(addr.clone())?;
-		// trigger write handle error
-		strm.write(b"01234567890123456789")?;
+		let mut buf = [0u8; 100];
+		let mut strm0 = TcpStream::connect(addr.clone())?;
+		strm0.set_read_timeout(Some(Duration::from_millis(10_000)))?;
+		strm0.write(b"hi")?;
+		let len = strm0.read(&mut buf)?;
+		assert_eq!(&buf[0..len], b"hi");
+
+		// a connection with data that was not cleared when the thread is replaced
+		let mut strm3 = TcpStream::connect(addr.clone())?;
+		strm3.set_read_timeout(Some(Duration::from_millis(10_000)))?;
+		strm3.write_all(b"keep")?;
+		let len = strm3.read(&mut buf)?;
+		assert_eq!(&buf[0..len], b"kept");
 
+		// block the only thread
+		let mut strm1 = TcpStream::connect(addr.clone())?;
+		strm1.write(b"block")?;
 		rx.recv()?;
 
-		// now fix it
-		evh.set_debug_info(DebugInfo::default())?;
+		// the replacement closes the connection because its data stayed with the stuck thread
+		assert_eq!(strm3.read(&mut buf)?, 0);
+		let mut count = 0;
+		while rlock!(closes).is_empty() {
+			sleep(Duration::from_millis(10));
+			count += 1;
+			assert!(count < 1_000);
+		}
+		assert_eq!(*rlock!(closes), vec!["ThreadReplaced"]);
 
-		strm.write(b"01234567890123456789")?;
+		// the replacement thread serves the existing connection and accepts new ones
+		strm0.write(b"again")?;
+		let len = strm0.read(&mut buf)?;
+		assert_eq!(&buf[0..len], b"again");
 
-		let mut buf = [0u8; 100];
+		let mut strm2 = TcpStream::connect(addr.clone())?;
+		strm2.set_read_timeout(Some(Duration::from_millis(10_000)))?;
+		strm2.write(b"new")?;
+		let len = strm2.read(&mut buf)?;
+		assert_eq!(&buf[0..len], b"new");
 
-		let len = strm.read(&mut buf)?;
-		// the message goes through now
-		assert_eq!(len, 4);
-		assert_eq!(&buf[0..4], b"test");
+		let mut watchdog_trips = 0;
+		for _ in 0..10 {
+			watchdog_trips += evh.wait_for_stats()?.watchdog_trips;
+			cbreak!(watchdog_trips > 0);
+		}
+		assert_eq!(watchdog_trips, 1);
+
+		// once the stuck callback returns, its connection is closed
+		wlock!(release) = true;
+		strm1.set_read_timeout(Some(Duration::from_millis(10_000)))?;
+		assert_eq!(strm1.read(&mut buf)?, 0);
+		let mut count = 0;
+		while rlock!(closes).len() < 2 {
+			sleep(Duration::from_millis(10));
+			count += 1;
+			assert!(count < 1_000);
+		}
+		assert_eq!(*rlock!(closes), vec!["ThreadReplaced", "CallbackTimeout"]);
+
+		strm0.write(b"after")?;
+		let len = strm0.read(&mut buf)?;
+		assert_eq!(&buf[0..len], b"after");
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_connection_close() -> Result<(), Error> {
+	fn test_evh_watch_expired() -> Result<(), Error> {
+		// a callback may record its start after the watchdog read the time
+		let now = watch_millis();
+		assert!(!watch_expired(now + 1_000, 0, now, 100));
+		assert!(!watch_expired(usize::MAX, 0, 0, 0));
+
+		assert!(watch_expired(now + 1, 0, now + 102, 100));
+		assert!(!watch_expired(now + 1, 0, now + 101, 100));
+		// not running
+		assert!(!watch_expired(0, 0, now + 1_000, 100));
+		// already reported
+		assert!(!watch_expired(now + 1, now + 1, now + 1_000, 100));
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_per_origin_stats() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(10),
+			EvhThreads(2),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(1_000)
+		)?;
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			connection.write_handle()?.write(&data)?;
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let addr1 = format!("127.0.0.1:{}", test_info.port());
+		let addr2 = format!("127.0.0.1:{}", pick_free_port()?);
+		let conn1 = EvhBuilder::build_server_connection(&addr1, 10_000)?;
+		let conn2 = EvhBuilder::build_server_connection(&addr2, 10_000)?;
+		let (id1, id2) = (conn1.id(), conn2.id());
+		evh.add_server_connection(conn1)?;
+		evh.add_server_connection(conn2)?;
+
+		// 3 reads on the first listener and 5 reads on the second. Each message is echoed
+		// back so that we know it was read before the next one is sent.
+		let mut buf = [0u8; 10];
+		let mut strm1 = TcpStream::connect(addr1)?;
+		for _ in 0..3 {
+			strm1.write(b"abc")?;
+			assert_eq!(strm1.read(&mut buf)?, 3);
+		}
+		let mut strm2 = TcpStream::connect(addr2)?;
+		for _ in 0..5 {
+			strm2.write(b"abcde")?;
+			assert_eq!(strm2.read(&mut buf)?, 5);
+		}
+
+		let stats = evh.wait_for_stats()?;
+		info!("stats={:?}", stats)?;
+		let per_origin = stats.per_origin();
+		assert_eq!(per_origin.len(), 2);
+		let get = |id| {
+			per_origin
+				.iter()
+				.find(|(origin_id, _)| *origin_id == id)
+				.unwrap()
+				.1
+		};
+		let origin1 = get(id1);
+		let origin2 = get(id2);
+		assert_eq!(origin1.accepts, 1);
+		assert_eq!(origin1.reads, 3);
+		assert_eq!(origin1.bytes_read, 9);
+		assert_eq!(origin2.accepts, 1);
+		assert_eq!(origin2.reads, 5);
+		assert_eq!(origin2.bytes_read, 25);
+		assert_eq!(stats.reads, 8);
+
+		// the next interval still has both listeners, with the counters reset
+		let stats = evh.wait_for_stats()?;
+		assert_eq!(stats.per_origin().len(), 2);
+		for (_, origin) in stats.per_origin() {
+			assert_eq!(origin, OriginStats::default());
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_trigger_empty() -> Result<(), Error> {
 		let test_info = test_info!()?;
 		let mut evh = evh_oro!(
 			Debug(false),
@@ -1498,120 +2010,90 @@ mod test {
 
 		let mut recv_msg = lock_box!(false)?;
 		let recv_msg_clone = recv_msg.clone();
+		let (tx, rx) = test_info.sync_channel();
 
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			let mut wh = connection.write_handle()?;
-			wh.write(b"test")?;
-			wh.close()?;
-			assert!(wh.write(b"test").is_err());
-			assert!(wh.trigger_on_read().is_err());
-			assert!(wh.close().is_err());
-			wlock!(recv_msg) = true;
+			info!("onRead")?;
 			ctx.clear_all(connection)?;
+			wlock!(recv_msg) = true;
+			tx.send(())?;
 			Ok(())
 		})?;
 
 		evh.start()?;
 		let port = test_info.port();
 		let addr = format!("127.0.0.1:{}", port);
-		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 		evh.add_server_connection(conn)?;
 
-		let mut strm = TcpStream::connect(addr)?;
-		strm.write(b"01234567890123456789")?;
-
-		let mut buf = [0u8; 100];
-		let len = strm.read(&mut buf)?;
-		assert_eq!(len, 4);
-		assert_eq!(&buf[0..4], b"test");
+		let conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		let mut wh = evh.add_client_connection(conn2)?;
+		wh.trigger_on_read()?;
 
-		// closed connection
-		assert_eq!(strm.read(&mut buf)?, 0);
+		rx.recv()?;
 		assert!(rlock!(recv_msg_clone));
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_normal_fatal_error() -> Result<(), Error> {
+	fn test_evh_short_buf() -> Result<(), Error> {
 		let test_info = test_info!()?;
-		let threads = 1;
-
-		let mut evh = evh!(
-			EvhThreads(threads),
+		let mut evh = evh_oro!(
+			Debug(false),
 			EvhTimeout(u16::MAX),
-			EvhHouseKeeperFrequencyMillis(usize::MAX)
+			EvhThreads(1),
+			EvhReadSlabSize(100)
 		)?;
-		evh.set_debug_info(DebugInfo {
-			normal_fatal_error: lock_box!(true)?,
-			..Default::default()
-		})?;
 
-		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			let mut data: Vec<u8> = vec![];
-
-			loop {
-				let next_chunk = ctx.next_chunk(connection)?;
-				cbreak!(next_chunk.is_none());
-				let next_chunk = next_chunk.unwrap();
-				data.extend(next_chunk.data());
-			}
+		let mut recv_msg = lock_box!(false)?;
+		let recv_msg_clone = recv_msg.clone();
+		let (tx, rx) = test_info.sync_channel();
 
-			let dstring = from_utf8(&data)?;
-			info!("data[{}]='{}'", connection.id(), dstring,)?;
-			let mut wh = connection.write_handle()?;
-			wh.write(&data)?;
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			info!("onRead")?;
+			let next_chunk = ctx.next_chunk(connection)?.unwrap();
+			assert_ne!(next_chunk.slab_id(), usize::MAX);
 
+			wlock!(recv_msg) = true;
+			tx.send(())?;
 			ctx.clear_all(connection)?;
 			Ok(())
 		})?;
 
-		evh.set_on_panic(move |_id, e| -> Result<(), Error> {
-			let e = e.downcast_ref::<&str>().unwrap_or(&"unknown error");
-			error!("on panic: {}", e)?;
-			Ok(())
-		})?;
-
-		evh.set_on_accept(move |_, _| -> Result<(), Error> { Ok(()) })?;
-		evh.set_on_close(move |_, _| -> Result<(), Error> { Ok(()) })?;
-		evh.set_on_housekeeper(move |_| -> Result<(), Error> { Ok(()) })?;
-
 		evh.start()?;
-
 		let port = test_info.port();
 		let addr = format!("127.0.0.1:{}", port);
-		info!("Host on {}", addr)?;
 		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
 
-		info!("adding connection now")?;
-		spawn(move || {
-			// this will never complete due to the fatal error that occurs in the server
-			// thread
-			let _ = evh.add_server_connection(conn);
-		});
+		let conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		let mut wh = evh.add_client_connection(conn2)?;
+		wh.write(b"01234567890123456789")?;
 
-		// sleep to ensure code is exercised
-		sleep(Duration::from_millis(QA_SLEEP));
+		rx.recv()?;
+		assert!(rlock!(recv_msg_clone));
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_panic_fatal_error() -> Result<(), Error> {
+	fn test_evh_multi_chunk() -> Result<(), Error> {
 		let test_info = test_info!()?;
-		let threads = 1;
-
-		let mut evh = evh!(
-			EvhThreads(threads),
-			EvhTimeout(u16::MAX),
-			EvhHouseKeeperFrequencyMillis(usize::MAX)
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhReadSlabSize(25),
 		)?;
-		evh.set_debug_info(DebugInfo {
-			panic_fatal_error: lock_box!(true)?,
-			..Default::default()
-		})?;
+
+		let (tx, rx) = test_info.sync_channel();
+		let b = b"0123456789012345678901234567890123456789012345678901234567890123456789012345678901234567890123456789";
+		let mut found_full = lock_box!(false)?;
+		let found_full_read = found_full.clone();
 
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			info!("onRead")?;
 			let mut data: Vec<u8> = vec![];
 
 			loop {
@@ -1621,1249 +2103,6571 @@ mod test {
 				data.extend(next_chunk.data());
 			}
 
+			let mut wh = connection.write_handle()?;
+
 			let dstring = from_utf8(&data)?;
 			info!("data[{}]='{}'", connection.id(), dstring,)?;
-			if dstring == "crash\r\n" {
-				let x: Option<u32> = None;
-				let _y = x.unwrap();
+
+			if dstring == "hi" {
+				wh.write(b"1111")?;
+				spawn(move || -> Result<(), Error> {
+					wh.trigger_on_read()?;
+					Ok(())
+				});
+			} else if dstring == "" {
+				wh.write(b"2222")?;
 			}
-			let mut wh = connection.write_handle()?;
-			wh.write(&data)?;
 
-			ctx.clear_all(connection)?;
-			Ok(())
-		})?;
+			if data == b {
+				wlock!(found_full) = true;
+				tx.send(())?;
+			}
 
-		let (tx, rx) = test_info.sync_channel();
-		evh.set_on_panic(move |_id, e| -> Result<(), Error> {
-			let e = e.downcast_ref::<&str>().unwrap_or(&"unknown error");
-			error!("on panic: {}", e)?;
-			tx.send(())?;
 			Ok(())
 		})?;
 
-		evh.set_on_accept(move |_, _| -> Result<(), Error> { Ok(()) })?;
-		evh.set_on_close(move |_, _| -> Result<(), Error> { Ok(()) })?;
-		evh.set_on_housekeeper(move |_| -> Result<(), Error> { Ok(()) })?;
-
 		evh.start()?;
-
 		let port = test_info.port();
 		let addr = format!("127.0.0.1:{}", port);
-		info!("Host on {}", addr)?;
+		info!("addr={}", addr)?;
 		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
-
-		info!("adding connection now")?;
 		evh.add_server_connection(conn)?;
 
-		let mut strm = TcpStream::connect(addr)?;
-		// trigger panic
-		strm.write(b"crash\r\n")?;
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write(b"0123456789012345678901234567890123456789012345678901234567890123456789012345678901234567890123456789")?;
+
 		rx.recv()?;
-		sleep(Duration::from_millis(QA_SLEEP));
+		assert!(rlock!(found_full_read));
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_invalid_write_handle() -> Result<(), Error> {
-		let connection = Connection {
-			handle: 0,
-			id: 0,
-			slab_offset: 0,
-			last_slab: 0,
-			first_slab: 0,
-			write_state: lock_box!(WriteState {
-				flags: 0,
-				write_buffer: vec![]
-			})?,
-			wakeup: None,
-			state: None,
-			tx: None,
-			ctype: ConnectionType::Connection,
-			debug_info: DebugInfo::default(),
-			origin_id: 0,
-			write_final: false,
-			disable_write_final: false,
-		};
-		assert!(WriteHandle::new(&connection, DebugInfo::default()).is_err());
+	fn test_evh_user_data() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(100)
+		)?;
 
-		let connection = Connection {
-			handle: 0,
-			id: 0,
-			slab_offset: 0,
-			last_slab: 0,
-			first_slab: 0,
-			write_state: lock_box!(WriteState {
-				flags: 0,
-				write_buffer: vec![]
-			})?,
-			wakeup: Some(Wakeup::new()?),
-			state: None,
-			tx: None,
-			ctype: ConnectionType::Connection,
-			debug_info: DebugInfo::default(),
-			origin_id: 0,
-			write_final: false,
-			disable_write_final: false,
-		};
-		assert!(WriteHandle::new(&connection, DebugInfo::default()).is_err());
-		Ok(())
-	}
+		let mut recv_msg = lock_box!(false)?;
+		let mut recv_msg2 = lock_box!(false)?;
+		let recv_msg_clone = recv_msg.clone();
+		let recv_msg2_clone = recv_msg2.clone();
+		let (tx, rx) = test_info.sync_channel();
 
-	#[test]
-	fn test_evh_stop_error() -> Result<(), Error> {
-		let test_info = test_info!()?;
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let x = ctx.get_user_data();
+			if !rlock!(recv_msg) {
+				info!("none")?;
+				assert!(x.is_none());
+			} else {
+				info!("some")?;
+				assert_eq!(
+					x.as_ref().unwrap().downcast_ref::<usize>().unwrap(),
+					&1usize
+				);
+				wlock!(recv_msg2) = true;
+				tx.send(())?;
+			}
+			ctx.set_user_data(Box::new(1usize));
+			info!("onRead")?;
+
+			let next_chunk = ctx.next_chunk(connection)?.unwrap();
+			assert_ne!(next_chunk.slab_id(), usize::MAX);
+
+			wlock!(recv_msg) = true;
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+
+		evh.start()?;
 		let port = test_info.port();
 		let addr = format!("127.0.0.1:{}", port);
-		{
-			let mut evh = evh_oro!(
-				Debug(false),
-				EvhTimeout(u16::MAX),
-				EvhThreads(5),
-				EvhReadSlabSize(100)
-			)?;
-			evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
-			evh.set_debug_info(DebugInfo {
-				stop_error: lock_box!(true).unwrap(),
-				..Default::default()
-			})?;
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
 
-			evh.start()?;
+		let conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		let mut wh = evh.add_client_connection(conn2)?;
+		wh.write(b"01234567890123456789")?;
 
-			let conn = EvhBuilder::build_server_connection(&addr, 100)?;
-			evh.add_server_connection(conn)?;
-		}
+		let conn3 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		let mut wh = evh.add_client_connection(conn3)?;
+		wh.write(b"01234567890123456789")?;
 
-		// we should still be able to connect because stop failed.
-		assert!(TcpStream::connect(addr).is_ok());
+		rx.recv()?;
+		assert!(rlock!(recv_msg_clone));
+		assert!(rlock!(recv_msg2_clone));
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_get_events_error() -> Result<(), Error> {
-		let config = EventHandlerConfig {
-			debug: false,
-			housekeeping_frequency_millis: 100,
-			stats_update_frequency_millis: 100,
-			threads: 1,
-			timeout: 1_000,
-			read_slab_count: 1,
-			read_slab_size: 100,
-			out_of_slabs_message: "".to_string(),
-		};
+	fn test_evh_pending1() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(100)
+		)?;
+
 		let debug_info = DebugInfo {
-			get_events_error: lock_box!(true)?,
+			pending: lock_box!(true)?,
 			..Default::default()
 		};
+		evh.set_debug_info(debug_info.clone())?;
 
-		let read_slabs = slab_allocator!(
-			SlabSize(config.read_slab_size),
-			SlabCount(config.read_slab_count)
-		)?;
-		let user_context = UserContextImpl {
-			read_slabs,
-			user_data: None,
-			slab_cur: usize::MAX,
-		};
-		let user_context_arr = array!(1, &lock_box!(user_context)?)?;
-		let state = array!(config.threads, &lock_box!(EventHandlerState::new()?)?)?;
-		let w = Wakeup::new()?;
-		let wakeups = array!(1, &w)?;
+		let mut recv_msg = lock_box!(false)?;
+		let recv_msg_clone = recv_msg.clone();
 
-		let global_stats = GlobalStats {
-			stats: EvhStats::new(),
-			update_counter: 0,
-			tx: None,
-		};
-		let stats = lock_box!(global_stats)?;
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut wh = connection.write_handle()?;
+			wh.write(b"test")?;
+			wh.close()?;
+			assert!(wh.write(b"test").is_err());
+			assert!(wh.close().is_err());
+			wlock!(recv_msg) = true;
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
 
-		let sample = EventHandlerContext::new(wakeups, 0, stats)?;
-		let sample = lock_box!(sample)?;
-		let ctx_arr = array!(1, &sample)?;
-		let callbacks = EventHandlerCallbacks {
-			on_read: Some(Box::pin(
-				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
-					Ok(())
-				},
-			)),
-			on_accept: Some(Box::pin(
-				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
-					Ok(())
-				},
-			)),
-			on_close: Some(Box::pin(
-				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
-					Ok(())
-				},
-			)),
-			on_panic: Some(Box::pin(
-				move |_: &mut Box<dyn UserContext + '_>, _| -> Result<(), Error> { Ok(()) },
-			)),
-			on_housekeeper: Some(Box::pin(
-				move |_: &mut Box<dyn UserContext + '_>| -> Result<(), Error> { Ok(()) },
-			)),
-		};
+		evh.start()?;
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
 
-		spawn(move || {
-			let _ = EventHandlerImpl::execute_thread(
-				config,
-				callbacks,
-				state,
-				ctx_arr,
-				user_context_arr,
-				0,
-				true,
-				&debug_info,
-			);
-		});
+		let mut strm = TcpStream::connect(addr)?;
+		strm.write(b"01234567890123456789")?;
+
+		let mut buf = [0u8; 100];
+		let len = strm.read(&mut buf)?;
+		assert_eq!(len, 4);
+		assert_eq!(&buf[0..4], b"test");
+
+		// closed connection
+		assert_eq!(strm.read(&mut buf)?, 0);
+		assert!(rlock!(recv_msg_clone));
 
-		sleep(Duration::from_millis(QA_SLEEP));
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_functions() -> Result<(), Error> {
+	fn test_evh_read_error() -> Result<(), Error> {
 		let test_info = test_info!()?;
-		let w = Wakeup::new()?;
-		let wakeups = array!(1, &w)?;
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(100)
+		)?;
 
-		let global_stats = GlobalStats {
-			stats: EvhStats::new(),
-			update_counter: 0,
-			tx: None,
-		};
-		let stats = lock_box!(global_stats)?;
-		let mut ehc = EventHandlerContext::new(wakeups, 0, stats)?;
-		let mut callbacks = EventHandlerCallbacks {
-			on_read: Some(Box::pin(
-				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
-					Ok(())
-				},
-			)),
-			on_accept: Some(Box::pin(
-				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
-					Ok(())
-				},
-			)),
-			on_close: Some(Box::pin(
-				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
-					Ok(())
-				},
-			)),
-			on_panic: Some(Box::pin(
-				move |_: &mut Box<dyn UserContext + '_>, _| -> Result<(), Error> { Ok(()) },
-			)),
-			on_housekeeper: Some(Box::pin(
-				move |_: &mut Box<dyn UserContext + '_>| -> Result<(), Error> { Ok(()) },
-			)),
+		let debug_info = DebugInfo {
+			read_err: lock_box!(true)?,
+			..Default::default()
 		};
+		evh.set_debug_info(debug_info.clone())?;
 
-		let mut v = VecDeque::new();
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-		let server = EvhBuilder::build_server_connection(&addr, 5)?;
-		v.push_back(ConnectionVariant::ServerConnection(server));
-		let client = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		v.push_back(ConnectionVariant::ClientConnection(client));
-		let client2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		v.push_back(ConnectionVariant::Connection(client2));
-		v.push_back(ConnectionVariant::Wakeup(Wakeup::new()?));
-		assert!(EventHandlerImpl::close_handles(&mut ehc, &v, &mut callbacks).is_ok());
+		let mut recv_msg = lock_box!(false)?;
+		let recv_msg_clone = recv_msg.clone();
 
-		let read_slabs = slab_allocator!(SlabSize(100), SlabCount(1))?;
-		let mut user_context = UserContextImpl {
-			read_slabs,
-			user_data: None,
-			slab_cur: usize::MAX,
-		};
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut wh = connection.write_handle()?;
+			wlock!(recv_msg) = true;
+			wh.write(b"test")?;
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
 
-		let port = pick_free_port()?;
+		evh.start()?;
+		let port = test_info.port();
 		let addr = format!("127.0.0.1:{}", port);
-		let conn = EvhBuilder::build_server_connection(&addr, 5)?;
-		ehc.id_hash
-			.insert(0, ConnectionVariant::ServerConnection(conn));
-		assert!(
-			EventHandlerImpl::process_write_id(&mut ehc, 0, &mut callbacks, &mut user_context)
-				.is_ok()
-		);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
 
-		let mut conn = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		conn.wakeup = Some(Wakeup::new()?);
-		conn.state = Some(lock_box!(EventHandlerState::new()?)?);
-		conn.write_handle()?.trigger_on_read()?;
-		conn.write_handle()?.close()?;
-		ehc.id_hash.insert(0, ConnectionVariant::Connection(conn));
-		assert!(
-			EventHandlerImpl::process_write_id(&mut ehc, 0, &mut callbacks, &mut user_context)
-				.is_ok()
-		);
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write(b"01234567890123456789")?;
 
-		let mut conn = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		conn.wakeup = Some(Wakeup::new()?);
-		conn.state = Some(lock_box!(EventHandlerState::new()?)?);
-		conn.write_handle()?.trigger_on_read()?;
-		conn.write_handle()?.close()?;
-		ehc.id_hash
-			.insert(0, ConnectionVariant::ClientConnection(conn));
-		assert!(
-			EventHandlerImpl::process_write_id(&mut ehc, 0, &mut callbacks, &mut user_context)
-				.is_ok()
-		);
+		let mut buf = [0u8; 100];
+		// this will be an error because the connection is closed when there's a read error
+		//assert!(strm.read(&mut buf).is_err());
+		assert_eq!(strm.read(&mut buf)?, 0);
+		assert!(!rlock!(recv_msg_clone));
 
-		ehc.id_hash
-			.insert(0, ConnectionVariant::Wakeup(Wakeup::new()?));
-		assert!(
-			EventHandlerImpl::process_write_id(&mut ehc, 0, &mut callbacks, &mut user_context)
-				.is_ok()
-		);
+		evh.set_debug_info(DebugInfo::default())?;
 
-		// try on a not found. just prints a warning
-		assert!(
-			EventHandlerImpl::process_write_id(&mut ehc, 1, &mut callbacks, &mut user_context)
-				.is_ok()
-		);
+		let mut strm = TcpStream::connect(addr)?;
+		strm.write(b"01234567890123456789")?;
 
-		let config = EventHandlerConfig {
-			debug: false,
-			housekeeping_frequency_millis: 100,
-			stats_update_frequency_millis: 100,
-			threads: 1,
-			timeout: 1_000,
-			read_slab_count: 1,
-			read_slab_size: 100,
-			out_of_slabs_message: "".to_string(),
-		};
-		let mut state = array!(config.threads, &lock_box!(EventHandlerState::new()?)?)?;
-		let debug_info = DebugInfo::default();
+		let mut buf = [0u8; 100];
+		// this will be an error because the connection is closed when there's a read error
 
-		let port = pick_free_port()?;
-		let addr = format!("127.0.0.1:{}", port);
-		let conn = EvhBuilder::build_server_connection(&addr, 5)?;
-		ehc.handle_hash.insert(conn.handle(), conn.id());
-		ehc.trigger_on_read_list.push(conn.handle());
-		ehc.id_hash
-			.insert(conn.id(), ConnectionVariant::ServerConnection(conn));
+		let len = strm.read(&mut buf)?;
+		assert_eq!(len, 4);
+		assert_eq!(&buf[0..len], b"test");
+		assert!(rlock!(recv_msg_clone));
 
-		let conn = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		ehc.handle_hash.insert(conn.handle(), conn.id());
-		ehc.trigger_on_read_list.push(conn.handle());
+		Ok(())
+	}
 
-		assert!(EventHandlerImpl::process_events(
-			&config,
-			&mut ehc,
-			&mut callbacks,
-			&mut state,
-			&mut user_context,
-			&debug_info,
-		)
-		.is_ok());
+	#[test]
+	fn test_evh_write_error() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(100)
+		)?;
 
 		let debug_info = DebugInfo {
-			wakeup_read_err: lock_box!(true)?,
+			write_err: lock_box!(true)?,
+			pending: lock_box!(true)?,
 			..Default::default()
 		};
-		let wakeup = Wakeup::new()?;
-		ehc.handle_hash.insert(0, 0);
-		ehc.id_hash.insert(0, ConnectionVariant::Wakeup(wakeup));
+		evh.set_debug_info(debug_info.clone())?;
 
-		assert!(EventHandlerImpl::process_read_event(
-			&config,
-			&mut ehc,
-			&mut callbacks,
-			0,
-			&mut state,
-			&mut user_context,
-			&debug_info,
-		)
-		.is_ok());
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut wh = connection.write_handle()?;
+			wh.write(b"test")?;
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
 
-		ehc.handle_hash.insert(0, 100);
+		evh.start()?;
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
 
-		assert!(EventHandlerImpl::process_read_event(
-			&config,
-			&mut ehc,
-			&mut callbacks,
-			0,
-			&mut state,
-			&mut user_context,
-			&debug_info,
-		)
-		.is_ok());
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write(b"01234567890123456789")?;
 
-		assert!(EventHandlerImpl::process_read_event(
-			&config,
-			&mut ehc,
-			&mut callbacks,
-			1230,
-			&mut state,
-			&mut user_context,
-			&debug_info,
-		)
-		.is_ok());
+		let mut buf = [0u8; 100];
+		let len = strm.read(&mut buf)?;
+		// the len will be 0 because it will be closed by the server due to the write error
+		assert_eq!(len, 0);
 
-		let port = pick_free_port()?;
-		assert!(create_listener(&format!("[::1]:{}", port), 1, &DebugInfo::default()).is_ok());
+		// now update debug info to avoid the error
 
-		ehc.handle_hash.clear();
-		ehc.id_hash.clear();
+		evh.set_debug_info(DebugInfo::default())?;
 
-		ehc.handle_hash.insert(0, 0);
-		ehc.id_hash
-			.insert(0, ConnectionVariant::Wakeup(Wakeup::new()?));
-		assert!(
-			EventHandlerImpl::call_on_close(&mut user_context, 0, &mut callbacks, &mut ehc).is_ok()
-		);
+		let mut strm = TcpStream::connect(addr)?;
+		strm.write(b"01234567890123456789")?;
 
-		ehc.handle_hash.clear();
-		ehc.id_hash.clear();
+		let mut buf = [0u8; 100];
+		let len = strm.read(&mut buf)?;
+		// now it's corrected
+		assert_eq!(len, 4);
 
-		ehc.handle_hash.insert(0, 0);
-		assert!(
-			EventHandlerImpl::call_on_close(&mut user_context, 0, &mut callbacks, &mut ehc).is_ok()
-		);
+		Ok(())
+	}
 
-		ehc.handle_hash.clear();
-		ehc.id_hash.clear();
+	// builds and starts an EventHandler listening on port that pushes the id of each accepted
+	// connection to accepted and sends on tx
+	macro_rules! id_evh {
+		($port:expr, $accepted:expr, $tx:expr) => {{
+			let mut accepted = $accepted.clone();
+			let tx = $tx.clone();
+			let mut evh = evh!(EvhTimeout(10), EvhThreads(2))?;
+			evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+				ctx.clear_all(connection)?;
+				Ok(())
+			})?;
+			evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+				assert_eq!(
+					ConnectionId::from(connection.id()).instance_id,
+					connection.evh_instance_id()
+				);
+				wlock!(accepted).push(connection.id());
+				tx.send(())?;
+				Ok(())
+			})?;
+			evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+			evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+			evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+			evh.start()?;
+			let addr = format!("127.0.0.1:{}", $port);
+			let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+			assert_eq!(conn.evh_instance_id(), 0);
+			evh.add_server_connection(conn)?;
+			evh
+		}};
+	}
 
-		assert!(
-			EventHandlerImpl::call_on_close(&mut user_context, 0, &mut callbacks, &mut ehc).is_ok()
-		);
+	#[test]
+	fn test_evh_connection_ids() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let (tx, rx) = test_info.sync_channel();
+		let accepted1 = lock_box!(vec![])?;
+		let accepted2 = lock_box!(vec![])?;
+		let accepted3 = lock_box!(vec![])?;
+		let port1 = test_info.port();
+		let port2 = pick_free_port()?;
+
+		let instance_id1;
+		let instance_id2;
+		{
+			let evh1 = id_evh!(port1, accepted1, tx);
+			let evh2 = id_evh!(port2, accepted2, tx);
+			instance_id1 = evh1.instance_id();
+			instance_id2 = evh2.instance_id();
+			assert_ne!(instance_id1, instance_id2);
+
+			let mut strms = vec![];
+			for _ in 0..5 {
+				strms.push(TcpStream::connect(format!("127.0.0.1:{}", port1))?);
+				rx.recv()?;
+				strms.push(TcpStream::connect(format!("127.0.0.1:{}", port2))?);
+				rx.recv()?;
+			}
+		}
 
-		callbacks.on_close = None;
-		assert!(
-			EventHandlerImpl::call_on_close(&mut user_context, 0, &mut callbacks, &mut ehc).is_ok()
-		);
+		// rebuild the first EventHandler after it was dropped
+		let evh3 = id_evh!(port1, accepted3, tx);
+		let instance_id3 = evh3.instance_id();
+		assert_ne!(instance_id3, instance_id1);
+		assert_ne!(instance_id3, instance_id2);
+		let mut strms = vec![];
+		for _ in 0..5 {
+			strms.push(TcpStream::connect(format!("127.0.0.1:{}", port1))?);
+			rx.recv()?;
+		}
 
-		ehc.handle_hash.insert(0, 0);
-		ehc.id_hash
-			.insert(0, ConnectionVariant::Wakeup(Wakeup::new()?));
-		assert!(
-			EventHandlerImpl::process_close(0, &mut ehc, &mut callbacks, &mut user_context).is_ok()
-		);
+		let mut all = HashSet::new();
+		for (accepted, instance_id) in [
+			(accepted1, instance_id1),
+			(accepted2, instance_id2),
+			(accepted3, instance_id3),
+		] {
+			let accepted = rlock!(accepted).clone();
+			assert_eq!(accepted.len(), 5);
+			for id in accepted.iter() {
+				let connection_id = ConnectionId::from(*id);
+				assert_eq!(connection_id.instance_id, instance_id);
+				assert_eq!(u128::from(connection_id), *id);
+				assert!(all.insert(*id));
+			}
+		}
 
-		let port = pick_free_port()?;
-		let _server = EvhBuilder::build_server_connection(&format!("127.0.0.1:{}", port), 1)?;
-		let mut client = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		Ok(())
+	}
 
-		// try to accept on the client (internal error printed out, but it returns ok)
-		assert!(EventHandlerImpl::process_accept(
-			&client,
-			&mut vec![],
-			&DebugInfo::default(),
-			&mut callbacks,
-		)
-		.is_ok());
+	fn close_reason_name(reason: &CloseReason) -> &'static str {
+		match reason {
+			CloseReason::PeerClosed => "PeerClosed",
+			CloseReason::ReadError(_) => "ReadError",
+			CloseReason::WriteError(_) => "WriteError",
+			CloseReason::OutOfSlabs => "OutOfSlabs",
+			CloseReason::IdleTimeout => "IdleTimeout",
+			CloseReason::LocalClose => "LocalClose",
+			CloseReason::Panic => "Panic",
+			CloseReason::CallbackTimeout => "CallbackTimeout",
+			CloseReason::TransformError(_) => "TransformError",
+			CloseReason::MemoryPressure => "MemoryPressure",
+			CloseReason::ThreadReplaced => "ThreadReplaced",
+		}
+	}
 
-		assert!(EventHandlerImpl::process_write_event(
-			&config,
-			&mut ehc,
-			&mut callbacks,
-			0,
-			&mut user_context
-		)
-		.is_ok());
+	#[test]
+	fn test_evh_close_reasons() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhReadSlabCount(1),
+			EvhStatsUpdateMillis(100)
+		)?;
 
-		ehc.handle_hash.clear();
-		ehc.id_hash.clear();
+		// "hold" keeps the connection's slab, "close" closes the connection and anything
+		// else is echoed back
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			if data != b"hold" {
+				ctx.clear_all(connection)?;
+				let mut wh = connection.write_handle()?;
+				if data == b"close" {
+					wh.close()?;
+				} else {
+					wh.write(&data)?;
+				}
+			}
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
 
-		ehc.handle_hash.insert(0, 0);
+		let mut reasons = lock_box!(vec![])?;
+		let reasons_clone = reasons.clone();
+		let (tx, rx) = test_info.sync_channel();
+		evh.set_on_close(move |_connection, reason, _ctx| -> Result<(), Error> {
+			wlock!(reasons).push(close_reason_name(reason));
+			tx.send(())?;
+			Ok(())
+		})?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
 
-		assert!(EventHandlerImpl::process_write_event(
-			&config,
-			&mut ehc,
-			&mut callbacks,
-			0,
-			&mut user_context
-		)
-		.is_ok());
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+		let mut buf = [0u8; 100];
 
-		ehc.handle_hash.clear();
-		ehc.id_hash.clear();
+		// the peer closes the connection
+		{
+			let mut strm = TcpStream::connect(addr.clone())?;
+			strm.write(b"hi")?;
+			assert_eq!(strm.read(&mut buf)?, 2);
+		}
+		rx.recv()?;
 
-		ehc.handle_hash.insert(0, 0);
-		ehc.id_hash
-			.insert(0, ConnectionVariant::Wakeup(Wakeup::new()?));
+		// the connection is closed locally
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write(b"close")?;
+		assert_eq!(strm.read(&mut buf)?, 0);
+		rx.recv()?;
 
-		assert!(EventHandlerImpl::process_write_event(
-			&config,
-			&mut ehc,
-			&mut callbacks,
-			0,
-			&mut user_context
-		)
-		.is_ok());
+		// simulated read error
+		evh.set_debug_info(DebugInfo {
+			read_err: lock_box!(true)?,
+			..Default::default()
+		})?;
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write(b"hi")?;
+		assert_eq!(strm.read(&mut buf)?, 0);
+		rx.recv()?;
 
-		client.wakeup = Some(Wakeup::new()?);
-		client.state = Some(lock_box!(EventHandlerState::new()?)?);
-		client.debug_info = DebugInfo {
-			write_err2: lock_box!(true)?,
+		// simulated write error
+		evh.set_debug_info(DebugInfo {
+			write_err: lock_box!(true)?,
+			pending: lock_box!(true)?,
 			..Default::default()
-		};
-		assert!(EventHandlerImpl::write_loop(&mut client, &mut callbacks).is_ok());
+		})?;
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write(b"hi")?;
+		assert_eq!(strm.read(&mut buf)?, 0);
+		rx.recv()?;
+		evh.set_debug_info(DebugInfo::default())?;
+
+		// the only read slab is held by the first connection so the second is closed
+		let mut strm1 = TcpStream::connect(addr.clone())?;
+		strm1.write(b"hold")?;
+		sleep(Duration::from_millis(100));
+		let mut strm2 = TcpStream::connect(addr.clone())?;
+		strm2.write(b"hi")?;
+		assert_eq!(strm2.read(&mut buf)?, 0);
+		rx.recv()?;
+
+		assert_eq!(
+			*rlock!(reasons_clone),
+			vec![
+				"PeerClosed",
+				"LocalClose",
+				"ReadError",
+				"WriteError",
+				"OutOfSlabs"
+			]
+		);
+
+		// the counts are also reported in the stats
+		let mut close_reasons = CloseReasonStats::default();
+		for _ in 0..100 {
+			let stats = evh.wait_for_stats()?;
+			close_reasons.peer_closed += stats.close_reasons.peer_closed;
+			close_reasons.read_error += stats.close_reasons.read_error;
+			close_reasons.write_error += stats.close_reasons.write_error;
+			close_reasons.out_of_slabs += stats.close_reasons.out_of_slabs;
+			close_reasons.local_close += stats.close_reasons.local_close;
+			cbreak!(close_reasons.out_of_slabs > 0);
+		}
+		assert_eq!(
+			close_reasons,
+			CloseReasonStats {
+				peer_closed: 1,
+				read_error: 1,
+				write_error: 1,
+				out_of_slabs: 1,
+				idle_timeout: 0,
+				local_close: 1,
+				panic: 0,
+				callback_timeout: 0,
+				transform_error: 0,
+				memory_pressure: 0,
+				thread_replaced: 0,
+			}
+		);
 
 		Ok(())
 	}
 
-	#[test]
-	fn test_evh_execute_thread_other_situations() -> Result<(), Error> {
-		let config = EventHandlerConfig {
-			debug: false,
-			housekeeping_frequency_millis: 100,
-			stats_update_frequency_millis: 100,
-			threads: 1,
-			timeout: 1_000,
-			read_slab_count: 1,
-			read_slab_size: 100,
-			out_of_slabs_message: "".to_string(),
-		};
-		let debug_info = DebugInfo {
-			internal_panic: lock_box!(true)?,
-			..Default::default()
-		};
+	fn xor_transform(data: &[u8], output: &mut Vec<u8>) -> Result<(), Error> {
+		output.extend(data.iter().map(|b| b ^ 0x5a));
+		Ok(())
+	}
 
-		let read_slabs = slab_allocator!(
-			SlabSize(config.read_slab_size),
-			SlabCount(config.read_slab_count)
+	#[test]
+	fn test_evh_transforms() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(100)
 		)?;
-		let user_context = UserContextImpl {
-			read_slabs,
-			user_data: None,
-			slab_cur: usize::MAX,
-		};
-		let user_context_arr = array!(1, &lock_box!(user_context)?)?;
-		let state = array!(config.threads, &lock_box!(EventHandlerState::new()?)?)?;
-		let w = Wakeup::new()?;
-		let wakeups = array!(1, &w)?;
 
-		let global_stats = GlobalStats {
-			stats: EvhStats::new(),
-			update_counter: 0,
-			tx: None,
-		};
-		let stats = lock_box!(global_stats)?;
+		// echo everything that is read
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			connection.write_handle()?.write(&data)?;
+			Ok(())
+		})?;
 
-		let sample = EventHandlerContext::new(wakeups, 0, stats)?;
-		let sample = lock_box!(sample)?;
-		let ctx_arr = array!(1, &sample)?;
-		let callbacks = EventHandlerCallbacks {
-			on_read: Some(Box::pin(
-				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
-					Ok(())
-				},
-			)),
-			on_accept: Some(Box::pin(
-				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
+		// the first connection is XOR'd in both directions. The second connection's inbound
+		// transform doubles each byte so that its output does not fit in the slab.
+		let mut accepts = lock_box!(0)?;
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			if rlock!(accepts) == 0 {
+				connection.set_inbound_transform(Box::new(xor_transform))?;
+				connection.set_outbound_transform(Box::new(xor_transform))?;
+			} else {
+				connection.set_inbound_transform(Box::new(|data, output| {
+					for b in data {
+						output.extend([*b, *b]);
+					}
+					Ok(())
+				}))?;
+			}
+			wlock!(accepts) += 1;
+			Ok(())
+		})?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+
+		// the peer XORs its data too so the handler sees the plain text and the echo is
+		// XOR'd on the way back
+		let plain: Vec<u8> = (0..500).map(|i| (i % 251) as u8).collect();
+		let mut wire = vec![];
+		xor_transform(&plain, &mut wire)?;
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write_all(&wire)?;
+		let mut echo = vec![0u8; wire.len()];
+		strm.read_exact(&mut echo)?;
+		assert_eq!(echo, wire);
+
+		// 60 bytes are read and 120 bytes are delivered
+		let mut strm = TcpStream::connect(addr.clone())?;
+		let data: Vec<u8> = (0..60).collect();
+		strm.write_all(&data)?;
+		let mut echo = vec![0u8; 120];
+		strm.read_exact(&mut echo)?;
+		let expected: Vec<u8> = data.iter().flat_map(|b| [*b, *b]).collect();
+		assert_eq!(echo, expected);
+
+		// only the bytes read from the socket are counted
+		let mut bytes_read = 0;
+		for _ in 0..100 {
+			let stats = evh.wait_for_stats()?;
+			bytes_read += stats.bytes_read;
+			cbreak!(bytes_read >= 560);
+		}
+		assert_eq!(bytes_read, 560);
+
+		Ok(())
+	}
+
+	// responses are 'R', the correlation_id (8 bytes), the length (4 bytes) and the data.
+	// Other messages are 'U', the length and the data and are passed on to on_read.
+	fn response_extractor() -> ResponseExtractor {
+		let mut buffer: Vec<u8> = vec![];
+		Box::new(move |data, rest| {
+			buffer.extend(data);
+			let mut responses = vec![];
+			loop {
+				let offset = match buffer.first() {
+					Some(b'R') => 13,
+					Some(b'U') => 5,
+					Some(b) => return Err(err!(ErrKind::IllegalArgument, "bad tag {}", b)),
+					None => break,
+				};
+				cbreak!(buffer.len() < offset);
+				let len =
+					u32::from_be_bytes(buffer[offset - 4..offset].try_into().unwrap()) as usize;
+				cbreak!(buffer.len() < offset + len);
+				let frame: Vec<u8> = buffer.drain(..offset + len).collect();
+				if frame[0] == b'R' {
+					let id = u64::from_be_bytes(frame[1..9].try_into().unwrap());
+					responses.push((id, frame[offset..].to_vec()));
+				} else {
+					rest.extend(&frame[offset..]);
+				}
+			}
+			Ok(responses)
+		})
+	}
+
+	struct Woken(AtomicBool);
+
+	impl Wake for Woken {
+		fn wake(self: Arc<Self>) {
+			self.0.store(true, Ordering::SeqCst);
+		}
+	}
+
+	fn response_frame(id: u64, data: &[u8]) -> Vec<u8> {
+		let mut frame = vec![b'R'];
+		frame.extend(id.to_be_bytes());
+		frame.extend((data.len() as u32).to_be_bytes());
+		frame.extend(data);
+		frame
+	}
+
+	#[test]
+	fn test_evh_request_response() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let listener = TcpListener::bind(format!("127.0.0.1:{}", test_info.port()))?;
+		let (tx, rx) = sync_channel::<Vec<u8>>(10);
+
+		// the server reads 100 requests (the correlation_id and a byte) and answers all but
+		// request 50 in a random order. Whatever is sent over the channel is written after.
+		let server = thread::spawn(move || -> Result<(), Error> {
+			let (mut strm, _) = listener.accept()?;
+			let mut requests = vec![];
+			for _ in 0..100 {
+				let mut buf = [0u8; 9];
+				strm.read_exact(&mut buf)?;
+				requests.push((u64::from_be_bytes(buf[0..8].try_into().unwrap()), buf[8]));
+			}
+			for i in (1..requests.len()).rev() {
+				requests.swap(i, random::<usize>() % (i + 1));
+			}
+			let mut out = vec![];
+			for (id, b) in requests {
+				if id != 50 {
+					out.extend(response_frame(id, &[b, b]));
+				}
+			}
+			// write the responses in small pieces so that frames are split across reads
+			for chunk in out.chunks(7) {
+				strm.write_all(chunk)?;
+			}
+			for data in rx {
+				strm.write_all(&data)?;
+			}
+			Ok(())
+		});
+
+		let mut evh = evh!(EvhTimeout(10_000), EvhThreads(1), EvhReadSlabSize(100))?;
+		let reads = lock_box!(Vec::<u8>::new())?;
+		let reads_clone = reads.clone();
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut reads = reads_clone.clone();
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				wlock!(reads).extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let unmatched = lock_box!(vec![])?;
+		let mut unmatched_clone = unmatched.clone();
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let mut conn = EvhBuilder::build_client_connection("127.0.0.1", test_info.port())?;
+		conn.set_response_extractor(response_extractor())?;
+		conn.set_unmatched_response_handler(Box::new(move |id, data| {
+			wlock!(unmatched_clone).push((id, data));
+			Ok(())
+		}))?;
+		let wh = evh.add_client_connection(conn)?;
+		info!("client connected to {}", addr)?;
+
+		// four threads write 25 requests each
+		let mut jhs = vec![];
+		for t in 0..4u64 {
+			let mut wh = wh.clone();
+			jhs.push(thread::spawn(move || -> Result<_, Error> {
+				let mut futures = vec![];
+				for id in (t * 25)..(t * 25 + 25) {
+					let timeout = if id == 50 { 300 } else { 30_000 };
+					let mut request = id.to_be_bytes().to_vec();
+					request.push(id as u8);
+					futures.push(wh.request(&request, id, timeout)?);
+				}
+				Ok(futures)
+			}));
+		}
+		let mut futures = vec![];
+		for jh in jhs {
+			futures.extend(jh.join().unwrap()?);
+		}
+		assert_eq!(futures.len(), 100);
+
+		// a correlation_id may not be used twice while it is pending
+		let mut wh2 = wh.clone();
+		let e = wh2.request(b"dup", 50, 1_000).err().unwrap();
+		assert!(matches!(e.kind(), ErrorKind::AlreadyRegistered(_)));
+
+		let mut withheld = None;
+		for future in futures {
+			let id = future.correlation_id();
+			if id == 50 {
+				withheld = Some(future);
+			} else {
+				assert_eq!(future.wait()?, vec![id as u8, id as u8]);
+			}
+		}
+		let start = Instant::now();
+		let e = withheld.unwrap().wait().unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Timeout(_)));
+		assert!(start.elapsed() < Duration::from_millis(5_000));
+		assert!(rlock!(unmatched).is_empty());
+
+		// the late response and one that was never requested go to the fallback handler.
+		// Other messages are still passed to on_read.
+		let mut data = response_frame(50, b"late");
+		data.extend(b"U");
+		data.extend(5u32.to_be_bytes());
+		data.extend(b"hello");
+		data.extend(response_frame(1_000, b"stray"));
+		tx.send(data)?;
+		let mut count = 0;
+		while rlock!(unmatched).len() < 2 || rlock!(reads).len() < 5 {
+			sleep(Duration::from_millis(1));
+			count += 1;
+			assert!(count < 10_000);
+		}
+		assert_eq!(
+			rlock!(unmatched),
+			vec![(50, b"late".to_vec()), (1_000, b"stray".to_vec())]
+		);
+		assert_eq!(rlock!(reads), b"hello".to_vec());
+
+		// pending requests fail once the connection is closed. The future is woken.
+		let mut future = wh2.request(b"x", 2_000, 30_000)?;
+		let woken = Arc::new(Woken(AtomicBool::new(false)));
+		let waker = Waker::from(woken.clone());
+		let mut cx = Context::from_waker(&waker);
+		assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+		wh2.close()?;
+		let mut count = 0;
+		while !woken.0.load(Ordering::SeqCst) {
+			sleep(Duration::from_millis(1));
+			count += 1;
+			assert!(count < 10_000);
+		}
+		match Pin::new(&mut future).poll(&mut cx) {
+			Poll::Ready(Err(e)) => assert!(matches!(e.kind(), ErrorKind::ConnectionClosed(_))),
+			_ => panic!("expected the future to be ready with an error"),
+		}
+		assert!(wh2.request(b"x", 2_001, 30_000).is_err());
+
+		drop(tx);
+		server.join().unwrap()?;
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_transform_error() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(100)
+		)?;
+
+		// "out" makes the handler write data that the outbound transform rejects
+		let mut write_err = lock_box!(false)?;
+		let write_err_clone = write_err.clone();
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			if data == b"out" {
+				wlock!(write_err) = connection.write_handle()?.write(b"bad").is_err();
+			}
+			Ok(())
+		})?;
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			let transform = |data: &[u8], output: &mut Vec<u8>| {
+				ensure!(data != b"bad", ErrKind::IllegalArgument, "bad data");
+				output.extend(data);
+				Ok(())
+			};
+			connection.set_inbound_transform(Box::new(transform))?;
+			connection.set_outbound_transform(Box::new(transform))?;
+			Ok(())
+		})?;
+		let mut reasons = lock_box!(vec![])?;
+		let reasons_clone = reasons.clone();
+		let (tx, rx) = test_info.sync_channel();
+		evh.set_on_close(move |_connection, reason, _ctx| -> Result<(), Error> {
+			wlock!(reasons).push(close_reason_name(reason));
+			tx.send(())?;
+			Ok(())
+		})?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+		let mut buf = [0u8; 100];
+
+		// the inbound transform fails
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write_all(b"bad")?;
+		assert_eq!(strm.read(&mut buf)?, 0);
+		rx.recv()?;
+
+		// the outbound transform fails
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write_all(b"out")?;
+		assert_eq!(strm.read(&mut buf)?, 0);
+		rx.recv()?;
+		assert!(rlock!(write_err_clone));
+
+		assert_eq!(
+			*rlock!(reasons_clone),
+			vec!["TransformError", "TransformError"]
+		);
+
+		let mut transform_error = 0;
+		for _ in 0..100 {
+			let stats = evh.wait_for_stats()?;
+			transform_error += stats.close_reasons.transform_error;
+			cbreak!(transform_error >= 2);
+		}
+		assert_eq!(transform_error, 2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_require_data_within() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhRequireDataWithinMillis(100)
+		)?;
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			connection.write_handle()?.write(&data)?;
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+
+		let mut reasons = lock_box!(vec![])?;
+		let reasons_clone = reasons.clone();
+		let (tx, rx) = test_info.sync_channel();
+		evh.set_on_close(move |_connection, reason, _ctx| -> Result<(), Error> {
+			wlock!(reasons).push(close_reason_name(reason));
+			tx.send(())?;
+			Ok(())
+		})?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+		let mut buf = [0u8; 100];
+
+		// a connection that never sends anything is closed
+		let mut silent = TcpStream::connect(addr.clone())?;
+		assert_eq!(silent.read(&mut buf)?, 0);
+		rx.recv()?;
+		assert_eq!(*rlock!(reasons_clone), vec!["IdleTimeout"]);
+
+		// a connection that sends data right away stays open past the deadline
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write(b"a")?;
+		assert_eq!(strm.read(&mut buf)?, 1);
+		sleep(Duration::from_millis(300));
+		strm.write(b"bc")?;
+		assert_eq!(strm.read(&mut buf)?, 2);
+		assert_eq!(&buf[0..2], b"bc");
+		assert_eq!(rlock!(reasons_clone).len(), 1);
+
+		Ok(())
+	}
+
+	#[test]
+	#[cfg(target_os = "linux")]
+	fn test_evh_defer_accept() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(EvhTimeout(10), EvhThreads(1), EvhDeferAcceptSecs(1))?;
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			connection.write_handle()?.write(&data)?;
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+
+		// connections that send data are accepted and processed normally
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write(b"defer")?;
+		let mut buf = [0u8; 100];
+		assert_eq!(strm.read(&mut buf)?, 5);
+		assert_eq!(&buf[0..5], b"defer");
+
+		Ok(())
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn test_evh_socket_buffer_sizes() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhRecvBufferSize(32 * 1024),
+			EvhSendBufferSize(32 * 1024)
+		)?;
+
+		let mut sizes = lock_box!(vec![])?;
+		let sizes_clone = sizes.clone();
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			let recv = connection.recv_buffer_size()?;
+			let send = connection.send_buffer_size()?;
+			wlock!(sizes).push((recv, send));
+			Ok(())
+		})?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+
+		let _strm = TcpStream::connect(addr.clone())?;
+		let mut count = 0;
+		loop {
+			sleep(Duration::from_millis(10));
+			count += 1;
+			cbreak!(!rlock!(sizes_clone).is_empty() || count > 500);
+		}
+
+		// linux doubles the requested value, others may round it
+		let (recv, send) = rlock!(sizes_clone)[0];
+		info!("configured buffer sizes: recv={},send={}", recv, send)?;
+		assert!((32 * 1024..=64 * 1024).contains(&recv));
+		assert!((32 * 1024..=64 * 1024).contains(&send));
+
+		// client connections are configured when they are added
+		let client = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		let default_recv = client.recv_buffer_size()?;
+		let default_send = client.send_buffer_size()?;
+		let client_handle = client.handle();
+		evh.add_client_connection(client)?;
+		let recv = recv_buffer_size_impl(client_handle)?;
+		let send = send_buffer_size_impl(client_handle)?;
+		assert!((32 * 1024..=64 * 1024).contains(&recv));
+		assert!((32 * 1024..=64 * 1024).contains(&send));
+
+		// when the options are 0 the kernel defaults are kept
+		let test_info2 = test_info!()?;
+		let mut evh2 = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhRecvBufferSize(0),
+			EvhSendBufferSize(0)
+		)?;
+
+		let mut sizes = lock_box!(vec![])?;
+		let sizes_clone = sizes.clone();
+		evh2.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh2.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			let recv = connection.recv_buffer_size()?;
+			let send = connection.send_buffer_size()?;
+			wlock!(sizes).push((recv, send));
+			Ok(())
+		})?;
+		evh2.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh2.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh2.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh2.start()?;
+
+		let port = test_info2.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh2.add_server_connection(conn)?;
+
+		let _strm = TcpStream::connect(addr.clone())?;
+		let mut count = 0;
+		loop {
+			sleep(Duration::from_millis(10));
+			count += 1;
+			cbreak!(!rlock!(sizes_clone).is_empty() || count > 500);
+		}
+
+		let (recv, send) = rlock!(sizes_clone)[0];
+		info!("default buffer sizes: recv={},send={}", recv, send)?;
+		assert_eq!(recv, default_recv);
+		assert_eq!(send, default_send);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_on_data_chunk() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh: Box<dyn EventHandler<NoOnRead, _, _, _, _> + Send + Sync> = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(100)
+		)?;
+
+		// count the lines of every chunk. Anything that starts with "partial" is retained
+		// until the end of the line is received.
+		let mut lines = lock_box!(0usize)?;
+		let mut partial = lock_box!(vec![])?;
+		let lines_clone = lines.clone();
+		let partial_clone = partial.clone();
+		evh.set_on_data_chunk(Box::new(move |_connection, chunk| {
+			if chunk.starts_with(b"partial") {
+				if !chunk.ends_with(b"\n") {
+					return Ok(ChunkAction::Retain);
+				}
+				wlock!(partial) = chunk.to_vec();
+			}
+			wlock!(lines) += chunk.iter().filter(|b| **b == b'\n').count();
+			Ok(ChunkAction::Free)
+		}))?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+
+		// a 1mb stream of lines that span many chunks
+		let mut data = vec![];
+		let mut expected = 0;
+		while data.len() < 1024 * 1024 {
+			data.extend(format!("line number {}\n", expected).as_bytes());
+			expected += 1;
+		}
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write_all(&data)?;
+		let mut count = 0;
+		while count < 1_000 && rlock!(lines_clone) < expected {
+			sleep(Duration::from_millis(10));
+			count += 1;
+		}
+		assert_eq!(rlock!(lines_clone), expected);
+
+		// a retained chunk is passed again with the data that follows it
+		strm.write(b"partial")?;
+		sleep(Duration::from_millis(100));
+		strm.write(b" line\n")?;
+		let mut count = 0;
+		while count < 1_000 && rlock!(lines_clone) < expected + 1 {
+			sleep(Duration::from_millis(10));
+			count += 1;
+		}
+		assert_eq!(rlock!(lines_clone), expected + 1);
+		assert_eq!(rlock!(partial_clone), b"partial line\n".to_vec());
+
+		// the slabs were freed as the chunks were processed
+		let mut found = false;
+		for _ in 0..50 {
+			let stats = evh.wait_for_stats()?;
+			if stats.read_slabs.len() == 1 {
+				assert_eq!(stats.read_slabs[0].used, 0);
+				found = true;
+				break;
+			}
+		}
+		assert!(found);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_on_data_chunk_and_on_read() -> Result<(), Error> {
+		let mut evh = evh_oro!(EvhTimeout(10), EvhThreads(1), EvhStatsUpdateMillis(100))?;
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_data_chunk(Box::new(move |_connection, _chunk| Ok(ChunkAction::Free)))?;
+		let e = evh.start().err().unwrap();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
+		Ok(())
+	}
+
+	// one client receives a large response while another sends pings on the same thread.
+	// Returns the slowest ping round trip, the number of writes of queued data and the number of
+	// bytes that were queued.
+	fn write_quota_helper(port: u16, max: usize) -> Result<(Duration, usize, u128), Error> {
+		const BIG: usize = 10 * 1024 * 1024;
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(100),
+			EvhMaxWriteBytesPerLoop(max)
+		)?;
+
+		let big: Vec<u8> = (0..BIG).map(|i| (i % 251) as u8).collect();
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			let mut wh = connection.write_handle()?;
+			if data == b"big" {
+				wh.write(&big)?;
+			} else {
+				wh.write(&data)?;
+			}
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+
+		let mut big_strm = TcpStream::connect(addr.clone())?;
+		let mut ping_strm = TcpStream::connect(addr.clone())?;
+		big_strm.write(b"big")?;
+		let reader = thread::spawn(move || -> Result<(), Error> {
+			let mut buf = vec![0u8; BIG];
+			big_strm.read_exact(&mut buf)?;
+			for (i, b) in buf.iter().enumerate() {
+				assert_eq!(*b, (i % 251) as u8);
+			}
+			Ok(())
+		});
+
+		let mut slowest = Duration::ZERO;
+		let mut buf = [0u8; 4];
+		while !reader.is_finished() {
+			let start = Instant::now();
+			ping_strm.write(b"ping")?;
+			ping_strm.read_exact(&mut buf)?;
+			assert_eq!(&buf, b"ping");
+			slowest = slowest.max(start.elapsed());
+		}
+		reader.join().unwrap()?;
+
+		// add up the stats until all of the queued data has been reported
+		let (mut delay_writes, mut bytes_delay_write) = (0, 0);
+		for _ in 0..100 {
+			let stats = evh.wait_for_stats()?;
+			delay_writes += stats.delay_writes;
+			bytes_delay_write += stats.bytes_delay_write;
+			cbreak!(bytes_delay_write > 0 && stats.bytes_delay_write == 0);
+		}
+		Ok((slowest, delay_writes, bytes_delay_write))
+	}
+
+	#[test]
+	fn test_evh_max_write_bytes_per_loop() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let quota = 64 * 1024;
+		let (slowest, delay_writes, bytes_delay_write) =
+			write_quota_helper(test_info.port(), quota)?;
+
+		// no single write of queued data was larger than the quota
+		let quota: u128 = try_into!(quota)?;
+		let delay_writes: u128 = try_into!(delay_writes)?;
+		assert!(bytes_delay_write > 0);
+		assert!(delay_writes * quota >= bytes_delay_write);
+
+		// the pings keep being answered while the large response is sent
+		info!("slowest ping with quota: {:?}", slowest)?;
+		assert!(slowest < Duration::from_millis(1_000));
+
+		// without a quota all data is still delivered. Timing depends on the machine so the
+		// difference is only logged.
+		let (unlimited, _, _) = write_quota_helper(pick_free_port()?, 0)?;
+		info!("slowest ping unlimited: {:?}", unlimited)?;
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_write_handle_error() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(100)
+		)?;
+
+		let debug_info = DebugInfo {
+			write_handle_err: lock_box!(true)?,
+			..Default::default()
+		};
+		evh.set_debug_info(debug_info.clone())?;
+
+		let (tx, rx) = test_info.sync_channel();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut wh = connection.write_handle()?;
+			match wh.write(b"test") {
+				Ok(_) => {
+					info!("write ok")?;
+				}
+				Err(e) => {
+					info!("write err: {}", e)?;
+					tx.send(())?;
+				}
+			}
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+
+		let mut strm = TcpStream::connect(addr.clone())?;
+		// trigger write handle error
+		strm.write(b"01234567890123456789")?;
+
+		rx.recv()?;
+
+		// now fix it
+		evh.set_debug_info(DebugInfo::default())?;
+
+		strm.write(b"01234567890123456789")?;
+
+		let mut buf = [0u8; 100];
+
+		let len = strm.read(&mut buf)?;
+		// the message goes through now
+		assert_eq!(len, 4);
+		assert_eq!(&buf[0..4], b"test");
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_connection_close() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(100)
+		)?;
+
+		let mut recv_msg = lock_box!(false)?;
+		let recv_msg_clone = recv_msg.clone();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut wh = connection.write_handle()?;
+			wh.write(b"test")?;
+			wh.close()?;
+			assert!(wh.write(b"test").is_err());
+			assert!(wh.trigger_on_read().is_err());
+			assert!(wh.close().is_err());
+			wlock!(recv_msg) = true;
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+
+		let mut strm = TcpStream::connect(addr)?;
+		strm.write(b"01234567890123456789")?;
+
+		let mut buf = [0u8; 100];
+		let len = strm.read(&mut buf)?;
+		assert_eq!(len, 4);
+		assert_eq!(&buf[0..4], b"test");
+
+		// closed connection
+		assert_eq!(strm.read(&mut buf)?, 0);
+		assert!(rlock!(recv_msg_clone));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_normal_fatal_error() -> Result<(), Error> {
+		let threads = 2;
+
+		let mut evh = evh!(
+			EvhThreads(threads),
+			EvhTimeout(u16::MAX),
+			EvhHouseKeeperFrequencyMillis(usize::MAX),
+			EvhStartTimeoutMillis(10_000)
+		)?;
+		evh.set_debug_info(DebugInfo {
+			normal_fatal_error: lock_box!(true)?,
+			..Default::default()
+		})?;
+
+		evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_accept(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_, _, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_, _| -> Result<(), Error> { Ok(()) })?;
+
+		// the threads fail during initialization, so start reports it rather than returning
+		// an evh that would hang when a connection is added
+		let begin = Instant::now();
+		let e = evh.start().unwrap_err();
+		assert!(begin.elapsed() < Duration::from_millis(10_000));
+		assert!(matches!(e.kind(), ErrorKind::IllegalState(_)));
+		let text = e.kind().to_string();
+		assert!(text.contains("failed to start: "), "{}", text);
+		assert!(text.contains("normal fatal err"), "{}", text);
+
+		assert!(!evh.is_running()?);
+		let health = evh.thread_health()?;
+		assert_eq!(health.len(), threads);
+		assert!(health.contains(&ThreadHealth::Failed(
+			"simulated test error: normal fatal err".to_string()
+		)));
+
+		match evh_oro!(EvhStartTimeoutMillis(0)) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+				return Err(err!(ErrKind::Test, "expected a configuration error"));
+			}
+			Err(e) => assert!(matches!(e.kind(), ErrorKind::Configuration(_))),
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_thread_health() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let threads = 3;
+
+		let mut evh = evh!(EvhThreads(threads), EvhTimeout(100))?;
+		evh.set_on_read(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_accept(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_, _, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_, _| -> Result<(), Error> { Ok(()) })?;
+
+		assert!(!evh.is_running()?);
+		assert_eq!(evh.thread_health()?, vec![ThreadHealth::Starting; threads]);
+
+		// once start returns, every thread has initialized
+		evh.start()?;
+		assert!(evh.is_running()?);
+		assert_eq!(evh.thread_health()?, vec![ThreadHealth::Running; threads]);
+
+		let mut controller = evh.controller()?;
+		assert!(controller.is_running()?);
+
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		controller.stop()?;
+		let mut count = 0;
+		while controller.thread_health()? != vec![ThreadHealth::Stopped; threads] {
+			sleep(Duration::from_millis(10));
+			count += 1;
+			assert!(count < 1_000);
+		}
+		assert!(!evh.is_running()?);
+
+		// the threads have exited, so adding a connection returns an error instead of hanging
+		let addr = format!("127.0.0.1:{}", pick_free_port()?);
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		let e = evh.add_server_connection(conn).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::IllegalState(_)));
+		assert!(e.kind().to_string().contains("is not running: stopped"));
+
+		Ok(())
+	}
+
+	struct TestRecorder {
+		counters: Arc<[AtomicU64; 8]>,
+		gauges: Arc<[AtomicU64; 8]>,
+		panic: bool,
+	}
+
+	impl MetricsRecorder for TestRecorder {
+		fn incr_counter(&self, metric: EvhMetric, by: u64) {
+			if self.panic {
+				panic!("recorder panic");
+			}
+			self.counters[metric as usize].fetch_add(by, Ordering::SeqCst);
+		}
+		fn set_gauge(&self, metric: EvhMetric, value: u64) {
+			self.gauges[metric as usize].store(value, Ordering::SeqCst);
+		}
+	}
+
+	#[test]
+	fn test_evh_metrics_recorder() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(EvhTimeout(10), EvhThreads(1), EvhStatsUpdateMillis(50))?;
+
+		let counters: Arc<[AtomicU64; 8]> = Arc::new(Default::default());
+		let gauges: Arc<[AtomicU64; 8]> = Arc::new(Default::default());
+		evh.set_metrics_recorder(Box::new(TestRecorder {
+			counters: counters.clone(),
+			gauges: gauges.clone(),
+			panic: false,
+		}))?;
+
+		// echo
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			connection.write_handle()?.write(&data)?;
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let mut stats = EvhStats::new();
+		let add = |stats: &mut EvhStats, update: EvhStats| {
+			stats.accepts += update.accepts;
+			stats.closes += update.closes;
+			stats.reads += update.reads;
+			stats.bytes_read += update.bytes_read;
+		};
+		let mut strm = TcpStream::connect(addr.clone())?;
+		strm.write_all(b"hello")?;
+		let mut buf = [0u8; 10];
+		let len = strm.read(&mut buf)?;
+		assert_eq!(&buf[0..len], b"hello");
+
+		// the gauge is set at the next stats update while the connection is still open
+		while stats.accepts == 0
+			|| gauges[EvhMetric::CurrentConnections as usize].load(Ordering::SeqCst) != 1
+		{
+			add(&mut stats, evh.wait_for_stats()?);
+		}
+		drop(strm);
+		while stats.closes == 0 {
+			add(&mut stats, evh.wait_for_stats()?);
+		}
+		add(&mut stats, evh.wait_for_stats()?);
+
+		let counter = |metric: EvhMetric| counters[metric as usize].load(Ordering::SeqCst);
+		assert_eq!(counter(EvhMetric::Accepts), 1);
+		assert_eq!(counter(EvhMetric::Closes), 1);
+		assert_eq!(counter(EvhMetric::Accepts), stats.accepts as u64);
+		assert_eq!(counter(EvhMetric::Closes), stats.closes as u64);
+		assert_eq!(counter(EvhMetric::Reads), stats.reads as u64);
+		assert_eq!(counter(EvhMetric::BytesRead), 5);
+		assert_eq!(counter(EvhMetric::BytesRead), stats.bytes_read as u64);
+		assert_eq!(counter(EvhMetric::BytesWritten), 5);
+		assert_eq!(
+			gauges[EvhMetric::CurrentConnections as usize].load(Ordering::SeqCst),
+			0
+		);
+		assert_eq!(
+			gauges[EvhMetric::PendingWriteBytes as usize].load(Ordering::SeqCst),
+			0
+		);
+
+		// the recorder can only be set before start
+		let recorder = TestRecorder {
+			counters: counters.clone(),
+			gauges: gauges.clone(),
+			panic: false,
+		};
+		assert!(evh.set_metrics_recorder(Box::new(recorder)).is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_metrics_recorder_panic() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(EvhTimeout(10), EvhThreads(1), EvhStatsUpdateMillis(100))?;
+
+		let gauges: Arc<[AtomicU64; 8]> = Arc::new(Default::default());
+		evh.set_metrics_recorder(Box::new(TestRecorder {
+			counters: Arc::new(Default::default()),
+			gauges: gauges.clone(),
+			panic: true,
+		}))?;
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			connection.write_handle()?.write(&data)?;
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		// the recorder panics on the first accept, but the connections are still served
+		for _ in 0..2 {
+			let mut strm = TcpStream::connect(addr.clone())?;
+			strm.write_all(b"hello")?;
+			let mut buf = [0u8; 10];
+			let len = strm.read(&mut buf)?;
+			assert_eq!(&buf[0..len], b"hello");
+		}
+		assert!(evh.is_running()?);
+
+		// the recorder is disabled, so the gauges are never set
+		evh.wait_for_stats()?;
+		assert_eq!(
+			gauges[EvhMetric::CurrentConnections as usize].load(Ordering::SeqCst),
+			0
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_panic_fatal_error() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let threads = 1;
+
+		let mut evh = evh!(
+			EvhThreads(threads),
+			EvhTimeout(u16::MAX),
+			EvhHouseKeeperFrequencyMillis(usize::MAX)
+		)?;
+		evh.set_debug_info(DebugInfo {
+			panic_fatal_error: lock_box!(true)?,
+			..Default::default()
+		})?;
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				let next_chunk = next_chunk.unwrap();
+				data.extend(next_chunk.data());
+			}
+
+			let dstring = from_utf8(&data)?;
+			info!("data[{}]='{}'", connection.id(), dstring,)?;
+			if dstring == "crash\r\n" {
+				let x: Option<u32> = None;
+				let _y = x.unwrap();
+			}
+			let mut wh = connection.write_handle()?;
+			wh.write(&data)?;
+
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+
+		let (tx, rx) = test_info.sync_channel();
+		evh.set_on_panic(move |_id, e| -> Result<(), Error> {
+			let e = e.downcast_ref::<&str>().unwrap_or(&"unknown error");
+			error!("on panic: {}", e)?;
+			tx.send(())?;
+			Ok(())
+		})?;
+
+		evh.set_on_accept(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_, _, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_, _| -> Result<(), Error> { Ok(()) })?;
+
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		info!("Host on {}", addr)?;
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+
+		info!("adding connection now")?;
+		evh.add_server_connection(conn)?;
+
+		let mut strm = TcpStream::connect(addr)?;
+		// trigger panic
+		strm.write(b"crash\r\n")?;
+		rx.recv()?;
+		sleep(Duration::from_millis(QA_SLEEP));
+
+		// the thread failed to recover, so adding another connection to it returns an error
+		let mut count = 0;
+		while evh.is_running()? {
+			sleep(Duration::from_millis(10));
+			count += 1;
+			assert!(count < 1_000);
+		}
+		let health = evh.thread_health()?;
+		assert_eq!(
+			health,
+			vec![ThreadHealth::Failed(
+				"simulated test error: panic fatal err".to_string()
+			)]
+		);
+		let addr = format!("127.0.0.1:{}", pick_free_port()?);
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		let e = evh.add_server_connection(conn).unwrap_err();
+		assert!(e
+			.kind()
+			.to_string()
+			.contains("is not running: simulated test error: panic fatal err"));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_invalid_write_handle() -> Result<(), Error> {
+		let connection = Connection {
+			handle: 0,
+			id: 0,
+			slab_offset: 0,
+			last_slab: 0,
+			first_slab: 0,
+			write_state: lock_box!(WriteState {
+				flags: 0,
+				write_buffer: vec![],
+				trigger_on_read_count: 0,
+				pending_files: VecDeque::new(),
+				deferred: vec![],
+				capture: None,
+				metrics: None,
+				write_delay_millis: 0,
+				rate_limit: 0,
+				hold_until: 0,
+				tokens: 0,
+				last_refill: 0,
+				next_write: None,
+				outbound_transform: None,
+				transform_error: None,
+				sensitive: false,
+				responses: HashMap::new(),
+				response_timers: vec![],
+				memory: None,
+				accounted: 0,
+				attachment_len: 0,
+				attachment_size_hint: None,
+				owner: None,
+			})?,
+			wakeup: None,
+			state: None,
+			tx: None,
+			ctype: ConnectionType::Connection,
+			debug_info: DebugInfo::default(),
+			origin_id: 0,
+			write_final: false,
+			disable_write_final: false,
+			proxy_header: None,
+			proxy_data: vec![],
+			peer_addr: None,
+			evh_instance_id: 0,
+			awaiting_data: false,
+			capture_writer: None,
+			capture: None,
+			attachment: None,
+			exported: false,
+			migrated: false,
+			accepted: true,
+			owned_slabs: 0,
+			slab_quota_paused: false,
+			memory_paused: false,
+			max_unread_bytes: 0,
+			unread_bytes: 0,
+			unread_paused: false,
+			read_eof: false,
+			last_read_millis: 0,
+			write_interest: WriteInterest::NotRegistered,
+			inbound_transform: None,
+			inbound_pending: vec![],
+			loopback: None,
+			sensitive: false,
+			response_extractor: None,
+			unmatched_response_handler: None,
+		};
+		assert!(WriteHandle::new(&connection, DebugInfo::default()).is_err());
+
+		let connection = Connection {
+			handle: 0,
+			id: 0,
+			slab_offset: 0,
+			last_slab: 0,
+			first_slab: 0,
+			write_state: lock_box!(WriteState {
+				flags: 0,
+				write_buffer: vec![],
+				trigger_on_read_count: 0,
+				pending_files: VecDeque::new(),
+				deferred: vec![],
+				capture: None,
+				metrics: None,
+				write_delay_millis: 0,
+				rate_limit: 0,
+				hold_until: 0,
+				tokens: 0,
+				last_refill: 0,
+				next_write: None,
+				outbound_transform: None,
+				transform_error: None,
+				sensitive: false,
+				responses: HashMap::new(),
+				response_timers: vec![],
+				memory: None,
+				accounted: 0,
+				attachment_len: 0,
+				attachment_size_hint: None,
+				owner: None,
+			})?,
+			wakeup: Some(Wakeup::new()?),
+			state: None,
+			tx: None,
+			ctype: ConnectionType::Connection,
+			debug_info: DebugInfo::default(),
+			origin_id: 0,
+			write_final: false,
+			disable_write_final: false,
+			proxy_header: None,
+			proxy_data: vec![],
+			peer_addr: None,
+			evh_instance_id: 0,
+			awaiting_data: false,
+			capture_writer: None,
+			capture: None,
+			attachment: None,
+			exported: false,
+			migrated: false,
+			accepted: true,
+			owned_slabs: 0,
+			slab_quota_paused: false,
+			memory_paused: false,
+			max_unread_bytes: 0,
+			unread_bytes: 0,
+			unread_paused: false,
+			read_eof: false,
+			last_read_millis: 0,
+			write_interest: WriteInterest::NotRegistered,
+			inbound_transform: None,
+			inbound_pending: vec![],
+			loopback: None,
+			sensitive: false,
+			response_extractor: None,
+			unmatched_response_handler: None,
+		};
+		assert!(WriteHandle::new(&connection, DebugInfo::default()).is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_stop_error() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		{
+			let mut evh = evh_oro!(
+				Debug(false),
+				EvhTimeout(u16::MAX),
+				EvhThreads(5),
+				EvhReadSlabSize(100)
+			)?;
+			evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+			evh.set_debug_info(DebugInfo {
+				stop_error: lock_box!(true).unwrap(),
+				..Default::default()
+			})?;
+
+			evh.start()?;
+
+			let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+			evh.add_server_connection(conn)?;
+		}
+
+		// we should still be able to connect because stop failed.
+		assert!(TcpStream::connect(addr).is_ok());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_get_events_error() -> Result<(), Error> {
+		let config = EventHandlerConfig {
+			debug: false,
+			housekeeping_frequency_millis: 100,
+			stats_update_frequency_millis: 100,
+			threads: 1,
+			timeout: 1_000,
+			read_slab_count: 1,
+			read_slab_size: 100,
+			out_of_slabs_message: "".to_string(),
+			client_bind_addr: None,
+			proxy_protocol: false,
+			proxy_header_timeout_millis: 1_000,
+			defer_accept_secs: None,
+			require_data_within_millis: None,
+			max_write_bytes_per_loop: 0,
+			fd_limit: None,
+			fd_warn_threshold_percent: 90,
+			callback_timeout_millis: None,
+			watchdog_action: WatchdogAction::Log,
+			recv_buffer_size: 0,
+			send_buffer_size: 0,
+			strict_sock_opts: false,
+			capture_all: None,
+			reloadable: lock_box!(ReloadableConfig::default())?,
+			reload_generation: Arc::new(AtomicU64::new(0)),
+			config_history: lock_box!(ConfigHistory::new(1)?)?,
+			name: None,
+			strict_ordering: false,
+			strict_keys: false,
+			start_timeout_millis: 5_000,
+			accept_budget_micros: 0,
+			max_slabs_per_connection: None,
+			slab_quota_close: false,
+			memory: None,
+			logger: lock_box!(None)?,
+		};
+		let debug_info = DebugInfo {
+			get_events_error: lock_box!(true)?,
+			..Default::default()
+		};
+
+		let read_slabs = slab_allocator!(
+			SlabSize(config.read_slab_size),
+			SlabCount(config.read_slab_count)
+		)?;
+		let user_context = UserContextImpl {
+			read_slabs,
+			user_data: None,
+			slab_cur: usize::MAX,
+			synthetic: false,
+			read_slab_high_water: 0,
+			reassignments: vec![],
+			resumed_reads: vec![],
+		};
+		let user_context_arr = array!(1, &lock_box!(user_context)?)?;
+		let state = array!(config.threads, &lock_box!(EventHandlerState::new()?)?)?;
+		let w = Wakeup::new()?;
+		let wakeups = array!(1, &w)?;
+
+		let global_stats = GlobalStats {
+			stats: EvhStats::new(),
+			update_counter: 0,
+			tx: None,
+			handle_counts: vec![0; config.threads],
+			listen_addrs: vec![],
+			last_fd_warning: None,
+			fd_warnings: 0,
+			stats_locks: 0,
+		};
+		let stats = lock_box!(global_stats)?;
+
+		let sample = EventHandlerContext::new(wakeups, 0, stats)?;
+		let sample = lock_box!(sample)?;
+		let ctx_arr = array!(1, &sample)?;
+		let callbacks = EventHandlerCallbacks {
+			on_read: Some(Box::pin(
+				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
+					Ok(())
+				},
+			)),
+			on_accept: Some(Box::pin(
+				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
+					Ok(())
+				},
+			)),
+			on_close: Some(Box::pin(
+				move |_: &mut Connection,
+				      _: &CloseReason,
+				      _: &mut Box<dyn UserContext + '_>|
+				      -> Result<(), Error> { Ok(()) },
+			)),
+			on_panic: Some(Box::pin(
+				move |_: &mut Box<dyn UserContext + '_>, _| -> Result<(), Error> { Ok(()) },
+			)),
+			on_housekeeper: Some(Box::pin(
+				move |_: &mut HousekeeperContext<'_>,
+				      _: &mut Box<dyn UserContext + '_>|
+				      -> Result<(), Error> { Ok(()) },
+			)),
+			on_raw_event: None,
+			on_data_chunk: None,
+			on_slab_quota: None,
+			on_read_eof: None,
+			on_panic_info: None,
+			handler: None,
+		};
+
+		spawn(move || {
+			let _ = EventHandlerImpl::execute_thread(
+				config,
+				callbacks,
+				state,
+				ctx_arr,
+				user_context_arr,
+				0,
+				true,
+				&debug_info,
+			);
+		});
+
+		sleep(Duration::from_millis(QA_SLEEP));
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_functions() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let w = Wakeup::new()?;
+		let wakeups = array!(1, &w)?;
+
+		let global_stats = GlobalStats {
+			stats: EvhStats::new(),
+			update_counter: 0,
+			tx: None,
+			handle_counts: vec![0; 1],
+			listen_addrs: vec![],
+			last_fd_warning: None,
+			fd_warnings: 0,
+			stats_locks: 0,
+		};
+		let stats = lock_box!(global_stats)?;
+		let mut ehc = EventHandlerContext::new(wakeups, 0, stats)?;
+		let mut callbacks = EventHandlerCallbacks {
+			on_read: Some(Box::pin(
+				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
+					Ok(())
+				},
+			)),
+			on_accept: Some(Box::pin(
+				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
+					Ok(())
+				},
+			)),
+			on_close: Some(Box::pin(
+				move |_: &mut Connection,
+				      _: &CloseReason,
+				      _: &mut Box<dyn UserContext + '_>|
+				      -> Result<(), Error> { Ok(()) },
+			)),
+			on_panic: Some(Box::pin(
+				move |_: &mut Box<dyn UserContext + '_>, _| -> Result<(), Error> { Ok(()) },
+			)),
+			on_housekeeper: Some(Box::pin(
+				move |_: &mut HousekeeperContext<'_>,
+				      _: &mut Box<dyn UserContext + '_>|
+				      -> Result<(), Error> { Ok(()) },
+			)),
+			on_raw_event: None,
+			on_data_chunk: None,
+			on_slab_quota: None,
+			on_read_eof: None,
+			on_panic_info: None,
+			handler: None,
+		};
+
+		let mut v = VecDeque::new();
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let server = EvhBuilder::build_server_connection(&addr, 5)?;
+		v.push_back(ConnectionVariant::ServerConnection(server));
+		let client = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		v.push_back(ConnectionVariant::ClientConnection(client));
+		let client2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		v.push_back(ConnectionVariant::Connection(client2));
+		v.push_back(ConnectionVariant::Wakeup(Wakeup::new()?));
+		assert!(EventHandlerImpl::close_handles(&mut ehc, &v, &mut callbacks).is_ok());
+
+		let read_slabs = slab_allocator!(SlabSize(100), SlabCount(1))?;
+		let mut user_context = UserContextImpl {
+			read_slabs,
+			user_data: None,
+			slab_cur: usize::MAX,
+			synthetic: false,
+			read_slab_high_water: 0,
+			reassignments: vec![],
+			resumed_reads: vec![],
+		};
+
+		let port = pick_free_port()?;
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 5)?;
+		ehc.id_hash
+			.insert(0, ConnectionVariant::ServerConnection(conn));
+		assert!(EventHandlerImpl::process_write_id(
+			&mut ehc,
+			0,
+			&mut callbacks,
+			&mut user_context,
+			&DebugInfo::default()
+		)
+		.is_ok());
+
+		let mut conn = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		conn.wakeup = Some(Wakeup::new()?);
+		conn.state = Some(lock_box!(EventHandlerState::new()?)?);
+		conn.write_handle()?.trigger_on_read()?;
+		conn.write_handle()?.close()?;
+		ehc.id_hash.insert(0, ConnectionVariant::Connection(conn));
+		assert!(EventHandlerImpl::process_write_id(
+			&mut ehc,
+			0,
+			&mut callbacks,
+			&mut user_context,
+			&DebugInfo::default()
+		)
+		.is_ok());
+
+		let mut conn = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		conn.wakeup = Some(Wakeup::new()?);
+		conn.state = Some(lock_box!(EventHandlerState::new()?)?);
+		conn.write_handle()?.trigger_on_read()?;
+		conn.write_handle()?.close()?;
+		ehc.id_hash
+			.insert(0, ConnectionVariant::ClientConnection(conn));
+		assert!(EventHandlerImpl::process_write_id(
+			&mut ehc,
+			0,
+			&mut callbacks,
+			&mut user_context,
+			&DebugInfo::default()
+		)
+		.is_ok());
+
+		ehc.id_hash
+			.insert(0, ConnectionVariant::Wakeup(Wakeup::new()?));
+		assert!(EventHandlerImpl::process_write_id(
+			&mut ehc,
+			0,
+			&mut callbacks,
+			&mut user_context,
+			&DebugInfo::default()
+		)
+		.is_ok());
+
+		// try on a not found. just prints a warning
+		assert!(EventHandlerImpl::process_write_id(
+			&mut ehc,
+			1,
+			&mut callbacks,
+			&mut user_context,
+			&DebugInfo::default()
+		)
+		.is_ok());
+
+		let config = EventHandlerConfig {
+			debug: false,
+			housekeeping_frequency_millis: 100,
+			stats_update_frequency_millis: 100,
+			threads: 1,
+			timeout: 1_000,
+			read_slab_count: 1,
+			read_slab_size: 100,
+			out_of_slabs_message: "".to_string(),
+			client_bind_addr: None,
+			proxy_protocol: false,
+			proxy_header_timeout_millis: 1_000,
+			defer_accept_secs: None,
+			require_data_within_millis: None,
+			max_write_bytes_per_loop: 0,
+			fd_limit: None,
+			fd_warn_threshold_percent: 90,
+			callback_timeout_millis: None,
+			watchdog_action: WatchdogAction::Log,
+			recv_buffer_size: 0,
+			send_buffer_size: 0,
+			strict_sock_opts: false,
+			capture_all: None,
+			reloadable: lock_box!(ReloadableConfig::default())?,
+			reload_generation: Arc::new(AtomicU64::new(0)),
+			config_history: lock_box!(ConfigHistory::new(1)?)?,
+			name: None,
+			strict_ordering: false,
+			strict_keys: false,
+			start_timeout_millis: 5_000,
+			accept_budget_micros: 0,
+			max_slabs_per_connection: None,
+			slab_quota_close: false,
+			memory: None,
+			logger: lock_box!(None)?,
+		};
+		let mut state = array!(config.threads, &lock_box!(EventHandlerState::new()?)?)?;
+		let debug_info = DebugInfo::default();
+
+		let port = pick_free_port()?;
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 5)?;
+		ehc.handle_hash.insert(conn.handle(), conn.id());
+		ehc.trigger_on_read_list.push(conn.handle());
+		ehc.id_hash
+			.insert(conn.id(), ConnectionVariant::ServerConnection(conn));
+
+		let conn = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		ehc.handle_hash.insert(conn.handle(), conn.id());
+		ehc.trigger_on_read_list.push(conn.handle());
+
+		assert!(EventHandlerImpl::process_events(
+			&config,
+			&mut ehc,
+			&mut callbacks,
+			&mut state,
+			&mut user_context,
+			&debug_info,
+		)
+		.is_ok());
+
+		let debug_info = DebugInfo {
+			wakeup_read_err: lock_box!(true)?,
+			..Default::default()
+		};
+		let wakeup = Wakeup::new()?;
+		ehc.handle_hash.insert(0, 0);
+		ehc.id_hash.insert(0, ConnectionVariant::Wakeup(wakeup));
+
+		assert!(EventHandlerImpl::process_read_event(
+			&config,
+			&mut ehc,
+			&mut callbacks,
+			0,
+			&mut state,
+			&mut user_context,
+			&debug_info,
+		)
+		.is_ok());
+
+		ehc.handle_hash.insert(0, 100);
+
+		assert!(EventHandlerImpl::process_read_event(
+			&config,
+			&mut ehc,
+			&mut callbacks,
+			0,
+			&mut state,
+			&mut user_context,
+			&debug_info,
+		)
+		.is_ok());
+
+		assert!(EventHandlerImpl::process_read_event(
+			&config,
+			&mut ehc,
+			&mut callbacks,
+			1230,
+			&mut state,
+			&mut user_context,
+			&debug_info,
+		)
+		.is_ok());
+
+		let port = pick_free_port()?;
+		assert!(create_listener(&format!("[::1]:{}", port), 1, &DebugInfo::default()).is_ok());
+
+		ehc.handle_hash.clear();
+		ehc.id_hash.clear();
+
+		ehc.handle_hash.insert(0, 0);
+		ehc.id_hash
+			.insert(0, ConnectionVariant::Wakeup(Wakeup::new()?));
+		assert!(EventHandlerImpl::call_on_close(
+			&mut user_context,
+			0,
+			&CloseReason::LocalClose,
+			&mut callbacks,
+			&mut ehc
+		)
+		.is_ok());
+
+		ehc.handle_hash.clear();
+		ehc.id_hash.clear();
+
+		ehc.handle_hash.insert(0, 0);
+		assert!(EventHandlerImpl::call_on_close(
+			&mut user_context,
+			0,
+			&CloseReason::LocalClose,
+			&mut callbacks,
+			&mut ehc
+		)
+		.is_ok());
+
+		ehc.handle_hash.clear();
+		ehc.id_hash.clear();
+
+		assert!(EventHandlerImpl::call_on_close(
+			&mut user_context,
+			0,
+			&CloseReason::LocalClose,
+			&mut callbacks,
+			&mut ehc
+		)
+		.is_ok());
+
+		callbacks.on_close = None;
+		assert!(EventHandlerImpl::call_on_close(
+			&mut user_context,
+			0,
+			&CloseReason::LocalClose,
+			&mut callbacks,
+			&mut ehc
+		)
+		.is_ok());
+
+		ehc.handle_hash.insert(0, 0);
+		ehc.id_hash
+			.insert(0, ConnectionVariant::Wakeup(Wakeup::new()?));
+		assert!(EventHandlerImpl::process_close(
+			0,
+			&mut ehc,
+			&mut callbacks,
+			&mut user_context,
+			CloseReason::LocalClose
+		)
+		.is_ok());
+
+		let port = pick_free_port()?;
+		let _server = EvhBuilder::build_server_connection(&format!("127.0.0.1:{}", port), 1)?;
+		let mut client = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+
+		// try to accept on the client (internal error printed out, but it returns ok)
+		assert!(EventHandlerImpl::process_accept(
+			&config,
+			&client,
+			&mut vec![],
+			&DebugInfo::default(),
+			&mut callbacks,
+		)
+		.is_ok());
+
+		assert!(EventHandlerImpl::process_write_event(
+			&config,
+			&mut ehc,
+			&mut callbacks,
+			0,
+			&mut user_context,
+			&DebugInfo::default()
+		)
+		.is_ok());
+
+		ehc.handle_hash.clear();
+		ehc.id_hash.clear();
+
+		ehc.handle_hash.insert(0, 0);
+
+		assert!(EventHandlerImpl::process_write_event(
+			&config,
+			&mut ehc,
+			&mut callbacks,
+			0,
+			&mut user_context,
+			&DebugInfo::default()
+		)
+		.is_ok());
+
+		ehc.handle_hash.clear();
+		ehc.id_hash.clear();
+
+		ehc.handle_hash.insert(0, 0);
+		ehc.id_hash
+			.insert(0, ConnectionVariant::Wakeup(Wakeup::new()?));
+
+		assert!(EventHandlerImpl::process_write_event(
+			&config,
+			&mut ehc,
+			&mut callbacks,
+			0,
+			&mut user_context,
+			&DebugInfo::default()
+		)
+		.is_ok());
+
+		client.wakeup = Some(Wakeup::new()?);
+		client.state = Some(lock_box!(EventHandlerState::new()?)?);
+		client.debug_info = DebugInfo {
+			write_err2: lock_box!(true)?,
+			..Default::default()
+		};
+		assert!(EventHandlerImpl::write_loop(&mut client, &mut callbacks, 0).is_ok());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_execute_thread_other_situations() -> Result<(), Error> {
+		let config = EventHandlerConfig {
+			debug: false,
+			housekeeping_frequency_millis: 100,
+			stats_update_frequency_millis: 100,
+			threads: 1,
+			timeout: 1_000,
+			read_slab_count: 1,
+			read_slab_size: 100,
+			out_of_slabs_message: "".to_string(),
+			client_bind_addr: None,
+			proxy_protocol: false,
+			proxy_header_timeout_millis: 1_000,
+			defer_accept_secs: None,
+			require_data_within_millis: None,
+			max_write_bytes_per_loop: 0,
+			fd_limit: None,
+			fd_warn_threshold_percent: 90,
+			callback_timeout_millis: None,
+			watchdog_action: WatchdogAction::Log,
+			recv_buffer_size: 0,
+			send_buffer_size: 0,
+			strict_sock_opts: false,
+			capture_all: None,
+			reloadable: lock_box!(ReloadableConfig::default())?,
+			reload_generation: Arc::new(AtomicU64::new(0)),
+			config_history: lock_box!(ConfigHistory::new(1)?)?,
+			name: None,
+			strict_ordering: false,
+			strict_keys: false,
+			start_timeout_millis: 5_000,
+			accept_budget_micros: 0,
+			max_slabs_per_connection: None,
+			slab_quota_close: false,
+			memory: None,
+			logger: lock_box!(None)?,
+		};
+		let debug_info = DebugInfo {
+			internal_panic: lock_box!(true)?,
+			..Default::default()
+		};
+
+		let read_slabs = slab_allocator!(
+			SlabSize(config.read_slab_size),
+			SlabCount(config.read_slab_count)
+		)?;
+		let user_context = UserContextImpl {
+			read_slabs,
+			user_data: None,
+			slab_cur: usize::MAX,
+			synthetic: false,
+			read_slab_high_water: 0,
+			reassignments: vec![],
+			resumed_reads: vec![],
+		};
+		let user_context_arr = array!(1, &lock_box!(user_context)?)?;
+		let state = array!(config.threads, &lock_box!(EventHandlerState::new()?)?)?;
+		let w = Wakeup::new()?;
+		let wakeups = array!(1, &w)?;
+
+		let global_stats = GlobalStats {
+			stats: EvhStats::new(),
+			update_counter: 0,
+			tx: None,
+			handle_counts: vec![0; config.threads],
+			listen_addrs: vec![],
+			last_fd_warning: None,
+			fd_warnings: 0,
+			stats_locks: 0,
+		};
+		let stats = lock_box!(global_stats)?;
+
+		let sample = EventHandlerContext::new(wakeups, 0, stats)?;
+		let sample = lock_box!(sample)?;
+		let ctx_arr = array!(1, &sample)?;
+		let callbacks = EventHandlerCallbacks {
+			on_read: Some(Box::pin(
+				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
 					Ok(())
 				},
 			)),
-			on_close: Some(Box::pin(
+			on_accept: Some(Box::pin(
 				move |_: &mut Connection, _: &mut Box<dyn UserContext + '_>| -> Result<(), Error> {
 					Ok(())
-				},
-			)),
-			on_panic: Some(Box::pin(
-				move |_: &mut Box<dyn UserContext + '_>, _| -> Result<(), Error> { Ok(()) },
-			)),
-			on_housekeeper: Some(Box::pin(
-				move |_: &mut Box<dyn UserContext + '_>| -> Result<(), Error> { Ok(()) },
-			)),
-		};
+				},
+			)),
+			on_close: Some(Box::pin(
+				move |_: &mut Connection,
+				      _: &CloseReason,
+				      _: &mut Box<dyn UserContext + '_>|
+				      -> Result<(), Error> { Ok(()) },
+			)),
+			on_panic: Some(Box::pin(
+				move |_: &mut Box<dyn UserContext + '_>, _| -> Result<(), Error> { Ok(()) },
+			)),
+			on_housekeeper: Some(Box::pin(
+				move |_: &mut HousekeeperContext<'_>,
+				      _: &mut Box<dyn UserContext + '_>|
+				      -> Result<(), Error> { Ok(()) },
+			)),
+			on_raw_event: None,
+			on_data_chunk: None,
+			on_slab_quota: None,
+			on_read_eof: None,
+			on_panic_info: None,
+			handler: None,
+		};
+
+		spawn(move || {
+			let _ = EventHandlerImpl::execute_thread(
+				config,
+				callbacks,
+				state,
+				ctx_arr,
+				user_context_arr,
+				0,
+				true,
+				&debug_info,
+			);
+		});
+
+		sleep(Duration::from_millis(QA_SLEEP));
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_out_of_slabs() -> Result<(), Error> {
+		let test_info = test_info!()?;
+
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(25),
+			EvhReadSlabCount(1)
+		)?;
+
+		let mut counter = lock_box!(0)?;
+		let counter_clone = counter.clone();
+
+		evh.set_on_read(move |conn, _| -> Result<(), Error> {
+			info!("on read")?;
+			let mut counter = counter.wlock()?;
+			let guard = counter.guard()?;
+			**guard += 1;
+
+			if **guard == 2 {
+				conn.write_handle()?.write(b"response")?;
+			}
+
+			Ok(())
+		})?;
+
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+
+		info!("connecting to addr {}", addr)?;
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+
+		info!("adding connection now")?;
+		evh.add_server_connection(conn)?;
+
+		let mut strm = TcpStream::connect(addr.clone())?;
+		// write over 25 bytes
+		strm.write(b"012345678901234567890123456789")?;
+
+		let mut buf = [0u8; 100];
+
+		let len = strm.read(&mut buf)?;
+		assert_eq!(len, 0);
+
+		// we should have only gotten a single request (second one causes the allocation
+		// error)
+		assert_eq!(rlock!(counter_clone), 1);
+
+		// now that the connection is closed, we can continue processing because the slabs
+		// is freed up.
+
+		// open two connections
+		let mut strm1 = TcpStream::connect(addr.clone())?;
+		let mut strm2 = TcpStream::connect(addr)?;
+
+		// write less than the single slab
+		strm1.write(b"test")?;
+		strm2.write(b"test")?;
+
+		// stream 2 gets closed, but 1 is ok
+		let len = strm2.read(&mut buf)?;
+		assert_eq!(len, 0);
+		assert_eq!(strm1.read(&mut buf)?, 8);
+		assert_eq!(&buf[0..8], b"response");
+		assert_eq!(rlock!(counter_clone), 2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_read_slabs_per_thread() -> Result<(), Error> {
+		let test_info = test_info!()?;
+
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(10),
+			EvhThreads(2),
+			EvhReadSlabSize(25),
+			EvhReadSlabCountPerThread(3),
+			EvhStatsUpdateMillis(100)
+		)?;
+
+		evh.set_on_read(move |conn, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(conn)?;
+				cbreak!(next_chunk.is_none());
+				let next_chunk = next_chunk.unwrap();
+				data.extend(next_chunk.data());
+			}
+
+			if data.starts_with(b"hold") {
+				// keep the data so that this thread's slabs stay allocated
+				if data.len() == 50 {
+					conn.write_handle()?.write(b"held")?;
+				}
+			} else if data == b"tid" {
+				// accepted connections are assigned to threads by handle
+				let handle: usize = try_into!(conn.handle())?;
+				conn.write_handle()?.write(&[(handle % 2) as u8])?;
+				ctx.clear_all(conn)?;
+			} else {
+				conn.write_handle()?.write(&data)?;
+				ctx.clear_all(conn)?;
+			}
+
+			Ok(())
+		})?;
+
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		// find a connection on each thread. The client and server handles are allocated from
+		// the same process so a udp socket is opened in between to change the server handle.
+		let mut strms: Vec<Option<TcpStream>> = vec![None, None];
+		let mut buf = [0u8; 100];
+		let mut open = vec![];
+		let mut fillers = vec![];
+		for _ in 0..20 {
+			cbreak!(strms[0].is_some() && strms[1].is_some());
+			let mut strm = TcpStream::connect(addr.clone())?;
+			strm.write(b"tid")?;
+			assert_eq!(strm.read(&mut buf)?, 1);
+			let tid = buf[0] as usize;
+			if strms[tid].is_none() {
+				strms[tid] = Some(strm);
+			} else {
+				open.push(strm);
+			}
+			fillers.push(UdpSocket::bind("127.0.0.1:0")?);
+		}
+		let mut strm0 = strms[0].take().unwrap();
+		let mut strm1 = strms[1].take().unwrap();
+
+		// use all three of thread 0's slabs (21 bytes of data per slab)
+		strm0.write(b"hold")?;
+		strm0.write(&[b'x'; 46])?;
+		assert_eq!(strm0.read(&mut buf)?, 4);
+		assert_eq!(&buf[0..4], b"held");
+
+		// thread 0 can't accept any more data so new connections on it are closed
+		// (the streams are kept open so that the server handles are not reused)
+		let mut refused = 0;
+		for _ in 0..20 {
+			let mut strm = TcpStream::connect(addr.clone())?;
+			strm.write(b"tid")?;
+			let len = strm.read(&mut buf)?;
+			if len == 0 {
+				refused += 1;
+				break;
+			}
+			assert_eq!(len, 1);
+			assert_eq!(buf[0], 1);
+			open.push(strm);
+			fillers.push(UdpSocket::bind("127.0.0.1:0")?);
+		}
+		assert_eq!(refused, 1);
+
+		// thread 1 is not affected
+		for _ in 0..10 {
+			strm1.write(b"0123456789012345678901234567890123456789")?;
+			let mut len = 0;
+			while len < 40 {
+				len += strm1.read(&mut buf[len..])?;
+			}
+			assert_eq!(&buf[0..40], b"0123456789012345678901234567890123456789");
+		}
+
+		let mut found = false;
+		for _ in 0..50 {
+			let stats = evh.wait_for_stats()?;
+			info!("stats={:?}", stats)?;
+			if stats.read_slabs.len() == 2
+				&& stats.read_slabs[0].used == 3
+				&& stats.read_slabs[1].used == 0
+			{
+				assert_eq!(stats.read_slabs[0].free, 0);
+				assert_eq!(stats.read_slabs[0].high_water, 3);
+				assert_eq!(stats.read_slabs[1].free, 3);
+				assert!(stats.read_slabs[1].high_water >= 1);
+				found = true;
+				break;
+			}
+		}
+		assert!(found);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_no_clear() -> Result<(), Error> {
+		let test_info = test_info!()?;
+
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(25),
+			EvhReadSlabCount(10)
+		)?;
+
+		evh.set_on_read(move |conn, _| -> Result<(), Error> {
+			info!("on read")?;
+			conn.write_handle()?.write(b"response")?;
+
+			Ok(())
+		})?;
+
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+
+		info!("connecting to addr {}", addr)?;
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+
+		info!("adding connection now")?;
+		evh.add_server_connection(conn)?;
+
+		let mut strm = TcpStream::connect(addr.clone())?;
+		// write over 25 bytes
+		strm.write(b"0123456789012345678")?;
+
+		let mut buf = [0u8; 100];
+		let len = strm.read(&mut buf)?;
+
+		assert_eq!(len, 8);
+		assert_eq!(&buf[0..8], b"response");
+
+		strm.write(b"hi")?;
+		let mut buf = [0u8; 100];
+		let len = strm.read(&mut buf)?;
+
+		assert_eq!(len, 8);
+		assert_eq!(&buf[0..8], b"response");
+
+		sleep(Duration::from_millis(QA_SLEEP));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_controller_stop() -> Result<(), Error> {
+		let mut client;
+		let mut controller;
+		let mut buf = [0u8; 100];
+		{
+			let test_info = test_info!()?;
+			let mut evh = evh_oro!(
+				Debug(false),
+				EvhTimeout(100),
+				EvhStatsUpdateMillis(try_into!(QA_SLEEP)?),
+				EvhThreads(1),
+				EvhReadSlabSize(100)
+			)?;
+
+			evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+				info!("onRead")?;
+				let mut data: Vec<u8> = vec![];
+
+				loop {
+					let next_chunk = ctx.next_chunk(connection)?;
+					cbreak!(next_chunk.is_none());
+					let next_chunk = next_chunk.unwrap();
+					data.extend(next_chunk.data());
+				}
+
+				let dstring = from_utf8(&data)?;
+				info!("data[{}]='{}'", connection.id(), dstring,)?;
+
+				assert_eq!(dstring, "hi");
+				let mut wh = connection.write_handle()?;
+				wh.write(b"test")?;
+				ctx.clear_all(connection)?;
+				Ok(())
+			})?;
+
+			evh.start()?;
+			controller = evh.controller()?;
+			let port = test_info.port();
+			let addr = format!("127.0.0.1:{}", port);
+			let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+			evh.add_server_connection(conn)?;
+
+			client = TcpStream::connect(addr)?;
+			client.write(b"hi")?;
+
+			let len = client.read(&mut buf)?;
+			assert_eq!(&buf[0..len], b"test");
+		}
+
+		client.write(b"hi")?;
+		let len = client.read(&mut buf)?;
+		assert_eq!(&buf[0..len], b"test");
+
+		let stats = controller.wait_for_stats()?;
+		info!("stats={:?}", stats)?;
+		assert_eq!(stats.accepts, 1);
+		assert_eq!(stats.reads, 2);
+		assert_eq!(stats.bytes_read, 4);
+
+		controller.stop()?;
+
+		// now it's closed
+		let len = client.read(&mut buf)?;
+		assert_eq!(len, 0);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_resources() -> Result<(), Error> {
+		// this test doesn't currently do assertions, but it can be used to monitor resources
+		// like file descriptors. Change `target` to a higher value and increase sleep at the
+		// end to be able to look at usage
+		let test_info = test_info!()?;
+		let mut loop_count = 0;
+		let target = 1;
+		loop {
+			info!("create evh {}", loop_count)?;
+			let mut evh = evh_oro!(
+				Debug(false),
+				EvhTimeout(u16::MAX),
+				EvhThreads(5),
+				EvhReadSlabSize(100)
+			)?;
+
+			let (tx, rx) = test_info.sync_channel();
+			evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+				let mut data: Vec<u8> = vec![];
+
+				loop {
+					let next_chunk = ctx.next_chunk(connection)?;
+					cbreak!(next_chunk.is_none());
+					let next_chunk = next_chunk.unwrap();
+					data.extend(next_chunk.data());
+				}
+
+				let _dstring = from_utf8(&data)?;
+				tx.send(())?;
+
+				ctx.clear_all(connection)?;
+				Ok(())
+			})?;
+			evh.start()?;
+			let port = test_info.port();
+			let addr = format!("127.0.0.1:{}", port);
+			let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+			evh.add_server_connection(conn)?;
+			let conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+			let mut wh = evh.add_client_connection(conn2)?;
+			wh.write(b"01234567890123456789")?;
+			rx.recv()?;
+
+			loop_count += 1;
+
+			if loop_count == target {
+				break;
+			}
+			//sleep(Duration::from_millis(100));
+		}
+
+		info!("sleep")?;
+		//sleep(Duration::from_millis(120_000));
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_origin_id() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			Debug(true),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(100)
+		)?;
+
+		let mut recv_msg = lock_box!(false)?;
+		let mut origin_assertion_ok = lock_box!(false)?;
+		let origin_assertion_ok_clone = origin_assertion_ok.clone();
+		let recv_msg_clone = recv_msg.clone();
+		let (tx, rx) = test_info.sync_channel();
+		let (tx2, rx2) = test_info.sync_channel();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			info!("onRead")?;
+			let mut data: Vec<u8> = vec![];
+
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				let next_chunk = next_chunk.unwrap();
+				data.extend(next_chunk.data());
+			}
+
+			let dstring = from_utf8(&data)?;
+			info!(
+				"data[{}]='{}', origin_id={}",
+				connection.id(),
+				dstring,
+				connection.origin_id()
+			)?;
+
+			assert_eq!(dstring, "hi");
+
+			if !rlock!(recv_msg) {
+				// origin id should be different from id (accepted connection)
+				assert_ne!(connection.id(), connection.origin_id());
+				// send back once
+				let mut wh = connection.write_handle()?;
+				wh.write(b"hi")?;
+			} else {
+				// this is the client, origin id should be equal to id
+				assert_eq!(connection.id(), connection.origin_id());
+				wlock!(origin_assertion_ok) = true;
+				tx2.send(())?;
+			}
+
+			wlock!(recv_msg) = true;
+			tx.send(())?;
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		let mut wh = evh.add_client_connection(conn2)?;
+		wh.write(b"hi")?;
+
+		rx.recv()?;
+		assert!(rlock!(recv_msg_clone));
+		rx2.recv()?;
+		assert!(rlock!(origin_assertion_ok_clone));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_pending_write_on_close() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(1),
+			EvhReadSlabSize(250),
+			EvhReadSlabCount(100_000),
+			EvhStatsUpdateMillis(5000),
+			EvhOutOfSlabsMessage("12345".to_string())
+		)?;
+
+		let debug_info = DebugInfo {
+			pending: lock_box!(true)?,
+			..Default::default()
+		};
+		evh.set_debug_info(debug_info)?;
+
+		let mut client_id = lock_box!(0)?;
+
+		let mut client_recv = lock_box!(false)?;
+		let mut server_recv = lock_box!(false)?;
+		let client_recv_clone = client_recv.clone();
+		let server_recv_clone = server_recv.clone();
+		let (tx, rx) = test_info.sync_channel();
+		let client_id_clone = client_id.clone();
+
+		let message = "0123456789";
+		let ret_message = [b'p'; 100_000];
+		let ret_message = from_utf8(&ret_message).unwrap().to_string();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			info!("onRead")?;
+			let mut data: Vec<u8> = vec![];
+
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				let next_chunk = next_chunk.unwrap();
+				data.extend(next_chunk.data());
+			}
+
+			let dstring = from_utf8(&data)?;
+
+			if dstring != message && rlock!(client_id_clone) != connection.id() {
+				return Ok(());
+			} else if dstring != ret_message && rlock!(client_id_clone) == connection.id() {
+				return Ok(());
+			} else {
+				info!("equal")?;
+			}
+
+			let mut wh = connection.write_handle()?;
+
+			// respond
+			if rlock!(client_id_clone) != connection.id() {
+				info!("wh write and close")?;
+				wh.write_and_close(ret_message.as_bytes())?;
+				wlock!(server_recv) = true;
+			} else {
+				wlock!(client_recv) = true;
+			}
+
+			if rlock!(client_recv) && rlock!(server_recv) {
+				tx.send(())?;
+			}
+
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			info!(
+				"onAccept: handle={},id={}",
+				connection.handle(),
+				connection.id()
+			)?;
+			Ok(())
+		})?;
+
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> {
+			info!("onClose")?;
+			Ok(())
+		})?;
+
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> {
+			info!("onHousekeeper")?;
+			Ok(())
+		})?;
+
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> {
+			info!("onPanic")?;
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		info!("connecting to addr {}", addr)?;
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+
+		info!("adding connection now")?;
+		evh.add_server_connection(conn)?;
+		let mut conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		conn2.debug_info = DebugInfo {
+			pending: lock_box!(true)?,
+			..Default::default()
+		};
+		wlock!(client_id) = conn2.id();
+
+		let mut wh = evh.add_client_connection(conn2)?;
+		wh.write(message.as_bytes())?;
+
+		rx.recv()?;
+		assert!(rlock!(client_recv_clone));
+		assert!(rlock!(server_recv_clone));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_out_of_slabs_message() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(1),
+			EvhReadSlabSize(25),
+			EvhReadSlabCount(1),
+			EvhStatsUpdateMillis(5000),
+			EvhOutOfSlabsMessage("outofslabs".to_string())
+		)?;
+
+		let debug_info = DebugInfo {
+			pending: lock_box!(true)?,
+			..Default::default()
+		};
+		evh.set_debug_info(debug_info)?;
+
+		let client_id = lock_box!(0)?;
+
+		let mut client_recv = lock_box!(false)?;
+		let mut server_recv = lock_box!(false)?;
+		let client_id_clone = client_id.clone();
+
+		let message = "012345678901234567890123456789";
+		let ret_message = "0123456789";
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			info!("onRead")?;
+			let mut data: Vec<u8> = vec![];
+
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				let next_chunk = next_chunk.unwrap();
+				data.extend(next_chunk.data());
+			}
+
+			let dstring = from_utf8(&data)?;
+
+			if dstring != message && rlock!(client_id_clone) != connection.id() {
+				info!("not equal server = {}", dstring)?;
+				return Ok(());
+			} else if dstring != "outofslabs" && rlock!(client_id_clone) == connection.id() {
+				info!("not equal client = {}", dstring)?;
+				return Ok(());
+			} else {
+				info!(
+					"equal {}",
+					if rlock!(client_id_clone) == connection.id() {
+						"client"
+					} else {
+						"server"
+					}
+				)?;
+			}
+
+			let mut wh = connection.write_handle()?;
+
+			// respond
+			if rlock!(client_id_clone) != connection.id() {
+				info!("wh write and close")?;
+				wh.write_and_close(ret_message.as_bytes())?;
+				wlock!(server_recv) = true;
+			} else {
+				wlock!(client_recv) = true;
+			}
+
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			info!(
+				"onAccept: handle={},id={}",
+				connection.handle(),
+				connection.id()
+			)?;
+			Ok(())
+		})?;
+
+		evh.set_on_close(move |connection, _reason, _ctx| -> Result<(), Error> {
+			info!("onClose: {}", connection.id())?;
+			Ok(())
+		})?;
+
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> {
+			info!("onHousekeeper")?;
+			Ok(())
+		})?;
+
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> {
+			info!("onPanic")?;
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		info!("connecting to addr {}", addr)?;
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+
+		info!("adding connection now")?;
+		evh.add_server_connection(conn)?;
+		let mut strm = TcpStream::connect(addr)?;
+
+		strm.write(message.as_bytes())?;
+		let mut buf = [0u8; 100];
+		info!("about to read")?;
+		let len = strm.read(&mut buf)?;
+		info!("read complete")?;
+
+		assert_eq!(&buf[0..len], b"outofslabs");
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_disabled_out_of_slabs_message() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(1),
+			EvhReadSlabSize(25),
+			EvhReadSlabCount(1),
+			EvhStatsUpdateMillis(5000),
+			EvhOutOfSlabsMessage("outofslabs".to_string())
+		)?;
+
+		let debug_info = DebugInfo {
+			pending: lock_box!(true)?,
+			..Default::default()
+		};
+		evh.set_debug_info(debug_info)?;
+
+		let client_id = lock_box!(0)?;
+
+		let mut client_recv = lock_box!(false)?;
+		let mut server_recv = lock_box!(false)?;
+		let client_id_clone = client_id.clone();
+
+		let message = "012345678901234567890123456789";
+		let ret_message = "0123456789";
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			info!("onRead")?;
+			let mut data: Vec<u8> = vec![];
+
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				let next_chunk = next_chunk.unwrap();
+				data.extend(next_chunk.data());
+			}
+
+			let dstring = from_utf8(&data)?;
+
+			if dstring != message && rlock!(client_id_clone) != connection.id() {
+				info!("not equal server = {}", dstring)?;
+				return Ok(());
+			} else if dstring != "outofslabs" && rlock!(client_id_clone) == connection.id() {
+				info!("not equal client = {}", dstring)?;
+				return Ok(());
+			} else {
+				info!(
+					"equal {}",
+					if rlock!(client_id_clone) == connection.id() {
+						"client"
+					} else {
+						"server"
+					}
+				)?;
+			}
+
+			let mut wh = connection.write_handle()?;
+
+			// respond
+			if rlock!(client_id_clone) != connection.id() {
+				info!("wh write and close")?;
+				wh.write_and_close(ret_message.as_bytes())?;
+				wlock!(server_recv) = true;
+			} else {
+				wlock!(client_recv) = true;
+			}
+
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			connection.disable_write_final();
+			info!(
+				"onAccept: handle={},id={}",
+				connection.handle(),
+				connection.id()
+			)?;
+			Ok(())
+		})?;
+
+		evh.set_on_close(move |connection, _reason, _ctx| -> Result<(), Error> {
+			info!("onClose: {}", connection.id())?;
+			Ok(())
+		})?;
+
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> {
+			info!("onHousekeeper")?;
+			Ok(())
+		})?;
+
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> {
+			info!("onPanic")?;
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		info!("connecting to addr {}", addr)?;
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+
+		info!("adding connection now")?;
+		evh.add_server_connection(conn)?;
+		let mut strm = TcpStream::connect(addr)?;
+
+		strm.write(message.as_bytes())?;
+		let mut buf = [0u8; 100];
+		info!("about to read")?;
+		assert_eq!(strm.read(&mut buf)?, 0);
+
+		Ok(())
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn test_evh_raw_event() -> Result<(), Error> {
+		use crate::Interest;
+		use std::os::unix::io::AsRawFd;
+		use std::os::unix::net::UnixStream;
+
+		let _test_info = test_info!()?;
+		let mut evh = evh_oro!(EvhTimeout(100), EvhThreads(2), EvhReadSlabSize(100))?;
+
+		let (raw_end, mut peer) = UnixStream::pair()?;
+		raw_end.set_nonblocking(true)?;
+
+		// the events are checked on the test thread. A failed assertion in the callback would
+		// only panic the evh thread and leave the test waiting.
+		let (tx, rx) = std::sync::mpsc::channel();
+
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_raw_event(Box::new(move |token, readable, writable| {
+			info!("raw event token={},r={},w={}", token, readable, writable)?;
+			tx.send((token, readable, writable))?;
+			Ok(())
+		}))?;
+		evh.start()?;
+
+		evh.register_raw(raw_end.as_raw_fd(), Interest::Read, 1234, false)?;
+		assert!(evh
+			.register_raw(raw_end.as_raw_fd(), Interest::Read, 1234, false)
+			.is_err());
+
+		let jh = thread::spawn(move || -> Result<UnixStream, Error> {
+			peer.write(b"raw")?;
+			Ok(peer)
+		});
+
+		loop {
+			let (token, readable, writable) = rx.recv_timeout(Duration::from_secs(30)).unwrap();
+			assert_eq!(token, 1234);
+			// the evh must not write to this handle, only report readiness
+			assert!(!writable);
+			cbreak!(readable);
+		}
+		let _peer = jh.join().unwrap()?;
+
+		// the evh did not read the data so it's still there for us
+		let mut buf = [0u8; 10];
+		assert_eq!((&raw_end).read(&mut buf)?, 3);
+		assert_eq!(&buf[0..3], b"raw");
+
+		evh.deregister_raw(1234)?;
+		assert!(evh.deregister_raw(1234).is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_client_connection_bound() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let listener = TcpListener::bind(format!("127.0.0.1:{}", test_info.port()))?;
+
+		// an explicit source port shows that the bind took effect
+		let local_port = pick_free_port()?;
+		let local_addr = SocketAddr::from(([127, 0, 0, 1], local_port));
+		let conn =
+			EvhBuilder::build_client_connection_bound("127.0.0.1", test_info.port(), local_addr)?;
+		let (_strm, peer) = listener.accept()?;
+		assert_eq!(peer.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+		assert_eq!(peer.port(), local_port);
+		close_impl(conn.handle())?;
+
+		// port 0 lets the os pick the source port
+		let local_addr = SocketAddr::from(([127, 0, 0, 1], 0));
+		let conn =
+			EvhBuilder::build_client_connection_bound("127.0.0.1", test_info.port(), local_addr)?;
+		let (_strm, peer) = listener.accept()?;
+		assert_eq!(peer.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+		close_impl(conn.handle())?;
+
+		// the evh level default is used by EventHandler::build_client_connection
+		let local_port = pick_free_port()?;
+		let bind_addr = format!("127.0.0.1:{}", local_port);
+		let mut evh = evh_oro!(EvhClientBindAddr(bind_addr), EvhThreads(1))?;
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		let conn = evh.build_client_connection("127.0.0.1", test_info.port())?;
+		let (_strm, peer) = listener.accept()?;
+		assert_eq!(peer.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+		assert_eq!(peer.port(), local_port);
+		close_impl(conn.handle())?;
+
+		Ok(())
+	}
+
+	// unroutable addresses are not reliably blackholed (some networks proxy them), so a local
+	// listener whose accept queue is full is used instead. Connects to it are never answered.
+	#[cfg(target_os = "linux")]
+	fn blackhole_addr() -> Result<SocketAddr, Error> {
+		use std::os::unix::io::AsRawFd;
+		let listener = TcpListener::bind("127.0.0.1:0")?;
+		let addr = listener.local_addr()?;
+		unsafe {
+			bmw_deps::libc::listen(listener.as_raw_fd(), 0);
+		}
+		let mut filled = vec![];
+		while let Ok(strm) = TcpStream::connect_timeout(&addr, Duration::from_millis(100)) {
+			filled.push(strm);
+		}
+		// keep the listener and its queued connections open for the rest of the test
+		std::mem::forget(listener);
+		std::mem::forget(filled);
+		Ok(addr)
+	}
+
+	#[test]
+	#[cfg(target_os = "linux")]
+	fn test_evh_client_connection_timeout() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let port = test_info.port();
+		let mut evh = evh_oro!(EvhThreads(1), EvhTimeout(100))?;
+		let mut client_id = lock_box!(u128::MAX)?;
+		let client_id_clone = client_id.clone();
+		let mut received = lock_box!(vec![])?;
+		let received_clone = received.clone();
+		let (tx, rx) = test_info.sync_channel();
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			if rlock!(client_id_clone) == connection.id() {
+				wlock!(received).extend(data);
+				tx.send(())?;
+			} else {
+				// echo
+				connection.write_handle()?.write(&data)?;
+			}
+			Ok(())
+		})?;
+		evh.start()?;
+		let conn = EvhBuilder::build_server_connection(&format!("127.0.0.1:{}", port), 10)?;
+		evh.add_server_connection(conn)?;
+
+		// a local connect completes well under the timeout
+		let start = Instant::now();
+		let conn = EvhBuilder::build_client_connection_timeout("127.0.0.1", port, 5_000)?;
+		assert!(start.elapsed() < Duration::from_millis(1_000));
+		wlock!(client_id) = conn.id();
+		let mut wh = evh.add_client_connection(conn)?;
+		wh.write(b"echo")?;
+		rx.recv()?;
+		assert_eq!(rlock!(received_clone), b"echo".to_vec());
+
+		// a connect that is never answered returns once the timeout expires
+		let dead = blackhole_addr()?;
+		let start = Instant::now();
+		let res =
+			EvhBuilder::build_client_connection_timeout(&dead.ip().to_string(), dead.port(), 300);
+		let elapsed = start.elapsed();
+		info!("connect timed out in {:?}", elapsed)?;
+		let e = res.err().unwrap();
+		assert!(matches!(e.kind(), ErrorKind::IO(_)));
+		assert_eq!(e.connect_kind(), Some(ConnectErrorKind::TimedOut));
+		assert!(elapsed >= Duration::from_millis(250));
+		assert!(elapsed < Duration::from_millis(2_000));
+
+		// nothing listening
+		let closed = pick_free_port()?;
+		let res = EvhBuilder::build_client_connection_timeout("127.0.0.1", closed, 1_000);
+		let e = res.err().unwrap();
+		assert_eq!(e.connect_kind(), Some(ConnectErrorKind::Refused));
+
+		let res = EvhBuilder::build_client_connection_timeout("127.0.0.1", port, 0);
+		let e = res.err().unwrap();
+		assert!(matches!(e.kind(), ErrorKind::IllegalArgument(_)));
+
+		Ok(())
+	}
+
+	#[test]
+	#[cfg(target_os = "linux")]
+	fn test_evh_client_connection_happy_eyeballs() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let listener = TcpListener::bind(format!("127.0.0.1:{}", test_info.port()))?;
+
+		let dead = blackhole_addr()?;
+		let live = SocketAddr::from(([127, 0, 0, 1], test_info.port()));
+		let start = Instant::now();
+		let handle = create_connection_happy_eyeballs(&[dead, live], Duration::from_millis(200))?;
+		let elapsed = start.elapsed();
+		info!("happy eyeballs connected in {:?}", elapsed)?;
+		// the dead address is tried first, then the live one after the attempt delay
+		assert!(elapsed >= Duration::from_millis(200));
+		assert!(elapsed < Duration::from_millis(5_000));
+		let (_strm, peer) = listener.accept()?;
+		assert_eq!(peer.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+		assert_eq!(
+			peer_addr_impl(handle)?.ip(),
+			IpAddr::V4(Ipv4Addr::LOCALHOST)
+		);
+		close_impl(handle)?;
+
+		// all attempts fail
+		let closed = SocketAddr::from(([127, 0, 0, 1], pick_free_port()?));
+		let res = create_connection_happy_eyeballs(&[closed], Duration::from_millis(200));
+		assert!(matches!(res.unwrap_err().kind(), ErrorKind::IO(_)));
+		assert!(create_connection_happy_eyeballs(&[], Duration::from_millis(200)).is_err());
+
+		// localhost may also resolve to ::1 where nothing is listening. The connection is
+		// used just like one from build_client_connection.
+		let mut evh = evh_oro!(EvhThreads(1))?;
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+		let conn =
+			EvhBuilder::build_client_connection_happy_eyeballs("localhost", test_info.port(), 250)?;
+		let (mut strm, _) = listener.accept()?;
+		let mut wh = evh.add_client_connection(conn)?;
+		wh.write(b"hello")?;
+		let mut buf = [0u8; 5];
+		strm.read_exact(&mut buf)?;
+		assert_eq!(&buf, b"hello");
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_client_connect_error_kind() -> Result<(), Error> {
+		// nothing is listening on this port so the connection is refused
+		let port = pick_free_port()?;
+		let e = EvhBuilder::build_client_connection("127.0.0.1", port)
+			.err()
+			.unwrap();
+		assert!(matches!(e.kind(), ErrorKind::IO(_)));
+		assert_eq!(e.connect_kind(), Some(ConnectErrorKind::Refused));
+
+		let local_addr = SocketAddr::from(([127, 0, 0, 1], 0));
+		let e = EvhBuilder::build_client_connection_bound("127.0.0.1", port, local_addr);
+		assert_eq!(
+			e.err().unwrap().connect_kind(),
+			Some(ConnectErrorKind::Refused)
+		);
+
+		let closed = SocketAddr::from(([127, 0, 0, 1], port));
+		let e = create_connection_happy_eyeballs(&[closed], Duration::from_millis(200));
+		assert_eq!(
+			e.unwrap_err().connect_kind(),
+			Some(ConnectErrorKind::Refused)
+		);
+
+		// errors that are not from a connection attempt are not classified
+		let e = create_connection_happy_eyeballs(&[], Duration::from_millis(200)).unwrap_err();
+		assert_eq!(e.connect_kind(), None);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_client_connection_bound_errors() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let _listener = TcpListener::bind(format!("127.0.0.1:{}", test_info.port()))?;
+
+		// 192.0.2.1 is reserved for documentation and not assigned to any local interface
+		let local_addr = SocketAddr::from(([192, 0, 2, 1], 0));
+		let res =
+			EvhBuilder::build_client_connection_bound("127.0.0.1", test_info.port(), local_addr);
+		let e = res.err().unwrap();
+		assert!(matches!(e.kind(), ErrorKind::IO(_)));
+		let text = e.to_string();
+		assert!(text.contains("could not bind to local address 192.0.2.1:0"));
+
+		let mut evh = evh_oro!(EvhClientBindAddr("192.0.2.1".to_string()))?;
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		let res = evh.build_client_connection("127.0.0.1", test_info.port());
+		assert!(res.err().unwrap().to_string().contains("192.0.2.1:0"));
+
+		// no remote address in the same family as the local address
+		let local_addr = SocketAddr::from(([0u16, 0, 0, 0, 0, 0, 0, 1], 0));
+		let res =
+			EvhBuilder::build_client_connection_bound("127.0.0.1", test_info.port(), local_addr);
+		assert!(res.is_err());
+
+		match evh_oro!(EvhClientBindAddr("not an address".to_string())) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+				return Err(err!(ErrKind::Test, "expected a configuration error"));
+			}
+			Err(e) => assert!(matches!(e.kind(), ErrorKind::Configuration(_))),
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_parse_bind_addr() -> Result<(), Error> {
+		let addr = parse_bind_addr("127.0.0.1")?;
+		assert_eq!(addr, SocketAddr::from(([127, 0, 0, 1], 0)));
+		let addr = parse_bind_addr("127.0.0.1:1234")?;
+		assert_eq!(addr, SocketAddr::from(([127, 0, 0, 1], 1234)));
+		let addr = parse_bind_addr("[::1]:1234")?;
+		assert_eq!(addr, SocketAddr::from(([0u16, 0, 0, 0, 0, 0, 0, 1], 1234)));
+
+		let link_local = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+		let addr = parse_bind_addr("[fe80::1%3]:1234")?;
+		assert_eq!(
+			addr,
+			SocketAddr::V6(SocketAddrV6::new(link_local, 1234, 0, 3))
+		);
+		let addr = parse_bind_addr("fe80::1%3")?;
+		assert_eq!(addr, SocketAddr::V6(SocketAddrV6::new(link_local, 0, 0, 3)));
+
+		#[cfg(target_os = "linux")]
+		{
+			let lo = interface_index("lo")?;
+			let addr = parse_bind_addr("[fe80::1%lo]:80")?;
+			assert_eq!(
+				addr,
+				SocketAddr::V6(SocketAddrV6::new(link_local, 80, 0, lo))
+			);
+		}
+
+		assert!(parse_bind_addr("fe80::1%no_such_interface").is_err());
+		assert!(parse_bind_addr("[fe80::1%3]:notaport").is_err());
+		assert!(parse_bind_addr("127.0.0.1%3").is_err());
+		assert!(parse_bind_addr("localhost").is_err());
+
+		Ok(())
+	}
+
+	// build a PROXY protocol v2 header for the specified command, family and address block
+	fn proxy_v2_header(cmd: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+		let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+		header.push(0x20 | cmd);
+		header.push((family << 4) | 0x1);
+		header.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+		header.extend_from_slice(addrs);
+		header
+	}
+
+	#[test]
+	fn test_evh_parse_proxy_header() -> Result<(), Error> {
+		let src_v4 = SocketAddr::from(([192, 168, 1, 10], 56324));
+		let src_v6 = SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1], 443));
+
+		let v1 = b"PROXY TCP4 192.168.1.10 10.0.0.1 56324 443\r\nhello";
+		assert_eq!(parse_proxy_header(v1)?, Some((v1.len() - 5, Some(src_v4))));
+		let v1 = b"PROXY TCP6 2001:db8::1 2001:db8::2 443 8080\r\n";
+		assert_eq!(parse_proxy_header(v1)?, Some((v1.len(), Some(src_v6))));
+		let v1 = b"PROXY UNKNOWN\r\n";
+		assert_eq!(parse_proxy_header(v1)?, Some((v1.len(), None)));
+
+		// incomplete headers
+		assert_eq!(parse_proxy_header(b"")?, None);
+		assert_eq!(parse_proxy_header(b"PRO")?, None);
+		assert_eq!(parse_proxy_header(b"PROXY TCP4 192.168.1.10")?, None);
+		assert_eq!(parse_proxy_header(b"\r\n\r\n\0\r")?, None);
+
+		// malformed v1 headers
+		assert!(parse_proxy_header(b"GET / HTTP/1.1\r\n").is_err());
+		assert!(parse_proxy_header(b"PROXY TCP4 1.2.3.4 5.6.7.8 1\r\n").is_err());
+		assert!(parse_proxy_header(b"PROXY TCP4 ::1 ::1 1 2\r\n").is_err());
+		assert!(parse_proxy_header(b"PROXY TCP4 1.2.3.4 5.6.7.8 70000 2\r\n").is_err());
+		assert!(parse_proxy_header(b"PROXY UDP4 1.2.3.4 5.6.7.8 1 2\r\n").is_err());
+		let mut long = b"PROXY ".to_vec();
+		long.extend_from_slice(&[b'1'; 110]);
+		assert!(parse_proxy_header(&long).is_err());
+
+		// v2 headers
+		let addrs = [192, 168, 1, 10, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb];
+		let mut v2 = proxy_v2_header(0x1, 0x1, &addrs);
+		let len = v2.len();
+		v2.extend_from_slice(b"hello");
+		assert_eq!(parse_proxy_header(&v2)?, Some((len, Some(src_v4))));
+		assert_eq!(parse_proxy_header(&v2[0..len - 1])?, None);
+
+		let mut addrs = vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+		addrs.extend_from_slice(&[0u8; 16]);
+		addrs.extend_from_slice(&[0x01, 0xbb, 0x1f, 0x90]);
+		let v2 = proxy_v2_header(0x1, 0x2, &addrs);
+		assert_eq!(parse_proxy_header(&v2)?, Some((v2.len(), Some(src_v6))));
+
+		// LOCAL command and AF_UNSPEC do not advertise an address
+		let v2 = proxy_v2_header(0x0, 0x0, &[]);
+		assert_eq!(parse_proxy_header(&v2)?, Some((v2.len(), None)));
+		let v2 = proxy_v2_header(0x1, 0x0, &[]);
+		assert_eq!(parse_proxy_header(&v2)?, Some((v2.len(), None)));
+
+		// malformed v2 headers
+		let mut v2 = proxy_v2_header(0x1, 0x1, &[0u8; 12]);
+		v2[12] = 0x11;
+		assert!(parse_proxy_header(&v2).is_err());
+		assert!(parse_proxy_header(&proxy_v2_header(0x2, 0x1, &[0u8; 12])).is_err());
+		assert!(parse_proxy_header(&proxy_v2_header(0x1, 0x1, &[0u8; 4])).is_err());
+		assert!(parse_proxy_header(&proxy_v2_header(0x1, 0x2, &[0u8; 12])).is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_proxy_protocol() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhProxyProtocol(true),
+			EvhProxyHeaderTimeoutMillis(500)
+		)?;
+
+		let mut accepts = lock_box!(0)?;
+		let accepts_clone = accepts.clone();
+		let mut closes = lock_box!(0)?;
+		let closes_clone = closes.clone();
+
+		// once the payload has been received, reply with the peer address and the payload
+		evh.set_on_read(move |conn, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(conn)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			if data.len() >= 5 {
+				ctx.clear_all(conn)?;
+				let reply = format!("{}|{}", conn.peer_addr()?, from_utf8(&data)?);
+				let mut wh = conn.write_handle()?;
+				wh.write(reply.as_bytes())?;
+				wh.close()?;
+			}
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_conn, _ctx| -> Result<(), Error> {
+			wlock!(accepts) += 1;
+			Ok(())
+		})?;
+		evh.set_on_close(move |_conn, _reason, _ctx| -> Result<(), Error> {
+			wlock!(closes) += 1;
+			Ok(())
+		})?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		// write each part (with a delay in between) and return everything the server sent
+		// back before closing the connection
+		let send = |parts: &[&[u8]]| -> Result<(String, SocketAddr), Error> {
+			let mut strm = TcpStream::connect(&addr)?;
+			for part in parts {
+				strm.write(part)?;
+				sleep(Duration::from_millis(50));
+			}
+			let mut reply = String::new();
+			strm.read_to_string(&mut reply)?;
+			Ok((reply, strm.local_addr()?))
+		};
+
+		// v1 in a single write and split across writes
+		let v1 = b"PROXY TCP4 192.168.1.10 10.0.0.1 56324 443\r\nhello";
+		assert_eq!(send(&[v1])?.0, "192.168.1.10:56324|hello");
+		assert_eq!(
+			send(&[&v1[0..10], &v1[10..44], &v1[44..46], &v1[46..]])?.0,
+			"192.168.1.10:56324|hello"
+		);
+
+		// v2 in a single write and split across writes
+		let mut addrs = vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+		addrs.extend_from_slice(&[0u8; 16]);
+		addrs.extend_from_slice(&[0x01, 0xbb, 0x1f, 0x90]);
+		let mut v2 = proxy_v2_header(0x1, 0x2, &addrs);
+		v2.extend_from_slice(b"hello");
+		assert_eq!(send(&[&v2])?.0, "[2001:db8::1]:443|hello");
+		assert_eq!(
+			send(&[&v2[0..8], &v2[8..20], &v2[20..52], &v2[52..]])?.0,
+			"[2001:db8::1]:443|hello"
+		);
+
+		// the LOCAL command does not advertise an address so the actual peer is returned
+		let mut v2 = proxy_v2_header(0x0, 0x0, &[]);
+		v2.extend_from_slice(b"hello");
+		let (reply, local_addr) = send(&[&v2])?;
+		assert_eq!(reply, format!("{}|hello", local_addr));
+
+		assert_eq!(rlock!(accepts_clone), 5);
+		assert_eq!(rlock!(closes_clone), 5);
+
+		// a malformed header and an incomplete header are closed without calling any of
+		// the handlers
+		assert_eq!(send(&[b"GET / HTTP/1.1\r\n\r\n"])?.0, "");
+		assert_eq!(send(&[b"PROXY TCP4 192.168.1.10"])?.0, "");
+
+		assert_eq!(rlock!(accepts_clone), 5);
+		assert_eq!(rlock!(closes_clone), 5);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_wakeup_coalescing() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(100)
+		)?;
+
+		evh.set_on_read(move |conn, ctx| -> Result<(), Error> {
+			ctx.clear_all(conn)?;
+			// hammer the write handle from 4 threads. Once the socket buffer is full, each
+			// write is queued and notifies the event handler thread.
+			for i in 0..4 {
+				let mut wh = conn.write_handle()?;
+				spawn(move || -> Result<(), Error> {
+					let data = [b'a' + i; 1_000];
+					for _ in 0..10_000 {
+						wh.write(&data)?;
+					}
+					Ok(())
+				});
+			}
+			Ok(())
+		})?;
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let mut strm = TcpStream::connect(addr)?;
+		strm.write(b"go")?;
+
+		// give the writers time to fill the socket buffer before reading
+		sleep(Duration::from_millis(1_000));
+
+		let mut counts = [0usize; 4];
+		let mut buf = [0u8; 100_000];
+		let mut total = 0;
+		while total < 40_000_000 {
+			let len = strm.read(&mut buf)?;
+			assert!(len > 0);
+			for b in &buf[0..len] {
+				counts[(b - b'a') as usize] += 1;
+			}
+			total += len;
+		}
+		assert_eq!(total, 40_000_000);
+		assert_eq!(counts, [10_000_000; 4]);
+
+		let mut wakeup_writes = 0;
+		let mut delay_writes = 0;
+		for _ in 0..3 {
+			let stats = evh.wait_for_stats()?;
+			wakeup_writes += stats.wakeup_writes;
+			delay_writes += stats.delay_writes;
+		}
+		info!(
+			"wakeup_writes={},delay_writes={}",
+			wakeup_writes, delay_writes
+		)?;
+		assert!(delay_writes > 0);
+		assert!(wakeup_writes < 400);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_send_file() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(100)
+		)?;
+
+		let file_len = 1024 * 1024;
+		let content: Vec<u8> = (0..file_len).map(|i| (i % 251) as u8).collect();
+		let mut path = PathBuf::from(test_info.directory());
+		path.push("send_file.dat");
+		File::create(&path)?.write_all(&content)?;
+		let path = path.to_str().unwrap().to_string();
+
+		let mut errors: Box<dyn LockBox<Vec<String>>> = lock_box!(vec![])?;
+		let errors_clone = errors.clone();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				let next_chunk = next_chunk.unwrap();
+				data.extend(next_chunk.data());
+			}
+			ctx.clear_all(connection)?;
+
+			let mut wh = connection.write_handle()?;
+			match &data[..] {
+				b"all" => wh.send_file(&path, 0, file_len as u64)?,
+				b"around" => {
+					wh.write(b"head")?;
+					wh.send_file(&path, 0, file_len as u64)?;
+					wh.write(b"tail")?;
+				}
+				b"part" => wh.send_file(&path, 1_000, 5_000)?,
+				b"many" => {
+					for i in 0..8u8 {
+						wh.send_file(&path, 0, file_len as u64)?;
+						wh.write(&[i])?;
+					}
+				}
+				b"errors" => {
+					if let Err(e) = wh.send_file("/no/such/file", 0, 1) {
+						wlock!(errors).push(e.to_string());
+					}
+					if let Err(e) = wh.send_file(&path, 1, file_len as u64) {
+						wlock!(errors).push(e.to_string());
+					}
+					wh.write(b"done")?;
+				}
+				_ => {}
+			}
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let mut strm = TcpStream::connect(addr)?;
+
+		strm.write(b"all")?;
+		let mut buf = vec![0u8; file_len];
+		strm.read_exact(&mut buf)?;
+		assert_eq!(buf, content);
+
+		// writes before and after the file must not be reordered
+		strm.write(b"around")?;
+		let mut buf = vec![0u8; file_len + 8];
+		strm.read_exact(&mut buf)?;
+		assert_eq!(&buf[0..4], b"head");
+		assert_eq!(&buf[4..file_len + 4], &content[..]);
+		assert_eq!(&buf[file_len + 4..], b"tail");
+
+		strm.write(b"part")?;
+		let mut buf = vec![0u8; 5_000];
+		strm.read_exact(&mut buf)?;
+		assert_eq!(&buf[..], &content[1_000..6_000]);
+
+		// more than the socket buffers can hold so the sends are resumed on writable events
+		strm.write(b"many")?;
+		sleep(Duration::from_millis(100));
+		for i in 0..8u8 {
+			let mut buf = vec![0u8; file_len + 1];
+			strm.read_exact(&mut buf)?;
+			assert_eq!(&buf[0..file_len], &content[..]);
+			assert_eq!(buf[file_len], i);
+		}
+
+		strm.write(b"errors")?;
+		let mut buf = [0u8; 4];
+		strm.read_exact(&mut buf)?;
+		assert_eq!(&buf, b"done");
+		let errors = rlock!(errors_clone).clone();
+		assert_eq!(errors.len(), 2);
+		assert!(errors[0].contains("could not open file '/no/such/file'"));
+		assert!(errors[1].contains("past the end of file"));
+
+		Ok(())
+	}
+
+	// parse the frames of a capture file into (millis, data) tuples
+	fn read_capture(path: &PathBuf) -> Result<Vec<(u64, Vec<u8>)>, Error> {
+		let mut ret = vec![];
+		let mut content = vec![];
+		if let Ok(mut file) = File::open(path) {
+			file.read_to_end(&mut content)?;
+		}
+		let options = FrameOptions {
+			max_len: u32::MAX as usize,
+			crc32: false,
+		};
+		let mut source = &content[..];
+		while !source.is_empty() {
+			let mut reader = BinReader::new(&mut source);
+			let millis = reader.read_u64()?;
+			ret.push((millis, read_frame(&mut reader, &options)?));
+		}
+		Ok(ret)
+	}
+
+	#[test]
+	fn test_evh_capture() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(EvhTimeout(10), EvhThreads(1), EvhReadSlabSize(100))?;
+
+		let mut id = lock_box!(0u128)?;
+		let id_clone = id.clone();
+		let mut closed = lock_box!(false)?;
+		let closed_clone = closed.clone();
+		let directory = test_info.directory().clone();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			// echo in two writes so that the outbound file has more frames than the inbound file
+			let mut wh = connection.write_handle()?;
+			let mid = data.len() / 2;
+			wh.write(&data[..mid])?;
+			wh.write(&data[mid..])?;
+			Ok(())
+		})?;
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			connection.enable_capture(&directory)?;
+			wlock!(id) = connection.id();
+			Ok(())
+		})?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> {
+			wlock!(closed) = true;
+			Ok(())
+		})?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+
+		let messages: Vec<&[u8]> = vec![b"abcd", b"0123456789", b"xy"];
+		let mut strm = TcpStream::connect(addr)?;
+		for message in &messages {
+			strm.write_all(message)?;
+			let echo = read_until_suffix(&mut strm, message)?;
+			assert_eq!(&echo, message);
+		}
+		drop(strm);
+
+		let mut count = 0;
+		loop {
+			sleep(Duration::from_millis(10));
+			count += 1;
+			cbreak!(rlock!(closed_clone) || count > 500);
+		}
+		assert!(rlock!(closed_clone));
+
+		// the files are written by another thread, wait for all the data to arrive
+		let id = rlock!(id_clone);
+		let expected = messages.concat();
+		let mut in_path = PathBuf::from(test_info.directory());
+		in_path.push(format!("{}.in", id));
+		let mut out_path = PathBuf::from(test_info.directory());
+		out_path.push(format!("{}.out", id));
+		let mut count = 0;
+		let (inbound, outbound) = loop {
+			let inbound = read_capture(&in_path)?;
+			let outbound = read_capture(&out_path)?;
+			let in_len: usize = inbound.iter().map(|(_, d)| d.len()).sum();
+			let out_len: usize = outbound.iter().map(|(_, d)| d.len()).sum();
+			count += 1;
+			if (in_len == expected.len() && out_len == expected.len()) || count > 500 {
+				break (inbound, outbound);
+			}
+			sleep(Duration::from_millis(10));
+		};
+
+		// the exact bytes can be reconstructed from the frames in order
+		let data: Vec<u8> = inbound.iter().flat_map(|(_, d)| d.clone()).collect();
+		assert_eq!(data, expected);
+		let data: Vec<u8> = outbound.iter().flat_map(|(_, d)| d.clone()).collect();
+		assert_eq!(data, expected);
+		assert_eq!(inbound.len(), messages.len());
+		assert_eq!(outbound.len(), messages.len() * 2);
+		for frames in [&inbound, &outbound] {
+			for i in 1..frames.len() {
+				assert!(frames[i - 1].0 <= frames[i].0);
+			}
+		}
+		// each echo is captured after the data that it echoes
+		for i in 0..messages.len() {
+			assert!(inbound[i].0 <= outbound[i * 2].0);
+		}
+
+		// a connection that is not managed by an evh can't be captured
+		let mut client = EvhBuilder::build_client_connection("127.0.0.1", port)?;
+		let err = client.enable_capture(test_info.directory()).unwrap_err();
+		assert!(matches!(err.kind(), ErrorKind::IllegalState(_)));
+
+		Ok(())
+	}
+
+	// run an echo server with the faults in debug_info injected and check that every byte
+	// is echoed exactly once and that the event loop does not spin
+	fn echo_under_faults(port: u16, debug_info: DebugInfo) -> Result<(), Error> {
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(2),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(100)
+		)?;
+		evh.set_debug_info(debug_info)?;
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			connection.write_handle()?.write(&data)?;
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh.add_server_connection(conn)?;
+
+		let mut strms = vec![];
+		for _ in 0..4 {
+			let strm = TcpStream::connect(addr.clone())?;
+			strm.set_read_timeout(Some(Duration::from_millis(10_000)))?;
+			strms.push(strm);
+		}
+		// small messages, messages spanning many slabs and one that fills the socket buffers
+		let sizes = [1, 7, 99, 100, 101, 1_000, 5_000, 300_000, 3];
+		for (i, size) in sizes.iter().enumerate() {
+			for (j, strm) in strms.iter_mut().enumerate() {
+				let message: Vec<u8> = (0..*size).map(|k| ((i + j + k) % 251) as u8).collect();
+				strm.write_all(&message)?;
+				let mut echo = vec![0u8; *size];
+				strm.read_exact(&mut echo)?;
+				assert_eq!(echo, message);
+			}
+		}
+
+		// nothing is echoed twice
+		for strm in &mut strms {
+			strm.set_read_timeout(Some(Duration::from_millis(50)))?;
+			let mut buf = [0u8; 1];
+			assert!(strm.read(&mut buf).is_err());
+		}
+
+		// once the traffic is done, an idle loop times out every 10ms on each of the 2 threads.
+		// A loop that keeps getting re-armed would run far more often than that.
+		evh.wait_for_stats()?;
+		for _ in 0..3 {
+			let stats = evh.wait_for_stats()?;
+			info!("event_loops={}", stats.event_loops)?;
+			assert!(stats.event_loops < 200);
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_fault_partial_read() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let debug_info = DebugInfo {
+			partial_read: lock_box!(Some(7))?,
+			..Default::default()
+		};
+		echo_under_faults(test_info.port(), debug_info)
+	}
+
+	#[test]
+	fn test_evh_fault_eagain() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let debug_info = DebugInfo {
+			eagain_probability: lock_box!(Some((0.3, 1_234)))?,
+			..Default::default()
+		};
+		echo_under_faults(test_info.port(), debug_info)?;
+
+		// the same seed produces the same faults
+		let a = DebugInfo {
+			eagain_probability: lock_box!(Some((0.5, 99)))?,
+			..Default::default()
+		};
+		let b = DebugInfo {
+			eagain_probability: lock_box!(Some((0.5, 99)))?,
+			..Default::default()
+		};
+		let a: Vec<bool> = (0..100).map(|_| a.is_spurious_eagain()).collect();
+		let b: Vec<bool> = (0..100).map(|_| b.is_spurious_eagain()).collect();
+		assert_eq!(a, b);
+		assert!(a.contains(&true) && a.contains(&false));
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_accept_budget() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhOnAcceptBudgetMicros(1_000),
+			EvhStatsUpdateMillis(100)
+		)?;
+
+		let accepts = lock_box!(0usize)?;
+		let accepts_clone = accepts.clone();
+		evh.set_on_accept(move |_, _| -> Result<(), Error> {
+			// a slow on_accept so that registering the burst takes much longer than the budget
+			sleep(Duration::from_millis(1));
+			let mut accepts = accepts_clone.clone();
+			wlock!(accepts) += 1;
+			Ok(())
+		})?;
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			connection.write_handle()?.write(&data)?;
+			Ok(())
+		})?;
+		evh.set_on_close(move |_, _, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_, _| -> Result<(), Error> { Ok(()) })?;
+
+		evh.start()?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let mut existing = TcpStream::connect(addr.clone())?;
+		let mut buf = [0u8; 10];
+		existing.write_all(b"ping")?;
+		assert_eq!(existing.read(&mut buf)?, 4);
+
+		// hold the loop while the connects queue up in the listen backlog
+		evh.set_debug_info(DebugInfo {
+			hold_loop: lock_box!(true)?,
+			..Default::default()
+		})?;
+		sleep(Duration::from_millis(50));
+		let mut strms = vec![];
+		for _ in 0..500 {
+			strms.push(TcpStream::connect(addr.clone())?);
+		}
+		evh.set_debug_info(DebugInfo::default())?;
+
+		// once the burst is being registered, the existing connection is still served
+		while rlock!(accepts) < 2 {
+			sleep(Duration::from_millis(1));
+		}
+		let start = Instant::now();
+		existing.write_all(b"ping")?;
+		assert_eq!(existing.read(&mut buf)?, 4);
+		let latency = start.elapsed();
+		info!("echo latency during the burst = {:?}", latency)?;
+		assert!(latency < Duration::from_millis(200));
+		assert!(rlock!(accepts) < 501);
+
+		let mut deferrals = 0;
+		let mut max_accept_micros = 0;
+		let mut count = 0;
+		while rlock!(accepts) < 501 {
+			let stats = evh.wait_for_stats()?;
+			deferrals += stats.accept_burst_deferrals;
+			max_accept_micros = max_accept_micros.max(stats.max_accept_micros);
+			count += 1;
+			assert!(count < 300);
+		}
+		assert!(deferrals > 0);
+		assert!(max_accept_micros >= 1_000);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_fault_accept_eagain_once() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let debug_info = DebugInfo {
+			accept_eagain_once: lock_box!(true)?,
+			..Default::default()
+		};
+		assert!(debug_info.take_accept_eagain());
+		assert!(!debug_info.take_accept_eagain());
+		let debug_info = DebugInfo {
+			accept_eagain_once: lock_box!(true)?,
+			..Default::default()
+		};
+		echo_under_faults(test_info.port(), debug_info)
+	}
+
+	#[test]
+	fn test_evh_fault_combined() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let debug_info = DebugInfo {
+			partial_read: lock_box!(Some(13))?,
+			eagain_probability: lock_box!(Some((0.2, 42)))?,
+			accept_eagain_once: lock_box!(true)?,
+			..Default::default()
+		};
+		echo_under_faults(test_info.port(), debug_info)
+	}
+
+	// read from strm until the data ends with `suffix`
+	fn read_until_suffix(strm: &mut TcpStream, suffix: &[u8]) -> Result<Vec<u8>, Error> {
+		let mut ret = vec![];
+		let mut buf = [0u8; 10_000];
+		while !ret.ends_with(suffix) {
+			let len = strm.read(&mut buf)?;
+			assert!(len > 0);
+			ret.extend(&buf[0..len]);
+		}
+		Ok(ret)
+	}
+
+	#[test]
+	fn test_evh_write_compressed() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(
+			Debug(false),
+			EvhTimeout(u16::MAX),
+			EvhThreads(1),
+			EvhReadSlabSize(100)
+		)?;
+
+		let content: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+		let content_clone = content.clone();
+		let mut errors: Box<dyn LockBox<Vec<String>>> = lock_box!(vec![])?;
+		let errors_clone = errors.clone();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				let next_chunk = next_chunk.unwrap();
+				data.extend(next_chunk.data());
+			}
+			ctx.clear_all(connection)?;
+
+			let mut wh = connection.write_handle()?;
+			match &data[..] {
+				b"deflate" => {
+					wh.write_compressed(&content_clone, CompressionFormat::Deflate, 6)?;
+					wh.write(b"after")?;
+				}
+				b"stream" => {
+					// level 0 so that the compressed data is written in several blocks
+					let mut stream = wh.begin_compressed_stream(CompressionFormat::Gzip, 0)?;
+					for chunk in content_clone.chunks(100_000) {
+						stream.write(chunk)?;
+						// written to the connection after the compressed data
+						connection.write_handle()?.write(b"during")?;
+					}
+					if let Err(e) = wh.begin_compressed_stream(CompressionFormat::Gzip, 1) {
+						wlock!(errors).push(e.to_string());
+					}
+					stream.finish()?;
+					wh.write(b"after")?;
+				}
+				b"errors" => {
+					if let Err(e) = wh.write_compressed(b"x", CompressionFormat::Gzip, 10) {
+						wlock!(errors).push(e.to_string());
+					}
+					wh.write(b"done")?;
+				}
+				_ => {}
+			}
+			Ok(())
+		})?;
+
+		evh.start()?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let mut strm = TcpStream::connect(addr)?;
+		strm.set_read_timeout(Some(Duration::from_millis(10_000)))?;
+
+		strm.write(b"deflate")?;
+		let data = read_until_suffix(&mut strm, b"after")?;
+		assert!(data.len() < content.len() / 10);
+		let mut decoder = ZlibDecoder::new(&data[..]);
+		let mut decompressed = vec![];
+		decoder.read_to_end(&mut decompressed)?;
+		assert_eq!(decompressed, content);
+		assert_eq!(decoder.into_inner(), b"after");
+
+		// plain writes made while the stream is open and after it is finished arrive after
+		// the compressed data, in order
+		strm.write(b"stream")?;
+		let data = read_until_suffix(&mut strm, b"after")?;
+		let mut decoder = GzDecoder::new(&data[..]);
+		let mut decompressed = vec![];
+		decoder.read_to_end(&mut decompressed)?;
+		assert_eq!(decompressed, content);
+		let mut expected = b"during".repeat(11);
+		expected.extend(b"after");
+		assert_eq!(decoder.into_inner(), &expected[..]);
+
+		strm.write(b"errors")?;
+		read_until_suffix(&mut strm, b"done")?;
+		let errors = rlock!(errors_clone).clone();
+		assert_eq!(errors.len(), 2);
+		assert!(errors[0].contains("already open"));
+		assert!(errors[1].contains("compression level"));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_export_import() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh1 = evh!(EvhTimeout(10), EvhThreads(1), EvhReadSlabSize(100))?;
+		let mut evh2 = evh!(EvhTimeout(10), EvhThreads(1), EvhReadSlabSize(100))?;
+
+		let mut exported = lock_box!(None::<ExportedHandle>)?;
+		let exported_clone = exported.clone();
+		let mut old_wh = lock_box!(None::<WriteHandle>)?;
+		let old_wh_clone = old_wh.clone();
+		let mut closed = lock_box!(false)?;
+		let closed_clone = closed.clone();
+		let mut imported = lock_box!(None::<(Option<(String, u64)>, u64)>)?;
+		let imported_clone = imported.clone();
+
+		evh1.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			let mut wh = connection.write_handle()?;
+			if data == b"export" {
+				// queued data is flushed before the handle is exported
+				wh.write(b"moving")?;
+				connection.set_attachment(&("session".to_string(), 7u64))?;
+				let handle = connection.export_handle()?;
+				wlock!(exported) = Some(handle);
+				wlock!(old_wh) = Some(wh);
+			} else {
+				wh.write(&[b"1:", &data[..]].concat())?;
+			}
+			Ok(())
+		})?;
+		evh1.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh1.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> {
+			wlock!(closed) = true;
+			Ok(())
+		})?;
+		evh1.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh1.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh1.start()?;
+
+		evh2.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			let mut wh = connection.write_handle()?;
+			wh.write(&[b"2:", &data[..]].concat())?;
+			Ok(())
+		})?;
+		evh2.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			let attachment = connection.attachment()?;
+			wlock!(imported) = Some((attachment, connection.evh_instance_id()));
+			Ok(())
+		})?;
+		evh2.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh2.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh2.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh2.start()?;
+
+		let port = test_info.port();
+		let addr = format!("127.0.0.1:{}", port);
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		evh1.add_server_connection(conn)?;
+
+		let mut strm = TcpStream::connect(addr)?;
+		strm.write_all(b"hello")?;
+		assert_eq!(read_until_suffix(&mut strm, b"1:hello")?, b"1:hello");
+		strm.write_all(b"export")?;
+		assert_eq!(read_until_suffix(&mut strm, b"moving")?, b"moving");
+
+		let mut count = 0;
+		let handle = loop {
+			if let Some(handle) = rlock!(exported_clone).clone() {
+				break handle;
+			}
+			count += 1;
+			assert!(count < 500);
+			sleep(Duration::from_millis(10));
+		};
+
+		// writes through the handles of the exporting EventHandler fail
+		let mut wh = rlock!(old_wh_clone).clone().unwrap();
+		assert!(wh.write(b"stale").is_err());
+
+		// an unknown version of the state is rejected
+		let mut state = handle.state().to_vec();
+		state[0] += 1;
+		let bad = ExportedHandle::new(handle.handle(), state);
+		assert!(EvhBuilder::import_connection(bad).is_err());
+
+		// rebuild it as a process receiving the handle and its state would
+		let handle = ExportedHandle::new(handle.handle(), handle.state().to_vec());
+		let connection = EvhBuilder::import_connection(handle)?;
+		assert_eq!(
+			connection.attachment()?,
+			Some(("session".to_string(), 7u64))
+		);
+		evh2.add_imported_connection(connection)?;
+
+		// the client keeps using the same stream
+		strm.write_all(b"again")?;
+		assert_eq!(read_until_suffix(&mut strm, b"2:again")?, b"2:again");
+		let (attachment, evh_instance_id) = rlock!(imported_clone).clone().unwrap();
+		assert_eq!(attachment, Some(("session".to_string(), 7u64)));
+		assert_eq!(evh_instance_id, evh2.instance_id());
+
+		// the exporting EventHandler never closed or called on_close for it
+		assert!(!rlock!(closed_clone));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_update_config() -> Result<(), Error> {
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhHouseKeeperFrequencyMillis(1_000)
+		)?;
+		let mut count = lock_box!(0usize)?;
+		let count_clone = count.clone();
+
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> {
+			wlock!(count) += 1;
+			Ok(())
+		})?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		sleep(Duration::from_millis(1_000));
+		let slow = rlock!(count_clone);
+		assert!(slow <= 2);
+
+		// shrink the interval without rebuilding the evh
+		evh.update_config(vec![ConfigOption::EvhHouseKeeperFrequencyMillis(20)])?;
+		sleep(Duration::from_millis(1_000));
+		let fast = rlock!(count_clone) - slow;
+		assert!(fast >= 10);
+
+		// options that can only be set when building are rejected and listed
+		let res = evh.update_config(vec![
+			ConfigOption::EvhHouseKeeperFrequencyMillis(5_000),
+			ConfigOption::EvhThreads(2),
+			ConfigOption::EvhReadSlabSize(100),
+		]);
+		let e = res.unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
+		let text = e.to_string();
+		assert!(text.contains("EvhThreads"));
+		assert!(text.contains("EvhReadSlabSize"));
+		assert!(!text.contains("EvhHouseKeeperFrequencyMillis"));
+
+		// invalid values and options of other components are rejected
+		let mut controller = evh.controller()?;
+		let invalid = ConfigOption::EvhHouseKeeperFrequencyMillis(0);
+		assert!(controller.update_config(vec![invalid]).is_err());
+		assert!(controller
+			.update_config(vec![ConfigOption::MaxSizeBytes(100)])
+			.is_err());
+
+		// nothing from the rejected updates was applied
+		let before = rlock!(count_clone);
+		sleep(Duration::from_millis(500));
+		assert!(rlock!(count_clone) - before >= 5);
+
+		// the controller applies changes as well
+		controller.update_config(vec![ConfigOption::EvhHouseKeeperFrequencyMillis(60_000)])?;
+		sleep(Duration::from_millis(100));
+		let before = rlock!(count_clone);
+		sleep(Duration::from_millis(500));
+		assert!(rlock!(count_clone) - before <= 1);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_update_config_audit() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut path = PathBuf::from(test_info.directory());
+		path.push("audit.log");
+		let path = path.display().to_string();
+
+		let mut evh = evh!(EvhTimeout(100), EvhThreads(1), EvhName("audit".to_string()))?;
+		let mut logger = logger_box!(
+			LogFilePath(&path),
+			DisplayStdout(false),
+			DisplayColors(false),
+			DisplayBacktrace(false),
+			AutoRotate(false)
+		)?;
+		logger.init()?;
+		evh.set_logger(logger)?;
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		// one line is logged for each option that changed
+		evh.update_config(vec![
+			ConfigOption::EvhTimeout(50),
+			ConfigOption::EvhStatsUpdateMillis(5_000),
+			ConfigOption::EvhOutOfSlabsMessage("full".to_string()),
+		])?;
+		// nothing changed
+		evh.update_config(vec![ConfigOption::EvhTimeout(50)])?;
+		evh.update_config(vec![ConfigOption::EvhTimeout(60)])?;
+
+		let mut contents = String::new();
+		File::open(&path)?.read_to_string(&mut contents)?;
+		let changes: Vec<&str> = contents
+			.lines()
+			.filter_map(|line| line.split("[audit] config change: ").nth(1))
+			.collect();
+		assert_eq!(
+			changes,
+			vec![
+				"EvhTimeout: 100 -> 50",
+				"EvhOutOfSlabsMessage: \"\" -> \"full\"",
+				"EvhTimeout: 50 -> 60",
+			]
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_set_logger() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut path = PathBuf::from(test_info.directory());
+		path.push("public.log");
+		let path = path.display().to_string();
+
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(1),
+			EvhReadSlabSize(25),
+			EvhReadSlabCount(1),
+			EvhStatsUpdateMillis(100),
+			EvhName("public".to_string())
+		)?;
+
+		let mut logger = logger_box!(
+			LogFilePath(&path),
+			DisplayStdout(false),
+			DisplayColors(false),
+			DisplayBacktrace(false),
+			AutoRotate(false)
+		)?;
+		logger.init()?;
+		evh.set_logger(logger)?;
+
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		// more data than the single read slab can hold
+		let mut strm = TcpStream::connect(addr)?;
+		strm.write_all(b"012345678901234567890123456789")?;
+		let mut buf = [0u8; 100];
+		loop {
+			cbreak!(strm.read(&mut buf)? == 0);
+		}
+
+		let mut found = false;
+		for _ in 0..100 {
+			let mut contents = String::new();
+			File::open(&path)?.read_to_string(&mut contents)?;
+			found = contents.contains("[public] cannot allocate any more slabs");
+			cbreak!(found);
+			sleep(Duration::from_millis(10));
+		}
+		assert!(found);
+
+		assert_eq!(evh.wait_for_stats()?.name, Some("public".to_string()));
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_accept_before_read() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(4),
+			EvhStatsUpdateMillis(5000),
+			EvhStrictOrdering(true)
+		)?;
+
+		let initialized = lock_box!(HashSet::new())?;
+		let mut initialized_clone = initialized.clone();
+		let mut violations = lock_box!(0usize)?;
+		let violations_clone = violations.clone();
+		let mut panics = lock_box!(0usize)?;
+		let panics_clone = panics.clone();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			if !rlock!(initialized).contains(&connection.id()) {
+				wlock!(violations) += 1;
+			}
+			ctx.clear_all(connection)?;
+			connection.write_handle()?.write(b"ok")?;
+			Ok(())
+		})?;
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			wlock!(initialized_clone).insert(connection.id());
+			Ok(())
+		})?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> {
+			wlock!(panics) += 1;
+			Ok(())
+		})?;
+		evh.start()?;
+
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		// the data is written right after connecting so it is usually ready along with the
+		// accept
+		for _ in 0..1_000 {
+			let mut strm = TcpStream::connect(&addr)?;
+			strm.write_all(b"hello")?;
+			let mut buf = [0u8; 2];
+			strm.read_exact(&mut buf)?;
+			assert_eq!(&buf, b"ok");
+		}
+
+		assert_eq!(rlock!(violations_clone), 0);
+		assert_eq!(rlock!(panics_clone), 0);
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_slab_quota_pause() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(1),
+			EvhReadSlabSize(25),
+			EvhStatsUpdateMillis(5000),
+			EvhMaxSlabsPerConnection(2)
+		)?;
+
+		let quota_hits = lock_box!(vec![])?;
+		let mut quota_hits_clone = quota_hits.clone();
+		let mut closes = lock_box!(0usize)?;
+		let closes_clone = closes.clone();
+
+		// echo everything except data starting with "hoard". Those connections keep their slabs.
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			if data.starts_with(b"hoard") {
+				return Ok(());
+			}
+			connection.write_handle()?.write(&data)?;
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+		evh.set_on_slab_quota(Box::new(move |connection, _ctx| -> Result<(), Error> {
+			wlock!(quota_hits_clone).push(connection.id());
+			Ok(())
+		}))?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> {
+			wlock!(closes) += 1;
+			Ok(())
+		})?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		// each slab holds 21 bytes so this needs more than 2 slabs
+		let mut hoarder = TcpStream::connect(&addr)?;
+		hoarder.write_all(b"hoard")?;
+		hoarder.write_all(&[b'x'; 200])?;
+
+		let mut count = 0;
+		for _ in 0..100 {
+			count = rlock!(quota_hits).len();
+			cbreak!(count > 0);
+			sleep(Duration::from_millis(10));
+		}
+		assert_eq!(count, 1);
+
+		// the other connection on the same thread keeps echoing
+		let mut strm = TcpStream::connect(&addr)?;
+		for _ in 0..10 {
+			strm.write_all(b"hello")?;
+			let mut buf = [0u8; 5];
+			strm.read_exact(&mut buf)?;
+			assert_eq!(&buf, b"hello");
+		}
+
+		// more data for the paused connection does not execute the callback again
+		hoarder.write_all(&[b'x'; 200])?;
+		strm.write_all(b"hello")?;
+		let mut buf = [0u8; 5];
+		strm.read_exact(&mut buf)?;
+		assert_eq!(rlock!(quota_hits).len(), 1);
+		assert_eq!(rlock!(closes_clone), 0);
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_max_unread_bytes() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(1),
+			EvhReadSlabSize(25),
+			EvhStatsUpdateMillis(5000)
+		)?;
+
+		let received = lock_box!(Vec::<u8>::new())?;
+		let received_clone = received.clone();
+		let max_unread = lock_box!(0usize)?;
+		let max_unread_clone = max_unread.clone();
+		let write_handle: Box<dyn LockBox<Option<WriteHandle>>> = lock_box!(None)?;
+		let mut write_handle_clone = write_handle.clone();
+
+		// data is only cleared by the synthetic callbacks, so reading stops at the limit until
+		// the test triggers one
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut max_unread = max_unread_clone.clone();
+			let mut received = received_clone.clone();
+			let unread = connection.unread_bytes();
+			if unread > rlock!(max_unread) {
+				wlock!(max_unread) = unread;
+			}
+			if ctx.is_synthetic() {
+				loop {
+					let next_chunk = ctx.next_chunk(connection)?;
+					cbreak!(next_chunk.is_none());
+					wlock!(received).extend(next_chunk.unwrap().data());
+				}
+				ctx.clear_all(connection)?;
+				assert_eq!(connection.unread_bytes(), 0);
+			}
+			Ok(())
+		})?;
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			connection.set_max_unread_bytes(100)?;
+			wlock!(write_handle_clone) = Some(connection.write_handle()?);
+			Ok(())
+		})?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let data: Vec<u8> = (0..10_240).map(|i| (i % 251) as u8).collect();
+		let mut strm = TcpStream::connect(&addr)?;
+		strm.write_all(&data)?;
+
+		let mut count = 0;
+		while rlock!(received).len() < data.len() {
+			let wh = rlock!(write_handle).clone();
+			if let Some(mut wh) = wh {
+				wh.trigger_on_read()?;
+			}
+			sleep(Duration::from_millis(5));
+			count += 1;
+			assert!(count < 6_000);
+		}
+
+		assert_eq!(rlock!(received), data);
+		assert_eq!(rlock!(max_unread), 100);
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_housekeeper_context() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(10),
+			EvhThreads(2),
+			EvhHouseKeeperFrequencyMillis(50),
+			EvhStatsUpdateMillis(5000)
+		)?;
+
+		let local_closes = lock_box!(0usize)?;
+		let local_closes_clone = local_closes.clone();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, reason, _ctx| -> Result<(), Error> {
+			let mut local_closes = local_closes_clone.clone();
+			if let CloseReason::LocalClose = reason {
+				wlock!(local_closes) += 1;
+			}
+			Ok(())
+		})?;
+		// ping every connection of this thread and close the ones that have not sent any
+		// data for 500ms
+		evh.set_on_housekeeper(move |hk, _ctx| -> Result<(), Error> {
+			assert!(hk.write_handle(u128::MAX)?.is_none());
+			assert!(hk.idle_millis(u128::MAX).is_none());
+			assert!(!hk.request_close(u128::MAX, CloseReason::LocalClose));
+			let ids: Vec<u128> = hk.connection_ids().collect();
+			for id in ids {
+				if hk.idle_millis(id).unwrap() > 500 {
+					assert!(hk.request_close(id, CloseReason::LocalClose));
+				} else {
+					hk.write_handle(id)?.unwrap().write(b"ping")?;
+				}
+			}
+			Ok(())
+		})?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let mut active = TcpStream::connect(&addr)?;
+		let mut idle = TcpStream::connect(&addr)?;
+		active.set_read_timeout(Some(Duration::from_millis(50)))?;
+		idle.set_read_timeout(Some(Duration::from_millis(50)))?;
+
+		// the idle connection is pinged until it is closed by the housekeeper
+		let mut idle_pings = 0;
+		let mut active_pings = 0;
+		let mut buf = [0u8; 100];
+		let mut count = 0;
+		loop {
+			active.write_all(b"x")?;
+			match active.read(&mut buf) {
+				Ok(len) => {
+					assert!(len > 0);
+					active_pings += len;
+				}
+				Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock),
+			}
+			match idle.read(&mut buf) {
+				Ok(0) => break,
+				Ok(len) => idle_pings += len,
+				Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock),
+			}
+			count += 1;
+			assert!(count < 400);
+		}
+
+		assert!(idle_pings >= 4);
+		assert_eq!(idle_pings % 4, 0);
+		assert_eq!(rlock!(local_closes), 1);
+
+		// the active connection is still pinged
+		let start = active_pings;
+		while active_pings < start + 8 {
+			active.write_all(b"x")?;
+			match active.read(&mut buf) {
+				Ok(len) => {
+					assert!(len > 0);
+					active_pings += len;
+				}
+				Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock),
+			}
+			count += 1;
+			assert!(count < 400);
+		}
+		assert_eq!(rlock!(local_closes), 1);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_slab_quota_close() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(1),
+			EvhReadSlabSize(25),
+			EvhStatsUpdateMillis(5000),
+			EvhMaxSlabsPerConnection(2),
+			EvhSlabQuotaAction("Close".to_string()),
+			EvhOutOfSlabsMessage("quota".to_string())
+		)?;
+
+		// echo everything except data starting with "hoard". Those connections keep their slabs.
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			if data.starts_with(b"hoard") {
+				return Ok(());
+			}
+			connection.write_handle()?.write(&data)?;
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let mut hoarder = TcpStream::connect(&addr)?;
+		hoarder.write_all(b"hoard")?;
+		hoarder.write_all(&[b'x'; 200])?;
+		let mut data = vec![];
+		hoarder.read_to_end(&mut data)?;
+		assert_eq!(&data, b"quota");
+
+		let mut strm = TcpStream::connect(&addr)?;
+		for _ in 0..10 {
+			strm.write_all(b"hello")?;
+			let mut buf = [0u8; 5];
+			strm.read_exact(&mut buf)?;
+			assert_eq!(&buf, b"hello");
+		}
+
+		// a quota of 0, an unknown action and an action without a quota are errors
+		match evh_oro!(EvhMaxSlabsPerConnection(0)) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+				return Err(err!(ErrKind::Test, "expected a configuration error"));
+			}
+			Err(e) => assert!(matches!(e.kind(), ErrorKind::Configuration(_))),
+		}
+		match evh_oro!(
+			EvhMaxSlabsPerConnection(2),
+			EvhSlabQuotaAction("Drop".to_string())
+		) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+				return Err(err!(ErrKind::Test, "expected a configuration error"));
+			}
+			Err(e) => assert!(matches!(e.kind(), ErrorKind::Configuration(_))),
+		}
+		match evh_oro!(EvhSlabQuotaAction("Close".to_string())) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+				return Err(err!(ErrKind::Test, "expected a configuration error"));
+			}
+			Err(e) => assert!(matches!(e.kind(), ErrorKind::Configuration(_))),
+		}
+		Ok(())
+	}
+
+	#[test]
+	#[cfg(not(target_os = "windows"))]
+	fn test_evh_handle_signals() -> Result<(), Error> {
+		use crate::evh::{dispatch_signal_mask, signal_mask};
+		use crate::{ShutdownMode, Signal};
+
+		// echo server that records its shutdown event and the close of the connections that it
+		// echoed data on. The connections that only probe whether the server still accepts
+		// connections are not recorded.
+		macro_rules! echo_evh {
+			($mode:expr, $signals:expr, $events:expr) => {{
+				let mut evh = evh!(Debug(false), EvhTimeout(100), EvhThreads(2))?;
+				let mut events: Box<dyn LockBox<Vec<String>>> = $events;
+				let mut events_clone = events.clone();
+				let mut echoed = lock_box!(HashSet::new())?;
+				let echoed_clone = echoed.clone();
+				evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+					wlock!(echoed).insert(connection.id());
+					let mut data: Vec<u8> = vec![];
+					loop {
+						let next_chunk = ctx.next_chunk(connection)?;
+						cbreak!(next_chunk.is_none());
+						data.extend(next_chunk.unwrap().data());
+					}
+					connection.write_handle()?.write(&data)?;
+					ctx.clear_all(connection)?;
+					Ok(())
+				})?;
+				evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+				evh.set_on_close(move |connection, _reason, _ctx| -> Result<(), Error> {
+					if rlock!(echoed_clone).contains(&connection.id()) {
+						wlock!(events_clone).push("close".to_string());
+					}
+					Ok(())
+				})?;
+				evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+				evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+				evh.set_on_shutdown(Box::new(move || -> Result<(), Error> {
+					wlock!(events).push("shutdown".to_string());
+					Ok(())
+				}))?;
 
-		spawn(move || {
-			let _ = EventHandlerImpl::execute_thread(
-				config,
-				callbacks,
-				state,
-				ctx_arr,
-				user_context_arr,
-				0,
-				true,
-				&debug_info,
-			);
-		});
+				// the evh must be started before signals may be handled
+				match evh.handle_signals($signals, $mode) {
+					Ok(_) => return Err(err!(ErrKind::Test, "expected an illegal state error")),
+					Err(e) => assert!(matches!(e.kind(), ErrorKind::IllegalState(_))),
+				}
+				evh.start()?;
+				match evh.handle_signals(&[], $mode) {
+					Ok(_) => return Err(err!(ErrKind::Test, "expected an illegal argument error")),
+					Err(e) => assert!(matches!(e.kind(), ErrorKind::IllegalArgument(_))),
+				}
+				evh.handle_signals($signals, $mode)?;
 
-		sleep(Duration::from_millis(QA_SLEEP));
+				let addr = format!("127.0.0.1:{}", pick_free_port()?);
+				let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+				evh.add_server_connection(conn)?;
+				(evh, addr)
+			}};
+		}
+
+		fn echo(strm: &mut TcpStream) -> Result<(), Error> {
+			strm.write_all(b"hello")?;
+			let mut buf = [0u8; 5];
+			strm.read_exact(&mut buf)?;
+			assert_eq!(&buf, b"hello");
+			Ok(())
+		}
+
+		fn wait_for(
+			events: &Box<dyn LockBox<Vec<String>>>,
+			expected: &[&str],
+		) -> Result<(), Error> {
+			for _ in 0..500 {
+				cbreak!(rlock!(events).len() >= expected.len());
+				sleep(Duration::from_millis(10));
+			}
+			assert_eq!(rlock!(events), expected);
+			Ok(())
+		}
+
+		let events1 = lock_box!(vec![])?;
+		let events2 = lock_box!(vec![])?;
+		let events3 = lock_box!(vec![])?;
+		let term = &[Signal::Term];
+		let (_evh1, addr1) = echo_evh!(ShutdownMode::Graceful(10_000), term, events1.clone());
+		let both = &[Signal::Term, Signal::Int];
+		let (_evh2, addr2) = echo_evh!(ShutdownMode::Immediate, both, events2.clone());
+		let int = &[Signal::Int];
+		let (_evh3, addr3) = echo_evh!(ShutdownMode::Graceful(300), int, events3.clone());
+
+		let mut strm1 = TcpStream::connect(&addr1)?;
+		echo(&mut strm1)?;
+		let mut strm2 = TcpStream::connect(&addr2)?;
+		echo(&mut strm2)?;
+		let mut strm3 = TcpStream::connect(&addr3)?;
+		echo(&mut strm3)?;
+
+		// both instances registered for SIGTERM are notified. The signal is dispatched
+		// directly; test_evh_handle_signals_raise raises a real one in a child process.
+		dispatch_signal_mask(signal_mask(&Signal::Term));
+
+		// the immediate shutdown closes the connection right away
+		wait_for(&events2, &["shutdown"])?;
+		let mut data = vec![];
+		strm2.read_to_end(&mut data)?;
+
+		// the graceful shutdown stops accepting connections but keeps the open one
+		let mut refused = false;
+		for _ in 0..500 {
+			refused = TcpStream::connect(&addr1).is_err();
+			cbreak!(refused);
+			sleep(Duration::from_millis(10));
+		}
+		assert!(refused);
+		echo(&mut strm1)?;
+		assert_eq!(rlock!(events1).len(), 0);
+
+		// once the connection is closed, on_shutdown is executed
+		drop(strm1);
+		wait_for(&events1, &["close", "shutdown"])?;
+
+		// the instance registered for SIGINT only was not affected
+		echo(&mut strm3)?;
+		assert_eq!(rlock!(events3).len(), 0);
+
+		// the open connection is closed when the timeout elapses
+		let start = Instant::now();
+		dispatch_signal_mask(signal_mask(&Signal::Int));
+		let mut data = vec![];
+		strm3.read_to_end(&mut data)?;
+		assert!(start.elapsed() >= Duration::from_millis(300));
+		wait_for(&events3, &["shutdown"])?;
+
+		// evh2 was registered for SIGINT as well but was already shut down
+		assert_eq!(rlock!(events2).len(), 1);
+		Ok(())
+	}
+
+	// a real signal affects the whole process, so it is raised in a child process that runs
+	// test_evh_handle_signals_child
+	#[test]
+	#[cfg(not(target_os = "windows"))]
+	fn test_evh_handle_signals_raise() -> Result<(), Error> {
+		let status = std::process::Command::new(std::env::current_exe()?)
+			.args([
+				"test::test::test_evh_handle_signals_child",
+				"--exact",
+				"--ignored",
+			])
+			.env("BMW_EVH_SIGNAL_CHILD", "1")
+			.status()?;
+		assert!(status.success());
+		Ok(())
+	}
+
+	#[test]
+	#[ignore]
+	#[cfg(not(target_os = "windows"))]
+	fn test_evh_handle_signals_child() -> Result<(), Error> {
+		use crate::{ShutdownMode, Signal};
+		use bmw_deps::libc::{raise, SIGTERM};
+
+		// only run as the child process of test_evh_handle_signals_raise
+		if std::env::var("BMW_EVH_SIGNAL_CHILD").is_err() {
+			return Ok(());
+		}
+
+		let mut evh = evh!(Debug(false), EvhTimeout(100), EvhThreads(1))?;
+		let mut events = lock_box!(vec![])?;
+		let mut events_clone = events.clone();
+		let events_check = events.clone();
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			ctx.clear_all(connection)?;
+			connection.write_handle()?.write(b"hi")?;
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> {
+			wlock!(events_clone).push("close".to_string());
+			Ok(())
+		})?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_shutdown(Box::new(move || -> Result<(), Error> {
+			wlock!(events).push("shutdown".to_string());
+			Ok(())
+		}))?;
+		evh.start()?;
+		evh.handle_signals(&[Signal::Term], ShutdownMode::Graceful(10_000))?;
+
+		let addr = format!("127.0.0.1:{}", pick_free_port()?);
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+		let mut strm = TcpStream::connect(&addr)?;
+		strm.write_all(b"hi")?;
+		let mut buf = [0u8; 2];
+		strm.read_exact(&mut buf)?;
+
+		// the connection is drained before on_shutdown runs
+		unsafe { raise(SIGTERM) };
+		sleep(Duration::from_millis(100));
+		assert_eq!(rlock!(events_check).len(), 0);
+		drop(strm);
+		for _ in 0..500 {
+			cbreak!(rlock!(events_check).len() >= 2);
+			sleep(Duration::from_millis(10));
+		}
+		assert_eq!(
+			rlock!(events_check),
+			vec!["close".to_string(), "shutdown".to_string()]
+		);
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_out_of_slabs() -> Result<(), Error> {
+	fn test_evh_write_delay_and_rate_limit() -> Result<(), Error> {
 		let test_info = test_info!()?;
-
-		let mut evh = evh_oro!(
+		let mut evh = evh!(
 			Debug(false),
-			EvhTimeout(u16::MAX),
+			EvhTimeout(100),
 			EvhThreads(1),
-			EvhReadSlabSize(25),
-			EvhReadSlabCount(1)
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(5000)
 		)?;
 
-		let mut counter = lock_box!(0)?;
-		let counter_clone = counter.clone();
-
-		evh.set_on_read(move |conn, _| -> Result<(), Error> {
-			info!("on read")?;
-			let mut counter = counter.wlock()?;
-			let guard = counter.guard()?;
-			**guard += 1;
-
-			if **guard == 2 {
-				conn.write_handle()?.write(b"response")?;
+		// "delay" is echoed after 50ms, "rate" is answered with 50KB at 10KB/s and "close" is
+		// answered with 20KB at 10KB/s followed by a close
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			if data.starts_with(b"delay") {
+				connection.set_write_delay_millis(50)?;
+				connection.write_handle()?.write(&data)?;
+			} else if data.starts_with(b"rate") {
+				connection.set_write_rate_limit_bytes_per_sec(10_000)?;
+				connection.write_handle()?.write(&[b'r'; 50_000])?;
+			} else if data.starts_with(b"close") {
+				connection.set_write_rate_limit_bytes_per_sec(10_000)?;
+				connection
+					.write_handle()?
+					.write_and_close(&[b'c'; 20_000])?;
 			}
-
 			Ok(())
 		})?;
-
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
 		evh.start()?;
 
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-
-		info!("connecting to addr {}", addr)?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
 		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
-
-		info!("adding connection now")?;
 		evh.add_server_connection(conn)?;
 
-		let mut strm = TcpStream::connect(addr.clone())?;
-		// write over 25 bytes
-		strm.write(b"012345678901234567890123456789")?;
+		// every round trip includes the delay
+		let mut strm = TcpStream::connect(&addr)?;
+		for _ in 0..3 {
+			let start = Instant::now();
+			strm.write_all(b"delay")?;
+			let mut buf = [0u8; 5];
+			strm.read_exact(&mut buf)?;
+			assert_eq!(&buf, b"delay");
+			assert!(start.elapsed().as_millis() >= 50);
+		}
 
-		let mut buf = [0u8; 100];
+		// 50KB at 10KB/s takes roughly 5 seconds
+		let mut strm = TcpStream::connect(&addr)?;
+		let start = Instant::now();
+		strm.write_all(b"rate")?;
+		let mut buf = vec![0u8; 50_000];
+		strm.read_exact(&mut buf)?;
+		let elapsed = start.elapsed().as_millis();
+		assert!(buf.iter().all(|b| *b == b'r'));
+		assert!(elapsed >= 3_500, "elapsed = {}", elapsed);
+		assert!(elapsed < 30_000, "elapsed = {}", elapsed);
+
+		// the connection is closed after all of the throttled data is written
+		let mut strm = TcpStream::connect(&addr)?;
+		let start = Instant::now();
+		strm.write_all(b"close")?;
+		let mut data = vec![];
+		strm.read_to_end(&mut data)?;
+		assert_eq!(data.len(), 20_000);
+		assert!(data.iter().all(|b| *b == b'c'));
+		assert!(start.elapsed().as_millis() >= 1_000);
+		Ok(())
+	}
 
-		let len = strm.read(&mut buf)?;
-		assert_eq!(len, 0);
+	// counts the callbacks. The counters are atomics so the test can read them after the
+	// handler has been moved into the EventHandler.
+	#[derive(Default)]
+	struct EchoHandler {
+		accepts: Arc<AtomicUsize>,
+		reads: Arc<AtomicUsize>,
+		bytes: Arc<AtomicUsize>,
+		closes: Arc<AtomicUsize>,
+	}
 
-		// we should have only gotten a single request (second one causes the allocation
-		// error)
-		assert_eq!(rlock!(counter_clone), 1);
+	impl ProtocolHandler for EchoHandler {
+		fn on_read(
+			&mut self,
+			connection: &mut Connection,
+			ctx: &mut Box<dyn UserContext + '_>,
+		) -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			self.reads.fetch_add(1, Ordering::SeqCst);
+			self.bytes.fetch_add(data.len(), Ordering::SeqCst);
+			connection.write_handle()?.write(&data)?;
+			Ok(())
+		}
+		fn on_accept(
+			&mut self,
+			_connection: &mut Connection,
+			_ctx: &mut Box<dyn UserContext + '_>,
+		) -> Result<(), Error> {
+			self.accepts.fetch_add(1, Ordering::SeqCst);
+			Ok(())
+		}
+		fn on_close(
+			&mut self,
+			_connection: &mut Connection,
+			_reason: &CloseReason,
+			_ctx: &mut Box<dyn UserContext + '_>,
+		) -> Result<(), Error> {
+			self.closes.fetch_add(1, Ordering::SeqCst);
+			Ok(())
+		}
+	}
 
-		// now that the connection is closed, we can continue processing because the slabs
-		// is freed up.
+	#[test]
+	fn test_evh_protocol_handler() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh: Box<
+			dyn EventHandler<NoOnRead, NoOnAccept, NoOnClose, NoOnHousekeeper, NoOnPanic>
+				+ Send
+				+ Sync,
+		> = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(2),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(5000)
+		)?;
+		let handler = EchoHandler::default();
+		let accepts = handler.accepts.clone();
+		let reads = handler.reads.clone();
+		let bytes = handler.bytes.clone();
+		let closes = handler.closes.clone();
+		evh.set_handler(Box::new(handler))?;
+		evh.start()?;
 
-		// open two connections
-		let mut strm1 = TcpStream::connect(addr.clone())?;
-		let mut strm2 = TcpStream::connect(addr)?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
 
-		// write less than the single slab
-		strm1.write(b"test")?;
-		strm2.write(b"test")?;
+		for _ in 0..3 {
+			let mut strm = TcpStream::connect(&addr)?;
+			strm.write_all(b"hi")?;
+			let mut buf = [0u8; 2];
+			strm.read_exact(&mut buf)?;
+			assert_eq!(&buf, b"hi");
+		}
 
-		// stream 2 gets closed, but 1 is ok
-		let len = strm2.read(&mut buf)?;
-		assert_eq!(len, 0);
-		assert_eq!(strm1.read(&mut buf)?, 8);
-		assert_eq!(&buf[0..8], b"response");
-		assert_eq!(rlock!(counter_clone), 2);
+		while closes.load(Ordering::SeqCst) < 3 {
+			sleep(Duration::from_millis(1));
+		}
+		assert_eq!(accepts.load(Ordering::SeqCst), 3);
+		assert_eq!(reads.load(Ordering::SeqCst), 3);
+		assert_eq!(bytes.load(Ordering::SeqCst), 6);
+
+		// a handler may not be used along with the closure handlers
+		let mut evh = evh!(EvhTimeout(100), EvhThreads(1))?;
+		evh.set_handler(Box::new(EchoHandler::default()))?;
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		let e = evh.start().unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_no_clear() -> Result<(), Error> {
+	fn test_evh_read_eof() -> Result<(), Error> {
 		let test_info = test_info!()?;
-
-		let mut evh = evh_oro!(
+		let mut evh = evh!(
 			Debug(false),
-			EvhTimeout(u16::MAX),
+			EvhTimeout(100),
 			EvhThreads(1),
-			EvhReadSlabSize(25),
-			EvhReadSlabCount(10)
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(5000)
 		)?;
-
-		evh.set_on_read(move |conn, _| -> Result<(), Error> {
-			info!("on read")?;
-			conn.write_handle()?.write(b"response")?;
-
+		let closes = lock_box!(vec![])?;
+		let mut closes_clone = closes.clone();
+		let eofs = lock_box!(0)?;
+		let mut eofs_clone = eofs.clone();
+
+		// the data is left in the slabs and the response is sent once the request is complete
+		evh.set_on_read(move |connection, _ctx| -> Result<(), Error> {
+			assert!(!connection.is_read_eof());
 			Ok(())
 		})?;
-
+		evh.set_on_read_eof(Box::new(move |connection, ctx| -> Result<(), Error> {
+			assert!(connection.is_read_eof());
+			wlock!(eofs_clone) += 1;
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			let mut wh = connection.write_handle()?;
+			wh.write(format!("len={}", data.len()).as_bytes())?;
+			wh.close()?;
+			Ok(())
+		}))?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |connection, reason, _ctx| -> Result<(), Error> {
+			wlock!(closes_clone).push((connection.id(), format!("{:?}", reason)));
+			Ok(())
+		})?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
 		evh.start()?;
 
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-
-		info!("connecting to addr {}", addr)?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
 		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
-
-		info!("adding connection now")?;
 		evh.add_server_connection(conn)?;
 
-		let mut strm = TcpStream::connect(addr.clone())?;
-		// write over 25 bytes
-		strm.write(b"0123456789012345678")?;
+		let mut strm = TcpStream::connect(&addr)?;
+		strm.write_all(&[b'x'; 250])?;
+		strm.shutdown(Shutdown::Write)?;
+		let mut response = vec![];
+		strm.read_to_end(&mut response)?;
+		assert_eq!(&response[..], b"len=250");
 
-		let mut buf = [0u8; 100];
-		let len = strm.read(&mut buf)?;
+		while rlock!(closes).len() == 0 {
+			sleep(Duration::from_millis(1));
+		}
+		sleep(Duration::from_millis(100));
+		assert_eq!(rlock!(closes).len(), 1);
+		assert_eq!(rlock!(closes)[0].1, "LocalClose");
+		assert_eq!(rlock!(eofs), 1);
 
-		assert_eq!(len, 8);
-		assert_eq!(&buf[0..8], b"response");
+		Ok(())
+	}
 
-		strm.write(b"hi")?;
-		let mut buf = [0u8; 100];
-		let len = strm.read(&mut buf)?;
+	#[test]
+	fn test_evh_register_key() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(2),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(5000)
+		)?;
+		let mut controller = evh.controller()?;
+		let mut controller_clone = controller.clone();
 
-		assert_eq!(len, 8);
-		assert_eq!(&buf[0..8], b"response");
+		// every accepted connection registers key 1, so it moves to the newest connection
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			controller_clone.register_key(connection.id(), 1)
+		})?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
 
-		sleep(Duration::from_millis(QA_SLEEP));
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
+
+		let mut a = TcpStream::connect(&addr)?;
+		while controller.write_handle_for_key(1)?.is_none() {
+			sleep(Duration::from_millis(1));
+		}
 
+		// the write handle is looked up and used on another thread
+		let controller_clone = controller.clone();
+		let a_id = spawn(move || -> Result<u128, Error> {
+			let mut wh = controller_clone.write_handle_for_key(1)?.unwrap();
+			wh.write(b"hello")?;
+			Ok(wh.id())
+		})
+		.join()
+		.unwrap()?;
+		let mut buf = [0u8; 5];
+		a.read_exact(&mut buf)?;
+		assert_eq!(&buf, b"hello");
+
+		let mut b = TcpStream::connect(&addr)?;
+		while controller.write_handle_for_key(1)?.unwrap().id() == a_id {
+			sleep(Duration::from_millis(1));
+		}
+		let mut wh = controller.write_handle_for_key(1)?.unwrap();
+		let b_id = wh.id();
+		wh.write(b"world")?;
+		b.read_exact(&mut buf)?;
+		assert_eq!(&buf, b"world");
+
+		// keys may be unregistered and registered from any thread
+		controller.unregister_key(1)?;
+		assert!(controller.write_handle_for_key(1)?.is_none());
+		controller.register_key(b_id, 2)?;
+		controller.register_key(a_id, 3)?;
+		assert_eq!(controller.write_handle_for_key(2)?.unwrap().id(), b_id);
+		assert_eq!(controller.write_handle_for_key(3)?.unwrap().id(), a_id);
+		let e = controller.register_key(u128::MAX, 4).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::IllegalArgument(_)));
+
+		// the keys are removed when the connections are closed
+		drop(b);
+		while controller.write_handle_for_key(2)?.is_some() {
+			sleep(Duration::from_millis(1));
+		}
+		assert!(controller.write_handle_for_key(3)?.is_some());
+		drop(a);
+		while controller.write_handle_for_key(3)?.is_some() {
+			sleep(Duration::from_millis(1));
+		}
+		let e = controller.register_key(a_id, 3).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::IllegalArgument(_)));
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_controller_stop() -> Result<(), Error> {
-		let mut client;
-		let mut controller;
-		let mut buf = [0u8; 100];
-		{
-			let test_info = test_info!()?;
-			let mut evh = evh_oro!(
-				Debug(false),
-				EvhTimeout(100),
-				EvhStatsUpdateMillis(try_into!(QA_SLEEP)?),
-				EvhThreads(1),
-				EvhReadSlabSize(100)
-			)?;
+	fn test_evh_register_key_strict() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(
+			Debug(false),
+			EvhTimeout(100),
+			EvhThreads(1),
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(5000),
+			EvhStrictKeys(true)
+		)?;
+		let ids = lock_box!(vec![])?;
+		let mut ids_clone = ids.clone();
 
-			evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-				info!("onRead")?;
-				let mut data: Vec<u8> = vec![];
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			wlock!(ids_clone).push(connection.id());
+			Ok(())
+		})?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
 
-				loop {
-					let next_chunk = ctx.next_chunk(connection)?;
-					cbreak!(next_chunk.is_none());
-					let next_chunk = next_chunk.unwrap();
-					data.extend(next_chunk.data());
-				}
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
 
-				let dstring = from_utf8(&data)?;
-				info!("data[{}]='{}'", connection.id(), dstring,)?;
+		let _a = TcpStream::connect(&addr)?;
+		let _b = TcpStream::connect(&addr)?;
+		while rlock!(ids).len() < 2 {
+			sleep(Duration::from_millis(1));
+		}
+		let ids: Vec<u128> = rlock!(ids).clone();
+		let (a_id, b_id) = (ids[0], ids[1]);
+
+		// a key registered to another connection is an error, registering it again is not
+		evh.register_key(a_id, 5)?;
+		let e = evh.register_key(b_id, 5).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::IllegalState(_)));
+		evh.register_key(a_id, 5)?;
+		assert_eq!(evh.write_handle_for_key(5)?.unwrap().id(), a_id);
+		Ok(())
+	}
 
-				assert_eq!(dstring, "hi");
-				let mut wh = connection.write_handle()?;
-				wh.write(b"test")?;
-				ctx.clear_all(connection)?;
-				Ok(())
-			})?;
+	#[test]
+	fn test_evh_loopback_echo() -> Result<(), Error> {
+		let mut evh1 = evh!(EvhTimeout(10), EvhThreads(2), EvhReadSlabSize(100))?;
+		let mut evh2 = evh!(EvhTimeout(10), EvhThreads(2), EvhReadSlabSize(100))?;
+		let mut received = lock_box!(Vec::<u8>::new())?;
+		let received_clone = received.clone();
+		let mut reasons = lock_box!(vec![])?;
+		let mut reasons1 = reasons.clone();
+		let reasons_clone = reasons.clone();
+
+		// evh1 echoes the data it receives
+		evh1.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			let mut wh = connection.write_handle()?;
+			if data.ends_with(b"quit") {
+				wh.write_and_close(&data)?;
+			} else {
+				wh.write(&data)?;
+			}
+			Ok(())
+		})?;
+		evh1.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh1.set_on_close(move |_connection, reason, _ctx| -> Result<(), Error> {
+			wlock!(reasons1).push(format!("server:{:?}", reason));
+			Ok(())
+		})?;
+		evh1.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh1.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh1.start()?;
 
-			evh.start()?;
-			controller = evh.controller()?;
-			let port = test_info.port();
-			let addr = format!("127.0.0.1:{}", port);
-			let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
-			evh.add_server_connection(conn)?;
+		evh2.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				wlock!(received).extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+		evh2.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh2.set_on_close(move |_connection, reason, _ctx| -> Result<(), Error> {
+			wlock!(reasons).push(format!("client:{:?}", reason));
+			Ok(())
+		})?;
+		evh2.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh2.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh2.start()?;
+
+		let (client, server) = EvhBuilder::build_loopback_pair()?;
+		// the synthetic handles can't be exported or confused with a socket
+		assert_ne!(client.handle(), server.handle());
+		evh1.add_imported_connection(server)?;
+		let mut wh = evh2.add_client_connection(client)?;
+
+		let mut expected = vec![];
+		for i in 0..100 {
+			let msg = format!("msg{}", i);
+			wh.write(msg.as_bytes())?;
+			expected.extend(msg.as_bytes());
+		}
+		let mut count = 0;
+		while rlock!(received_clone) != expected {
+			count += 1;
+			assert!(count < 1_000);
+			sleep(Duration::from_millis(1));
+		}
+
+		// write_and_close on the server side delivers the data before the client sees the close
+		wh.write(b"quit")?;
+		expected.extend(b"quit");
+		let mut count = 0;
+		while rlock!(reasons_clone).len() < 2 {
+			count += 1;
+			assert!(count < 1_000);
+			sleep(Duration::from_millis(1));
+		}
+		assert_eq!(rlock!(received_clone), expected);
+		let mut reasons = rlock!(reasons_clone).clone();
+		reasons.sort();
+		assert_eq!(reasons, vec!["client:PeerClosed", "server:LocalClose"]);
+
+		// writes to a closed connection fail like they do for a TCP connection
+		assert!(wh.write(b"late").is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_loopback_backpressure() -> Result<(), Error> {
+		let mut evh = evh!(EvhTimeout(10), EvhThreads(2), EvhReadSlabSize(100))?;
+		let mut received = lock_box!(Vec::<u8>::new())?;
+		let received_clone = received.clone();
+		let mut reasons = lock_box!(vec![])?;
+		let reasons_clone = reasons.clone();
+
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				wlock!(received).extend(next_chunk.unwrap().data());
+			}
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, reason, _ctx| -> Result<(), Error> {
+			wlock!(reasons).push(format!("{:?}", reason));
+			Ok(())
+		})?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
 
-			client = TcpStream::connect(addr)?;
-			client.write(b"hi")?;
+		assert!(EvhBuilder::build_loopback_pair_with_buffer_size(0).is_err());
+		let (client, server) = EvhBuilder::build_loopback_pair_with_buffer_size(16)?;
+		let inbound = server.loopback.as_ref().unwrap().inbound.clone();
+		let mut wh = evh.add_client_connection(client)?;
+
+		// the server side is not registered yet, so only the first 16 bytes are buffered and
+		// the rest is queued by the writer
+		let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+		wh.write(&data)?;
+		wh.write(b"end")?;
+		assert_eq!(inbound.lock().unwrap().data.len(), 16);
+		sleep(Duration::from_millis(50));
+		assert_eq!(inbound.lock().unwrap().data.len(), 16);
+
+		// once the server side reads, the writer resumes until everything is delivered
+		evh.add_imported_connection(server)?;
+		let expected = [&data[..], b"end"].concat();
+		let mut count = 0;
+		while rlock!(received_clone).len() < expected.len() {
+			count += 1;
+			assert!(count < 5_000);
+			sleep(Duration::from_millis(1));
+		}
+		assert_eq!(rlock!(received_clone), expected);
+		assert!(inbound.lock().unwrap().data.len() <= 16);
 
-			let len = client.read(&mut buf)?;
-			assert_eq!(&buf[0..len], b"test");
+		// closing the client side is seen as a peer close by the server side
+		wh.close()?;
+		let mut count = 0;
+		while rlock!(reasons_clone).len() < 2 {
+			count += 1;
+			assert!(count < 1_000);
+			sleep(Duration::from_millis(1));
 		}
+		let mut reasons = rlock!(reasons_clone).clone();
+		reasons.sort();
+		assert_eq!(reasons, vec!["LocalClose", "PeerClosed"]);
 
-		client.write(b"hi")?;
-		let len = client.read(&mut buf)?;
-		assert_eq!(&buf[0..len], b"test");
+		Ok(())
+	}
 
-		let stats = controller.wait_for_stats()?;
-		info!("stats={:?}", stats)?;
-		assert_eq!(stats.accepts, 1);
-		assert_eq!(stats.reads, 2);
-		assert_eq!(stats.bytes_read, 4);
+	#[test]
+	fn test_evh_duplicate_registration() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh_oro!(EvhThreads(1), EvhTimeout(100))?;
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
 
-		controller.stop()?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
+		let handle = conn.handle();
+		let id = conn.id();
+		evh.add_server_connection(conn)?;
 
-		// now it's closed
-		let len = client.read(&mut buf)?;
-		assert_eq!(len, 0);
+		// a second connection for the same handle is rejected
+		let dup = Connection::new(
+			handle,
+			None,
+			None,
+			ConnectionType::Server,
+			DebugInfo::default(),
+			id + 1,
+			None,
+		)?;
+		let e = evh.add_server_connection(dup).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::AlreadyRegistered(_)));
+		let text = e.to_string();
+		assert!(text.contains(&format!("handle {} ", handle)));
+		assert!(text.contains(&format!("connection id {}", id)));
+
+		// the existing registration still accepts connections
+		let mut strm = TcpStream::connect(&addr)?;
+		strm.write_all(b"test")?;
+
+		let conn = EvhBuilder::build_client_connection("127.0.0.1", test_info.port())?;
+		let handle = conn.handle();
+		let id = conn.id();
+		let mut wh = evh.add_client_connection(conn)?;
+		let dup = Connection::new(
+			handle,
+			None,
+			None,
+			ConnectionType::Client,
+			DebugInfo::default(),
+			id + 1,
+			None,
+		)?;
+		let e = evh.add_client_connection(dup).err().unwrap();
+		assert!(matches!(e.kind(), ErrorKind::AlreadyRegistered(_)));
+		assert!(e.to_string().contains(&format!("connection id {}", id)));
+
+		// the handle was not closed by the rejected registration
+		wh.write(b"still open")?;
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_resources() -> Result<(), Error> {
-		// this test doesn't currently do assertions, but it can be used to monitor resources
-		// like file descriptors. Change `target` to a higher value and increase sleep at the
-		// end to be able to look at usage
+	fn test_evh_close_write_race() -> Result<(), Error> {
 		let test_info = test_info!()?;
-		let mut loop_count = 0;
-		let target = 1;
-		loop {
-			info!("create evh {}", loop_count)?;
-			let mut evh = evh_oro!(
-				Debug(false),
-				EvhTimeout(u16::MAX),
-				EvhThreads(5),
-				EvhReadSlabSize(100)
-			)?;
+		let listener = TcpListener::bind(format!("127.0.0.1:{}", test_info.port()))?;
+		let iterations = 2_000u32;
+
+		// each accepted stream must only receive the tag of the iteration that opened it
+		let checked = lock_box!(0u32)?;
+		let checked_clone = checked.clone();
+		let server = thread::spawn(move || -> Result<(), Error> {
+			for _ in 0..iterations {
+				let (mut strm, _) = listener.accept()?;
+				let mut checked = checked.clone();
+				thread::spawn(move || -> Result<(), Error> {
+					let mut data = vec![];
+					strm.read_to_end(&mut data)?;
+					assert_eq!(data.len() % 4, 0);
+					for tag in data.chunks(4) {
+						assert_eq!(tag, &data[0..4]);
+					}
+					wlock!(checked) += 1;
+					Ok(())
+				});
+			}
+			Ok(())
+		});
 
-			let (tx, rx) = test_info.sync_channel();
-			evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-				let mut data: Vec<u8> = vec![];
+		let mut evh = evh_oro!(EvhThreads(2), EvhTimeout(100))?;
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			ctx.clear_all(connection)?;
+			Ok(())
+		})?;
+		evh.start()?;
 
+		for i in 0..iterations {
+			let conn = EvhBuilder::build_client_connection("127.0.0.1", test_info.port())?;
+			let mut wh = evh.add_client_connection(conn)?;
+			let mut writer = wh.clone();
+			let tag = i.to_be_bytes();
+			let jh = thread::spawn(move || -> Result<(), Error> {
 				loop {
-					let next_chunk = ctx.next_chunk(connection)?;
-					cbreak!(next_chunk.is_none());
-					let next_chunk = next_chunk.unwrap();
-					data.extend(next_chunk.data());
+					match writer.write(&tag) {
+						Ok(_) => thread::yield_now(),
+						Err(e) => {
+							assert!(matches!(e.kind(), ErrorKind::ConnectionClosed(_)));
+							return Ok(());
+						}
+					}
 				}
+			});
+			wh.write(&tag)?;
+			wh.close()?;
+			jh.join().unwrap()?;
+			let e = wh.write(&tag).unwrap_err();
+			assert!(matches!(e.kind(), ErrorKind::ConnectionClosed(_)));
+		}
 
-				let _dstring = from_utf8(&data)?;
-				tx.send(())?;
-
-				ctx.clear_all(connection)?;
-				Ok(())
-			})?;
-			evh.start()?;
-			let port = test_info.port();
-			let addr = format!("127.0.0.1:{}", port);
-			let conn = EvhBuilder::build_server_connection(&addr, 100)?;
-			evh.add_server_connection(conn)?;
-			let conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-			let mut wh = evh.add_client_connection(conn2)?;
-			wh.write(b"01234567890123456789")?;
-			rx.recv()?;
-
-			loop_count += 1;
-
-			if loop_count == target {
-				break;
-			}
-			//sleep(Duration::from_millis(100));
+		server.join().unwrap()?;
+		let mut count = 0;
+		while rlock!(checked_clone) < iterations {
+			count += 1;
+			assert!(count < 10_000);
+			sleep(Duration::from_millis(1));
 		}
 
-		info!("sleep")?;
-		//sleep(Duration::from_millis(120_000));
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_origin_id() -> Result<(), Error> {
+	fn test_evh_sensitive_connection() -> Result<(), Error> {
 		let test_info = test_info!()?;
-		let mut evh = evh_oro!(
-			Debug(true),
-			EvhTimeout(u16::MAX),
-			EvhThreads(1),
-			EvhReadSlabSize(100)
-		)?;
+		let mut evh = evh!(EvhThreads(1), EvhTimeout(100))?;
 
-		let mut recv_msg = lock_box!(false)?;
-		let mut origin_assertion_ok = lock_box!(false)?;
-		let origin_assertion_ok_clone = origin_assertion_ok.clone();
-		let recv_msg_clone = recv_msg.clone();
-		let (tx, rx) = test_info.sync_channel();
-		let (tx2, rx2) = test_info.sync_channel();
+		// the first connection accepted is sensitive
+		let accepted = lock_box!(0usize)?;
+		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
+			let mut accepted = accepted.clone();
+			if rlock!(accepted) == 0 {
+				connection.set_sensitive(true)?;
+				assert!(connection.enable_capture("/tmp").is_err());
+			}
+			wlock!(accepted) += 1;
+			Ok(())
+		})?;
 
+		// whether the payload is still in the raw slab memory after clear_all
+		let present = lock_box!(vec![])?;
+		let present_clone = present.clone();
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			info!("onRead")?;
-			let mut data: Vec<u8> = vec![];
-
+			let mut present = present.clone();
+			let mut data = vec![];
+			let mut slab_ids = vec![];
 			loop {
 				let next_chunk = ctx.next_chunk(connection)?;
 				cbreak!(next_chunk.is_none());
 				let next_chunk = next_chunk.unwrap();
+				slab_ids.push(next_chunk.slab_id());
 				data.extend(next_chunk.data());
 			}
-
-			let dstring = from_utf8(&data)?;
-			info!(
-				"data[{}]='{}', origin_id={}",
-				connection.id(),
-				dstring,
-				connection.origin_id()
-			)?;
-
-			assert_eq!(dstring, "hi");
-
-			if !rlock!(recv_msg) {
-				// origin id should be different from id (accepted connection)
-				assert_ne!(connection.id(), connection.origin_id());
-				// send back once
-				let mut wh = connection.write_handle()?;
-				wh.write(b"hi")?;
-			} else {
-				// this is the client, origin id should be equal to id
-				assert_eq!(connection.id(), connection.origin_id());
-				wlock!(origin_assertion_ok) = true;
-				tx2.send(())?;
+			if data.len() < 14 {
+				return Ok(());
 			}
-
-			wlock!(recv_msg) = true;
-			tx.send(())?;
+			assert_eq!(&data, b"secret-payload");
+			connection.write_handle()?.write(b"secret-reply")?;
 			ctx.clear_all(connection)?;
+
+			let mut found = false;
+			for slab_id in slab_ids {
+				let raw = ctx.raw_read_slab(slab_id)?;
+				if raw.windows(data.len()).any(|w| w == &data[..]) {
+					found = true;
+				}
+			}
+			wlock!(present).push(found);
 			Ok(())
 		})?;
-
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
 		evh.start()?;
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 100)?;
 		evh.add_server_connection(conn)?;
 
-		let conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		let mut wh = evh.add_client_connection(conn2)?;
-		wh.write(b"hi")?;
+		let mut expected = vec![];
+		for sensitive in [true, false] {
+			let mut strm = TcpStream::connect(&addr)?;
+			strm.write_all(b"secret-payload")?;
+			let mut buf = [0u8; 12];
+			strm.read_exact(&mut buf)?;
+			assert_eq!(&buf, b"secret-reply");
+			expected.push(!sensitive);
+
+			let mut count = 0;
+			while rlock!(present_clone).len() < expected.len() {
+				count += 1;
+				assert!(count < 10_000);
+				sleep(Duration::from_millis(1));
+			}
+		}
 
-		rx.recv()?;
-		assert!(rlock!(recv_msg_clone));
-		rx2.recv()?;
-		assert!(rlock!(origin_assertion_ok_clone));
+		// the sensitive connection's slab was zeroized, the other one is intact until reuse
+		assert_eq!(rlock!(present_clone), expected);
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_pending_write_on_close() -> Result<(), Error> {
-		let test_info = test_info!()?;
-		let mut evh = evh!(
-			Debug(false),
-			EvhTimeout(100),
-			EvhThreads(1),
-			EvhReadSlabSize(250),
-			EvhReadSlabCount(100_000),
-			EvhStatsUpdateMillis(5000),
-			EvhOutOfSlabsMessage("12345".to_string())
-		)?;
-
-		let debug_info = DebugInfo {
-			pending: lock_box!(true)?,
-			..Default::default()
-		};
-		evh.set_debug_info(debug_info)?;
-
-		let mut client_id = lock_box!(0)?;
+	fn test_zeroize_buffers() -> Result<(), Error> {
+		let mut buf = b"0123456789".to_vec();
+		drain_zeroize(&mut buf, 4);
+		assert_eq!(&buf, b"456789");
+		// the bytes past the new length are zeroed
+		let spare = unsafe { std::slice::from_raw_parts(buf.as_ptr(), 10) };
+		assert_eq!(&spare[6..], &[0u8; 4]);
+
+		let mut buf = Vec::with_capacity(2);
+		extend_zeroize(&mut buf, b"ab");
+		extend_zeroize(&mut buf, b"cdefgh");
+		assert_eq!(&buf, b"abcdefgh");
+		extend_zeroize(&mut buf, b"");
+		assert_eq!(&buf, b"abcdefgh");
+		drain_zeroize(&mut buf, 100);
+		assert!(buf.is_empty());
+
+		let mut buf = b"secret".to_vec();
+		zeroize(&mut buf);
+		assert_eq!(&buf, &[0u8; 6]);
+		Ok(())
+	}
 
-		let mut client_recv = lock_box!(false)?;
-		let mut server_recv = lock_box!(false)?;
-		let client_recv_clone = client_recv.clone();
-		let server_recv_clone = server_recv.clone();
-		let (tx, rx) = test_info.sync_channel();
-		let client_id_clone = client_id.clone();
+	type NoOnEvh = Box<
+		dyn EventHandler<NoOnRead, NoOnAccept, NoOnClose, NoOnHousekeeper, NoOnPanic> + Send + Sync,
+	>;
 
-		let message = "0123456789";
-		let ret_message = [b'p'; 100_000];
-		let ret_message = from_utf8(&ret_message).unwrap().to_string();
+	// set the callbacks selected by the bits of `mask` (on_read, on_accept, on_close, on_panic,
+	// on_housekeeper)
+	fn set_callbacks(evh: &mut NoOnEvh, mask: usize) -> Result<(), Error> {
+		if mask & 1 != 0 {
+			evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		}
+		if mask & 2 != 0 {
+			evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		}
+		if mask & 4 != 0 {
+			evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		}
+		if mask & 8 != 0 {
+			evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		}
+		if mask & 16 != 0 {
+			evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		}
+		Ok(())
+	}
 
+	#[test]
+	fn test_evh_reassign_connection() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut evh = evh!(EvhTimeout(10), EvhThreads(4), EvhStatsUpdateMillis(50))?;
+
+		// key -> the threads that processed data for a connection with that key
+		let threads: Box<dyn LockBox<HashMap<usize, HashSet<thread::ThreadId>>>> =
+			lock_box!(HashMap::new())?;
+		let threads_clone = threads.clone();
+		// client index -> a write handle taken before the connection was moved
+		let handles: Box<dyn LockBox<HashMap<usize, WriteHandle>>> = lock_box!(HashMap::new())?;
+		let handles_clone = handles.clone();
+
+		// "key:K:I" declares the key K of client I and moves the connection to thread K.
+		// "data:K" records the thread that processed it.
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			info!("onRead")?;
 			let mut data: Vec<u8> = vec![];
-
 			loop {
 				let next_chunk = ctx.next_chunk(connection)?;
 				cbreak!(next_chunk.is_none());
-				let next_chunk = next_chunk.unwrap();
-				data.extend(next_chunk.data());
+				data.extend(next_chunk.unwrap().data());
 			}
-
-			let dstring = from_utf8(&data)?;
-
-			if dstring != message && rlock!(client_id_clone) != connection.id() {
-				return Ok(());
-			} else if dstring != ret_message && rlock!(client_id_clone) == connection.id() {
-				return Ok(());
+			ctx.clear_all(connection)?;
+			let text = from_utf8(&data).unwrap().to_string();
+			let parts: Vec<&str> = text.split(':').collect();
+			let key: usize = parts[1].parse().unwrap();
+			let mut wh = connection.write_handle()?;
+			if parts[0] == "key" {
+				let index: usize = parts[2].parse().unwrap();
+				let mut handles = handles_clone.clone();
+				wlock!(handles).insert(index, wh.clone());
+				ctx.reassign_connection(connection.id(), key)?;
+				wh.write(b"ok")?;
 			} else {
-				info!("equal")?;
+				let mut threads = threads_clone.clone();
+				let id = thread::current().id();
+				wlock!(threads).entry(key).or_default().insert(id);
+				wh.write(b"done")?;
 			}
+			Ok(())
+		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
 
-			let mut wh = connection.write_handle()?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
 
-			// respond
-			if rlock!(client_id_clone) != connection.id() {
-				info!("wh write and close")?;
-				wh.write_and_close(ret_message.as_bytes())?;
-				wlock!(server_recv) = true;
-			} else {
-				wlock!(client_recv) = true;
+		// 8 keys so that keys k and k + 4 share a thread. Hints of 4 or more wrap.
+		let mut strms = vec![];
+		for i in 0..40 {
+			let key = i / 5;
+			let mut strm = TcpStream::connect(&addr)?;
+			strm.write_all(format!("key:{}:{}", key, i).as_bytes())?;
+			let mut buf = [0u8; 2];
+			strm.read_exact(&mut buf)?;
+			assert_eq!(&buf, b"ok");
+			strms.push((key, strm));
+		}
+		for _ in 0..3 {
+			for (key, strm) in &mut strms {
+				strm.write_all(format!("data:{}", key).as_bytes())?;
+				let mut buf = [0u8; 4];
+				strm.read_exact(&mut buf)?;
+				assert_eq!(&buf, b"done");
 			}
+		}
 
-			if rlock!(client_recv) && rlock!(server_recv) {
-				tx.send(())?;
+		{
+			let threads = threads.rlock()?;
+			let guard = threads.guard()?;
+			assert_eq!(guard.len(), 8);
+			for key in 0..4 {
+				assert_eq!(guard[&key].len(), 1);
+				assert_eq!(guard[&key], guard[&(key + 4)]);
+			}
+			let distinct: HashSet<_> = guard.values().flatten().collect();
+			assert_eq!(distinct.len(), 4);
+		}
+
+		// handles taken before the move write through the new thread
+		for (i, (_, strm)) in strms.iter_mut().enumerate() {
+			let mut wh = rlock!(handles)[&i].clone();
+			wh.write(b"late")?;
+			let mut buf = [0u8; 4];
+			strm.read_exact(&mut buf)?;
+			assert_eq!(&buf, b"late");
+		}
+
+		// each thread has the connections of two keys
+		let mut reassignments = 0;
+		let mut count = 0;
+		loop {
+			let stats = evh.wait_for_stats()?;
+			reassignments += stats.reassignments;
+			cbreak!(stats.thread_connections == vec![10, 10, 10, 10]);
+			count += 1;
+			assert!(count < 100);
+		}
+		assert!(reassignments > 0 && reassignments <= 40);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_memory_budget() -> Result<(), Error> {
+		let test_info = test_info!()?;
+
+		// a budget of 0 or one that does not cover the read slabs is an error
+		match evh_oro!(EvhMemoryBudgetBytes(0)) {
+			Ok(mut evh) => {
+				evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+				return Err(err!(ErrKind::Test, "expected a configuration error"));
 			}
+			Err(e) => assert!(matches!(e.kind(), ErrorKind::Configuration(_))),
+		}
+		let mut evh = evh_oro!(EvhMemoryBudgetBytes(1_000))?;
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		assert!(matches!(
+			evh.start().unwrap_err().kind(),
+			ErrorKind::Configuration(_)
+		));
+
+		// find the fixed cost with a budget that is not reached
+		let mut evh = evh_oro!(EvhThreads(1), EvhMemoryBudgetBytes(usize::MAX - 1))?;
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+		let stats = evh.wait_for_stats()?;
+		let fixed = stats.memory_used;
+		assert!(fixed > 0);
+		assert_eq!(stats.memory_pressure, MemoryPressure::None);
+
+		let mut evh = evh!(
+			EvhTimeout(10),
+			EvhThreads(1),
+			EvhHouseKeeperFrequencyMillis(500),
+			EvhStatsUpdateMillis(50),
+			EvhSendBufferSize(16_384),
+			EvhMemoryBudgetBytes(fixed + 1_000_000)
+		)?;
 
+		// echo the data and queue 500,000 bytes for each 'b' so that clients that do not
+		// read build up a large write backlog
+		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
+			let mut data: Vec<u8> = vec![];
+			loop {
+				let next_chunk = ctx.next_chunk(connection)?;
+				cbreak!(next_chunk.is_none());
+				data.extend(next_chunk.unwrap().data());
+			}
 			ctx.clear_all(connection)?;
+			let mut wh = connection.write_handle()?;
+			wh.write(&data)?;
+			for _ in data.iter().filter(|b| **b == b'b') {
+				wh.write(&[b'x'; 500_000])?;
+			}
 			Ok(())
 		})?;
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
 
-		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
-			info!(
-				"onAccept: handle={},id={}",
-				connection.handle(),
-				connection.id()
-			)?;
-			Ok(())
-		})?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
+		evh.add_server_connection(conn)?;
 
-		evh.set_on_close(move |_connection, _ctx| -> Result<(), Error> {
-			info!("onClose")?;
-			Ok(())
-		})?;
+		// two clients that each leave about 1.5mb queued
+		let mut hog1 = TcpStream::connect(&addr)?;
+		hog1.write_all(b"bbb")?;
+		let mut hog2 = TcpStream::connect(&addr)?;
+		hog2.write_all(b"bbb")?;
 
-		evh.set_on_housekeeper(move |_ctx| -> Result<(), Error> {
-			info!("onHousekeeper")?;
-			Ok(())
-		})?;
+		let mut pressure_events = 0;
+		let mut reads_paused = 0;
+		let mut count = 0;
+		loop {
+			let stats = evh.wait_for_stats()?;
+			pressure_events += stats.memory_pressure_events;
+			reads_paused += stats.memory_reads_paused;
+			cbreak!(stats.memory_pressure >= MemoryPressure::RejectAccepts);
+			count += 1;
+			assert!(count < 400);
+		}
+		assert!(pressure_events >= 2);
+		assert!(reads_paused > 0);
+
+		// new connections are closed while the pressure is at least RejectAccepts
+		let mut rejected = TcpStream::connect(&addr)?;
+		rejected.set_read_timeout(Some(Duration::from_millis(5_000)))?;
+		let mut buf = [0u8; 5];
+		assert!(matches!(rejected.read(&mut buf), Ok(0) | Err(_)));
+
+		// the hogs are closed until the memory is within the budget again
+		let mut accepts_rejected = 0;
+		let mut closed = 0;
+		let mut count = 0;
+		let stats = loop {
+			let stats = evh.wait_for_stats()?;
+			accepts_rejected += stats.memory_accepts_rejected;
+			closed += stats.close_reasons.memory_pressure;
+			if stats.memory_recoveries > 0 {
+				break stats;
+			}
+			count += 1;
+			assert!(count < 400);
+		};
+		assert!(accepts_rejected > 0);
+		assert!(closed > 0);
+		assert_eq!(stats.memory_pressure, MemoryPressure::None);
+		assert!(stats.memory_used <= fixed + 1_000_000);
+		assert_eq!(stats.memory_budget, fixed + 1_000_000);
+
+		let mut strm = TcpStream::connect(&addr)?;
+		strm.write_all(b"hello")?;
+		strm.read_exact(&mut buf)?;
+		assert_eq!(&buf, b"hello");
+		Ok(())
+	}
 
-		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> {
-			info!("onPanic")?;
-			Ok(())
-		})?;
+	#[test]
+	fn test_evh_missing_callbacks() -> Result<(), Error> {
+		let names = [
+			"on_read",
+			"on_accept",
+			"on_close",
+			"on_panic",
+			"on_housekeeper",
+		];
+		for mask in 0..32 {
+			let mut evh: NoOnEvh = evh!(EvhThreads(1), EvhTimeout(10))?;
+			set_callbacks(&mut evh, mask)?;
+			match evh.start() {
+				Ok(_) => assert_eq!(mask, 31),
+				Err(e) => match e.kind() {
+					ErrorKind::Configuration(text) => {
+						// every missing callback is named, the others are not
+						for (i, name) in names.iter().enumerate() {
+							let listed = text.split([':', ',']).any(|s| s.trim() == *name);
+							assert_eq!(listed, mask & (1 << i) == 0, "{} {}", mask, text);
+						}
+					}
+					_ => panic!("unexpected error: {}", e),
+				},
+			}
+		}
 
+		// on_data_chunk replaces on_read
+		let mut evh: NoOnEvh = evh!(EvhThreads(1), EvhTimeout(10))?;
+		set_callbacks(&mut evh, 30)?;
+		evh.set_on_data_chunk(Box::new(move |_connection, _chunk| Ok(ChunkAction::Free)))?;
 		evh.start()?;
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-		info!("connecting to addr {}", addr)?;
-		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 
-		info!("adding connection now")?;
-		evh.add_server_connection(conn)?;
-		let mut conn2 = EvhBuilder::build_client_connection("127.0.0.1", port)?;
-		conn2.debug_info = DebugInfo {
-			pending: lock_box!(true)?,
-			..Default::default()
-		};
-		wlock!(client_id) = conn2.id();
+		Ok(())
+	}
 
-		let mut wh = evh.add_client_connection(conn2)?;
-		wh.write(message.as_bytes())?;
+	#[test]
+	fn test_evh_set_callback_after_start() -> Result<(), Error> {
+		let mut evh: NoOnEvh = evh!(EvhThreads(1), EvhTimeout(10))?;
+		set_callbacks(&mut evh, 31)?;
+		evh.start()?;
 
-		rx.recv()?;
-		assert!(rlock!(client_recv_clone));
-		assert!(rlock!(server_recv_clone));
+		for mask in [1, 2, 4, 8, 16] {
+			let e = set_callbacks(&mut evh, mask).unwrap_err();
+			assert!(matches!(e.kind(), ErrorKind::IllegalState(_)));
+		}
+		let e = evh
+			.set_on_data_chunk(Box::new(move |_connection, _chunk| Ok(ChunkAction::Free)))
+			.unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::IllegalState(_)));
+		let e = evh
+			.set_on_read_eof(Box::new(move |_connection, _ctx| Ok(())))
+			.unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::IllegalState(_)));
 
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_out_of_slabs_message() -> Result<(), Error> {
+	fn test_evh_oro_rejects_callbacks() -> Result<(), Error> {
+		// on_read is still required
+		let mut evh: Box<dyn EventHandler<NoOnRead, _, _, _, _> + Send + Sync> =
+			evh_oro!(EvhThreads(1), EvhTimeout(10))?;
+		match evh.start() {
+			Err(e) => match e.kind() {
+				ErrorKind::Configuration(text) => {
+					assert!(text.ends_with("required callbacks not set: on_read"))
+				}
+				_ => panic!("unexpected error: {}", e),
+			},
+			Ok(_) => panic!("start succeeded without on_read"),
+		}
+
+		// a ProtocolHandler would replace on_read
+		let mut evh = evh_oro!(EvhThreads(1), EvhTimeout(10))?;
+		let handler = Box::new(EchoHandler::default());
+		let e = evh.set_handler(handler).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
+		evh.set_on_read(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.start()?;
+
+		// the closure types of evh_oro! can't be named, so mark the mode the way it does
+		let mut evh: NoOnEvh = evh!(EvhThreads(1), EvhTimeout(10))?;
+		evh.set_on_read_only()?;
+		for mask in [2, 4, 8, 16] {
+			match set_callbacks(&mut evh, mask) {
+				Err(e) => match e.kind() {
+					ErrorKind::Configuration(text) => {
+						assert!(text.contains("not supported in on_read_only mode"))
+					}
+					_ => panic!("unexpected error: {}", e),
+				},
+				Ok(_) => panic!("callback {} was accepted in on_read_only mode", mask),
+			}
+		}
+		set_callbacks(&mut evh, 1)?;
+		evh.start()?;
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_evh_write_interest_not_registered() -> Result<(), Error> {
 		let test_info = test_info!()?;
 		let mut evh = evh!(
 			Debug(false),
 			EvhTimeout(100),
 			EvhThreads(1),
-			EvhReadSlabSize(25),
-			EvhReadSlabCount(1),
-			EvhStatsUpdateMillis(5000),
-			EvhOutOfSlabsMessage("outofslabs".to_string())
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(5000)
 		)?;
 
-		let debug_info = DebugInfo {
-			pending: lock_box!(true)?,
-			..Default::default()
-		};
-		evh.set_debug_info(debug_info)?;
-
-		let client_id = lock_box!(0)?;
-
-		let mut client_recv = lock_box!(false)?;
-		let mut server_recv = lock_box!(false)?;
-		let client_id_clone = client_id.clone();
-
-		let message = "012345678901234567890123456789";
-		let ret_message = "0123456789";
+		let debug_info = DebugInfo::default();
+		evh.set_debug_info(debug_info.clone())?;
 
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			info!("onRead")?;
-			let mut data: Vec<u8> = vec![];
-
+			let mut data = vec![];
 			loop {
 				let next_chunk = ctx.next_chunk(connection)?;
 				cbreak!(next_chunk.is_none());
-				let next_chunk = next_chunk.unwrap();
-				data.extend(next_chunk.data());
-			}
-
-			let dstring = from_utf8(&data)?;
-
-			if dstring != message && rlock!(client_id_clone) != connection.id() {
-				info!("not equal server = {}", dstring)?;
-				return Ok(());
-			} else if dstring != "outofslabs" && rlock!(client_id_clone) == connection.id() {
-				info!("not equal client = {}", dstring)?;
-				return Ok(());
-			} else {
-				info!(
-					"equal {}",
-					if rlock!(client_id_clone) == connection.id() {
-						"client"
-					} else {
-						"server"
-					}
-				)?;
-			}
-
-			let mut wh = connection.write_handle()?;
-
-			// respond
-			if rlock!(client_id_clone) != connection.id() {
-				info!("wh write and close")?;
-				wh.write_and_close(ret_message.as_bytes())?;
-				wlock!(server_recv) = true;
-			} else {
-				wlock!(client_recv) = true;
+				data.extend(next_chunk.unwrap().data());
 			}
-
+			connection.write_handle()?.write(&data)?;
 			ctx.clear_all(connection)?;
 			Ok(())
 		})?;
-
-		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
-			info!(
-				"onAccept: handle={},id={}",
-				connection.handle(),
-				connection.id()
-			)?;
-			Ok(())
-		})?;
-
-		evh.set_on_close(move |connection, _ctx| -> Result<(), Error> {
-			info!("onClose: {}", connection.id())?;
-			Ok(())
-		})?;
-
-		evh.set_on_housekeeper(move |_ctx| -> Result<(), Error> {
-			info!("onHousekeeper")?;
-			Ok(())
-		})?;
-
-		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> {
-			info!("onPanic")?;
-			Ok(())
-		})?;
-
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
 		evh.start()?;
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-		info!("connecting to addr {}", addr)?;
-		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 
-		info!("adding connection now")?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10)?;
 		evh.add_server_connection(conn)?;
-		let mut strm = TcpStream::connect(addr)?;
 
-		strm.write(message.as_bytes())?;
+		// none of the echoed messages block, so write interest is never registered
+		let mut strm = TcpStream::connect(&addr)?;
 		let mut buf = [0u8; 100];
-		info!("about to read")?;
-		let len = strm.read(&mut buf)?;
-		info!("read complete")?;
+		for i in 0..1_000 {
+			let msg = format!("msg{}", i);
+			strm.write_all(msg.as_bytes())?;
+			let mut len = 0;
+			while len < msg.len() {
+				let rlen = strm.read(&mut buf[len..])?;
+				assert!(rlen > 0);
+				len += rlen;
+			}
+			assert_eq!(&buf[0..len], msg.as_bytes());
+		}
+
+		assert_eq!(rlock!(debug_info.write_interest_registers), 0);
+		assert_eq!(rlock!(debug_info.write_interest_deregisters), 0);
 
-		assert_eq!(&buf[0..len], b"outofslabs");
 		Ok(())
 	}
 
 	#[test]
-	fn test_evh_disabled_out_of_slabs_message() -> Result<(), Error> {
+	fn test_evh_write_interest_pending() -> Result<(), Error> {
 		let test_info = test_info!()?;
 		let mut evh = evh!(
 			Debug(false),
 			EvhTimeout(100),
 			EvhThreads(1),
-			EvhReadSlabSize(25),
-			EvhReadSlabCount(1),
-			EvhStatsUpdateMillis(5000),
-			EvhOutOfSlabsMessage("outofslabs".to_string())
+			EvhReadSlabSize(100),
+			EvhStatsUpdateMillis(5000)
 		)?;
 
+		// every write is queued, so write interest is registered until the data is written
 		let debug_info = DebugInfo {
 			pending: lock_box!(true)?,
 			..Default::default()
 		};
-		evh.set_debug_info(debug_info)?;
-
-		let client_id = lock_box!(0)?;
-
-		let mut client_recv = lock_box!(false)?;
-		let mut server_recv = lock_box!(false)?;
-		let client_id_clone = client_id.clone();
-
-		let message = "012345678901234567890123456789";
-		let ret_message = "0123456789";
+		evh.set_debug_info(debug_info.clone())?;
 
 		evh.set_on_read(move |connection, ctx| -> Result<(), Error> {
-			info!("onRead")?;
-			let mut data: Vec<u8> = vec![];
-
+			let mut data = vec![];
 			loop {
 				let next_chunk = ctx.next_chunk(connection)?;
 				cbreak!(next_chunk.is_none());
-				let next_chunk = next_chunk.unwrap();
-				data.extend(next_chunk.data());
-			}
-
-			let dstring = from_utf8(&data)?;
-
-			if dstring != message && rlock!(client_id_clone) != connection.id() {
-				info!("not equal server = {}", dstring)?;
-				return Ok(());
-			} else if dstring != "outofslabs" && rlock!(client_id_clone) == connection.id() {
-				info!("not equal client = {}", dstring)?;
-				return Ok(());
-			} else {
-				info!(
-					"equal {}",
-					if rlock!(client_id_clone) == connection.id() {
-						"client"
-					} else {
-						"server"
-					}
-				)?;
+				data.extend(next_chunk.unwrap().data());
 			}
-
-			let mut wh = connection.write_handle()?;
-
-			// respond
-			if rlock!(client_id_clone) != connection.id() {
-				info!("wh write and close")?;
-				wh.write_and_close(ret_message.as_bytes())?;
-				wlock!(server_recv) = true;
-			} else {
-				wlock!(client_recv) = true;
-			}
-
+			connection.write_handle()?.write(&data)?;
 			ctx.clear_all(connection)?;
 			Ok(())
 		})?;
-
-		evh.set_on_accept(move |connection, _ctx| -> Result<(), Error> {
-			connection.disable_write_final();
-			info!(
-				"onAccept: handle={},id={}",
-				connection.handle(),
-				connection.id()
-			)?;
-			Ok(())
-		})?;
-
-		evh.set_on_close(move |connection, _ctx| -> Result<(), Error> {
-			info!("onClose: {}", connection.id())?;
-			Ok(())
-		})?;
-
-		evh.set_on_housekeeper(move |_ctx| -> Result<(), Error> {
-			info!("onHousekeeper")?;
-			Ok(())
-		})?;
-
-		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> {
-			info!("onPanic")?;
-			Ok(())
-		})?;
-
+		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
 		evh.start()?;
-		let port = test_info.port();
-		let addr = format!("127.0.0.1:{}", port);
-		info!("connecting to addr {}", addr)?;
-		let conn = EvhBuilder::build_server_connection(&addr, 10_000)?;
 
-		info!("adding connection now")?;
+		let addr = format!("127.0.0.1:{}", test_info.port());
+		let conn = EvhBuilder::build_server_connection(&addr, 10)?;
 		evh.add_server_connection(conn)?;
-		let mut strm = TcpStream::connect(addr)?;
 
-		strm.write(message.as_bytes())?;
+		let mut strm = TcpStream::connect(&addr)?;
 		let mut buf = [0u8; 100];
-		info!("about to read")?;
-		assert_eq!(strm.read(&mut buf)?, 0);
+		for i in 1..=10 {
+			strm.write_all(b"hi")?;
+			let mut len = 0;
+			while len < 2 {
+				let rlen = strm.read(&mut buf[len..])?;
+				assert!(rlen > 0);
+				len += rlen;
+			}
+			assert_eq!(&buf[0..len], b"hi");
+
+			// the interest is deregistered after the data was written
+			let mut count = 0;
+			while rlock!(debug_info.write_interest_deregisters) < i {
+				sleep(Duration::from_millis(1));
+				count += 1;
+				assert!(count < 10_000);
+			}
+			assert_eq!(rlock!(debug_info.write_interest_registers), i);
+			assert_eq!(rlock!(debug_info.write_interest_deregisters), i);
+		}
 
 		Ok(())
 	}
@@ -24,12 +24,22 @@ use crate::win::*;
 use crate::linux::*;
 
 use crate::constants::*;
+use bmw_conf::{ConfigHistory, ConfigOption, WatchdogAction};
+use bmw_deps::flate2::write::{GzEncoder, ZlibEncoder};
 use bmw_err::*;
+use bmw_log::{DynLogger, SharedLogger};
 use bmw_util::*;
 use std::any::Any;
-use std::collections::{HashMap, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
 use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Waker;
 
 /// The [`crate::EventHandler`] trait is implemented by the returned value of the
 /// [`crate::EvhBuilder::build_evh`] function.
@@ -50,13 +60,13 @@ where
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnClose: FnMut(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnClose: FnMut(&mut Connection, &CloseReason, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnHousekeeper: FnMut(&mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnHousekeeper: FnMut(&mut HousekeeperContext<'_>, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
@@ -75,45 +85,90 @@ where
 	/// none
 	/// # Returns
 	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::Configuration`] - If both the OnRead and OnDataChunk handlers are set.
+	/// [`bmw_err::ErrKind::Configuration`] - If a [`crate::ProtocolHandler`] is set along with
+	/// any of the OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic or OnDataChunk handlers.
+	/// [`bmw_err::ErrKind::Configuration`] - If a required handler is not set. Unless a
+	/// [`crate::ProtocolHandler`] is set, OnRead (or OnDataChunk) is required and, except for
+	/// event handlers built with [`crate::evh_oro`], so are OnAccept, OnClose, OnPanic and
+	/// OnHousekeeper. The message names every missing handler.
+	/// [`bmw_err::ErrKind::IllegalState`] - If a thread failed while initializing. The message
+	/// names the thread and the error that it failed with.
+	/// [`bmw_err::ErrKind::Timeout`] - If the threads did not all finish initializing within
+	/// [`bmw_conf::ConfigOption::EvhStartTimeoutMillis`].
 	/// # See Also
-	/// [`crate`], [`crate::EventHandler`]
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::thread_health`]
 	fn start(&mut self) -> Result<(), Error>;
 	/// Set the OnRead handler for this [`crate::EventHandler`]. When data is ready on any
 	/// connections that have been added to this event handler, the OnRead callback will be
-	/// executed.
+	/// executed. For accepted connections, OnRead is never executed before the OnAccept
+	/// callback for the connection has returned, even if data arrives along with the
+	/// connection. Such data is delivered by the first OnRead callback after OnAccept.
 	/// # Input Parameters
 	/// The OnRead handler to use as a callback for this [`crate::EventHandler`].
 	/// # Returns
 	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalState`] - If [`crate::EventHandler::start`] has already been
+	/// called.
 	/// # See Also
 	/// [`crate`], [`crate::EventHandler`], [`crate::UserContext`]
 	fn set_on_read(&mut self, on_read: OnRead) -> Result<(), Error>;
 	/// Set the OnAccept handler for this [`crate::EventHandler`]. When new connections are
-	/// accepted by a server connection, this callback will be executed.
+	/// accepted by a server connection, this callback will be executed. It returns before any
+	/// other callback is executed for the connection, so it may be used to initialize
+	/// per-connection state. If the `EvhStrictOrdering` option is set, the
+	/// [`crate::EventHandler`] panics if this ordering is ever violated.
 	/// # Input Parameters
 	/// The OnAccept handler to use as a callback for this [`crate::EventHandler`].
 	/// # Returns
 	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalState`] - If [`crate::EventHandler::start`] has already been
+	/// called.
+	/// [`bmw_err::ErrKind::Configuration`] - If the [`crate::EventHandler`] was built with
+	/// [`crate::evh_oro`] which does not support this handler.
 	/// # See Also
 	/// [`crate`], [`crate::EventHandler`], [`crate::UserContext`]
 	fn set_on_accept(&mut self, on_accept: OnAccept) -> Result<(), Error>;
 	/// Set the OnClose handler for this [`crate::EventHandler`]. When connections are
-	/// closed, this callback will be executed.
+	/// closed, this callback will be executed with the [`crate::CloseReason`] that the
+	/// connection was closed for. It is not executed for accepted connections that are closed
+	/// before their OnAccept callback was executed (i.e. while waiting for a PROXY protocol
+	/// header).
 	/// # Input Parameters
 	/// The OnClose handler to use as a callback for this [`crate::EventHandler`].
 	/// # Returns
 	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalState`] - If [`crate::EventHandler::start`] has already been
+	/// called.
+	/// [`bmw_err::ErrKind::Configuration`] - If the [`crate::EventHandler`] was built with
+	/// [`crate::evh_oro`] which does not support this handler.
 	/// # See Also
-	/// [`crate`], [`crate::EventHandler`], [`crate::UserContext`]
+	/// [`crate`], [`crate::EventHandler`], [`crate::UserContext`], [`crate::CloseReason`]
 	fn set_on_close(&mut self, on_close: OnClose) -> Result<(), Error>;
 	/// Set the OnHousekeeper handler for this [`crate::EventHandler`]. Periodically,
 	/// housekeeping needs to occur. This function allows the user to specify a hook that is
 	/// executed periodically. This can be used to close stale connections, log data, or
-	/// anything the user wishes to implement.
+	/// anything the user wishes to implement. The handler is passed a
+	/// [`crate::HousekeeperContext`] which gives access to the connections of the thread that
+	/// is executing it, so keepalive messages may be sent or idle connections closed without
+	/// keeping a separate registry of the connections.
+	///
+	/// The handler is executed on the event loop thread. No events of that thread's
+	/// connections are processed until it returns, so long running work should be handed off
+	/// to another thread.
 	/// # Input Parameters
 	/// The OnHousekeeper handler to use as a callback for this [`crate::EventHandler`].
 	/// # Returns
 	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalState`] - If [`crate::EventHandler::start`] has already been
+	/// called.
+	/// [`bmw_err::ErrKind::Configuration`] - If the [`crate::EventHandler`] was built with
+	/// [`crate::evh_oro`] which does not support this handler.
 	/// # See Also
 	/// [`crate`], [`crate::EventHandler`], [`crate::UserContext`]
 	fn set_on_housekeeper(&mut self, on_housekeeper: OnHousekeeper) -> Result<(), Error>;
@@ -125,9 +180,28 @@ where
 	/// The OnPanic handler to use as a callback for this [`crate::EventHandler`].
 	/// # Returns
 	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalState`] - If [`crate::EventHandler::start`] has already been
+	/// called.
+	/// [`bmw_err::ErrKind::Configuration`] - If the [`crate::EventHandler`] was built with
+	/// [`crate::evh_oro`] which does not support this handler.
 	/// # See Also
 	/// [`crate`], [`crate::EventHandler`], [`crate::UserContext`]
 	fn set_on_panic(&mut self, on_panic: OnPanic) -> Result<(), Error>;
+	/// Sets the logger used by this [`crate::EventHandler`] for its internal warnings and
+	/// errors (accept errors, running out of read slabs, watchdog reports, fatal thread
+	/// errors, etc). If no logger is set, the global logger is used. In both cases, each line
+	/// is prefixed with the name configured with [`bmw_conf::ConfigOption::EvhName`], if any,
+	/// so that the lines of several [`crate::EventHandler`] instances may be told apart. The
+	/// logger may be set before or after [`crate::EventHandler::start`] is called.
+	/// # Input Parameters
+	/// logger - the [`bmw_log::DynLogger`] to use. It must already be initialized. To keep
+	/// logging to the same logger from elsewhere, pass a clone of a [`bmw_log::SharedLogger`].
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EvhStats::name`]
+	fn set_logger(&mut self, logger: DynLogger) -> Result<(), Error>;
 	/// Add a server connection to this [`crate::EventHandler`].
 	/// # Input Parameters
 	/// connection - the [`crate::Connection`] to add to this [`crate::EventHandler`] instance.
@@ -137,6 +211,11 @@ where
 	/// [`bmw_err::ErrKind::IllegalArgument`] - If the connection is not a server connection.
 	/// [`bmw_err::ErrKind::IO`] - If an i/o error occurs in the [`crate::EventHandler`] while
 	/// adding this connection.
+	/// [`bmw_err::ErrKind::IllegalState`] - If the thread that the connection is assigned to
+	/// is not running.
+	/// [`bmw_err::ErrKind::AlreadyRegistered`] - If the handle of the connection is already
+	/// registered with this [`crate::EventHandler`]. The message names the handle and the id
+	/// of the existing connection.
 	/// # See Also
 	/// [`crate`], [`crate::EventHandler`], [`crate::EvhBuilder::build_server_connection`]
 	fn add_server_connection(&mut self, connection: Connection) -> Result<(), Error>;
@@ -149,9 +228,47 @@ where
 	/// [`bmw_err::ErrKind::IllegalArgument`] - If the connection is not a client connection.
 	/// [`bmw_err::ErrKind::IO`] - If an i/o error occurs in the [`crate::EventHandler`] while
 	/// adding this connection.
+	/// [`bmw_err::ErrKind::IllegalState`] - If the thread that the connection is assigned to
+	/// is not running.
+	/// [`bmw_err::ErrKind::AlreadyRegistered`] - If the handle of the connection is already
+	/// registered with this [`crate::EventHandler`]. The message names the handle and the id
+	/// of the existing connection.
 	/// # See Also
 	/// [`crate`], [`crate::EventHandler`], [`crate::EvhBuilder::build_client_connection`]
 	fn add_client_connection(&mut self, connection: Connection) -> Result<WriteHandle, Error>;
+	/// Add a connection that was built with [`crate::EvhBuilder::import_connection`] to this
+	/// [`crate::EventHandler`]. The connection is handled like an accepted connection, so the
+	/// on_accept handler is called for it before any data is read. The PROXY protocol header
+	/// is not expected on an imported connection, even if EvhProxyProtocol is configured.
+	/// # Input Parameters
+	/// connection - the [`crate::Connection`] to add to this [`crate::EventHandler`] instance.
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] - If the connection was not built with
+	/// [`crate::EvhBuilder::import_connection`] or has already been added.
+	/// [`bmw_err::ErrKind::IO`] - If an i/o error occurs in the [`crate::EventHandler`] while
+	/// adding this connection.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::Connection::export_handle`]
+	fn add_imported_connection(&mut self, connection: Connection) -> Result<(), Error>;
+	/// Builds a client side [`crate::Connection`] that can be added to this
+	/// [`crate::EventHandler`] via the [`crate::EventHandler::add_client_connection`] function.
+	/// If EvhClientBindAddr was configured, the socket is bound to that local address before
+	/// connecting. Otherwise, this is equivalent to calling
+	/// [`crate::EvhBuilder::build_client_connection`].
+	/// # Input Parameters
+	/// host - The remote host to connect to.
+	/// port - The remote port to connect to.
+	/// # Returns
+	/// On success, the [`crate::Connection`] is returned and on failure, [`bmw_err::Error`] is
+	/// returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IO`] - If an i/o error occurs, including a failure to bind to the
+	/// configured local address.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EvhBuilder::build_client_connection_bound`]
+	fn build_client_connection(&self, host: &str, port: u16) -> Result<Connection, Error>;
 	/// This function will block until statistical data is ready for this
 	/// [`crate::EventHandler`]. The time this function blocks is specified by the
 	/// [`bmw_conf::ConfigOption::EvhStatsUpdateMillis`] parameter. It is important to note
@@ -169,8 +286,315 @@ where
 	/// [`crate`], [`crate::EventHandler`], [`crate::EvhStats`]
 	fn wait_for_stats(&mut self) -> Result<EvhStats, Error>;
 	fn controller(&mut self) -> Result<EvhController, Error>;
+	/// Returns the instance id of this [`crate::EventHandler`]. The instance id is a random u64
+	/// chosen when the [`crate::EventHandler`] is built and is never reused within the same
+	/// process. It makes up the upper 64 bits of the ids of the connections accepted by this
+	/// [`crate::EventHandler`]. See [`crate::ConnectionId`] and
+	/// [`crate::Connection::evh_instance_id`].
+	fn instance_id(&self) -> u64;
+	/// Set the OnRawEvent handler for this [`crate::EventHandler`]. When a handle registered
+	/// via [`crate::EventHandler::register_raw`] becomes ready, this callback is executed on
+	/// the thread that owns the registration with the `token` of the registration and whether
+	/// the handle is readable and/or writable.
+	/// # Input Parameters
+	/// on_raw_event - The OnRawEvent handler to use as a callback for this
+	/// [`crate::EventHandler`].
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::register_raw`]
+	fn set_on_raw_event(
+		&mut self,
+		on_raw_event: Box<dyn FnMut(u64, bool, bool) -> Result<(), Error> + Send + Sync>,
+	) -> Result<(), Error>;
+	/// Set the OnDataChunk handler for this [`crate::EventHandler`]. This is an alternative to
+	/// the OnRead handler for protocols where each chunk of data can be processed on its own.
+	/// Instead of calling the OnRead handler with the connection's chunk list, the callback is
+	/// executed each time data is read with the connection and the data in the current read
+	/// slab. If the callback returns [`crate::ChunkAction::Free`] (or an error), all of the
+	/// connection's read slabs are freed. If it returns [`crate::ChunkAction::Retain`], the
+	/// data is kept and the next call for this connection receives it followed by the newly
+	/// read data. Once a retained slab is full, the next call only receives data from a new
+	/// slab, but the full slab is kept until [`crate::ChunkAction::Free`] is returned or the
+	/// connection is closed. Only one of the OnRead and OnDataChunk handlers may be set. Since
+	/// the OnRead handler is not set, its type must be specified with [`crate::NoOnRead`] when
+	/// the [`crate::EventHandler`] is built.
+	/// # Input Parameters
+	/// on_data_chunk - The OnDataChunk handler to use as a callback for this
+	/// [`crate::EventHandler`].
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::set_on_read`]
+	fn set_on_data_chunk(&mut self, on_data_chunk: OnDataChunk) -> Result<(), Error>;
+	/// Set the OnSlabQuota handler for this [`crate::EventHandler`]. If the
+	/// `EvhMaxSlabsPerConnection` option is set and `EvhSlabQuotaAction` is "Pause", the
+	/// [`crate::EventHandler`] stops reading from a connection when it would need more read
+	/// slabs than the quota allows and executes this callback once. The data that has been
+	/// read so far is available through [`crate::UserContext::next_chunk`]. Reading resumes
+	/// on the next read event or [`crate::Connection::trigger_on_read`] after slabs have been
+	/// freed with [`crate::UserContext::clear_through`] or [`crate::UserContext::clear_all`].
+	/// If the callback frees slabs itself, reading continues right away.
+	/// # Input Parameters
+	/// on_slab_quota - The OnSlabQuota handler to use as a callback for this
+	/// [`crate::EventHandler`].
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::UserContext`]
+	fn set_on_slab_quota(&mut self, on_slab_quota: OnSlabQuota) -> Result<(), Error>;
+	/// Set the OnReadEof handler for this [`crate::EventHandler`]. By default, a connection
+	/// is closed as soon as a read returns end of file. If this handler is set, the
+	/// [`crate::EventHandler`] instead treats end of file as a half-close: the peer shut down
+	/// its write side but may still be reading. The callback is executed once, after all of
+	/// the data sent by the peer has been delivered to the OnRead handler, and the data that
+	/// was not cleared is still available through [`crate::UserContext::next_chunk`]. The
+	/// connection stays registered for writes so that a final response can be sent. It is
+	/// closed (and the OnClose handler is executed once) when [`crate::WriteHandle::close`]
+	/// is called, after any data written before that has been sent, or when a write fails.
+	/// Since nothing more is read from the connection, the handler is responsible for
+	/// closing it.
+	/// [`crate::Connection::is_read_eof`] returns true once end of file has been reached.
+	/// # Input Parameters
+	/// on_read_eof - The OnReadEof handler to use as a callback for this
+	/// [`crate::EventHandler`].
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::set_on_read`]
+	fn set_on_read_eof(&mut self, on_read_eof: OnReadEof) -> Result<(), Error>;
+	/// Set the OnPanicInfo handler for this [`crate::EventHandler`]. This handler is executed
+	/// in the same cases as the [`crate::EventHandler::set_on_panic`] handler and just before
+	/// it, but instead of the raw panic payload, it is passed a [`bmw_util::PanicInfoCapture`]
+	/// with the panic message, the name of the thread and the location of the panic.
+	/// # Input Parameters
+	/// on_panic_info - The OnPanicInfo handler to use as a callback for this
+	/// [`crate::EventHandler`].
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::set_on_panic`]
+	fn set_on_panic_info(&mut self, on_panic_info: OnPanicInfo) -> Result<(), Error>;
+	/// Set a [`crate::ProtocolHandler`] for this [`crate::EventHandler`]. This is an
+	/// alternative to setting the OnRead, OnAccept, OnClose, OnHousekeeper and OnPanic
+	/// handlers individually. Since all of the callbacks are methods of the same value, its
+	/// state does not need to be shared between closures. The handler is shared by all of the
+	/// threads of the [`crate::EventHandler`] and each call holds a lock on it. To avoid
+	/// ambiguity, [`crate::EventHandler::start`] returns an error if any of the closure
+	/// handlers is set as well. Since the closure handlers are not set, their types must be
+	/// specified with [`crate::NoOnRead`], [`crate::NoOnAccept`], [`crate::NoOnClose`],
+	/// [`crate::NoOnHousekeeper`] and [`crate::NoOnPanic`] when the [`crate::EventHandler`]
+	/// is built.
+	/// # Input Parameters
+	/// handler - The [`crate::ProtocolHandler`] to use for this [`crate::EventHandler`].
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::ProtocolHandler`]
+	fn set_handler(&mut self, handler: Box<dyn ProtocolHandler + Send + Sync>)
+		-> Result<(), Error>;
+	/// Set a [`crate::MetricsRecorder`] for this [`crate::EventHandler`]. The counters are
+	/// recorded at the same points that [`crate::EvhStats`] is updated, so they do not have to
+	/// be derived from [`crate::EventHandler::wait_for_stats`]. The gauges are set by each
+	/// thread at its statistical update (see [`bmw_conf::ConfigOption::EvhStatsUpdateMillis`])
+	/// with the total for all threads. The recorder is called from the threads of the
+	/// [`crate::EventHandler`] and from [`crate::WriteHandle::write`], so its methods should
+	/// be cheap. If the recorder panics, a warning is logged and nothing else is recorded.
+	/// This function must be called before [`crate::EventHandler::start`].
+	/// # Input Parameters
+	/// recorder - The [`crate::MetricsRecorder`] to use for this [`crate::EventHandler`].
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::MetricsRecorder`], [`crate::EvhMetric`]
+	fn set_metrics_recorder(
+		&mut self,
+		recorder: Box<dyn MetricsRecorder + Send + Sync>,
+	) -> Result<(), Error>;
+	/// Register a foreign handle with this [`crate::EventHandler`]. The event handler only
+	/// reports readiness for this handle through the callback specified by
+	/// [`crate::EventHandler::set_on_raw_event`]. It never reads from or writes to the handle
+	/// and no [`crate::Connection`] or read slabs are associated with it. Events are edge
+	/// triggered so the owner of the handle must read/write until the operation would block.
+	/// Raw registrations are not included in [`crate::EvhStats`].
+	/// # Input Parameters
+	/// handle - The handle to register.
+	/// interest - The [`crate::Interest`] for this handle.
+	/// token - A caller specified value that is passed to the OnRawEvent callback. Tokens
+	/// must be unique within this [`crate::EventHandler`].
+	/// close_on_stop - If true, the handle is closed when the [`crate::EventHandler`] stops.
+	/// Otherwise, the handle is left open and the caller is responsible for closing it.
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] - If the token is already registered.
+	/// [`bmw_err::ErrKind::IO`] - If an i/o error occurs in the [`crate::EventHandler`] while
+	/// registering this handle.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::deregister_raw`]
+	fn register_raw(
+		&mut self,
+		handle: Handle,
+		interest: Interest,
+		token: u64,
+		close_on_stop: bool,
+	) -> Result<(), Error>;
+	/// Deregister a handle previously registered via [`crate::EventHandler::register_raw`].
+	/// When this function returns, no further events will be reported for the handle and the
+	/// handle is not closed by the [`crate::EventHandler`].
+	/// # Input Parameters
+	/// token - The token that was specified when the handle was registered.
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] - If the token is not registered.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::register_raw`]
+	fn deregister_raw(&mut self, token: u64) -> Result<(), Error>;
+	/// Returns the soft limit on the number of open file descriptors (RLIMIT_NOFILE) that
+	/// was read when this [`crate::EventHandler`] was built. When the number of connections
+	/// exceeds [`bmw_conf::ConfigOption::EvhFdWarnThresholdPercent`] percent of this limit,
+	/// a warning is logged at most once per minute.
+	/// # Returns
+	/// On success, the limit is returned or [`None`] if the limit is unknown or unlimited (as
+	/// is always the case on windows). On failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::fd_headroom`]
+	fn fd_limit(&self) -> Result<Option<usize>, Error>;
+	/// Change configuration options of this [`crate::EventHandler`] while it is running. The
+	/// options are validated with the same rules as when the [`crate::EventHandler`] is built
+	/// and are applied together. Each thread picks up the new values at the start of its next
+	/// event loop iteration, so no connections are dropped. Only the following options may be
+	/// changed:
+	/// * [`bmw_conf::ConfigOption::EvhHouseKeeperFrequencyMillis`]
+	/// * [`bmw_conf::ConfigOption::EvhStatsUpdateMillis`]
+	/// * [`bmw_conf::ConfigOption::EvhTimeout`]
+	/// * [`bmw_conf::ConfigOption::EvhMaxWriteBytesPerLoop`]
+	/// * [`bmw_conf::ConfigOption::EvhOutOfSlabsMessage`]
+	///
+	/// Each option whose value changed is logged at the info level with its old and new value
+	/// (see [`bmw_conf::Config::diff`]) so that there is an audit trail of runtime changes.
+	/// # Input Parameters
+	/// options - the options to change. Options that are not specified keep their values.
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::Configuration`] - If any of the options may not be changed after
+	/// the [`crate::EventHandler`] is built (the message lists them) or if a value is invalid.
+	/// No options are changed in that case.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EvhController::update_config`]
+	fn update_config(&mut self, options: Vec<ConfigOption>) -> Result<(), Error>;
+	/// Returns the number of additional connections that may be opened before the limit
+	/// returned by [`crate::EventHandler::fd_limit`] is reached. The count of open connections
+	/// is the one from the last statistical update. Other file descriptors used by the
+	/// process are not taken into account.
+	/// # Returns
+	/// On success, the headroom is returned or [`usize::MAX`] if there is no known limit. On
+	/// failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::fd_limit`]
+	fn fd_headroom(&self) -> Result<usize, Error>;
+	/// Returns the health of each of the threads of this [`crate::EventHandler`], indexed by
+	/// thread id. This may be used to monitor the [`crate::EventHandler`] after it has started.
+	/// # Returns
+	/// On success, a [`crate::ThreadHealth`] for each thread is returned and on failure,
+	/// [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::is_running`]
+	fn thread_health(&self) -> Result<Vec<ThreadHealth>, Error>;
+	/// Returns true if every thread of this [`crate::EventHandler`] is
+	/// [`crate::ThreadHealth::Running`].
+	/// # Returns
+	/// On success, whether all of the threads are running is returned and on failure,
+	/// [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::thread_health`]
+	fn is_running(&self) -> Result<bool, Error>;
+	/// Shut down this [`crate::EventHandler`] when one of the specified signals is received
+	/// by the process. The signal handler itself only records the signal and writes to a
+	/// pipe. A background thread then notifies every [`crate::EventHandler`] that registered
+	/// for the signal, so several instances in the same process may use this function. With
+	/// [`crate::ShutdownMode::Graceful`], the server connections are closed so no new
+	/// connections are accepted and the [`crate::EventHandler`] waits for the open connections
+	/// to be closed (or the timeout to elapse). Then the OnShutdown handler (see
+	/// [`crate::EventHandler::set_on_shutdown`]) is executed and the [`crate::EventHandler`]
+	/// is stopped. Calling this function again replaces the previous registration. On
+	/// windows, [`crate::Signal::Int`] is raised by Ctrl-C and Ctrl-Break and
+	/// [`crate::Signal::Term`] when the console is closed or the system shuts down.
+	/// # Input Parameters
+	/// signals - the signals to shut down on.
+	/// mode - the [`crate::ShutdownMode`] to use.
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalState`] - If [`crate::EventHandler::start`] has not been
+	/// called.
+	/// [`bmw_err::ErrKind::IllegalArgument`] - If no signals are specified.
+	/// [`bmw_err::ErrKind::IO`] - If the signal handler could not be installed.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::set_on_shutdown`]
+	fn handle_signals(&mut self, signals: &[Signal], mode: ShutdownMode) -> Result<(), Error>;
+	/// Set the OnShutdown handler for this [`crate::EventHandler`]. When the
+	/// [`crate::EventHandler`] is shut down because of a signal (see
+	/// [`crate::EventHandler::handle_signals`]), this callback is executed after the
+	/// connections have been drained and before the threads of the [`crate::EventHandler`]
+	/// exit, so that the application may flush its own state. It is not executed when the
+	/// [`crate::EventHandler`] is dropped or stopped with [`crate::EvhController::stop`].
+	/// # Input Parameters
+	/// on_shutdown - The OnShutdown handler to use as a callback for this
+	/// [`crate::EventHandler`].
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::handle_signals`]
+	fn set_on_shutdown(
+		&mut self,
+		on_shutdown: Box<dyn FnMut() -> Result<(), Error> + Send + Sync>,
+	) -> Result<(), Error>;
+	/// Register an application assigned `key` (i.e. a peer id or session token) for the
+	/// connection with the specified `connection_id` so that a [`crate::WriteHandle`] for it
+	/// can be looked up with [`crate::EventHandler::write_handle_for_key`] from any thread.
+	/// The registration is removed when the connection is closed. A connection may have
+	/// several keys. This may be called from within the OnAccept handler. If the key is
+	/// already registered to another connection, it is moved to this connection and a warning
+	/// is logged unless the EvhStrictKeys option is set, in which case an error is returned.
+	/// # Input Parameters
+	/// connection_id - the id of the connection. See [`crate::Connection::id`].
+	/// key - the key to register.
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] - If no open connection has the specified id.
+	/// [`bmw_err::ErrKind::IllegalState`] - If EvhStrictKeys is set and the key is registered
+	/// to another connection.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::unregister_key`]
+	fn register_key(&mut self, connection_id: u128, key: u64) -> Result<(), Error>;
+	/// Remove the registration of `key` made by [`crate::EventHandler::register_key`]. Keys
+	/// that are not registered are ignored.
+	/// # Input Parameters
+	/// key - the key to unregister.
+	/// # Returns
+	/// On success, [`unit`] is returned and on failure, [`bmw_err::Error`] is returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::register_key`]
+	fn unregister_key(&mut self, key: u64) -> Result<(), Error>;
+	/// Look up the [`crate::WriteHandle`] of the connection that `key` is registered to. See
+	/// [`crate::EventHandler::register_key`].
+	/// # Input Parameters
+	/// key - the key to look up.
+	/// # Returns
+	/// On success, the [`crate::WriteHandle`] is returned or [`None`] if the key is not
+	/// registered (or the connection has been closed). On failure, [`bmw_err::Error`] is
+	/// returned.
+	/// # See Also
+	/// [`crate`], [`crate::EventHandler`], [`crate::EventHandler::register_key`]
+	fn write_handle_for_key(&self, key: u64) -> Result<Option<WriteHandle>, Error>;
 	#[doc(hidden)]
 	fn set_debug_info(&mut self, debug_info: DebugInfo) -> Result<(), Error>;
+	#[doc(hidden)]
+	fn set_on_read_only(&mut self) -> Result<(), Error>;
 }
 
 #[derive(Clone)]
@@ -181,6 +605,234 @@ pub struct EvhController {
 	pub(crate) stats: Box<dyn LockBox<GlobalStats>>,
 	pub(crate) config: EventHandlerConfig,
 	pub(crate) debug_info: DebugInfo,
+	pub(crate) ids: ConnectionIds,
+	pub(crate) keys: Box<dyn LockBox<KeyRegistry>>,
+}
+
+/// The value returned by the OnDataChunk handler. See
+/// [`crate::EventHandler::set_on_data_chunk`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ChunkAction {
+	/// The data has been processed and the connection's read slabs may be freed.
+	Free,
+	/// Keep the data so that it is passed again, followed by the next data read.
+	Retain,
+}
+
+/// The type of the OnRead handler for an [`crate::EventHandler`] that only uses the
+/// OnDataChunk handler. See [`crate::EventHandler::set_on_data_chunk`].
+pub type NoOnRead = fn(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error>;
+
+/// The type of the OnAccept handler for an [`crate::EventHandler`] that does not set it. See
+/// [`crate::EventHandler::set_handler`].
+pub type NoOnAccept = fn(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error>;
+
+/// The type of the OnClose handler for an [`crate::EventHandler`] that does not set it. See
+/// [`crate::EventHandler::set_handler`].
+pub type NoOnClose =
+	fn(&mut Connection, &CloseReason, &mut Box<dyn UserContext + '_>) -> Result<(), Error>;
+
+/// The type of the OnHousekeeper handler for an [`crate::EventHandler`] that does not set it.
+/// See [`crate::EventHandler::set_handler`].
+pub type NoOnHousekeeper =
+	fn(&mut HousekeeperContext<'_>, &mut Box<dyn UserContext + '_>) -> Result<(), Error>;
+
+/// The type of the OnPanic handler for an [`crate::EventHandler`] that does not set it. See
+/// [`crate::EventHandler::set_handler`].
+pub type NoOnPanic = fn(&mut Box<dyn UserContext + '_>, Box<dyn Any + Send>) -> Result<(), Error>;
+
+/// An inbound or outbound transform of a [`crate::Connection`]. The transform appends its
+/// output for the input to the [`std::vec::Vec`]. See
+/// [`crate::Connection::set_inbound_transform`] and
+/// [`crate::Connection::set_outbound_transform`].
+pub type Transform = Box<dyn FnMut(&[u8], &mut Vec<u8>) -> Result<(), Error> + Send>;
+
+/// The response extractor of a [`crate::Connection`]. It is called with the data read from the
+/// connection and returns the (correlation_id, response) pairs of the responses that it found.
+/// Data that is not part of a response is appended to the [`std::vec::Vec`] and is passed to
+/// the OnRead handler. See [`crate::Connection::set_response_extractor`].
+pub type ResponseExtractor =
+	Box<dyn FnMut(&[u8], &mut Vec<u8>) -> Result<Vec<(u64, Vec<u8>)>, Error> + Send>;
+
+/// The handler for responses returned by a [`crate::ResponseExtractor`] that do not match a
+/// pending request. It is called with the correlation_id and the response. See
+/// [`crate::Connection::set_unmatched_response_handler`].
+pub type UnmatchedResponseHandler = Box<dyn FnMut(u64, Vec<u8>) -> Result<(), Error> + Send>;
+
+/// A protocol implementation for an [`crate::EventHandler`]. Each method corresponds to one of
+/// the closure handlers and is executed in the same situations. See
+/// [`crate::EventHandler::set_handler`]. All of the methods have a default implementation that
+/// does nothing, so only the ones that are needed have to be implemented.
+pub trait ProtocolHandler {
+	/// Executed when data is ready on a connection. See [`crate::EventHandler::set_on_read`].
+	fn on_read(
+		&mut self,
+		_connection: &mut Connection,
+		_ctx: &mut Box<dyn UserContext + '_>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+	/// Executed when a connection is accepted. See [`crate::EventHandler::set_on_accept`].
+	fn on_accept(
+		&mut self,
+		_connection: &mut Connection,
+		_ctx: &mut Box<dyn UserContext + '_>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+	/// Executed when a connection is closed. See [`crate::EventHandler::set_on_close`].
+	fn on_close(
+		&mut self,
+		_connection: &mut Connection,
+		_reason: &CloseReason,
+		_ctx: &mut Box<dyn UserContext + '_>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+	/// Executed periodically by each thread. See [`crate::EventHandler::set_on_housekeeper`].
+	fn on_housekeeper(
+		&mut self,
+		_hk: &mut HousekeeperContext<'_>,
+		_ctx: &mut Box<dyn UserContext + '_>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+	/// Executed when a thread panics. See [`crate::EventHandler::set_on_panic`].
+	fn on_panic(
+		&mut self,
+		_ctx: &mut Box<dyn UserContext + '_>,
+		_e: Box<dyn Any + Send>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+	/// Executed when a thread panics, before [`crate::ProtocolHandler::on_panic`]. See
+	/// [`crate::EventHandler::set_on_panic_info`].
+	fn on_panic_info(
+		&mut self,
+		_ctx: &mut Box<dyn UserContext + '_>,
+		_info: &PanicInfoCapture,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+}
+
+/// The metrics that an [`crate::EventHandler`] reports to its [`crate::MetricsRecorder`]. See
+/// [`crate::EventHandler::set_metrics_recorder`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EvhMetric {
+	/// A counter of the connections accepted. See [`crate::EvhStats::accepts`].
+	Accepts,
+	/// A counter of the connections closed. See [`crate::EvhStats::closes`].
+	Closes,
+	/// A counter of the reads completed. See [`crate::EvhStats::reads`].
+	Reads,
+	/// A counter of the bytes read. See [`crate::EvhStats::bytes_read`].
+	BytesRead,
+	/// A counter of the bytes written, both directly by [`crate::WriteHandle::write`] and by
+	/// the event loop for data that could not be written right away.
+	BytesWritten,
+	/// A gauge of the accepted and client connections that are open.
+	CurrentConnections,
+	/// A gauge of the bytes that have been queued for writing but not written yet. Data sent
+	/// with [`crate::WriteHandle::send_file`] is not included.
+	PendingWriteBytes,
+	/// A counter of the microseconds the threads spent processing events, not including the
+	/// time they spent waiting for events.
+	EventLoopMicros,
+}
+
+/// A recorder of the metrics of an [`crate::EventHandler`], i.e. an adapter for an external
+/// monitoring system. See [`crate::EventHandler::set_metrics_recorder`].
+pub trait MetricsRecorder {
+	/// Add `by` to the counter `metric`.
+	fn incr_counter(&self, metric: EvhMetric, by: u64);
+	/// Set the gauge `metric` to `value`.
+	fn set_gauge(&self, metric: EvhMetric, value: u64);
+}
+
+/// A process signal that an [`crate::EventHandler`] may shut down on. See
+/// [`crate::EventHandler::handle_signals`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Signal {
+	/// SIGTERM on unix. On windows, the console being closed or the system shutting down.
+	Term,
+	/// SIGINT on unix. On windows, Ctrl-C or Ctrl-Break.
+	Int,
+}
+
+/// How an [`crate::EventHandler`] shuts down when a signal is received. See
+/// [`crate::EventHandler::handle_signals`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShutdownMode {
+	/// Stop right away. All connections are closed.
+	Immediate,
+	/// Stop accepting connections and wait up to the specified number of milliseconds for
+	/// the open connections to be closed before stopping.
+	Graceful(usize),
+}
+
+/// The state of one of the threads of an [`crate::EventHandler`]. See
+/// [`crate::EventHandler::thread_health`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum ThreadHealth {
+	/// The thread has not finished initializing yet.
+	Starting,
+	/// The thread is processing events.
+	Running,
+	/// The thread exited because the [`crate::EventHandler`] was stopped.
+	Stopped,
+	/// The thread exited with the specified error.
+	Failed(String),
+}
+
+/// The readiness that a raw handle is registered for. See
+/// [`crate::EventHandler::register_raw`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interest {
+	/// Report when the handle is readable.
+	Read,
+	/// Report when the handle is writable.
+	Write,
+	/// Report when the handle is readable or writable.
+	ReadWrite,
+}
+
+/// The reason that a [`crate::Connection`] was closed. This value is passed to the OnClose
+/// handler. See [`crate::EventHandler::set_on_close`].
+#[derive(Debug)]
+pub enum CloseReason {
+	/// The remote peer closed the connection.
+	PeerClosed,
+	/// An error occurred while reading from the connection.
+	ReadError(Error),
+	/// An error occurred while writing to the connection.
+	WriteError(Error),
+	/// No read slabs were available to store data for the connection. See the
+	/// `EvhReadSlabCount`, `EvhReadSlabCountPerThread` and `EvhOutOfSlabsMessage`
+	/// configuration options.
+	OutOfSlabs,
+	/// The connection did not send required data in time. Currently, this is only used for
+	/// the PROXY protocol header. See the `EvhProxyHeaderTimeoutMillis` configuration option.
+	IdleTimeout,
+	/// The connection was closed locally by calling [`crate::WriteHandle::close`].
+	LocalClose,
+	/// The OnRead handler panicked while processing the connection.
+	Panic,
+	/// A callback for the connection ran for longer than `EvhCallbackTimeoutMillis` and its
+	/// thread was replaced. The connection is closed once the callback returns. See the
+	/// `EvhWatchdogAction` configuration option.
+	CallbackTimeout,
+	/// The inbound or outbound transform of the connection returned an error. See
+	/// [`crate::Connection::set_inbound_transform`] and
+	/// [`crate::Connection::set_outbound_transform`].
+	TransformError(Error),
+	/// The memory in use exceeded `EvhMemoryBudgetBytes` and the connection was one of the
+	/// largest consumers. See [`crate::MemoryPressure::CloseConnections`].
+	MemoryPressure,
+	/// The thread of the connection was replaced by the watchdog while the connection held read
+	/// data that had not been cleared. The data stays with the stuck thread so the replacement
+	/// thread closes the connection. See the `EvhWatchdogAction` configuration option.
+	ThreadReplaced,
 }
 
 /// A chunk of data returned by the [`crate::EventHandler`]. Chunks are of a maximum size defined
@@ -251,6 +903,39 @@ pub trait UserContext {
 	/// # See Also
 	/// [`crate`], [`crate::UserContext`], [`crate::UserContext::get_user_data`]
 	fn set_user_data(&mut self, user_data: Box<dyn Any + Send + Sync>);
+	/// Returns whether the current [`crate::EventHandler::set_on_read`] callback is the
+	/// synthetic invocation requested by [`crate::WriteHandle::trigger_on_read`] as opposed to
+	/// a callback resulting from data being read from the connection.
+	/// # Input Parameters
+	/// none
+	/// # Returns
+	/// true if this callback was requested by [`crate::WriteHandle::trigger_on_read`],
+	/// otherwise false.
+	/// # See Also
+	/// [`crate`], [`crate::UserContext`], [`crate::WriteHandle::trigger_on_read`]
+	fn is_synthetic(&self) -> bool;
+	/// Move a [`crate::Connection`] of this thread to another thread of the
+	/// [`crate::EventHandler`] so that, for example, all the connections with the same shard
+	/// key are processed by the same thread and its thread local caches stay hot. The
+	/// connection is moved once the current callback returns and its callbacks are called on
+	/// the new thread from then on. Existing [`crate::WriteHandle`]s continue to work. Data
+	/// that was read but not consumed is dropped, so this is usually called after
+	/// [`crate::UserContext::clear_all`]. The request is ignored if the connection is closed
+	/// before it is moved, is already on the hinted thread or is a loopback connection.
+	/// # Input Parameters
+	/// * `connection_id` - the [`crate::Connection::id`] of a connection of this thread.
+	/// * `thread_hint` - the thread to move the connection to. Values that are not less
+	///   than the number of threads wrap modulo the number of threads.
+	/// # Returns
+	/// [`unit`] on success, otherwise [`bmw_err::Error`].
+	/// # See Also
+	/// [`crate`], [`crate::UserContext`], [`crate::EvhStats::thread_connections`]
+	fn reassign_connection(&mut self, connection_id: u128, thread_hint: usize)
+		-> Result<(), Error>;
+	// the raw bytes of a read slab, whether or not it is allocated. Used to verify that the
+	// slabs of sensitive connections are zeroized.
+	#[cfg(test)]
+	fn raw_read_slab(&self, slab_id: usize) -> Result<Vec<u8>, Error>;
 }
 
 /// The [`crate::Connection`] struct represents a connection. It may be either a server side
@@ -268,12 +953,110 @@ pub struct Connection {
 	pub(crate) write_state: Box<dyn LockBox<WriteState>>,
 	pub(crate) wakeup: Option<Wakeup>,
 	pub(crate) state: Option<Box<dyn LockBox<EventHandlerState>>>,
-	pub(crate) tx: Option<SyncSender<()>>,
+	// receives the result of the registration of a server or client connection
+	pub(crate) tx: Option<SyncSender<Result<(), Error>>>,
 	pub(crate) ctype: ConnectionType,
 	pub(crate) debug_info: DebugInfo,
 	pub(crate) origin_id: u128,
 	pub(crate) write_final: bool,
 	pub(crate) disable_write_final: bool,
+	// bytes of the PROXY protocol header read so far. Some until the header is consumed.
+	pub(crate) proxy_header: Option<Vec<u8>>,
+	// data that followed the PROXY protocol header in the same read
+	pub(crate) proxy_data: Vec<u8>,
+	// the source address advertised in the PROXY protocol header
+	pub(crate) peer_addr: Option<SocketAddr>,
+	// the instance id of the EventHandler that the connection belongs to or 0
+	pub(crate) evh_instance_id: u64,
+	// true until the first data is read if EvhRequireDataWithinMillis is configured
+	pub(crate) awaiting_data: bool,
+	// set when the connection is registered with an EventHandler thread
+	pub(crate) capture_writer: Option<CaptureWriter>,
+	// set once capture is enabled for this connection
+	pub(crate) capture: Option<Capture>,
+	// serialized data set with Connection::set_attachment. It travels with an exported handle.
+	pub(crate) attachment: Option<Vec<u8>>,
+	// set by Connection::export_handle. The evh thread deregisters the handle without closing it.
+	pub(crate) exported: bool,
+	// set while the connection is moved to another thread by UserContext::reassign_connection.
+	// The thread that receives it does not treat it as a new connection.
+	pub(crate) migrated: bool,
+	// set once on_accept has returned. on_read and on_close are not called before that.
+	pub(crate) accepted: bool,
+	// the number of read slabs in this connection's chain
+	pub(crate) owned_slabs: usize,
+	// set when reading stopped because of EvhMaxSlabsPerConnection
+	pub(crate) slab_quota_paused: bool,
+	// set when reading stopped because of MemoryPressure::PauseReads
+	pub(crate) memory_paused: bool,
+	// set with Connection::set_max_unread_bytes. 0 if reads are not limited.
+	pub(crate) max_unread_bytes: usize,
+	// the number of bytes in this connection's read slabs that have not been cleared
+	pub(crate) unread_bytes: usize,
+	// set when reading stopped because unread_bytes reached max_unread_bytes
+	pub(crate) unread_paused: bool,
+	// set once the peer has shut down its write side and the OnReadEof handler was executed
+	pub(crate) read_eof: bool,
+	// the time, in milliseconds since the epoch, at which data was last read or the
+	// connection was created
+	pub(crate) last_read_millis: usize,
+	// whether write interest is registered with the selector. It is only registered while
+	// data is pending.
+	pub(crate) write_interest: WriteInterest,
+	// set with Connection::set_inbound_transform. Shared with a replica of the connection.
+	pub(crate) inbound_transform: Option<Arc<Mutex<Transform>>>,
+	// inbound data that was already transformed but did not fit in the slab it was read into
+	pub(crate) inbound_pending: Vec<u8>,
+	// set for connections built with EvhBuilder::build_loopback_pair. The handle is synthetic.
+	pub(crate) loopback: Option<Loopback>,
+	// set with Connection::set_sensitive
+	pub(crate) sensitive: bool,
+	// set with Connection::set_response_extractor. Shared with a replica of the connection.
+	pub(crate) response_extractor: Option<Arc<Mutex<ResponseExtractor>>>,
+	// set with Connection::set_unmatched_response_handler
+	pub(crate) unmatched_response_handler: Option<Arc<Mutex<UnmatchedResponseHandler>>>,
+}
+
+/// The [`crate::HousekeeperContext`] is passed to the OnHousekeeper handler. See
+/// [`crate::EventHandler::set_on_housekeeper`]. It only gives access to the connections of the
+/// thread that is executing the handler, so no locking is needed to enumerate them. Listeners
+/// and connections whose OnAccept handler has not been executed yet are not included.
+pub struct HousekeeperContext<'a> {
+	pub(crate) id_hash: &'a HashMap<u128, ConnectionVariant>,
+	pub(crate) now: usize,
+	pub(crate) close_requests: Vec<(u128, CloseReason)>,
+}
+
+/// A [`crate::Connection`] that has been removed from its [`crate::EventHandler`] with
+/// [`crate::Connection::export_handle`]. The underlying handle is still open and is owned by
+/// whoever holds this struct. It may be added to another [`crate::EventHandler`] in the same
+/// process via [`crate::EvhBuilder::import_connection`] or passed to another process. To do
+/// the latter, send [`crate::ExportedHandle::handle`] over a unix domain socket (SCM_RIGHTS)
+/// along with the bytes returned by [`crate::ExportedHandle::state`] and rebuild it on the
+/// other side with [`crate::ExportedHandle::new`].
+#[derive(Debug, Clone)]
+pub struct ExportedHandle {
+	pub(crate) handle: Handle,
+	pub(crate) state: Vec<u8>,
+}
+
+/// The parts of a [`crate::Connection`] id. Connection ids are u128 values. The upper 64 bits
+/// are the instance id of whatever assigned the id and the lower 64 bits are a counter that it
+/// increments for each id that it assigns. Accepted connections are assigned ids by the
+/// [`crate::EventHandler`] that accepted them, so the instance id is the one returned by
+/// [`crate::EventHandler::instance_id`]. Server and client connections are assigned ids when
+/// they are built by the [`crate::EvhBuilder`], which has its own instance id, so that the id
+/// is known before the connection is added. Instance ids are random and never reused within a
+/// process, so connection ids are unique for the lifetime of the process, even when several
+/// [`crate::EventHandler`]s are running or one is dropped and rebuilt. The
+/// [`std::convert::From`] implementations convert to and from the u128 value, which is what
+/// should be stored (i.e. as a key in a bmw_util hashtable).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionId {
+	/// The instance id of the [`crate::EventHandler`] that assigned the id.
+	pub instance_id: u64,
+	/// The value of the [`crate::EventHandler`]'s counter when the id was assigned.
+	pub counter: u64,
 }
 
 /// Builder struct for the crate. All implementations are created through this struct.
@@ -290,6 +1073,61 @@ pub struct WriteHandle {
 	pub(crate) wakeup: Wakeup,
 	pub(crate) state: Box<dyn LockBox<EventHandlerState>>,
 	pub(crate) debug_info: DebugInfo,
+	pub(crate) loopback: Option<Loopback>,
+}
+
+/// The format of the data written by [`crate::WriteHandle::write_compressed`] and
+/// [`crate::WriteHandle::begin_compressed_stream`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompressionFormat {
+	/// The gzip format (RFC 1952). This is the HTTP `gzip` content coding.
+	Gzip,
+	/// The zlib format (RFC 1950). This is the HTTP `deflate` content coding.
+	Deflate,
+}
+
+/// A compressed stream of data written to a connection. Returned by
+/// [`crate::WriteHandle::begin_compressed_stream`]. Data passed to
+/// [`crate::CompressedStream::write`] is compressed in blocks and the compressed data is written
+/// to the connection as it is produced. [`crate::CompressedStream::finish`] writes the rest of
+/// the compressed data. If the stream is dropped without calling finish, it is finished and any
+/// errors are ignored.
+pub struct CompressedStream {
+	pub(crate) wh: WriteHandle,
+	// None once the stream is finished
+	pub(crate) encoder: Option<Encoder>,
+}
+
+/// The response to a request written with [`crate::WriteHandle::request`]. The response is
+/// the data returned for the request's correlation_id by the connection's
+/// [`crate::ResponseExtractor`]. It may be waited for with [`crate::ResponseFuture::wait`] or
+/// awaited, since [`std::future::Future`] is implemented. If no response is read within the
+/// request's timeout, the result is an [`bmw_err::ErrKind::Timeout`] error. If the connection
+/// closes first, it is an [`bmw_err::ErrKind::ConnectionClosed`] error.
+pub struct ResponseFuture {
+	pub(crate) correlation_id: u64,
+	pub(crate) slot: Arc<ResponseSlot>,
+}
+
+// where the evh thread stores the result of a request for its ResponseFuture
+pub(crate) struct ResponseSlot {
+	pub(crate) state: Mutex<ResponseSlotState>,
+	pub(crate) cond: Condvar,
+}
+
+pub(crate) struct ResponseSlotState {
+	pub(crate) result: Option<Result<Vec<u8>, Error>>,
+	pub(crate) waker: Option<Waker>,
+}
+
+pub(crate) struct PendingResponse {
+	pub(crate) deadline: usize,
+	pub(crate) slot: Arc<ResponseSlot>,
+}
+
+pub(crate) enum Encoder {
+	Gzip(GzEncoder<Vec<u8>>),
+	Deflate(ZlibEncoder<Vec<u8>>),
 }
 
 /// Statistical information for the [`crate::EventHandler`]. This struct may be retrieved by
@@ -326,6 +1164,145 @@ pub struct EvhStats {
 	/// last statistical interval. See [`crate::EventHandler::wait_for_stats`]. See also
 	/// [`crate::EvhStats::delay_writes`].
 	pub bytes_delay_write: u128,
+	/// The number of times the [`crate::EventHandler`] threads were woken up by writing to
+	/// their wakeup pipe in the last statistical interval. See
+	/// [`crate::EventHandler::wait_for_stats`]. Notifications for a thread that already has
+	/// a wakeup pending, or that is not blocked waiting for events, are coalesced and do not
+	/// write to the pipe.
+	pub wakeup_writes: usize,
+	/// The number of connections closed for each [`crate::CloseReason`] in the last
+	/// statistical interval. See [`crate::EventHandler::wait_for_stats`].
+	pub close_reasons: CloseReasonStats,
+	/// The utilization of each thread's read slab allocator, indexed by thread id. Unlike the
+	/// other fields, these values are not reset at each statistical interval. Each entry is
+	/// the state as of the thread's last stats update.
+	pub read_slabs: Vec<ReadSlabStats>,
+	/// The number of connections of each thread, indexed by thread id. Like
+	/// [`crate::EvhStats::read_slabs`], each entry is the state as of the thread's last stats
+	/// update.
+	pub thread_connections: Vec<usize>,
+	/// The number of connections moved to another thread by
+	/// [`crate::UserContext::reassign_connection`] in the last statistical interval.
+	pub reassignments: usize,
+	/// The number of times the watchdog found a callback that had been running for longer
+	/// than [`bmw_conf::ConfigOption::EvhCallbackTimeoutMillis`] in the last statistical
+	/// interval. Each callback is only counted once no matter how long it runs.
+	pub watchdog_trips: usize,
+	/// The number of capture records that were dropped because the capture writer could not
+	/// keep up in the last statistical interval. See [`crate::Connection::enable_capture`].
+	pub capture_drops: usize,
+	/// The number of event loops in which registering accepted connections and calling
+	/// on_accept used up [`bmw_conf::ConfigOption::EvhOnAcceptBudgetMicros`] and the rest of
+	/// the connections were deferred to a later loop, in the last statistical interval.
+	pub accept_burst_deferrals: usize,
+	/// The longest time in microseconds that a thread spent registering accepted connections
+	/// and calling on_accept in one event loop during the last statistical interval.
+	pub max_accept_micros: usize,
+	/// The name of the [`crate::EventHandler`] as configured with
+	/// [`bmw_conf::ConfigOption::EvhName`] or [`None`] if no name was configured.
+	pub name: Option<String>,
+	/// The memory in bytes in use by the [`crate::EventHandler`] as counted against
+	/// [`bmw_conf::ConfigOption::EvhMemoryBudgetBytes`] when the stats were retrieved. 0 if no
+	/// budget is configured. See [`crate::MemoryPressure`].
+	pub memory_used: usize,
+	/// The configured [`bmw_conf::ConfigOption::EvhMemoryBudgetBytes`] or 0 if no budget is
+	/// configured.
+	pub memory_budget: usize,
+	/// The [`crate::MemoryPressure`] when the stats were retrieved.
+	pub memory_pressure: MemoryPressure,
+	/// The number of times the [`crate::MemoryPressure`] was raised in the last statistical
+	/// interval.
+	pub memory_pressure_events: usize,
+	/// The number of connections whose reads were paused due to
+	/// [`crate::MemoryPressure::PauseReads`] in the last statistical interval.
+	pub memory_reads_paused: usize,
+	/// The number of accepted connections that were closed right away due to
+	/// [`crate::MemoryPressure::RejectAccepts`] in the last statistical interval.
+	pub memory_accepts_rejected: usize,
+	/// The number of times the memory in use went back within the budget in the last
+	/// statistical interval.
+	pub memory_recoveries: usize,
+	// counters broken down by origin_id. See [`crate::EvhStats::per_origin`].
+	pub(crate) origins: HashMap<u128, OriginStats>,
+}
+
+/// The number of connections closed for each [`crate::CloseReason`]. See
+/// [`crate::EvhStats::close_reasons`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CloseReasonStats {
+	/// Connections closed with [`crate::CloseReason::PeerClosed`].
+	pub peer_closed: usize,
+	/// Connections closed with [`crate::CloseReason::ReadError`].
+	pub read_error: usize,
+	/// Connections closed with [`crate::CloseReason::WriteError`].
+	pub write_error: usize,
+	/// Connections closed with [`crate::CloseReason::OutOfSlabs`].
+	pub out_of_slabs: usize,
+	/// Connections closed with [`crate::CloseReason::IdleTimeout`].
+	pub idle_timeout: usize,
+	/// Connections closed with [`crate::CloseReason::LocalClose`].
+	pub local_close: usize,
+	/// Connections closed with [`crate::CloseReason::Panic`].
+	pub panic: usize,
+	/// Connections closed with [`crate::CloseReason::CallbackTimeout`].
+	pub callback_timeout: usize,
+	/// Connections closed with [`crate::CloseReason::TransformError`].
+	pub transform_error: usize,
+	/// Connections closed with [`crate::CloseReason::MemoryPressure`].
+	pub memory_pressure: usize,
+	/// Connections closed with [`crate::CloseReason::ThreadReplaced`].
+	pub thread_replaced: usize,
+}
+
+/// The steps taken by an [`crate::EventHandler`] while the memory it uses exceeds the budget
+/// set with `EvhMemoryBudgetBytes`. The memory in use is the fixed cost of the read slab
+/// arenas and per thread structures plus the data queued for writing, the attachments (see
+/// [`crate::Connection::set_attachment_size_hint`]) and the queued capture records. It is
+/// checked at each housekeeping interval (`EvhHouseKeeperFrequencyMillis`). While the budget is
+/// exceeded, the pressure is raised by one step per interval, in the order of the variants
+/// below. Each step keeps the measures of the previous ones. Once the memory in use is within
+/// the budget again, the pressure goes back to [`crate::MemoryPressure::None`], the paused
+/// connections are read again and new connections are accepted. See
+/// [`crate::EvhStats::memory_pressure`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemoryPressure {
+	/// The memory in use is within the budget or no budget is configured.
+	None,
+	/// Each thread stops reading from its connections that use the most memory until the
+	/// memory they use covers the amount that the budget is exceeded by. Their data is left
+	/// in the socket so that no more responses are queued for them.
+	PauseReads,
+	/// Newly accepted connections are closed right away.
+	RejectAccepts,
+	/// Each thread closes the connection that uses the most memory with
+	/// [`crate::CloseReason::MemoryPressure`] at each housekeeping interval.
+	CloseConnections,
+}
+
+/// Utilization of a single thread's read slab allocator. See [`crate::EvhStats::read_slabs`]
+/// and the `EvhReadSlabCountPerThread` configuration option.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadSlabStats {
+	/// The number of read slabs currently allocated.
+	pub used: usize,
+	/// The number of read slabs currently available.
+	pub free: usize,
+	/// The highest number of read slabs that have been allocated at once since the
+	/// [`crate::EventHandler`] was started.
+	pub high_water: usize,
+}
+
+/// The counters for a single origin. See [`crate::EvhStats::per_origin`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OriginStats {
+	/// The number of connections accepted on this origin in the last statistical interval.
+	pub accepts: usize,
+	/// The number of reads completed on connections of this origin in the last statistical
+	/// interval.
+	pub reads: usize,
+	/// The total number of bytes read on connections of this origin in the last statistical
+	/// interval.
+	pub bytes_read: u128,
 }
 
 #[derive(Clone, Debug)]
@@ -342,6 +1319,21 @@ pub struct DebugInfo {
 	pub(crate) internal_panic: Box<dyn LockBox<bool>>,
 	pub(crate) get_events_error: Box<dyn LockBox<bool>>,
 	pub(crate) os_error: Box<dyn LockBox<bool>>,
+	pub(crate) fd_limit: Box<dyn LockBox<Option<usize>>>,
+	// reads return at most this many bytes per call
+	pub(crate) partial_read: Box<dyn LockBox<Option<usize>>>,
+	// (probability, seed) of a read or write spuriously returning WouldBlock
+	pub(crate) eagain_probability: Box<dyn LockBox<Option<(f64, u64)>>>,
+	pub(crate) accept_eagain_once: Box<dyn LockBox<bool>>,
+	// the threads wait before get_events while this is set
+	pub(crate) hold_loop: Box<dyn LockBox<bool>>,
+	// the number of times write interest was registered and deregistered. They are shared
+	// with the DebugInfo passed to set_debug_info so that tests can read them.
+	pub(crate) write_interest_registers: Box<dyn LockBox<usize>>,
+	pub(crate) write_interest_deregisters: Box<dyn LockBox<usize>>,
+	// handles that received an injected WouldBlock and must be re-armed
+	#[cfg(test)]
+	pub(crate) faults: Box<dyn LockBox<std::collections::HashSet<Handle>>>,
 }
 
 // crate local structures
@@ -349,33 +1341,263 @@ pub struct DebugInfo {
 pub(crate) struct EventHandlerState {
 	pub(crate) nconnections: VecDeque<ConnectionVariant>,
 	pub(crate) write_queue: VecDeque<u128>,
+	// the ids of the loopback connections that have data to read (or reached EOF)
+	pub(crate) read_queue: VecDeque<u128>,
+	pub(crate) raw_deregister: Vec<(u64, SyncSender<()>)>,
 	pub(crate) stop: bool,
+	// set to stop accepting connections before a graceful shutdown
+	pub(crate) drain: bool,
+	// the number of connections the thread still has open while draining
+	pub(crate) remaining: Option<usize>,
+	// set by the thread once it has initialized and when it exits
+	pub(crate) health: ThreadHealth,
 }
 
+// one direction of a loopback pair. See EvhBuilder::build_loopback_pair.
+pub(crate) struct LoopbackBuffer {
+	pub(crate) data: VecDeque<u8>,
+	pub(crate) capacity: usize,
+	// set when the writing side is closed. The reader sees EOF once the data is read.
+	pub(crate) write_closed: bool,
+	// set when the reading side is closed. Writes fail from then on.
+	pub(crate) read_closed: bool,
+	// the EventHandler threads of the two sides. Set once they are registered.
+	pub(crate) reader: Option<LoopbackWaker>,
+	pub(crate) writer: Option<LoopbackWaker>,
+}
+
+// the EventHandler thread that a side of a loopback pair is registered with
+#[derive(Clone)]
+pub(crate) struct LoopbackWaker {
+	pub(crate) id: u128,
+	pub(crate) wakeup: Wakeup,
+	pub(crate) state: Box<dyn LockBox<EventHandlerState>>,
+}
+
+// one side of a loopback pair. The inbound buffer of one side is the outbound buffer of the
+// other side.
+#[derive(Clone)]
+pub(crate) struct Loopback {
+	pub(crate) inbound: Arc<Mutex<LoopbackBuffer>>,
+	pub(crate) outbound: Arc<Mutex<LoopbackBuffer>>,
+}
+
+pub(crate) struct RawRegistration {
+	pub(crate) handle: Handle,
+	pub(crate) id: u128,
+	pub(crate) token: u64,
+	pub(crate) interest: Interest,
+	pub(crate) close_on_stop: bool,
+	pub(crate) tx: Option<SyncSender<()>>,
+}
+
+pub(crate) type OnRawEvent = Box<dyn FnMut(u64, bool, bool) -> Result<(), Error> + Send + Sync>;
+
+pub(crate) type OnShutdown = Box<dyn FnMut() -> Result<(), Error> + Send + Sync>;
+
+// an EventHandler that shuts down when one of the signals in the mask is received. See
+// EventHandler::handle_signals.
+pub(crate) struct SignalRegistration {
+	pub(crate) signals: usize,
+	pub(crate) mode: ShutdownMode,
+	pub(crate) controller: EvhController,
+	pub(crate) on_shutdown: Box<dyn LockBox<Option<OnShutdown>>>,
+}
+
+pub(crate) type OnDataChunk =
+	Box<dyn FnMut(&mut Connection, &[u8]) -> Result<ChunkAction, Error> + Send + Sync>;
+
+pub(crate) type OnSlabQuota = Box<
+	dyn FnMut(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error> + Send + Sync,
+>;
+
+pub(crate) type OnReadEof = Box<
+	dyn FnMut(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error> + Send + Sync,
+>;
+
+pub(crate) type OnPanicInfo = Box<
+	dyn FnMut(&mut Box<dyn UserContext + '_>, &PanicInfoCapture) -> Result<(), Error> + Send + Sync,
+>;
 #[derive(Clone)]
 pub(crate) struct Wakeup {
 	pub(crate) id: u128,
 	pub(crate) reader: Handle,
 	pub(crate) writer: Handle,
-	pub(crate) requested: Box<dyn LockBox<bool>>,
-	pub(crate) needed: Box<dyn LockBox<bool>>,
+	// set when there is work for the thread that it has not yet picked up
+	pub(crate) pending: Arc<AtomicBool>,
+	// set while the thread is (about to be) blocked waiting for events
+	pub(crate) blocking: Arc<AtomicBool>,
+	// the number of times a byte was written to the wakeup pipe
+	pub(crate) writes: Arc<AtomicUsize>,
 }
 
 pub(crate) struct WriteState {
 	pub(crate) flags: u8,
 	pub(crate) write_buffer: Vec<u8>,
+	pub(crate) trigger_on_read_count: usize,
+	pub(crate) pending_files: VecDeque<PendingFile>,
+	// data passed to WriteHandle::write while a CompressedStream is open. It is queued when
+	// the stream is finished.
+	pub(crate) deferred: Vec<u8>,
+	// set if capture is enabled for the connection so that writes are recorded
+	pub(crate) capture: Option<Capture>,
+	// set when the connection is registered if a MetricsRecorder is set
+	pub(crate) metrics: Option<Arc<Metrics>>,
+	// the delay applied to each flush and the rate limit in bytes per second (0 is off). See
+	// Connection::set_write_delay_millis and Connection::set_write_rate_limit_bytes_per_sec.
+	pub(crate) write_delay_millis: usize,
+	pub(crate) rate_limit: usize,
+	// queued data is not written before this time (0 if the current flush is not held)
+	pub(crate) hold_until: usize,
+	// the token bucket of the rate limit and the last time it was refilled
+	pub(crate) tokens: usize,
+	pub(crate) last_refill: usize,
+	// set by write_loop if it must be called again at this time to continue writing
+	pub(crate) next_write: Option<usize>,
+	// set with Connection::set_outbound_transform. Data is transformed before it is queued.
+	pub(crate) outbound_transform: Option<Mutex<Transform>>,
+	// the error returned by the outbound transform. It is the reason the connection is closed.
+	pub(crate) transform_error: Option<Error>,
+	// set with Connection::set_sensitive. Buffers are zeroized when their data is released.
+	pub(crate) sensitive: bool,
+	// the requests written with WriteHandle::request that are waiting for a response
+	pub(crate) responses: HashMap<u64, PendingResponse>,
+	// (deadline, correlation_id) of requests whose timeout the evh thread has not picked up
+	pub(crate) response_timers: Vec<(usize, u64)>,
+	// set when the connection is registered if EvhMemoryBudgetBytes is configured
+	pub(crate) memory: Option<MemoryBudget>,
+	// the bytes currently counted against the memory budget for this connection
+	pub(crate) accounted: usize,
+	// the length of the serialized attachment and the size set with
+	// Connection::set_attachment_size_hint, which takes precedence
+	pub(crate) attachment_len: usize,
+	pub(crate) attachment_size_hint: Option<usize>,
+	// the thread that the connection was moved to by UserContext::reassign_connection. Write
+	// handles notify it instead of the thread that they were created on.
+	pub(crate) owner: Option<(Wakeup, Box<dyn LockBox<EventHandlerState>>)>,
+}
+
+pub(crate) struct PendingFile {
+	pub(crate) file: File,
+	pub(crate) offset: u64,
+	pub(crate) remaining: u64,
+	// data written after this file region was queued. It is sent once the region completes.
+	pub(crate) trailing: Vec<u8>,
+}
+
+// the callbacks that the watchdog reports on. See CallbackWatch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum WatchedCallback {
+	Read = 1,
+	Accept = 2,
+	Close = 3,
+	Housekeeper = 4,
+	DataChunk = 5,
+	RawEvent = 6,
+	SlabQuota = 7,
+	ReadEof = 8,
+	Handler = 9,
+}
+
+// what the watchdog thread needs to replace a stuck thread
+pub(crate) struct Watchdog {
+	pub(crate) config: EventHandlerConfig,
+	pub(crate) state: Array<Box<dyn LockBox<EventHandlerState>>>,
+	pub(crate) wakeups: Array<Wakeup>,
+	pub(crate) stats: Box<dyn LockBox<GlobalStats>>,
+	pub(crate) ids: ConnectionIds,
+	pub(crate) keys: Box<dyn LockBox<KeyRegistry>>,
+	pub(crate) debug_info: DebugInfo,
+	pub(crate) watches: Vec<CallbackWatch>,
+	pub(crate) ctx_arr: Array<Box<dyn LockBox<EventHandlerContext>>>,
+	pub(crate) user_context_arr: Array<Box<dyn LockBox<UserContextImpl>>>,
+	pub(crate) capture_writer: CaptureWriter,
+	pub(crate) metrics: Option<Arc<Metrics>>,
+}
+
+// the MetricsRecorder set with EventHandler::set_metrics_recorder along with the state that is
+// shared by the threads and the write handles of their connections
+pub(crate) struct Metrics {
+	pub(crate) recorder: Box<dyn MetricsRecorder + Send + Sync>,
+	// set once the recorder panics. Nothing is recorded after that.
+	pub(crate) disabled: AtomicBool,
+	// the gauges of each thread as of its last stats update
+	pub(crate) connections: Vec<AtomicU64>,
+	pub(crate) pending_write_bytes: Vec<AtomicU64>,
+	// used to log the warning if the recorder panics
+	pub(crate) config: EventHandlerConfig,
+}
+
+// the capture writer thread of an EventHandler. The thread is started the first time a
+// connection enables capture.
+#[derive(Clone)]
+pub(crate) struct CaptureWriter {
+	pub(crate) tx: Box<dyn LockBox<Option<SyncSender<CaptureRecord>>>>,
+	pub(crate) drops: Arc<AtomicUsize>,
+	// the bytes of data in the records that are queued for the writer thread
+	pub(crate) queued_bytes: Arc<AtomicUsize>,
+}
+
+// the capture of a single connection
+#[derive(Clone)]
+pub(crate) struct Capture {
+	pub(crate) id: u128,
+	pub(crate) tx: SyncSender<CaptureRecord>,
+	pub(crate) drops: Arc<AtomicUsize>,
+	pub(crate) queued_bytes: Arc<AtomicUsize>,
+}
+
+pub(crate) enum CaptureRecord {
+	Open(u128, PathBuf),
+	// connection id, true for inbound data, timestamp in millis and the data
+	Data(u128, bool, u64, Vec<u8>),
+	Close(u128),
+}
+
+// the connections of a thread by id and whether they held read data after they were last
+// processed
+pub(crate) type TrackedConnections = HashMap<u128, (Connection, bool)>;
+
+// state of a thread that is shared with the watchdog thread (see EvhCallbackTimeoutMillis)
+#[derive(Clone)]
+pub(crate) struct CallbackWatch {
+	// the time (millis since a monotonic base, see watch_millis) that the current callback
+	// started or 0 if none is running
+	pub(crate) started: Arc<AtomicUsize>,
+	// the WatchedCallback that is running
+	pub(crate) callback: Arc<AtomicUsize>,
+	// the id of the connection the callback was called for (upper and lower 64 bits)
+	pub(crate) id_high: Arc<AtomicU64>,
+	pub(crate) id_low: Arc<AtomicU64>,
+	// incremented each time a replacement thread takes over
+	pub(crate) generation: Arc<AtomicUsize>,
+	// the connections owned by the thread. Only maintained with SpawnReplacement.
+	pub(crate) connections: Option<Box<dyn LockBox<TrackedConnections>>>,
 }
 
 pub(crate) struct GlobalStats {
 	pub(crate) stats: EvhStats,
 	pub(crate) update_counter: usize,
 	pub(crate) tx: Option<SyncSender<()>>,
+	// the number of connections owned by each thread as of its last stats update
+	pub(crate) handle_counts: Vec<usize>,
+	pub(crate) listen_addrs: Vec<SocketAddr>,
+	pub(crate) last_fd_warning: Option<usize>,
+	pub(crate) fd_warnings: usize,
+	// the number of times an event loop thread took this lock to publish its thread_stats
+	pub(crate) stats_locks: usize,
 }
 
 pub(crate) struct UserContextImpl {
 	pub(crate) read_slabs: Box<dyn SlabAllocator + Send + Sync>,
 	pub(crate) user_data: Option<Box<dyn Any + Send + Sync>>,
 	pub(crate) slab_cur: usize,
+	pub(crate) synthetic: bool,
+	pub(crate) read_slab_high_water: usize,
+	// (connection_id, thread_hint) requested with UserContext::reassign_connection
+	pub(crate) reassignments: Vec<(u128, usize)>,
+	// the connections that were paused by Connection::set_max_unread_bytes and had data cleared
+	pub(crate) resumed_reads: Vec<u128>,
 }
 
 #[derive(Clone)]
@@ -388,6 +1610,67 @@ pub(crate) struct EventHandlerConfig {
 	pub(crate) housekeeping_frequency_millis: usize,
 	pub(crate) stats_update_frequency_millis: usize,
 	pub(crate) out_of_slabs_message: String,
+	pub(crate) client_bind_addr: Option<SocketAddr>,
+	pub(crate) proxy_protocol: bool,
+	pub(crate) proxy_header_timeout_millis: usize,
+	pub(crate) defer_accept_secs: Option<usize>,
+	pub(crate) require_data_within_millis: Option<usize>,
+	pub(crate) max_write_bytes_per_loop: usize,
+	pub(crate) fd_limit: Option<usize>,
+	pub(crate) fd_warn_threshold_percent: usize,
+	pub(crate) callback_timeout_millis: Option<usize>,
+	pub(crate) watchdog_action: WatchdogAction,
+	pub(crate) recv_buffer_size: usize,
+	pub(crate) send_buffer_size: usize,
+	pub(crate) strict_sock_opts: bool,
+	pub(crate) capture_all: Option<PathBuf>,
+	pub(crate) name: Option<String>,
+	pub(crate) strict_ordering: bool,
+	pub(crate) strict_keys: bool,
+	pub(crate) start_timeout_millis: usize,
+	// EvhOnAcceptBudgetMicros (0 is no limit)
+	pub(crate) accept_budget_micros: usize,
+	pub(crate) max_slabs_per_connection: Option<usize>,
+	// EvhSlabQuotaAction is "Close" rather than "Pause"
+	pub(crate) slab_quota_close: bool,
+	// set if EvhMemoryBudgetBytes is configured
+	pub(crate) memory: Option<MemoryBudget>,
+	// set by EventHandler::set_logger. Shared by all clones of the config.
+	pub(crate) logger: Box<dyn LockBox<Option<SharedLogger>>>,
+	// the options changed by EventHandler::update_config. Shared by all clones of the config.
+	pub(crate) reloadable: Box<dyn LockBox<ReloadableConfig>>,
+	// incremented each time the reloadable options change
+	pub(crate) reload_generation: Arc<AtomicU64>,
+	// the recent snapshots of the reloadable options. Shared by all clones of the config.
+	pub(crate) config_history: Box<dyn LockBox<ConfigHistory>>,
+}
+
+// the accounting of EvhMemoryBudgetBytes. Shared by all clones of the config and the write states
+// of the connections. See MemoryPressure.
+#[derive(Clone)]
+pub(crate) struct MemoryBudget {
+	pub(crate) budget: usize,
+	// the read slab arenas and per thread structures. Set by start.
+	pub(crate) fixed: Arc<AtomicUsize>,
+	// the queued write data and the attachments of the connections
+	pub(crate) variable: Arc<AtomicUsize>,
+	// the data queued for the capture writer thread (CaptureWriter::queued_bytes)
+	pub(crate) capture: Arc<AtomicUsize>,
+	// the current MemoryPressure as a usize
+	pub(crate) pressure: Arc<AtomicUsize>,
+	// the time (millis since the epoch) that the pressure was last changed
+	pub(crate) changed_at: Arc<AtomicUsize>,
+}
+
+// a snapshot of the options that may be changed while the EventHandler is running. The threads
+// copy it into their own EventHandlerConfig when reload_generation changes.
+#[derive(Clone, Default)]
+pub(crate) struct ReloadableConfig {
+	pub(crate) timeout: u16,
+	pub(crate) housekeeping_frequency_millis: usize,
+	pub(crate) stats_update_frequency_millis: usize,
+	pub(crate) out_of_slabs_message: String,
+	pub(crate) max_write_bytes_per_loop: usize,
 }
 pub(crate) struct EventHandlerImpl<OnRead, OnAccept, OnClose, OnHousekeeper, OnPanic>
 where
@@ -403,13 +1686,13 @@ where
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnClose: FnMut(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnClose: FnMut(&mut Connection, &CloseReason, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnHousekeeper: FnMut(&mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnHousekeeper: FnMut(&mut HousekeeperContext<'_>, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
@@ -427,9 +1710,31 @@ where
 	pub(crate) state: Array<Box<dyn LockBox<EventHandlerState>>>,
 	pub(crate) wakeups: Array<Wakeup>,
 	pub(crate) stopper: Option<ThreadPoolStopper>,
+	// the thread pool that runs the event loops. Dropping it shuts it down, so it is kept
+	// until the EventHandler is dropped.
+	pub(crate) thread_pool: Option<Box<dyn Any + Send + Sync>>,
 	pub(crate) stats: Box<dyn LockBox<GlobalStats>>,
 	pub(crate) debug_info: DebugInfo,
 	pub(crate) has_controller: bool,
+	// set by evh_oro!. Only on_read (or on_data_chunk) is required and the other closure
+	// callbacks may not be set.
+	pub(crate) on_read_only: bool,
+	pub(crate) raw_tokens: HashMap<u64, usize>,
+	pub(crate) ids: ConnectionIds,
+	pub(crate) watches: Vec<CallbackWatch>,
+	pub(crate) capture_writer: CaptureWriter,
+	pub(crate) on_shutdown: Box<dyn LockBox<Option<OnShutdown>>>,
+	pub(crate) keys: Box<dyn LockBox<KeyRegistry>>,
+	pub(crate) metrics: Option<Arc<Metrics>>,
+}
+
+// the keys registered with EventHandler::register_key. The write handles of all open
+// connections are kept so that a key can be registered for any of them. Shared by the
+// threads and controllers of an EventHandler.
+pub(crate) struct KeyRegistry {
+	pub(crate) keys: HashMap<u64, u128>,
+	// connection id -> (write handle, the keys registered for the connection)
+	pub(crate) connections: HashMap<u128, (WriteHandle, Vec<u64>)>,
 }
 
 #[derive(Clone)]
@@ -447,13 +1752,13 @@ where
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnClose: FnMut(&mut Connection, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnClose: FnMut(&mut Connection, &CloseReason, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
 		+ Sync
 		+ Unpin,
-	OnHousekeeper: FnMut(&mut Box<dyn UserContext + '_>) -> Result<(), Error>
+	OnHousekeeper: FnMut(&mut HousekeeperContext<'_>, &mut Box<dyn UserContext + '_>) -> Result<(), Error>
 		+ Send
 		+ 'static
 		+ Clone
@@ -471,6 +1776,12 @@ where
 	pub(crate) on_close: Option<Pin<Box<OnClose>>>,
 	pub(crate) on_panic: Option<Pin<Box<OnPanic>>>,
 	pub(crate) on_housekeeper: Option<Pin<Box<OnHousekeeper>>>,
+	pub(crate) on_raw_event: Option<Box<dyn LockBox<OnRawEvent>>>,
+	pub(crate) on_data_chunk: Option<Box<dyn LockBox<OnDataChunk>>>,
+	pub(crate) on_slab_quota: Option<Box<dyn LockBox<OnSlabQuota>>>,
+	pub(crate) on_read_eof: Option<Box<dyn LockBox<OnReadEof>>>,
+	pub(crate) on_panic_info: Option<Box<dyn LockBox<OnPanicInfo>>>,
+	pub(crate) handler: Option<Box<dyn LockBox<Box<dyn ProtocolHandler + Send + Sync>>>>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -490,6 +1801,17 @@ pub(crate) struct Event {
 pub(crate) enum EventTypeIn {
 	Read,
 	Write,
+	// drop the write interest of a handle that is registered with EventTypeIn::Write and keep
+	// the read interest
+	DeregisterWrite,
+	Raw(Interest),
+}
+
+// the write interest registration state of a connection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum WriteInterest {
+	NotRegistered,
+	Registered,
 }
 
 pub(crate) struct EventIn {
@@ -504,14 +1826,44 @@ pub(crate) struct EventHandlerContext {
 	pub(crate) in_events: Vec<EventIn>,
 	pub(crate) handle_hash: HashMap<Handle, u128>,
 	pub(crate) id_hash: HashMap<u128, ConnectionVariant>,
+	pub(crate) raw_token_hash: HashMap<u64, Handle>,
 	pub(crate) wakeups: Array<Wakeup>,
 	pub(crate) tid: usize,
 	pub(crate) last_housekeeping: usize,
 	pub(crate) trigger_on_read_list: Vec<Handle>,
 	pub(crate) trigger_itt: usize,
+	// read and write events of loopback connections, which are not registered with the
+	// selector
+	pub(crate) loopback_events: Vec<Event>,
+	pub(crate) loopback_itt: usize,
 	pub(crate) thread_stats: EvhStats,
 	pub(crate) global_stats: Box<dyn LockBox<GlobalStats>>,
 	pub(crate) last_stats_update: usize,
+	// (connection id, deadline) for connections still waiting on a PROXY protocol header
+	pub(crate) proxy_deadlines: VecDeque<(u128, usize)>,
+	// (connection id, deadline) for connections that have not sent any data yet
+	pub(crate) data_deadlines: VecDeque<(u128, usize)>,
+	pub(crate) ids: ConnectionIds,
+	// connections taken from EventHandlerState::nconnections that are being registered
+	pub(crate) pending_connections: VecDeque<ConnectionVariant>,
+	// set if EvhCallbackTimeoutMillis is configured
+	pub(crate) watch: Option<CallbackWatch>,
+	// the CallbackWatch generation that this context belongs to
+	pub(crate) generation: usize,
+	// connections taken over from a replaced thread that are closed because their read data
+	// stayed with that thread
+	pub(crate) replacement_closes: Vec<Handle>,
+	pub(crate) capture_writer: Option<CaptureWriter>,
+	pub(crate) metrics: Option<Arc<Metrics>>,
+	// set once the thread has closed its server connections for a graceful shutdown
+	pub(crate) draining: bool,
+	// (time, connection id) of the delayed or rate limited writes to resume
+	pub(crate) write_timers: BinaryHeap<Reverse<(usize, u128)>>,
+	// (deadline, connection id, correlation_id) of requests written with WriteHandle::request
+	pub(crate) response_timers: BinaryHeap<Reverse<(usize, u128, u64)>>,
+	pub(crate) keys: Box<dyn LockBox<KeyRegistry>>,
+	// the ids of the connections whose reads were paused by MemoryPressure::PauseReads
+	pub(crate) memory_paused: Vec<u128>,
 
 	#[cfg(target_os = "linux")]
 	pub(crate) linux_ctx: LinuxContext,
@@ -521,11 +1873,21 @@ pub(crate) struct EventHandlerContext {
 	pub(crate) windows_ctx: WindowsContext,
 }
 
+// assigns ids to the accepted connections, wakeups and raw registrations of an EventHandler
+// (and the connections built by EvhBuilder). The counter is shared by all threads. See
+// ConnectionId for the format.
+#[derive(Clone)]
+pub(crate) struct ConnectionIds {
+	pub(crate) instance_id: u64,
+	pub(crate) counter: Arc<AtomicU64>,
+}
+
 pub(crate) enum ConnectionVariant {
 	ServerConnection(Connection),
 	ClientConnection(Connection),
 	Connection(Connection),
 	Wakeup(Wakeup),
+	Raw(RawRegistration),
 }
 
 #[derive(PartialEq)]
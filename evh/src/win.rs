@@ -16,8 +16,10 @@
 // limitations under the License.
 
 use crate::constants::*;
+use crate::evh::{connect_error, connect_happy_eyeballs, connect_with_timeout, signal_mask};
 use crate::types::{
-	DebugInfo, Event, EventHandlerConfig, EventHandlerContext, EventType, EventTypeIn,
+	DebugInfo, Event, EventHandlerConfig, EventHandlerContext, EventType, EventTypeIn, Interest,
+	Signal,
 };
 use bmw_deps::bitvec::vec::BitVec;
 use bmw_deps::errno::{errno, set_errno, Errno};
@@ -27,23 +29,39 @@ use bmw_deps::wepoll_sys::{
 	epoll_create, epoll_ctl, epoll_data_t, epoll_event, epoll_wait, EPOLLIN, EPOLLONESHOT,
 	EPOLLOUT, EPOLLRDHUP, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD,
 };
+use bmw_deps::windows_sys::Win32::Foundation::BOOL;
 use bmw_deps::windows_sys::Win32::Networking::WinSock::{
-	accept, closesocket, ioctlsocket, recv, send, setsockopt, shutdown, SD_SEND, SOCKADDR,
+	accept, bind, closesocket, connect, getsockopt, ioctlsocket, recv, send, setsockopt, shutdown,
+	socket, WSAGetLastError, AF_INET, AF_INET6, INVALID_SOCKET, IPPROTO_TCP, SD_SEND, SOCKADDR,
+	SOCKADDR_IN, SOCKADDR_IN6, SOCKADDR_STORAGE, SOCKET_ERROR, SOCK_STREAM,
+};
+use bmw_deps::windows_sys::Win32::System::Console::{
+	SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT, CTRL_LOGOFF_EVENT,
+	CTRL_SHUTDOWN_EVENT,
 };
 use bmw_err::*;
 use bmw_log::*;
-use std::mem::{size_of, zeroed};
-use std::net::{TcpListener, TcpStream};
+use std::fs::File;
+use std::mem::{size_of, zeroed, ManuallyDrop};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
 use std::os::raw::{c_int, c_void};
-use std::os::windows::io::IntoRawSocket;
+use std::os::windows::fs::FileExt;
+use std::os::windows::io::{FromRawSocket, IntoRawSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::Duration;
 
 info!();
 
 const SOL_SOCKET: c_int = 0xFFFF;
 const SO_SNDBUF: c_int = 0x1001;
+const SO_RCVBUF: c_int = 0x1002;
 const WINSOCK_BUF_SIZE: c_int = 100_000_000;
+// size of the buffer used to emulate sendfile
+const SENDFILE_CHUNK_SIZE: usize = 64 * 1024;
 
-pub(crate) type Handle = usize;
+/// The underlying handle type used by the [`crate::EventHandler`] on this platform.
+pub type Handle = usize;
 
 pub(crate) struct WindowsContext {
 	selector: Handle,
@@ -68,6 +86,23 @@ pub(crate) fn write_impl(handle: Handle, buf: &[u8]) -> Result<isize, Error> {
 	Ok(try_into!(res)?)
 }
 
+pub(crate) fn sendfile_impl(
+	handle: Handle,
+	file: &File,
+	offset: u64,
+	len: u64,
+) -> Result<isize, Error> {
+	// TransmitFile blocks the calling thread, so emulate sendfile with a read and a
+	// non-blocking send. Only the bytes accepted by send are consumed.
+	let mut buf = vec![0u8; SENDFILE_CHUNK_SIZE];
+	let max: usize = try_into!(len.min(SENDFILE_CHUNK_SIZE as u64))?;
+	let rlen = file.seek_read(&mut buf[0..max], offset)?;
+	if rlen == 0 {
+		return Ok(0);
+	}
+	write_impl(handle, &buf[0..rlen])
+}
+
 pub(crate) fn wakeup_impl() -> Result<(Handle, Handle), Error> {
 	let (port, listener) = loop {
 		let port = pick_unused_port().unwrap_or(random());
@@ -89,6 +124,56 @@ pub(crate) fn wakeup_impl() -> Result<(Handle, Handle), Error> {
 	Ok((listener_handle, stream_handle))
 }
 
+// the signals received since the last call to wait_signal_impl
+static SIGNALS_PENDING: AtomicUsize = AtomicUsize::new(0);
+// the signals that install_signal_handler_impl was called for
+static SIGNALS_INSTALLED: AtomicUsize = AtomicUsize::new(0);
+static SIGNAL_LOCK: Mutex<()> = Mutex::new(());
+static SIGNAL_CONDVAR: Condvar = Condvar::new();
+
+// windows executes this on a new thread so there are no restrictions like in a unix handler
+unsafe extern "system" fn ctrl_handler(ctrl_type: u32) -> BOOL {
+	let mask = match ctrl_type {
+		CTRL_C_EVENT | CTRL_BREAK_EVENT => SIGNAL_MASK_INT,
+		CTRL_CLOSE_EVENT | CTRL_LOGOFF_EVENT | CTRL_SHUTDOWN_EVENT => SIGNAL_MASK_TERM,
+		_ => 0,
+	} & SIGNALS_INSTALLED.load(Ordering::SeqCst);
+	if mask == 0 {
+		// let the next handler (by default the one that exits the process) handle it
+		return 0;
+	}
+	SIGNALS_PENDING.fetch_or(mask, Ordering::SeqCst);
+	let _lock = SIGNAL_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+	SIGNAL_CONDVAR.notify_all();
+	1
+}
+
+// calls must be serialized by the caller
+pub(crate) fn install_signal_handler_impl(signal: Signal) -> Result<(), Error> {
+	if SIGNALS_INSTALLED.load(Ordering::SeqCst) == 0 {
+		if unsafe { SetConsoleCtrlHandler(Some(ctrl_handler), 1) } == 0 {
+			let text = format!("could not install the console control handler: {}", errno());
+			return Err(err!(ErrKind::IO, text));
+		}
+	}
+	SIGNALS_INSTALLED.fetch_or(signal_mask(&signal), Ordering::SeqCst);
+	Ok(())
+}
+
+// blocks until a signal is received and returns the mask of the signals received
+pub(crate) fn wait_signal_impl() -> Result<usize, Error> {
+	let mut lock = SIGNAL_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+	loop {
+		let pending = SIGNALS_PENDING.swap(0, Ordering::SeqCst);
+		if pending != 0 {
+			return Ok(pending);
+		}
+		lock = SIGNAL_CONDVAR
+			.wait(lock)
+			.unwrap_or_else(PoisonError::into_inner);
+	}
+}
+
 pub(crate) fn shutdown_impl(handle: Handle) -> Result<(), Error> {
 	unsafe {
 		shutdown(handle, SD_SEND);
@@ -143,6 +228,58 @@ pub(crate) fn close_impl_ctx(handle: Handle, ctx: &mut EventHandlerContext) -> R
 	Ok(())
 }
 
+pub(crate) fn deregister_raw_impl(
+	handle: Handle,
+	ctx: &mut EventHandlerContext,
+) -> Result<(), Error> {
+	let handle_as_usize: usize = try_into!(handle)?;
+
+	if handle_as_usize >= ctx.windows_ctx.filter_set.len() {
+		ctx.windows_ctx
+			.filter_set
+			.resize(handle_as_usize + 100, false);
+	}
+
+	ctx.windows_ctx.filter_set.replace(handle_as_usize, false);
+
+	let data = epoll_data_t {
+		fd: try_into!(handle)?,
+	};
+
+	let mut event = epoll_event { events: 0, data };
+
+	set_errno(Errno(0));
+	let res = unsafe {
+		epoll_ctl(
+			ctx.windows_ctx.selector as *mut c_void,
+			EPOLL_CTL_DEL as i32,
+			handle_as_usize,
+			&mut event,
+		)
+	};
+
+	if res < 0 {
+		let e = errno();
+		warn!(
+			"epoll_ctl del error: {}, fd = {}, tid = {}",
+			e, handle, ctx.tid
+		)?;
+	}
+
+	Ok(())
+}
+
+fn raw_interest_flags(interest: &Interest) -> u32 {
+	let mut flags = EPOLLONESHOT;
+	if *interest == Interest::Read || *interest == Interest::ReadWrite {
+		flags |= EPOLLIN | EPOLLRDHUP;
+	}
+	if *interest == Interest::Write || *interest == Interest::ReadWrite {
+		flags |= EPOLLOUT;
+	}
+	flags
+}
+
 pub(crate) fn read_impl(
 	handle: Handle,
 	buf: &mut [u8],
@@ -161,9 +298,7 @@ pub(crate) fn read_impl(
 }
 
 pub(crate) fn accept_impl(handle: Handle, debug_info: &DebugInfo) -> Result<Option<Handle>, Error> {
-	if debug_info.is_os_error() {
-		return Err(err!(ErrKind::Test, "os error"));
-	}
+	set_errno(Errno(0));
 	let handle = unsafe {
 		accept(
 			handle,
@@ -172,21 +307,225 @@ pub(crate) fn accept_impl(handle: Handle, debug_info: &DebugInfo) -> Result<Opti
 		)
 	};
 
-	if handle == usize::MAX {
-		Ok(None)
-	} else {
-		Ok(Some(handle))
+	if handle == INVALID_SOCKET {
+		// as on the other platforms, the os error is simulated in place of would block
+		if errno().0 == WINNONBLOCKING && !debug_info.is_os_error() {
+			return Ok(None);
+		}
+		let fmt = format!("accept failed: {}", errno());
+		return Err(err!(ErrKind::IO, fmt));
+	}
+
+	Ok(Some(handle))
+}
+
+// there is no equivalent of TCP_DEFER_ACCEPT on this platform
+pub(crate) fn set_defer_accept_impl(handle: Handle, _secs: usize) -> Result<(), Error> {
+	warn!(
+		"EvhDeferAcceptSecs is not supported on this platform. Ignoring for handle {}",
+		handle
+	)?;
+	Ok(())
+}
+
+pub(crate) fn set_recv_buffer_size_impl(handle: Handle, size: usize) -> Result<(), Error> {
+	set_buffer_size(handle, SO_RCVBUF, size, "SO_RCVBUF")
+}
+
+pub(crate) fn set_send_buffer_size_impl(handle: Handle, size: usize) -> Result<(), Error> {
+	set_buffer_size(handle, SO_SNDBUF, size, "SO_SNDBUF")
+}
+
+pub(crate) fn recv_buffer_size_impl(handle: Handle) -> Result<usize, Error> {
+	buffer_size(handle, SO_RCVBUF, "SO_RCVBUF")
+}
+
+pub(crate) fn send_buffer_size_impl(handle: Handle) -> Result<usize, Error> {
+	buffer_size(handle, SO_SNDBUF, "SO_SNDBUF")
+}
+
+fn set_buffer_size(handle: Handle, opt: c_int, size: usize, name: &str) -> Result<(), Error> {
+	let optval: c_int = try_into!(size)?;
+	let res = unsafe {
+		setsockopt(
+			handle,
+			SOL_SOCKET,
+			opt,
+			&optval as *const _ as *const u8,
+			size_of::<c_int>() as c_int,
+		)
+	};
+	if res != 0 {
+		let text = format!("setting {} failed: {}", name, errno());
+		return Err(err!(ErrKind::IO, text));
+	}
+	Ok(())
+}
+
+fn buffer_size(handle: Handle, opt: c_int, name: &str) -> Result<usize, Error> {
+	let mut optval: c_int = 0;
+	let mut len = size_of::<c_int>() as c_int;
+	let res = unsafe {
+		getsockopt(
+			handle,
+			SOL_SOCKET,
+			opt,
+			&mut optval as *mut _ as *mut u8,
+			&mut len,
+		)
+	};
+	if res != 0 {
+		let text = format!("getting {} failed: {}", name, errno());
+		return Err(err!(ErrKind::IO, text));
 	}
+	Ok(try_into!(optval)?)
+}
+
+pub(crate) fn peer_addr_impl(handle: Handle) -> Result<SocketAddr, Error> {
+	// borrow the socket without taking ownership so it's not closed when dropped
+	let strm = ManuallyDrop::new(unsafe { TcpStream::from_raw_socket(try_into!(handle)?) });
+	Ok(strm.peer_addr()?)
+}
+
+pub(crate) fn local_addr_impl(handle: Handle) -> Result<SocketAddr, Error> {
+	// borrow the socket without taking ownership so it's not closed when dropped
+	let strm = ManuallyDrop::new(unsafe { TcpStream::from_raw_socket(try_into!(handle)?) });
+	Ok(strm.local_addr()?)
+}
+
+// there is no file descriptor limit comparable to RLIMIT_NOFILE on windows
+pub(crate) fn fd_limit_impl() -> Option<usize> {
+	None
 }
 
 pub(crate) fn create_connection(host: &str, port: u16) -> Result<Handle, Error> {
-	let strm = TcpStream::connect(format!("{}:{}", host, port))?;
+	let strm = TcpStream::connect(format!("{}:{}", host, port)).map_err(|e| {
+		let text = format!("could not connect to {}:{}: {}", host, port, e);
+		connect_error(&text, ConnectErrorKind::from(&e))
+	})?;
+	strm.set_nonblocking(true)?;
+	let fd = strm.into_raw_socket();
+
+	Ok(try_into!(fd)?)
+}
+
+pub(crate) fn create_connection_timeout(
+	host: &str,
+	port: u16,
+	timeout: Duration,
+) -> Result<Handle, Error> {
+	let strm = connect_with_timeout(host, port, timeout)?;
 	strm.set_nonblocking(true)?;
 	let fd = strm.into_raw_socket();
 
 	Ok(try_into!(fd)?)
 }
 
+pub(crate) fn create_connection_happy_eyeballs(
+	addrs: &[SocketAddr],
+	attempt_delay: Duration,
+) -> Result<Handle, Error> {
+	let strm = connect_happy_eyeballs(addrs, attempt_delay)?;
+	strm.set_nonblocking(true)?;
+	let fd = strm.into_raw_socket();
+
+	Ok(try_into!(fd)?)
+}
+
+pub(crate) fn create_connection_bound(
+	host: &str,
+	port: u16,
+	local_addr: SocketAddr,
+) -> Result<Handle, Error> {
+	let mut last_err = None;
+	for remote in (host, port).to_socket_addrs()? {
+		// only addresses in the same family as the local address can be used
+		if remote.is_ipv4() != local_addr.is_ipv4() {
+			continue;
+		}
+
+		let family = match local_addr {
+			SocketAddr::V4(_) => AF_INET,
+			SocketAddr::V6(_) => AF_INET6,
+		};
+		let fd = unsafe { socket(family.into(), SOCK_STREAM, IPPROTO_TCP) };
+		if fd == INVALID_SOCKET {
+			let text = format!("could not create socket: {}", unsafe { WSAGetLastError() });
+			return Err(err!(ErrKind::IO, text));
+		}
+		// take ownership so the socket is closed if bind or connect fails
+		let strm = unsafe { TcpStream::from_raw_socket(try_into!(fd)?) };
+
+		let (addr, len) = to_sockaddr(&local_addr);
+		if unsafe { bind(fd, &addr as *const _ as *const SOCKADDR, len) } == SOCKET_ERROR {
+			let text = format!(
+				"could not bind to local address {}: {}",
+				local_addr,
+				unsafe { WSAGetLastError() }
+			);
+			return Err(err!(ErrKind::IO, text));
+		}
+
+		let (addr, len) = to_sockaddr(&remote);
+		if unsafe { connect(fd, &addr as *const _ as *const SOCKADDR, len) } == SOCKET_ERROR {
+			last_err = Some(unsafe { WSAGetLastError() });
+			continue;
+		}
+
+		strm.set_nonblocking(true)?;
+		return Ok(try_into!(strm.into_raw_socket())?);
+	}
+
+	match last_err {
+		Some(e) => {
+			let text = format!(
+				"could not connect to {}:{} from local address {}: {}",
+				host, port, local_addr, e
+			);
+			Err(connect_error(&text, ConnectErrorKind::from_raw_os_error(e)))
+		}
+		None => {
+			let text = format!(
+				"no address for {}:{} matches the family of local address {}",
+				host, port, local_addr
+			);
+			Err(err!(ErrKind::IO, text))
+		}
+	}
+}
+
+pub(crate) fn interface_index(name: &str) -> Result<u32, Error> {
+	let text = format!("interface names are not supported as scope ids: {}", name);
+	Err(err!(ErrKind::IllegalArgument, text))
+}
+
+fn to_sockaddr(addr: &SocketAddr) -> (SOCKADDR_STORAGE, i32) {
+	let mut storage: SOCKADDR_STORAGE = unsafe { zeroed() };
+	let len = match addr {
+		SocketAddr::V4(addr) => {
+			let sin = &mut storage as *mut _ as *mut SOCKADDR_IN;
+			unsafe {
+				(*sin).sin_family = AF_INET;
+				(*sin).sin_port = addr.port().to_be();
+				(*sin).sin_addr.S_un.S_addr = u32::from_ne_bytes(addr.ip().octets());
+			}
+			size_of::<SOCKADDR_IN>()
+		}
+		SocketAddr::V6(addr) => {
+			let sin6 = &mut storage as *mut _ as *mut SOCKADDR_IN6;
+			unsafe {
+				(*sin6).sin6_family = AF_INET6;
+				(*sin6).sin6_port = addr.port().to_be();
+				(*sin6).sin6_flowinfo = addr.flowinfo();
+				(*sin6).sin6_addr.u.Byte = addr.ip().octets();
+				(*sin6).Anonymous.sin6_scope_id = addr.scope_id();
+			}
+			size_of::<SOCKADDR_IN6>()
+		}
+	};
+	(storage, len as i32)
+}
+
 pub(crate) fn create_listener(
 	addr: &str,
 	_size: usize,
@@ -212,70 +551,7 @@ pub(crate) fn update_ctx(
 	handle: Handle,
 	etype: EventTypeIn,
 ) -> Result<(), Error> {
-	let fd_usize: usize = try_into!(handle)?;
-	let fd_i32: i32 = try_into!(handle)?;
-
-	let filter_len = ctx.windows_ctx.filter_set.len();
-	if fd_usize >= filter_len {
-		ctx.windows_ctx.filter_set.resize(fd_usize + 100, false);
-	}
-
-	let mut event = if etype == EventTypeIn::Read {
-		debug!("proc handle adding read to {}", handle)?;
-		let data = epoll_data_t { fd: fd_i32 };
-		let event = epoll_event {
-			events: EPOLLIN | EPOLLONESHOT | EPOLLRDHUP,
-			data,
-		};
-		event
-	} else {
-		let data = epoll_data_t { fd: fd_i32 };
-		let event = epoll_event {
-			events: EPOLLIN | EPOLLOUT | EPOLLONESHOT | EPOLLRDHUP,
-			data,
-		};
-		event
-	};
-
-	if *ctx.windows_ctx.filter_set.get(fd_usize).unwrap() {
-		set_errno(Errno(0));
-		let res = unsafe {
-			epoll_ctl(
-				ctx.windows_ctx.selector as *mut c_void,
-				try_into!(EPOLL_CTL_MOD)?,
-				fd_usize,
-				&mut event,
-			)
-		};
-		if res < 0 {
-			warn!(
-				"epoll_ctl1: {}, handle={}, tid={}",
-				errno(),
-				handle,
-				ctx.tid
-			)?;
-		}
-	} else {
-		set_errno(Errno(0));
-		let res = unsafe {
-			epoll_ctl(
-				ctx.windows_ctx.selector as *mut c_void,
-				try_into!(EPOLL_CTL_ADD)?,
-				fd_usize,
-				&mut event,
-			)
-		};
-		if res < 0 {
-			warn!(
-				"epoll_ctl1: {}, handle={}, tid={}",
-				errno(),
-				handle,
-				ctx.tid
-			)?;
-		}
-	}
-
-	Ok(())
+	register(&mut ctx.windows_ctx, ctx.tid, handle, &etype)
 }
 
 pub(crate) fn get_events(
@@ -292,69 +568,62 @@ pub(crate) fn get_events_in(
 	ctx: &mut EventHandlerContext,
 ) -> Result<(), Error> {
 	for evt in &ctx.in_events {
-		let fd_usize: usize = try_into!(evt.handle)?;
-		let fd_i32: i32 = try_into!(evt.handle)?;
-		let filter_len = ctx.windows_ctx.filter_set.len();
-		if fd_usize >= filter_len {
-			ctx.windows_ctx.filter_set.resize(fd_usize + 100, false);
-		}
-		let mut event = if evt.etype == EventTypeIn::Read {
-			debug!("proc handle adding read to {}", evt.handle)?;
-			let data = epoll_data_t { fd: fd_i32 };
-			let event = epoll_event {
-				events: EPOLLIN | EPOLLONESHOT | EPOLLRDHUP,
-				data,
-			};
-			event
-		} else {
-			let data = epoll_data_t { fd: fd_i32 };
-			let event = epoll_event {
-				events: EPOLLIN | EPOLLOUT | EPOLLONESHOT | EPOLLRDHUP,
-				data,
-			};
-			event
-		};
-		if *ctx.windows_ctx.filter_set.get(fd_usize).unwrap() {
-			set_errno(Errno(0));
-			let res = unsafe {
-				epoll_ctl(
-					ctx.windows_ctx.selector as *mut c_void,
-					try_into!(EPOLL_CTL_MOD)?,
-					fd_usize,
-					&mut event,
-				)
-			};
-			if res < 0 {
-				warn!(
-					"epoll_ctl1: {}, handle={}, tid={}",
-					errno(),
-					evt.handle,
-					ctx.tid
-				)?;
-			}
-		} else {
-			set_errno(Errno(0));
-			let res = unsafe {
-				epoll_ctl(
-					ctx.windows_ctx.selector as *mut c_void,
-					try_into!(EPOLL_CTL_ADD)?,
-					fd_usize,
-					&mut event,
-				)
-			};
-			if res < 0 {
-				warn!(
-					"epoll_ctl1: {}, handle={}, tid={}",
-					errno(),
-					evt.handle,
-					ctx.tid
-				)?;
-			}
-		};
+		register(&mut ctx.windows_ctx, ctx.tid, evt.handle, &evt.etype)?;
+	}
+
+	Ok(())
+}
+
+// wepoll has no edge triggered mode, so every registration is one shot and is re-armed via
+// update_ctx after each event. Write interest is only requested while data is pending, which
+// gives the same semantics as the edge triggered epoll and kqueue backends.
+fn event_flags(etype: &EventTypeIn) -> u32 {
+	match etype {
+		EventTypeIn::Read | EventTypeIn::DeregisterWrite => EPOLLIN | EPOLLONESHOT | EPOLLRDHUP,
+		EventTypeIn::Write => EPOLLIN | EPOLLOUT | EPOLLONESHOT | EPOLLRDHUP,
+		EventTypeIn::Raw(interest) => raw_interest_flags(interest),
+	}
+}
+
+// add the handle to the selector or re-arm it if it has already been added
+fn register(
+	windows_ctx: &mut WindowsContext,
+	tid: usize,
+	handle: Handle,
+	etype: &EventTypeIn,
+) -> Result<(), Error> {
+	let fd_usize: usize = try_into!(handle)?;
+	let fd_i32: i32 = try_into!(handle)?;
+	if fd_usize >= windows_ctx.filter_set.len() {
+		windows_ctx.filter_set.resize(fd_usize + 100, false);
+	}
+
+	debug!("proc handle registering {}", handle)?;
+	let data = epoll_data_t { fd: fd_i32 };
+	let mut event = epoll_event {
+		events: event_flags(etype),
+		data,
+	};
+	let op = if *windows_ctx.filter_set.get(fd_usize).unwrap() {
+		EPOLL_CTL_MOD
+	} else {
+		EPOLL_CTL_ADD
+	};
 
-		ctx.windows_ctx.filter_set.replace(fd_usize, true);
+	set_errno(Errno(0));
+	let res = unsafe {
+		epoll_ctl(
+			windows_ctx.selector as *mut c_void,
+			try_into!(op)?,
+			fd_usize,
+			&mut event,
+		)
+	};
+	if res < 0 {
+		warn!("epoll_ctl1: {}, handle={}, tid={}", errno(), handle, tid)?;
 	}
 
+	windows_ctx.filter_set.replace(fd_usize, true);
 	Ok(())
 }
 
@@ -368,14 +637,15 @@ pub(crate) fn get_events_out(
 	}; MAX_RET_HANDLES as usize];
 	set_errno(Errno(0));
 	let results = {
-		let (requested, _lock) = ctx.wakeups[ctx.tid].pre_block()?;
+		let timeout = ctx.events_timeout(config.timeout)?;
+		let requested = ctx.wakeups[ctx.tid].pre_block()?;
 
 		unsafe {
 			epoll_wait(
 				ctx.windows_ctx.selector as *mut c_void,
 				epoll_events.as_mut_ptr(),
 				try_into!(MAX_RET_HANDLES)?,
-				if requested { 0 } else { config.timeout.into() },
+				if requested { 0 } else { timeout.into() },
 			)
 		}
 	};
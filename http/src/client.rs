@@ -136,7 +136,7 @@ impl HttpClientImpl {
 		let config = Self::build_config(configs)?;
 		let mut evh = evh!(
 			EvhReadSlabSize(config.evh_read_slab_size),
-			EvhReadSlabCount(config.evh_read_slab_count)
+			EvhReadSlabCountPerThread(config.evh_read_slab_count)
 		)?;
 		let state: Box<dyn LockBox<HashMap<u128, HttpClientState>>> = lock_box!(HashMap::new())?;
 		let mut state_clone = state.clone();
@@ -159,8 +159,8 @@ impl HttpClientImpl {
 			Ok(())
 		})?;
 		evh.set_on_accept(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
-		evh.set_on_close(move |_connection, _ctx| -> Result<(), Error> { Ok(()) })?;
-		evh.set_on_housekeeper(move |_ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_close(move |_connection, _reason, _ctx| -> Result<(), Error> { Ok(()) })?;
+		evh.set_on_housekeeper(move |_hk, _ctx| -> Result<(), Error> { Ok(()) })?;
 		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
 		let controller = evh.controller()?;
 		evh.start()?;
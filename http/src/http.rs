@@ -152,7 +152,7 @@ impl HttpServer for HttpServerImpl {
 
 		let mut evh = evh!(
 			EvhReadSlabSize(self.config.evh_slab_size),
-			EvhReadSlabCount(self.config.evh_slab_count),
+			EvhReadSlabCountPerThread(self.config.evh_slab_count),
 			EvhOutOfSlabsMessage(oos_msg),
 			EvhHouseKeeperFrequencyMillis(self.config.evh_housekeeping_frequency),
 		)?;
@@ -179,10 +179,10 @@ impl HttpServer for HttpServerImpl {
 		evh.set_on_accept(move |connection, ctx| -> Result<(), Error> {
 			HttpServerImpl::process_on_accept(connection, ctx)
 		})?;
-		evh.set_on_close(move |connection, ctx| -> Result<(), Error> {
+		evh.set_on_close(move |connection, _reason, ctx| -> Result<(), Error> {
 			HttpServerImpl::process_on_close(connection, ctx)
 		})?;
-		evh.set_on_housekeeper(move |ctx| -> Result<(), Error> {
+		evh.set_on_housekeeper(move |_hk, ctx| -> Result<(), Error> {
 			HttpServerImpl::process_on_housekeeper(ctx, &config_clone2)
 		})?;
 		evh.set_on_panic(move |_ctx, _e| -> Result<(), Error> { Ok(()) })?;
@@ -17,15 +17,13 @@
 // limitations under the License.
 
 use crate::types::LogImpl;
-use crate::{Log, LogBuilder, LogConfig2_Options};
+use crate::{DynLogger, LogBuilder, LogConfig2_Options};
 use bmw_err::Error;
 
 impl LogBuilder {
 	/// Build a logger based based on the specified configuration. This should generally be
 	/// done by calling the [`crate::logger`] macro.
-	pub fn build_log(
-		configs: Vec<LogConfig2_Options>,
-	) -> Result<Box<dyn Log + Send + Sync>, Error> {
+	pub fn build_log(configs: Vec<LogConfig2_Options>) -> Result<DynLogger, Error> {
 		Ok(Box::new(LogImpl::new(configs)?))
 	}
 }
@@ -134,7 +134,9 @@
 //! [2022-02-24 13:52:24.123]: (TRACE) [..ibconcord/src/main.rs:136]: trace
 //! ```
 //!
-//! If enabled, color coding is included as well.
+//! If enabled, color coding is included as well. The `DisplayModulePath` option adds the
+//! module path of the caller before the file path and the `LogPrefixToken` option places a fixed
+//! token (for instance a node name) at the start of every line.
 //!
 //! Logging may be configured in many ways. The [`crate::log_init`] macro
 //! allows for convenient configuration of logging.
@@ -30,12 +30,16 @@ use bmw_deps::dyn_clone::clone_trait_object;
 use bmw_deps::rand::random;
 use bmw_deps::url_path::UrlPath;
 use bmw_err::*;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::fs::{remove_file, rename, File, OpenOptions};
 use std::io::Write;
+use std::marker::PhantomData;
 use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // convenience macro
 macro_rules! some_or_err {
@@ -61,6 +65,86 @@ macro_rules! none_or_err {
 
 clone_trait_object!(Log);
 
+thread_local! {
+	// per thread log level overrides set by LogLevelGuard. The key is the id of the
+	// standalone logger or None for the global logger.
+	static LOG_LEVEL_OVERRIDES: RefCell<HashMap<Option<u64>, LogLevel>> = RefCell::new(HashMap::new());
+	// the number of TimeScopes alive on this thread
+	static TIME_SCOPE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+impl LogLevelGuard {
+	fn new(key: Option<u64>, level: LogLevel) -> Self {
+		let prev = LOG_LEVEL_OVERRIDES.with(|overrides| overrides.borrow_mut().insert(key, level));
+		Self {
+			key,
+			prev,
+			_marker: PhantomData,
+		}
+	}
+
+	// return the current thread's override for the specified key if any
+	fn get(key: Option<u64>) -> Option<LogLevel> {
+		LOG_LEVEL_OVERRIDES.with(|overrides| overrides.borrow().get(&key).copied())
+	}
+}
+
+impl Drop for LogLevelGuard {
+	fn drop(&mut self) {
+		// try_with because the thread local may already be destroyed if the guard is
+		// dropped during thread teardown
+		let _ = LOG_LEVEL_OVERRIDES.try_with(|overrides| {
+			let mut overrides = overrides.borrow_mut();
+			match self.prev {
+				Some(prev) => overrides.insert(self.key, prev),
+				None => overrides.remove(&self.key),
+			}
+		});
+	}
+}
+
+impl TimeScope {
+	/// get the time elapsed since this scope was created.
+	pub fn elapsed(&self) -> Duration {
+		self.start.elapsed()
+	}
+}
+
+impl Drop for TimeScope {
+	fn drop(&mut self) {
+		let _ = TIME_SCOPE_DEPTH.try_with(|depth| depth.set(depth.get().saturating_sub(1)));
+		let elapsed = self.start.elapsed();
+		if let Some(threshold) = self.threshold {
+			if elapsed <= threshold {
+				return;
+			}
+		}
+		// don't format anything if the line is filtered out
+		let global_level = LogLevelGuard::get(None).unwrap_or(self.global_level);
+		if (self.level as usize) < (global_level as usize) {
+			return;
+		}
+		let indent = "  ".repeat(self.depth);
+		let line = match self.threshold {
+			Some(threshold) => format!(
+				"{}{}: elapsed {:?} (over {:?}) [depth={}]",
+				indent, self.label, elapsed, threshold, self.depth
+			),
+			None => format!(
+				"{}{}: elapsed {:?} [depth={}]",
+				indent, self.label, elapsed, self.depth
+			),
+		};
+		let _ = GlobalLogContainer::log_from(
+			self.level,
+			&line,
+			self.global_level,
+			LoggingType::Standard,
+			self.module_path,
+		);
+	}
+}
+
 impl Default for LogConfig2 {
 	fn default() -> Self {
 		Self {
@@ -73,7 +157,12 @@ impl Default for LogConfig2 {
 			display_log_level: true,
 			display_line_num: true,
 			display_millis: true,
+			display_elapsed: false,
 			display_backtrace: false,
+			display_module_path: false,
+			log_prefix_token: "".to_string(),
+			file_level: LogLevel::Trace as u8,
+			stdout_level: LogLevel::Trace as u8,
 			log_file_path: "".to_string(),
 			delete_rotation: false,
 			auto_rotate: false,
@@ -105,28 +194,150 @@ impl GlobalLogContainer {
 		global_level: LogLevel,
 		logging_type: LoggingType,
 	) -> Result<(), Error> {
-		if level as usize >= global_level as usize {
+		let record = LogRecord::new(level, line, logging_type, None);
+		Self::log_record(record, global_level)
+	}
+
+	// called by the logging macros with the module path of the call site
+	pub fn log_from(
+		level: LogLevel,
+		line: &str,
+		global_level: LogLevel,
+		logging_type: LoggingType,
+		module_path: &str,
+	) -> Result<(), Error> {
+		let record = LogRecord::new(level, line, logging_type, Some(module_path));
+		Self::log_record(record, global_level)
+	}
+
+	fn log_record(record: LogRecord, global_level: LogLevel) -> Result<(), Error> {
+		// a thread local override takes precedence over the global level
+		let global_level = LogLevelGuard::get(None).unwrap_or(global_level);
+		if record.level as usize >= global_level as usize {
 			Self::check_init()?; // check if we need to call init
 			let mut log = BMW_GLOBAL_LOG.write()?;
 
-			// call logger based on logging type (unwrap ok because check_init ensures
-			// there's a logger
-			match logging_type {
-				LoggingType::Standard => (*log).as_mut().unwrap().log(level, line)?,
-				LoggingType::Plain => (*log).as_mut().unwrap().log_plain(level, line)?,
-				LoggingType::All => (*log).as_mut().unwrap().log_all(level, line)?,
-			}
+			// unwrap ok because check_init ensures there's a logger
+			(*log).as_mut().unwrap().log_record(record)?;
 		}
 		Ok(())
 	}
 
+	// called by time_scope and time_block with the log level of the call site
+	pub fn time_scope(
+		level: LogLevel,
+		label: &'static str,
+		threshold: Option<Duration>,
+		global_level: LogLevel,
+		module_path: &'static str,
+	) -> TimeScope {
+		let depth = TIME_SCOPE_DEPTH.with(|depth| {
+			let cur = depth.get();
+			depth.set(cur + 1);
+			cur
+		});
+		TimeScope {
+			label,
+			level,
+			global_level,
+			threshold,
+			module_path,
+			depth,
+			start: Instant::now(),
+			_marker: PhantomData,
+		}
+	}
+
+	pub fn level_guard(level: LogLevel) -> LogLevelGuard {
+		LogLevelGuard::new(None, level)
+	}
+
+	// returns the number of suppressed invocations since the last logged one if this
+	// invocation should be logged, otherwise None. Only a single atomic op is done.
+	pub fn sampled(counter: &AtomicU64, n: u64) -> Option<u64> {
+		let n = n.max(1);
+		let count = counter.fetch_add(1, Ordering::Relaxed);
+		if count.is_multiple_of(n) {
+			Some(if count == 0 { 0 } else { n - 1 })
+		} else {
+			None
+		}
+	}
+
+	// returns the number of suppressed invocations since the last logged one if at least
+	// millis have passed since the last logged invocation, otherwise None. last holds the
+	// time of the last logged invocation plus one so that zero means never logged.
+	pub fn rate_limited(last: &AtomicU64, suppressed: &AtomicU64, millis: u64) -> Option<u64> {
+		let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+			Ok(d) => u64::try_from(d.as_millis())
+				.unwrap_or(u64::MAX)
+				.saturating_add(1),
+			Err(_) => 1,
+		};
+		let prev = last.load(Ordering::Relaxed);
+		if (prev == 0 || now >= prev.saturating_add(millis))
+			&& last
+				.compare_exchange(prev, now, Ordering::Relaxed, Ordering::Relaxed)
+				.is_ok()
+		{
+			Some(suppressed.swap(0, Ordering::Relaxed))
+		} else {
+			suppressed.fetch_add(1, Ordering::Relaxed);
+			None
+		}
+	}
+
+	// the global logger may only be initialized once. A default logger installed by
+	// check_init does not count and is replaced.
 	pub fn init(values: Vec<LogConfig2_Options>) -> Result<(), Error> {
 		let mut log = BMW_GLOBAL_LOG.write()?;
+		let mut init = BMW_GLOBAL_LOG_INIT.write()?;
+		if let Some(path) = &*init {
+			let text = format!(
+				"the global logger is already initialized with LogFilePath '{}'",
+				path
+			);
+			return Err(err!(ErrKind::AlreadyInitialized, text));
+		}
+		let (logger, path) = Self::build(values)?;
+		if let Some(mut prev) = (*log).replace(logger) {
+			prev.close()?;
+		}
+		(*init) = Some(path);
+		Ok(())
+	}
+
+	// the new logger is built before the lock is taken so that the logging macros only wait
+	// for the swap. They log to either the old or the new logger, never to a closed one.
+	pub fn reinit(values: Vec<LogConfig2_Options>) -> Result<(), Error> {
+		let (logger, path) = Self::build(values)?;
+		let prev = {
+			let mut log = BMW_GLOBAL_LOG.write()?;
+			let mut init = BMW_GLOBAL_LOG_INIT.write()?;
+			(*init) = Some(path);
+			(*log).replace(logger)
+		};
+		if let Some(mut prev) = prev {
+			prev.close()?;
+		}
+		Ok(())
+	}
+
+	pub fn is_initialized() -> Result<bool, Error> {
+		Ok(BMW_GLOBAL_LOG_INIT.read()?.is_some())
+	}
+
+	// build and init a global logger. Also returns its LogFilePath.
+	fn build(values: Vec<LogConfig2_Options>) -> Result<(DynLogger, String), Error> {
+		let path = values
+			.iter()
+			.find(|v| v.name() == "LogFilePath")
+			.and_then(|v| v.value_string())
+			.unwrap_or_default();
 		let mut logger = LogBuilder::build_log(values)?;
 		logger.set_log_level(LogLevel::Trace);
 		logger.init()?;
-		(*log) = Some(logger);
-		Ok(())
+		Ok((logger, path))
 	}
 
 	pub fn set_log_option(option: LogConfig2_Options) -> Result<(), Error> {
@@ -179,9 +390,13 @@ impl GlobalLogContainer {
 			}
 		}
 
-		// haven't initialized yet, so call init
+		// haven't initialized yet, so install the default logger
 		if need_init {
-			Self::init(vec![])?;
+			let (logger, _) = Self::build(vec![])?;
+			let mut log = BMW_GLOBAL_LOG.write()?;
+			if (*log).is_none() {
+				(*log) = Some(logger);
+			}
 		}
 		Ok(())
 	}
@@ -190,15 +405,20 @@ impl GlobalLogContainer {
 impl Log for LogImpl {
 	// all logging goes through the log_impl fn.
 	fn log(&mut self, level: LogLevel, line: &str) -> Result<(), Error> {
-		self.log_impl(level, line, LoggingType::Standard)
+		self.log_impl(level, line, LoggingType::Standard, None)
 	}
 	// all logging goes through the log_impl fn.
 	fn log_all(&mut self, level: LogLevel, line: &str) -> Result<(), Error> {
-		self.log_impl(level, line, LoggingType::All)
+		self.log_impl(level, line, LoggingType::All, None)
 	}
 	// all logging goes through the log_impl fn.
 	fn log_plain(&mut self, level: LogLevel, line: &str) -> Result<(), Error> {
-		self.log_impl(level, line, LoggingType::Plain)
+		self.log_impl(level, line, LoggingType::Plain, None)
+	}
+	// all logging goes through the log_impl fn.
+	fn log_record(&mut self, record: LogRecord) -> Result<(), Error> {
+		let (level, line, module_path) = (record.level, record.line, record.module_path);
+		self.log_impl(level, line, record.logging_type, module_path)
 	}
 	fn rotate(&mut self) -> Result<(), Error> {
 		if !self.is_init {
@@ -286,6 +506,9 @@ impl Log for LogImpl {
 	fn set_log_level(&mut self, log_level: LogLevel) {
 		self.log_level = log_level;
 	}
+	fn level_guard(&self, level: LogLevel) -> LogLevelGuard {
+		LogLevelGuard::new(Some(self.id), level)
+	}
 	fn init(&mut self) -> Result<(), Error> {
 		if self.is_init {
 			// init already was called
@@ -315,6 +538,7 @@ impl Log for LogImpl {
 			let mut file = self.file.write()?;
 			*file = Some(f);
 		}
+		self.epoch = Instant::now();
 		self.is_init = true;
 
 		Ok(())
@@ -343,6 +567,12 @@ impl Log for LogImpl {
 			return Err(err!(ErrKind::Log, "cannot modify log file path after init"));
 		}
 
+		if name == "FileLevel" || name == "StdoutLevel" {
+			if let Some(v) = value.value_u8() {
+				Self::check_level(name, v)?;
+			}
+		}
+
 		match value.value_u8() {
 			Some(v) => self.config.set_u8(name, v),
 			None => {}
@@ -404,6 +634,104 @@ impl Log for LogImpl {
 	fn debug_lineno_is_none(&mut self) {
 		self.config.debug_lineno_is_none = true;
 	}
+
+	#[cfg(test)]
+	fn debug_capture_stdout(&mut self) -> Arc<RwLock<Vec<u8>>> {
+		let capture = Arc::new(RwLock::new(vec![]));
+		self.debug_stdout_capture = Some(capture.clone());
+		capture
+	}
+}
+
+impl<'a> LogRecord<'a> {
+	/// Create a record of the specified line. module_path is the module that the line was
+	/// logged from (i.e. the value of [`std::module_path`] at the call site) or None if it is
+	/// not known.
+	pub fn new(
+		level: LogLevel,
+		line: &'a str,
+		logging_type: LoggingType,
+		module_path: Option<&'a str>,
+	) -> Self {
+		Self {
+			level,
+			line,
+			logging_type,
+			module_path,
+		}
+	}
+}
+
+impl SharedLogger {
+	/// Wrap the specified logger so that it can be shared. See [`crate::SharedLogger`].
+	pub fn new(logger: DynLogger) -> Self {
+		Self {
+			inner: Arc::new(RwLock::new(logger)),
+		}
+	}
+}
+
+// every call holds the lock on the shared logger so that a line is written in one piece
+impl Log for SharedLogger {
+	fn log(&mut self, level: LogLevel, line: &str) -> Result<(), Error> {
+		self.inner.write()?.log(level, line)
+	}
+	fn log_all(&mut self, level: LogLevel, line: &str) -> Result<(), Error> {
+		self.inner.write()?.log_all(level, line)
+	}
+	fn log_plain(&mut self, level: LogLevel, line: &str) -> Result<(), Error> {
+		self.inner.write()?.log_plain(level, line)
+	}
+	fn log_record(&mut self, record: LogRecord) -> Result<(), Error> {
+		self.inner.write()?.log_record(record)
+	}
+	fn rotate(&mut self) -> Result<(), Error> {
+		self.inner.write()?.rotate()
+	}
+	fn need_rotate(&self) -> Result<bool, Error> {
+		self.inner.read()?.need_rotate()
+	}
+	fn set_log_level(&mut self, level: LogLevel) {
+		let mut inner = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+		inner.set_log_level(level);
+	}
+	fn level_guard(&self, level: LogLevel) -> LogLevelGuard {
+		let inner = self.inner.read().unwrap_or_else(PoisonError::into_inner);
+		inner.level_guard(level)
+	}
+	fn init(&mut self) -> Result<(), Error> {
+		self.inner.write()?.init()
+	}
+	fn close(&mut self) -> Result<(), Error> {
+		self.inner.write()?.close()
+	}
+	fn set_config_option(&mut self, value: LogConfig2_Options) -> Result<(), Error> {
+		self.inner.write()?.set_config_option(value)
+	}
+
+	#[cfg(test)]
+	fn debug_process_resolve_frame_error(&mut self) {
+		let mut inner = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+		inner.debug_process_resolve_frame_error();
+	}
+
+	#[cfg(test)]
+	fn debug_invalid_metadata(&mut self) {
+		let mut inner = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+		inner.debug_invalid_metadata();
+	}
+
+	#[cfg(test)]
+	fn debug_lineno_is_none(&mut self) {
+		let mut inner = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+		inner.debug_lineno_is_none();
+	}
+
+	#[cfg(test)]
+	fn debug_capture_stdout(&mut self) -> Arc<RwLock<Vec<u8>>> {
+		let mut inner = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+		inner.debug_capture_stdout()
+	}
 }
 
 impl LogImpl {
@@ -450,21 +778,40 @@ impl LogImpl {
 			return Err(err!(ErrKind::Configuration, text));
 		}
 
+		Self::check_level("FileLevel", config.file_level)?;
+		Self::check_level("StdoutLevel", config.stdout_level)?;
+
 		let log_level = LogLevel::Info;
 		let cur_size = 0;
 		let file = Arc::new(RwLock::new(None));
 		let is_init = false;
 		let last_rotation = Instant::now();
+		let id = random();
 		Ok(Self {
 			config,
+			id,
 			log_level,
 			cur_size,
 			file,
 			is_init,
 			last_rotation,
+			epoch: Instant::now(),
+			#[cfg(test)]
+			debug_stdout_capture: None,
 		})
 	}
 
+	fn check_level(name: &str, level: u8) -> Result<(), Error> {
+		if level > LogLevel::Fatal as u8 {
+			let text = format!(
+				"{} must be a valid LogLevel (i.e. LogLevel::Info as u8)",
+				name
+			);
+			return Err(err!(ErrKind::Configuration, text));
+		}
+		Ok(())
+	}
+
 	fn rotate_if_needed(&mut self) -> Result<(), Error> {
 		if !self.config.auto_rotate {
 			return Ok(()); // auto rotate not enabled
@@ -490,6 +837,7 @@ impl LogImpl {
 		level: LogLevel,
 		line: &str,
 		logging_type: LoggingType,
+		module_path: Option<&str>,
 	) -> Result<(), Error> {
 		if !self.is_init {
 			let ekind = ErrKind::Log;
@@ -497,9 +845,18 @@ impl LogImpl {
 			return Err(err!(ekind, text));
 		}
 
-		if level as usize >= self.log_level as usize {
+		let log_level = LogLevelGuard::get(Some(self.id)).unwrap_or(self.log_level);
+		if level as usize >= log_level as usize {
+			// each destination has its own minimum level. If no destination will accept
+			// this record, we skip formatting entirely.
+			let show_file = self.file.read()?.is_some() && level as u8 >= self.config.file_level;
+			let show_stdout = (self.config.display_stdout || logging_type == LoggingType::All)
+				&& level as u8 >= self.config.stdout_level;
+			if !show_file && !show_stdout {
+				return Ok(());
+			}
+
 			self.rotate_if_needed()?;
-			let show_stdout = self.config.display_stdout || logging_type == LoggingType::All;
 			let show_timestamp =
 				self.config.display_timestamp && logging_type != LoggingType::Plain;
 			let show_colors = self.config.display_colors;
@@ -507,23 +864,32 @@ impl LogImpl {
 				self.config.display_log_level && logging_type != LoggingType::Plain;
 			let show_line_num = self.config.display_line_num && logging_type != LoggingType::Plain;
 			let show_millis = self.config.display_millis && logging_type != LoggingType::Plain;
+			let show_elapsed = self.config.display_elapsed && logging_type != LoggingType::Plain;
 			let show_bt =
 				self.config.display_backtrace && level as usize >= LogLevel::Error as usize;
+			// only known for records logged through the macros
+			let module_path = match self.config.display_module_path {
+				true if logging_type != LoggingType::Plain => module_path,
+				_ => None,
+			};
 			let max_len = self.config.line_num_data_max_len;
 
 			// call the main logging function with the specified params
 			self.do_log_impl(
+				show_file,
 				show_stdout,
 				show_timestamp,
 				show_colors,
 				show_log_level,
 				show_line_num,
 				show_millis,
+				show_elapsed,
 				show_bt,
 				level,
 				max_len,
 				line,
 				logging_type,
+				module_path,
 			)?;
 		}
 		Ok(())
@@ -531,18 +897,36 @@ impl LogImpl {
 
 	fn do_log_impl(
 		&mut self,
+		show_file: bool,
 		show_stdout: bool,
 		show_timestamp: bool,
 		show_colors: bool,
 		show_log_level: bool,
 		show_line_num: bool,
 		show_millis: bool,
+		show_elapsed: bool,
 		show_bt: bool,
 		level: LogLevel,
 		max_len: u64,
 		line: &str,
 		logging_type: LoggingType,
+		module_path: Option<&str>,
 	) -> Result<(), Error> {
+		// the prefix token starts every line, including plain lines, so that the lines can be
+		// filtered by it
+		if !self.config.log_prefix_token.is_empty() {
+			let token = format!("{} ", self.config.log_prefix_token);
+			if show_file {
+				let mut file = self.file.write()?;
+				if let Some(file) = (*file).as_mut() {
+					file.write_all(token.as_bytes())?;
+					self.cur_size += u64!(token.len());
+				}
+			}
+			if show_stdout {
+				self.print_stdout(&token);
+			}
+		}
 		// if timestamp needs to be shown we print/write it here
 		if show_timestamp {
 			let date = Local::now();
@@ -554,7 +938,7 @@ impl LogImpl {
 				format!("{}", date.format("%Y-%m-%d %H:%M:%S"))
 			};
 
-			{
+			if show_file {
 				let mut file = self.file.write()?;
 				match (*file).as_mut() {
 					Some(file) => {
@@ -570,15 +954,40 @@ impl LogImpl {
 
 			if show_stdout {
 				if show_colors {
-					print!("[{}]: ", formatted_timestamp.to_string().dimmed());
+					self.print_stdout(&format!("[{}]: ", formatted_timestamp.to_string().dimmed()));
 				} else {
-					print!("[{}]: ", formatted_timestamp);
+					self.print_stdout(&format!("[{}]: ", formatted_timestamp));
+				}
+			}
+		}
+		// the elapsed time since init is monotonic so durations can be computed across lines
+		if show_elapsed {
+			let elapsed = self.epoch.elapsed();
+			let formatted_elapsed =
+				format!("+{}.{:06}s", elapsed.as_secs(), elapsed.subsec_micros());
+
+			if show_file {
+				let mut file = self.file.write()?;
+				if let Some(file) = (*file).as_mut() {
+					let formatted_elapsed = format!("[{}]: ", formatted_elapsed);
+					let formatted_elapsed = formatted_elapsed.as_bytes();
+					file.write_all(formatted_elapsed)?;
+					let formatted_len: u64 = u64!(formatted_elapsed.len());
+					self.cur_size += formatted_len;
+				}
+			}
+
+			if show_stdout {
+				if show_colors {
+					self.print_stdout(&format!("[{}]: ", formatted_elapsed.dimmed()));
+				} else {
+					self.print_stdout(&format!("[{}]: ", formatted_elapsed));
 				}
 			}
 		}
 		// if log level needs to be shown we print/write it here
 		if show_log_level {
-			{
+			if show_file {
 				let mut file = self.file.write()?;
 				match (*file).as_mut() {
 					Some(file) => {
@@ -602,27 +1011,46 @@ impl LogImpl {
 					// specific colors for each level
 					match level {
 						LogLevel::Trace => {
-							print!("({})", format!("{}", level).magenta());
+							self.print_stdout(&format!("({})", format!("{}", level).magenta()));
 						}
 						LogLevel::Debug => {
-							print!("({})", format!("{}", level).cyan());
+							self.print_stdout(&format!("({})", format!("{}", level).cyan()));
 						}
 						LogLevel::Info => {
-							print!(" ({})", format!("{}", level).green());
+							self.print_stdout(&format!(" ({})", format!("{}", level).green()));
 						}
 						LogLevel::Warn => {
-							print!(" ({})", format!("{}", level).yellow());
+							self.print_stdout(&format!(" ({})", format!("{}", level).yellow()));
 						}
 						LogLevel::Error => {
-							print!("({})", format!("{}", level).bright_blue());
+							self.print_stdout(&format!("({})", format!("{}", level).bright_blue()));
 						}
 						LogLevel::Fatal => {
-							print!("({})", format!("{}", level).red());
+							self.print_stdout(&format!("({})", format!("{}", level).red()));
 						}
 					}
 				} else {
 					// without color
-					print!("({}) ", level);
+					self.print_stdout(&format!("({}) ", level));
+				}
+			}
+		}
+		if let Some(module_path) = module_path {
+			if show_file {
+				let mut file = self.file.write()?;
+				if let Some(file) = (*file).as_mut() {
+					let formatted_module_path = format!("[{}]: ", module_path);
+					let formatted_module_path = formatted_module_path.as_bytes();
+					file.write_all(formatted_module_path)?;
+					self.cur_size += u64!(formatted_module_path.len());
+				}
+			}
+
+			if show_stdout {
+				if show_colors {
+					self.print_stdout(&format!(" [{}]", module_path.blue()));
+				} else {
+					self.print_stdout(&format!(" [{}]", module_path));
 				}
 			}
 		}
@@ -657,7 +1085,7 @@ impl LogImpl {
 				logged_from_file = format!("..{}", &logged_from_file[start..]);
 			}
 
-			{
+			if show_file {
 				let mut file = self.file.write()?;
 				match (*file).as_mut() {
 					Some(file) => {
@@ -674,19 +1102,19 @@ impl LogImpl {
 			// if we're showing stdout, do so here
 			if show_stdout {
 				if show_colors {
-					print!(" [{}]", logged_from_file.yellow());
+					self.print_stdout(&format!(" [{}]", logged_from_file.yellow()));
 				} else {
-					print!(" [{}]", logged_from_file);
+					self.print_stdout(&format!(" [{}]", logged_from_file));
 				}
 			}
 		}
 
 		if show_stdout && logging_type != LoggingType::Plain {
-			print!(": ");
+			self.print_stdout(": ");
 		}
 
 		// write the line to the file (if it exists)
-		{
+		if show_file {
 			let mut file = self.file.write()?;
 			match (*file).as_mut() {
 				Some(file) => {
@@ -712,17 +1140,29 @@ impl LogImpl {
 
 		// finally print the actual line
 		if show_stdout {
-			println!("{}", line);
+			self.print_stdout(&format!("{}\n", line));
 			if show_bt {
 				let bt = Backtrace::new();
 				let bt_text = format!("{:?}", bt);
-				print!("{}", bt_text);
+				self.print_stdout(&bt_text);
 			}
 		}
 
 		Ok(())
 	}
 
+	// all stdout output goes through here so that it may be captured in tests
+	fn print_stdout(&self, s: &str) {
+		#[cfg(test)]
+		if let Some(ref capture) = self.debug_stdout_capture {
+			if let Ok(mut capture) = capture.write() {
+				capture.extend_from_slice(s.as_bytes());
+				return;
+			}
+		}
+		print!("{}", s);
+	}
+
 	fn process_resolve_frame(
 		config: &LogConfig2,
 		symbol: &Symbol,
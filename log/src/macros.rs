@@ -70,7 +70,7 @@ macro_rules! trace {
 	};
 	($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Trace, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard)
+                GlobalLogContainer::log_from(LogLevel::Trace, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard, module_path!())
 	}};
 	($line:expr,$($values:tt)*) => {
                 trace!(&format!($line, $($values)*)[..])
@@ -88,7 +88,7 @@ macro_rules! trace_plain {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Trace, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain)
+                GlobalLogContainer::log_from(LogLevel::Trace, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 trace_plain!(&format!($line, $($values)*)[..])
@@ -106,7 +106,7 @@ macro_rules! trace_all {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Trace, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All)
+                GlobalLogContainer::log_from(LogLevel::Trace, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 trace_all!(&format!($line, $($values)*)[..])
@@ -148,7 +148,7 @@ macro_rules! debug {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Debug, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard)
+                GlobalLogContainer::log_from(LogLevel::Debug, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 debug!(&format!($line, $($values)*)[..])
@@ -166,7 +166,7 @@ macro_rules! debug_plain {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Debug, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain)
+                GlobalLogContainer::log_from(LogLevel::Debug, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 debug_plain!(&format!($line, $($values)*)[..])
@@ -184,7 +184,7 @@ macro_rules! debug_all {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Debug, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All)
+                GlobalLogContainer::log_from(LogLevel::Debug, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 debug_all!(&format!($line, $($values)*)[..])
@@ -226,7 +226,7 @@ macro_rules! info {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Info, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard)
+                GlobalLogContainer::log_from(LogLevel::Info, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 info!(&format!($line, $($values)*)[..])
@@ -244,7 +244,7 @@ macro_rules! info_plain {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Info, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain)
+                GlobalLogContainer::log_from(LogLevel::Info, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 info_plain!(&format!($line, $($values)*)[..])
@@ -262,7 +262,7 @@ macro_rules! info_all {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Info, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All)
+                GlobalLogContainer::log_from(LogLevel::Info, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 info_all!(&format!($line, $($values)*)[..])
@@ -304,7 +304,7 @@ macro_rules! warn {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Warn, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard)
+                GlobalLogContainer::log_from(LogLevel::Warn, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 warn!(&format!($line, $($values)*)[..])
@@ -322,7 +322,7 @@ macro_rules! warn_plain {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Warn, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain)
+                GlobalLogContainer::log_from(LogLevel::Warn, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 warn_plain!(&format!($line, $($values)*)[..])
@@ -340,7 +340,7 @@ macro_rules! warn_all {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Warn, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All)
+                GlobalLogContainer::log_from(LogLevel::Warn, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 warn_all!(&format!($line, $($values)*)[..])
@@ -382,7 +382,7 @@ macro_rules! error {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Error, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard)
+                GlobalLogContainer::log_from(LogLevel::Error, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 error!(&format!($line, $($values)*)[..])
@@ -400,7 +400,7 @@ macro_rules! error_plain {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Error, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain)
+                GlobalLogContainer::log_from(LogLevel::Error, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 error_plain!(&format!($line, $($values)*)[..])
@@ -418,7 +418,7 @@ macro_rules! error_all {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Error, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All)
+                GlobalLogContainer::log_from(LogLevel::Error, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 error_all!(&format!($line, $($values)*)[..])
@@ -460,7 +460,7 @@ macro_rules! fatal {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Fatal, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard)
+                GlobalLogContainer::log_from(LogLevel::Fatal, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Standard, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 fatal!(&format!($line, $($values)*)[..])
@@ -478,7 +478,7 @@ macro_rules! fatal_plain {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Fatal, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain)
+                GlobalLogContainer::log_from(LogLevel::Fatal, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::Plain, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 fatal_plain!(&format!($line, $($values)*)[..])
@@ -496,20 +496,241 @@ macro_rules! fatal_all {
         };
         ($line:expr) => {{
                 use bmw_log::*;
-                GlobalLogContainer::log(LogLevel::Fatal, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All)
+                GlobalLogContainer::log_from(LogLevel::Fatal, $line, BMW_GLOBAL_LOG_LEVEL, LoggingType::All, module_path!())
         }};
         ($line:expr,$($values:tt)*) => {
                 fatal_all!(&format!($line, $($values)*)[..])
         };
 }
 
+/// Log at the [`crate::LogLevel::Info`] log level, but only every nth invocation of this call
+/// site. The first parameter is n, the remaining parameters are the same as for [`crate::info`].
+/// Logged lines have " (sampled 1/n, m suppressed since last)" appended where m is the number of
+/// invocations that were not logged since the last logged one. Suppressed invocations cost a
+/// single atomic operation and no formatting is done. Each call site keeps its own counter.
+///
+/// # Examples
+///
+///```
+/// use bmw_err::Error;
+/// use bmw_log::*;
+///
+/// info!();
+///
+/// fn main() -> Result<(), Error> {
+///     for i in 0..100 {
+///         // only 10 of these are logged
+///         info_sampled!(10, "malformed message {}", i)?;
+///     }
+///
+///     Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! info_sampled {
+	($($args:tt)*) => {
+		log_sampled_impl!(info, $($args)*)
+	};
+}
+
+/// Log at the [`crate::LogLevel::Info`] log level, but at most once per the specified number of
+/// milliseconds for this call site. The first parameter is the interval in milliseconds, the
+/// remaining parameters are the same as for [`crate::info`]. Logged lines have
+/// " (rate limited 1/<millis>ms, m suppressed since last)" appended where m is the number of
+/// invocations that were not logged since the last logged one. Suppressed invocations do no
+/// formatting. Each call site keeps its own state.
+///
+/// # Examples
+///
+///```
+/// use bmw_err::Error;
+/// use bmw_log::*;
+///
+/// info!();
+///
+/// fn main() -> Result<(), Error> {
+///     for i in 0..100 {
+///         // only the first one is logged
+///         info_rate_limited!(60_000, "malformed message {}", i)?;
+///     }
+///
+///     Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! info_rate_limited {
+	($($args:tt)*) => {
+		log_rate_limited_impl!(info, $($args)*)
+	};
+}
+
+/// Same as [`info_sampled`] except that the [`crate::LogLevel::Trace`] log level is used.
+#[macro_export]
+macro_rules! trace_sampled {
+	($($args:tt)*) => {
+		log_sampled_impl!(trace, $($args)*)
+	};
+}
+
+/// Same as [`info_rate_limited`] except that the [`crate::LogLevel::Trace`] log level is used.
+#[macro_export]
+macro_rules! trace_rate_limited {
+	($($args:tt)*) => {
+		log_rate_limited_impl!(trace, $($args)*)
+	};
+}
+
+/// Same as [`info_sampled`] except that the [`crate::LogLevel::Debug`] log level is used.
+#[macro_export]
+macro_rules! debug_sampled {
+	($($args:tt)*) => {
+		log_sampled_impl!(debug, $($args)*)
+	};
+}
+
+/// Same as [`info_rate_limited`] except that the [`crate::LogLevel::Debug`] log level is used.
+#[macro_export]
+macro_rules! debug_rate_limited {
+	($($args:tt)*) => {
+		log_rate_limited_impl!(debug, $($args)*)
+	};
+}
+
+/// Same as [`info_sampled`] except that the [`crate::LogLevel::Warn`] log level is used.
+#[macro_export]
+macro_rules! warn_sampled {
+	($($args:tt)*) => {
+		log_sampled_impl!(warn, $($args)*)
+	};
+}
+
+/// Same as [`info_rate_limited`] except that the [`crate::LogLevel::Warn`] log level is used.
+#[macro_export]
+macro_rules! warn_rate_limited {
+	($($args:tt)*) => {
+		log_rate_limited_impl!(warn, $($args)*)
+	};
+}
+
+/// Same as [`info_sampled`] except that the [`crate::LogLevel::Error`] log level is used.
+#[macro_export]
+macro_rules! error_sampled {
+	($($args:tt)*) => {
+		log_sampled_impl!(error, $($args)*)
+	};
+}
+
+/// Same as [`info_rate_limited`] except that the [`crate::LogLevel::Error`] log level is used.
+#[macro_export]
+macro_rules! error_rate_limited {
+	($($args:tt)*) => {
+		log_rate_limited_impl!(error, $($args)*)
+	};
+}
+
+/// Same as [`info_sampled`] except that the [`crate::LogLevel::Fatal`] log level is used.
+#[macro_export]
+macro_rules! fatal_sampled {
+	($($args:tt)*) => {
+		log_sampled_impl!(fatal, $($args)*)
+	};
+}
+
+/// Same as [`info_rate_limited`] except that the [`crate::LogLevel::Fatal`] log level is used.
+#[macro_export]
+macro_rules! fatal_rate_limited {
+	($($args:tt)*) => {
+		log_rate_limited_impl!(fatal, $($args)*)
+	};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_sampled_impl {
+	($log:ident, $n:expr, $line:expr) => {{
+		static BMW_LOG_SAMPLED_COUNTER: std::sync::atomic::AtomicU64 =
+			std::sync::atomic::AtomicU64::new(0);
+		let n: u64 = $n;
+		match bmw_log::GlobalLogContainer::sampled(&BMW_LOG_SAMPLED_COUNTER, n) {
+			Some(suppressed) => $log!(
+				"{} (sampled 1/{}, {} suppressed since last)",
+				$line,
+				n.max(1),
+				suppressed
+			),
+			None => Ok(()),
+		}
+	}};
+	($log:ident, $n:expr, $line:expr, $($values:tt)*) => {{
+		static BMW_LOG_SAMPLED_COUNTER: std::sync::atomic::AtomicU64 =
+			std::sync::atomic::AtomicU64::new(0);
+		let n: u64 = $n;
+		match bmw_log::GlobalLogContainer::sampled(&BMW_LOG_SAMPLED_COUNTER, n) {
+			Some(suppressed) => $log!(
+				"{} (sampled 1/{}, {} suppressed since last)",
+				format!($line, $($values)*),
+				n.max(1),
+				suppressed
+			),
+			None => Ok(()),
+		}
+	}};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_rate_limited_impl {
+	($log:ident, $millis:expr, $line:expr) => {{
+		static BMW_LOG_RATE_LIMITED_LAST: std::sync::atomic::AtomicU64 =
+			std::sync::atomic::AtomicU64::new(0);
+		static BMW_LOG_RATE_LIMITED_SUPPRESSED: std::sync::atomic::AtomicU64 =
+			std::sync::atomic::AtomicU64::new(0);
+		let millis: u64 = $millis;
+		match bmw_log::GlobalLogContainer::rate_limited(
+			&BMW_LOG_RATE_LIMITED_LAST,
+			&BMW_LOG_RATE_LIMITED_SUPPRESSED,
+			millis,
+		) {
+			Some(suppressed) => $log!(
+				"{} (rate limited 1/{}ms, {} suppressed since last)",
+				$line,
+				millis,
+				suppressed
+			),
+			None => Ok(()),
+		}
+	}};
+	($log:ident, $millis:expr, $line:expr, $($values:tt)*) => {{
+		static BMW_LOG_RATE_LIMITED_LAST: std::sync::atomic::AtomicU64 =
+			std::sync::atomic::AtomicU64::new(0);
+		static BMW_LOG_RATE_LIMITED_SUPPRESSED: std::sync::atomic::AtomicU64 =
+			std::sync::atomic::AtomicU64::new(0);
+		let millis: u64 = $millis;
+		match bmw_log::GlobalLogContainer::rate_limited(
+			&BMW_LOG_RATE_LIMITED_LAST,
+			&BMW_LOG_RATE_LIMITED_SUPPRESSED,
+			millis,
+		) {
+			Some(suppressed) => $log!(
+				"{} (rate limited 1/{}ms, {} suppressed since last)",
+				format!($line, $($values)*),
+				millis,
+				suppressed
+			),
+			None => Ok(()),
+		}
+	}};
+}
+
 /// Initialize the global log. This macro takes a list of ConfigOption, If none are
 /// specified, the default values are used. Note that if this macro
-/// is not called before logging occurs, the default configuration is used. After
-/// either this macro is called or the default is set via another logging macro,
-/// calling this macro again will result in an error. It usually makes sense to
+/// is not called before logging occurs, the default configuration is used until it is. Once
+/// this macro or [`crate::log_reinit`] has been called, calling this macro again returns an
+/// [`bmw_err::ErrKind::AlreadyInitialized`] error that contains the LogFilePath of the
+/// existing global logger, which is left unchanged. It usually makes sense to
 /// initialize this macro very early in the startup of an application so that no
-/// unanticipated logging occurs before this macro is called by mistake.
+/// unanticipated logging occurs before this macro is called by mistake. See
+/// [`crate::log_reinit`] to replace the global logger and [`crate::log_is_initialized`].
 ///
 /// # Examples
 ///
@@ -570,6 +791,56 @@ macro_rules! log_init {
 	}};
 }
 
+/// Replace the global logger with a new one built from the specified configuration. Unlike
+/// [`crate::log_init`], this may be called any number of times, for example by tests that
+/// each want their own log file. The new logger is built and its file opened first. It is
+/// then swapped in and the file of the old logger is closed. Concurrent calls to the logging
+/// macros log to either the old or the new logger. The macro returns () on success or Error
+/// on failure. The configuration options are the same as for [`crate::log_init`].
+///
+///```
+/// use bmw_err::*;
+/// use bmw_log::*;
+/// use bmw_test::*;
+/// use std::path::PathBuf;
+///
+/// info!();
+///
+/// fn main() -> Result<(), Error> {
+///     let test_info = test_info!()?;
+///     let mut buf = PathBuf::new();
+///     buf.push(test_info.directory());
+///     buf.push("./reinit.log");
+///     let buf = buf.display().to_string();
+///
+///     log_reinit!(DisplayStdout(false), LogFilePath(&buf))?;
+///     assert!(log_is_initialized!()?);
+///     info!("logged to reinit.log")?;
+///
+///     Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! log_reinit {
+	($($config:tt)*) => {{
+		use bmw_log::GlobalLogContainer;
+		use bmw_log::LogConfig2_Options::*;
+		let v: Vec<LogConfig2_Options> = vec![$($config)*];
+		GlobalLogContainer::reinit(v)
+	}};
+}
+
+/// Returns true if the global logger was initialized with [`crate::log_init`] or
+/// [`crate::log_reinit`]. The default logger that is used if the logging macros are called
+/// first does not count. The macro returns a [`bool`] on success or Error on failure.
+#[macro_export]
+macro_rules! log_is_initialized {
+	() => {{
+		use bmw_log::GlobalLogContainer;
+		GlobalLogContainer::is_initialized()
+	}};
+}
+
 /// Configure the global log with the specified ConfigOption. This macro takes
 /// a single argument. The macro returns () on success or Error on failure.
 /// See [`crate::Log::set_config_option`] which is the underlying function call for
@@ -603,6 +874,162 @@ macro_rules! need_rotate {
 	}};
 }
 
+/// Override the log level of the global logger for the current thread. The macro returns a
+/// [`crate::LogLevelGuard`] and the override remains in effect until the guard is dropped, at
+/// which point the previous level is restored. While the override is in place, it is used
+/// instead of the level set by the log level macros (i.e. [`crate::info`]) in scope. Other threads
+/// are not affected. See [`crate::Log::level_guard`] for the equivalent for standalone loggers.
+///
+/// # Examples
+///
+///```
+/// use bmw_err::Error;
+/// use bmw_log::*;
+///
+/// info!();
+///
+/// fn main() -> Result<(), Error> {
+///     trace!("this will not be logged")?;
+///     {
+///         let _guard = log_level_guard!(LogLevel::Trace);
+///         trace!("this will be logged")?;
+///     }
+///     trace!("this will not be logged either")?;
+///
+///     Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! log_level_guard {
+	($level:expr) => {{
+		use bmw_log::GlobalLogContainer;
+		GlobalLogContainer::level_guard($level)
+	}};
+}
+
+/// Returns a [`crate::TimeScope`] guard that logs the time elapsed until it is dropped, along
+/// with the specified label, at the [`crate::LogLevel::Debug`] log level. The log level set for
+/// the call site (see [`crate::info`]) applies, so if debug lines are filtered out, only the
+/// [`std::time::Instant`] is captured and nothing is formatted. Scopes that are nested on the
+/// same thread are indented and include their depth. If `warn_over = millis` is specified, the
+/// line is logged at the [`crate::LogLevel::Warn`] log level and only if more than `millis`
+/// milliseconds have elapsed.
+///
+/// # Examples
+///
+///```
+/// use bmw_err::Error;
+/// use bmw_log::*;
+///
+/// debug!();
+///
+/// fn main() -> Result<(), Error> {
+///     let _scope = time_scope!("startup");
+///     {
+///         // logged only if loading the config takes more than 50ms
+///         let _scope = time_scope!("load config", warn_over = 50);
+///     }
+///
+///     Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! time_scope {
+	($label:expr) => {{
+		use bmw_log::*;
+		GlobalLogContainer::time_scope(
+			LogLevel::Debug,
+			$label,
+			None,
+			BMW_GLOBAL_LOG_LEVEL,
+			module_path!(),
+		)
+	}};
+	($label:expr, warn_over = $millis:expr) => {{
+		use bmw_log::*;
+		let millis: u64 = $millis;
+		GlobalLogContainer::time_scope(
+			LogLevel::Warn,
+			$label,
+			Some(std::time::Duration::from_millis(millis)),
+			BMW_GLOBAL_LOG_LEVEL,
+			module_path!(),
+		)
+	}};
+}
+
+/// Evaluates the block and returns its value while logging the time it took at the specified
+/// [`crate::LogLevel`] along with the label. See [`crate::time_scope`]. The time is also logged
+/// if the block returns early, e.g. because `?` propagated an error.
+///
+/// # Examples
+///
+///```
+/// use bmw_err::Error;
+/// use bmw_log::*;
+///
+/// info!();
+///
+/// fn main() -> Result<(), Error> {
+///     let sum = time_block!(LogLevel::Info, "sum", {
+///         let v: u64 = "123".parse()?;
+///         v + 1
+///     });
+///     assert_eq!(sum, 124);
+///
+///     Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! time_block {
+	($level:expr, $label:expr, $block:block) => {{
+		let _bmw_time_scope = bmw_log::GlobalLogContainer::time_scope(
+			$level,
+			$label,
+			None,
+			BMW_GLOBAL_LOG_LEVEL,
+			module_path!(),
+		);
+		$block
+	}};
+}
+
+/// Log a line to a standalone logger along with the module path of the call site. The first
+/// parameter is the logger (any [`crate::Log`] implementation, i.e. a [`crate::DynLogger`] or a
+/// [`crate::SharedLogger`]) and the second is the [`crate::LogLevel`]. The rest of the parameters
+/// are the same as for [`crate::info`]. The module path is displayed if DisplayModulePath is
+/// configured. Loggers that are called through [`crate::Log::log`] directly do not know it.
+///
+/// # Examples
+///
+///```
+/// use bmw_err::Error;
+/// use bmw_log::*;
+///
+/// fn main() -> Result<(), Error> {
+///     let mut logger = logger!(DisplayModulePath(true), LogPrefixToken("node1"))?;
+///     logger.init()?;
+///     logger.set_log_level(LogLevel::Info);
+///
+///     // node1 [2024-01-01 00:00:00.000]: (INFO) [rust_out]: [..src/main.rs:10]: v=1
+///     log_record!(logger, LogLevel::Info, "v={}", 1)?;
+///
+///     Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! log_record {
+	($logger:expr, $level:expr, $line:expr) => {{
+		use bmw_log::*;
+		let record = LogRecord::new($level, $line, LoggingType::Standard, Some(module_path!()));
+		$logger.log_record(record)
+	}};
+	($logger:expr, $level:expr, $line:expr, $($values:tt)*) => {{
+		let line = format!($line, $($values)*);
+		log_record!($logger, $level, &line[..])
+	}};
+}
+
 /// This macro builds a [`crate::Log`] implementation and returns it. Specifically, it return a
 /// Box<dyn Log + Send + Sync>. This example below shows all of the allowed configurations that may
 /// be specified. All of these are optional.
@@ -637,12 +1064,17 @@ macro_rules! need_rotate {
 ///         DisplayLogLevel(true), // whether or not to display the log level
 ///         DisplayLineNum(true), // whether or not to display the code line number
 ///         DisplayMillis(true), // whether or not to display millisecond precision
+///         DisplayElapsed(false), // whether or not to display the time elapsed since init
+///         DisplayModulePath(false), // whether or not to display the module path
+///         LogPrefixToken(""), // a token to place at the start of every line
 ///         LogFilePath(&buf), // path to the log file or None if no file logging
 ///         AutoRotate(true), // whether or not to automatically rotate the log file
 ///         DisplayBacktrace(false), // whether or not to display a backtrace on error/fatal
 ///         LineNumDataMaxLen(30), // maximum length of line num data
 ///         DeleteRotation(false), // whether or not to delete the rotated log file (test only)
 ///         FileHeader("my_header"), // header to place at the top of each file
+///         FileLevel(LogLevel::Trace as u8), // minimum level written to the log file
+///         StdoutLevel(LogLevel::Trace as u8), // minimum level displayed on stdout
 ///     )?;
 ///
 ///     logger.init()?;
@@ -661,6 +1093,14 @@ macro_rules! need_rotate {
 /// * The value for MaxSizeBytes must be at least 50 bytes.
 /// * The value for LineNumDataMaxLen must be at least 10 bytes.
 /// * The parent directory of LogFilePath must exist.
+/// * The values for FileLevel and StdoutLevel must be a [`crate::LogLevel`] cast to u8.
+///
+/// # Destination levels
+///
+/// FileLevel and StdoutLevel set a minimum level for each destination independently. The
+/// logger's level (see [`crate::Log::set_log_level`]) is still the floor, so a record is only
+/// written to a destination if it is at or above both levels. Records that no destination
+/// accepts are not formatted at all. Colors only apply to stdout.
 #[macro_export]
 macro_rules! logger {
         ($($config:tt)*) => {{
@@ -669,3 +1109,35 @@ macro_rules! logger {
                 LogBuilder::build_log(v)
         }};
 }
+
+/// Build a logger and return it as a [`crate::DynLogger`] so that it can be stored in a struct
+/// field or passed to a component that accepts "some logger". The configuration options are
+/// the same as for [`crate::logger`]. To share the logger between threads, wrap it in a
+/// [`crate::SharedLogger`].
+///
+/// # Examples
+///
+///```
+/// use bmw_err::*;
+/// use bmw_log::*;
+///
+/// struct Component {
+///     logger: DynLogger,
+/// }
+///
+/// fn main() -> Result<(), Error> {
+///     let mut component = Component {
+///         logger: logger_box!(DisplayStdout(false), DisplayColors(false))?,
+///     };
+///     component.logger.init()?;
+///     component.logger.log(LogLevel::Info, "hello")?;
+///     Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! logger_box {
+        ($($config:tt)*) => {{
+                let logger: Result<bmw_log::DynLogger, bmw_err::Error> = bmw_log::logger!($($config)*);
+                logger
+        }};
+}
@@ -19,7 +19,9 @@ use crate::LogConfig2_Options;
 use bmw_deps::dyn_clone::DynClone;
 use bmw_deps::lazy_static::lazy_static;
 use bmw_err::*;
+use std::marker::PhantomData;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 /// Internal enum used by the global logging macros like [`crate::info`], [`crate::info_plain`],
 /// and [`crate::info_all`] to configure which option is being used. This should not generally be
@@ -106,6 +108,12 @@ pub trait Log: DynClone {
 	/// no timestamp, log level, or line num info is logged. This function returns () or a
 	/// Error.
 	fn log_plain(&mut self, level: LogLevel, line: &str) -> Result<(), Error>;
+	/// Log a [`crate::LogRecord`]. This is what the global logging macros and the
+	/// [`crate::log_record`] macro call so that the module path of the call site is known to
+	/// the logger. It is displayed if DisplayModulePath is configured. The record's
+	/// [`crate::LoggingType`] selects between the behavior of [`Log::log`], [`Log::log_all`]
+	/// and [`Log::log_plain`]. This function returns () or a Error.
+	fn log_record(&mut self, record: LogRecord) -> Result<(), Error>;
 	/// Do a log rotation. The name of the file rotated is automatically generated and stored
 	/// in the same directory as the original log file. Logging then proceeds with the original
 	/// log file. The name of the rotated log file will be of the form:
@@ -132,6 +140,11 @@ pub trait Log: DynClone {
 	/// Sets the log level threshold. Logging only occurs if the logged line is logged at at
 	/// least this level
 	fn set_log_level(&mut self, level: LogLevel);
+	/// Override the log level threshold of this logger for the current thread until the
+	/// returned [`crate::LogLevelGuard`] is dropped. Other threads (including threads using a
+	/// clone of this logger) continue to use the level set by [`Log::set_log_level`]. See
+	/// [`crate::log_level_guard`] for the equivalent for the global logger.
+	fn level_guard(&self, level: LogLevel) -> LogLevelGuard;
 	/// Initialize the log. The function does any needed i/o operations to secure the file
 	/// handle. It may only be called once and must be called before any logging or rotations
 	/// occur.
@@ -155,6 +168,100 @@ pub trait Log: DynClone {
 
 	#[cfg(test)]
 	fn debug_lineno_is_none(&mut self);
+
+	#[cfg(test)]
+	fn debug_capture_stdout(&mut self) -> Arc<RwLock<Vec<u8>>>;
+}
+
+/// A boxed [`crate::Log`] trait object. This is the type returned by [`crate::logger`] and
+/// [`crate::logger_box`]. The [`crate::Log`] trait is object safe, so a logger can be stored in
+/// a struct or passed to a component without naming a concrete type. Note that cloning a
+/// [`crate::DynLogger`] creates a separate logger that only shares the file handle. To share a
+/// single logger between threads, use [`crate::SharedLogger`].
+pub type DynLogger = Box<dyn Log + Send + Sync>;
+
+/// A cheaply cloneable handle to a single [`crate::DynLogger`]. All clones refer to the same
+/// logger and each call holds a lock on it for its duration, so lines logged concurrently from
+/// several threads are never interleaved and rotation state is shared. Since
+/// [`crate::SharedLogger`] implements [`crate::Log`], it may be used anywhere a logger is
+/// expected, including as a [`crate::DynLogger`].
+///
+/// # Examples
+///
+///```
+/// use bmw_err::*;
+/// use bmw_log::*;
+/// use std::thread::spawn;
+///
+/// struct Component {
+///     logger: SharedLogger,
+/// }
+///
+/// fn main() -> Result<(), Error> {
+///     let mut logger = logger_box!(DisplayStdout(false))?;
+///     logger.init()?;
+///     let component = Component {
+///         logger: SharedLogger::new(logger),
+///     };
+///
+///     let mut logger = component.logger.clone();
+///     spawn(move || -> Result<(), Error> {
+///         logger.log(LogLevel::Info, "from another thread")?;
+///         Ok(())
+///     })
+///     .join()
+///     .unwrap()?;
+///
+///     let mut logger = component.logger.clone();
+///     logger.log(LogLevel::Info, "from the main thread")?;
+///     Ok(())
+/// }
+///```
+#[derive(Clone)]
+pub struct SharedLogger {
+	pub(crate) inner: Arc<RwLock<DynLogger>>,
+}
+
+/// RAII guard returned by [`crate::log_level_guard`] and [`Log::level_guard`]. While the guard
+/// is alive, the log level threshold of the associated logger is overridden for the current
+/// thread only. When the guard is dropped (including during a panic), the previous value is
+/// restored. Guards may be nested and are restored in the reverse order of creation. The guard
+/// must be bound to a named variable (i.e. `let _guard = ...`) or it is dropped immediately.
+#[must_use]
+pub struct LogLevelGuard {
+	pub(crate) key: Option<u64>,
+	pub(crate) prev: Option<LogLevel>,
+	// the override is thread local so the guard must be dropped on the thread that created it
+	pub(crate) _marker: PhantomData<*const ()>,
+}
+
+/// RAII guard returned by [`crate::time_scope`] and used by [`crate::time_block`]. When the guard
+/// is dropped, the time elapsed since it was created is logged through the global logger along
+/// with its label. Scopes that are created while another scope is alive on the same thread are
+/// indented by their depth. If the line would be filtered out by the log level, nothing is
+/// formatted or logged. The guard must be bound to a named variable (i.e. `let _scope = ...`)
+/// or it is dropped immediately.
+#[must_use]
+pub struct TimeScope {
+	pub(crate) label: &'static str,
+	pub(crate) level: LogLevel,
+	pub(crate) global_level: LogLevel,
+	pub(crate) threshold: Option<Duration>,
+	pub(crate) module_path: &'static str,
+	pub(crate) depth: usize,
+	pub(crate) start: Instant,
+	// the depth is thread local so the guard must be dropped on the thread that created it
+	pub(crate) _marker: PhantomData<*const ()>,
+}
+
+/// A line to be logged along with where it was logged from. Records are usually built by the
+/// logging macros, which capture the module path of the call site with [`std::module_path`].
+/// See [`crate::Log::log_record`] and [`crate::log_record`].
+pub struct LogRecord<'a> {
+	pub(crate) level: LogLevel,
+	pub(crate) line: &'a str,
+	pub(crate) logging_type: LoggingType,
+	pub(crate) module_path: Option<&'a str>,
 }
 
 /// Builder struct used to build [`crate::Log`] implementations.
@@ -165,5 +272,9 @@ pub struct GlobalLogContainer {}
 
 lazy_static! {
 	#[doc(hidden)]
-	pub static ref BMW_GLOBAL_LOG: Arc<RwLock<Option<Box<dyn Log + Send + Sync>>>> = Arc::new(RwLock::new(None));
+	pub static ref BMW_GLOBAL_LOG: Arc<RwLock<Option<DynLogger>>> = Arc::new(RwLock::new(None));
+	// the LogFilePath of the global logger once log_init or log_reinit was called. It is only
+	// changed while the write lock of BMW_GLOBAL_LOG is held.
+	#[doc(hidden)]
+	pub static ref BMW_GLOBAL_LOG_INIT: Arc<RwLock<Option<String>>> = Arc::new(RwLock::new(None));
 }
@@ -27,10 +27,14 @@ mod test {
 	use bmw_err::*;
 	use bmw_log::*;
 	use bmw_test::*;
+	use std::collections::{HashMap, HashSet};
 	use std::fs::{read_dir, read_to_string, File, OpenOptions};
 	use std::io::{Read, Write};
 	use std::path::PathBuf;
+	use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 	use std::sync::{Arc, RwLock};
+	use std::thread::{sleep, spawn};
+	use std::time::Duration;
 
 	// lock used to prevent two tests from calling log_init at the same time
 	lazy_static! {
@@ -39,6 +43,75 @@ mod test {
 
 	trace!();
 
+	#[test]
+	fn test_log_init_once() -> Result<(), Error> {
+		let _lock = LOCK.write()?;
+		let test_info = test_info!()?;
+		let first = format!("{}/first.log", test_info.directory());
+		let second = format!("{}/second.log", test_info.directory());
+
+		log_reinit!(LogFilePath(&first), DisplayStdout(false))?;
+		assert!(log_is_initialized!()?);
+
+		// a second init is an error that names the existing file and changes nothing
+		let e = log_init!(LogFilePath(&second), DisplayStdout(false)).unwrap_err();
+		match e.kind() {
+			ErrorKind::AlreadyInitialized(text) => assert!(text.contains(&first)),
+			kind => return Err(err!(ErrKind::Test, "unexpected kind: {}", kind)),
+		}
+		info!("before")?;
+
+		// reinit while other threads log. Each line lands in exactly one of the files.
+		let logged = Arc::new(AtomicUsize::new(0));
+		let reinit = Arc::new(AtomicBool::new(false));
+		let mut jhs = vec![];
+		for i in 0..4 {
+			let logged = logged.clone();
+			let reinit = reinit.clone();
+			jhs.push(spawn(move || -> Result<(), Error> {
+				for j in 0..1_000 {
+					info!("t{}-{}", i, j)?;
+					logged.fetch_add(1, Ordering::SeqCst);
+				}
+				while !reinit.load(Ordering::SeqCst) {
+					sleep(Duration::from_millis(1));
+				}
+				info!("t{}-end", i)?;
+				Ok(())
+			}));
+		}
+		while logged.load(Ordering::SeqCst) < 400 {
+			sleep(Duration::from_millis(1));
+		}
+		log_reinit!(LogFilePath(&second), DisplayStdout(false))?;
+		reinit.store(true, Ordering::SeqCst);
+		for jh in jhs {
+			jh.join().unwrap()?;
+		}
+
+		let first = read_to_string(&first)?;
+		let second = read_to_string(&second)?;
+		assert!(first.lines().any(|line| line.ends_with("]: before")));
+		let mut counts = HashMap::new();
+		for line in first.lines().chain(second.lines()) {
+			if let Some(pos) = line.rfind("]: t") {
+				*counts.entry(line[pos + 3..].to_string()).or_insert(0) += 1;
+			}
+		}
+		assert_eq!(counts.len(), 4 * 1_001);
+		assert!(counts.values().all(|count| *count == 1));
+		for i in 0..4 {
+			assert!(second.contains(&format!("]: t{}-end", i)));
+			assert!(!first.contains(&format!("]: t{}-end", i)));
+		}
+		assert!(first.contains("]: t"));
+
+		let mut lock = BMW_GLOBAL_LOG.write()?;
+		*lock = None;
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_log_basic() -> Result<(), Error> {
 		let test_info = test_info!(true)?; // obtain test info struct
@@ -152,7 +225,7 @@ mod test {
 
 		// init log
 		let path = buf.display().to_string();
-		log_init!(LogFilePath(&path))?;
+		log_reinit!(LogFilePath(&path))?;
 
 		// do logging at all levels and all styles
 		trace!("mactest1")?;
@@ -1151,4 +1224,700 @@ mod test {
 		assert_eq!(conf.max_age_millis, 1_000 * 60 * 60);
 		Ok(())
 	}
+
+	#[test]
+	fn test_log_level_guard() -> Result<(), Error> {
+		// lock so we don't interfere with the other test's global logging
+		let _lock = LOCK.write()?;
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("guard.log");
+		let path = buf.display().to_string();
+		log_reinit!(LogFilePath(&path))?;
+
+		// only info and above in this scope
+		info!();
+
+		trace!("outside1")?;
+		{
+			let _guard = log_level_guard!(LogLevel::Trace);
+			trace!("inside1")?;
+		}
+		trace!("outside2")?;
+
+		// the guard must restore the level even if we panic while it's in scope
+		let res = std::panic::catch_unwind(|| {
+			let _guard = log_level_guard!(LogLevel::Trace);
+			panic!("panic with guard");
+		});
+		assert!(res.is_err());
+		trace!("outside3")?;
+
+		let contents = read_to_string(&path)?;
+		assert!(contents.contains("inside1"));
+		assert!(!contents.contains("outside1"));
+		assert!(!contents.contains("outside2"));
+		assert!(!contents.contains("outside3"));
+
+		// set the GLOBAL logger back to none for the other tests
+		let mut lock = BMW_GLOBAL_LOG.write()?;
+		*lock = None;
+
+		Ok(())
+	}
+
+	fn timed(label: &'static str, fail: bool) -> Result<u32, Error> {
+		debug!();
+		let v = time_block!(LogLevel::Info, label, {
+			if fail {
+				return Err(err!(ErrKind::Test, "timed block failed"));
+			}
+			let v: u32 = "41".parse()?;
+			v + 1
+		});
+		Ok(v)
+	}
+
+	#[test]
+	fn test_time_scope() -> Result<(), Error> {
+		// lock so we don't interfere with the other test's global logging
+		let _lock = LOCK.write()?;
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("time_scope.log");
+		let path = buf.display().to_string();
+		log_reinit!(LogFilePath(&path))?;
+
+		debug!();
+
+		{
+			let _outer = time_scope!("outer_scope");
+			sleep(Duration::from_millis(20));
+			{
+				let _inner = time_scope!("inner_scope");
+				sleep(Duration::from_millis(10));
+			}
+			// fast scopes are suppressed by the threshold, slow ones are logged
+			let _fast = time_scope!("fast_scope", warn_over = 10_000);
+			let _slow = time_scope!("slow_scope", warn_over = 1);
+			sleep(Duration::from_millis(5));
+		}
+
+		// the expression form returns the value of the block and logs on early return
+		assert_eq!(timed("block_ok", false)?, 42);
+		assert!(timed("block_err", true).is_err());
+
+		{
+			// filtered out by the log level
+			info!();
+			let _scope = time_scope!("filtered_scope");
+		}
+
+		let contents = read_to_string(&path)?;
+		let line = |label: &str| -> Option<String> {
+			contents
+				.lines()
+				.find(|l| l.contains(label))
+				.map(|l| l.to_string())
+		};
+		let outer = line("outer_scope: elapsed").unwrap();
+		assert!(outer.contains("[depth=0]"));
+		let inner = line("  inner_scope: elapsed").unwrap();
+		assert!(inner.contains("[depth=1]"));
+		assert!(line("fast_scope").is_none());
+		let slow = line("slow_scope").unwrap();
+		assert!(slow.contains("(WARN)"));
+		assert!(slow.contains("(over 1ms)"));
+		assert!(line("block_ok: elapsed").unwrap().contains("(INFO)"));
+		assert!(line("block_err: elapsed").is_some());
+		assert!(line("filtered_scope").is_none());
+
+		// inner scopes are logged before the outer scope that contains them
+		let pos = |label: &str| contents.find(label).unwrap();
+		assert!(pos("inner_scope") < pos("outer_scope"));
+
+		// the logged durations are plausible
+		let millis = |line: &str| -> f64 {
+			let d = line.split("elapsed ").nth(1).unwrap();
+			let d = d.split(' ').next().unwrap();
+			if let Some(ms) = d.strip_suffix("ms") {
+				ms.parse().unwrap()
+			} else if let Some(s) = d.strip_suffix('s') {
+				s.parse::<f64>().unwrap() * 1_000.0
+			} else {
+				0.0
+			}
+		};
+		assert!(millis(&outer) >= 35.0);
+		assert!(millis(&inner) >= 10.0);
+		assert!(millis(&inner) < millis(&outer));
+
+		// set the GLOBAL logger back to none for the other tests
+		let mut lock = BMW_GLOBAL_LOG.write()?;
+		*lock = None;
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_log_level_guard_nested() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("nested.log");
+		let path = buf.display().to_string();
+
+		let mut logger = logger!(LogFilePath(&path))?;
+		logger.init()?;
+		logger.set_log_level(LogLevel::Info);
+
+		logger.log(LogLevel::Debug, "debug1")?;
+		{
+			let _guard1 = logger.level_guard(LogLevel::Debug);
+			logger.log(LogLevel::Debug, "debug2")?;
+			logger.log(LogLevel::Trace, "trace1")?;
+			{
+				let _guard2 = logger.level_guard(LogLevel::Trace);
+				logger.log(LogLevel::Trace, "trace2")?;
+			}
+			// back to the first guard's level
+			logger.log(LogLevel::Debug, "debug3")?;
+			logger.log(LogLevel::Trace, "trace3")?;
+		}
+		// back to the original level
+		logger.log(LogLevel::Debug, "debug4")?;
+		logger.log(LogLevel::Info, "info1")?;
+
+		let contents = read_to_string(&path)?;
+		assert!(!contents.contains("debug1"));
+		assert!(contents.contains("debug2"));
+		assert!(!contents.contains("trace1"));
+		assert!(contents.contains("trace2"));
+		assert!(contents.contains("debug3"));
+		assert!(!contents.contains("trace3"));
+		assert!(!contents.contains("debug4"));
+		assert!(contents.contains("info1"));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_log_level_guard_threads() -> Result<(), Error> {
+		// lock so we don't interfere with the other test's global logging
+		let _lock = LOCK.write()?;
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("threads.log");
+		let path = buf.display().to_string();
+		log_reinit!(LogFilePath(&path))?;
+
+		// only info and above in this scope
+		info!();
+
+		{
+			// the guard on this thread must not affect the other thread
+			let _guard = log_level_guard!(LogLevel::Trace);
+			trace!("mainthread1")?;
+			std::thread::spawn(move || -> Result<(), Error> {
+				trace!("otherthread1")?;
+				Ok(())
+			})
+			.join()
+			.unwrap()?;
+		}
+
+		// and a guard on the other thread must not affect this thread
+		let (tx, rx) = test_info.sync_channel();
+		let (tx2, rx2) = test_info.sync_channel();
+		let handle = std::thread::spawn(move || -> Result<(), Error> {
+			let _guard = log_level_guard!(LogLevel::Trace);
+			tx.send(())?;
+			rx2.recv()?;
+			trace!("otherthread2")?;
+			Ok(())
+		});
+		rx.recv()?;
+		trace!("mainthread2")?;
+		tx2.send(())?;
+		handle.join().unwrap()?;
+
+		let contents = read_to_string(&path)?;
+		assert!(contents.contains("mainthread1"));
+		assert!(!contents.contains("otherthread1"));
+		assert!(contents.contains("otherthread2"));
+		assert!(!contents.contains("mainthread2"));
+
+		// set the GLOBAL logger back to none for the other tests
+		let mut lock = BMW_GLOBAL_LOG.write()?;
+		*lock = None;
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_log_sampled() -> Result<(), Error> {
+		// lock so we don't interfere with the other test's global logging
+		let _lock = LOCK.write()?;
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("sampled.log");
+		let path = buf.display().to_string();
+		log_reinit!(LogFilePath(&path))?;
+
+		for i in 0..100 {
+			info_sampled!(10, "sampledline{}", i)?;
+		}
+		// a different call site has its own counter
+		warn_sampled!(10, "othersite")?;
+
+		let contents = read_to_string(&path)?;
+		let lines: Vec<&str> = contents
+			.lines()
+			.filter(|l| l.contains("sampledline"))
+			.collect();
+		assert_eq!(lines.len(), 10);
+		for (i, line) in lines.iter().enumerate() {
+			let suppressed = if i == 0 { 0 } else { 9 };
+			assert!(line.ends_with(&format!(
+				"sampledline{} (sampled 1/10, {} suppressed since last)",
+				i * 10,
+				suppressed
+			)));
+		}
+		assert!(contents.contains("othersite (sampled 1/10, 0 suppressed since last)"));
+
+		// set the GLOBAL logger back to none for the other tests
+		let mut lock = BMW_GLOBAL_LOG.write()?;
+		*lock = None;
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_log_rate_limited() -> Result<(), Error> {
+		// lock so we don't interfere with the other test's global logging
+		let _lock = LOCK.write()?;
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("rate_limited.log");
+		let path = buf.display().to_string();
+		log_reinit!(LogFilePath(&path))?;
+
+		for i in 0..12 {
+			// the first 6 are within one interval, the rest in the next
+			if i == 6 {
+				std::thread::sleep(std::time::Duration::from_millis(300));
+			}
+			info_rate_limited!(200, "ratelimitedline{}", i)?;
+		}
+
+		let contents = read_to_string(&path)?;
+		let lines: Vec<&str> = contents
+			.lines()
+			.filter(|l| l.contains("ratelimitedline"))
+			.collect();
+		assert_eq!(lines.len(), 2);
+		assert!(
+			lines[0].ends_with("ratelimitedline0 (rate limited 1/200ms, 0 suppressed since last)")
+		);
+		assert!(
+			lines[1].ends_with("ratelimitedline6 (rate limited 1/200ms, 5 suppressed since last)")
+		);
+
+		// set the GLOBAL logger back to none for the other tests
+		let mut lock = BMW_GLOBAL_LOG.write()?;
+		*lock = None;
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_log_destination_levels() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("destination_levels.log");
+		let buf = buf.display().to_string();
+
+		let mut log = logger!(
+			LogFilePath(&buf),
+			DisplayColors(false),
+			FileLevel(LogLevel::Debug as u8),
+			StdoutLevel(LogLevel::Error as u8)
+		)?;
+		let capture = log.debug_capture_stdout();
+		log.init()?;
+		log.set_log_level(LogLevel::Trace);
+
+		log.log(LogLevel::Trace, "line_trace")?;
+		log.log(LogLevel::Debug, "line_debug")?;
+		log.log(LogLevel::Info, "line_info")?;
+		log.log(LogLevel::Warn, "line_warn")?;
+		log.log(LogLevel::Error, "line_error")?;
+		log.log(LogLevel::Fatal, "line_fatal")?;
+
+		let contents = read_to_string(&buf)?;
+		assert!(!contents.contains("line_trace"));
+		for name in [
+			"line_debug",
+			"line_info",
+			"line_warn",
+			"line_error",
+			"line_fatal",
+		] {
+			assert!(contents.contains(name));
+		}
+
+		let stdout = String::from_utf8(capture.read()?.clone())?;
+		for name in ["line_trace", "line_debug", "line_info", "line_warn"] {
+			assert!(!stdout.contains(name));
+		}
+		assert!(stdout.contains("line_error"));
+		assert!(stdout.contains("line_fatal"));
+
+		// destination levels can be changed at runtime
+		log.set_config_option(StdoutLevel(LogLevel::Warn as u8))?;
+		log.log(LogLevel::Warn, "line_warn2")?;
+		let stdout = String::from_utf8(capture.read()?.clone())?;
+		assert!(stdout.contains("line_warn2"));
+
+		// the logger's level is still the floor
+		log.set_log_level(LogLevel::Fatal);
+		log.log(LogLevel::Error, "line_error2")?;
+		assert!(!read_to_string(&buf)?.contains("line_error2"));
+		assert!(!String::from_utf8(capture.read()?.clone())?.contains("line_error2"));
+
+		// invalid levels are rejected
+		assert!(log.set_config_option(FileLevel(6)).is_err());
+		assert!(logger!(StdoutLevel(100)).is_err());
+
+		Ok(())
+	}
+
+	struct Component {
+		logger: SharedLogger,
+	}
+
+	#[test]
+	fn test_log_shared_logger() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("shared.log");
+		let buf = buf.display().to_string();
+
+		// the logger is only known as a trait object
+		let mut logger: DynLogger = logger_box!(
+			LogFilePath(&buf),
+			DisplayStdout(false),
+			DisplayColors(false),
+			DisplayBacktrace(false),
+			AutoRotate(false)
+		)?;
+		logger.init()?;
+		let component = Arc::new(Component {
+			logger: SharedLogger::new(logger),
+		});
+
+		let mut handles = vec![];
+		for t in 0..3 {
+			let component = component.clone();
+			handles.push(spawn(move || -> Result<(), Error> {
+				let mut logger = component.logger.clone();
+				for i in 0..200 {
+					let line = format!("thread-{}-line-{}-{}", t, i, "x".repeat(200));
+					logger.log(LogLevel::Info, &line)?;
+				}
+				Ok(())
+			}));
+		}
+		for handle in handles {
+			handle.join().unwrap()?;
+		}
+
+		// every line is complete: one header, one message and nothing else
+		let contents = read_to_string(&buf)?;
+		let lines: Vec<&str> = contents.lines().collect();
+		assert_eq!(lines.len(), 600);
+		let mut found = HashSet::new();
+		for line in lines {
+			assert_eq!(line.matches("thread-").count(), 1);
+			assert_eq!(line.matches("(INFO)").count(), 1);
+			let message = &line[line.find("thread-").unwrap()..];
+			assert!(message.ends_with(&"x".repeat(200)));
+			assert!(found.insert(message.to_string()));
+		}
+		for t in 0..3 {
+			for i in 0..200 {
+				let line = format!("thread-{}-line-{}-{}", t, i, "x".repeat(200));
+				assert!(found.contains(&line));
+			}
+		}
+
+		Ok(())
+	}
+
+	// the elapsed seconds of each line that has the elapsed time column
+	fn elapsed_secs(contents: &str) -> Vec<(String, f64)> {
+		let mut ret = vec![];
+		for line in contents.lines() {
+			if let (Some(start), Some(end)) = (line.find("[+"), line.find("s]: ")) {
+				let secs: f64 = line[start + 2..end].parse().unwrap();
+				let message = line[line.rfind(' ').unwrap() + 1..].to_string();
+				ret.push((message, secs));
+			}
+		}
+		ret
+	}
+
+	#[test]
+	fn test_log_display_elapsed() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("elapsed.log");
+		let buf = buf.display().to_string();
+
+		let mut log = logger!(
+			LogFilePath(&buf),
+			DisplayStdout(false),
+			DisplayTimestamp(false),
+			DisplayElapsed(true),
+			DisplayLineNum(false)
+		)?;
+		log.init()?;
+		log.set_log_level(LogLevel::Trace);
+
+		log.log(LogLevel::Info, "line1")?;
+		sleep(Duration::from_millis(300));
+		log.log(LogLevel::Info, "line2")?;
+
+		let contents = read_to_string(&buf)?;
+		assert!(!contents.contains("[20"));
+		let elapsed = elapsed_secs(&contents);
+		assert_eq!(elapsed.len(), 2);
+		assert_eq!(elapsed[0].0, "line1");
+		assert_eq!(elapsed[1].0, "line2");
+		let diff = elapsed[1].1 - elapsed[0].1;
+		assert!((0.3..2.0).contains(&diff), "diff = {}", diff);
+		// microsecond precision
+		assert_eq!(
+			contents.find("s]: ").unwrap() - contents.find('.').unwrap(),
+			7
+		);
+
+		// the option takes effect for the following lines only
+		log.set_config_option(DisplayElapsed(false))?;
+		log.log(LogLevel::Info, "line3")?;
+		log.set_config_option(DisplayTimestamp(true))?;
+		log.set_config_option(DisplayElapsed(true))?;
+		log.log(LogLevel::Info, "line4")?;
+
+		let contents = read_to_string(&buf)?;
+		let lines: Vec<&str> = contents.lines().collect();
+		assert_eq!(lines.len(), 4);
+		assert!(lines[0].starts_with("[+"));
+		assert!(lines[1].starts_with("[+"));
+		assert!(!lines[2].contains("[+"));
+		assert!(!lines[2].contains("[20"));
+		assert!(lines[3].starts_with("[20"));
+		assert!(lines[3].contains("]: [+"));
+		let elapsed = elapsed_secs(&contents);
+		assert_eq!(elapsed.len(), 3);
+		assert_eq!(elapsed[2].0, "line4");
+		assert!(elapsed[2].1 >= elapsed[1].1);
+
+		// rotation does not reset the elapsed time
+		log.rotate()?;
+		log.log(LogLevel::Info, "line5")?;
+		let elapsed = elapsed_secs(&read_to_string(&buf)?);
+		assert_eq!(elapsed.len(), 1);
+		assert_eq!(elapsed[0].0, "line5");
+		assert!(elapsed[0].1 >= 0.3);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_log_display_elapsed_global() -> Result<(), Error> {
+		// lock so we don't interfere with the other test's global logging
+		let _lock = LOCK.write()?;
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("elapsed_global.log");
+		let path = buf.display().to_string();
+		log_reinit!(LogFilePath(&path), DisplayStdout(false))?;
+
+		info!();
+		info!("global1")?;
+		set_log_option!(DisplayElapsed(true))?;
+		info!("global2")?;
+
+		let contents = read_to_string(&path)?;
+		let lines: Vec<&str> = contents.lines().collect();
+		assert_eq!(lines.len(), 2);
+		assert!(!lines[0].contains("[+"));
+		assert!(lines[1].contains("]: [+"));
+		assert_eq!(elapsed_secs(&contents).len(), 1);
+
+		// set the GLOBAL logger back to none for the other tests
+		let mut lock = BMW_GLOBAL_LOG.write()?;
+		*lock = None;
+
+		Ok(())
+	}
+
+	// logs from a nested module so the module path differs from the test module's
+	mod nested {
+		use crate as bmw_log;
+		use bmw_err::*;
+		use bmw_log::*;
+
+		info!();
+
+		pub(crate) fn log_standalone(logger: &mut DynLogger) -> Result<(), Error> {
+			log_record!(logger, LogLevel::Info, "from {}", "nested")
+		}
+
+		pub(crate) fn log_global() -> Result<(), Error> {
+			info!("global nested")
+		}
+	}
+
+	#[test]
+	fn test_log_module_path() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("module_path.log");
+		let buf = buf.display().to_string();
+
+		let mut log = logger!(
+			LogFilePath(&buf),
+			DisplayStdout(false),
+			DisplayTimestamp(false),
+			DisplayLineNum(false),
+			DisplayModulePath(true)
+		)?;
+		log.init()?;
+		log.set_log_level(LogLevel::Trace);
+
+		nested::log_standalone(&mut log)?;
+		log_record!(log, LogLevel::Warn, "from test")?;
+		// the module path is not known for direct calls
+		log.log(LogLevel::Info, "direct")?;
+		// alongside the file path
+		log.set_config_option(DisplayLineNum(true))?;
+		nested::log_standalone(&mut log)?;
+		log_record!(log, LogLevel::Info, "x")?;
+		log.set_config_option(DisplayModulePath(false))?;
+		nested::log_standalone(&mut log)?;
+
+		let contents = read_to_string(&buf)?;
+		let lines: Vec<&str> = contents.lines().collect();
+		assert_eq!(lines.len(), 6);
+		assert_eq!(
+			lines[0],
+			"(INFO)  [bmw_log::test::test::nested]: from nested"
+		);
+		assert_eq!(lines[1], "(WARN)  [bmw_log::test::test]: from test");
+		assert_eq!(lines[2], "(INFO)  direct");
+		assert!(lines[3].starts_with("(INFO)  [bmw_log::test::test::nested]: ["));
+		assert!(lines[3].ends_with("]: from nested"));
+		assert!(lines[4].starts_with("(INFO)  [bmw_log::test::test]: ["));
+		assert!(!lines[5].contains("bmw_log::test"));
+		assert!(lines[5].ends_with("]: from nested"));
+
+		// the global logger gets the module path from the macros
+		let _lock = LOCK.write()?;
+		let mut buf = PathBuf::new();
+		buf.push(test_info.directory());
+		buf.push("module_path_global.log");
+		let path = buf.display().to_string();
+		log_reinit!(
+			LogFilePath(&path),
+			DisplayStdout(false),
+			DisplayLineNum(false),
+			DisplayModulePath(true)
+		)?;
+		nested::log_global()?;
+		info!("global test")?;
+		info_plain!("plain")?;
+
+		let contents = read_to_string(&path)?;
+		let lines: Vec<&str> = contents.lines().collect();
+		assert_eq!(lines.len(), 3);
+		assert!(lines[0].ends_with("(INFO)  [bmw_log::test::test::nested]: global nested"));
+		assert!(lines[1].ends_with("(INFO)  [bmw_log::test::test]: global test"));
+		assert_eq!(lines[2], "plain");
+
+		// set the GLOBAL logger back to none for the other tests
+		let mut lock = BMW_GLOBAL_LOG.write()?;
+		*lock = None;
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_log_prefix_token() -> Result<(), Error> {
+		let test_info = test_info!()?;
+		let directory = test_info.directory();
+		let mut buf = PathBuf::new();
+		buf.push(directory);
+		buf.push("token.log");
+		let path = buf.display().to_string();
+
+		let mut log = logger!(
+			LogFilePath(&path),
+			DisplayStdout(true),
+			DisplayColors(false),
+			LogPrefixToken("node7"),
+			MaxSizeBytes(100),
+			AutoRotate(true),
+			FileHeader("header")
+		)?;
+		let stdout = log.debug_capture_stdout();
+		log.init()?;
+		log.set_log_level(LogLevel::Trace);
+
+		// enough to trigger several automatic rotations
+		for i in 0..10 {
+			log.log(LogLevel::Info, &format!("line{}", i))?;
+			log.log_plain(LogLevel::Info, &format!("plain{}", i))?;
+		}
+		log.rotate()?;
+		log.log_all(LogLevel::Warn, "last")?;
+
+		let mut files = 0;
+		let mut lines = 0;
+		for entry in read_dir(directory)? {
+			let contents = read_to_string(entry?.path())?;
+			let mut itt = contents.lines();
+			// the header is not a log line
+			assert_eq!(itt.next(), Some("header"));
+			for line in itt {
+				assert!(line.starts_with("node7 "), "line = {}", line);
+				lines += 1;
+			}
+			files += 1;
+		}
+		assert!(files > 2);
+		assert_eq!(lines, 21);
+
+		let stdout = String::from_utf8(stdout.read()?.clone())?;
+		let stdout: Vec<&str> = stdout.lines().collect();
+		assert_eq!(stdout.len(), 21);
+		for line in &stdout {
+			assert!(line.starts_with("node7 "), "line = {}", line);
+		}
+		assert_eq!(stdout[1], "node7 plain0");
+
+		Ok(())
+	}
 }
@@ -25,11 +25,17 @@ use std::time::Instant;
 #[derive(Clone)]
 pub(crate) struct LogImpl {
 	pub(crate) config: LogConfig2,
+	pub(crate) id: u64,
 	pub(crate) log_level: LogLevel,
 	pub(crate) cur_size: u64,
 	pub(crate) file: Arc<RwLock<Option<File>>>,
 	pub(crate) is_init: bool,
 	pub(crate) last_rotation: Instant,
+	// the start of the elapsed time shown by DisplayElapsed. Set by init and not reset by
+	// rotation.
+	pub(crate) epoch: Instant,
+	#[cfg(test)]
+	pub(crate) debug_stdout_capture: Option<Arc<RwLock<Vec<u8>>>>,
 }
 
 #[derive(Configurable, Clone)]
@@ -42,7 +48,12 @@ pub(crate) struct LogConfig2 {
 	pub(crate) display_log_level: bool,
 	pub(crate) display_line_num: bool,
 	pub(crate) display_millis: bool,
+	pub(crate) display_elapsed: bool,
 	pub(crate) display_backtrace: bool,
+	pub(crate) display_module_path: bool,
+	pub(crate) log_prefix_token: String,
+	pub(crate) file_level: u8,
+	pub(crate) stdout_level: u8,
 	pub(crate) log_file_path: String,
 	pub(crate) line_num_data_max_len: u64,
 	pub(crate) delete_rotation: bool,
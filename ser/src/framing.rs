@@ -0,0 +1,202 @@
+// Copyright (c) 2023-2024, The BitcoinMW Developers
+// Some code and concepts from:
+// * Grin: https://github.com/mimblewimble/grin
+// * Arti: https://gitlab.torproject.org/tpo/core/arti
+// * BitcoinMW: https://github.com/bitcoinmw/bitcoinmw
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::types::FrameIterator;
+use crate::{FrameOptions, Reader, Writer};
+use bmw_err::{err, Error};
+use std::io::{ErrorKind as IoErrorKind, Read};
+
+// the CRC32C (Castagnoli) lookup table for the reflected polynomial
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+	let mut table = [0u32; 256];
+	let mut i = 0;
+	while i < 256 {
+		let mut crc = i as u32;
+		let mut j = 0;
+		while j < 8 {
+			crc = if crc & 1 == 1 {
+				(crc >> 1) ^ 0x82F6_3B78
+			} else {
+				crc >> 1
+			};
+			j += 1;
+		}
+		table[i] = crc;
+		i += 1;
+	}
+	table
+}
+
+/// Returns the CRC32C (Castagnoli) checksum of `data`. This is the checksum used by
+/// [`crate::write_frame`] when [`crate::FrameOptions::crc32`] is set.
+pub fn crc32c(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for b in data {
+		crc = CRC32C_TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8);
+	}
+	!crc
+}
+
+impl Default for FrameOptions {
+	fn default() -> Self {
+		Self {
+			max_len: 16 * 1024 * 1024,
+			crc32: false,
+		}
+	}
+}
+
+/// Write `payload` to `writer` as a frame. A frame is the length of the payload (4 bytes, big
+/// endian) followed by the payload and, if [`crate::FrameOptions::crc32`] is set, the
+/// [`crate::crc32c`] of the payload (4 bytes, big endian). The byte order does not depend on
+/// the endianness of the writer.
+/// # Errors
+/// * [`bmw_err::ErrKind::IllegalArgument`] - If the payload is longer than
+///   [`crate::FrameOptions::max_len`] or [`u32::MAX`].
+pub fn write_frame<W: Writer>(
+	writer: &mut W,
+	payload: &[u8],
+	options: &FrameOptions,
+) -> Result<(), Error> {
+	if payload.len() > options.max_len || payload.len() > u32::MAX as usize {
+		let fmt = format!(
+			"frame length {} exceeds max_len {}",
+			payload.len(),
+			options.max_len
+		);
+		return Err(err!(ErrKind::IllegalArgument, fmt));
+	}
+	writer.write_fixed_bytes((payload.len() as u32).to_be_bytes())?;
+	writer.write_fixed_bytes(payload)?;
+	if options.crc32 {
+		writer.write_fixed_bytes(crc32c(payload).to_be_bytes())?;
+	}
+	Ok(())
+}
+
+/// Read a frame written by [`crate::write_frame`] with the same `options` from `reader` and
+/// return its payload.
+/// # Errors
+/// * [`bmw_err::ErrKind::CapacityExceeded`] - If the length of the frame is greater than
+///   [`crate::FrameOptions::max_len`]. This is checked before the payload is allocated.
+/// * [`bmw_err::ErrKind::Checksum`] - If [`crate::FrameOptions::crc32`] is set and the
+///   checksum does not match the payload.
+/// * Any error returned by the reader, for instance if the data ends before the frame does.
+pub fn read_frame<R: Reader>(reader: &mut R, options: &FrameOptions) -> Result<Vec<u8>, Error> {
+	let mut header = [0u8; 4];
+	reader.read_fixed_bytes(&mut header)?;
+	let len = u32::from_be_bytes(header) as usize;
+	check_len(len, options)?;
+	let mut payload = vec![0u8; len];
+	reader.read_fixed_bytes(&mut payload)?;
+	if options.crc32 {
+		let mut crc = [0u8; 4];
+		reader.read_fixed_bytes(&mut crc)?;
+		check_crc(&payload, crc)?;
+	}
+	Ok(payload)
+}
+
+fn check_len(len: usize, options: &FrameOptions) -> Result<(), Error> {
+	if len > options.max_len {
+		let fmt = format!("frame length {} exceeds max_len {}", len, options.max_len);
+		return Err(err!(ErrKind::CapacityExceeded, fmt));
+	}
+	Ok(())
+}
+
+fn check_crc(payload: &[u8], crc: [u8; 4]) -> Result<(), Error> {
+	let expected = u32::from_be_bytes(crc);
+	let found = crc32c(payload);
+	if expected != found {
+		let fmt = format!("frame crc {:08x} does not match {:08x}", found, expected);
+		return Err(err!(ErrKind::Checksum, fmt));
+	}
+	Ok(())
+}
+
+impl<R: Read> FrameIterator<R> {
+	/// Create a [`crate::FrameIterator`] that reads the frames in `source`. The frames must
+	/// have been written with the same `options`.
+	pub fn new(source: R, options: FrameOptions) -> Self {
+		Self {
+			source,
+			options,
+			done: false,
+		}
+	}
+
+	// None if the source ended before the first byte of the frame
+	fn read_frame(&mut self) -> Result<Option<Vec<u8>>, Error> {
+		let mut header = [0u8; 4];
+		let rlen = loop {
+			match self.source.read(&mut header[0..1]) {
+				Ok(rlen) => break rlen,
+				Err(e) if e.kind() == IoErrorKind::Interrupted => {}
+				Err(e) => return Err(e.into()),
+			}
+		};
+		if rlen == 0 {
+			return Ok(None);
+		}
+		self.read_exact(&mut header[1..])?;
+		let len = u32::from_be_bytes(header) as usize;
+		check_len(len, &self.options)?;
+		let mut payload = vec![0u8; len];
+		self.read_exact(&mut payload)?;
+		if self.options.crc32 {
+			let mut crc = [0u8; 4];
+			self.read_exact(&mut crc)?;
+			check_crc(&payload, crc)?;
+		}
+		Ok(Some(payload))
+	}
+
+	fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+		match self.source.read_exact(buf) {
+			Ok(_) => Ok(()),
+			Err(e) if e.kind() == IoErrorKind::UnexpectedEof => Err(err!(
+				ErrKind::UnexpectedEof,
+				"data ended in the middle of a frame"
+			)),
+			Err(e) => Err(e.into()),
+		}
+	}
+}
+
+impl<R: Read> Iterator for FrameIterator<R> {
+	type Item = Result<Vec<u8>, Error>;
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
+		}
+		match self.read_frame() {
+			Ok(Some(payload)) => Some(Ok(payload)),
+			Ok(None) => {
+				self.done = true;
+				None
+			}
+			Err(e) => {
+				// the position in the source is unknown after an error
+				self.done = true;
+				Some(Err(e))
+			}
+		}
+	}
+}
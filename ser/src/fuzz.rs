@@ -0,0 +1,63 @@
+// Copyright (c) 2023-2024, The BitcoinMW Developers
+// Some code and concepts from:
+// * Grin: https://github.com/mimblewimble/grin
+// * Arti: https://gitlab.torproject.org/tpo/core/arti
+// * BitcoinMW: https://github.com/bitcoinmw/bitcoinmw
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{BinReader, DeserializeBorrowed, Endianness, Reader, Serializable, StrRef, Writer};
+use bmw_deps::chrono::NaiveDate;
+use bmw_err::Error;
+use std::sync::Arc;
+
+/// A recursive type that is used to check the nesting limit of [`std::boxed::Box`].
+#[derive(Debug, PartialEq)]
+pub struct Nested(pub Option<Box<Nested>>);
+
+impl Serializable for Nested {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		self.0.write(writer)
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Self, Error> {
+		Ok(Self(Serializable::read(reader)?))
+	}
+}
+
+/// The representative nested type read by [`crate::deserialize_fuzz_entry`]. It covers
+/// length prefixed data, option tags, bools, chars, floats, dates and pointer nesting.
+pub type FuzzValue = Vec<(
+	String,
+	Option<Box<(Vec<bool>, (NaiveDate, Arc<(char, f64)>))>>,
+)>;
+
+// read a `T` from `bytes` with a BinReader in the byte order `endianness`
+pub(crate) fn read_bin<T: Serializable>(
+	mut bytes: &[u8],
+	endianness: Endianness,
+) -> Result<T, Error> {
+	T::read(&mut BinReader::with_endianness(&mut bytes, endianness))
+}
+
+/// Deserializes a representative nested type from `bytes` in both byte orders and discards
+/// the result. It never panics on malformed input and is intended to be called by a fuzzer,
+/// e.g. from a cargo-fuzz target:
+///
+///```ignore
+/// fuzz_target!(|data: &[u8]| bmw_ser::deserialize_fuzz_entry(data));
+///```
+pub fn deserialize_fuzz_entry(mut bytes: &[u8]) {
+	let _ = read_bin::<FuzzValue>(bytes, Endianness::Big);
+	let _ = read_bin::<FuzzValue>(bytes, Endianness::Little);
+	let _ = read_bin::<Nested>(bytes, Endianness::Big);
+	let _ = StrRef::read_borrowed(&mut BinReader::from_slice(&mut bytes));
+}
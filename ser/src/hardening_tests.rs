@@ -0,0 +1,307 @@
+// Copyright (c) 2023-2024, The BitcoinMW Developers
+// Some code and concepts from:
+// * Grin: https://github.com/mimblewimble/grin
+// * Arti: https://gitlab.torproject.org/tpo/core/arti
+// * BitcoinMW: https://github.com/bitcoinmw/bitcoinmw
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A corpus of hostile inputs for the [`crate::Serializable`] implementations of this crate.
+//! Each input was crafted for a specific class of malformed data (a length prefix past the end
+//! of the data, an invalid bool byte, nesting that is too deep, etc.) and must be rejected
+//! with an error rather than a panic, an abort or an allocation of the claimed size. The
+//! corpus is run against [`crate::BinReader`] here and against the slab reader of the
+//! bmw_util crate there. New findings should be added to [`hostile_inputs`] along with the
+//! fix. The module is only built for tests and with the `hardening_tests` feature.
+
+pub use crate::fuzz::{FuzzValue, Nested};
+use crate::ser::MAX_READ_DEPTH;
+use crate::{BinReader, BytesRef, DeserializeBorrowed, Endianness, Reader, Serializable, StrRef};
+use bmw_deps::chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use bmw_err::Error;
+
+/// The type that a hostile input is read as. See [`read_target`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+	/// a [`prim@u64`]
+	U64,
+	/// a [`prim@bool`]
+	Bool,
+	/// an [`std::option::Option<u8>`]
+	OptionU8,
+	/// a [`std::vec::Vec<u8>`]
+	VecU8,
+	/// a [`std::vec::Vec<u128>`]
+	VecU128,
+	/// a [`std::vec::Vec<Vec<u8>>`]
+	VecVecU8,
+	/// a [`std::string::String`]
+	String,
+	/// a tuple of a [`prim@u32`] and a [`std::string::String`]
+	Tuple,
+	/// a `DateTime<Utc>`
+	DateTime,
+	/// a `NaiveDateTime`
+	NaiveDateTime,
+	/// a `NaiveDate`
+	NaiveDate,
+	/// a [`Nested`]
+	Nested,
+	/// a [`crate::StrRef`]
+	StrRef,
+	/// a [`crate::BytesRef`]
+	BytesRef,
+	/// the type read by [`crate::deserialize_fuzz_entry`]
+	FuzzValue,
+}
+
+/// Read a value of the type `target` from `reader` and discard it.
+pub fn read_target<R: Reader>(target: Target, reader: &mut R) -> Result<(), Error> {
+	match target {
+		Target::U64 => u64::read(reader).map(|_| ()),
+		Target::Bool => bool::read(reader).map(|_| ()),
+		Target::OptionU8 => Option::<u8>::read(reader).map(|_| ()),
+		Target::VecU8 => Vec::<u8>::read(reader).map(|_| ()),
+		Target::VecU128 => Vec::<u128>::read(reader).map(|_| ()),
+		Target::VecVecU8 => Vec::<Vec<u8>>::read(reader).map(|_| ()),
+		Target::String => String::read(reader).map(|_| ()),
+		Target::Tuple => <(u32, String)>::read(reader).map(|_| ()),
+		Target::DateTime => DateTime::<Utc>::read(reader).map(|_| ()),
+		Target::NaiveDateTime => NaiveDateTime::read(reader).map(|_| ()),
+		Target::NaiveDate => NaiveDate::read(reader).map(|_| ()),
+		Target::Nested => Nested::read(reader).map(|_| ()),
+		Target::StrRef => StrRef::read_borrowed(reader).map(|_| ()),
+		Target::BytesRef => BytesRef::read_borrowed(reader).map(|_| ()),
+		Target::FuzzValue => FuzzValue::read(reader).map(|_| ()),
+	}
+}
+
+/// Returns the corpus of hostile inputs. Each entry is a description of the class of
+/// malformed data, the type that it is read as and the big-endian encoded bytes.
+pub fn hostile_inputs() -> Vec<(&'static str, Target, Vec<u8>)> {
+	let usize_max = usize::MAX.to_be_bytes().to_vec();
+	let mut ret = vec![];
+
+	ret.push(("empty input", Target::U64, vec![]));
+	ret.push(("truncated integer", Target::U64, vec![1, 2, 3]));
+	ret.push(("bool byte other than 0 or 1", Target::Bool, vec![2]));
+	ret.push(("option tag other than 0 or 1", Target::OptionU8, vec![7, 1]));
+	ret.push(("vec length of usize::MAX", Target::VecU8, usize_max.clone()));
+
+	let mut v = (1usize << 60).to_be_bytes().to_vec();
+	v.extend_from_slice(&[0u8; 16]);
+	ret.push(("vec length overflowing the byte size", Target::VecU128, v));
+
+	let mut v = 2usize.to_be_bytes().to_vec();
+	v.extend_from_slice(&0usize.to_be_bytes());
+	v.extend_from_slice(&usize_max);
+	ret.push(("nested vec length of usize::MAX", Target::VecVecU8, v));
+
+	ret.push((
+		"string length of usize::MAX",
+		Target::String,
+		usize_max.clone(),
+	));
+
+	let mut v = 10usize.to_be_bytes().to_vec();
+	v.extend_from_slice(b"abc");
+	ret.push(("string shorter than its length", Target::String, v));
+
+	let mut v = 2usize.to_be_bytes().to_vec();
+	v.extend_from_slice(&[0xc3, 0x28]);
+	ret.push(("string that is not utf8", Target::String, v));
+
+	let mut v = 1u32.to_be_bytes().to_vec();
+	v.extend_from_slice(&usize_max);
+	ret.push(("tuple with a corrupt second element", Target::Tuple, v));
+
+	let mut v = 0i64.to_be_bytes().to_vec();
+	v.extend_from_slice(&1_000_000_000u32.to_be_bytes());
+	ret.push((
+		"timestamp nanoseconds of a leap second",
+		Target::DateTime,
+		v,
+	));
+
+	let mut v = i64::MAX.to_be_bytes().to_vec();
+	v.extend_from_slice(&0u32.to_be_bytes());
+	ret.push(("timestamp seconds out of range", Target::NaiveDateTime, v));
+
+	let v = i32::MAX.to_be_bytes().to_vec();
+	ret.push(("date days overflowing i32", Target::NaiveDate, v));
+
+	ret.push((
+		"nesting deeper than the read limit",
+		Target::Nested,
+		vec![1u8; MAX_READ_DEPTH * 4],
+	));
+
+	let mut v = 2usize.to_be_bytes().to_vec();
+	v.extend_from_slice(&[0xff, 0xfe]);
+	ret.push(("borrowed string that is not utf8", Target::StrRef, v));
+
+	ret.push((
+		"borrowed bytes length of usize::MAX",
+		Target::BytesRef,
+		usize_max.clone(),
+	));
+
+	let mut v = 1usize.to_be_bytes().to_vec();
+	v.extend_from_slice(&1usize.to_be_bytes());
+	v.push(b'a');
+	v.push(1);
+	v.extend_from_slice(&1usize.to_be_bytes());
+	v.push(3);
+	ret.push(("bool inside of a nested value", Target::FuzzValue, v));
+
+	ret
+}
+
+/// Returns true if the [`hostile_inputs`] entries for `target` are made up of single bytes and
+/// are therefore hostile in either byte order. The other entries are encoded big-endian.
+pub fn is_endian_neutral(target: Target) -> bool {
+	matches!(target, Target::Bool | Target::OptionU8 | Target::Nested)
+}
+
+/// Reads each of the [`hostile_inputs`] with the function `reader` and returns the
+/// descriptions of the inputs that did not result in an error.
+pub fn accepted_inputs<F>(mut reader: F) -> Vec<&'static str>
+where
+	F: FnMut(Target, &[u8]) -> Result<(), Error>,
+{
+	let mut ret = vec![];
+	for (name, target, bytes) in hostile_inputs() {
+		if reader(target, &bytes).is_ok() {
+			ret.push(name);
+		}
+	}
+	ret
+}
+
+/// Read `bytes` with a [`crate::BinReader`] in the byte order `endianness`.
+pub fn read_bin(target: Target, mut bytes: &[u8], endianness: Endianness) -> Result<(), Error> {
+	match endianness {
+		Endianness::Big => read_target(target, &mut BinReader::from_slice(&mut bytes)),
+		Endianness::Little => read_target(
+			target,
+			&mut BinReader::with_endianness(&mut bytes, endianness),
+		),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::hardening_tests::{
+		accepted_inputs, hostile_inputs, is_endian_neutral, read_bin, read_target, FuzzValue,
+		Nested, Target,
+	};
+	use crate::{deserialize, deserialize_fuzz_entry, serialize, BinReader, Endianness};
+	use bmw_deps::chrono::NaiveDate;
+	use bmw_deps::rand::{self, Rng};
+	use bmw_err::*;
+	use std::sync::Arc;
+
+	#[test]
+	fn test_hostile_inputs_bin_reader() -> Result<(), Error> {
+		assert!(hostile_inputs().len() >= 12);
+		let accepted = accepted_inputs(|target, bytes| read_bin(target, bytes, Endianness::Big));
+		assert!(accepted.is_empty(), "accepted: {:?}", accepted);
+
+		// a reader that does not support borrowing
+		let accepted = accepted_inputs(|target, mut bytes| {
+			read_target(target, &mut BinReader::new(&mut bytes))
+		});
+		assert!(accepted.is_empty(), "accepted: {:?}", accepted);
+
+		for (name, target, bytes) in hostile_inputs() {
+			if is_endian_neutral(target) {
+				let res = read_bin(target, &bytes, Endianness::Little);
+				assert!(res.is_err(), "accepted: {}", name);
+			}
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_hostile_input_errors() -> Result<(), Error> {
+		for (name, target, bytes) in hostile_inputs() {
+			let kind = read_bin(target, &bytes, Endianness::Big)
+				.unwrap_err()
+				.kind();
+			let expected = match target {
+				// the data runs out before the claimed length is read
+				Target::U64 | Target::VecU8 | Target::VecU128 | Target::VecVecU8 => {
+					matches!(kind, ErrorKind::IO(_))
+				}
+				Target::String | Target::Tuple => {
+					matches!(kind, ErrorKind::IO(_) | ErrorKind::CorruptedData(_))
+				}
+				Target::BytesRef => matches!(kind, ErrorKind::IO(_)),
+				_ => matches!(kind, ErrorKind::CorruptedData(_)),
+			};
+			assert!(expected, "{}: {:?}", name, kind);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_hostile_nesting_resets_depth() -> Result<(), Error> {
+		let n: Result<Nested, Error> = deserialize(&mut &vec![1u8; 10_000][..]);
+		assert!(n.is_err());
+
+		let n = Nested(Some(Box::new(Nested(None))));
+		let mut v = vec![];
+		serialize(&mut v, &n)?;
+		assert_eq!(deserialize::<Nested, _>(&mut &v[..])?, n);
+		Ok(())
+	}
+
+	#[test]
+	fn test_deserialize_fuzz_entry() -> Result<(), Error> {
+		let value: FuzzValue = vec![
+			("none".to_string(), None),
+			(
+				"some".to_string(),
+				Some(Box::new((
+					vec![true, false],
+					(
+						NaiveDate::from_ymd_opt(2024, 2, 29).unwrap(),
+						Arc::new(('x', 1.5)),
+					),
+				))),
+			),
+		];
+		let mut v = vec![];
+		serialize(&mut v, &value)?;
+		assert_eq!(deserialize::<FuzzValue, _>(&mut &v[..])?, value);
+		deserialize_fuzz_entry(&v);
+
+		// every prefix and random mutations of a valid encoding
+		for i in 0..v.len() {
+			deserialize_fuzz_entry(&v[0..i]);
+			assert!(read_bin(Target::FuzzValue, &v[0..i], Endianness::Big).is_err());
+		}
+		let mut rng = rand::thread_rng();
+		for _ in 0..10_000 {
+			let mut m = v.clone();
+			for _ in 0..rng.gen_range(1..4) {
+				let i = rng.gen_range(0..m.len());
+				m[i] = rng.gen();
+			}
+			deserialize_fuzz_entry(&m);
+		}
+		for (_, _, bytes) in hostile_inputs() {
+			deserialize_fuzz_entry(&bytes);
+		}
+		Ok(())
+	}
+}
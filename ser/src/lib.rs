@@ -23,12 +23,26 @@
 //! data structures in the bmw_util crate. It allows a specific way for data to be serialized so
 //! that it can be stored in various forms. The Reader and Writer traits are abstractions
 //! for reading and writing serializable data structures. The [`crate::Serializable`] macro is implemented for
-//! several data structures in this crate as well.
+//! several data structures in this crate as well, including the chrono `DateTime<Utc>`,
+//! `NaiveDateTime` and `NaiveDate` types. Their byte layout is documented on each implementation
+//! for consumers of the data that are not written in rust.
 
+mod framing;
+mod fuzz;
+#[cfg(any(test, feature = "hardening_tests"))]
+#[doc(hidden)]
+pub mod hardening_tests;
 mod ser;
 mod test;
 mod types;
 
-pub use crate::types::{BinReader, BinWriter, Reader, Serializable, Writer};
+pub use crate::types::{
+	BinReader, BinWriter, BytesRef, DeserializeBorrowed, Endianness, FrameIterator, FrameOptions,
+	Reader, Serializable, StrRef, Writer,
+};
 
-pub use crate::ser::{deserialize, serialize};
+pub use crate::framing::{crc32c, read_frame, write_frame};
+
+pub use crate::fuzz::deserialize_fuzz_entry;
+
+pub use crate::ser::{deserialize, deserialize_le, serialize, serialize_le};
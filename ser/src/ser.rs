@@ -15,9 +15,29 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{BinReader, BinWriter, Reader, Serializable, Writer};
+use crate::{
+	BinReader, BinWriter, BytesRef, DeserializeBorrowed, Endianness, Reader, Serializable, StrRef,
+	Writer,
+};
+use bmw_deps::chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
 use bmw_err::{err, Error};
+use std::cell::Cell;
 use std::io::{Read, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Maximum nesting of [`Box`], [`Rc`] and [`Arc`] values allowed while deserializing. Reads
+/// past this depth return an error instead of overflowing the stack on malicious input. The
+/// value is small enough for a default (2mb) thread stack in unoptimized builds.
+pub(crate) const MAX_READ_DEPTH: usize = 1024;
+
+// the most elements that Vec::read reserves space for before they are read. The length
+// prefix is not trusted, so larger vectors grow as their elements are actually read.
+const MAX_PREALLOC_BYTES: usize = 64 * 1024;
+
+thread_local! {
+	static READ_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
 
 /// Serializes a Serializable into any std::io::Write implementation.
 pub fn serialize<W: Serializable>(sink: &mut dyn Write, thing: &W) -> Result<(), Error> {
@@ -31,6 +51,32 @@ pub fn deserialize<T: Serializable, R: Read>(source: &mut R) -> Result<T, Error>
 	T::read(&mut reader)
 }
 
+/// Same as [`crate::serialize`] except that multi-byte integers are written in little-endian
+/// byte order. See [`crate::Endianness`].
+pub fn serialize_le<W: Serializable>(sink: &mut dyn Write, thing: &W) -> Result<(), Error> {
+	let mut writer = BinWriter::with_endianness(sink, Endianness::Little);
+	thing.write(&mut writer)
+}
+
+/// Same as [`crate::deserialize`] except that multi-byte integers are read in little-endian
+/// byte order. See [`crate::Endianness`].
+pub fn deserialize_le<T: Serializable, R: Read>(source: &mut R) -> Result<T, Error> {
+	let mut reader = BinReader::with_endianness(source, Endianness::Little);
+	T::read(&mut reader)
+}
+
+// read an integer of type `$int` from `$self` in the reader's byte order
+macro_rules! read_int {
+	($self:ident, $int:ty) => {{
+		let mut b = [0u8; std::mem::size_of::<$int>()];
+		$self.source.read_exact(&mut b)?;
+		Ok(match $self.endianness {
+			Endianness::Big => <$int>::from_be_bytes(b),
+			Endianness::Little => <$int>::from_le_bytes(b),
+		})
+	}};
+}
+
 /// implement Serializable for some commonly used types (primative and standard)
 
 macro_rules! impl_int {
@@ -68,19 +114,21 @@ impl Serializable for bool {
 		Ok(())
 	}
 	fn read<R: Reader>(reader: &mut R) -> Result<bool, Error> {
-		Ok(reader.read_u8()? != 0)
+		match reader.read_u8()? {
+			0 => Ok(false),
+			1 => Ok(true),
+			b => Err(invalid_tag_error("bool", b)),
+		}
 	}
 }
 
+// written as the u64 of the same bits so that the writer's byte order is used
 impl Serializable for f64 {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
-		writer.write_fixed_bytes(self.to_be_bytes())?;
-		Ok(())
+		writer.write_u64(self.to_bits())
 	}
 	fn read<R: Reader>(reader: &mut R) -> Result<f64, Error> {
-		let mut b = [0u8; 8];
-		reader.read_fixed_bytes(&mut b)?;
-		Ok(f64::from_be_bytes(b))
+		Ok(f64::from_bits(reader.read_u64()?))
 	}
 }
 
@@ -123,7 +171,8 @@ impl<S: Serializable> Serializable for Vec<S> {
 	}
 	fn read<R: Reader>(reader: &mut R) -> Result<Vec<S>, Error> {
 		let len = reader.read_usize()?;
-		let mut v = Vec::with_capacity(len);
+		let max = MAX_PREALLOC_BYTES / std::mem::size_of::<S>().max(1);
+		let mut v = Vec::with_capacity(len.min(max));
 		for _ in 0..len {
 			v.push(Serializable::read(reader)?);
 		}
@@ -143,11 +192,90 @@ impl<S: Serializable> Serializable for Option<S> {
 	fn read<R: Reader>(reader: &mut R) -> Result<Option<S>, Error> {
 		Ok(match reader.read_u8()? {
 			0 => None,
-			_ => Some(S::read(reader)?),
+			1 => Some(S::read(reader)?),
+			b => return Err(invalid_tag_error("option tag", b)),
 		})
 	}
 }
 
+// counts a level of nesting while a value stored behind a pointer type is read so that deeply
+// recursive input fails with an error rather than a stack overflow. The level is released
+// when the guard is dropped. The pointer impls hold the guard in their own frame, so a level
+// of a recursive read does not need an extra stack frame.
+struct ReadDepthGuard;
+
+impl ReadDepthGuard {
+	fn enter() -> Option<ReadDepthGuard> {
+		let depth = READ_DEPTH.with(|d| d.get()) + 1;
+		if depth > MAX_READ_DEPTH {
+			return None;
+		}
+		READ_DEPTH.with(|d| d.set(depth));
+		Some(ReadDepthGuard)
+	}
+}
+
+impl Drop for ReadDepthGuard {
+	fn drop(&mut self) {
+		READ_DEPTH.with(|d| d.set(d.get() - 1));
+	}
+}
+
+// kept out of line so the error construction doesn't add to the stack frame of every nested
+// read
+#[cold]
+fn max_read_depth_error() -> Error {
+	let fmt = format!("maximum read depth ({}) exceeded", MAX_READ_DEPTH);
+	err!(ErrKind::CorruptedData, fmt)
+}
+
+// an Option may be on the path of a nested read as well, so this is also kept out of line
+#[cold]
+fn invalid_tag_error(name: &str, b: u8) -> Error {
+	let fmt = format!("invalid {}: {}", name, b);
+	err!(ErrKind::CorruptedData, fmt)
+}
+
+impl<S: Serializable> Serializable for Box<S> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		S::write(self, writer)
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Box<S>, Error> {
+		match ReadDepthGuard::enter() {
+			Some(_depth) => S::read(reader).map(Box::new),
+			None => Err(max_read_depth_error()),
+		}
+	}
+}
+
+/// Note: sharing is not preserved. Each deserialized [`Rc`] is a fresh allocation, even if
+/// the serialized values pointed to the same data.
+impl<S: Serializable> Serializable for Rc<S> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		S::write(self, writer)
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Rc<S>, Error> {
+		match ReadDepthGuard::enter() {
+			Some(_depth) => S::read(reader).map(Rc::new),
+			None => Err(max_read_depth_error()),
+		}
+	}
+}
+
+/// Note: sharing is not preserved. Each deserialized [`Arc`] is a fresh allocation, even if
+/// the serialized values pointed to the same data.
+impl<S: Serializable> Serializable for Arc<S> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		S::write(self, writer)
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Arc<S>, Error> {
+		match ReadDepthGuard::enter() {
+			Some(_depth) => S::read(reader).map(Arc::new),
+			None => Err(max_read_depth_error()),
+		}
+	}
+}
+
 impl Serializable for String {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
 		writer.write_usize(self.len())?;
@@ -155,12 +283,11 @@ impl Serializable for String {
 		Ok(())
 	}
 	fn read<R: Reader>(reader: &mut R) -> Result<String, Error> {
-		let mut ret = String::new();
 		let len = reader.read_usize()?;
-		for _ in 0..len {
-			ret.push(reader.read_u8()? as char);
+		match String::from_utf8(reader.read_bytes_vec(len)?) {
+			Ok(ret) => Ok(ret),
+			Err(e) => Err(err!(ErrKind::CorruptedData, format!("invalid utf8: {}", e))),
 		}
-		Ok(ret)
 	}
 }
 
@@ -228,14 +355,109 @@ impl_arr!(30);
 impl_arr!(31);
 impl_arr!(32);
 
+// the number of days from 0001-01-01 (day 1 of the common era) to 1970-01-01
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+// write seconds and nanoseconds since the unix epoch. A leap second (nanos >= 1e9) can not be
+// represented in the canonical encoding.
+fn write_timestamp<W: Writer>(writer: &mut W, secs: i64, nanos: u32) -> Result<(), Error> {
+	if nanos >= 1_000_000_000 {
+		let fmt = format!(
+			"leap second timestamps can not be serialized: {}.{}",
+			secs, nanos
+		);
+		return Err(err!(ErrKind::IllegalArgument, fmt));
+	}
+	writer.write_i64(secs)?;
+	writer.write_u32(nanos)
+}
+
+fn read_timestamp<R: Reader>(reader: &mut R) -> Result<DateTime<Utc>, Error> {
+	let secs = reader.read_i64()?;
+	let nanos = reader.read_u32()?;
+	if nanos >= 1_000_000_000 {
+		let fmt = format!("invalid nanoseconds: {}", nanos);
+		return Err(err!(ErrKind::CorruptedData, fmt));
+	}
+	match DateTime::from_timestamp(secs, nanos) {
+		Some(ts) => Ok(ts),
+		None => {
+			let fmt = format!("timestamp out of range: {} seconds", secs);
+			Err(err!(ErrKind::CorruptedData, fmt))
+		}
+	}
+}
+
+/// Byte layout: an i64 of the seconds since 1970-01-01T00:00:00Z (negative before the epoch)
+/// followed by a u32 of the nanoseconds within that second, 12 bytes in all. Both integers use
+/// the byte order of the [`crate::Writer`] (big-endian unless written with
+/// [`crate::serialize_le`]). The nanoseconds are always less than 1,000,000,000, so leap
+/// seconds can not be written and a value of 1,000,000,000 or more is rejected on read with
+/// [`bmw_err::ErrKind::CorruptedData`], as are seconds outside of the range supported by
+/// chrono (about 262,000 years either side of the epoch).
+impl Serializable for DateTime<Utc> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		write_timestamp(writer, self.timestamp(), self.timestamp_subsec_nanos())
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Self, Error> {
+		read_timestamp(reader)
+	}
+}
+
+/// Byte layout: the same as [`DateTime<Utc>`], i.e. an i64 of seconds and a u32 of
+/// nanoseconds since 1970-01-01T00:00:00, with the value interpreted as if it were in UTC.
+impl Serializable for NaiveDateTime {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		let utc = self.and_utc();
+		write_timestamp(writer, utc.timestamp(), utc.timestamp_subsec_nanos())
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Self, Error> {
+		Ok(read_timestamp(reader)?.naive_utc())
+	}
+}
+
+/// Byte layout: an i32 of the days since 1970-01-01 (negative before the epoch), 4 bytes in
+/// the byte order of the [`crate::Writer`]. Days outside of the range supported by chrono are
+/// rejected on read with [`bmw_err::ErrKind::CorruptedData`].
+impl Serializable for NaiveDate {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		// chrono's date range is well within i32 days of the epoch
+		writer.write_i32(self.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE)
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Self, Error> {
+		let days = reader.read_i32()?;
+		let date = days
+			.checked_add(UNIX_EPOCH_DAYS_FROM_CE)
+			.and_then(NaiveDate::from_num_days_from_ce_opt);
+		match date {
+			Some(date) => Ok(date),
+			None => {
+				let fmt = format!("date out of range: {} days", days);
+				Err(err!(ErrKind::CorruptedData, fmt))
+			}
+		}
+	}
+}
+
 impl<'a> BinWriter<'a> {
-	/// Wraps a standard Write in a new BinWriter
+	/// Wraps a standard Write in a new BinWriter that writes multi-byte integers in
+	/// big-endian byte order.
 	pub fn new(sink: &'a mut dyn Write) -> BinWriter<'a> {
-		BinWriter { sink }
+		Self::with_endianness(sink, Endianness::Big)
+	}
+
+	/// Wraps a standard Write in a new BinWriter that writes multi-byte integers in the
+	/// specified byte order.
+	pub fn with_endianness(sink: &'a mut dyn Write, endianness: Endianness) -> BinWriter<'a> {
+		BinWriter { sink, endianness }
 	}
 }
 
 impl<'a> Writer for BinWriter<'a> {
+	fn endianness(&self) -> Endianness {
+		self.endianness
+	}
+
 	fn write_fixed_bytes<T: AsRef<[u8]>>(&mut self, bytes: T) -> Result<(), Error> {
 		self.sink.write_all(bytes.as_ref())?;
 		Ok(())
@@ -243,13 +465,50 @@ impl<'a> Writer for BinWriter<'a> {
 }
 
 impl<'a, R: Read> BinReader<'a, R> {
-	/// Constructor for a new BinReader for the provided source
+	/// Constructor for a new BinReader for the provided source. Multi-byte integers are read
+	/// in big-endian byte order.
 	pub fn new(source: &'a mut R) -> Self {
-		BinReader { source }
+		Self::with_endianness(source, Endianness::Big)
+	}
+
+	/// Constructor for a new BinReader for the provided source that reads multi-byte
+	/// integers in the specified byte order.
+	pub fn with_endianness(source: &'a mut R, endianness: Endianness) -> Self {
+		BinReader {
+			source,
+			borrow: None,
+			endianness,
+		}
 	}
 }
 
+impl<'a, 'b> BinReader<'a, &'b [u8]> {
+	/// Constructor for a new BinReader for the provided byte slice. Unlike
+	/// [`crate::BinReader::new`], the returned reader supports [`crate::Reader::read_bytes_ref`]
+	/// by handing out slices of the source.
+	pub fn from_slice(source: &'a mut &'b [u8]) -> Self {
+		BinReader {
+			source,
+			borrow: Some(split_slice),
+			endianness: Endianness::Big,
+		}
+	}
+}
+
+fn split_slice<'r>(source: &'r mut &[u8], len: usize) -> Result<&'r [u8], Error> {
+	if len > source.len() {
+		let fmt = format!("needed {} bytes, but only {} remain", len, source.len());
+		return Err(err!(ErrKind::IO, fmt));
+	}
+	let (ret, rem) = source.split_at(len);
+	*source = rem;
+	Ok(ret)
+}
+
 impl<'a, R: Read> Reader for BinReader<'a, R> {
+	fn endianness(&self) -> Endianness {
+		self.endianness
+	}
 	fn read_u8(&mut self) -> Result<u8, Error> {
 		let mut b = [0u8; 1];
 		self.source.read_exact(&mut b)?;
@@ -261,50 +520,32 @@ impl<'a, R: Read> Reader for BinReader<'a, R> {
 		Ok(b[0] as i8)
 	}
 	fn read_i16(&mut self) -> Result<i16, Error> {
-		let mut b = [0u8; 2];
-		self.source.read_exact(&mut b)?;
-		Ok(i16::from_be_bytes(b))
+		read_int!(self, i16)
 	}
 	fn read_u16(&mut self) -> Result<u16, Error> {
-		let mut b = [0u8; 2];
-		self.source.read_exact(&mut b)?;
-		Ok(u16::from_be_bytes(b))
+		read_int!(self, u16)
 	}
 	fn read_u32(&mut self) -> Result<u32, Error> {
-		let mut b = [0u8; 4];
-		self.source.read_exact(&mut b)?;
-		Ok(u32::from_be_bytes(b))
+		read_int!(self, u32)
 	}
 	fn read_i32(&mut self) -> Result<i32, Error> {
-		let mut b = [0u8; 4];
-		self.source.read_exact(&mut b)?;
-		Ok(i32::from_be_bytes(b))
+		read_int!(self, i32)
 	}
 	fn read_u64(&mut self) -> Result<u64, Error> {
-		let mut b = [0u8; 8];
-		self.source.read_exact(&mut b)?;
-		Ok(u64::from_be_bytes(b))
+		read_int!(self, u64)
 	}
 	fn read_i128(&mut self) -> Result<i128, Error> {
-		let mut b = [0u8; 16];
-		self.source.read_exact(&mut b)?;
-		Ok(i128::from_be_bytes(b))
+		read_int!(self, i128)
 	}
 	fn read_usize(&mut self) -> Result<usize, Error> {
-		let mut b = [0u8; 8];
-		self.source.read_exact(&mut b)?;
-		Ok(usize::from_be_bytes(b))
+		read_int!(self, usize)
 	}
 
 	fn read_u128(&mut self) -> Result<u128, Error> {
-		let mut b = [0u8; 16];
-		self.source.read_exact(&mut b)?;
-		Ok(u128::from_be_bytes(b))
+		read_int!(self, u128)
 	}
 	fn read_i64(&mut self) -> Result<i64, Error> {
-		let mut b = [0u8; 8];
-		self.source.read_exact(&mut b)?;
-		Ok(i64::from_be_bytes(b))
+		read_int!(self, i64)
 	}
 
 	fn read_fixed_bytes(&mut self, buf: &mut [u8]) -> Result<(), Error> {
@@ -321,4 +562,46 @@ impl<'a, R: Read> Reader for BinReader<'a, R> {
 			Err(err!(ErrKind::CorruptedData, fmt))
 		}
 	}
+
+	fn read_bytes_ref(&mut self, len: usize) -> Result<&[u8], Error> {
+		match self.borrow {
+			Some(borrow) => borrow(self.source, len),
+			None => {
+				let text = "read_bytes_ref requires a BinReader built with from_slice";
+				Err(err!(ErrKind::OperationNotSupported, text))
+			}
+		}
+	}
+}
+
+impl<'a> StrRef<'a> {
+	/// Returns the borrowed string.
+	pub fn as_str(&self) -> &'a str {
+		self.value
+	}
+}
+
+impl<'a> DeserializeBorrowed<'a> for StrRef<'a> {
+	fn read_borrowed<R: Reader>(reader: &'a mut R) -> Result<Self, Error> {
+		let len = reader.read_usize()?;
+		match std::str::from_utf8(reader.read_bytes_ref(len)?) {
+			Ok(value) => Ok(Self { value }),
+			Err(e) => Err(err!(ErrKind::CorruptedData, format!("invalid utf8: {}", e))),
+		}
+	}
+}
+
+impl<'a> BytesRef<'a> {
+	/// Returns the borrowed bytes.
+	pub fn as_bytes(&self) -> &'a [u8] {
+		self.value
+	}
+}
+
+impl<'a> DeserializeBorrowed<'a> for BytesRef<'a> {
+	fn read_borrowed<R: Reader>(reader: &'a mut R) -> Result<Self, Error> {
+		let len = reader.read_usize()?;
+		let value = reader.read_bytes_ref(len)?;
+		Ok(Self { value })
+	}
 }
@@ -17,7 +17,12 @@
 
 #[cfg(test)]
 mod test {
-	use crate::{deserialize, serialize, Reader, Serializable, Writer};
+	use crate::{
+		crc32c, deserialize, deserialize_le, read_frame, serialize, serialize_le, write_frame,
+		BinReader, BinWriter, BytesRef, DeserializeBorrowed, Endianness, FrameIterator,
+		FrameOptions, Reader, Serializable, StrRef, Writer,
+	};
+	use bmw_deps::chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 	use bmw_deps::rand;
 	use bmw_err::*;
 	use std::fmt::Debug;
@@ -231,6 +236,8 @@ mod test {
 		let ser_in: Result<SerErr, Error> = deserialize(&mut &v[..]);
 		assert!(ser_in.is_err());
 
+		ser_helper(Box::new(5u8))?;
+
 		// test a vec of strings
 		let v = vec!["test1".to_string(), "a".to_string(), "okokok".to_string()];
 		ser_helper(v)?;
@@ -245,4 +252,310 @@ mod test {
 
 		Ok(())
 	}
+
+	struct Header<'a> {
+		msg_type: u8,
+		len: u64,
+		name: StrRef<'a>,
+	}
+
+	impl<'a> DeserializeBorrowed<'a> for Header<'a> {
+		fn read_borrowed<R: Reader>(reader: &'a mut R) -> Result<Self, Error> {
+			let msg_type = reader.read_u8()?;
+			let len = reader.read_u64()?;
+			let name = StrRef::read_borrowed(reader)?;
+			Ok(Self {
+				msg_type,
+				len,
+				name,
+			})
+		}
+	}
+
+	#[test]
+	fn test_read_bytes_ref() -> Result<(), Error> {
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &7u8)?;
+		serialize(&mut v, &1_000u64)?;
+		serialize(&mut v, &"header_name".to_string())?;
+
+		{
+			let mut source = &v[..];
+			let mut reader = BinReader::from_slice(&mut source);
+			let header = Header::read_borrowed(&mut reader)?;
+			assert_eq!(header.msg_type, 7);
+			assert_eq!(header.len, 1_000);
+			assert_eq!(header.name.as_str(), "header_name");
+
+			// the name points into v, so nothing was copied
+			let range = v.as_ptr_range();
+			assert!(range.contains(&header.name.as_str().as_ptr()));
+		}
+
+		// a Vec<u8> can be read as BytesRef
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &vec![1u8, 2, 3])?;
+		let mut source = &v[..];
+		let mut reader = BinReader::from_slice(&mut source);
+		assert_eq!(BytesRef::read_borrowed(&mut reader)?.as_bytes(), &[1, 2, 3]);
+
+		// reading past the end of the data is an error
+		let mut source = &v[..];
+		let mut reader = BinReader::from_slice(&mut source);
+		assert!(reader.read_bytes_ref(v.len() + 1).is_err());
+		assert_eq!(reader.read_bytes_ref(v.len())?, &v[..]);
+
+		// invalid utf8 is an error
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &vec![0xffu8, 0xfe])?;
+		let mut source = &v[..];
+		let mut reader = BinReader::from_slice(&mut source);
+		let e = StrRef::read_borrowed(&mut reader).unwrap_err().kind();
+		assert!(matches!(e, ErrorKind::CorruptedData(_)));
+
+		// a BinReader built with new does not support read_bytes_ref
+		let mut source = &v[..];
+		let mut reader = BinReader::new(&mut source);
+		let e = reader.read_bytes_ref(1).unwrap_err().kind();
+		assert!(matches!(e, ErrorKind::OperationNotSupported(_)));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_endianness() -> Result<(), Error> {
+		// known byte patterns
+		let mut v: Vec<u8> = vec![];
+		serialize_le(&mut v, &0x01020304u32)?;
+		assert_eq!(v, [0x04, 0x03, 0x02, 0x01]);
+		let mut v: Vec<u8> = vec![];
+		serialize_le(&mut v, &0x0102030405060708u64)?;
+		assert_eq!(v, [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+
+		// the default is unchanged (big-endian)
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &0x01020304u32)?;
+		assert_eq!(v, [0x01, 0x02, 0x03, 0x04]);
+		let mut v: Vec<u8> = vec![];
+		let mut writer = BinWriter::with_endianness(&mut v, Endianness::default());
+		0x0102030405060708u64.write(&mut writer)?;
+		assert_eq!(v, [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+
+		// round trip all types in little-endian
+		let rand_u8: u8 = rand::random();
+		let ser_out = SerAll {
+			a: rand::random(),
+			b: rand::random(),
+			c: rand::random(),
+			d: rand::random(),
+			e: rand::random(),
+			f: rand::random(),
+			g: rand::random(),
+			h: rand::random(),
+			i: rand::random(),
+			j: rand::random(),
+			k: rand::random(),
+			l: true,
+			m: rand::random(),
+			n: rand_u8 as char,
+			v: vec![rand::random(), rand::random(), rand::random()],
+			o: Some(rand::random()),
+		};
+		let mut v: Vec<u8> = vec![];
+		serialize_le(&mut v, &ser_out)?;
+		let ser_in: SerAll = deserialize_le(&mut &v[..])?;
+		assert_eq!(ser_in, ser_out);
+
+		let mut source = &v[..];
+		let mut reader = BinReader::with_endianness(&mut source, Endianness::Little);
+		assert_eq!(reader.endianness(), Endianness::Little);
+		assert_eq!(SerAll::read(&mut reader)?, ser_out);
+
+		// mixing byte orders reads different values
+		let mut v: Vec<u8> = vec![];
+		serialize_le(&mut v, &0x01020304u32)?;
+		let value: u32 = deserialize(&mut &v[..])?;
+		assert_eq!(value, 0x04030201);
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &(1u16, -2i64))?;
+		let value: (u16, i64) = deserialize_le(&mut &v[..])?;
+		assert_ne!(value, (1u16, -2i64));
+		assert_eq!(value.0, 0x0100);
+
+		// a length read in the wrong byte order runs past the end of the data
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &"abc".to_string())?;
+		let value: Result<String, Error> = deserialize_le(&mut &v[..]);
+		assert!(value.is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_framing() -> Result<(), Error> {
+		assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+		assert_eq!(crc32c(b""), 0);
+
+		for crc32 in [false, true] {
+			let options = FrameOptions {
+				crc32,
+				..Default::default()
+			};
+			let frames: Vec<Vec<u8>> = vec![b"hello".to_vec(), vec![], vec![7u8; 1_000]];
+			let mut v: Vec<u8> = vec![];
+			// the frame header is big endian regardless of the writer
+			let mut writer = BinWriter::with_endianness(&mut v, Endianness::Little);
+			for frame in &frames {
+				write_frame(&mut writer, frame, &options)?;
+			}
+			let overhead = if crc32 { 8 } else { 4 };
+			assert_eq!(v.len(), 1_005 + 3 * overhead);
+			assert_eq!(&v[0..4], &5u32.to_be_bytes());
+
+			let mut source = &v[..];
+			let mut reader = BinReader::new(&mut source);
+			for frame in &frames {
+				assert_eq!(&read_frame(&mut reader, &options)?, frame);
+			}
+			assert!(read_frame(&mut reader, &options).is_err());
+
+			let read = FrameIterator::new(&v[..], options).collect::<Result<Vec<_>, Error>>()?;
+			assert_eq!(read, frames);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_framing_errors() -> Result<(), Error> {
+		let options = FrameOptions {
+			max_len: 10,
+			crc32: true,
+		};
+		let mut v: Vec<u8> = vec![];
+		write_frame(&mut BinWriter::new(&mut v), b"0123456789", &options)?;
+		let e = write_frame(&mut BinWriter::new(&mut v), b"0123456789a", &options).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::IllegalArgument(_)));
+
+		// a corrupted byte fails the checksum
+		let mut corrupted = v.clone();
+		corrupted[6] ^= 0x01;
+		let e = read_frame(&mut BinReader::new(&mut &corrupted[..]), &options).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Checksum(_)));
+		let mut iter = FrameIterator::new(&corrupted[..], options);
+		assert!(matches!(
+			iter.next().unwrap().unwrap_err().kind(),
+			ErrorKind::Checksum(_)
+		));
+		assert!(iter.next().is_none());
+
+		// the declared length is checked before the payload is allocated
+		let mut oversized = u32::MAX.to_be_bytes().to_vec();
+		oversized.extend_from_slice(b"abc");
+		let e = read_frame(&mut BinReader::new(&mut &oversized[..]), &options).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::CapacityExceeded(_)));
+		let mut iter = FrameIterator::new(&oversized[..], options);
+		let e = iter.next().unwrap().unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::CapacityExceeded(_)));
+
+		// the data ends in the middle of a frame
+		for len in [2, 6, v.len() - 1] {
+			let mut iter = FrameIterator::new(&v[..len], options);
+			let e = iter.next().unwrap().unwrap_err();
+			assert!(matches!(e.kind(), ErrorKind::UnexpectedEof(_)));
+			assert!(iter.next().is_none());
+			assert!(read_frame(&mut BinReader::new(&mut &v[..len]), &options).is_err());
+		}
+		assert!(FrameIterator::new(&v[..0], options).next().is_none());
+		Ok(())
+	}
+
+	#[test]
+	fn test_chrono() -> Result<(), Error> {
+		// pre-1970, the epoch itself and far-future values near the limits of chrono
+		let secs = [
+			-62_135_596_800, // 0001-01-01
+			-1,
+			0,
+			1,
+			1_700_000_000,
+			DateTime::<Utc>::MAX_UTC.timestamp(),
+			DateTime::<Utc>::MIN_UTC.timestamp(),
+		];
+		for secs in secs {
+			for nanos in [0, 1, 999_999_999] {
+				let ts = DateTime::from_timestamp(secs, nanos).unwrap();
+				ser_helper(ts)?;
+				ser_helper(ts.naive_utc())?;
+			}
+		}
+
+		// the byte layout is an i64 of seconds followed by a u32 of nanoseconds
+		let ts = DateTime::from_timestamp(-2, 500).unwrap();
+		let mut v = vec![];
+		serialize(&mut v, &ts)?;
+		let mut expected = (-2i64).to_be_bytes().to_vec();
+		expected.extend_from_slice(&500u32.to_be_bytes());
+		assert_eq!(v, expected);
+		let mut v = vec![];
+		serialize_le(&mut v, &ts)?;
+		let mut expected = (-2i64).to_le_bytes().to_vec();
+		expected.extend_from_slice(&500u32.to_le_bytes());
+		assert_eq!(v, expected);
+		assert_eq!(deserialize_le::<DateTime<Utc>, _>(&mut &v[..])?, ts);
+
+		// corrupted nanos are rejected
+		for nanos in [1_000_000_000u32, u32::MAX] {
+			let mut v = 0i64.to_be_bytes().to_vec();
+			v.extend_from_slice(&nanos.to_be_bytes());
+			let e = deserialize::<DateTime<Utc>, _>(&mut &v[..]).unwrap_err();
+			assert!(matches!(e.kind(), ErrorKind::CorruptedData(_)));
+			let e = deserialize::<NaiveDateTime, _>(&mut &v[..]).unwrap_err();
+			assert!(matches!(e.kind(), ErrorKind::CorruptedData(_)));
+		}
+
+		// seconds out of range
+		for secs in [i64::MAX, i64::MIN, DateTime::<Utc>::MAX_UTC.timestamp() + 1] {
+			let mut v = secs.to_be_bytes().to_vec();
+			v.extend_from_slice(&0u32.to_be_bytes());
+			let e = deserialize::<DateTime<Utc>, _>(&mut &v[..]).unwrap_err();
+			assert!(matches!(e.kind(), ErrorKind::CorruptedData(_)));
+		}
+
+		// leap seconds can not be written
+		let leap = NaiveDate::from_ymd_opt(2016, 12, 31)
+			.unwrap()
+			.and_hms_nano_opt(23, 59, 59, 1_500_000_000)
+			.unwrap()
+			.and_utc();
+		assert!(serialize(&mut vec![], &leap).is_err());
+
+		// dates are the days since the epoch
+		let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+		let mut v = vec![];
+		serialize(&mut v, &epoch)?;
+		assert_eq!(v, 0i32.to_be_bytes());
+		let mut v = vec![];
+		serialize(&mut v, &NaiveDate::from_ymd_opt(1969, 12, 31).unwrap())?;
+		assert_eq!(v, (-1i32).to_be_bytes());
+		for date in [
+			epoch,
+			NaiveDate::from_ymd_opt(1, 1, 1).unwrap(),
+			NaiveDate::from_ymd_opt(1900, 2, 28).unwrap(),
+			NaiveDate::from_ymd_opt(2400, 2, 29).unwrap(),
+			NaiveDate::MIN,
+			NaiveDate::MAX,
+		] {
+			ser_helper(date)?;
+		}
+		for days in [i32::MAX, i32::MIN] {
+			let v = days.to_be_bytes();
+			let e = deserialize::<NaiveDate, _>(&mut &v[..]).unwrap_err();
+			assert!(matches!(e.kind(), ErrorKind::CorruptedData(_)));
+		}
+
+		// truncated input
+		let v = [0u8; 11];
+		assert!(deserialize::<DateTime<Utc>, _>(&mut &v[..]).is_err());
+		Ok(())
+	}
 }
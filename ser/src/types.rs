@@ -18,8 +18,38 @@
 use bmw_err::{err, Error};
 use std::io::{Read, Write};
 
+/// The byte order that [`crate::BinWriter`] and [`crate::BinReader`] use for multi-byte
+/// integers. The default is big-endian, which is the format used by
+/// [`crate::serialize`]/[`crate::deserialize`] and by the data structures in the bmw_util
+/// crate. Little-endian is available for interoperating with external formats. See
+/// [`crate::serialize_le`] and [`crate::deserialize_le`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+	/// Most significant byte first.
+	#[default]
+	Big,
+	/// Least significant byte first.
+	Little,
+}
+
+// the bytes of `$n` in the byte order of `$self`
+macro_rules! endian_bytes {
+	($self:ident, $n:ident) => {
+		match $self.endianness() {
+			Endianness::Big => $n.to_be_bytes(),
+			Endianness::Little => $n.to_le_bytes(),
+		}
+	};
+}
+
 /// Writer trait used for serializing data.
 pub trait Writer {
+	/// the byte order used by the default implementations of the multi-byte write functions.
+	/// The default is [`crate::Endianness::Big`].
+	fn endianness(&self) -> Endianness {
+		Endianness::Big
+	}
+
 	/// write a u8 to the stream
 	fn write_u8(&mut self, n: u8) -> Result<(), Error> {
 		self.write_fixed_bytes(&[n])
@@ -32,47 +62,47 @@ pub trait Writer {
 
 	/// write a u16 to the stream
 	fn write_u16(&mut self, n: u16) -> Result<(), Error> {
-		self.write_fixed_bytes(n.to_be_bytes())
+		self.write_fixed_bytes(endian_bytes!(self, n))
 	}
 
 	/// write an i16 to the stream
 	fn write_i16(&mut self, n: i16) -> Result<(), Error> {
-		self.write_fixed_bytes(n.to_be_bytes())
+		self.write_fixed_bytes(endian_bytes!(self, n))
 	}
 
 	/// write a u32 to the stream
 	fn write_u32(&mut self, n: u32) -> Result<(), Error> {
-		self.write_fixed_bytes(n.to_be_bytes())
+		self.write_fixed_bytes(endian_bytes!(self, n))
 	}
 
 	/// write an i32 to the stream
 	fn write_i32(&mut self, n: i32) -> Result<(), Error> {
-		self.write_fixed_bytes(n.to_be_bytes())
+		self.write_fixed_bytes(endian_bytes!(self, n))
 	}
 
 	/// write a u64 to the stream
 	fn write_u64(&mut self, n: u64) -> Result<(), Error> {
-		self.write_fixed_bytes(n.to_be_bytes())
+		self.write_fixed_bytes(endian_bytes!(self, n))
 	}
 
 	/// write an i128 to the stream
 	fn write_i128(&mut self, n: i128) -> Result<(), Error> {
-		self.write_fixed_bytes(n.to_be_bytes())
+		self.write_fixed_bytes(endian_bytes!(self, n))
 	}
 
 	/// write a u128 to the stream
 	fn write_u128(&mut self, n: u128) -> Result<(), Error> {
-		self.write_fixed_bytes(n.to_be_bytes())
+		self.write_fixed_bytes(endian_bytes!(self, n))
 	}
 
 	/// write an i64 to the stream
 	fn write_i64(&mut self, n: i64) -> Result<(), Error> {
-		self.write_fixed_bytes(n.to_be_bytes())
+		self.write_fixed_bytes(endian_bytes!(self, n))
 	}
 
 	/// write a usize to the stream
 	fn write_usize(&mut self, n: usize) -> Result<(), Error> {
-		self.write_fixed_bytes(n.to_be_bytes())
+		self.write_fixed_bytes(endian_bytes!(self, n))
 	}
 
 	/// write `bytes` to the stream and specify the length so that variable length data may be
@@ -96,6 +126,11 @@ pub trait Writer {
 
 /// Reader trait used for deserializing data.
 pub trait Reader {
+	/// the byte order that multi-byte values are read in. The default is
+	/// [`crate::Endianness::Big`].
+	fn endianness(&self) -> Endianness {
+		Endianness::Big
+	}
 	/// read a u8 from the reader and return the value
 	fn read_u8(&mut self) -> Result<u8, Error>;
 	/// read an i8 from the reader and return the value
@@ -123,6 +158,31 @@ pub trait Reader {
 	/// expect a specific byte, otherwise return an error
 	fn expect_u8(&mut self, val: u8) -> Result<u8, Error>;
 
+	/// read `len` bytes from the reader and return a reference to them without copying. The
+	/// returned slice borrows the reader, so it must be dropped before the reader is used
+	/// again. Readers that cannot hand out references into their underlying data return an
+	/// [`bmw_err::ErrKind::OperationNotSupported`] error, which is the default.
+	fn read_bytes_ref(&mut self, _len: usize) -> Result<&[u8], Error> {
+		let text = "read_bytes_ref is not supported by this reader";
+		Err(err!(ErrKind::OperationNotSupported, text))
+	}
+
+	/// read `len` bytes from the reader into a new [`std::vec::Vec`]. The vector grows as the
+	/// bytes are read, so a corrupt length fails when the data runs out rather than
+	/// allocating `len` bytes up front.
+	fn read_bytes_vec(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+		let mut ret = vec![];
+		let mut chunk = [0u8; 4096];
+		let mut rem = len;
+		while rem > 0 {
+			let clen = rem.min(chunk.len());
+			self.read_fixed_bytes(&mut chunk[0..clen])?;
+			ret.extend_from_slice(&chunk[0..clen]);
+			rem -= clen;
+		}
+		Ok(ret)
+	}
+
 	/// Read bytes, expect them all to be 0u8. Otherwise, reutrn an error.
 	fn read_empty_bytes(&mut self, length: usize) -> Result<(), Error> {
 		for _ in 0..length {
@@ -200,14 +260,100 @@ pub trait Serializable {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error>;
 }
 
+/// The zero-copy counterpart of [`crate::Serializable::read`]. Types that implement this trait
+/// are read from a [`crate::Reader`] that supports [`crate::Reader::read_bytes_ref`] (i.e. a
+/// [`crate::BinReader`] built with [`crate::BinReader::from_slice`]) and may hold references
+/// into the reader's underlying data via [`crate::StrRef`] and [`crate::BytesRef`]. Since the
+/// references borrow the reader for the lifetime `'a`, only the last field read may be a
+/// borrowed field. Fields read before it may be read with a reborrow of the reader.
+///
+/// # Examples
+///
+///```
+/// use bmw_err::*;
+/// use bmw_ser::*;
+///
+/// struct Header<'a> {
+///     version: u8,
+///     name: StrRef<'a>,
+/// }
+///
+/// impl<'a> DeserializeBorrowed<'a> for Header<'a> {
+///     fn read_borrowed<R: Reader>(reader: &'a mut R) -> Result<Self, Error> {
+///         let version = reader.read_u8()?;
+///         let name = StrRef::read_borrowed(reader)?;
+///         Ok(Self { version, name })
+///     }
+/// }
+///
+/// fn main() -> Result<(), Error> {
+///     let mut v: Vec<u8> = vec![];
+///     serialize(&mut v, &1u8)?;
+///     serialize(&mut v, &"name".to_string())?;
+///
+///     let mut source = &v[..];
+///     let mut reader = BinReader::from_slice(&mut source);
+///     let header = Header::read_borrowed(&mut reader)?;
+///     assert_eq!(header.version, 1);
+///     assert_eq!(header.name.as_str(), "name");
+///     Ok(())
+/// }
+///```
+pub trait DeserializeBorrowed<'a>: Sized {
+	/// read data from the reader and build the underlying type, borrowing from the reader
+	/// where possible.
+	fn read_borrowed<R: Reader>(reader: &'a mut R) -> Result<Self, Error>;
+}
+
+/// A borrowed string read with [`crate::DeserializeBorrowed`]. It has the same serialized
+/// form as [`std::string::String`]. The data must be valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StrRef<'a> {
+	pub(crate) value: &'a str,
+}
+
+/// A borrowed byte slice read with [`crate::DeserializeBorrowed`]. It has the same serialized
+/// form as [`std::vec::Vec<u8>`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BytesRef<'a> {
+	pub(crate) value: &'a [u8],
+}
+
 /// Utility wrapper for an underlying byte Writer. Defines higher level methods
 /// to write numbers, byte vectors, hashes, etc.
 pub struct BinWriter<'a> {
 	pub(crate) sink: &'a mut dyn Write,
+	pub(crate) endianness: Endianness,
 }
 
 /// Utility wrapper for an underlying byte Reader. Defines higher level methods
 /// to write numbers, byte vectors, hashes, etc.
 pub struct BinReader<'a, R: Read> {
 	pub(crate) source: &'a mut R,
+	// set if the source is a byte slice. Used by read_bytes_ref.
+	pub(crate) borrow: Option<BorrowFn<R>>,
+	pub(crate) endianness: Endianness,
+}
+
+pub(crate) type BorrowFn<R> = fn(&mut R, usize) -> Result<&[u8], Error>;
+
+/// Options for [`crate::write_frame`], [`crate::read_frame`] and [`crate::FrameIterator`].
+/// The writer and the reader of a frame must use the same options.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameOptions {
+	/// The maximum length of a frame's payload. Longer frames are rejected when written and
+	/// when read. The default is 16 MiB.
+	pub max_len: usize,
+	/// If true, a CRC32C checksum of the payload follows it. The default is false.
+	pub crc32: bool,
+}
+
+/// An iterator over the frames written with [`crate::write_frame`] to a [`std::io::Read`].
+/// Each item is the payload of the next frame. The iterator ends when the source ends at a
+/// frame boundary. If the source ends in the middle of a frame, an
+/// [`bmw_err::ErrKind::UnexpectedEof`] error is returned. After an error, the iterator ends.
+pub struct FrameIterator<R: Read> {
+	pub(crate) source: R,
+	pub(crate) options: FrameOptions,
+	pub(crate) done: bool,
 }
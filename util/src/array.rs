@@ -16,20 +16,27 @@
 // limitations under the License.
 
 use crate::types::{
-	Array, ArrayIterator, ArrayList, ArrayListIterator, Direction, List, Queue, SortableList, Stack,
+	Array, Array2D, ArrayIterator, ArrayList, ArrayListIterator, Direction, List, Queue,
+	SortableList, Stack,
 };
 use bmw_err::*;
 use bmw_ser::Serializable;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
+use std::iter::StepBy;
 use std::ops::{Index, IndexMut};
+use std::slice::Iter;
 
 impl<T: Clone> Array<T> {
 	pub(crate) fn new(size: usize, d: &T) -> Result<Self, Error> {
 		if size == 0 {
 			return Err(err!(ErrKind::IllegalArgument, "size must not be 0"));
 		}
-		let mut data = Vec::with_capacity(size);
+		let mut data = Vec::new();
+		if let Err(e) = data.try_reserve_exact(size) {
+			let fmt = format!("could not allocate an array of size {}: {}", size, e);
+			return Err(err!(ErrKind::Alloc, fmt));
+		}
 		data.resize(size, d.clone());
 
 		let ret = Self { data };
@@ -125,6 +132,126 @@ impl<T> Index<usize> for Array<T> {
 	}
 }
 
+impl<T: Clone> Array2D<T> {
+	pub(crate) fn new(rows: usize, cols: usize, d: &T) -> Result<Self, Error> {
+		if rows == 0 || cols == 0 {
+			return Err(err!(
+				ErrKind::IllegalArgument,
+				"rows and cols must not be 0"
+			));
+		}
+		let size = match rows.checked_mul(cols) {
+			Some(size) => size,
+			None => {
+				let fmt = format!("{} rows of {} cols overflows usize", rows, cols);
+				return Err(err!(ErrKind::Overflow, fmt));
+			}
+		};
+		let data = Array::new(size, d)?;
+		Ok(Self { data, rows, cols })
+	}
+
+	/// Set every cell of row `r` to `value`.
+	pub fn fill_row(&mut self, r: usize, value: &T) -> Result<(), Error> {
+		let start = self.offset(r, 0)?;
+		self.data.as_mut()[start..start + self.cols].fill(value.clone());
+		Ok(())
+	}
+}
+
+impl<T> Array2D<T> {
+	pub fn rows(&self) -> usize {
+		self.rows
+	}
+
+	pub fn cols(&self) -> usize {
+		self.cols
+	}
+
+	pub fn get(&self, r: usize, c: usize) -> Result<&T, Error> {
+		let offset = self.offset(r, c)?;
+		Ok(&self.data.data[offset])
+	}
+
+	pub fn get_mut(&mut self, r: usize, c: usize) -> Result<&mut T, Error> {
+		let offset = self.offset(r, c)?;
+		Ok(&mut self.data.data[offset])
+	}
+
+	/// Iterate over the cells of row `r` from the first column to the last.
+	pub fn row_iter(&self, r: usize) -> Result<Iter<'_, T>, Error> {
+		let start = self.offset(r, 0)?;
+		Ok(self.data.data[start..start + self.cols].iter())
+	}
+
+	/// Iterate over the cells of column `c` from the first row to the last.
+	pub fn col_iter(&self, c: usize) -> Result<StepBy<Iter<'_, T>>, Error> {
+		let start = self.offset(0, c)?;
+		Ok(self.data.data[start..].iter().step_by(self.cols))
+	}
+
+	/// The cells in row major order. Cell (r, c) is at index `r * cols() + c`.
+	pub fn as_flat(&self) -> &[T] {
+		&self.data.data
+	}
+
+	/// The cells in row major order for bulk updates. See [`crate::Array2D::as_flat`].
+	pub fn as_flat_mut(&mut self) -> &mut [T] {
+		&mut self.data.data
+	}
+
+	fn offset(&self, r: usize, c: usize) -> Result<usize, Error> {
+		if r >= self.rows {
+			let fmt = format!("row {} >= {}", r, self.rows);
+			return Err(err!(ErrKind::ArrayIndexOutOfBounds, fmt));
+		}
+		if c >= self.cols {
+			let fmt = format!("col {} >= {}", c, self.cols);
+			return Err(err!(ErrKind::ArrayIndexOutOfBounds, fmt));
+		}
+		Ok(r * self.cols + c)
+	}
+}
+
+impl<T> Debug for Array2D<T>
+where
+	T: Debug,
+{
+	fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+		write!(fmt, "[")?;
+		for (r, row) in self.data.data.chunks(self.cols).enumerate() {
+			if r > 0 {
+				write!(fmt, ", ")?;
+			}
+			write!(fmt, "{:?}", row)?;
+		}
+		write!(fmt, "]")?;
+		Ok(())
+	}
+}
+
+impl<T> PartialEq for Array2D<T>
+where
+	T: PartialEq,
+{
+	fn eq(&self, rhs: &Self) -> bool {
+		self.rows == rhs.rows && self.cols == rhs.cols && self.data == rhs.data
+	}
+}
+
+impl<T> Clone for Array2D<T>
+where
+	T: Clone,
+{
+	fn clone(&self) -> Self {
+		Self {
+			data: self.data.clone(),
+			rows: self.rows,
+			cols: self.cols,
+		}
+	}
+}
+
 unsafe impl<T> Send for ArrayList<T> where T: Send {}
 
 unsafe impl<T> Sync for ArrayList<T> where T: Sync {}
@@ -341,6 +468,16 @@ where
 			ret
 		}
 	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		// a backward iterator stops once it reaches index 0
+		let remaining = if self.dir == Direction::Forward {
+			self.arr.size.saturating_sub(self.c)
+		} else {
+			self.c.min(self.arr.size)
+		};
+		(remaining, Some(remaining))
+	}
 }
 
 impl<'a, T> Iterator for ArrayIterator<'a, T>
@@ -356,4 +493,9 @@ where
 			Some(&self.array_ref[self.cur - 1])
 		}
 	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let remaining = self.array_ref.size().saturating_sub(self.cur);
+		(remaining, Some(remaining))
+	}
 }
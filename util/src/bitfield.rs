@@ -0,0 +1,153 @@
+// Copyright (c) 2023-2024, The BitcoinMW Developers
+// Some code and concepts from:
+// * Grin: https://github.com/mimblewimble/grin
+// * Arti: https://gitlab.torproject.org/tpo/core/arti
+// * BitcoinMW: https://github.com/bitcoinmw/bitcoinmw
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::BitField;
+use bmw_deps::bitvec::vec::BitVec;
+use bmw_err::{err, Error};
+use bmw_log::*;
+
+info!();
+
+impl BitField {
+	pub(crate) fn new(len: usize) -> Result<Self, Error> {
+		if len > u32::MAX as usize {
+			let text = format!("a BitField may have at most {} bits", u32::MAX);
+			return Err(err!(ErrKind::IllegalArgument, text));
+		}
+		Ok(Self {
+			bits: BitVec::repeat(false, len),
+		})
+	}
+
+	/// Returns the number of bits in this [`crate::BitField`].
+	pub fn len(&self) -> usize {
+		self.bits.len()
+	}
+
+	/// Returns true if this [`crate::BitField`] has no bits.
+	pub fn is_empty(&self) -> bool {
+		self.bits.is_empty()
+	}
+
+	/// Returns the value of the bit at `index`.
+	/// # Errors
+	/// [`bmw_err::ErrKind::ArrayIndexOutOfBounds`] - if `index` is not less than
+	/// [`crate::BitField::len`].
+	pub fn get(&self, index: usize) -> Result<bool, Error> {
+		match self.bits.get(index) {
+			Some(bit) => Ok(*bit),
+			None => Err(self.out_of_bounds(index)),
+		}
+	}
+
+	/// Sets the bit at `index` to `value`.
+	/// # Errors
+	/// [`bmw_err::ErrKind::ArrayIndexOutOfBounds`] - if `index` is not less than
+	/// [`crate::BitField::len`].
+	pub fn set(&mut self, index: usize, value: bool) -> Result<(), Error> {
+		if index >= self.bits.len() {
+			return Err(self.out_of_bounds(index));
+		}
+		self.bits.set(index, value);
+		Ok(())
+	}
+
+	/// Returns the number of bits that are set.
+	pub fn count_ones(&self) -> usize {
+		self.bits.count_ones()
+	}
+
+	/// Returns an iterator over the indices of the bits that are set, in ascending order.
+	pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+		self.bits.iter_ones()
+	}
+
+	/// Returns the bitwise AND of this [`crate::BitField`] and `other`.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] - if the lengths of the fields are not equal.
+	pub fn and(&self, other: &BitField) -> Result<BitField, Error> {
+		self.combine(other, |a, b| a & b)
+	}
+
+	/// Returns the bitwise OR of this [`crate::BitField`] and `other`.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] - if the lengths of the fields are not equal.
+	pub fn or(&self, other: &BitField) -> Result<BitField, Error> {
+		self.combine(other, |a, b| a | b)
+	}
+
+	/// Returns the bitwise XOR of this [`crate::BitField`] and `other`.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] - if the lengths of the fields are not equal.
+	pub fn xor(&self, other: &BitField) -> Result<BitField, Error> {
+		self.combine(other, |a, b| a ^ b)
+	}
+
+	// the packed bytes with the unused bits of the last byte cleared
+	pub(crate) fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = self.bits.as_raw_slice().to_vec();
+		let used = self.bits.len() % 8;
+		if let Some(last) = bytes.last_mut() {
+			if used != 0 {
+				*last &= (1u8 << used) - 1;
+			}
+		}
+		bytes
+	}
+
+	// build a field of len bits from packed bytes. The unused bits of the last byte must be 0.
+	pub(crate) fn from_bytes(len: usize, bytes: Vec<u8>) -> Result<Self, Error> {
+		if bytes.len() != len.div_ceil(8) {
+			let text = format!(
+				"{} bytes can't hold a BitField of {} bits",
+				bytes.len(),
+				len
+			);
+			return Err(err!(ErrKind::CorruptedData, text));
+		}
+		let used = len % 8;
+		if let Some(last) = bytes.last() {
+			if used != 0 && last >> used != 0 {
+				let text = "BitField has bits set past its length";
+				return Err(err!(ErrKind::CorruptedData, text));
+			}
+		}
+		let mut bits = BitVec::from_vec(bytes);
+		bits.truncate(len);
+		Ok(Self { bits })
+	}
+
+	fn combine(&self, other: &BitField, f: fn(u8, u8) -> u8) -> Result<BitField, Error> {
+		if self.len() != other.len() {
+			let text = format!(
+				"BitField lengths don't match: {} and {}",
+				self.len(),
+				other.len()
+			);
+			return Err(err!(ErrKind::IllegalArgument, text));
+		}
+		let a = self.to_bytes();
+		let b = other.to_bytes();
+		let bytes = a.iter().zip(b.iter()).map(|(a, b)| f(*a, *b)).collect();
+		Self::from_bytes(self.len(), bytes)
+	}
+
+	fn out_of_bounds(&self, index: usize) -> Error {
+		let text = format!("index {} is out of bounds for {} bits", index, self.len());
+		err!(ErrKind::ArrayIndexOutOfBounds, text)
+	}
+}
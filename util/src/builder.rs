@@ -19,8 +19,9 @@ use crate::types::{
 	HashImpl, HashImplSync, LockImpl, SearchTrieImpl, SlabAllocatorImpl, ThreadPoolImpl,
 };
 use crate::{
-	Array, ArrayList, Hashset, Hashtable, Lock, LockBox, Match, Pattern, Queue, SearchTrie,
-	SlabAllocator, SortableList, Stack, ThreadPool, UtilBuilder,
+	Array, Array2D, ArrayList, BitField, Hashset, Hashtable, Lock, LockBox, Match, MinHeap,
+	Pattern, Queue, SearchTrie, ShardedHashtable, SlabAllocator, SlabReader, SlabWriter,
+	SortableList, Stack, StringInterner, ThreadPool, UtilBuilder,
 };
 use bmw_conf::ConfigOption;
 use bmw_err::*;
@@ -55,6 +56,25 @@ impl UtilBuilder {
 		Array::new(size, default)
 	}
 
+	pub fn build_array2d<T>(rows: usize, cols: usize, default: &T) -> Result<Array2D<T>, Error>
+	where
+		T: Clone,
+	{
+		Array2D::new(rows, cols, default)
+	}
+
+	/// Build a [`crate::MinHeap`] that can hold up to `capacity` values.
+	///
+	/// # Errors
+	///
+	/// [`bmw_err::ErrorKind::IllegalArgument`] is returned if `capacity` is 0.
+	pub fn build_min_heap<V>(capacity: usize) -> Result<MinHeap<V>, Error>
+	where
+		V: Ord + Clone,
+	{
+		MinHeap::new(capacity)
+	}
+
 	pub fn build_array_list<T>(size: usize, default: &T) -> Result<impl SortableList<T>, Error>
 	where
 		T: Clone + Debug + PartialEq + Serializable,
@@ -234,6 +254,34 @@ impl UtilBuilder {
 		Box::new(SlabAllocatorImpl::new())
 	}
 
+	/// Build a [`crate::SlabWriter`] that writes to the slab `slab_id`, which must already be
+	/// allocated in `slabs`. If `slabs` is [`None`], the global thread local slab allocator is
+	/// used and initialized with the default values if it has not been initialized yet. No
+	/// slabs are allocated by this function. The writer allocates additional slabs from the
+	/// allocator once the data written no longer fits in the first slab, as long as the
+	/// pointer to the next slab at the end of the slab has all bits set. If `slab_ptr_size`
+	/// is [`None`], the number of bytes used to link the slabs is derived from the slab
+	/// count of the allocator. This is equivalent to [`crate::SlabWriter::new`].
+	pub fn build_slab_writer(
+		slabs: Option<Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>>,
+		slab_id: usize,
+		slab_ptr_size: Option<usize>,
+	) -> Result<SlabWriter, Error> {
+		SlabWriter::new(slabs, slab_id, slab_ptr_size)
+	}
+
+	/// Build a [`crate::SlabReader`] that reads the data written by a [`crate::SlabWriter`]
+	/// starting at the slab `slab_id`. The parameters must match the ones that the writer
+	/// was built with. This function does not allocate any slabs. This is equivalent to
+	/// [`crate::SlabReader::new`].
+	pub fn build_slab_reader(
+		slabs: Option<Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>>,
+		slab_id: usize,
+		slab_ptr_size: Option<usize>,
+	) -> Result<SlabReader, Error> {
+		SlabReader::new(slabs, slab_id, slab_ptr_size)
+	}
+
 	/// Build a [`crate::Lock`].
 	pub fn build_lock<T>(t: T) -> Result<impl Lock<T>, Error>
 	where
@@ -357,6 +405,48 @@ impl UtilBuilder {
 		Ok(bx)
 	}
 
+	/// Build a [`crate::ShardedHashtable`]. The `configs` are the same as for
+	/// [`crate::UtilBuilder::build_hashtable_sync_box`] and are applied to each shard, so
+	/// MaxEntries and SlabCount are per shard. In addition, Shards may be specified to set the
+	/// number of shards. The default is the number of threads available to the process.
+	///
+	/// # Errors
+	///
+	/// [`bmw_err::ErrorKind::Configuration`] is returned if Shards is 0 or specified more than
+	/// once or if the configuration is not valid for
+	/// [`crate::UtilBuilder::build_hashtable_sync_box`].
+	pub fn build_sharded_hashtable<K, V>(
+		configs: Vec<ConfigOption>,
+	) -> Result<ShardedHashtable<K, V>, Error>
+	where
+		K: Serializable + Hash + PartialEq + Debug + 'static + Clone,
+		V: Serializable + Clone + 'static,
+	{
+		ShardedHashtable::new(configs)
+	}
+
+	/// Build a [`crate::StringInterner`]. MaxEntries sets the maximum number of symbols (the
+	/// default is 1_000) and SlabSize and SlabCount configure the interner's slab allocator, so
+	/// the byte capacity is SlabSize * SlabCount (the defaults are 256 and 256).
+	///
+	/// # Errors
+	///
+	/// [`bmw_err::ErrorKind::Configuration`] is returned if any value is 0, if MaxEntries is
+	/// greater than or equal to [`u32::MAX`] or if any other option is specified.
+	pub fn build_string_interner(configs: Vec<ConfigOption>) -> Result<StringInterner, Error> {
+		StringInterner::new(configs)
+	}
+
+	/// Build a [`crate::BitField`] of `len` bits, all of which are cleared.
+	///
+	/// # Errors
+	///
+	/// [`bmw_err::ErrorKind::IllegalArgument`] is returned if `len` is greater than
+	/// [`u32::MAX`].
+	pub fn build_bitfield(len: usize) -> Result<BitField, Error> {
+		BitField::new(len)
+	}
+
 	pub fn build_hashset_sync<K>(
 		mut configs: Vec<ConfigOption>,
 	) -> Result<impl Hashset<K> + Send + Sync, Error>
@@ -19,8 +19,24 @@ pub(crate) const BUFFER_SIZE: usize = 412;
 
 pub(crate) const THREAD_POOL_DEFAULT_MIN_SIZE: usize = 1;
 pub(crate) const THREAD_POOL_DEFAULT_SYNC_CHANNEL_SIZE: usize = 10;
+// how often an idle worker checks whether the thread pool is shutting down
+pub(crate) const THREAD_POOL_SHUTDOWN_POLL_MILLIS: u64 = 10;
+// how long dropping a thread pool waits for its worker threads to drain the queue
+pub(crate) const THREAD_POOL_DROP_TIMEOUT_MILLIS: u64 = 1_000;
 
 pub(crate) const HASH_DEFAULT_MAX_ENTRIES: usize = 1_000;
 pub(crate) const HASH_DEFAULT_MAX_LOAD_FACTOR: f64 = 0.7;
 pub(crate) const HASH_DEFAULT_SLAB_SIZE: usize = 514;
 pub(crate) const HASH_DEFAULT_SLAB_COUNT: usize = 1_000;
+pub(crate) const HASH_DEFAULT_BLOOM_HASHES: usize = 3;
+
+/// The seed that hashtables and hashsets built with DeterministicHashing(true) hash their keys
+/// with. The hashes are stable across Rust releases but not across targets. See
+/// [`crate::hashtable`].
+pub const DETERMINISTIC_HASH_SEED: u64 = 0x6b6d_7762_6873_6564;
+
+pub(crate) const INTERN_DEFAULT_SLAB_SIZE: usize = 256;
+pub(crate) const INTERN_DEFAULT_SLAB_COUNT: usize = 256;
+pub(crate) const INTERN_INDEX_SLAB_SIZE: usize = 64;
+pub(crate) const INTERN_NONE: u32 = u32::MAX;
+pub(crate) const HEAP_NONE: usize = usize::MAX;
@@ -16,29 +16,77 @@
 // limitations under the License.
 
 use crate::constants::*;
-use crate::misc::{set_max, slice_to_usize, usize_to_slice};
-use crate::types::{Direction, HashImpl, HashImplSync};
+use crate::misc::{checked_add_usize, set_max, slice_to_usize, usize_to_slice};
+use crate::types::{Direction, HashImpl, HashImplSync, SlabRef};
 use crate::{
-	Hashset, HashsetIterator, Hashtable, HashtableIterator, List, ListIterator, LockBox,
-	SlabAllocator, SlabAllocatorConfig, SlabReader, SlabWriter, SortableList, UtilBuilder,
-	GLOBAL_SLAB_ALLOCATOR,
+	Array, Hashset, HashsetIterator, Hashtable, HashtableIterator, HashtableSnapshotIterator,
+	HashtableValueIterator, List, ListIterator, LockBox, ShardedHashtable,
+	ShardedHashtableSnapshotIterator, SlabAllocator, SlabAllocatorConfig, SlabReader, SlabWriter,
+	SortableList, UtilBuilder, GLOBAL_SLAB_ALLOCATOR,
 };
 use bmw_conf::ConfigOptionName as CN;
 use bmw_conf::{ConfigBuilder, ConfigOption};
+use bmw_deps::rand::random;
+use bmw_deps::siphasher::sip::SipHasher13;
 use bmw_err::*;
 use bmw_log::*;
-use bmw_ser::{Reader, Serializable, Writer};
-use std::collections::hash_map::DefaultHasher;
+use bmw_ser::{serialize, BinReader, Reader, Serializable, Writer};
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::thread;
 
+// mixed into the hash used to pick a shard so that it is independent of the hash that the
+// shard uses to pick a slot. Otherwise the keys of a shard would only use some of its slots.
+const SHARD_HASH_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
 const SLOT_EMPTY: usize = usize::MAX;
 const SLOT_DELETED: usize = usize::MAX - 1;
 
 info!();
 
+// the index of the `i`th counter for `hash`. Double hashing is used to derive the
+// BloomHashes indices from a single hash.
+fn bloom_index(hash: usize, i: usize, len: usize) -> usize {
+	let h2 = hash.rotate_left(32) | 1;
+	hash.wrapping_add(i.wrapping_mul(h2)) % len
+}
+
+fn bloom_add(bloom: &mut Array<u8>, hashes: usize, hash: usize) {
+	let len = bloom.size();
+	for i in 0..hashes {
+		let index = bloom_index(hash, i, len);
+		bloom[index] = bloom[index].saturating_add(1);
+	}
+}
+
+fn bloom_remove(bloom: &mut Array<u8>, hashes: usize, hash: usize) {
+	let len = bloom.size();
+	for i in 0..hashes {
+		let index = bloom_index(hash, i, len);
+		// a saturated counter no longer knows how many keys it counts so it is left as is
+		if bloom[index] != u8::MAX {
+			bloom[index] = bloom[index].saturating_sub(1);
+		}
+	}
+}
+
+fn bloom_contains(bloom: &Array<u8>, hashes: usize, hash: usize) -> bool {
+	let len = bloom.size();
+	(0..hashes).all(|i| bloom[bloom_index(hash, i, len)] != 0)
+}
+
+// the number of entries an iterator has left after returning one (or none if it ended early
+// because of an error)
+fn next_remaining(remaining: usize, returned: bool) -> usize {
+	if returned {
+		remaining.saturating_sub(1)
+	} else {
+		0
+	}
+}
+
 impl<'a, K, V> Iterator for HashtableIterator<'a, K, V>
 where
 	K: Serializable + Clone,
@@ -46,12 +94,142 @@ where
 {
 	type Item = (K, V);
 	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
-		match self.hashtable.get_next(&mut self.cur) {
+		let ret = match self.hashtable.get_next(&mut self.cur) {
 			Ok(x) => x,
 			Err(e) => {
 				let _ = error!("get_next generated unexpected error: {}", e);
 				None
 			}
+		};
+		self.remaining = next_remaining(self.remaining, ret.is_some());
+		ret
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(self.remaining, Some(self.remaining))
+	}
+}
+
+impl<'a, K, V> Iterator for HashtableValueIterator<'a, K, V>
+where
+	K: Serializable + Clone,
+	V: Serializable + Clone,
+{
+	type Item = V;
+	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		let hashtable = &self.hashtable;
+		let reader = &mut self.slab_reader;
+		let ret = match hashtable.get_next_slot(&mut self.cur, Direction::Backward, reader) {
+			// the key must be read to find where the value starts
+			Ok(true) => match K::read(reader).and_then(|_| V::read(reader)) {
+				Ok(v) => Some(v),
+				Err(e) => {
+					let _ = warn!("deserialization generated error: {}", e);
+					None
+				}
+			},
+			Ok(false) => None,
+			Err(e) => {
+				let _ = warn!("get_next_slot generated error: {}", e);
+				None
+			}
+		};
+		self.remaining = next_remaining(self.remaining, ret.is_some());
+		ret
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(self.remaining, Some(self.remaining))
+	}
+}
+
+impl<K, V> Iterator for HashtableSnapshotIterator<K, V>
+where
+	K: Serializable,
+	V: Serializable,
+{
+	type Item = (K, V);
+	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		while self.cur < self.entries.len() {
+			let (slab_id, generation) = self.entries[self.cur];
+			self.cur += 1;
+			match self.read_entry(slab_id, generation) {
+				Ok(Some(entry)) => return Some(entry),
+				// the entry was removed since the snapshot
+				Ok(None) => {}
+				Err(e) => {
+					let _ = warn!("snapshot read_entry generated error: {}", e);
+				}
+			}
+		}
+		None
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(0, Some(self.entries.len() - self.cur))
+	}
+}
+
+impl<K, V> HashtableSnapshotIterator<K, V>
+where
+	K: Serializable,
+	V: Serializable,
+{
+	// read the entry that starts at slab_id if the slab has not been freed since the snapshot.
+	// Entries are removed by freeing their first slab before the rest of the chain and the
+	// slab allocator's lock is held while the chain is copied, so the whole chain is intact.
+	fn read_entry(&mut self, slab_id: usize, generation: u64) -> Result<Option<(K, V)>, Error> {
+		let bytes_per_slab = self.bytes_per_slab;
+		let slab_size = self.slab_size;
+		self.buffer.clear();
+		{
+			let slabs = self.slabs.rlock()?;
+			let guard = slabs.guard()?;
+			if guard.generation(slab_id)? != generation {
+				return Ok(None);
+			}
+			let mut cur = slab_id;
+			loop {
+				let slab = guard.get(cur)?;
+				self.buffer.extend(&slab.get()[0..bytes_per_slab]);
+				cur = slice_to_usize(&slab.get()[bytes_per_slab..slab_size])?;
+				cbreak!(cur >= self.max_value);
+			}
+		}
+
+		// skip the pointers of the iteration list that precede the key
+		let mut data = &self.buffer[self.ptr_size * 2..];
+		let mut reader = BinReader::new(&mut data);
+		Ok(Some((K::read(&mut reader)?, V::read(&mut reader)?)))
+	}
+}
+
+impl<K, V> Iterator for ShardedHashtableSnapshotIterator<K, V>
+where
+	K: Serializable + Clone + 'static,
+	V: Serializable + 'static,
+{
+	type Item = (K, V);
+	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		loop {
+			if let Some(cur) = self.cur.as_mut() {
+				if let Some(entry) = cur.next() {
+					return Some(entry);
+				}
+			}
+			self.cur = None;
+			let shard = self.shards.get(self.next_shard)?;
+			self.next_shard += 1;
+			// the shard is only locked while its snapshot is taken
+			let snapshot = shard
+				.rlock()
+				.and_then(|shard| shard.guard().and_then(|shard| shard.snapshot_iter()));
+			match snapshot {
+				Ok(snapshot) => self.cur = Some(snapshot),
+				Err(e) => {
+					let _ = warn!("snapshot of shard generated error: {}", e);
+				}
+			}
 		}
 	}
 }
@@ -62,6 +240,23 @@ where
 {
 	type Item = K;
 	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		let ret = self.next_key();
+		self.remaining = next_remaining(self.remaining, ret.is_some());
+		ret
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(self.remaining, Some(self.remaining))
+	}
+}
+
+impl<'a, K> HashsetIterator<'a, K>
+where
+	K: Serializable + Clone,
+{
+	// for a hashtable, only the key is read. Each slot is seeked to, so the unread value does
+	// not affect the next entry.
+	fn next_key(&mut self) -> Option<K> {
 		let hashset = &mut self.hashset;
 		match hashset.get_next_slot(&mut self.cur, Direction::Backward, &mut self.slab_reader) {
 			Ok(ret) => match ret {
@@ -89,6 +284,21 @@ where
 	type Item = V;
 
 	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		let ret = self.next_value();
+		self.remaining = next_remaining(self.remaining, ret.is_some());
+		ret
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(self.remaining, Some(self.remaining))
+	}
+}
+
+impl<'a, V> ListIterator<'a, V>
+where
+	V: Serializable + Clone,
+{
+	fn next_value(&mut self) -> Option<V> {
 		let list = self.linked_list_ref;
 		if list.size == 0 {
 			return None;
@@ -127,7 +337,23 @@ where
 		Self {
 			hashtable,
 			cur,
+			remaining: hashtable.size,
+			_phantom_data: PhantomData,
+		}
+	}
+}
+
+impl<'a, K, V> HashtableValueIterator<'a, K, V>
+where
+	K: Serializable + Clone,
+{
+	fn new(hashtable: &'a HashImpl<K>, cur: usize) -> Self {
+		Self {
+			hashtable,
+			cur,
+			remaining: hashtable.size,
 			_phantom_data: PhantomData,
+			slab_reader: hashtable.slab_reader.clone(),
 		}
 	}
 }
@@ -140,6 +366,7 @@ where
 		Self {
 			hashset,
 			cur,
+			remaining: hashset.size,
 			_phantom_data: PhantomData,
 			slab_reader: hashset.slab_reader.clone(),
 		}
@@ -156,6 +383,7 @@ where
 			linked_list_ref,
 			cur,
 			direction,
+			remaining: linked_list_ref.size,
 			_phantom_data: PhantomData,
 			slab_reader: linked_list_ref.slab_reader.clone(),
 		}
@@ -288,25 +516,19 @@ where
 	V: Serializable + Clone,
 {
 	fn insert(&mut self, key: &K, value: &V) -> Result<(), Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.static_impl.hash_key(key);
 		self.static_impl
 			.insert_hash_impl(Some(key), Some(value), None, hash)
 	}
 	fn get(&self, key: &K) -> Result<Option<V>, Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.static_impl.hash_key(key);
 		match self.static_impl.get_impl(key, hash)? {
 			Some((_entry, mut reader)) => Ok(Some(V::read(&mut reader)?)),
 			None => Ok(None),
 		}
 	}
 	fn remove(&mut self, key: &K) -> Result<Option<V>, Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.static_impl.hash_key(key);
 		match self.static_impl.get_impl(key, hash)? {
 			Some((entry, mut reader)) => {
 				let v = V::read(&mut reader)?;
@@ -326,6 +548,18 @@ where
 	fn iter<'b>(&'b self) -> HashtableIterator<'b, K, V> {
 		HashtableIterator::new(&self.static_impl, self.static_impl.tail)
 	}
+	fn keys<'b>(&'b self) -> HashsetIterator<'b, K> {
+		HashsetIterator::new(&self.static_impl, self.static_impl.tail)
+	}
+	fn values<'b>(&'b self) -> HashtableValueIterator<'b, K, V> {
+		HashtableValueIterator::new(&self.static_impl, self.static_impl.tail)
+	}
+	fn iter_ordered_by_key(&self) -> std::vec::IntoIter<(K, V)>
+	where
+		K: Ord,
+	{
+		self.static_impl.iter_ordered_by_key_impl()
+	}
 	fn max_load_factor(&self) -> f64 {
 		self.static_impl.max_load_factor
 	}
@@ -333,9 +567,7 @@ where
 		self.static_impl.max_entries
 	}
 	fn bring_to_front(&mut self, key: &K) -> Result<(), Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.static_impl.hash_key(key);
 		self.static_impl.bring_to_front_impl::<V>(key, hash)
 	}
 	fn remove_oldest(&mut self) -> Result<(), Error> {
@@ -347,9 +579,7 @@ where
 		offset: usize,
 		data: &mut [u8; BUFFER_SIZE],
 	) -> Result<bool, Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.static_impl.hash_key(key);
 		self.static_impl.raw_read_impl(key, hash, offset, data)
 	}
 	fn raw_write(
@@ -362,9 +592,7 @@ where
 	where
 		V: Clone,
 	{
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let h = hasher.finish() as usize;
+		let h = self.static_impl.hash_key(key);
 		self.static_impl.raw_write_impl::<V>(key, h, off, data, len)
 	}
 	fn slabs(
@@ -372,6 +600,12 @@ where
 	) -> Result<Option<Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>>, Error> {
 		self.static_impl.slabs_impl()
 	}
+	fn compact(&mut self, max_moves: usize) -> Result<usize, Error> {
+		self.static_impl.compact_impl(max_moves)
+	}
+	fn snapshot_iter(&self) -> Result<HashtableSnapshotIterator<K, V>, Error> {
+		self.static_impl.snapshot_iter_impl()
+	}
 }
 
 impl<K> Hashset<K> for HashImplSync<K>
@@ -379,32 +613,16 @@ where
 	K: Serializable + Hash + PartialEq + Debug + Clone,
 {
 	fn insert(&mut self, key: &K) -> Result<(), Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
-		self.static_impl
-			.insert_hash_impl::<K>(Some(key), None, None, hash)
+		let hash = self.static_impl.hash_key(key);
+		self.static_impl.insert_set_impl(key, hash)
 	}
 	fn contains(&self, key: &K) -> Result<bool, Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
-		match self.static_impl.get_impl(key, hash)? {
-			Some(_) => Ok(true),
-			None => Ok(false),
-		}
+		let hash = self.static_impl.hash_key(key);
+		self.static_impl.contains_set_impl(key, hash)
 	}
 	fn remove(&mut self, key: &K) -> Result<bool, Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
-		match self.static_impl.get_impl(key, hash)? {
-			Some((entry, _reader)) => {
-				self.static_impl.remove_impl(entry)?;
-				Ok(true)
-			}
-			None => Ok(false),
-		}
+		let hash = self.static_impl.hash_key(key);
+		self.static_impl.remove_set_impl(key, hash)
 	}
 	fn size(&self) -> usize {
 		self.static_impl.size
@@ -416,6 +634,12 @@ where
 	fn iter<'b>(&'b self) -> HashsetIterator<'b, K> {
 		HashsetIterator::new(&self.static_impl, self.static_impl.tail)
 	}
+	fn keys<'b>(&'b self) -> HashsetIterator<'b, K> {
+		Hashset::iter(self)
+	}
+	fn rebuild_bloom(&mut self) -> Result<(), Error> {
+		self.static_impl.rebuild_bloom_impl()
+	}
 	fn max_load_factor(&self) -> f64 {
 		self.static_impl.max_load_factor
 	}
@@ -462,6 +686,7 @@ where
 struct SaInfo {
 	slab_size: usize,
 	slab_count: usize,
+	id_limit: usize,
 	slabs: Option<Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>>,
 }
 
@@ -469,11 +694,13 @@ impl SaInfo {
 	fn new(
 		slab_size: usize,
 		slab_count: usize,
+		id_limit: usize,
 		slabs: Option<Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>>,
 	) -> Self {
 		Self {
 			slab_size,
 			slab_count,
+			id_limit,
 			slabs,
 		}
 	}
@@ -498,6 +725,10 @@ where
 				CN::DebugLargeSlabCount,
 				CN::GlobalSlabAllocator,
 				CN::IsSync,
+				CN::BloomBits,
+				CN::BloomHashes,
+				CN::DeterministicHashing,
+				CN::ContiguousThresholdBytes,
 			],
 			vec![],
 		)?;
@@ -519,6 +750,14 @@ where
 		let max_load_factor_specified = config.get(&CN::MaxLoadFactor).is_some();
 		let slab_size_specified = config.get(&CN::SlabSize).is_some();
 		let slab_count_specified = config.get(&CN::SlabCount).is_some();
+		let bloom_bits = config.get_or_usize(&CN::BloomBits, 0);
+		let bloom_hashes = config.get_or_usize(&CN::BloomHashes, HASH_DEFAULT_BLOOM_HASHES);
+		let bloom_bits_specified = config.get(&CN::BloomBits).is_some();
+		let bloom_hashes_specified = config.get(&CN::BloomHashes).is_some();
+		let deterministic_hashing = config.get_or_bool(&CN::DeterministicHashing, false);
+		let contiguous_threshold = config
+			.get(&CN::ContiguousThresholdBytes)
+			.map(|_| config.get_or_usize(&CN::ContiguousThresholdBytes, 0));
 
 		if is_sync && is_global_slab_allocator {
 			let text = "IsSync is not allowed with GlobalSlabAllocator";
@@ -550,6 +789,31 @@ where
 			return Err(err!(ErrKind::Configuration, text));
 		}
 
+		if !is_hashset && (bloom_bits_specified || bloom_hashes_specified) {
+			let text = "BloomBits/BloomHashes are only valid for a hashset";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+
+		if is_list && config.get(&CN::DeterministicHashing).is_some() {
+			let text = "DeterministicHashing not valid for a list";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+
+		if !is_hashtable && contiguous_threshold.is_some() {
+			let text = "ContiguousThresholdBytes is only valid for a hashtable";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+
+		if bloom_hashes_specified && !bloom_bits_specified {
+			let text = "BloomHashes requires BloomBits to be specified";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+
+		if bloom_bits_specified && (bloom_bits == 0 || bloom_hashes == 0) {
+			let text = "BloomBits and BloomHashes must be greater than 0";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+
 		if (slab_count_specified && !slab_size_specified)
 			|| (slab_size_specified && !slab_count_specified)
 		{
@@ -572,10 +836,11 @@ where
 			let sc = SlabAllocatorConfig {
 				slab_size,
 				slab_count,
+				..Default::default()
 			};
 			slabs.init(sc)?;
 			let slabs_ret = UtilBuilder::build_lock_box(slabs)?;
-			SaInfo::new(slab_size, slab_count, Some(slabs_ret))
+			SaInfo::new(slab_size, slab_count, slab_count, Some(slabs_ret))
 		} else {
 			GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<SaInfo, Error> {
 				let slabs = unsafe { f.get().as_mut().unwrap() };
@@ -591,12 +856,14 @@ where
 					slabs.slab_size()?
 				};
 				let slab_count = slabs.slab_count()?;
-				Ok(SaInfo::new(slab_size, slab_count, None))
+				let id_limit = slabs.id_limit()?;
+				Ok(SaInfo::new(slab_size, slab_count, id_limit, None))
 			})?
 		};
 
 		let slab_size = sa.slab_size;
 		let slab_count = sa.slab_count;
+		let id_limit = sa.id_limit;
 		let slabs = sa.slabs;
 
 		if slab_size > 256 * 256 {
@@ -623,7 +890,7 @@ where
 		}
 
 		let (entry_array, ptr_size) = if is_list {
-			let mut x = slab_count;
+			let mut x = id_limit;
 			let mut ptr_size = 0;
 			loop {
 				cbreak!(x == 0);
@@ -633,10 +900,23 @@ where
 			debug!("ptr_size={}", ptr_size)?;
 			(None, ptr_size)
 		} else {
-			let size: usize = (max_entries as f64 / max_load_factor).ceil() as usize;
+			let size = (max_entries as f64 / max_load_factor).ceil();
+			// the cast below saturates so check the range first
+			if size >= usize::MAX as f64 {
+				let fmt = format!(
+					"entry array size overflows usize: MaxEntries={} / MaxLoadFactor={}",
+					max_entries, max_load_factor
+				);
+				return Err(err!(ErrKind::Configuration, fmt));
+			}
+			let size = size as usize;
 			let entry_array = UtilBuilder::build_array(size, &SLOT_EMPTY)?;
 			debug!("entry array init to size = {}", size)?;
-			let mut x = entry_array.size() + 2; // two more, one for deleted and one for empty
+			let mut x = checked_add_usize(entry_array.size(), 2)?; // two more, one for deleted and one for empty
+			if id_limit > slab_count {
+				// the slab chains must also be able to point to heap overflow slabs
+				x = x.max(id_limit);
+			}
 			let mut ptr_size = 0;
 			loop {
 				cbreak!(x == 0);
@@ -650,6 +930,13 @@ where
 		set_max(&mut ptr[0..ptr_size]);
 		let max_value = slice_to_usize(&ptr[0..ptr_size])?;
 
+		// the filter is allocated once here and never resized
+		let bloom = if bloom_bits_specified {
+			Some(UtilBuilder::build_array(bloom_bits, &0u8)?)
+		} else {
+			None
+		};
+
 		let bytes_per_slab = slab_size.saturating_sub(ptr_size);
 		if slab_size < ptr_size * 4 {
 			let fmt = format!("SlabSize is too small. Must be at least {}", ptr_size * 4);
@@ -670,6 +957,12 @@ where
 			),
 		};
 
+		// a random seed keeps the slots that keys hash to from being predicted
+		let hash_seed = match deterministic_hashing {
+			true => DETERMINISTIC_HASH_SEED,
+			false => random(),
+		};
+
 		let ret = Self {
 			slabs,
 			entry_array,
@@ -688,10 +981,35 @@ where
 			is_hashtable,
 			debug_get_next_slot_error: false,
 			debug_entry_array_len: false,
+			bloom,
+			bloom_hashes,
+			hash_seed,
+			contiguous_threshold,
+			#[cfg(test)]
+			debug_key_reads: std::cell::Cell::new(0),
 		};
 		Ok(ret)
 	}
 
+	fn iter_ordered_by_key_impl<V>(&self) -> std::vec::IntoIter<(K, V)>
+	where
+		K: Ord,
+		V: Serializable + Clone,
+	{
+		let mut entries: Vec<(K, V)> = HashtableIterator::new(self, self.tail).collect();
+		// keys are unique so the sort is total
+		entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+		entries.into_iter()
+	}
+
+	// SipHash-1-3 is pinned (rather than using DefaultHasher) so that the slots are the same
+	// across Rust releases with DeterministicHashing
+	pub(crate) fn hash_key<Q: Hash + ?Sized>(&self, key: &Q) -> usize {
+		let mut hasher = SipHasher13::new_with_keys(self.hash_seed, 0);
+		key.hash(&mut hasher);
+		hasher.finish() as usize
+	}
+
 	fn get_next<V>(
 		&self,
 		cur: &mut usize,
@@ -787,6 +1105,12 @@ where
 			self.entry_array = Some(entry_array);
 		}
 
+		if let Some(bloom) = self.bloom.as_mut() {
+			for i in 0..bloom.size() {
+				bloom[i] = 0;
+			}
+		}
+
 		Ok(())
 	}
 
@@ -948,27 +1272,31 @@ where
 		K: Serializable + PartialEq + Clone,
 		V: Serializable + Clone,
 	{
-		let entry_array_len = self.entry_array.as_ref().unwrap().size();
+		let entry_array = self.entry_array.as_ref();
+		let entry_array_len = try_opt!(entry_array, ErrKind::IllegalState, "no entry array").size();
 
-		let key_val = key.unwrap();
+		let key_val = try_opt!(key, ErrKind::IllegalArgument, "insert requires a key");
 		let mut entry = hash % entry_array_len;
 
 		// check the load factor
-		if (self.size + 1) as f64 > self.max_load_factor * entry_array_len as f64 {
-			let fmt = format!("load factor ({}) exceeded", self.max_load_factor);
-			return Err(err!(ErrKind::CapacityExceeded, fmt));
-		}
+		let max_load_factor = self.max_load_factor;
+		ensure!(
+			(self.size + 1) as f64 <= max_load_factor * entry_array_len as f64,
+			ErrKind::CapacityExceeded,
+			"load factor ({}) exceeded",
+			max_load_factor
+		);
 
 		let mut i = 0;
 		let mut slab_id = self.max_value;
 		let mut raw_exists = false;
 		loop {
 			debug!("loop")?;
-			if i >= entry_array_len || self.debug_entry_array_len {
-				let msg = "HashImpl: Capacity exceeded";
-				debug!("err1")?;
-				return Err(err!(ErrKind::CapacityExceeded, msg));
-			}
+			ensure!(
+				i < entry_array_len && !self.debug_entry_array_len,
+				ErrKind::CapacityExceeded,
+				"HashImpl: Capacity exceeded"
+			);
 			let entry_value = self.lookup_entry(entry);
 			cbreak!(entry_value == SLOT_EMPTY || entry_value == SLOT_DELETED);
 
@@ -1021,7 +1349,10 @@ where
 		debug!("in insert_impl")?;
 		let slab_id = match slab_id_allocated {
 			Some(slab_id) => slab_id,
-			None => self.allocate()?,
+			None => match self.contiguous_slabs(key, value)? {
+				Some(n_slabs) => self.allocate_contiguous(n_slabs)?,
+				None => self.allocate()?,
+			},
 		};
 		debug!("alloc={},entry={:?}", slab_id, entry)?;
 		self.slab_writer.seek(slab_id, 0);
@@ -1117,6 +1448,8 @@ where
 	}
 
 	fn read_key(&self, slab_id: usize) -> Result<Option<(K, SlabReader)>, Error> {
+		#[cfg(test)]
+		self.debug_key_reads.set(self.debug_key_reads.get() + 1);
 		let ptr_size = self.ptr_size;
 		// get a reader, we have to clone the rc because we are not mutable
 		let mut reader = self.slab_reader.clone();
@@ -1154,6 +1487,80 @@ where
 		}
 	}
 
+	// the number of slabs needed to store this entry if its value is larger than the
+	// ContiguousThresholdBytes, otherwise None.
+	fn contiguous_slabs<V>(
+		&self,
+		key: Option<&K>,
+		value: Option<&V>,
+	) -> Result<Option<usize>, Error>
+	where
+		V: Serializable,
+	{
+		let threshold = match self.contiguous_threshold {
+			Some(threshold) => threshold,
+			None => return Ok(None),
+		};
+		let mut buf = vec![];
+		match value {
+			Some(value) => serialize(&mut buf, value)?,
+			None => return Ok(None),
+		}
+		if buf.len() <= threshold {
+			return Ok(None);
+		}
+		if let Some(key) = key {
+			serialize(&mut buf, key)?;
+		}
+		let len = buf.len() + self.ptr_size * 2;
+		Ok(Some(len.div_ceil(self.bytes_per_slab)))
+	}
+
+	// allocate n_slabs adjacent slabs which are linked in order so they can be read like any
+	// other chain. If no run of adjacent slabs is free, a single slab is allocated and the
+	// entry is chained as usual.
+	fn allocate_contiguous(&mut self, n_slabs: usize) -> Result<usize, Error> {
+		let bytes_per_slab = self.bytes_per_slab;
+		let slab_size = self.slab_size;
+		let link = |slabs: &mut dyn SlabAllocator| -> Result<Option<usize>, Error> {
+			let range = match slabs.allocate_contiguous(n_slabs) {
+				Ok(range) => range,
+				Err(e) => match e.kind() {
+					ErrorKind::Fragmentation(_) | ErrorKind::CapacityExceeded(_) => {
+						debug!("contiguous allocation failed: {}", e)?;
+						return Ok(None);
+					}
+					_ => return Err(e),
+				},
+			};
+			let end = range.start() + range.count();
+			for id in range.start()..end {
+				let mut slab = slabs.get_mut(id)?;
+				let next = &mut slab.get_mut()[bytes_per_slab..slab_size];
+				match id + 1 < end {
+					true => usize_to_slice(id + 1, next)?,
+					false => set_max(next),
+				}
+			}
+			Ok(Some(range.start()))
+		};
+		let start = match &mut self.slabs {
+			Some(slabs) => {
+				let mut slabs = slabs.wlock()?;
+				let guard = slabs.guard()?;
+				link(&mut ***guard)?
+			}
+			None => GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<Option<usize>, Error> {
+				let slabs = unsafe { f.get().as_mut().unwrap() };
+				link(&mut **slabs)
+			})?,
+		};
+		match start {
+			Some(start) => Ok(start),
+			None => self.allocate(),
+		}
+	}
+
 	fn free(&mut self, slab_id: usize) -> Result<(), Error> {
 		match &mut self.slabs {
 			Some(slabs) => {
@@ -1281,6 +1688,190 @@ where
 		Ok(self.slabs.clone())
 	}
 
+	fn snapshot_iter_impl<V>(&self) -> Result<HashtableSnapshotIterator<K, V>, Error> {
+		// the global slab allocator is thread local so it cannot be read without the
+		// hashtable
+		let slabs = match &self.slabs {
+			Some(slabs) => slabs.clone(),
+			None => {
+				let text = "snapshot_iter requires a dedicated slab allocator";
+				return Err(err!(ErrKind::IllegalState, text));
+			}
+		};
+		let entry_array = try_opt!(
+			self.entry_array.as_ref(),
+			ErrKind::IllegalState,
+			"snapshot_iter called with no entry array"
+		);
+
+		let mut entries = Vec::with_capacity(self.size);
+		{
+			let slabs = slabs.rlock()?;
+			let guard = slabs.guard()?;
+			for entry in 0..entry_array.size() {
+				let slab_id = entry_array[entry];
+				if slab_id == SLOT_EMPTY || slab_id == SLOT_DELETED {
+					continue;
+				}
+				entries.push((slab_id, guard.generation(slab_id)?));
+			}
+		}
+
+		Ok(HashtableSnapshotIterator {
+			slabs,
+			entries,
+			cur: 0,
+			buffer: vec![],
+			bytes_per_slab: self.bytes_per_slab,
+			slab_size: self.slab_size,
+			ptr_size: self.ptr_size,
+			max_value: self.max_value,
+			_phantom_data: PhantomData,
+		})
+	}
+
+	fn compact_impl(&mut self, max_moves: usize) -> Result<usize, Error> {
+		// slabs in the global slab allocator are shared with other data structures so
+		// they cannot be relocated by us.
+		let mut slabs = match &self.slabs {
+			Some(slabs) => slabs.clone(),
+			None => {
+				let text = "compact requires a dedicated slab allocator";
+				return Err(err!(ErrKind::IllegalState, text));
+			}
+		};
+		let entry_array = match self.entry_array.as_mut() {
+			Some(entry_array) => entry_array,
+			None => {
+				let text = "compact called with no entry array";
+				return Err(err!(ErrKind::IllegalState, text));
+			}
+		};
+		let bytes_per_slab = self.bytes_per_slab;
+		let slab_size = self.slab_size;
+		let ptr_size = self.ptr_size;
+
+		// find the location where each of our slab ids is stored
+		let mut refs = HashMap::new();
+		{
+			let slabs = slabs.rlock()?;
+			let guard = slabs.guard()?;
+			for entry in 0..entry_array.size() {
+				let mut cur = entry_array[entry];
+				if cur == SLOT_EMPTY || cur == SLOT_DELETED {
+					continue;
+				}
+				refs.insert(cur, SlabRef::Entry(entry));
+				loop {
+					let slab = guard.get(cur)?;
+					let next = slice_to_usize(&slab.get()[bytes_per_slab..slab_size])?;
+					cbreak!(next >= self.max_value);
+					refs.insert(next, SlabRef::Next(cur));
+					cur = next;
+				}
+			}
+		}
+
+		// the slab allocator is locked during compaction so just record the moves
+		let mut moved = HashMap::new();
+		let moves = {
+			let mut slabs = slabs.wlock()?;
+			let guard = slabs.guard()?;
+			guard.compact(max_moves, &mut |old, new| {
+				moved.insert(old, new);
+				Ok(())
+			})?
+		};
+
+		// update the stored slab ids. Note that the slab holding a chain pointer may itself
+		// have been moved.
+		let mut slabs = slabs.wlock()?;
+		let guard = slabs.guard()?;
+		let mut ptr = [0u8; 8];
+		for (old, new) in &moved {
+			match refs.get(old) {
+				Some(SlabRef::Entry(entry)) => entry_array[*entry] = *new,
+				Some(SlabRef::Next(prev)) => {
+					let prev = moved.get(prev).unwrap_or(prev);
+					usize_to_slice(*new, &mut ptr[0..ptr_size])?;
+					let mut slab = guard.get_mut(*prev)?;
+					slab.get_mut()[bytes_per_slab..slab_size].clone_from_slice(&ptr[0..ptr_size]);
+				}
+				None => warn!("slab {} moved, but not referenced by this hashtable", old)?,
+			}
+		}
+		debug!("compact moved {} slabs", moves)?;
+
+		Ok(moves)
+	}
+
+	fn insert_set_impl(&mut self, key: &K, hash: usize) -> Result<(), Error>
+	where
+		K: PartialEq,
+	{
+		let size = self.size;
+		self.insert_hash_impl::<K>(Some(key), None, None, hash)?;
+		// only count new keys so that a single remove undoes the insert
+		if self.size != size {
+			if let Some(bloom) = self.bloom.as_mut() {
+				bloom_add(bloom, self.bloom_hashes, hash);
+			}
+		}
+		Ok(())
+	}
+
+	fn contains_set_impl(&self, key: &K, hash: usize) -> Result<bool, Error>
+	where
+		K: PartialEq,
+	{
+		if let Some(bloom) = self.bloom.as_ref() {
+			if !bloom_contains(bloom, self.bloom_hashes, hash) {
+				return Ok(false);
+			}
+		}
+		Ok(self.get_impl(key, hash)?.is_some())
+	}
+
+	fn remove_set_impl(&mut self, key: &K, hash: usize) -> Result<bool, Error>
+	where
+		K: PartialEq,
+	{
+		if let Some(bloom) = self.bloom.as_ref() {
+			if !bloom_contains(bloom, self.bloom_hashes, hash) {
+				return Ok(false);
+			}
+		}
+		match self.get_impl(key, hash)? {
+			Some((entry, _reader)) => {
+				self.remove_impl(entry)?;
+				if let Some(bloom) = self.bloom.as_mut() {
+					bloom_remove(bloom, self.bloom_hashes, hash);
+				}
+				Ok(true)
+			}
+			None => Ok(false),
+		}
+	}
+
+	fn rebuild_bloom_impl(&mut self) -> Result<(), Error>
+	where
+		K: Hash,
+	{
+		let mut bloom = try_opt!(
+			self.bloom.take(),
+			ErrKind::IllegalState,
+			"this hashset has no bloom filter"
+		);
+		for i in 0..bloom.size() {
+			bloom[i] = 0;
+		}
+		for key in HashsetIterator::new(self, self.tail) {
+			bloom_add(&mut bloom, self.bloom_hashes, self.hash_key(&key));
+		}
+		self.bloom = Some(bloom);
+		Ok(())
+	}
+
 	#[cfg(test)]
 	pub(crate) fn set_debug_get_next_slot_error(&mut self, v: bool) {
 		self.debug_get_next_slot_error = v;
@@ -1292,6 +1883,109 @@ where
 	}
 }
 
+impl<K, V> ShardedHashtable<K, V>
+where
+	K: Serializable + Hash + PartialEq + Debug + Clone + 'static,
+	V: Serializable + Clone + 'static,
+{
+	pub(crate) fn new(configs: Vec<ConfigOption>) -> Result<Self, Error> {
+		let (shard_configs, configs): (Vec<ConfigOption>, Vec<ConfigOption>) = configs
+			.into_iter()
+			.partition(|config| matches!(config, ConfigOption::Shards(_)));
+		let config = ConfigBuilder::build_config(shard_configs);
+		config.check_config(vec![CN::Shards], vec![])?;
+		let default_shards = thread::available_parallelism().map_or(1, |n| n.get());
+		let shard_count = config.get_or_usize(&CN::Shards, default_shards);
+		if shard_count == 0 {
+			let text = "Shards must be greater than 0";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+
+		let mut shards = vec![];
+		for _ in 0..shard_count {
+			let hashtable = UtilBuilder::build_hashtable_sync_box(configs.clone())?;
+			shards.push(UtilBuilder::build_lock_box(hashtable)?);
+		}
+		Ok(Self { shards })
+	}
+
+	/// Insert a key/value pair into the shard that `key` belongs to.
+	pub fn insert(&mut self, key: &K, value: &V) -> Result<(), Error> {
+		let shard = self.shard(key);
+		let mut shard = self.shards[shard].wlock()?;
+		shard.guard()?.insert(key, value)
+	}
+
+	/// Get the value associated with the specified `key`.
+	pub fn get(&self, key: &K) -> Result<Option<V>, Error> {
+		let shard = self.shard(key);
+		let shard = self.shards[shard].rlock()?;
+		shard.guard()?.get(key)
+	}
+
+	/// Remove the specified `key` from the shard that it belongs to.
+	pub fn remove(&mut self, key: &K) -> Result<Option<V>, Error> {
+		let shard = self.shard(key);
+		let mut shard = self.shards[shard].wlock()?;
+		shard.guard()?.remove(key)
+	}
+
+	/// Return the total size of all shards. The shards are locked one at a time, so if other
+	/// threads are modifying the hashtable, the result is not a snapshot of a single point in
+	/// time.
+	pub fn size(&self) -> Result<usize, Error> {
+		let mut size = 0;
+		for shard in &self.shards {
+			size += shard.rlock()?.guard()?.size();
+		}
+		Ok(size)
+	}
+
+	/// Clear all shards. The shards are cleared one at a time.
+	pub fn clear(&mut self) -> Result<(), Error> {
+		self.for_each_shard(|shard| shard.clear())
+	}
+
+	/// Return the number of shards.
+	pub fn shards(&self) -> usize {
+		self.shards.len()
+	}
+
+	/// Returns an [`std::iter::Iterator`] over snapshots of the shards. See
+	/// [`crate::Hashtable::snapshot_iter`]. The snapshot of each shard is taken when the
+	/// iteration reaches it and the shard is only locked while the snapshot is taken, so
+	/// writers to a shard are never blocked for more than that. The same weak consistency
+	/// applies: entries that are present for the whole iteration are returned exactly once.
+	pub fn snapshot_iter(&self) -> ShardedHashtableSnapshotIterator<K, V> {
+		ShardedHashtableSnapshotIterator {
+			shards: self.shards.clone(),
+			next_shard: 0,
+			cur: None,
+		}
+	}
+
+	/// Call `f` with each shard in turn (i.e. for maintenance operations such as
+	/// [`crate::Hashtable::compact`]). Only the lock of the shard that is passed to `f` is held
+	/// while `f` executes. If `f` returns an error, the remaining shards are not visited and
+	/// the error is returned.
+	pub fn for_each_shard<F>(&mut self, mut f: F) -> Result<(), Error>
+	where
+		F: FnMut(&mut Box<dyn Hashtable<K, V> + Send + Sync>) -> Result<(), Error>,
+	{
+		for shard in &mut self.shards {
+			let mut shard = shard.wlock()?;
+			f(shard.guard()?)?;
+		}
+		Ok(())
+	}
+
+	fn shard(&self, key: &K) -> usize {
+		let mut hasher = SipHasher13::new_with_keys(SHARD_HASH_SEED, 0);
+		key.hash(&mut hasher);
+		hasher.finish() as usize % self.shards.len()
+	}
+}
+
 impl<K> Drop for HashImpl<K>
 where
 	K: Serializable + Clone,
@@ -1311,24 +2005,18 @@ where
 	V: Serializable + Clone,
 {
 	fn insert(&mut self, key: &K, value: &V) -> Result<(), Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.hash_key(key);
 		self.insert_hash_impl(Some(key), Some(value), None, hash)
 	}
 	fn get(&self, key: &K) -> Result<Option<V>, Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.hash_key(key);
 		match self.get_impl(key, hash)? {
 			Some((_entry, mut reader)) => Ok(Some(V::read(&mut reader)?)),
 			None => Ok(None),
 		}
 	}
 	fn remove(&mut self, key: &K) -> Result<Option<V>, Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.hash_key(key);
 		match self.get_impl(key, hash)? {
 			Some((entry, mut reader)) => {
 				let v = V::read(&mut reader)?;
@@ -1348,6 +2036,18 @@ where
 	fn iter<'b>(&'b self) -> HashtableIterator<'b, K, V> {
 		HashtableIterator::new(self, self.tail)
 	}
+	fn keys<'b>(&'b self) -> HashsetIterator<'b, K> {
+		HashsetIterator::new(self, self.tail)
+	}
+	fn values<'b>(&'b self) -> HashtableValueIterator<'b, K, V> {
+		HashtableValueIterator::new(self, self.tail)
+	}
+	fn iter_ordered_by_key(&self) -> std::vec::IntoIter<(K, V)>
+	where
+		K: Ord,
+	{
+		self.iter_ordered_by_key_impl()
+	}
 	fn max_load_factor(&self) -> f64 {
 		self.max_load_factor
 	}
@@ -1355,18 +2055,14 @@ where
 		self.max_entries
 	}
 	fn bring_to_front(&mut self, key: &K) -> Result<(), Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.hash_key(key);
 		self.bring_to_front_impl::<V>(key, hash)
 	}
 	fn remove_oldest(&mut self) -> Result<(), Error> {
 		self.remove_oldest_impl()
 	}
 	fn raw_read(&self, key: &K, chunk: usize, data: &mut [u8; BUFFER_SIZE]) -> Result<bool, Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.hash_key(key);
 		self.raw_read_impl(key, hash, chunk, data)
 	}
 	fn raw_write(
@@ -1376,9 +2072,7 @@ where
 		data: &[u8; BUFFER_SIZE],
 		len: usize,
 	) -> Result<(), Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
+		let hash = self.hash_key(key);
 		self.raw_write_impl::<V>(key, hash, chunk, data, len)
 	}
 	fn slabs(
@@ -1386,6 +2080,12 @@ where
 	) -> Result<Option<Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>>, Error> {
 		self.slabs_impl()
 	}
+	fn compact(&mut self, max_moves: usize) -> Result<usize, Error> {
+		self.compact_impl(max_moves)
+	}
+	fn snapshot_iter(&self) -> Result<HashtableSnapshotIterator<K, V>, Error> {
+		self.snapshot_iter_impl()
+	}
 }
 
 impl<K> Hashset<K> for HashImpl<K>
@@ -1393,31 +2093,16 @@ where
 	K: Serializable + Hash + PartialEq + Debug + Clone,
 {
 	fn insert(&mut self, key: &K) -> Result<(), Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
-		self.insert_hash_impl::<K>(Some(key), None, None, hash)
+		let hash = self.hash_key(key);
+		self.insert_set_impl(key, hash)
 	}
 	fn contains(&self, key: &K) -> Result<bool, Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
-		match self.get_impl(key, hash)? {
-			Some(_) => Ok(true),
-			None => Ok(false),
-		}
+		let hash = self.hash_key(key);
+		self.contains_set_impl(key, hash)
 	}
 	fn remove(&mut self, key: &K) -> Result<bool, Error> {
-		let mut hasher = DefaultHasher::new();
-		key.hash(&mut hasher);
-		let hash = hasher.finish() as usize;
-		match self.get_impl(key, hash)? {
-			Some((entry, _reader)) => {
-				self.remove_impl(entry)?;
-				Ok(true)
-			}
-			None => Ok(false),
-		}
+		let hash = self.hash_key(key);
+		self.remove_set_impl(key, hash)
 	}
 	fn size(&self) -> usize {
 		self.size
@@ -1429,6 +2114,12 @@ where
 	fn iter<'b>(&'b self) -> HashsetIterator<'b, K> {
 		HashsetIterator::new(self, self.tail)
 	}
+	fn keys<'b>(&'b self) -> HashsetIterator<'b, K> {
+		Hashset::iter(self)
+	}
+	fn rebuild_bloom(&mut self) -> Result<(), Error> {
+		self.rebuild_bloom_impl()
+	}
 	fn max_load_factor(&self) -> f64 {
 		self.max_load_factor
 	}
@@ -0,0 +1,226 @@
+// Copyright (c) 2023-2024, The BitcoinMW Developers
+// Some code and concepts from:
+// * Grin: https://github.com/mimblewimble/grin
+// * Arti: https://gitlab.torproject.org/tpo/core/arti
+// * BitcoinMW: https://github.com/bitcoinmw/bitcoinmw
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::constants::HEAP_NONE;
+use crate::types::{Array, HeapHandle, MinHeap};
+use bmw_err::*;
+
+impl<V: Ord + Clone> MinHeap<V> {
+	pub(crate) fn new(capacity: usize) -> Result<Self, Error> {
+		// slots are handed out from the end of the free list, so slot 0 is used first
+		let mut free = Array::new(capacity, &0)?;
+		for i in 0..capacity {
+			free[i] = capacity - (i + 1);
+		}
+		Ok(Self {
+			values: Array::new(capacity, &None)?,
+			slots: Array::new(capacity, &0)?,
+			positions: Array::new(capacity, &HEAP_NONE)?,
+			generations: Array::new(capacity, &0)?,
+			free,
+			free_len: capacity,
+			len: 0,
+		})
+	}
+}
+
+impl<V: Ord> MinHeap<V> {
+	/// Push `value` onto the heap.
+	/// # Errors
+	/// [`bmw_err::ErrKind::CapacityExceeded`] - if the heap already holds
+	/// [`crate::MinHeap::capacity`] values.
+	pub fn push(&mut self, value: V) -> Result<(), Error> {
+		self.push_with_handle(value)?;
+		Ok(())
+	}
+
+	/// Push `value` onto the heap and return a [`crate::HeapHandle`] that can be used to
+	/// [`crate::MinHeap::remove`] it or [`crate::MinHeap::update_key`] it later.
+	/// # Errors
+	/// [`bmw_err::ErrKind::CapacityExceeded`] - if the heap already holds
+	/// [`crate::MinHeap::capacity`] values.
+	pub fn push_with_handle(&mut self, value: V) -> Result<HeapHandle, Error> {
+		if self.len == self.capacity() {
+			let fmt = format!("the heap is full (capacity = {})", self.capacity());
+			return Err(err!(ErrKind::CapacityExceeded, fmt));
+		}
+		self.free_len -= 1;
+		let slot = self.free[self.free_len];
+		let index = self.len;
+		self.len += 1;
+		self.values[index] = Some(value);
+		self.slots[index] = slot;
+		self.positions[slot] = index;
+		self.sift_up(index);
+		Ok(HeapHandle {
+			slot,
+			generation: self.generations[slot],
+		})
+	}
+
+	/// Returns the smallest value without removing it or None if the heap is empty.
+	pub fn peek(&self) -> Option<&V> {
+		match self.len {
+			0 => None,
+			_ => self.values[0].as_ref(),
+		}
+	}
+
+	/// Remove and return the smallest value or None if the heap is empty.
+	pub fn pop(&mut self) -> Option<V> {
+		match self.len {
+			0 => None,
+			_ => self.remove_at(0),
+		}
+	}
+
+	/// Remove the value that was pushed with `handle` and return it. None is returned if that
+	/// value has already been popped or removed.
+	pub fn remove(&mut self, handle: HeapHandle) -> Option<V> {
+		let index = self.index_of(handle)?;
+		self.remove_at(index)
+	}
+
+	/// Replace the value that was pushed with `handle` by `value` and move it to its new
+	/// position in the heap. The handle stays valid.
+	/// # Errors
+	/// [`bmw_err::ErrKind::IllegalArgument`] - if the value has already been popped or removed.
+	pub fn update_key(&mut self, handle: HeapHandle, value: V) -> Result<(), Error> {
+		let index = match self.index_of(handle) {
+			Some(index) => index,
+			None => {
+				let text = "the handle's value is no longer in the heap";
+				return Err(err!(ErrKind::IllegalArgument, text));
+			}
+		};
+		self.values[index] = Some(value);
+		let index = self.sift_up(index);
+		self.sift_down(index);
+		Ok(())
+	}
+
+	/// Returns the value that was pushed with `handle` or None if it is no longer in the heap.
+	pub fn get(&self, handle: HeapHandle) -> Option<&V> {
+		let index = self.index_of(handle)?;
+		self.values[index].as_ref()
+	}
+
+	/// Returns true if the value that was pushed with `handle` is still in the heap.
+	pub fn contains(&self, handle: HeapHandle) -> bool {
+		self.index_of(handle).is_some()
+	}
+
+	/// Remove all values. Handles to the removed values become invalid.
+	pub fn clear(&mut self) {
+		for index in 0..self.len {
+			self.values[index] = None;
+			let slot = self.slots[index];
+			self.release(slot);
+		}
+		self.len = 0;
+	}
+
+	/// Returns the number of values in the heap.
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Returns true if the heap has no values.
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// Returns the maximum number of values the heap can hold.
+	pub fn capacity(&self) -> usize {
+		self.values.data.len()
+	}
+
+	fn index_of(&self, handle: HeapHandle) -> Option<usize> {
+		if handle.slot >= self.capacity() || self.generations[handle.slot] != handle.generation {
+			return None;
+		}
+		match self.positions[handle.slot] {
+			HEAP_NONE => None,
+			index => Some(index),
+		}
+	}
+
+	fn remove_at(&mut self, index: usize) -> Option<V> {
+		let last = self.len - 1;
+		self.swap(index, last);
+		let value = self.values[last].take();
+		let slot = self.slots[last];
+		self.release(slot);
+		self.len = last;
+		if index < last {
+			let index = self.sift_up(index);
+			self.sift_down(index);
+		}
+		value
+	}
+
+	// return a slot to the free list. Bumping the generation invalidates the old handles.
+	fn release(&mut self, slot: usize) {
+		self.positions[slot] = HEAP_NONE;
+		self.generations[slot] = self.generations[slot].wrapping_add(1);
+		self.free[self.free_len] = slot;
+		self.free_len += 1;
+	}
+
+	fn swap(&mut self, a: usize, b: usize) {
+		self.values.data.swap(a, b);
+		self.slots.data.swap(a, b);
+		self.positions[self.slots[a]] = a;
+		self.positions[self.slots[b]] = b;
+	}
+
+	fn less(&self, a: usize, b: usize) -> bool {
+		self.values[a] < self.values[b]
+	}
+
+	fn sift_up(&mut self, mut index: usize) -> usize {
+		while index > 0 {
+			let parent = (index - 1) / 2;
+			if !self.less(index, parent) {
+				break;
+			}
+			self.swap(index, parent);
+			index = parent;
+		}
+		index
+	}
+
+	fn sift_down(&mut self, mut index: usize) -> usize {
+		loop {
+			let left = 2 * index + 1;
+			if left >= self.len {
+				break;
+			}
+			let right = left + 1;
+			let child = if right < self.len && self.less(right, left) {
+				right
+			} else {
+				left
+			};
+			if !self.less(child, index) {
+				break;
+			}
+			self.swap(child, index);
+			index = child;
+		}
+		index
+	}
+}
@@ -0,0 +1,255 @@
+// Copyright (c) 2023-2024, The BitcoinMW Developers
+// Some code and concepts from:
+// * Grin: https://github.com/mimblewimble/grin
+// * Arti: https://gitlab.torproject.org/tpo/core/arti
+// * BitcoinMW: https://github.com/bitcoinmw/bitcoinmw
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::constants::*;
+use crate::types::{InternEntry, StringInterner};
+use crate::{SlabAllocatorConfig, Symbol, UtilBuilder};
+use bmw_conf::ConfigOption::*;
+use bmw_conf::ConfigOptionName as CN;
+use bmw_conf::{ConfigBuilder, ConfigOption};
+use bmw_err::{err, Error};
+use bmw_log::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+info!();
+
+impl Symbol {
+	/// Returns the numeric id of this symbol. Ids are assigned in the order that strings are
+	/// first interned, starting at 0.
+	pub fn id(&self) -> u32 {
+		self.0
+	}
+}
+
+impl StringInterner {
+	pub(crate) fn new(configs: Vec<ConfigOption>) -> Result<Self, Error> {
+		let config = ConfigBuilder::build_config(configs);
+		config.check_config(vec![CN::MaxEntries, CN::SlabSize, CN::SlabCount], vec![])?;
+		let max_symbols = config.get_or_usize(&CN::MaxEntries, HASH_DEFAULT_MAX_ENTRIES);
+		let slab_size = config.get_or_usize(&CN::SlabSize, INTERN_DEFAULT_SLAB_SIZE);
+		let slab_count = config.get_or_usize(&CN::SlabCount, INTERN_DEFAULT_SLAB_COUNT);
+
+		// INTERN_NONE terminates the chains so it can't be a symbol
+		if max_symbols == 0 || max_symbols >= INTERN_NONE as usize {
+			let text = format!("MaxEntries must be between 1 and {}", INTERN_NONE - 1);
+			return Err(err!(ErrKind::Configuration, text));
+		}
+		if slab_size == 0 || slab_count == 0 {
+			let text = "SlabSize and SlabCount must be greater than 0";
+			return Err(err!(ErrKind::Configuration, text));
+		}
+
+		let mut slabs = UtilBuilder::build_sync_slabs();
+		slabs.init(SlabAllocatorConfig {
+			slab_size,
+			slab_count,
+			..Default::default()
+		})?;
+		let index = UtilBuilder::build_hashtable_sync_box(vec![
+			MaxEntries(max_symbols),
+			GlobalSlabAllocator(false),
+			SlabSize(INTERN_INDEX_SLAB_SIZE),
+			SlabCount(max_symbols),
+		])?;
+		let entries = UtilBuilder::build_array(max_symbols, &InternEntry::default())?;
+
+		Ok(Self {
+			slabs,
+			index,
+			entries,
+			len: 0,
+			bytes_used: 0,
+			slabs_allocated: 0,
+			max_symbols,
+			slab_size,
+			slab_count,
+		})
+	}
+
+	/// Intern `s` and return its [`crate::Symbol`]. If `s` has already been interned, the
+	/// existing symbol is returned, so equal strings always have the same symbol.
+	/// # Errors
+	/// * [`bmw_err::ErrKind::CapacityExceeded`] - If `s` is new and the maximum number of
+	///   symbols has been reached or there is not enough byte capacity left to store it. The
+	///   interner is unchanged in this case.
+	pub fn intern(&mut self, s: &str) -> Result<Symbol, Error> {
+		let hash = Self::hash(s);
+		let head = self.index.get(&hash)?.unwrap_or(INTERN_NONE);
+		let mut next = head;
+		while next != INTERN_NONE {
+			if self.matches(next, s)? {
+				return Ok(Symbol(next));
+			}
+			next = self.entries[next as usize].next;
+		}
+
+		if self.len == self.max_symbols {
+			let text = format!("maximum number of symbols ({}) reached", self.max_symbols);
+			return Err(err!(ErrKind::CapacityExceeded, text));
+		}
+		let capacity = self.slab_size * self.slab_count;
+		if self.bytes_used + s.len() > capacity {
+			let text = format!(
+				"not enough capacity to intern {} bytes ({} of {} bytes used)",
+				s.len(),
+				self.bytes_used,
+				capacity
+			);
+			return Err(err!(ErrKind::CapacityExceeded, text));
+		}
+
+		self.write(s.as_bytes())?;
+		let id = self.len as u32;
+		self.entries[self.len] = InternEntry {
+			offset: self.bytes_used,
+			len: s.len(),
+			next: head,
+		};
+		self.index.insert(&hash, &id)?;
+		self.len += 1;
+		self.bytes_used += s.len();
+		Ok(Symbol(id))
+	}
+
+	/// Returns the [`crate::Symbol`] for `s` if it has been interned, without interning it.
+	pub fn get(&self, s: &str) -> Result<Option<Symbol>, Error> {
+		let mut next = self.index.get(&Self::hash(s))?.unwrap_or(INTERN_NONE);
+		while next != INTERN_NONE {
+			if self.matches(next, s)? {
+				return Ok(Some(Symbol(next)));
+			}
+			next = self.entries[next as usize].next;
+		}
+		Ok(None)
+	}
+
+	/// Returns the string that `symbol` was issued for.
+	/// # Errors
+	/// * [`bmw_err::ErrKind::IllegalArgument`] - If `symbol` was not issued by this interner.
+	pub fn resolve(&self, symbol: Symbol) -> Result<String, Error> {
+		let id = symbol.0 as usize;
+		if id >= self.len {
+			let text = format!("symbol {} was not issued by this interner", symbol.0);
+			return Err(err!(ErrKind::IllegalArgument, text));
+		}
+		let mut ret = Vec::with_capacity(self.entries[id].len);
+		self.for_each_chunk(id, |chunk| {
+			ret.extend_from_slice(chunk);
+			true
+		})?;
+		match String::from_utf8(ret) {
+			Ok(s) => Ok(s),
+			Err(e) => Err(err!(ErrKind::CorruptedData, e.to_string())),
+		}
+	}
+
+	/// Returns the number of strings that have been interned.
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Returns true if no strings have been interned.
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// Returns the number of bytes used to store the interned strings.
+	pub fn bytes_used(&self) -> usize {
+		self.bytes_used
+	}
+
+	/// Returns the total number of bytes that can be stored (SlabSize * SlabCount).
+	pub fn capacity(&self) -> usize {
+		self.slab_size * self.slab_count
+	}
+
+	/// Returns an iterator over the interned strings and their symbols in the order that they
+	/// were interned.
+	pub fn iter(&self) -> impl Iterator<Item = (Symbol, String)> + '_ {
+		(0..self.len).filter_map(move |id| {
+			let symbol = Symbol(id as u32);
+			self.resolve(symbol).ok().map(|s| (symbol, s))
+		})
+	}
+
+	fn hash(s: &str) -> u64 {
+		let mut hasher = DefaultHasher::new();
+		s.hash(&mut hasher);
+		hasher.finish()
+	}
+
+	// append data after the bytes already used. Slabs are allocated in order from an
+	// allocator that never frees, so the nth slab of the interner has id n.
+	fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+		let mut offset = self.bytes_used;
+		let mut data = data;
+		while !data.is_empty() {
+			let slab_id = offset / self.slab_size;
+			if slab_id == self.slabs_allocated {
+				let id = self.slabs.allocate()?.id();
+				if id != slab_id {
+					let text = format!("expected slab {}, allocated {}", slab_id, id);
+					return Err(err!(ErrKind::IllegalState, text));
+				}
+				self.slabs_allocated += 1;
+			}
+			let slab_offset = offset % self.slab_size;
+			let len = data.len().min(self.slab_size - slab_offset);
+			let mut slab = self.slabs.get_mut(slab_id)?;
+			slab.get_mut()[slab_offset..slab_offset + len].clone_from_slice(&data[..len]);
+			data = &data[len..];
+			offset += len;
+		}
+		Ok(())
+	}
+
+	// call f with each piece of the string of symbol id until f returns false
+	fn for_each_chunk<F>(&self, id: usize, mut f: F) -> Result<(), Error>
+	where
+		F: FnMut(&[u8]) -> bool,
+	{
+		let entry = &self.entries[id];
+		let mut offset = entry.offset;
+		let end = entry.offset + entry.len;
+		while offset < end {
+			let slab_offset = offset % self.slab_size;
+			let len = (end - offset).min(self.slab_size - slab_offset);
+			let slab = self.slabs.get(offset / self.slab_size)?;
+			if !f(&slab.get()[slab_offset..slab_offset + len]) {
+				break;
+			}
+			offset += len;
+		}
+		Ok(())
+	}
+
+	fn matches(&self, id: u32, s: &str) -> Result<bool, Error> {
+		let id = id as usize;
+		if self.entries[id].len != s.len() {
+			return Ok(false);
+		}
+		let mut rem = s.as_bytes();
+		let mut ret = true;
+		self.for_each_chunk(id, |chunk| {
+			ret = rem.starts_with(chunk);
+			rem = &rem[chunk.len().min(rem.len())..];
+			ret
+		})?;
+		Ok(ret)
+	}
+}
@@ -51,7 +51,7 @@
 //!
 //! The data structures included in this library are: [`crate::Hashtable`], [`crate::Hashset`],
 //! [`crate::List`], [`crate::Array`], [`crate::ArrayList`], [`crate::Stack`], [`crate::Queue`],
-//! and [`crate::SearchTrie`].  The advantage of these implementations is that they do not allocate memory
+//! [`crate::MinHeap`] and [`crate::SearchTrie`].  The advantage of these implementations is that they do not allocate memory
 //! on the heap after initialization of the data structure.
 //!
 //! So, we can create a [`crate::hashtable`],
@@ -83,35 +83,45 @@
 //!
 
 mod array;
+mod bitfield;
 mod builder;
 mod constants;
 mod hash;
+mod heap;
+mod intern;
 mod lock;
 mod macros;
 mod misc;
+mod panic;
 mod rand;
 mod search_trie;
 mod ser;
 mod slabs;
 mod test;
 mod test_configurable_derive;
+mod test_ser_hardening;
 mod test_serializable_derive;
 mod threadpool;
 mod types;
 
-pub use crate::lock::lock_box_from_usize;
+pub use crate::constants::DETERMINISTIC_HASH_SEED;
+pub use crate::lock::{lock_box_from_usize, lock_order_report, set_lock_order_mode};
 pub use crate::misc::*;
 pub use crate::rand::*;
+pub use crate::threadpool::{current_task_context, with_task_context};
 
 #[doc(hidden)]
 pub use crate::slabs::GLOBAL_SLAB_ALLOCATOR;
 
 pub use crate::types::{
-	Array, ArrayList, Hashset, HashsetIterator, Hashtable, HashtableIterator, List, ListIterator,
-	Lock, LockBox, Match, Pattern, PoolResult, Queue, RwLockReadGuardWrapper,
-	RwLockWriteGuardWrapper, SearchTrie, Slab, SlabAllocator, SlabAllocatorConfig, SlabMut,
-	SlabReader, SlabWriter, SortableList, Stack, ThreadPool, ThreadPoolExecutor, ThreadPoolHandle,
-	ThreadPoolStopper, UtilBuilder,
+	Array, Array2D, ArrayList, BitField, Hashset, HashsetIterator, Hashtable, HashtableIterator,
+	HashtableSnapshotIterator, HashtableValueIterator, HeapHandle, List, ListIterator, Lock,
+	LockBox, LockOrderMode, LockOrderReport, Match, MinHeap, PanicInfoCapture, Pattern, PoolResult,
+	Queue, RwLockReadGuardWrapper, RwLockWriteGuardWrapper, ScheduleHandle, ScheduleStats,
+	SearchTrie, ShardedHashtable, ShardedHashtableSnapshotIterator, Slab, SlabAllocator,
+	SlabAllocatorConfig, SlabMut, SlabRange, SlabReader, SlabStats, SlabWriter, SortableList,
+	SplitIter, Stack, StreamingMatcher, StringInterner, Symbol, TaskContext, ThreadPool,
+	ThreadPoolExecutor, ThreadPoolHandle, ThreadPoolShutdownMode, ThreadPoolStopper, UtilBuilder,
 };
 
 #[doc(hidden)]
@@ -16,14 +16,26 @@
 // limitations under the License.
 
 use crate::types::LockImpl;
-use crate::{Lock, LockBox, RwLockReadGuardWrapper, RwLockWriteGuardWrapper};
+use crate::{
+	Lock, LockBox, LockOrderMode, LockOrderReport, RwLockReadGuardWrapper, RwLockWriteGuardWrapper,
+};
 use bmw_deps::rand::random;
 use bmw_err::{err, map_err, Error};
+use bmw_log::*;
 use std::cell::RefCell;
 use std::collections::HashSet;
 use std::fmt::{Debug, Formatter};
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+#[cfg(debug_assertions)]
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicU8, Ordering};
+#[cfg(debug_assertions)]
+use std::sync::Mutex;
+
+info!();
+
 impl<T> Clone for Box<dyn LockBox<T>>
 where
 	T: Send + Sync + 'static,
@@ -40,6 +52,144 @@ thread_local! {
 	pub static LOCKS: RefCell<HashSet<u128>> = RefCell::new(HashSet::new());
 }
 
+// the tracker mode. 0 means that BMW_LOCK_ORDER has not been read yet.
+#[cfg(debug_assertions)]
+static LOCK_ORDER_MODE: AtomicU8 = AtomicU8::new(0);
+#[cfg(debug_assertions)]
+const LOCK_ORDER_OFF: u8 = 1;
+#[cfg(debug_assertions)]
+const LOCK_ORDER_LOG: u8 = 2;
+#[cfg(debug_assertions)]
+const LOCK_ORDER_STRICT: u8 = 3;
+
+// the observed lock ordering. A std Mutex is used so that the tracker does not track itself.
+#[cfg(debug_assertions)]
+static LOCK_ORDER_GRAPH: Mutex<LockOrderGraph> = Mutex::new(LockOrderGraph {
+	edges: BTreeMap::new(),
+	cycles: vec![],
+});
+
+#[cfg(debug_assertions)]
+struct LockOrderGraph {
+	edges: BTreeMap<u128, BTreeSet<u128>>,
+	cycles: Vec<Vec<u128>>,
+}
+
+#[cfg(debug_assertions)]
+impl LockOrderGraph {
+	// the path from `from` to `to` if there is one
+	fn path(&self, from: u128, to: u128) -> Option<Vec<u128>> {
+		let mut visited = HashSet::new();
+		let mut stack = vec![vec![from]];
+		while let Some(path) = stack.pop() {
+			let last = path[path.len() - 1];
+			if last == to {
+				return Some(path);
+			}
+			if !visited.insert(last) {
+				continue;
+			}
+			if let Some(next) = self.edges.get(&last) {
+				for id in next {
+					let mut path = path.clone();
+					path.push(*id);
+					stack.push(path);
+				}
+			}
+		}
+		None
+	}
+}
+
+/// Set the [`crate::LockOrderMode`] of the lock ordering tracker. This overrides the
+/// `BMW_LOCK_ORDER` environment variable. The tracker does not exist in builds without
+/// `debug_assertions` so this function has no effect in them.
+pub fn set_lock_order_mode(mode: LockOrderMode) {
+	#[cfg(debug_assertions)]
+	{
+		let mode = match mode {
+			LockOrderMode::Off => LOCK_ORDER_OFF,
+			LockOrderMode::Log => LOCK_ORDER_LOG,
+			LockOrderMode::Strict => LOCK_ORDER_STRICT,
+		};
+		LOCK_ORDER_MODE.store(mode, Ordering::SeqCst);
+	}
+	#[cfg(not(debug_assertions))]
+	let _ = mode;
+}
+
+/// Return the lock ordering that the tracker observed since the process started. The report
+/// is empty if the tracker is off or in builds without `debug_assertions`. See
+/// [`crate::LockOrderMode`].
+pub fn lock_order_report() -> LockOrderReport {
+	#[cfg(debug_assertions)]
+	{
+		let graph = LOCK_ORDER_GRAPH.lock().unwrap_or_else(|e| e.into_inner());
+		let mut edges = vec![];
+		for (held, acquired) in &graph.edges {
+			for id in acquired {
+				edges.push((*held, *id));
+			}
+		}
+		LockOrderReport {
+			edges,
+			cycles: graph.cycles.clone(),
+		}
+	}
+	#[cfg(not(debug_assertions))]
+	LockOrderReport::default()
+}
+
+#[cfg(debug_assertions)]
+fn lock_order_mode() -> u8 {
+	let mode = LOCK_ORDER_MODE.load(Ordering::Relaxed);
+	if mode != 0 {
+		return mode;
+	}
+	let mode = match std::env::var("BMW_LOCK_ORDER") {
+		Ok(value) if value == "strict" => LOCK_ORDER_STRICT,
+		Ok(_) => LOCK_ORDER_LOG,
+		Err(_) => LOCK_ORDER_OFF,
+	};
+	// set_lock_order_mode may have been called in the meantime
+	let _ = LOCK_ORDER_MODE.compare_exchange(0, mode, Ordering::SeqCst, Ordering::SeqCst);
+	LOCK_ORDER_MODE.load(Ordering::SeqCst)
+}
+
+// record an edge from each lock held by this thread to the lock `id` that is being acquired
+// and report any cycle that the new edges complete
+#[cfg(debug_assertions)]
+fn track_lock_order(id: u128, held: &HashSet<u128>) {
+	if held.is_empty() {
+		return;
+	}
+	let mode = lock_order_mode();
+	if mode == LOCK_ORDER_OFF {
+		return;
+	}
+	let mut cycles = vec![];
+	{
+		let mut graph = LOCK_ORDER_GRAPH.lock().unwrap_or_else(|e| e.into_inner());
+		for held in held {
+			if *held == id || !graph.edges.entry(*held).or_default().insert(id) {
+				continue;
+			}
+			if let Some(path) = graph.path(id, *held) {
+				let mut cycle = vec![*held];
+				cycle.extend(path);
+				graph.cycles.push(cycle.clone());
+				cycles.push(cycle);
+			}
+		}
+	}
+	for cycle in cycles {
+		let _ = warn!("lock order cycle detected, potential deadlock: {:?}", cycle);
+		if mode == LOCK_ORDER_STRICT {
+			panic!("lock order cycle detected, potential deadlock: {:?}", cycle);
+		}
+	}
+}
+
 /// Rebuild a [`crate::LockBox`] from the [`prim@usize`] which is returned from the
 /// [`crate::LockBox::danger_to_usize`] function.
 pub fn lock_box_from_usize<T>(value: usize) -> Box<dyn LockBox<T> + Send + Sync>
@@ -166,6 +316,8 @@ impl<T> LockImpl<T> {
 	fn do_wlock(&mut self, ignore_poison: bool) -> Result<RwLockWriteGuardWrapper<'_, T>, Error> {
 		let contains = LOCKS.with(|f| -> Result<bool, Error> {
 			let ret = (*f.borrow()).contains(&self.id);
+			#[cfg(debug_assertions)]
+			track_lock_order(self.id, &f.borrow());
 			(*f.borrow_mut()).insert(self.id);
 
 			Ok(ret)
@@ -195,6 +347,8 @@ impl<T> LockImpl<T> {
 	fn do_rlock(&self, ignore_poison: bool) -> Result<RwLockReadGuardWrapper<'_, T>, Error> {
 		let contains = LOCKS.with(|f| -> Result<bool, Error> {
 			let ret = (*f.borrow()).contains(&self.id);
+			#[cfg(debug_assertions)]
+			track_lock_order(self.id, &f.borrow());
 			(*f.borrow_mut()).insert(self.id);
 			Ok(ret)
 		})?;
@@ -148,6 +148,12 @@ macro_rules! rlock {
 ///                                 allocator. If not specified, the default value of
 ///                                 40,960 is used.
 ///
+/// * OverflowToHeap([`bool`]) (optional) - if true, once all slabs are in use up to SlabCount
+///                                 additional slabs are allocated from the heap instead of
+///                                 returning an error. See
+///                                 [`crate::SlabAllocator::shrink_overflow`]. The default
+///                                 value is false.
+///
 /// # Return
 /// Return Ok(()) on success or [`bmw_err::Error`] on failure.
 ///
@@ -155,8 +161,9 @@ macro_rules! rlock {
 ///
 /// * [`bmw_err::ErrKind::Configuration`] - Is returned if a
 ///                                           ConfigOption other than
-///                                           ConfigOption::SlabSize or
-///                                           ConfigOption::SlabCount is
+///                                           ConfigOption::SlabSize,
+///                                           ConfigOption::SlabCount, or
+///                                           ConfigOption::OverflowToHeap is
 ///                                           specified.
 ///
 /// * [`bmw_err::ErrKind::IllegalState`] - Is returned if the global thread local
@@ -227,6 +234,9 @@ macro_rules! global_slab_allocator {
                         slab_count_specified = true;
                         if slab_count_specified {}
                     },
+                    bmw_conf::ConfigOption::OverflowToHeap(overflow_to_heap) => {
+                        config.overflow_to_heap = overflow_to_heap;
+                    },
                     _ => {
                         error = Some(format!("'{:?}' is not allowed for hashset", $config));
                     }
@@ -258,6 +268,12 @@ macro_rules! global_slab_allocator {
 ///                                 allocator. If not specified, the default value of
 ///                                 40,960 is used.
 ///
+/// * OverflowToHeap([`bool`]) (optional) - if true, once all slabs are in use up to SlabCount
+///                                 additional slabs are allocated from the heap instead of
+///                                 returning an error. See
+///                                 [`crate::SlabAllocator::shrink_overflow`]. The default
+///                                 value is false.
+///
 /// # Return
 /// Return `Ok(Rc<RefCell<dyn SlabAllocator>>)` on success or [`bmw_err::Error`] on failure.
 ///
@@ -265,8 +281,9 @@ macro_rules! global_slab_allocator {
 ///
 /// * [`bmw_err::ErrKind::Configuration`] - Is returned if a
 ///                                           ConfigOption other than
-///                                           ConfigOption::SlabSize or
-///                                           ConfigOption::SlabCount is
+///                                           ConfigOption::SlabSize,
+///                                           ConfigOption::SlabCount, or
+///                                           ConfigOption::OverflowToHeap is
 ///                                           specified.
 ///
 /// * [`bmw_err::ErrKind::IllegalArgument`] - Is returned if the SlabSize is 0 or the SlabCount
@@ -346,11 +363,12 @@ macro_rules! slab_allocator {
 		use bmw_util::{SlabAllocatorConfig, UtilBuilder};
 		let mut slab_config = SlabAllocatorConfig::default();
 		let config = config!($($config)*);
-	        match config.check_config(vec![CN::SlabSize, CN::SlabCount], vec![]) {
+	        match config.check_config(vec![CN::SlabSize, CN::SlabCount, CN::OverflowToHeap], vec![]) {
                         Ok(_) => {
 
 		                slab_config.slab_size = config.get_or_usize(&CN::SlabSize, slab_config.slab_size);
 		                slab_config.slab_count = config.get_or_usize(&CN::SlabCount, slab_config.slab_count);
+		                slab_config.overflow_to_heap = config.get_or_bool(&CN::OverflowToHeap, false);
 
 		                let mut slabs = UtilBuilder::build_sync_slabs();
 		                match slabs.init(slab_config) {
@@ -373,8 +391,8 @@ macro_rules! slab_allocator {
 /// The pattern macro takes the following parameters:
 ///
 /// * Regex([`std::string::String`]) (required) - The regular expression to use for matching (note this is not a
-///                                      full regular expression. Only some parts of regular expressions
-///                                      are implemented like wildcards and carets). See [`crate::Pattern`]
+///                                      full regular expression. Only a restricted syntax is implemented
+///                                      like wildcards, classes and anchors). See [`crate::Pattern`]
 ///                                      for full details.
 /// * PatternId([`prim@usize`]) (required) - The id for this pattern. This id is returned in the
 ///                                      [`crate::Match`] array if this match occurs when the
@@ -740,6 +758,18 @@ macro_rules! search_trie_box {
 /// MaxEntries can always be inserted, it's the capacity of the array that becomes larger as this ratio
 /// goes down. So if 100 MaxEntries are specified and the MaxLoadFactor is 0.5, a 200 slot array
 /// will be used and 100 entries will be allowed. The default MaxLoadFactor is 0.7.
+/// * DeterministicHashing ([`bool`]) (optional) - If true, keys are hashed with the fixed seed
+/// [`crate::DETERMINISTIC_HASH_SEED`] instead of a seed that is chosen at random when the
+/// hashtable is built. Two hashtables built with the same configuration that see the same
+/// sequence of inserts and removes then place every entry in the same slot of the entry array,
+/// which makes collisions and performance reproducible. The iteration order (newest first) only
+/// depends on that sequence either way. To iterate in an order that does not depend on it, use
+/// [`crate::Hashtable::iter_ordered_by_key`]. Keys are hashed with SipHash-1-3 keyed with the
+/// seed, so the slot placement does not change between Rust releases. It does depend on the
+/// target, since the [`std::hash::Hash`] implementations of integers use its byte order and
+/// hashes are truncated to [`prim@usize`]. Note that with a fixed seed, anyone who controls the
+/// keys can choose keys that collide, which turns each lookup into a linear scan of the entry
+/// array. Only use it if the keys are trusted. The default value is false.
 /// * GlobalSlabAllocator ([`bool`]) (optional) - If true, the [`crate::global_slab_allocator`] is
 /// used instead of using an internally built slab allocator. The global slab allocator is
 /// thread_local and the returned value cannot be passed to other threads. The default value is
@@ -748,6 +778,11 @@ macro_rules! search_trie_box {
 /// with this [`crate::Hashtable`]. This option is only allowed if GlobalSlabAllocator is false.
 /// * SlabCount ([`prim@usize`]) (optional) - The count of slabs. This option is only allowed if
 /// GlobalSlabAllocator is false.
+/// * ContiguousThresholdBytes ([`prim@usize`]) (optional) - If specified, entries whose
+/// serialized value is larger than this many bytes are stored in adjacent slabs allocated with
+/// [`crate::SlabAllocator::allocate_contiguous`], so reading them does not jump around the slab
+/// allocator. If no run of adjacent free slabs is long enough, the entry is stored in a chain of
+/// slabs as usual. By default, all entries are stored in chains of slabs.
 ///
 /// # Returns
 ///
@@ -828,6 +863,8 @@ macro_rules! hashtable {
 /// MaxEntries can always be inserted, it's the capacity of the array that becomes larger as this ratio
 /// goes down. So if 100 MaxEntries are specified and the MaxLoadFactor is 0.5, a 200 slot array
 /// will be used and 100 entries will be allowed. The default MaxLoadFactor is 0.7.
+/// * DeterministicHashing ([`bool`]) (optional) - If true, keys are hashed with a fixed seed
+/// instead of a random one. See [`crate::hashtable`]. The default value is false.
 /// * GlobalSlabAllocator ([`bool`]) (optional) - If true, the [`crate::global_slab_allocator`] is
 /// used instead of using an internally built slab allocator. The global slab allocator is
 /// thread_local and the returned value cannot be passed to other threads. The default value is
@@ -836,6 +873,9 @@ macro_rules! hashtable {
 /// with this [`crate::Hashtable`]. This option is only allowed if GlobalSlabAllocator is false.
 /// * SlabCount ([`prim@usize`]) (optional) - The count of slabs. This option is only allowed if
 /// GlobalSlabAllocator is false.
+/// * ContiguousThresholdBytes ([`prim@usize`]) (optional) - Values larger than this many bytes
+/// are stored in adjacent slabs. See [`crate::hashtable`]. By default, all entries are stored
+/// in chains of slabs.
 ///
 /// # Returns           
 ///
@@ -917,6 +957,8 @@ macro_rules! hashtable_box {
 /// MaxEntries can always be inserted, it's the capacity of the array that becomes larger as this ratio
 /// goes down. So if 100 MaxEntries are specified and the MaxLoadFactor is 0.5, a 200 slot array
 /// will be used and 100 entries will be allowed. The default MaxLoadFactor is 0.7.
+/// * DeterministicHashing ([`bool`]) (optional) - If true, keys are hashed with a fixed seed
+/// instead of a random one. See [`crate::hashtable`]. The default value is false.
 /// * GlobalSlabAllocator ([`bool`]) (optional) - If true, the [`crate::global_slab_allocator`] is
 /// used instead of using an internally built slab allocator. The global slab allocator is
 /// thread_local and the returned value cannot be passed to other threads. The default value is
@@ -925,6 +967,9 @@ macro_rules! hashtable_box {
 /// with this [`crate::Hashtable`]. This option is only allowed if GlobalSlabAllocator is false.
 /// * SlabCount ([`prim@usize`]) (optional) - The count of slabs. This option is only allowed if
 /// GlobalSlabAllocator is false.
+/// * ContiguousThresholdBytes ([`prim@usize`]) (optional) - Values larger than this many bytes
+/// are stored in adjacent slabs. See [`crate::hashtable`]. By default, all entries are stored
+/// in chains of slabs.
 ///
 /// # Returns
 ///
@@ -1006,6 +1051,8 @@ macro_rules! hashtable_sync {
 /// MaxEntries can always be inserted, it's the capacity of the array that becomes larger as this ratio
 /// goes down. So if 100 MaxEntries are specified and the MaxLoadFactor is 0.5, a 200 slot array
 /// will be used and 100 entries will be allowed. The default MaxLoadFactor is 0.7.
+/// * DeterministicHashing ([`bool`]) (optional) - If true, keys are hashed with a fixed seed
+/// instead of a random one. See [`crate::hashtable`]. The default value is false.
 /// * GlobalSlabAllocator ([`bool`]) (optional) - If true, the [`crate::global_slab_allocator`] is
 /// used instead of using an internally built slab allocator. The global slab allocator is
 /// thread_local and the returned value cannot be passed to other threads. The default value is
@@ -1014,6 +1061,9 @@ macro_rules! hashtable_sync {
 /// with this [`crate::Hashtable`]. This option is only allowed if GlobalSlabAllocator is false.
 /// * SlabCount ([`prim@usize`]) (optional) - The count of slabs. This option is only allowed if
 /// GlobalSlabAllocator is false.
+/// * ContiguousThresholdBytes ([`prim@usize`]) (optional) - Values larger than this many bytes
+/// are stored in adjacent slabs. See [`crate::hashtable`]. By default, all entries are stored
+/// in chains of slabs.
 ///
 /// # Returns
 ///
@@ -1081,6 +1131,116 @@ macro_rules! hashtable_sync_box {
         }};
 }
 
+/// The [`crate::sharded_hashtable`] macro builds a [`crate::ShardedHashtable`] with the specified
+/// configuration. A [`crate::ShardedHashtable`] may be shared by several threads (i.e. the threads
+/// of an event handler) without serializing all access through a single lock.
+///
+/// # Input Parameters
+/// * Shards ([`prim@usize`]) (optional) - The number of shards. The default is the number of
+/// threads available to the process.
+/// * All of the parameters of [`crate::hashtable_sync_box`]. These are applied to each shard, so
+/// MaxEntries and SlabCount are per shard.
+///
+/// # Returns
+///
+/// A Ok(`ShardedHashtable<K, V>`) on success or a [`bmw_err::Error`] on failure.
+///
+/// # Errors
+///
+/// * [`bmw_err::ErrKind::Configuration`] - If Shards is 0 or specified more than once.
+/// * All of the errors of [`crate::hashtable_sync_box`].
+///
+/// # Examples
+///```
+/// use bmw_util::*;
+/// use bmw_err::*;
+/// use std::thread::spawn;
+///
+/// fn main() -> Result<(), Error> {
+///         let mut hashtable = sharded_hashtable!(
+///                 Shards(4),
+///                 GlobalSlabAllocator(false),
+///                 SlabSize(100),
+///                 SlabCount(100)
+///         )?;
+///
+///         // clones share the same shards
+///         let mut hashtable_clone = hashtable.clone();
+///         spawn(move || -> Result<(), Error> {
+///                 hashtable_clone.insert(&1u64, &10u8)?;
+///                 Ok(())
+///         }).join().unwrap()?;
+///
+///         hashtable.insert(&2u64, &20u8)?;
+///
+///         assert_eq!(hashtable.get(&1u64)?, Some(10u8));
+///         assert_eq!(hashtable.size()?, 2);
+///
+///         Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! sharded_hashtable {
+        ($($config:tt)*) => {{
+                #[allow(unused_imports)]
+                use bmw_conf::ConfigOption::*;
+                use bmw_conf::ConfigOption;
+                let v: Vec<ConfigOption> = vec![$($config)*];
+                bmw_util::UtilBuilder::build_sharded_hashtable(v)
+        }};
+}
+
+/// The [`crate::string_interner`] macro builds a [`crate::StringInterner`] with the specified
+/// configuration.
+///
+/// # Input Parameters
+/// * MaxEntries ([`prim@usize`]) (optional) - The maximum number of symbols. The default value
+/// is 1_000.
+/// * SlabSize ([`prim@usize`]) (optional) - The size of the slabs that the strings are stored
+/// in. Strings may span slabs. The default value is 256.
+/// * SlabCount ([`prim@usize`]) (optional) - The number of slabs. The byte capacity of the
+/// interner is SlabSize * SlabCount. The default value is 256.
+///
+/// # Returns
+///
+/// A Ok(`StringInterner`) on success or a [`bmw_err::Error`] on failure.
+///
+/// # Errors
+///
+/// * [`bmw_err::ErrKind::Configuration`] - If a value is 0 or an option is specified more than
+/// once or is not one of the above.
+///
+/// # Examples
+///```
+/// use bmw_util::*;
+/// use bmw_err::*;
+///
+/// fn main() -> Result<(), Error> {
+///         let mut interner = string_interner!(MaxEntries(10), SlabSize(16), SlabCount(4))?;
+///
+///         let a = interner.intern("127.0.0.1:8080")?;
+///         let b = interner.intern("content-type")?;
+///         assert_eq!(interner.intern("127.0.0.1:8080")?, a);
+///         assert_ne!(a, b);
+///
+///         assert_eq!(interner.resolve(b)?, "content-type");
+///         assert_eq!(interner.len(), 2);
+///         assert_eq!(interner.bytes_used(), 26);
+///
+///         Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! string_interner {
+        ($($config:tt)*) => {{
+                #[allow(unused_imports)]
+                use bmw_conf::ConfigOption::*;
+                use bmw_conf::ConfigOption;
+                let v: Vec<ConfigOption> = vec![$($config)*];
+                bmw_util::UtilBuilder::build_string_interner(v)
+        }};
+}
+
 /// The [`crate::hashset`] macro builds a [`crate::Hashset`] with the specified configuration.
 ///
 /// # Input Parameters
@@ -1093,6 +1253,8 @@ macro_rules! hashtable_sync_box {
 /// MaxEntries can always be inserted, it's the capacity of the array that becomes larger as this ratio
 /// goes down. So if 100 MaxEntries are specified and the MaxLoadFactor is 0.5, a 200 slot array
 /// will be used and 100 entries will be allowed. The default MaxLoadFactor is 0.7.
+/// * DeterministicHashing ([`bool`]) (optional) - If true, keys are hashed with a fixed seed
+/// instead of a random one. See [`crate::hashtable`]. The default value is false.
 /// * GlobalSlabAllocator ([`bool`]) (optional) - If true, the [`crate::global_slab_allocator`] is
 /// used instead of using an internally built slab allocator. The global slab allocator is
 /// thread_local and the returned value cannot be passed to other threads. The default value is
@@ -1101,6 +1263,14 @@ macro_rules! hashtable_sync_box {
 /// with this [`crate::Hashset`]. This option is only allowed if GlobalSlabAllocator is false.
 /// * SlabCount ([`prim@usize`]) (optional) - The count of slabs. This option is only allowed if
 /// GlobalSlabAllocator is false.
+/// * BloomBits ([`prim@usize`]) (optional) - If specified, a counting bloom filter with this
+/// many counters (one byte each) is allocated when the hashset is built and is checked before
+/// the slabs are read. Lookups and removes of keys that are not in the hashset then usually
+/// return without reading any slabs. Removes decrement the counters so the filter stays exact,
+/// except for counters that saturated, which only cause false positives until
+/// [`crate::Hashset::rebuild_bloom`] is called. By default, no bloom filter is used.
+/// * BloomHashes ([`prim@usize`]) (optional) - The number of counters that each key sets in the
+/// bloom filter. Only allowed if BloomBits is specified. The default value is 3.
 ///
 /// # Returns
 ///
@@ -1117,6 +1287,8 @@ macro_rules! hashtable_sync_box {
 /// SlabCount are not specified.
 /// * [`bmw_err::ErrKind::IllegalArgument`] - If the parameters specified for the SlabSize or
 /// SlabCount are not valid. See [`crate::SlabAllocator`].
+/// * [`bmw_err::ErrKind::Configuration`] - If BloomHashes is specified without BloomBits or
+/// either of them is 0.
 ///
 /// # Examples
 ///```
@@ -1177,6 +1349,8 @@ macro_rules! hashset {
 /// MaxEntries can always be inserted, it's the capacity of the array that becomes larger as this ratio
 /// goes down. So if 100 MaxEntries are specified and the MaxLoadFactor is 0.5, a 200 slot array
 /// will be used and 100 entries will be allowed. The default MaxLoadFactor is 0.7.
+/// * DeterministicHashing ([`bool`]) (optional) - If true, keys are hashed with a fixed seed
+/// instead of a random one. See [`crate::hashtable`]. The default value is false.
 /// * GlobalSlabAllocator ([`bool`]) (optional) - If true, the [`crate::global_slab_allocator`] is
 /// used instead of using an internally built slab allocator. The global slab allocator is
 /// thread_local and the returned value cannot be passed to other threads. The default value is
@@ -1185,6 +1359,10 @@ macro_rules! hashset {
 /// with this [`crate::Hashset`]. This option is only allowed if GlobalSlabAllocator is false.
 /// * SlabCount ([`prim@usize`]) (optional) - The count of slabs. This option is only allowed if
 /// GlobalSlabAllocator is false.
+/// * BloomBits ([`prim@usize`]) (optional) - The number of counters in the bloom filter that is
+/// checked before the slabs are read. See [`crate::hashset`]. By default, no bloom filter is used.
+/// * BloomHashes ([`prim@usize`]) (optional) - The number of counters set for each key. Only
+/// allowed if BloomBits is specified. The default value is 3.
 ///
 /// # Returns
 ///
@@ -1201,6 +1379,8 @@ macro_rules! hashset {
 /// SlabCount are not specified.
 /// * [`bmw_err::ErrKind::IllegalArgument`] - If the parameters specified for the SlabSize or
 /// SlabCount are not valid. See [`crate::SlabAllocator`].
+/// * [`bmw_err::ErrKind::Configuration`] - If BloomHashes is specified without BloomBits or
+/// either of them is 0.
 ///
 /// # Examples
 ///```
@@ -1260,6 +1440,8 @@ macro_rules! hashset_box {
 /// MaxEntries can always be inserted, it's the capacity of the array that becomes larger as this ratio
 /// goes down. So if 100 MaxEntries are specified and the MaxLoadFactor is 0.5, a 200 slot array
 /// will be used and 100 entries will be allowed. The default MaxLoadFactor is 0.7.
+/// * DeterministicHashing ([`bool`]) (optional) - If true, keys are hashed with a fixed seed
+/// instead of a random one. See [`crate::hashtable`]. The default value is false.
 /// * GlobalSlabAllocator ([`bool`]) (optional) - If true, the [`crate::global_slab_allocator`] is
 /// used instead of using an internally built slab allocator. The global slab allocator is
 /// thread_local and the returned value cannot be passed to other threads. The default value is
@@ -1268,6 +1450,10 @@ macro_rules! hashset_box {
 /// with this [`crate::Hashset`]. This option is only allowed if GlobalSlabAllocator is false.
 /// * SlabCount ([`prim@usize`]) (optional) - The count of slabs. This option is only allowed if
 /// GlobalSlabAllocator is false.
+/// * BloomBits ([`prim@usize`]) (optional) - The number of counters in the bloom filter that is
+/// checked before the slabs are read. See [`crate::hashset`]. By default, no bloom filter is used.
+/// * BloomHashes ([`prim@usize`]) (optional) - The number of counters set for each key. Only
+/// allowed if BloomBits is specified. The default value is 3.
 ///
 /// # Returns
 ///
@@ -1281,6 +1467,8 @@ macro_rules! hashset_box {
 /// * [`bmw_err::ErrKind::Configuration`] - If GlobalSlabAllocator is true (not allowed for IsSync)
 /// * [`bmw_err::ErrKind::IllegalArgument`] - If the parameters specified for the SlabSize or
 /// SlabCount are not valid. See [`crate::SlabAllocator`].
+/// * [`bmw_err::ErrKind::Configuration`] - If BloomHashes is specified without BloomBits or
+/// either of them is 0.
 ///
 /// # Examples
 ///```
@@ -1346,6 +1534,8 @@ macro_rules! hashset_sync {
 /// MaxEntries can always be inserted, it's the capacity of the array that becomes larger as this ratio
 /// goes down. So if 100 MaxEntries are specified and the MaxLoadFactor is 0.5, a 200 slot array
 /// will be used and 100 entries will be allowed. The default MaxLoadFactor is 0.7.
+/// * DeterministicHashing ([`bool`]) (optional) - If true, keys are hashed with a fixed seed
+/// instead of a random one. See [`crate::hashtable`]. The default value is false.
 /// * GlobalSlabAllocator ([`bool`]) (optional) - If true, the [`crate::global_slab_allocator`] is
 /// used instead of using an internally built slab allocator. The global slab allocator is
 /// thread_local and the returned value cannot be passed to other threads. The default value is
@@ -1354,6 +1544,10 @@ macro_rules! hashset_sync {
 /// with this [`crate::Hashset`]. This option is only allowed if GlobalSlabAllocator is false.
 /// * SlabCount ([`prim@usize`]) (optional) - The count of slabs. This option is only allowed if
 /// GlobalSlabAllocator is false.
+/// * BloomBits ([`prim@usize`]) (optional) - The number of counters in the bloom filter that is
+/// checked before the slabs are read. See [`crate::hashset`]. By default, no bloom filter is used.
+/// * BloomHashes ([`prim@usize`]) (optional) - The number of counters set for each key. Only
+/// allowed if BloomBits is specified. The default value is 3.
 ///
 /// # Returns
 ///
@@ -1367,6 +1561,8 @@ macro_rules! hashset_sync {
 /// * [`bmw_err::ErrKind::Configuration`] - If GlobalSlabAllocator is true (not allowed for IsSync)
 /// * [`bmw_err::ErrKind::IllegalArgument`] - If the parameters specified for the SlabSize or
 /// SlabCount are not valid. See [`crate::SlabAllocator`].
+/// * [`bmw_err::ErrKind::Configuration`] - If BloomHashes is specified without BloomBits or
+/// either of them is 0.
 ///
 /// # Examples
 ///```
@@ -1774,6 +1970,93 @@ macro_rules! array {
 	}};
 }
 
+/// The [`crate::array2d!`] macro builds an [`crate::Array2D`].
+///
+/// # Input Paramters
+/// * rows ([`prim@usize`]) (required) - the number of rows
+/// * cols ([`prim@usize`]) (required) - the number of columns
+/// * default ([`bmw_ser::Serializable`]) (required) - a reference to the value to initialize each
+/// cell with
+///
+/// # Return
+/// Returns [`crate::Array2D`] on success and a [`bmw_err::Error`] on failure.
+///
+/// # Errors
+/// * [`bmw_err::ErrKind::IllegalArgument`] - if rows or cols is 0.
+/// * [`bmw_err::ErrKind::Overflow`] - if rows * cols overflows usize.
+///
+/// # Examples
+///```
+/// use bmw_err::*;
+/// use bmw_util::*;
+///
+/// fn main() -> Result<(), Error> {
+///         // per thread, per bucket counters
+///         let mut counters = array2d!(4, 16, &0u64)?;
+///
+///         *counters.get_mut(2, 5)? += 1;
+///         counters.fill_row(3, &7)?;
+///
+///         assert_eq!(counters.get(2, 5)?, &1);
+///         assert_eq!(counters.row_iter(3)?.sum::<u64>(), 7 * 16);
+///         assert_eq!(counters.col_iter(5)?.sum::<u64>(), 1 + 7);
+///         assert!(counters.get(4, 0).is_err());
+///
+///         Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! array2d {
+	( $rows:expr, $cols:expr, $default:expr ) => {{
+		bmw_util::UtilBuilder::build_array2d($rows, $cols, $default)
+	}};
+}
+
+/// The [`crate::min_heap!`] macro builds a [`crate::MinHeap`].
+///
+/// # Input Paramters
+/// * capacity ([`prim@usize`]) (required) - the maximum number of values in the heap
+///
+/// # Return
+/// Returns [`crate::MinHeap`] on success and a [`bmw_err::Error`] on failure.
+///
+/// # Errors
+/// * [`bmw_err::ErrKind::IllegalArgument`] - if capacity is 0.
+///
+/// # Examples
+///```
+/// use bmw_err::*;
+/// use bmw_util::*;
+///
+/// fn main() -> Result<(), Error> {
+///         // (deadline, timer id)
+///         let mut timers = min_heap!(16)?;
+///
+///         timers.push((300u64, 1u32))?;
+///         let handle = timers.push_with_handle((100, 2))?;
+///         timers.push((200, 3))?;
+///
+///         // reschedule timer 2 after timer 3
+///         timers.update_key(handle, (250, 2))?;
+///         assert_eq!(timers.pop(), Some((200, 3)));
+///
+///         // cancel timer 2
+///         assert_eq!(timers.remove(handle), Some((250, 2)));
+///         assert_eq!(timers.remove(handle), None);
+///
+///         assert_eq!(timers.peek(), Some(&(300, 1)));
+///         assert_eq!(timers.len(), 1);
+///
+///         Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! min_heap {
+	( $capacity:expr ) => {{
+		bmw_util::UtilBuilder::build_min_heap($capacity)
+	}};
+}
+
 /// The [`crate::array_list`] macro builds an [`crate::ArrayList`] in the form of an impl
 /// SortableList.
 ///
@@ -2739,3 +3022,34 @@ macro_rules! block_on {
 		$res.block_on()
 	}};
 }
+
+/// Convert an integer expression to a usize. Unlike [`bmw_err::try_into`], the error that is
+/// returned names the expression and its value.
+///
+/// # Errors
+/// * [`bmw_err::ErrKind::Overflow`] - if the value does not fit in a usize.
+///
+/// # Examples
+///```
+/// use bmw_err::*;
+/// use bmw_util::*;
+///
+/// fn main() -> Result<(), Error> {
+///         let x: u64 = 1234;
+///         let y = usize_from!(x)?;
+///         assert_eq!(y, 1234);
+///
+///         let z: i32 = -1;
+///         assert!(usize_from!(z).is_err());
+///         Ok(())
+/// }
+///```
+#[macro_export]
+macro_rules! usize_from {
+	($value:expr) => {{
+		use bmw_err::map_err;
+		let value = $value;
+		let text = format!("{} ({}) does not fit in a usize", stringify!($value), value);
+		map_err!(usize::try_from(value), bmw_err::ErrKind::Overflow, text)
+	}};
+}
@@ -15,10 +15,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::types::SplitIter;
 use bmw_deps::url_path::UrlPath;
 use bmw_err::*;
 use bmw_log::*;
 use std::cell::RefCell;
+use std::hint::black_box;
 use std::path::PathBuf;
 use std::str::from_utf8;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -81,6 +83,35 @@ pub fn canonicalize_base_path(base_dir: &String, path: &String) -> Result<String
 	}
 }
 
+/// Multiply two usize values. If the result overflows, an error naming both operands is
+/// returned instead of wrapping. This should be used for size computations that are based on
+/// user supplied configuration values.
+/// # Errors
+/// [`bmw_err::ErrKind::Overflow`] - if `a * b` does not fit in a usize.
+pub fn checked_mul_usize(a: usize, b: usize) -> Result<usize, Error> {
+	match a.checked_mul(b) {
+		Some(v) => Ok(v),
+		None => {
+			let fmt = format!("usize overflow: {} * {}", a, b);
+			Err(err!(ErrKind::Overflow, fmt))
+		}
+	}
+}
+
+/// Add two usize values. If the result overflows, an error naming both operands is returned
+/// instead of wrapping.
+/// # Errors
+/// [`bmw_err::ErrKind::Overflow`] - if `a + b` does not fit in a usize.
+pub fn checked_add_usize(a: usize, b: usize) -> Result<usize, Error> {
+	match a.checked_add(b) {
+		Some(v) => Ok(v),
+		None => {
+			let fmt = format!("usize overflow: {} + {}", a, b);
+			Err(err!(ErrKind::Overflow, fmt))
+		}
+	}
+}
+
 /// Utility to convert a u128 to an arbitrary length slice (up to 16 bytes).
 pub fn u128_to_slice(mut n: u128, slice: &mut [u8]) -> Result<(), Error> {
 	let len = slice.len();
@@ -208,6 +239,111 @@ pub fn slice_to_u32(slice: &[u8]) -> Result<u32, Error> {
 	Ok(ret)
 }
 
+/// Returns the index of the first occurrence of `needle` in `haystack` or [`None`] if it does
+/// not occur. An empty needle is found at index 0, even in an empty haystack. The search uses
+/// the Boyer-Moore-Horspool algorithm, which skips ahead by up to the length of the needle
+/// after a mismatch, so it is suitable for large buffers such as data joined from several
+/// slabs.
+pub fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	match needle.len() {
+		0 => Some(0),
+		1 => haystack.iter().position(|b| *b == needle[0]),
+		_ => horspool_find(haystack, needle, &horspool_table(needle)),
+	}
+}
+
+/// Returns an iterator over the subslices of `haystack` that are separated by `delim`. The
+/// subslices borrow from `haystack` and nothing is allocated. Like [`str::split`], occurrences
+/// are matched from left to right without overlapping, adjacent delimiters yield an empty
+/// subslice, a delimiter at the start or end yields an empty first or last subslice and an
+/// empty `haystack` yields one empty subslice. An empty `delim` yields all of `haystack` as one
+/// subslice.
+/// # Examples
+///
+///```
+/// use bmw_util::*;
+///
+/// let lines: Vec<&[u8]> = split_on(b"GET / HTTP/1.1\r\nHost: x\r\n", b"\r\n").collect();
+/// assert_eq!(lines, vec![&b"GET / HTTP/1.1"[..], &b"Host: x"[..], &b""[..]]);
+///```
+pub fn split_on<'a>(haystack: &'a [u8], delim: &'a [u8]) -> SplitIter<'a> {
+	SplitIter {
+		remaining: haystack,
+		delim,
+		skip: horspool_table(delim),
+		done: false,
+	}
+}
+
+/// Returns true if `a` and `b` are equal, taking the same amount of time regardless of where
+/// they differ. This should be used to compare secrets such as MACs and tokens so that the
+/// comparison does not reveal how much of a guess was correct. Slices of different lengths are
+/// not equal and this is returned right away, so only the length is revealed. Two empty
+/// slices are equal.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false;
+	}
+	let mut diff = 0u8;
+	for (x, y) in a.iter().zip(b.iter()) {
+		diff |= x ^ y;
+	}
+	// keep the compiler from turning the loop into an early exit
+	black_box(diff) == 0
+}
+
+impl<'a> Iterator for SplitIter<'a> {
+	type Item = &'a [u8];
+	fn next(&mut self) -> Option<&'a [u8]> {
+		if self.done {
+			return None;
+		}
+		let found = match self.delim.len() {
+			0 => None,
+			1 => self.remaining.iter().position(|b| *b == self.delim[0]),
+			_ => horspool_find(self.remaining, self.delim, &self.skip),
+		};
+		match found {
+			Some(i) => {
+				let ret = &self.remaining[..i];
+				self.remaining = &self.remaining[i + self.delim.len()..];
+				Some(ret)
+			}
+			None => {
+				self.done = true;
+				Some(self.remaining)
+			}
+		}
+	}
+}
+
+// the distance to shift the needle when the byte of the haystack aligned with its last byte is
+// the index into the table
+fn horspool_table(needle: &[u8]) -> [usize; 256] {
+	let mut table = [needle.len(); 256];
+	for (i, b) in needle
+		.iter()
+		.enumerate()
+		.take(needle.len().saturating_sub(1))
+	{
+		table[*b as usize] = needle.len() - 1 - i;
+	}
+	table
+}
+
+fn horspool_find(haystack: &[u8], needle: &[u8], table: &[usize; 256]) -> Option<usize> {
+	let m = needle.len();
+	let mut pos = 0;
+	while pos + m <= haystack.len() {
+		let last = haystack[pos + m - 1];
+		if last == needle[m - 1] && haystack[pos..pos + m - 1] == needle[..m - 1] {
+			return Some(pos);
+		}
+		pos += table[last as usize];
+	}
+	None
+}
+
 /// Get the time since the Unix Epoch in u64
 pub fn time_since_epoch() -> Result<u64, Error> {
 	let now = SystemTime::now();
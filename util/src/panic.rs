@@ -0,0 +1,146 @@
+// Copyright (c) 2023-2024, The BitcoinMW Developers
+// Some code and concepts from:
+// * Grin: https://github.com/mimblewimble/grin
+// * Arti: https://gitlab.torproject.org/tpo/core/arti
+// * BitcoinMW: https://github.com/bitcoinmw/bitcoinmw
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::PanicInfoCapture;
+use bmw_deps::lazy_static::lazy_static;
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::panic::{set_hook, take_hook};
+use std::sync::{Mutex, Once, PoisonError};
+use std::thread::{current, ThreadId};
+
+// the message used when the payload is not a string. This is what the default hook prints.
+const NON_STRING_PAYLOAD: &str = "Box<dyn Any>";
+
+// the thread name, file and line of a panic
+type PanicLocation = (Option<String>, String, u32);
+
+lazy_static! {
+	// the thread name and location of the last panic on each thread that enabled capture. The
+	// entry is removed when the panic is captured.
+	static ref PANIC_LOCATIONS: Mutex<HashMap<ThreadId, PanicLocation>> =
+		Mutex::new(HashMap::new());
+}
+
+static HOOK_INSTALLED: Once = Once::new();
+
+thread_local! {
+	// set by PanicInfoCapture::enable
+	static CAPTURE_ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+// chain a hook in front of the existing one that records the location of panics on threads
+// that enabled capture
+fn install_hook() {
+	HOOK_INSTALLED.call_once(|| {
+		let prev = take_hook();
+		set_hook(Box::new(move |info| {
+			if CAPTURE_ENABLED.with(|enabled| enabled.get()) {
+				if let Some(location) = info.location() {
+					let thread = current();
+					let name = thread.name().map(|name| name.to_string());
+					let file = location.file().to_string();
+					PANIC_LOCATIONS
+						.lock()
+						.unwrap_or_else(PoisonError::into_inner)
+						.insert(thread.id(), (name, file, location.line()));
+				}
+			}
+			prev(info);
+		}));
+	});
+}
+
+impl PanicInfoCapture {
+	/// Record the thread name and location of panics on the current thread so that they are
+	/// included in the [`crate::PanicInfoCapture`] built for them. A panic hook that calls the
+	/// previously installed hook is installed the first time this is called.
+	pub fn enable() {
+		install_hook();
+		CAPTURE_ENABLED.with(|enabled| enabled.set(true));
+	}
+
+	/// Build a [`crate::PanicInfoCapture`] from the payload of a panic that occurred on the
+	/// thread with the id `thread` (i.e. the payload returned by
+	/// [`std::panic::catch_unwind`] or [`std::thread::JoinHandle::join`]). The message is
+	/// taken from a `&str` or [`std::string::String`] payload. For any other payload, it is
+	/// `Box<dyn Any>`. The thread name and location are only available if the thread called
+	/// [`crate::PanicInfoCapture::enable`] before it panicked.
+	pub fn from_payload(payload: &(dyn Any + Send), thread: ThreadId) -> Self {
+		let message = match payload.downcast_ref::<&str>() {
+			Some(message) => message.to_string(),
+			None => match payload.downcast_ref::<String>() {
+				Some(message) => message.clone(),
+				None => NON_STRING_PAYLOAD.to_string(),
+			},
+		};
+		let location = PANIC_LOCATIONS
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.remove(&thread);
+		match location {
+			Some((thread_name, file, line)) => Self {
+				message,
+				thread_name,
+				file: Some(file),
+				line: Some(line),
+			},
+			None => Self {
+				message,
+				thread_name: None,
+				file: None,
+				line: None,
+			},
+		}
+	}
+
+	/// The panic message.
+	pub fn message(&self) -> &str {
+		&self.message
+	}
+
+	/// The name of the thread that panicked or [`None`] if it is unnamed or unknown.
+	pub fn thread_name(&self) -> Option<&str> {
+		self.thread_name.as_deref()
+	}
+
+	/// The source file of the panic or [`None`] if it is unknown.
+	pub fn file(&self) -> Option<&str> {
+		self.file.as_deref()
+	}
+
+	/// The line of the panic or [`None`] if it is unknown.
+	pub fn line(&self) -> Option<u32> {
+		self.line
+	}
+}
+
+impl Display for PanicInfoCapture {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+		write!(
+			f,
+			"thread '{}' panicked",
+			self.thread_name().unwrap_or("<unnamed>")
+		)?;
+		if let (Some(file), Some(line)) = (&self.file, self.line) {
+			write!(f, " at {}:{}", file, line)?;
+		}
+		write!(f, ": {}", self.message)
+	}
+}
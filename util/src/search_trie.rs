@@ -15,8 +15,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::types::{Dictionary, Node, SearchTrieImpl};
-use crate::{Match, Pattern, SearchTrie, Stack, UtilBuilder};
+use crate::types::{CompiledRegex, Dictionary, Node, RegexAtom, SearchTrieImpl};
+use crate::{Match, Pattern, SearchTrie, Stack, StreamingMatcher, UtilBuilder};
 use bmw_conf::ConfigOptionName as CN;
 use bmw_conf::{ConfigBuilder, ConfigOption};
 use bmw_err::{err, Error};
@@ -29,15 +29,195 @@ impl Default for Node {
 	fn default() -> Self {
 		Self {
 			next: [u32::MAX; 257],
+			classes: vec![],
+			multi_set: [u64::MAX; 4],
+			end_next: u32::MAX,
 			pattern_id: usize::MAX,
 			is_multi: false,
 			is_term: false,
 			is_start_only: false,
+			is_end_only: false,
 			is_multi_line: true,
 		}
 	}
 }
 
+fn byte_set(b: u8) -> [u64; 4] {
+	let mut set = [0u64; 4];
+	set_add(&mut set, b);
+	set
+}
+
+fn set_add(set: &mut [u64; 4], b: u8) {
+	set[(b >> 6) as usize] |= 1u64 << (b & 63);
+}
+
+fn set_contains(set: &[u64; 4], b: u8) -> bool {
+	set[(b >> 6) as usize] & (1u64 << (b & 63)) != 0
+}
+
+fn regex_err(text: &str, position: usize) -> Error {
+	let fmt = format!("{} at position {}", text, position);
+	err!(ErrKind::IllegalArgument, fmt)
+}
+
+// compile the restricted regex syntax documented on Pattern
+pub(crate) fn compile_regex(regex: &str) -> Result<CompiledRegex, Error> {
+	let bytes = regex.as_bytes();
+	let len = bytes.len();
+	if len == 0 {
+		let text = "regex length must be greater than 0";
+		let e = err!(ErrKind::IllegalArgument, text);
+		return Err(e);
+	}
+
+	let mut atoms = vec![];
+	let mut is_start_only = false;
+	let mut is_end_only = false;
+	let mut i = 0;
+
+	if bytes[0] == b'^' {
+		is_start_only = true;
+		i = 1;
+	}
+
+	while i < len {
+		let atom = match bytes[i] {
+			b'^' => {
+				let text = "'^' is only supported at the start of a regex";
+				return Err(regex_err(text, i));
+			}
+			b'$' => {
+				if i != len - 1 {
+					let text = "'$' is only supported at the end of a regex";
+					return Err(regex_err(text, i));
+				}
+				is_end_only = true;
+				break;
+			}
+			b'*' => {
+				let text = "'*' must follow a byte, '.' or a character class";
+				return Err(regex_err(text, i));
+			}
+			b']' => {
+				let text = "unmatched ']'";
+				return Err(regex_err(text, i));
+			}
+			b'.' => RegexAtom::Wildcard,
+			b'\\' => {
+				i += 1;
+				match bytes.get(i) {
+					Some(b) if b"\\.*[]^$".contains(b) => RegexAtom::Byte(*b),
+					Some(b) => {
+						let fmt = format!("Illegal escape character '{}'", *b as char);
+						return Err(regex_err(&fmt, i));
+					}
+					None => {
+						let text = "Illegal escape character at termination of string";
+						return Err(regex_err(text, i - 1));
+					}
+				}
+			}
+			b'[' => {
+				let (set, class_end) = compile_class(bytes, i)?;
+				i = class_end;
+				RegexAtom::Class(set)
+			}
+			b => RegexAtom::Byte(b),
+		};
+		i += 1;
+		let is_multi = bytes.get(i) == Some(&b'*');
+		if is_multi {
+			i += 1;
+		}
+		atoms.push((atom, is_multi));
+	}
+
+	if atoms.len() == 0 {
+		let text = "Regex must be at least one byte long not including the ^ and $ characters";
+		let e = err!(ErrKind::IllegalArgument, text);
+		return Err(e);
+	}
+
+	Ok(CompiledRegex {
+		atoms,
+		is_start_only,
+		is_end_only,
+	})
+}
+
+// compile the class starting with the '[' at `start`. Returns the set of bytes in the class and
+// the position of the closing ']'
+fn compile_class(bytes: &[u8], start: usize) -> Result<([u64; 4], usize), Error> {
+	let mut set = [0u64; 4];
+	let mut is_empty = true;
+	let mut i = start + 1;
+
+	if bytes.get(i) == Some(&b'^') {
+		let text = "negated character classes are not supported";
+		return Err(regex_err(text, i));
+	}
+
+	loop {
+		let lo = match bytes.get(i) {
+			None => {
+				let text = "unterminated character class";
+				return Err(regex_err(text, start));
+			}
+			Some(b']') => {
+				if is_empty {
+					let text = "empty character class";
+					return Err(regex_err(text, start));
+				}
+				return Ok((set, i));
+			}
+			Some(_) => class_byte(bytes, &mut i)?,
+		};
+
+		// a '-' which is followed by the closing ']' is a literal
+		let hi = if bytes.get(i + 1) == Some(&b'-')
+			&& bytes.get(i + 2).is_some()
+			&& bytes.get(i + 2) != Some(&b']')
+		{
+			let range_start = i;
+			i += 2;
+			let hi = class_byte(bytes, &mut i)?;
+			if hi < lo {
+				let text = "reversed character class range";
+				return Err(regex_err(text, range_start));
+			}
+			hi
+		} else {
+			lo
+		};
+
+		for b in lo..=hi {
+			set_add(&mut set, b);
+		}
+		is_empty = false;
+		i += 1;
+	}
+}
+
+// return the class byte at `i`, advancing `i` past the escape character if there is one
+fn class_byte(bytes: &[u8], i: &mut usize) -> Result<u8, Error> {
+	if bytes[*i] != b'\\' {
+		return Ok(bytes[*i]);
+	}
+	*i += 1;
+	match bytes.get(*i) {
+		Some(b) if b"\\[]-^".contains(b) => Ok(*b),
+		Some(b) => {
+			let fmt = format!("Illegal escape character '{}'", *b as char);
+			Err(regex_err(&fmt, *i))
+		}
+		None => {
+			let text = "Illegal escape character at termination of string";
+			Err(regex_err(text, *i - 1))
+		}
+	}
+}
+
 impl Dictionary {
 	fn new() -> Result<Self, Error> {
 		Ok(Self {
@@ -46,148 +226,165 @@ impl Dictionary {
 		})
 	}
 
-	fn add(&mut self, pattern: Pattern) -> Result<(), Error> {
-		if pattern.regex.len() == 0 {
-			let text = "regex length must be greater than 0";
-			let e = err!(ErrKind::IllegalArgument, text);
-			return Err(e);
-		}
-
-		let lower;
-		let mut regex = if pattern.is_case_sensitive {
-			pattern.regex.as_str().bytes().peekable()
+	// add the pattern and return the maximum number of bytes a match of it may span
+	fn add(&mut self, pattern: Pattern, max_wildcard_length: usize) -> Result<usize, Error> {
+		let compiled = if pattern.is_case_sensitive {
+			compile_regex(&pattern.regex)?
 		} else {
-			lower = pattern.regex.to_lowercase();
-			lower.as_str().bytes().peekable()
+			compile_regex(&pattern.regex.to_lowercase())?
 		};
-		let mut cur_byte = regex.next().unwrap();
-		let mut cur_node = &mut self.nodes[0];
-		let mut is_start_only = false;
-
-		if cur_byte == '^' as u8 {
-			cur_byte = match regex.next() {
-				Some(cur_byte) => {
-					is_start_only = true;
-					cur_byte
-				}
-				None => {
-					let text = "Regex must be at least one byte long not including the ^ character";
-					let e = err!(ErrKind::IllegalArgument, text);
-					return Err(e);
-				}
-			}
-		}
+		let mut cur = 0;
+		let mut max_match_len: usize = 0;
 
-		loop {
-			let (check_index, is_multi) = if cur_byte == '.' as u8 {
-				let peek = regex.peek();
-				let is_multi = match peek {
-					Some(peek) => {
-						if *peek == '*' as u8 {
-							regex.next();
-							true
-						} else {
-							false
-						}
-					}
-					_ => false,
-				};
-				(256usize, is_multi) // wild card is 256
-			} else if cur_byte == '\\' as u8 {
-				let next = regex.next();
-				match next {
-					Some(next) => {
-						if next == '\\' as u8 {
-							(cur_byte as usize, false)
-						} else if next == '.' as u8 {
-							(next as usize, false)
-						} else {
-							let fmt = format!("Illegal escape character '{}'", next as char);
-							let e = err!(ErrKind::IllegalArgument, fmt);
-							return Err(e);
-						}
-					}
-					None => {
-						let fmt = "Illegal escape character at termination of string";
-						let e = err!(ErrKind::IllegalArgument, fmt);
-						return Err(e);
-					}
-				}
+		for (atom, is_multi) in compiled.atoms {
+			max_match_len = if is_multi {
+				max_match_len.saturating_add(max_wildcard_length)
 			} else {
-				(cur_byte as usize, false)
+				max_match_len.saturating_add(1)
 			};
-			let index = match cur_node.next[check_index] {
-				u32::MAX => {
-					cur_node.next[check_index] = self.next + 1;
-					self.next += 1;
-					self.next
+			// repeated bytes use a class edge so that they don't share a node with the
+			// literal edge of another pattern
+			let (index, multi_set) = match atom {
+				RegexAtom::Byte(b) if !is_multi => {
+					(self.next_index(cur, b as usize)?, [u64::MAX; 4])
 				}
-				_ => cur_node.next[check_index],
+				RegexAtom::Byte(b) => (self.class_index(cur, byte_set(b), true)?, byte_set(b)),
+				RegexAtom::Wildcard => (self.next_index(cur, 256)?, [u64::MAX; 4]), // wild card is 256
+				RegexAtom::Class(set) => (self.class_index(cur, set, is_multi)?, set),
 			};
+			cur = index;
+			self.nodes[cur].is_multi = is_multi;
+			self.nodes[cur].multi_set = multi_set;
+		}
 
-			if index >= self.nodes.len().try_into()? {
-				self.nodes.push(Node::default());
-			}
-			cur_node = &mut self.nodes[index as usize];
-			cur_node.is_multi = is_multi;
-			cur_byte = match regex.next() {
-				Some(cur_byte) => cur_byte,
-				None => {
-					cur_node.pattern_id = pattern.id;
-					cur_node.is_term = pattern.is_termination_pattern;
-					cur_node.is_start_only = is_start_only;
-					cur_node.is_multi_line = pattern.is_multi_line;
-					break;
+		// end anchored patterns get their own node so they don't replace an unanchored
+		// pattern with the same regex
+		if compiled.is_end_only {
+			cur = match self.nodes[cur].end_next {
+				u32::MAX => {
+					let index = self.push_node()?;
+					self.nodes[cur].end_next = index;
+					index
 				}
-			};
+				index => index,
+			} as usize;
 		}
 
-		Ok(())
+		let node = &mut self.nodes[cur];
+		node.pattern_id = pattern.id;
+		node.is_term = pattern.is_termination_pattern;
+		node.is_start_only = compiled.is_start_only;
+		node.is_end_only = compiled.is_end_only;
+		node.is_multi_line = pattern.is_multi_line;
+
+		Ok(max_match_len)
+	}
+
+	fn next_index(&mut self, cur: usize, check_index: usize) -> Result<usize, Error> {
+		let index = match self.nodes[cur].next[check_index] {
+			u32::MAX => {
+				let index = self.push_node()?;
+				self.nodes[cur].next[check_index] = index;
+				index
+			}
+			index => index,
+		};
+		Ok(index as usize)
+	}
+
+	fn class_index(&mut self, cur: usize, set: [u64; 4], is_multi: bool) -> Result<usize, Error> {
+		for (class_set, index) in &self.nodes[cur].classes {
+			if *class_set == set && self.nodes[*index as usize].is_multi == is_multi {
+				return Ok(*index as usize);
+			}
+		}
+		let index = self.push_node()?;
+		self.nodes[cur].classes.push((set, index));
+		Ok(index as usize)
+	}
+
+	fn push_node(&mut self) -> Result<u32, Error> {
+		self.next += 1;
+		if self.next >= self.nodes.len().try_into()? {
+			self.nodes.push(Node::default());
+		}
+		Ok(self.next)
 	}
 }
 
 impl SearchTrie for SearchTrieImpl {
 	fn tmatch(&mut self, text: &[u8], matches: &mut [Match]) -> Result<usize, Error> {
-		let match_count = 0;
+		let mut match_count = 0;
+		let mut on_match = |m: Match| -> bool {
+			if match_count >= matches.len() {
+				// too many matches return with the first set of matches
+				return false;
+			}
+			matches[match_count] = m;
+			match_count += 1;
+			true
+		};
 		let max_wildcard_length = self.max_wildcard_length;
 		let termination_length = self.termination_length;
-		let dictionary = &self.dictionary_case_insensitive;
-		loop {
-			if self.branch_stack.pop().is_none() {
-				break;
-			}
-		}
-		let (match_count, term_pos) = Self::tmatch_impl(
+		let len = text.len();
+
+		self.clear_branch_stack();
+		let (stopped, term_pos) = Self::tmatch_impl(
 			text,
-			matches,
-			match_count,
-			dictionary,
+			0,
+			0,
+			len,
+			&self.dictionary_case_insensitive,
 			false,
 			max_wildcard_length,
 			&mut self.branch_stack,
 			termination_length,
 			usize::MAX,
+			len,
+			&mut on_match,
 		)?;
-		let dictionary = &self.dictionary_case_sensitive;
-		loop {
-			if self.branch_stack.pop().is_none() {
-				break;
-			}
+
+		if !stopped {
+			self.clear_branch_stack();
+			Self::tmatch_impl(
+				text,
+				0,
+				0,
+				len,
+				&self.dictionary_case_sensitive,
+				true,
+				max_wildcard_length,
+				&mut self.branch_stack,
+				termination_length,
+				term_pos,
+				len,
+				&mut on_match,
+			)?;
 		}
-		let (match_count, _term_pos) = Self::tmatch_impl(
-			text,
-			matches,
-			match_count,
-			dictionary,
-			true,
-			max_wildcard_length,
-			&mut self.branch_stack,
-			termination_length,
-			term_pos,
-		)?;
 
 		Ok(match_count)
 	}
+
+	fn streaming_matcher(&mut self) -> Result<StreamingMatcher<'_>, Error> {
+		if self.max_match_len == usize::MAX {
+			let text =
+				"streaming requires MaxWildCardLength to be configured when a pattern contains '*'";
+			let e = err!(ErrKind::Configuration, text);
+			return Err(e);
+		}
+
+		Ok(StreamingMatcher {
+			trie: self,
+			window: vec![],
+			offset: 0,
+			next_insensitive: 0,
+			next_sensitive: 0,
+			term_pos: usize::MAX,
+			is_insensitive_done: false,
+			is_sensitive_done: false,
+			is_stopped: false,
+		})
+	}
 }
 
 impl SearchTrieImpl {
@@ -217,14 +414,20 @@ impl SearchTrieImpl {
 
 		let mut dictionary_case_insensitive = Dictionary::new()?;
 		let mut dictionary_case_sensitive = Dictionary::new()?;
+		let mut max_match_len = 0;
 
-		let branch_stack = UtilBuilder::build_stack_sync_box(patterns.len(), &(0, 0))?;
+		// each byte of a regex adds at most one edge to the trie so this bounds the branches
+		let branch_capacity = patterns.iter().map(|p| p.regex.len()).sum::<usize>().max(1);
+		let branch_stack = UtilBuilder::build_stack_sync_box(branch_capacity, &(0, 0))?;
 
 		for pattern in patterns.iter() {
-			if pattern.is_case_sensitive {
-				dictionary_case_sensitive.add(pattern.clone())?;
+			let len = if pattern.is_case_sensitive {
+				dictionary_case_sensitive.add(pattern.clone(), max_wildcard_length)?
 			} else {
-				dictionary_case_insensitive.add(pattern.clone())?;
+				dictionary_case_insensitive.add(pattern.clone(), max_wildcard_length)?
+			};
+			if len > max_match_len {
+				max_match_len = len;
 			}
 		}
 		// no additional memory is needed. Shrink to the maximum possible
@@ -236,35 +439,91 @@ impl SearchTrieImpl {
 			dictionary_case_sensitive,
 			termination_length,
 			max_wildcard_length,
+			max_match_len,
 			branch_stack,
 		})
 	}
 
-	fn tmatch_impl(
+	fn clear_branch_stack(&mut self) {
+		loop {
+			if self.branch_stack.pop().is_none() {
+				break;
+			}
+		}
+	}
+
+	// scan the starting positions `start..end` of `text`. `offset` is the position of
+	// `text[0]` within the full input and is applied to reported matches, to the ^
+	// check, to termination_length, and to term_pos. `input_end` is the length of the full
+	// input, or usize::MAX if it is not known yet, and is used for the $ check. Returns
+	// whether `on_match` stopped the scan and the position of the termination match (or
+	// usize::MAX).
+
+	fn tmatch_impl<F>(
 		text: &[u8],
-		matches: &mut [Match],
-		mut match_count: usize,
+		offset: usize,
+		mut start: usize,
+		end: usize,
 		dictionary: &Dictionary,
 		case_sensitive: bool,
 		max_wildcard_length: usize,
 		branch_stack: &mut Box<dyn Stack<(usize, usize)> + Send + Sync>,
 		termination_length: usize,
 		term_pos: usize,
-	) -> Result<(usize, usize), Error> {
+		input_end: usize,
+		on_match: &mut F,
+	) -> Result<(bool, usize), Error>
+	where
+		F: FnMut(Match) -> bool,
+	{
 		let mut itt = 0;
 		let len = text.len();
 		let mut cur_node = &dictionary.nodes[0];
-		let mut start = 0;
 		let mut multi_counter = 0;
 		let mut is_branch = false;
 		let mut has_newline = false;
 
+		// report the pattern of `node` for the text `start..end` if it has one and all of its
+		// conditions are met. Returns Some if the scan must end.
+		let mut report = |node: &Node, start: usize, end: usize, has_newline: bool| {
+			if node.pattern_id == usize::MAX
+				|| (node.is_start_only && start != 0)
+				|| (node.is_end_only && end != input_end)
+				|| (has_newline && !node.is_multi_line)
+				|| end >= term_pos
+			{
+				return None;
+			}
+			let m = Match {
+				start,
+				end,
+				id: node.pattern_id,
+			};
+			if !on_match(m) {
+				Some((true, usize::MAX))
+			} else if node.is_term {
+				Some((false, end - 1))
+			} else {
+				None
+			}
+		};
+
 		loop {
-			if start >= len || start >= termination_length {
+			if start >= end || offset + start >= termination_length {
 				break;
 			}
 			if is_branch {
+				// the branch has already consumed the byte before itt
 				is_branch = false;
+				if let Some(ret) = report(cur_node, offset + start, offset + itt, has_newline) {
+					return Ok(ret);
+				}
+				if cur_node.end_next != u32::MAX {
+					let end_node = &dictionary.nodes[cur_node.end_next as usize];
+					if let Some(ret) = report(end_node, offset + start, offset + itt, has_newline) {
+						return Ok(ret);
+					}
+				}
 			} else {
 				has_newline = false;
 				itt = start;
@@ -293,9 +552,22 @@ impl SearchTrieImpl {
 					multi_counter = 0;
 				}
 
-				match cur_node.next[byte as usize] {
+				// the literal edge is taken first, then the class edges. The others are
+				// added to the branch stack.
+				let mut next = cur_node.next[byte as usize];
+				for (set, index) in &cur_node.classes {
+					if set_contains(set, byte) {
+						if next == u32::MAX {
+							next = *index;
+						} else {
+							branch_stack.push((itt + 1, *index as usize))?;
+						}
+					}
+				}
+
+				match next {
 					u32::MAX => {
-						if cur_node.is_multi {
+						if cur_node.is_multi && set_contains(&cur_node.multi_set, byte) {
 							multi_counter += 1;
 							if multi_counter >= max_wildcard_length {
 								// wild card max length. break as no
@@ -303,6 +575,15 @@ impl SearchTrieImpl {
 								break;
 							}
 							itt += 1;
+							// an end anchored pattern may end on any repetition
+							if cur_node.end_next != u32::MAX {
+								let end_node = &dictionary.nodes[cur_node.end_next as usize];
+								if let Some(ret) =
+									report(end_node, offset + start, offset + itt, has_newline)
+								{
+									return Ok(ret);
+								}
+							}
 							continue;
 						}
 						// check wildcard
@@ -318,37 +599,24 @@ impl SearchTrieImpl {
 							u32::MAX => {}
 							_ => {
 								// we have a branch here. Add it to the stack.
-								branch_stack.push((itt, cur_node.next[256] as usize))?;
+								branch_stack.push((itt + 1, cur_node.next[256] as usize))?;
 							}
 						}
-						cur_node = &dictionary.nodes[cur_node.next[byte as usize] as usize]
+						cur_node = &dictionary.nodes[next as usize]
 					}
 				}
 
-				match cur_node.pattern_id {
-					usize::MAX => {}
-					_ => {
-						if !(cur_node.is_start_only && start != 0) {
-							if match_count >= matches.len() {
-								// too many matches return with the
-								// first set of matches
-								return Ok((match_count, usize::MAX));
-							}
+				itt += 1;
 
-							if (!has_newline || cur_node.is_multi_line) && itt + 1 < term_pos {
-								matches[match_count].set_id(cur_node.pattern_id);
-								matches[match_count].set_end(itt + 1);
-								matches[match_count].set_start(start);
-								match_count += 1;
-								if cur_node.is_term {
-									return Ok((match_count, itt));
-								}
-							}
-						}
+				if let Some(ret) = report(cur_node, offset + start, offset + itt, has_newline) {
+					return Ok(ret);
+				}
+				if cur_node.end_next != u32::MAX {
+					let end_node = &dictionary.nodes[cur_node.end_next as usize];
+					if let Some(ret) = report(end_node, offset + start, offset + itt, has_newline) {
+						return Ok(ret);
 					}
 				}
-
-				itt += 1;
 			}
 
 			match branch_stack.pop() {
@@ -363,7 +631,129 @@ impl SearchTrieImpl {
 				}
 			}
 		}
-		Ok((match_count, usize::MAX))
+		Ok((false, usize::MAX))
+	}
+}
+
+impl StreamingMatcher<'_> {
+	/// Feed the next `chunk` of the stream to the matcher. `on_match` is called for each match
+	/// found with positions relative to the start of the stream. Matches which may still extend
+	/// into data that has not been fed yet are reported by a later call to `feed` or by
+	/// [`crate::StreamingMatcher::finish`]. If `on_match` returns false, matching stops and
+	/// all further data is ignored. The result is false if matching has been stopped.
+	pub fn feed<F>(&mut self, chunk: &[u8], mut on_match: F) -> Result<bool, Error>
+	where
+		F: FnMut(Match) -> bool,
+	{
+		// only retain a bounded amount of the stream at a time
+		let piece_len = self.trie.max_match_len.max(1);
+		for piece in chunk.chunks(piece_len) {
+			if self.is_stopped {
+				break;
+			}
+			self.window.extend_from_slice(piece);
+			self.scan(false, &mut on_match)?;
+		}
+		Ok(!self.is_stopped)
+	}
+
+	/// Signal the end of the stream and report the matches that are still pending.
+	/// The result is false if matching was stopped by `on_match`.
+	pub fn finish<F>(mut self, mut on_match: F) -> Result<bool, Error>
+	where
+		F: FnMut(Match) -> bool,
+	{
+		if !self.is_stopped {
+			self.scan(true, &mut on_match)?;
+		}
+		Ok(!self.is_stopped)
+	}
+
+	// scan all starting positions whose matches are fully determined by the data received.
+	// Case sensitive matches may be dropped by a later case insensitive termination match, so
+	// that pass lags one maximum match length behind the case insensitive pass.
+	fn scan<F>(&mut self, is_final: bool, on_match: &mut F) -> Result<(), Error>
+	where
+		F: FnMut(Match) -> bool,
+	{
+		let lag = if is_final { 0 } else { self.trie.max_match_len };
+		let end = self.offset + self.window.len();
+		// the end of the stream is only known once it has finished
+		let input_end = if is_final { end } else { usize::MAX };
+		let max_wildcard_length = self.trie.max_wildcard_length;
+		let termination_length = self.trie.termination_length;
+
+		let limit = end.saturating_sub(lag);
+		if !self.is_insensitive_done && limit > self.next_insensitive {
+			self.trie.clear_branch_stack();
+			let (stopped, term_pos) = SearchTrieImpl::tmatch_impl(
+				&self.window,
+				self.offset,
+				self.next_insensitive - self.offset,
+				limit - self.offset,
+				&self.trie.dictionary_case_insensitive,
+				false,
+				max_wildcard_length,
+				&mut self.trie.branch_stack,
+				termination_length,
+				usize::MAX,
+				input_end,
+				on_match,
+			)?;
+			if stopped {
+				self.is_stopped = true;
+				return Ok(());
+			}
+			if term_pos != usize::MAX {
+				self.is_insensitive_done = true;
+				self.term_pos = term_pos;
+			}
+			self.next_insensitive = limit;
+		}
+
+		let limit = if self.is_insensitive_done {
+			end.saturating_sub(lag)
+		} else {
+			self.next_insensitive.saturating_sub(lag)
+		};
+		if !self.is_sensitive_done && limit > self.next_sensitive {
+			self.trie.clear_branch_stack();
+			let (stopped, term_pos) = SearchTrieImpl::tmatch_impl(
+				&self.window,
+				self.offset,
+				self.next_sensitive - self.offset,
+				limit - self.offset,
+				&self.trie.dictionary_case_sensitive,
+				true,
+				max_wildcard_length,
+				&mut self.trie.branch_stack,
+				termination_length,
+				self.term_pos,
+				input_end,
+				on_match,
+			)?;
+			if stopped {
+				self.is_stopped = true;
+				return Ok(());
+			}
+			if term_pos != usize::MAX {
+				self.is_sensitive_done = true;
+			}
+			self.next_sensitive = limit;
+		}
+
+		// discard the data that no remaining starting position needs
+		let mut keep = end;
+		if !self.is_insensitive_done {
+			keep = keep.min(self.next_insensitive);
+		}
+		if !self.is_sensitive_done {
+			keep = keep.min(self.next_sensitive);
+		}
+		self.window.drain(..keep - self.offset);
+		self.offset = keep;
+
+		Ok(())
 	}
 }
 
@@ -385,15 +775,6 @@ impl Match {
 	pub fn id(&self) -> usize {
 		self.id
 	}
-	pub(crate) fn set_start(&mut self, start: usize) {
-		self.start = start;
-	}
-	pub(crate) fn set_end(&mut self, end: usize) {
-		self.end = end;
-	}
-	pub(crate) fn set_id(&mut self, id: usize) {
-		self.id = id;
-	}
 }
 
 impl Pattern {
@@ -420,6 +801,7 @@ impl Pattern {
 			let tx = "Patterns may not be both a termination pattern and case sensitive";
 			return Err(err!(ErrKind::IllegalArgument, tx));
 		}
+		compile_regex(&regex)?;
 		Ok(Self {
 			regex,
 			is_termination_pattern,
@@ -445,9 +827,9 @@ impl Pattern {
 impl Serializable for Pattern {
 	fn read<R: Reader>(reader: &mut R) -> Result<Self, Error> {
 		let regex = String::read(reader)?;
-		let is_case_sensitive = if reader.read_u8()? == 0 { false } else { true };
-		let is_termination_pattern = if reader.read_u8()? == 0 { false } else { true };
-		let is_multi_line = if reader.read_u8()? == 0 { false } else { true };
+		let is_case_sensitive = bool::read(reader)?;
+		let is_termination_pattern = bool::read(reader)?;
+		let is_multi_line = bool::read(reader)?;
 		let id = reader.read_usize()?;
 
 		let ret = Self {
@@ -18,8 +18,9 @@
 use crate::misc::set_max;
 use crate::misc::{slice_to_usize, usize_to_slice};
 use crate::{
-	Array, ArrayList, Hashset, Hashtable, List, LockBox, SlabAllocator, SlabAllocatorConfig,
-	SlabReader, SlabWriter, SortableList, UtilBuilder, GLOBAL_SLAB_ALLOCATOR,
+	Array, Array2D, ArrayList, BitField, Hashset, Hashtable, List, LockBox, SlabAllocator,
+	SlabAllocatorConfig, SlabRange, SlabReader, SlabWriter, SortableList, StringInterner, Symbol,
+	UtilBuilder, GLOBAL_SLAB_ALLOCATOR,
 };
 use bmw_conf::ConfigOption::*;
 use bmw_err::{cbreak, err, Error};
@@ -41,22 +42,58 @@ impl<S: Serializable + Clone> Serializable for Array<S> {
 		Ok(())
 	}
 	fn read<R: Reader>(reader: &mut R) -> Result<Array<S>, Error> {
-		let len = reader.read_usize()?;
-		let mut a: Option<Array<S>> = None;
-		for i in 0..len {
-			let s = Serializable::read(reader)?;
-			if i == 0 {
-				a = Some(UtilBuilder::build_array(len, &s)?);
-			}
-			a.as_mut().unwrap()[i] = s;
-		}
-
-		if a.is_none() {
+		// the elements are read before the array is allocated so that a corrupt length
+		// fails when the data runs out
+		let v: Vec<S> = Serializable::read(reader)?;
+		if v.is_empty() {
 			let e = err!(ErrKind::CorruptedData, "size of array cannot be 0");
 			return Err(e);
 		}
+		let mut a = UtilBuilder::build_array(v.len(), &v[0])?;
+		for (i, s) in v.into_iter().enumerate().skip(1) {
+			a[i] = s;
+		}
+		Ok(a)
+	}
+}
 
-		Ok(a.unwrap())
+impl<S: Serializable + Clone> Serializable for Array2D<S> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		writer.write_usize(self.rows)?;
+		writer.write_usize(self.cols)?;
+		for s in self.as_flat() {
+			Serializable::write(s, writer)?;
+		}
+		Ok(())
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Array2D<S>, Error> {
+		let rows = reader.read_usize()?;
+		let cols = reader.read_usize()?;
+		if rows == 0 || cols == 0 {
+			let e = err!(
+				ErrKind::CorruptedData,
+				"rows and cols of array2d cannot be 0"
+			);
+			return Err(e);
+		}
+		let size = match rows.checked_mul(cols) {
+			Some(size) => size,
+			None => {
+				let fmt = format!("{} rows of {} cols overflows usize", rows, cols);
+				return Err(err!(ErrKind::CorruptedData, fmt));
+			}
+		};
+		// as with Array, the cells are read before the array is allocated
+		let mut v = vec![];
+		for _ in 0..size {
+			let s: S = Serializable::read(reader)?;
+			v.push(s);
+		}
+		let mut a = UtilBuilder::build_array2d(rows, cols, &v[0])?;
+		for (cell, s) in a.as_flat_mut().iter_mut().zip(v).skip(1) {
+			*cell = s;
+		}
+		Ok(a)
 	}
 }
 
@@ -154,16 +191,73 @@ impl<S: Serializable + Clone + Debug + PartialEq> Serializable for ArrayList<S>
 		Ok(())
 	}
 	fn read<R: Reader>(reader: &mut R) -> Result<ArrayList<S>, Error> {
+		let v: Vec<S> = Serializable::read(reader)?;
+		if v.is_empty() {
+			let e = err!(ErrKind::CorruptedData, "size of array list cannot be 0");
+			return Err(e);
+		}
+		let mut a = ArrayList::new(v.len(), &v[0])?;
+		for s in v {
+			a.push(s)?;
+		}
+		Ok(a)
+	}
+}
+
+// the length in bits followed by the packed bytes. Bits set past the length are rejected so
+// that each field has a single encoding.
+impl Serializable for BitField {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		writer.write_u32(self.len() as u32)?;
+		writer.write_fixed_bytes(self.to_bytes())
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Self, Error> {
+		let len = reader.read_u32()? as usize;
+		let bytes = reader.read_bytes_vec(len.div_ceil(8))?;
+		BitField::from_bytes(len, bytes)
+	}
+}
+
+impl Serializable for Symbol {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		writer.write_u32(self.0)
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Self, Error> {
+		Ok(Symbol(reader.read_u32()?))
+	}
+}
+
+// the strings are written in symbol order and interned in the same order when read, so the
+// symbols issued by the original interner resolve to the same strings
+impl Serializable for StringInterner {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+		writer.write_usize(self.max_symbols)?;
+		writer.write_usize(self.slab_size)?;
+		writer.write_usize(self.slab_count)?;
+		writer.write_usize(self.len())?;
+		for (_, s) in self.iter() {
+			s.write(writer)?;
+		}
+		Ok(())
+	}
+	fn read<R: Reader>(reader: &mut R) -> Result<Self, Error> {
+		let max_symbols = reader.read_usize()?;
+		let slab_size = reader.read_usize()?;
+		let slab_count = reader.read_usize()?;
 		let len = reader.read_usize()?;
-		let mut a: Option<ArrayList<S>> = None;
-		for i in 0..len {
-			let s = Serializable::read(reader)?;
-			if i == 0 {
-				a = Some(ArrayList::new(len, &s)?);
+		let mut interner = UtilBuilder::build_string_interner(vec![
+			MaxEntries(max_symbols),
+			SlabSize(slab_size),
+			SlabCount(slab_count),
+		])?;
+		for id in 0..len {
+			let s = String::read(reader)?;
+			if interner.intern(&s)?.0 as usize != id {
+				let text = format!("duplicate interned string: {}", s);
+				return Err(err!(ErrKind::CorruptedData, text));
 			}
-			a.as_mut().unwrap().push(s)?;
 		}
-		Ok(a.unwrap())
+		Ok(interner)
 	}
 }
 
@@ -174,11 +268,11 @@ impl SlabWriter {
 		slab_ptr_size: Option<usize>,
 	) -> Result<Self, Error> {
 		debug!("new with slab_id = {}", slab_id)?;
-		let (slab_size, slab_count) = match slabs {
+		let (slab_size, id_limit) = match slabs {
 			Some(ref slabs) => {
 				let slabs = slabs.rlock()?;
 				let guard = slabs.guard()?;
-				((**guard).slab_size()?, (**guard).slab_count()?)
+				((**guard).slab_size()?, (**guard).id_limit()?)
 			}
 			None => GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<(usize, usize), Error> {
 				let slabs = unsafe { f.get().as_mut().unwrap() };
@@ -195,15 +289,15 @@ impl SlabWriter {
 						slabs.slab_size()?
 					}
 				};
-				let slab_count = slabs.slab_count()?;
-				Ok((slab_size, slab_count))
+				let id_limit = slabs.id_limit()?;
+				Ok((slab_size, id_limit))
 			})?,
 		};
 
 		let slab_ptr_size = match slab_ptr_size {
 			Some(s) => s,
 			None => {
-				let mut x = slab_count;
+				let mut x = id_limit;
 				let mut ptr_size = 0;
 				loop {
 					if x == 0 {
@@ -224,11 +318,24 @@ impl SlabWriter {
 			offset: 0,
 			slab_size,
 			bytes_per_slab,
+			range: None,
 		};
 
 		Ok(ret)
 	}
 
+	/// Build a [`crate::SlabWriter`] that writes to the [`crate::SlabRange`] `range` as one
+	/// region of `range.count() * slab_size` bytes. No next pointers are written. Writing past
+	/// the end of the range returns [`bmw_err::ErrKind::CapacityExceeded`].
+	pub fn from_range(
+		slabs: Option<Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>>,
+		range: SlabRange,
+	) -> Result<Self, Error> {
+		let mut ret = Self::new(slabs, range.start, Some(0))?;
+		ret.range = Some(range);
+		Ok(ret)
+	}
+
 	/// go to a particular slab_id/offset within the [`crate::SlabAllocator`] associated with
 	/// this [`crate::SlabWriter`].
 	pub fn seek(&mut self, slab_id: usize, offset: usize) {
@@ -316,9 +423,22 @@ impl SlabWriter {
 			cbreak!(bytes_offset >= bytes_len);
 
 			if self.offset >= self.bytes_per_slab {
-				debug!("alloc slab b_offset={}, b_len={}", bytes_offset, bytes_len)?;
-				// we need to allocate another slab
-				self.next_slab(max_value)?;
+				match self.range {
+					Some(range) => {
+						// the next slab of a range is the adjacent one
+						if self.slab_id + 1 >= range.start + range.count {
+							let text = "write past the end of the slab range";
+							return Err(err!(ErrKind::CapacityExceeded, text));
+						}
+						self.offset = 0;
+						self.slab_id += 1;
+					}
+					None => {
+						debug!("alloc slab b_offset={}, b_len={}", bytes_offset, bytes_len)?;
+						// we need to allocate another slab
+						self.next_slab(max_value)?;
+					}
+				}
 			}
 
 			let index = if skip { 0 } else { bytes_offset };
@@ -421,11 +541,11 @@ impl<'a> SlabReader {
 		slab_id: usize,
 		slab_ptr_size: Option<usize>,
 	) -> Result<Self, Error> {
-		let (slab_size, slab_count) = match slabs.as_ref() {
+		let (slab_size, id_limit) = match slabs.as_ref() {
 			Some(slabs) => {
 				let slabs = slabs.rlock()?;
 				let guard = slabs.guard()?;
-				(guard.slab_size()?, guard.slab_count()?)
+				(guard.slab_size()?, guard.id_limit()?)
 			}
 			None => GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<(usize, usize), Error> {
 				let slabs = unsafe { f.get().as_mut().unwrap() };
@@ -440,15 +560,15 @@ impl<'a> SlabReader {
 					slabs.init(SlabAllocatorConfig::default())?;
 					slabs.slab_size()?
 				};
-				let slab_count = slabs.slab_count()?;
-				Ok((slab_size, slab_count))
+				let id_limit = slabs.id_limit()?;
+				Ok((slab_size, id_limit))
 			})?,
 		};
 
 		let slab_ptr_size = if slab_ptr_size.is_some() {
 			slab_ptr_size.unwrap()
 		} else {
-			let mut x = slab_count;
+			let mut x = id_limit;
 			let mut ptr_size = 0;
 			loop {
 				cbreak!(x == 0);
@@ -470,10 +590,23 @@ impl<'a> SlabReader {
 			slab_size,
 			bytes_per_slab,
 			max_value,
+			range: None,
 		};
 		Ok(ret)
 	}
 
+	/// Build a [`crate::SlabReader`] that reads the [`crate::SlabRange`] `range` as one region
+	/// of `range.count() * slab_size` bytes, as written by [`crate::SlabWriter::from_range`].
+	/// Reading past the end of the range returns [`bmw_err::ErrKind::IO`].
+	pub fn from_range(
+		slabs: Option<Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>>,
+		range: SlabRange,
+	) -> Result<Self, Error> {
+		let mut ret = Self::new(slabs, range.start, Some(0))?;
+		ret.range = Some(range);
+		Ok(ret)
+	}
+
 	/// go to a particular slab_id/offset within the [`crate::SlabAllocator`] associated with
 	/// this [`crate::SlabReader`].
 	pub fn seek(&mut self, slab_id: usize, offset: usize) {
@@ -550,8 +683,17 @@ impl<'a> SlabReader {
 
 			if self.offset >= self.bytes_per_slab {
 				self.offset = 0;
-				let next = self.get_next_id(self.slab_id)?;
-				if next >= self.max_value {
+				let next = match self.range {
+					Some(range) => match self.slab_id + 1 < range.start + range.count {
+						true => self.slab_id + 1,
+						false => {
+							let text = "read past the end of the slab range";
+							return Err(err!(ErrKind::IO, text));
+						}
+					},
+					None => self.get_next_id(self.slab_id)?,
+				};
+				if self.range.is_none() && next >= self.max_value {
 					let t = format!("overflow: next={}, self.max_value={}", next, self.max_value);
 					let e = err!(ErrKind::IO, t);
 					return Err(e);
@@ -654,4 +796,45 @@ impl Reader for SlabReader {
 			Err(err!(ErrKind::CorruptedData, fmt))
 		}
 	}
+
+	// only supported for the thread local slab allocator and only if the requested range is
+	// within a single slab. A slab allocator in a LockBox cannot hand out references without
+	// holding its lock.
+	fn read_bytes_ref(&mut self, len: usize) -> Result<&[u8], Error> {
+		if self.slabs.is_some() {
+			let text = "read_bytes_ref is only supported with the global slab allocator";
+			return Err(err!(ErrKind::OperationNotSupported, text));
+		}
+
+		let (mut slab_id, mut offset) = (self.slab_id, self.offset);
+		if offset >= self.bytes_per_slab && len > 0 {
+			offset = 0;
+			slab_id = self.get_next_id(slab_id)?;
+			if slab_id >= self.max_value {
+				let t = format!(
+					"overflow: next={}, self.max_value={}",
+					slab_id, self.max_value
+				);
+				return Err(err!(ErrKind::IO, t));
+			}
+		}
+
+		if offset.saturating_add(len) > self.bytes_per_slab {
+			let fmt = format!(
+				"read_bytes_ref of {} bytes at offset {} spans more than one slab",
+				len, offset
+			);
+			return Err(err!(ErrKind::OperationNotSupported, fmt));
+		}
+
+		let ret = GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<&[u8], Error> {
+			let slabs = unsafe { f.get().as_ref().unwrap() };
+			let slab = slabs.get(slab_id)?;
+			Ok(&slab.data[offset..(offset + len)])
+		})?;
+
+		self.slab_id = slab_id;
+		self.offset = offset + len;
+		Ok(ret)
+	}
 }
@@ -15,10 +15,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::misc::{set_max, slice_to_usize, usize_to_slice};
-use crate::types::SlabAllocatorImpl;
-use crate::{Array, Slab, SlabAllocator, SlabAllocatorConfig, SlabMut, UtilBuilder};
-use bmw_err::{err, Error};
+use crate::misc::{checked_add_usize, checked_mul_usize, set_max, slice_to_usize, usize_to_slice};
+use crate::types::{HeapSlab, SlabAllocatorImpl};
+use crate::{
+	Array, Slab, SlabAllocator, SlabAllocatorConfig, SlabMut, SlabRange, SlabStats, UtilBuilder,
+};
+use bmw_err::{cbreak, err, Error};
 use bmw_log::*;
 use std::cell::UnsafeCell;
 
@@ -29,6 +31,7 @@ impl Default for SlabAllocatorConfig {
 		Self {
 			slab_size: 256,
 			slab_count: 40 * 1024,
+			overflow_to_heap: false,
 		}
 	}
 }
@@ -69,6 +72,18 @@ impl<'a> Slab<'a> {
 	}
 }
 
+impl SlabRange {
+	/// get the id of the first slab in this range.
+	pub fn start(&self) -> usize {
+		self.start
+	}
+
+	/// get the number of slabs in this range.
+	pub fn count(&self) -> usize {
+		self.count
+	}
+}
+
 impl SlabAllocator for SlabAllocatorImpl {
 	fn is_init(&self) -> bool {
 		self.config.is_some()
@@ -80,6 +95,9 @@ impl SlabAllocator for SlabAllocatorImpl {
 		let config = self.config.as_ref().unwrap();
 		debug!("allocate:self.config={:?}", config)?;
 		if self.first_free == self.max_value {
+			if config.overflow_to_heap {
+				return self.allocate_overflow();
+			}
 			return Err(err!(ErrKind::CapacityExceeded, "no more slabs available"));
 		}
 
@@ -106,6 +124,9 @@ impl SlabAllocator for SlabAllocatorImpl {
 		debug!("slabs free id ={}", id)?;
 		match &self.config {
 			Some(config) => {
+				if id >= config.slab_count && config.overflow_to_heap {
+					return self.free_overflow(id);
+				}
 				if id >= config.slab_count {
 					let fmt = format!("slab.id = {}, total slabs = {}", id, config.slab_count);
 					return Err(err!(ErrKind::ArrayIndexOutOfBounds, fmt));
@@ -133,6 +154,7 @@ impl SlabAllocator for SlabAllocatorImpl {
 				self.first_free = id;
 				debug!("update firstfree to {}", self.first_free)?;
 				self.free_count += 1;
+				self.generations[id] = self.generations[id].wrapping_add(1);
 				Ok(())
 			}
 			None => {
@@ -142,11 +164,98 @@ impl SlabAllocator for SlabAllocatorImpl {
 			}
 		}
 	}
+	fn allocate_contiguous(&mut self, n_slabs: usize) -> Result<SlabRange, Error> {
+		let (slab_size, slab_count) = match &self.config {
+			Some(config) => (config.slab_size, config.slab_count),
+			None => return Err(err!(ErrKind::IllegalState, "not initialized")),
+		};
+		if n_slabs == 0 {
+			return Err(err!(
+				ErrKind::IllegalArgument,
+				"n_slabs must be greater than 0"
+			));
+		}
+		if n_slabs > self.free_count {
+			let fmt = format!("{} slabs requested, {} free", n_slabs, self.free_count);
+			return Err(err!(ErrKind::CapacityExceeded, fmt));
+		}
+
+		// first fit: find the lowest run of n_slabs free slabs
+		let mut run = 0;
+		let mut start = None;
+		for id in 0..slab_count {
+			if self.is_allocated(id)? {
+				run = 0;
+			} else {
+				run += 1;
+				if run == n_slabs {
+					start = Some(id + 1 - n_slabs);
+					break;
+				}
+			}
+		}
+		let start = match start {
+			Some(start) => start,
+			None => {
+				let fmt = format!(
+					"no run of {} adjacent free slabs ({} free)",
+					n_slabs, self.free_count
+				);
+				return Err(err!(ErrKind::Fragmentation, fmt));
+			}
+		};
+		debug!(
+			"slab allocate contiguous start = {}, count = {}",
+			start, n_slabs
+		)?;
+
+		// mark the slabs as not free and take them out of the free list
+		let mut invalid_ptr = [0u8; 8];
+		usize_to_slice(self.max_value - 1, &mut invalid_ptr[0..self.ptr_size])?;
+		for id in start..start + n_slabs {
+			let offset = (self.ptr_size + slab_size) * id;
+			self.data.as_mut()[offset..offset + self.ptr_size]
+				.clone_from_slice(&invalid_ptr[0..self.ptr_size]);
+		}
+		self.free_count -= n_slabs;
+		self.rebuild_free_list()?;
+
+		Ok(SlabRange {
+			start,
+			count: n_slabs,
+		})
+	}
+	fn free_range(&mut self, range: SlabRange) -> Result<(), Error> {
+		let slab_count = match &self.config {
+			Some(config) => config.slab_count,
+			None => return Err(err!(ErrKind::IllegalState, "not initialized")),
+		};
+		let end = checked_add_usize(range.start, range.count)?;
+		if end > slab_count {
+			let fmt = format!("slab range end = {}, total slabs = {}", end, slab_count);
+			return Err(err!(ErrKind::ArrayIndexOutOfBounds, fmt));
+		}
+		// check the whole range first so that an invalid range frees nothing
+		for id in range.start..end {
+			if !self.is_allocated(id)? {
+				let fmt = format!("slab.id = {} has been freed when not allocated", id);
+				return Err(err!(ErrKind::IllegalState, fmt));
+			}
+		}
+		for id in range.start..end {
+			self.free(id)?;
+		}
+		Ok(())
+	}
 	fn get<'a>(&'a self, id: usize) -> Result<Slab<'a>, Error> {
 		if self.config.is_none() {
 			return Err(err!(ErrKind::IllegalState, "not initialized"));
 		}
 		let config = self.config.as_ref().unwrap();
+		if id >= config.slab_count && config.overflow_to_heap {
+			let data = &self.heap_slab(id)?.data;
+			return Ok(Slab { data, id });
+		}
 		if id >= config.slab_count {
 			let fmt = format!("slab.id = {}, total slabs = {}", id, config.slab_count);
 			return Err(err!(ErrKind::ArrayIndexOutOfBounds, fmt));
@@ -163,6 +272,10 @@ impl SlabAllocator for SlabAllocatorImpl {
 			return Err(err!(ErrKind::IllegalState, "not initialized"));
 		}
 		let config = self.config.as_ref().unwrap();
+		if id >= config.slab_count && config.overflow_to_heap {
+			let data = &mut self.heap_slab_mut(id)?.data;
+			return Ok(SlabMut { data, id });
+		}
 		if id >= config.slab_count {
 			let fmt = format!("slab.id = {}, total slabs = {}", id, config.slab_count);
 			return Err(err!(ErrKind::ArrayIndexOutOfBounds, fmt));
@@ -228,16 +341,14 @@ impl SlabAllocator for SlabAllocatorImpl {
 				}
 
 				// build data array
-				let mut data = UtilBuilder::build_array(
-					config.slab_count * (config.slab_size + self.ptr_size),
-					&0u8,
-				)?;
+				let entry_size = checked_add_usize(config.slab_size, self.ptr_size)?;
+				let size = checked_mul_usize(config.slab_count, entry_size)?;
+				let mut data = UtilBuilder::build_array(size, &0u8)?;
 
 				// calculate the pointer size and max_value
 				self.ptr_size = 0;
-				let mut x = config.slab_count + 2; // two more,
-								   // one for termination
-								   // pointer and one for free status
+				// two more, one for termination pointer and one for free status
+				let mut x = checked_add_usize(config.slab_count, 2)?;
 				loop {
 					if x == 0 {
 						break;
@@ -259,12 +370,143 @@ impl SlabAllocator for SlabAllocatorImpl {
 				)?;
 				self.data = data;
 				self.free_count = config.slab_count;
+				let id_limit = match config.overflow_to_heap {
+					true => checked_mul_usize(config.slab_count, 2)?,
+					false => config.slab_count,
+				};
+				self.generations = vec![0; id_limit];
 				self.config = Some(config);
 				self.first_free = 0;
 				Ok(())
 			}
 		}
 	}
+
+	fn compact(
+		&mut self,
+		max_moves: usize,
+		relocate: &mut dyn FnMut(usize, usize) -> Result<(), Error>,
+	) -> Result<usize, Error> {
+		let (slab_size, slab_count) = match &self.config {
+			Some(config) => (config.slab_size, config.slab_count),
+			None => {
+				let text = "slab allocator has not been initialized";
+				return Err(err!(ErrKind::IllegalState, text));
+			}
+		};
+		let entry_size = self.ptr_size + slab_size;
+		let mut moves = 0;
+		let mut lo = 0;
+		let mut hi = slab_count;
+
+		loop {
+			cbreak!(moves >= max_moves);
+			// find the lowest free slab and the highest allocated slab
+			while lo < hi && self.is_allocated(lo)? {
+				lo += 1;
+			}
+			while hi > lo && !self.is_allocated(hi - 1)? {
+				hi -= 1;
+			}
+			cbreak!(lo >= hi);
+			hi -= 1;
+
+			debug!("compact: move slab {} to {}", hi, lo)?;
+			// copy the slab data (and the allocated marker) to the free slab
+			let data = self.data.as_mut();
+			data.copy_within(hi * entry_size..(hi + 1) * entry_size, lo * entry_size);
+			// mark the old location as free. The free list is rebuilt below.
+			let mut ptr = [0u8; 8];
+			usize_to_slice(self.max_value, &mut ptr[0..self.ptr_size])?;
+			data[hi * entry_size..hi * entry_size + self.ptr_size]
+				.clone_from_slice(&ptr[0..self.ptr_size]);
+			self.generations[hi] = self.generations[hi].wrapping_add(1);
+
+			relocate(hi, lo)?;
+			moves += 1;
+			lo += 1;
+		}
+
+		if moves > 0 {
+			self.rebuild_free_list()?;
+		}
+
+		Ok(moves)
+	}
+
+	fn high_water_mark(&self) -> Result<usize, Error> {
+		let slab_count = match &self.config {
+			Some(config) => config.slab_count,
+			None => {
+				let text = "slab allocator has not been initialized";
+				return Err(err!(ErrKind::IllegalState, text));
+			}
+		};
+		let mut hi = slab_count;
+		while hi > 0 && !self.is_allocated(hi - 1)? {
+			hi -= 1;
+		}
+		Ok(hi)
+	}
+
+	fn id_limit(&self) -> Result<usize, Error> {
+		match &self.config {
+			Some(config) if config.overflow_to_heap => checked_mul_usize(config.slab_count, 2),
+			Some(config) => Ok(config.slab_count),
+			None => {
+				let text = "slab allocator has not been initialized";
+				Err(err!(ErrKind::IllegalState, text))
+			}
+		}
+	}
+
+	fn stats(&self) -> Result<SlabStats, Error> {
+		let slab_count = match &self.config {
+			Some(config) => config.slab_count,
+			None => {
+				let text = "slab allocator has not been initialized";
+				return Err(err!(ErrKind::IllegalState, text));
+			}
+		};
+		let heap_overflow_slabs = self.overflow.iter().filter(|s| s.is_some()).count();
+		Ok(SlabStats {
+			slab_count,
+			free_count: self.free_count,
+			heap_overflow_slabs,
+			heap_overflow_in_use: heap_overflow_slabs - self.overflow_free.len(),
+		})
+	}
+
+	fn shrink_overflow(&mut self) -> Result<usize, Error> {
+		if self.config.is_none() {
+			return Err(err!(ErrKind::IllegalState, "not initialized"));
+		}
+		let released = self.overflow_free.len();
+		for index in self.overflow_free.drain(..) {
+			self.overflow[index] = None;
+		}
+		// drop the released entries at the end so the list only covers ids that are held
+		while let Some(None) = self.overflow.last() {
+			self.overflow.pop();
+		}
+		self.overflow.shrink_to_fit();
+		self.overflow_free.shrink_to_fit();
+		debug!("released {} overflow slabs", released)?;
+		Ok(released)
+	}
+
+	fn generation(&self, id: usize) -> Result<u64, Error> {
+		if self.config.is_none() {
+			return Err(err!(ErrKind::IllegalState, "not initialized"));
+		}
+		match self.generations.get(id) {
+			Some(generation) => Ok(*generation),
+			None => {
+				let fmt = format!("slab.id = {}, id limit = {}", id, self.generations.len());
+				Err(err!(ErrKind::ArrayIndexOutOfBounds, fmt))
+			}
+		}
+	}
 }
 
 impl SlabAllocatorImpl {
@@ -277,9 +519,117 @@ impl SlabAllocatorImpl {
 			first_free: 0,
 			ptr_size: 8,
 			max_value: 0,
+			overflow: vec![],
+			overflow_free: vec![],
+			generations: vec![],
 		}
 	}
 
+	// allocate a heap slab once the arena is exhausted. Freed heap slabs are reused first.
+	fn allocate_overflow<'a>(&'a mut self) -> Result<SlabMut<'a>, Error> {
+		let (slab_size, slab_count) = match &self.config {
+			Some(config) => (config.slab_size, config.slab_count),
+			None => return Err(err!(ErrKind::IllegalState, "not initialized")),
+		};
+		let index = match self.overflow_free.pop() {
+			Some(index) => index,
+			None => {
+				let index = match self.overflow.iter().position(|s| s.is_none()) {
+					Some(index) => index,
+					None => {
+						if self.overflow.len() >= slab_count {
+							let text = "no more slabs available";
+							return Err(err!(ErrKind::CapacityExceeded, text));
+						}
+						self.overflow.push(None);
+						self.overflow.len() - 1
+					}
+				};
+				self.overflow[index] = Some(HeapSlab {
+					data: vec![0u8; slab_size],
+					allocated: false,
+				});
+				index
+			}
+		};
+		let id = slab_count + index;
+		debug!("slab allocate overflow id = {}", id)?;
+		let slab = self.heap_slab_mut(id)?;
+		slab.allocated = true;
+		let data = &mut slab.data;
+		Ok(SlabMut { data, id })
+	}
+
+	fn free_overflow(&mut self, id: usize) -> Result<(), Error> {
+		debug!("slabs free overflow id ={}", id)?;
+		let slab = self.heap_slab_mut(id)?;
+		if !slab.allocated {
+			let fmt = format!("slab.id = {} has been freed when not allocated", id);
+			return Err(err!(ErrKind::IllegalState, fmt));
+		}
+		slab.allocated = false;
+		if let Some(generation) = self.generations.get_mut(id) {
+			*generation = generation.wrapping_add(1);
+		}
+		let slab_count = self.config.as_ref().map(|c| c.slab_count).unwrap_or(0);
+		self.overflow_free.push(id - slab_count);
+		Ok(())
+	}
+
+	fn heap_slab(&self, id: usize) -> Result<&HeapSlab, Error> {
+		let slab_count = self.config.as_ref().map(|c| c.slab_count).unwrap_or(0);
+		match self.overflow.get(id.saturating_sub(slab_count)) {
+			Some(Some(slab)) => Ok(slab),
+			_ => {
+				let fmt = format!("slab.id = {} is not a heap overflow slab", id);
+				Err(err!(ErrKind::ArrayIndexOutOfBounds, fmt))
+			}
+		}
+	}
+
+	fn heap_slab_mut(&mut self, id: usize) -> Result<&mut HeapSlab, Error> {
+		let slab_count = self.config.as_ref().map(|c| c.slab_count).unwrap_or(0);
+		match self.overflow.get_mut(id.saturating_sub(slab_count)) {
+			Some(Some(slab)) => Ok(slab),
+			_ => {
+				let fmt = format!("slab.id = {} is not a heap overflow slab", id);
+				Err(err!(ErrKind::ArrayIndexOutOfBounds, fmt))
+			}
+		}
+	}
+
+	fn is_allocated(&self, id: usize) -> Result<bool, Error> {
+		let slab_size = match &self.config {
+			Some(config) => config.slab_size,
+			None => 0,
+		};
+		let offset = (self.ptr_size + slab_size) * id;
+		let slab_entry = slice_to_usize(&self.data.as_slice()[offset..offset + self.ptr_size])?;
+		Ok(slab_entry == self.max_value - 1)
+	}
+
+	// rebuild the free list in ascending order so that subsequent allocations are made from
+	// the low end of the slab allocator.
+	fn rebuild_free_list(&mut self) -> Result<(), Error> {
+		let (slab_size, slab_count) = match &self.config {
+			Some(config) => (config.slab_size, config.slab_count),
+			None => return Ok(()),
+		};
+		let ptr_size = self.ptr_size;
+		let mut next = self.max_value;
+		let mut ptr = [0u8; 8];
+		for id in (0..slab_count).rev() {
+			if !self.is_allocated(id)? {
+				usize_to_slice(next, &mut ptr[0..ptr_size])?;
+				let offset = (ptr_size + slab_size) * id;
+				self.data.as_mut()[offset..offset + ptr_size].clone_from_slice(&ptr[0..ptr_size]);
+				next = id;
+			}
+		}
+		self.first_free = next;
+		Ok(())
+	}
+
 	fn build_free_list(
 		data: &mut Array<u8>,
 		slab_count: usize,
@@ -26,16 +26,19 @@ mod test {
 	use bmw_deps::rand;
 	use bmw_deps::rand::random;
 	use bmw_deps::random_string;
+	use bmw_derive::Serializable;
 	use bmw_err::*;
 	use bmw_log::*;
 	use bmw_ser::{deserialize, serialize, Reader, Serializable, Writer};
 	use bmw_test::*;
 	use bmw_util::*;
+	use std::any::Any;
 	use std::collections::HashMap;
 	use std::fmt::Debug;
 	use std::fs::{create_dir_all, File};
 	use std::io::Write;
 	use std::path::PathBuf;
+	use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 	use std::sync::{Arc, RwLock};
 
 	info!();
@@ -234,6 +237,76 @@ mod test {
 		Ok(())
 	}
 
+	#[derive(Clone)]
+	struct DropCounter(Arc<AtomicUsize>);
+
+	impl Drop for DropCounter {
+		fn drop(&mut self) {
+			self.0.fetch_add(1, Ordering::SeqCst);
+		}
+	}
+
+	#[test]
+	fn test_array2d() -> Result<(), Error> {
+		let mut grid = UtilBuilder::build_array2d(3, 4, &0usize)?;
+		assert_eq!(grid.rows(), 3);
+		assert_eq!(grid.cols(), 4);
+		for r in 0..3 {
+			for c in 0..4 {
+				*grid.get_mut(r, c)? = r * 10 + c;
+			}
+		}
+
+		// bounds are checked on both axes
+		assert!(grid.get(3, 0).is_err());
+		assert!(grid.get(0, 4).is_err());
+		assert!(grid.get_mut(3, 0).is_err());
+		assert!(grid.get_mut(0, 4).is_err());
+		assert!(grid.row_iter(3).is_err());
+		assert!(grid.col_iter(4).is_err());
+		assert!(grid.fill_row(3, &0).is_err());
+		assert!(UtilBuilder::build_array2d(0, 4, &0usize).is_err());
+		assert!(UtilBuilder::build_array2d(4, 0, &0usize).is_err());
+		assert!(UtilBuilder::build_array2d(usize::MAX, 2, &0usize).is_err());
+
+		// rows are iterated by column and columns by row
+		let row: Vec<usize> = grid.row_iter(1)?.cloned().collect();
+		assert_eq!(row, vec![10, 11, 12, 13]);
+		let col: Vec<usize> = grid.col_iter(2)?.cloned().collect();
+		assert_eq!(col, vec![2, 12, 22]);
+		let col: Vec<usize> = grid.col_iter(3)?.cloned().collect();
+		assert_eq!(col, vec![3, 13, 23]);
+
+		grid.fill_row(2, &7)?;
+		let row: Vec<usize> = grid.row_iter(2)?.cloned().collect();
+		assert_eq!(row, vec![7, 7, 7, 7]);
+		assert_eq!(grid.get(1, 3)?, &13);
+
+		// the flat view is row major
+		assert_eq!(grid.as_flat()[4], 10);
+		grid.as_flat_mut()[4] = 100;
+		assert_eq!(grid.get(1, 0)?, &100);
+
+		// serialization round trip
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &grid)?;
+		let grid2: Array2D<usize> = deserialize(&mut &v[..])?;
+		assert_eq!(grid, grid2);
+		assert_eq!(grid2.rows(), 3);
+		assert_eq!(grid2.cols(), 4);
+		assert_ne!(grid, array2d!(4, 3, &0usize)?);
+
+		// every cell is dropped exactly once
+		let drops = Arc::new(AtomicUsize::new(0));
+		let default = DropCounter(drops.clone());
+		let cells = array2d!(5, 7, &default)?;
+		assert_eq!(drops.load(Ordering::SeqCst), 0);
+		drop(cells);
+		assert_eq!(drops.load(Ordering::SeqCst), 35);
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_raw_array_list() -> Result<(), Error> {
 		let mut list1 = ArrayList::new(10, &0)?;
@@ -1094,6 +1167,676 @@ mod test {
 		Ok(())
 	}
 
+	// insert and then get keys from 8 threads, each with its own key range
+	fn sharded_workload<F>(mut insert_get: F) -> Result<(), Error>
+	where
+		F: FnMut(u64, u64) -> Result<Option<u64>, Error> + Send + Clone + 'static,
+	{
+		let mut handles = vec![];
+		for t in 0..8u64 {
+			let mut insert_get = insert_get.clone();
+			handles.push(std::thread::spawn(move || -> Result<(), Error> {
+				for i in t * 1_000..t * 1_000 + 500 {
+					assert_eq!(insert_get(i, i * 2)?, Some(i * 2));
+				}
+				Ok(())
+			}));
+		}
+		for handle in handles {
+			handle.join().unwrap()?;
+		}
+		// sanity check that the closure still works on this thread
+		assert_eq!(insert_get(u64::MAX, 1)?, Some(1));
+		Ok(())
+	}
+
+	#[derive(Serializable, Debug, PartialEq)]
+	struct PeerRecord {
+		addr: Symbol,
+		headers: Vec<Symbol>,
+		count: u64,
+	}
+
+	#[test]
+	fn test_string_interner() -> Result<(), Error> {
+		let mut interner = string_interner!(MaxEntries(100), SlabSize(8), SlabCount(32))?;
+		assert!(interner.is_empty());
+		assert_eq!(interner.capacity(), 256);
+
+		// strings span slabs
+		let a = interner.intern("127.0.0.1:8080")?;
+		let b = interner.intern("content-type")?;
+		let empty = interner.intern("")?;
+		assert_eq!(interner.intern("127.0.0.1:8080")?, a);
+		assert_eq!(interner.intern("content-type")?, b);
+		assert_eq!(interner.intern("")?, empty);
+		assert_ne!(a, b);
+		assert_eq!((a.id(), b.id(), empty.id()), (0, 1, 2));
+		assert_eq!(interner.len(), 3);
+		assert_eq!(interner.bytes_used(), 26);
+
+		assert_eq!(interner.resolve(a)?, "127.0.0.1:8080");
+		assert_eq!(interner.resolve(b)?, "content-type");
+		assert_eq!(interner.resolve(empty)?, "");
+		assert_eq!(interner.get("content-type")?, Some(b));
+		// a prefix of an interned string is a different string
+		assert_eq!(interner.get("content")?, None);
+		assert_eq!(interner.len(), 3);
+
+		let strings: Vec<(Symbol, String)> = interner.iter().collect();
+		assert_eq!(
+			strings,
+			vec![
+				(a, "127.0.0.1:8080".to_string()),
+				(b, "content-type".to_string()),
+				(empty, "".to_string())
+			]
+		);
+
+		// a symbol from another interner
+		let mut other = string_interner!()?;
+		for i in 0..10 {
+			other.intern(&format!("{}", i))?;
+		}
+		let foreign = other.intern("x")?;
+		assert!(interner.resolve(foreign).is_err());
+
+		// multi-byte characters
+		let c = interner.intern("ünïcödé")?;
+		assert_eq!(interner.resolve(c)?, "ünïcödé");
+
+		assert!(string_interner!(MaxEntries(0)).is_err());
+		assert!(string_interner!(SlabSize(0)).is_err());
+		assert!(string_interner!(MaxLoadFactor(0.5)).is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn test_string_interner_capacity() -> Result<(), Error> {
+		// out of symbols
+		let mut interner = string_interner!(MaxEntries(10))?;
+		for i in 0..10 {
+			interner.intern(&format!("key{}", i))?;
+		}
+		let e = interner.intern("key10").unwrap_err().kind();
+		assert!(matches!(e, ErrorKind::CapacityExceeded(_)));
+		// existing strings are still found
+		assert_eq!(interner.intern("key3")?.id(), 3);
+		assert_eq!(interner.len(), 10);
+
+		// out of bytes
+		let mut interner = string_interner!(SlabSize(10), SlabCount(3))?;
+		interner.intern("0123456789abcdef")?;
+		let e = interner.intern("0123456789abcdef0").unwrap_err().kind();
+		assert!(matches!(e, ErrorKind::CapacityExceeded(_)));
+		assert_eq!(interner.bytes_used(), 16);
+		assert_eq!(interner.len(), 1);
+		// the rest still fits
+		let s = interner.intern("0123456789abcd")?;
+		assert_eq!(interner.resolve(s)?, "0123456789abcd");
+		assert_eq!(interner.bytes_used(), 30);
+		let e = interner.intern("z").unwrap_err().kind();
+		assert!(matches!(e, ErrorKind::CapacityExceeded(_)));
+
+		// the index holds MaxEntries symbols
+		let mut interner = string_interner!(MaxEntries(1_000), SlabCount(1_000))?;
+		for i in 0..1_000 {
+			assert_eq!(interner.intern(&format!("header-{}", i))?.id(), i);
+		}
+		for i in 0..1_000 {
+			assert_eq!(interner.get(&format!("header-{}", i))?.unwrap().id(), i);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_bitfield() -> Result<(), Error> {
+		// round trip at lengths that do and don't end on a byte boundary
+		for len in [0, 1, 7, 8, 9, 1023] {
+			let mut field = UtilBuilder::build_bitfield(len)?;
+			assert_eq!(field.len(), len);
+			assert_eq!(field.is_empty(), len == 0);
+			let mut model = vec![false; len];
+			for i in (0..len).filter(|i| i % 3 == 0 || i % 7 == 1) {
+				field.set(i, true)?;
+				model[i] = true;
+			}
+			if len > 0 {
+				field.set(len - 1, true)?;
+				model[len - 1] = true;
+			}
+
+			let mut v: Vec<u8> = vec![];
+			serialize(&mut v, &field)?;
+			assert_eq!(v.len(), 4 + len.div_ceil(8));
+			let field2: BitField = deserialize(&mut &v[..])?;
+			assert_eq!(field2, field);
+			for (i, bit) in model.iter().enumerate() {
+				assert_eq!(field2.get(i)?, *bit);
+			}
+			assert_eq!(field2.count_ones(), model.iter().filter(|b| **b).count());
+			let ones: Vec<usize> = (0..len).filter(|i| model[*i]).collect();
+			assert_eq!(field2.iter_ones().collect::<Vec<usize>>(), ones);
+
+			assert!(matches!(
+				field.get(len).unwrap_err().kind(),
+				ErrorKind::ArrayIndexOutOfBounds(_)
+			));
+			assert!(matches!(
+				field.set(len, true).unwrap_err().kind(),
+				ErrorKind::ArrayIndexOutOfBounds(_)
+			));
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_bitfield_ops() -> Result<(), Error> {
+		for len in [0, 7, 9, 1023] {
+			let a_model: Vec<bool> = (0..len).map(|_| random::<bool>()).collect();
+			let b_model: Vec<bool> = (0..len).map(|_| random::<bool>()).collect();
+			let mut a = UtilBuilder::build_bitfield(len)?;
+			let mut b = UtilBuilder::build_bitfield(len)?;
+			for i in 0..len {
+				a.set(i, a_model[i])?;
+				b.set(i, b_model[i])?;
+			}
+
+			let and = a.and(&b)?;
+			let or = a.or(&b)?;
+			let xor = a.xor(&b)?;
+			for i in 0..len {
+				assert_eq!(and.get(i)?, a_model[i] && b_model[i]);
+				assert_eq!(or.get(i)?, a_model[i] || b_model[i]);
+				assert_eq!(xor.get(i)?, a_model[i] != b_model[i]);
+			}
+			assert_eq!(and.len(), len);
+			assert_eq!(a.xor(&a)?.count_ones(), 0);
+		}
+
+		// the lengths must match
+		let a = UtilBuilder::build_bitfield(8)?;
+		let b = UtilBuilder::build_bitfield(9)?;
+		for res in [a.and(&b), a.or(&b), a.xor(&b)] {
+			assert!(matches!(
+				res.unwrap_err().kind(),
+				ErrorKind::IllegalArgument(_)
+			));
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_bitfield_ser_errors() -> Result<(), Error> {
+		// 9 bits with a bit set past the end in the second byte
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &9u32)?;
+		v.extend([0xFF, 0x03]);
+		let e = deserialize::<BitField, _>(&mut &v[..]).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::CorruptedData(_)));
+
+		// without the extra bit it is valid
+		let len = v.len();
+		v[len - 1] = 0x01;
+		let field: BitField = deserialize(&mut &v[..])?;
+		assert_eq!(field.count_ones(), 9);
+
+		// too few bytes
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &16u32)?;
+		v.push(0xFF);
+		assert!(deserialize::<BitField, _>(&mut &v[..]).is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn test_min_heap() -> Result<(), Error> {
+		let mut heap = min_heap!(1_000)?;
+		assert_eq!(heap.capacity(), 1_000);
+		assert!(heap.is_empty());
+		assert_eq!(heap.peek(), None);
+		assert_eq!(heap.pop(), None);
+
+		// random input (with duplicates) pops in the same order as a sorted model
+		for _ in 0..5 {
+			let mut model = vec![];
+			for _ in 0..1_000 {
+				let v = random_u32() % 500;
+				heap.push(v)?;
+				model.push(v);
+			}
+			model.sort();
+			assert_eq!(heap.len(), 1_000);
+			assert_eq!(heap.peek(), Some(&model[0]));
+			let mut popped = vec![];
+			while let Some(v) = heap.pop() {
+				popped.push(v);
+			}
+			assert_eq!(popped, model);
+			assert!(heap.is_empty());
+		}
+
+		// capacity exhaustion errors cleanly and the heap is still usable
+		let mut heap = min_heap!(3)?;
+		heap.push(3)?;
+		heap.push(1)?;
+		heap.push(2)?;
+		let e = heap.push(0).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::CapacityExceeded(_)));
+		assert!(heap.push_with_handle(0).is_err());
+		assert_eq!(heap.len(), 3);
+		assert_eq!(heap.pop(), Some(1));
+		heap.push(0)?;
+		assert_eq!(heap.pop(), Some(0));
+		assert_eq!(heap.pop(), Some(2));
+		assert_eq!(heap.pop(), Some(3));
+
+		let heap: Result<MinHeap<u32>, Error> = min_heap!(0);
+		let e = heap.err().unwrap();
+		assert!(matches!(e.kind(), ErrorKind::IllegalArgument(_)));
+		Ok(())
+	}
+
+	#[test]
+	fn test_min_heap_handles() -> Result<(), Error> {
+		let mut heap = min_heap!(100)?;
+		let mut handles = vec![];
+		for i in 0..100u64 {
+			handles.push(heap.push_with_handle((i * 7) % 100)?);
+		}
+
+		// cancelation removes the right element
+		for i in (0..100).step_by(3) {
+			let expected = (i as u64 * 7) % 100;
+			assert_eq!(heap.get(handles[i]), Some(&expected));
+			assert_eq!(heap.remove(handles[i]), Some(expected));
+			assert!(!heap.contains(handles[i]));
+			assert_eq!(heap.remove(handles[i]), None);
+		}
+		assert_eq!(heap.len(), 66);
+
+		// update_key re-orders in both directions
+		heap.update_key(handles[1], 1_000)?;
+		heap.update_key(handles[2], 0)?;
+		assert_eq!(heap.peek(), Some(&0));
+		assert!(heap.update_key(handles[0], 5).is_err());
+
+		let mut model: Vec<u64> = (0..100)
+			.filter(|i| i % 3 != 0 && *i != 1 && *i != 2)
+			.map(|i| (i as u64 * 7) % 100)
+			.collect();
+		model.push(1_000);
+		model.push(0);
+		model.sort();
+		let mut popped = vec![];
+		while let Some(v) = heap.pop() {
+			popped.push(v);
+		}
+		assert_eq!(popped, model);
+
+		// handles of popped values are stale even after their slots are reused
+		for handle in &handles {
+			assert!(!heap.contains(*handle));
+		}
+		let handle = heap.push_with_handle(5)?;
+		assert_eq!(heap.get(handles[0]), None);
+		assert_eq!(heap.remove(handles[0]), None);
+		assert_eq!(heap.len(), 1);
+		assert_eq!(heap.get(handle), Some(&5));
+
+		// clear invalidates the remaining handles
+		heap.push(6)?;
+		heap.clear();
+		assert!(heap.is_empty());
+		assert!(!heap.contains(handle));
+		for i in 0..100 {
+			heap.push(100 - i)?;
+		}
+		assert_eq!(heap.pop(), Some(1));
+		Ok(())
+	}
+
+	// timers keyed by (deadline, id) like the evh and thread pool schedulers use
+	#[test]
+	fn test_min_heap_reschedule_model() -> Result<(), Error> {
+		let mut heap = min_heap!(256)?;
+		let mut model: Vec<(u64, usize)> = vec![];
+		let mut handles = vec![];
+		for id in 0..256 {
+			let entry = (random_u64() % 10_000, id);
+			handles.push(heap.push_with_handle(entry)?);
+			model.push(entry);
+		}
+		for _ in 0..1_000 {
+			let id = random_u32() as usize % 256;
+			let entry = (random_u64() % 10_000, id);
+			heap.update_key(handles[id], entry)?;
+			model[id] = entry;
+			assert_eq!(heap.peek(), model.iter().min());
+		}
+		model.sort();
+		for entry in model {
+			assert_eq!(heap.pop(), Some(entry));
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_string_interner_serialization() -> Result<(), Error> {
+		let mut interner = string_interner!(MaxEntries(20), SlabSize(8), SlabCount(50))?;
+		let record = PeerRecord {
+			addr: interner.intern("10.0.0.1:443")?,
+			headers: vec![
+				interner.intern("host")?,
+				interner.intern("accept")?,
+				interner.intern("host")?,
+			],
+			count: 7,
+		};
+
+		// the interner is serialized alongside the struct so the symbols can be resolved
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &interner)?;
+		serialize(&mut v, &record)?;
+		let mut reader = &v[..];
+		let interner2: StringInterner = deserialize(&mut reader)?;
+		let record2: PeerRecord = deserialize(&mut reader)?;
+
+		assert_eq!(record2, record);
+		assert_eq!(interner2.len(), 3);
+		assert_eq!(interner2.bytes_used(), interner.bytes_used());
+		assert_eq!(interner2.capacity(), interner.capacity());
+		assert_eq!(interner2.resolve(record2.addr)?, "10.0.0.1:443");
+		let headers: Vec<String> = record2
+			.headers
+			.iter()
+			.map(|h| interner2.resolve(*h))
+			.collect::<Result<_, _>>()?;
+		assert_eq!(headers, vec!["host", "accept", "host"]);
+		assert_eq!(interner2.get("accept")?, Some(record.headers[1]));
+		Ok(())
+	}
+
+	#[test]
+	fn test_sharded_hashtable() -> Result<(), Error> {
+		let mut h = sharded_hashtable!(
+			Shards(4),
+			MaxEntries(4_000),
+			GlobalSlabAllocator(false),
+			SlabSize(64),
+			SlabCount(4_000)
+		)?;
+		assert_eq!(h.shards(), 4);
+
+		let h_clone = h.clone();
+		sharded_workload(move |k, v| {
+			let mut h = h_clone.clone();
+			h.insert(&k, &v)?;
+			h.get(&k)
+		})?;
+
+		// nothing was lost and the size is aggregated over all shards
+		assert_eq!(h.size()?, 4_001);
+		for t in 0..8u64 {
+			for i in t * 1_000..t * 1_000 + 500 {
+				assert_eq!(h.get(&i)?, Some(i * 2));
+			}
+		}
+
+		// the keys are spread over all shards
+		let mut sizes = vec![];
+		h.for_each_shard(|shard| {
+			sizes.push(shard.size());
+			Ok(())
+		})?;
+		assert_eq!(sizes.len(), 4);
+		assert_eq!(sizes.iter().sum::<usize>(), 4_001);
+		assert!(sizes.iter().all(|size| *size > 0));
+
+		assert_eq!(h.remove(&0u64)?, Some(0u64));
+		assert_eq!(h.remove(&0u64)?, None);
+		assert_eq!(h.size()?, 4_000);
+
+		h.clear()?;
+		assert_eq!(h.size()?, 0);
+		assert_eq!(h.get(&1u64)?, None);
+
+		// an error from the callback stops the iteration
+		let mut count = 0;
+		assert!(h
+			.for_each_shard(|_| {
+				count += 1;
+				Err(err!(ErrKind::IllegalState, "stop"))
+			})
+			.is_err());
+		assert_eq!(count, 1);
+
+		// the default is one shard per available thread
+		let h: ShardedHashtable<u64, u64> =
+			sharded_hashtable!(GlobalSlabAllocator(false), SlabSize(64), SlabCount(10))?;
+		let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+		assert_eq!(h.shards(), threads);
+
+		// invalid configurations
+		let res: Result<ShardedHashtable<u64, u64>, Error> = sharded_hashtable!(
+			Shards(0),
+			GlobalSlabAllocator(false),
+			SlabSize(64),
+			SlabCount(10)
+		);
+		assert_eq!(
+			res.err().unwrap(),
+			err!(ErrKind::Configuration, "Shards must be greater than 0")
+		);
+		let res: Result<ShardedHashtable<u64, u64>, Error> = sharded_hashtable!(
+			Shards(2),
+			Shards(3),
+			GlobalSlabAllocator(false),
+			SlabSize(64),
+			SlabCount(10)
+		);
+		assert!(res.is_err());
+		let res: Result<ShardedHashtable<u64, u64>, Error> = sharded_hashtable!(Shards(2));
+		assert!(res.is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_sharded_hashtable_contention() -> Result<(), Error> {
+		// the same workload with a sharded table and with a single locked table. This only
+		// checks that both complete with the same result since timings vary between machines.
+		let h = sharded_hashtable!(
+			Shards(8),
+			MaxEntries(4_000),
+			GlobalSlabAllocator(false),
+			SlabSize(64),
+			SlabCount(4_000)
+		)?;
+		let single = UtilBuilder::build_hashtable_sync_box(vec![
+			MaxEntries(8_001),
+			GlobalSlabAllocator(false),
+			SlabSize(64),
+			SlabCount(8_001),
+		])?;
+		let single = lock_box!(single)?;
+
+		let start = std::time::Instant::now();
+		let h_clone = h.clone();
+		sharded_workload(move |k, v| {
+			let mut h = h_clone.clone();
+			h.insert(&k, &v)?;
+			h.get(&k)
+		})?;
+		let sharded_elapsed = start.elapsed();
+
+		let start = std::time::Instant::now();
+		let single_clone = single.clone();
+		sharded_workload(move |k, v| {
+			let mut single = single_clone.clone();
+			let mut single = single.wlock()?;
+			single.guard()?.insert(&k, &v)?;
+			single.guard()?.get(&k)
+		})?;
+		let single_elapsed = start.elapsed();
+
+		info!(
+			"sharded: {:?}, single lock: {:?}",
+			sharded_elapsed, single_elapsed
+		)?;
+
+		assert_eq!(h.size()?, 4_001);
+		assert_eq!(rlock!(single).size(), 4_001);
+		for t in 0..8u64 {
+			for i in t * 1_000..t * 1_000 + 500 {
+				assert_eq!(h.get(&i)?, rlock!(single).get(&i)?);
+			}
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_hashtable_snapshot_iter() -> Result<(), Error> {
+		let hashtable = hashtable_sync_box!(
+			MaxEntries(120_000),
+			SlabSize(64),
+			SlabCount(150_000),
+			GlobalSlabAllocator(false)
+		)?;
+		let mut hashtable: Box<dyn LockBox<Box<dyn Hashtable<u64, String> + Send + Sync>>> =
+			lock_box!(hashtable)?;
+		{
+			let mut hashtable = hashtable.wlock()?;
+			let guard = hashtable.guard()?;
+			for k in 0..100_000u64 {
+				guard.insert(&k, &k.to_string())?;
+			}
+		}
+
+		// the writer inserts and removes other keys with values of varying length so that
+		// the slabs of removed entries are reused for different data
+		let stop = Arc::new(AtomicBool::new(false));
+		let ops = Arc::new(AtomicUsize::new(0));
+		let mut hashtable_clone = hashtable.clone();
+		let stop_clone = stop.clone();
+		let ops_clone = ops.clone();
+		let writer = std::thread::spawn(move || -> Result<(), Error> {
+			let mut i = 0usize;
+			while !stop_clone.load(Ordering::SeqCst) {
+				let k = 1_000_000 + (i % 10_000) as u64;
+				let mut hashtable = hashtable_clone.wlock()?;
+				let guard = hashtable.guard()?;
+				if guard.remove(&k)?.is_none() {
+					guard.insert(&k, &"x".repeat(i % 150))?;
+				}
+				ops_clone.fetch_add(1, Ordering::SeqCst);
+				i += 1;
+			}
+			Ok(())
+		});
+
+		for _ in 0..3 {
+			let snapshot = hashtable.rlock()?.guard()?.snapshot_iter()?;
+			let mut keys = std::collections::HashSet::new();
+			for (k, v) in snapshot {
+				assert!(keys.insert(k), "duplicate key {}", k);
+				if k < 100_000 {
+					assert_eq!(v, k.to_string());
+				} else {
+					assert!(v.chars().all(|c| c == 'x'));
+				}
+			}
+			for k in 0..100_000u64 {
+				assert!(keys.contains(&k), "missing key {}", k);
+			}
+		}
+		stop.store(true, Ordering::SeqCst);
+		writer.join().unwrap()?;
+		info!(
+			"writer ops during snapshot iteration: {}",
+			ops.load(Ordering::SeqCst)
+		)?;
+
+		// entries removed after the snapshot are skipped and entries inserted after it are
+		// not returned
+		let mut hashtable =
+			hashtable_sync!(SlabSize(64), SlabCount(100), GlobalSlabAllocator(false))?;
+		for k in 0..10u64 {
+			hashtable.insert(&k, &k.to_string())?;
+		}
+		let snapshot = hashtable.snapshot_iter()?;
+		assert_eq!(snapshot.size_hint(), (0, Some(10)));
+		hashtable.remove(&3)?;
+		hashtable.insert(&100, &"100".to_string())?;
+		// replacing a value moves it to a new slab
+		hashtable.insert(&5, &"five".to_string())?;
+		let mut entries: Vec<(u64, String)> = snapshot.collect();
+		entries.sort();
+		let expected: Vec<(u64, String)> = [0, 1, 2, 4, 6, 7, 8, 9]
+			.iter()
+			.map(|k: &u64| (*k, k.to_string()))
+			.collect();
+		assert_eq!(entries, expected);
+
+		// the global slab allocator cannot be read without the hashtable
+		let mut hashtable = hashtable!()?;
+		hashtable.insert(&1u64, &1u64)?;
+		let e = hashtable.snapshot_iter().err().unwrap();
+		assert_eq!(
+			e,
+			err!(
+				ErrKind::IllegalState,
+				"snapshot_iter requires a dedicated slab allocator"
+			)
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_sharded_hashtable_snapshot_iter() -> Result<(), Error> {
+		let mut h = sharded_hashtable!(
+			Shards(4),
+			MaxEntries(1_000),
+			GlobalSlabAllocator(false),
+			SlabSize(64),
+			SlabCount(1_000)
+		)?;
+		for k in 0..2_000u64 {
+			h.insert(&k, &(k * 2))?;
+		}
+
+		let mut snapshot = h.snapshot_iter();
+		let (k, _) = snapshot.next().unwrap();
+		// the shards that have not been reached yet can still be written to
+		let mut h2 = h.clone();
+		h2.remove(&k)?;
+		let mut entries: Vec<(u64, u64)> = snapshot.collect();
+		entries.push((k, k * 2));
+		entries.sort();
+		let expected: Vec<(u64, u64)> = (0..2_000u64).map(|k| (k, k * 2)).collect();
+		assert_eq!(entries, expected);
+
+		assert_eq!(h.snapshot_iter().count(), 1_999);
+		Ok(())
+	}
+
+	#[test]
+	fn test_slab_generation() -> Result<(), Error> {
+		let mut slabs = slab_allocator!(SlabSize(64), SlabCount(2))?;
+		let id = slabs.allocate()?.id();
+		assert_eq!(slabs.generation(id)?, 0);
+		slabs.free(id)?;
+		assert_eq!(slabs.generation(id)?, 1);
+		assert_eq!(slabs.allocate()?.id(), id);
+		assert_eq!(slabs.generation(id)?, 1);
+		slabs.free(id)?;
+		assert_eq!(slabs.generation(id)?, 2);
+		assert!(slabs.generation(2).is_err());
+		Ok(())
+	}
+
 	#[test]
 	fn test_sync_hashset() -> Result<(), Error> {
 		let h = UtilBuilder::build_hashset_sync(vec![
@@ -1653,6 +2396,142 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_hashset_bloom() -> Result<(), Error> {
+		let config = vec![
+			IsHashset(true),
+			GlobalSlabAllocator(false),
+			SlabSize(64),
+			SlabCount(200),
+			MaxEntries(200),
+		];
+		let mut plain: HashImpl<u32> = HashImpl::new(config.clone())?;
+		let mut config = config;
+		config.push(BloomBits(10_000));
+		config.push(BloomHashes(3));
+		let mut bloom: HashImpl<u32> = HashImpl::new(config)?;
+
+		for i in 0..100 {
+			Hashset::insert(&mut plain, &i)?;
+			Hashset::insert(&mut bloom, &i)?;
+		}
+		// inserting an existing key is not counted twice
+		Hashset::insert(&mut bloom, &0)?;
+
+		// negative lookups skip the bucket traversal when the filter misses
+		plain.debug_key_reads.set(0);
+		bloom.debug_key_reads.set(0);
+		for i in 1_000..2_000 {
+			assert!(!Hashset::contains(&plain, &i)?);
+			assert!(!Hashset::contains(&bloom, &i)?);
+		}
+		info!(
+			"key reads: plain={}, bloom={}",
+			plain.debug_key_reads.get(),
+			bloom.debug_key_reads.get()
+		)?;
+		assert!(plain.debug_key_reads.get() > 100);
+		assert!(bloom.debug_key_reads.get() < 10);
+
+		for i in 0..100 {
+			assert!(Hashset::contains(&bloom, &i)?);
+		}
+
+		// removes stay exact so removed keys are filtered again
+		for i in 0..50 {
+			assert!(Hashset::remove(&mut bloom, &i)?);
+			assert!(!Hashset::remove(&mut bloom, &i)?);
+		}
+		bloom.debug_key_reads.set(0);
+		for i in 0..50 {
+			assert!(!Hashset::contains(&bloom, &i)?);
+		}
+		assert!(bloom.debug_key_reads.get() < 10);
+		for i in 50..100 {
+			assert!(Hashset::contains(&bloom, &i)?);
+		}
+		assert_eq!(Hashset::<u32>::size(&bloom), 50);
+
+		// clear resets the filter
+		Hashset::clear(&mut bloom)?;
+		bloom.debug_key_reads.set(0);
+		for i in 0..100 {
+			assert!(!Hashset::contains(&bloom, &i)?);
+		}
+		assert_eq!(bloom.debug_key_reads.get(), 0);
+
+		// without a bloom filter there is nothing to rebuild
+		let e = Hashset::rebuild_bloom(&mut plain).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::IllegalState(_)));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_hashset_bloom_saturation_rebuild() -> Result<(), Error> {
+		// a single counter is shared by all keys so it saturates
+		let mut hashset: HashImpl<u32> = HashImpl::new(vec![
+			IsHashset(true),
+			GlobalSlabAllocator(false),
+			SlabSize(64),
+			SlabCount(300),
+			MaxEntries(300),
+			BloomBits(1),
+			BloomHashes(1),
+		])?;
+		for i in 0..300 {
+			Hashset::insert(&mut hashset, &i)?;
+		}
+		assert_eq!(hashset.bloom.as_ref().unwrap()[0], u8::MAX);
+
+		// after removing every key, the saturated counter still lets lookups through. This
+		// only costs a traversal, the result is still correct.
+		for i in 0..300 {
+			assert!(Hashset::remove(&mut hashset, &i)?);
+		}
+		assert_eq!(hashset.bloom.as_ref().unwrap()[0], u8::MAX);
+		hashset.debug_key_reads.set(0);
+		assert!(!Hashset::contains(&hashset, &1)?);
+		assert!(!Hashset::remove(&mut hashset, &1)?);
+
+		// rebuilding from the (empty) contents resets the counter
+		Hashset::insert(&mut hashset, &7)?;
+		Hashset::rebuild_bloom(&mut hashset)?;
+		assert_eq!(hashset.bloom.as_ref().unwrap()[0], 1);
+		assert!(Hashset::contains(&hashset, &7)?);
+		assert!(Hashset::remove(&mut hashset, &7)?);
+		hashset.debug_key_reads.set(0);
+		assert!(!Hashset::contains(&hashset, &7)?);
+		assert_eq!(hashset.debug_key_reads.get(), 0);
+
+		// the sync variant uses the same filter
+		let mut hashset = hashset_sync!(
+			GlobalSlabAllocator(false),
+			SlabSize(64),
+			SlabCount(100),
+			BloomBits(1_000)
+		)?;
+		hashset.insert(&1u64)?;
+		hashset.insert(&2u64)?;
+		assert!(hashset.remove(&1)?);
+		assert!(!hashset.contains(&1)?);
+		assert!(hashset.contains(&2)?);
+		hashset.rebuild_bloom()?;
+		assert!(hashset.contains(&2)?);
+
+		// configuration errors
+		let e = UtilBuilder::build_hashset::<u32>(vec![BloomHashes(3)]).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
+		let e = UtilBuilder::build_hashset::<u32>(vec![BloomBits(0)]).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
+		let e = UtilBuilder::build_hashset::<u32>(vec![BloomBits(10), BloomHashes(0)]).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
+		let e = UtilBuilder::build_hashtable::<u32, u32>(vec![BloomBits(10)]).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_remove_oldest() -> Result<(), Error> {
 		{
@@ -1838,36 +2717,290 @@ mod test {
 		hashtable.bring_to_front(&1)?;
 		Ok(())
 	}
-	#[test]
-	fn test_hashtable_raw() -> Result<(), Error> {
-		let mut hashtable = UtilBuilder::build_hashtable::<u32, u32>(vec![
-			SlabSize(25),
-			SlabCount(1_000),
-			GlobalSlabAllocator(false),
-		])?;
-
-		let mut data2 = [0u8; BUFFER_SIZE];
-		let data = [8u8; BUFFER_SIZE];
-		hashtable.raw_write(&0, 0, &data, BUFFER_SIZE)?;
-		hashtable.raw_read(&0, 0, &mut data2)?;
-		assert_eq!(data, data2);
+	// counts how many times a value is deserialized
+	static COUNTED_READS: AtomicUsize = AtomicUsize::new(0);
 
-		let data = [10u8; BUFFER_SIZE];
-		hashtable.raw_write(&7, 383, &data, BUFFER_SIZE)?;
-		hashtable.raw_read(&7, 383, &mut data2)?;
-		assert_eq!(data, data2);
-		assert!(!hashtable.raw_read(&9, 384, &mut data2)?);
+	#[derive(Clone, Debug, PartialEq)]
+	struct CountedValue(Vec<u8>);
 
-		Ok(())
+	impl Serializable for CountedValue {
+		fn read<R: Reader>(reader: &mut R) -> Result<Self, Error> {
+			COUNTED_READS.fetch_add(1, Ordering::SeqCst);
+			Ok(Self(Vec::read(reader)?))
+		}
+		fn write<W: Writer>(&self, writer: &mut W) -> Result<(), Error> {
+			self.0.write(writer)
+		}
 	}
 
 	#[test]
-	fn test_hashtable_sync_raw() -> Result<(), Error> {
-		let mut hashtable = UtilBuilder::build_hashtable_sync::<u32, u32>(vec![
-			SlabSize(25),
-			SlabCount(1_000),
+	fn test_hashtable_keys_values() -> Result<(), Error> {
+		// the values span several slabs
+		let mut hashtable = hashtable!(
 			GlobalSlabAllocator(false),
-		])?;
+			SlabSize(64),
+			SlabCount(2_000),
+			MaxEntries(100)
+		)?;
+		for i in 0..20u32 {
+			hashtable.insert(&i, &CountedValue(vec![i as u8; 500]))?;
+		}
+
+		COUNTED_READS.store(0, Ordering::SeqCst);
+		let keys: Vec<u32> = hashtable.keys().collect();
+		assert_eq!(COUNTED_READS.load(Ordering::SeqCst), 0);
+		let expected: Vec<u32> = hashtable.iter().map(|(k, _)| k).collect();
+		assert_eq!(keys, expected);
+		assert_eq!(keys, (0..20).rev().collect::<Vec<u32>>());
+
+		let values: Vec<CountedValue> = hashtable.values().collect();
+		assert_eq!(values.len(), 20);
+		for (key, value) in keys.iter().zip(values.iter()) {
+			assert_eq!(value, &CountedValue(vec![*key as u8; 500]));
+		}
+
+		// exact size hints
+		let mut itt = hashtable.keys();
+		assert_eq!(itt.size_hint(), (20, Some(20)));
+		itt.next();
+		assert_eq!(itt.size_hint(), (19, Some(19)));
+		assert_eq!(itt.count(), 19);
+		let mut itt = hashtable.values();
+		itt.next();
+		itt.next();
+		assert_eq!(itt.size_hint(), (18, Some(18)));
+		let mut itt = hashtable.iter();
+		assert_eq!(itt.size_hint(), (20, Some(20)));
+		for _ in 0..20 {
+			itt.next();
+		}
+		assert_eq!(itt.size_hint(), (0, Some(0)));
+		assert!(itt.next().is_none());
+
+		// removals and the sync variant
+		hashtable.remove(&3)?;
+		assert_eq!(hashtable.keys().size_hint(), (19, Some(19)));
+		assert_eq!(hashtable.keys().count(), 19);
+		assert!(!hashtable.keys().any(|k| k == 3));
+
+		let mut hashtable =
+			hashtable_sync!(GlobalSlabAllocator(false), SlabSize(100), SlabCount(100))?;
+		hashtable.insert(&"a".to_string(), &1u64)?;
+		hashtable.insert(&"b".to_string(), &2u64)?;
+		assert_eq!(hashtable.keys().collect::<Vec<String>>(), vec!["b", "a"]);
+		assert_eq!(hashtable.values().collect::<Vec<u64>>(), vec![2, 1]);
+
+		let mut hashset = hashset!()?;
+		hashset.insert(&1u32)?;
+		hashset.insert(&2u32)?;
+		assert_eq!(hashset.keys().size_hint(), (2, Some(2)));
+		assert_eq!(
+			hashset.keys().collect::<Vec<u32>>(),
+			hashset.iter().collect::<Vec<u32>>()
+		);
+
+		let mut list = list![1u32, 2, 3];
+		assert_eq!(list.iter().size_hint(), (3, Some(3)));
+		list.delete_head()?;
+		let mut itt = list.iter_rev();
+		itt.next();
+		assert_eq!(itt.size_hint(), (1, Some(1)));
+
+		let mut array_list = array_list!(10, &0u32)?;
+		for i in 0..4 {
+			array_list.push(i)?;
+		}
+		let mut itt = array_list.iter();
+		itt.next();
+		assert_eq!(itt.size_hint(), (3, Some(3)));
+		assert_eq!(itt.count(), 3);
+		assert_eq!(
+			array_list.iter_rev().size_hint().0,
+			array_list.iter_rev().count()
+		);
+
+		let array = array!(5, &0u8)?;
+		let mut itt = array.iter();
+		itt.next();
+		assert_eq!(itt.size_hint(), (4, Some(4)));
+		assert_eq!(itt.count(), 4);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_hashtable_deterministic_hashing() -> Result<(), Error> {
+		let build = |configs: Vec<ConfigOption>| -> Result<HashImpl<u32>, Error> {
+			let mut configs = configs;
+			configs.extend(vec![
+				IsHashtable(true),
+				GlobalSlabAllocator(false),
+				SlabSize(64),
+				SlabCount(1_000),
+				MaxEntries(200),
+			]);
+			HashImpl::new(configs)
+		};
+		let populate = |hashtable: &mut HashImpl<u32>| -> Result<(), Error> {
+			for i in 0..100u32 {
+				Hashtable::insert(hashtable, &(i * 7919), &format!("value{}", i))?;
+			}
+			for i in (0..100u32).step_by(3) {
+				Hashtable::<u32, String>::remove(hashtable, &(i * 7919))?;
+			}
+			Ok(())
+		};
+		let slots = |hashtable: &HashImpl<u32>| -> Vec<usize> {
+			let entry_array = hashtable.entry_array.as_ref().unwrap();
+			(0..entry_array.size()).map(|i| entry_array[i]).collect()
+		};
+
+		let mut first = build(vec![DeterministicHashing(true)])?;
+		populate(&mut first)?;
+		let first_entries: Vec<(u32, String)> = Hashtable::iter(&first).collect();
+		let mut first_bytes: Vec<u8> = vec![];
+		{
+			let mut boxed: Box<dyn Hashtable<u32, String>> =
+				UtilBuilder::build_hashtable_box(vec![DeterministicHashing(true)])?;
+			for (k, v) in first_entries.iter().rev() {
+				boxed.insert(k, v)?;
+			}
+			serialize(&mut first_bytes, &boxed)?;
+		}
+
+		for _ in 0..10 {
+			let mut hashtable = build(vec![DeterministicHashing(true)])?;
+			populate(&mut hashtable)?;
+			assert_eq!(hashtable.hash_seed, DETERMINISTIC_HASH_SEED);
+			// the same slots are used and the iteration order is the same
+			assert_eq!(slots(&hashtable), slots(&first));
+			let entries: Vec<(u32, String)> = Hashtable::iter(&hashtable).collect();
+			assert_eq!(entries, first_entries);
+
+			let mut boxed: Box<dyn Hashtable<u32, String>> =
+				UtilBuilder::build_hashtable_box(vec![DeterministicHashing(true)])?;
+			for (k, v) in entries.iter().rev() {
+				boxed.insert(k, v)?;
+			}
+			let mut bytes: Vec<u8> = vec![];
+			serialize(&mut bytes, &boxed)?;
+			assert_eq!(bytes, first_bytes);
+		}
+
+		// by default, each hashtable has its own seed
+		let a = build(vec![])?;
+		let b = build(vec![])?;
+		assert_ne!(a.hash_seed, b.hash_seed);
+		assert_ne!(a.hash_key(&1u32), b.hash_key(&1u32));
+
+		// lists don't hash
+		assert!(HashImpl::<u32>::new(vec![IsList(true), DeterministicHashing(true)]).is_err());
+		let mut hashset = hashset!(DeterministicHashing(true))?;
+		hashset.insert(&1u32)?;
+		assert!(hashset.contains(&1u32)?);
+
+		Ok(())
+	}
+
+	// hashes are truncated to usize, so the slots are pinned for 64 bit targets
+	#[test]
+	#[cfg(target_pointer_width = "64")]
+	fn test_hashtable_deterministic_slots() -> Result<(), Error> {
+		// the slots must not change between Rust releases. String keys are used because the
+		// Hash implementations of integers depend on the byte order of the target.
+		let mut hashtable: HashImpl<String> = HashImpl::new(vec![
+			IsHashtable(true),
+			DeterministicHashing(true),
+			GlobalSlabAllocator(false),
+			SlabSize(64),
+			SlabCount(100),
+			MaxEntries(70),
+		])?;
+		assert_eq!(hashtable.hash_seed, DETERMINISTIC_HASH_SEED);
+		let expected = [
+			("alpha", 14226843403819690225u64, 25),
+			("bravo", 12724396223021240143, 43),
+			("charlie", 16759143621152152180, 80),
+			("delta", 5396972763982597391, 91),
+			("echo", 17572917040800709890, 90),
+		];
+		let len = hashtable.entry_array.as_ref().unwrap().size();
+		assert_eq!(len, 100);
+		for (key, hash, slot) in expected {
+			assert_eq!(hashtable.hash_key(key) as u64, hash);
+			assert_eq!(hashtable.hash_key(key) % len, slot);
+			Hashtable::insert(&mut hashtable, &key.to_string(), &0u8)?;
+		}
+		// none of the keys collide so each one is in the slot it hashes to
+		let entry_array = hashtable.entry_array.as_ref().unwrap();
+		let used: Vec<usize> = (0..len).filter(|i| entry_array[*i] != usize::MAX).collect();
+		assert_eq!(used, vec![25, 43, 80, 90, 91]);
+		Ok(())
+	}
+
+	#[test]
+	fn test_hashtable_iter_ordered_by_key() -> Result<(), Error> {
+		let mut a = hashtable!()?;
+		let mut b = hashtable_sync!(GlobalSlabAllocator(false), SlabSize(100), SlabCount(100))?;
+		let keys = [5u64, 1, 9, 3, 7];
+		for key in keys {
+			a.insert(&key, &(key * 10))?;
+		}
+		for key in keys.iter().rev() {
+			b.insert(key, &(key * 10))?;
+		}
+		assert_ne!(
+			a.iter().collect::<Vec<(u64, u64)>>(),
+			b.iter().collect::<Vec<(u64, u64)>>()
+		);
+
+		let expected = vec![(1, 10), (3, 30), (5, 50), (7, 70), (9, 90)];
+		assert_eq!(
+			a.iter_ordered_by_key().collect::<Vec<(u64, u64)>>(),
+			expected
+		);
+		assert_eq!(
+			b.iter_ordered_by_key().collect::<Vec<(u64, u64)>>(),
+			expected
+		);
+
+		a.remove(&5)?;
+		assert_eq!(a.iter_ordered_by_key().len(), 4);
+		a.clear()?;
+		assert_eq!(a.iter_ordered_by_key().next(), None);
+		Ok(())
+	}
+
+	#[test]
+	fn test_hashtable_raw() -> Result<(), Error> {
+		let mut hashtable = UtilBuilder::build_hashtable::<u32, u32>(vec![
+			SlabSize(25),
+			SlabCount(1_000),
+			GlobalSlabAllocator(false),
+		])?;
+
+		let mut data2 = [0u8; BUFFER_SIZE];
+		let data = [8u8; BUFFER_SIZE];
+		hashtable.raw_write(&0, 0, &data, BUFFER_SIZE)?;
+		hashtable.raw_read(&0, 0, &mut data2)?;
+		assert_eq!(data, data2);
+
+		let data = [10u8; BUFFER_SIZE];
+		hashtable.raw_write(&7, 383, &data, BUFFER_SIZE)?;
+		hashtable.raw_read(&7, 383, &mut data2)?;
+		assert_eq!(data, data2);
+		assert!(!hashtable.raw_read(&9, 384, &mut data2)?);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_hashtable_sync_raw() -> Result<(), Error> {
+		let mut hashtable = UtilBuilder::build_hashtable_sync::<u32, u32>(vec![
+			SlabSize(25),
+			SlabCount(1_000),
+			GlobalSlabAllocator(false),
+		])?;
 
 		let mut data2 = [0u8; BUFFER_SIZE];
 		let data = [8u8; BUFFER_SIZE];
@@ -2179,6 +3312,51 @@ mod test {
 		Ok(())
 	}
 
+	// acquire `first` and then `second` on a new thread
+	fn lock_in_order(
+		mut first: Box<dyn LockBox<u32>>,
+		mut second: Box<dyn LockBox<u32>>,
+	) -> Result<(), Error> {
+		spawn(move || -> Result<(), Error> {
+			let _first = first.wlock()?;
+			let _second = second.wlock()?;
+			Ok(())
+		})
+		.join()
+		.unwrap()
+	}
+
+	#[cfg(debug_assertions)]
+	#[test]
+	fn test_lock_order() -> Result<(), Error> {
+		set_lock_order_mode(LockOrderMode::Log);
+
+		// A then B and B then A on two threads is a cycle even though the threads never
+		// run at the same time
+		let a = lock_box!(0u32)?;
+		let b = lock_box!(0u32)?;
+		lock_in_order(a.clone(), b.clone())?;
+		lock_in_order(b.clone(), a.clone())?;
+		let report = lock_order_report();
+		assert!(report.edges.contains(&(a.id(), b.id())));
+		assert!(report.edges.contains(&(b.id(), a.id())));
+		assert!(report.cycles.contains(&vec![b.id(), a.id(), b.id()]));
+
+		// the same order on both threads is not
+		let c = lock_box!(0u32)?;
+		let d = lock_box!(0u32)?;
+		lock_in_order(c.clone(), d.clone())?;
+		lock_in_order(c.clone(), d.clone())?;
+		let report = lock_order_report();
+		assert!(report.edges.contains(&(c.id(), d.id())));
+		assert!(!report.edges.contains(&(d.id(), c.id())));
+		for cycle in report.cycles {
+			assert!(!cycle.contains(&c.id()) && !cycle.contains(&d.id()));
+		}
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_lock_macro() -> Result<(), Error> {
 		let mut lock = lock!(1)?;
@@ -2446,6 +3624,269 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_slab_allocator_compact() -> Result<(), Error> {
+		let mut slabs = slab_allocator!(SlabSize(16), SlabCount(10))?;
+		for i in 0..10 {
+			let mut slab = slabs.allocate()?;
+			assert_eq!(slab.id(), i);
+			slab.get_mut()[0] = i as u8;
+		}
+		// leave slabs 0, 3, 7, 9 allocated
+		for i in [1, 2, 4, 5, 6, 8] {
+			slabs.free(i)?;
+		}
+		assert_eq!(slabs.high_water_mark()?, 10);
+
+		// only move one slab
+		let mut moved = vec![];
+		let count = slabs.compact(1, &mut |old, new| {
+			moved.push((old, new));
+			Ok(())
+		})?;
+		assert_eq!(count, 1);
+		assert_eq!(moved, vec![(9, 1)]);
+		assert_eq!(slabs.high_water_mark()?, 8);
+
+		// finish the compaction
+		let mut moved = vec![];
+		let count = slabs.compact(usize::MAX, &mut |old, new| {
+			moved.push((old, new));
+			Ok(())
+		})?;
+		assert_eq!(count, 1);
+		assert_eq!(moved, vec![(7, 2)]);
+		assert_eq!(slabs.high_water_mark()?, 4);
+		assert_eq!(slabs.compact(usize::MAX, &mut |_, _| Ok(()))?, 0);
+
+		// data moved with the slabs
+		assert_eq!(slabs.get(0)?.get()[0], 0);
+		assert_eq!(slabs.get(1)?.get()[0], 9);
+		assert_eq!(slabs.get(2)?.get()[0], 7);
+		assert_eq!(slabs.get(3)?.get()[0], 3);
+
+		// the free list is intact and allocates from the low end
+		assert_eq!(slabs.free_count()?, 6);
+		assert_eq!(slabs.allocate()?.id(), 4);
+		slabs.free(1)?;
+		assert!(slabs.free(1).is_err());
+		assert_eq!(slabs.allocate()?.id(), 1);
+		for _ in 0..5 {
+			slabs.allocate()?;
+		}
+		assert!(slabs.allocate().is_err());
+
+		let mut slabs = UtilBuilder::build_slabs();
+		assert!(slabs.compact(1, &mut |_, _| Ok(())).is_err());
+		assert!(slabs.high_water_mark().is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn test_slab_allocator_contiguous() -> Result<(), Error> {
+		let mut slabs = slab_allocator(16, 10)?;
+		let range = {
+			let mut slabs = slabs.wlock()?;
+			let guard = slabs.guard()?;
+			for i in 0..10 {
+				assert_eq!((**guard).allocate()?.id(), i);
+			}
+			// fragment the arena so no two free slabs are adjacent
+			for i in [1, 3, 5, 7, 9] {
+				(**guard).free(i)?;
+			}
+			let e = (**guard).allocate_contiguous(2).unwrap_err();
+			assert!(matches!(e.kind(), ErrorKind::Fragmentation(_)));
+			let e = (**guard).allocate_contiguous(6).unwrap_err();
+			assert!(matches!(e.kind(), ErrorKind::CapacityExceeded(_)));
+			let e = (**guard).allocate_contiguous(0).unwrap_err();
+			assert!(matches!(e.kind(), ErrorKind::IllegalArgument(_)));
+			// nothing was allocated by the failed calls
+			assert_eq!((**guard).free_count()?, 5);
+
+			// compaction moves slabs 9 -> 1 and 7 -> 3 leaving 5..9 free
+			assert_eq!((**guard).compact(usize::MAX, &mut |_, _| Ok(()))?, 2);
+			let range = (**guard).allocate_contiguous(4)?;
+			assert_eq!(range.start(), 5);
+			assert_eq!(range.count(), 4);
+			assert_eq!((**guard).free_count()?, 1);
+			// the remaining free slab is still in the free list
+			assert_eq!((**guard).allocate()?.id(), 9);
+			(**guard).free(9)?;
+			range
+		};
+
+		// the range is written and read as one region with no next pointers
+		let mut writer = SlabWriter::from_range(Some(slabs.clone()), range)?;
+		let data: Vec<u8> = (0..64).collect();
+		writer.write_fixed_bytes(&data)?;
+		assert!(writer.write_u8(0).is_err());
+		let mut reader = SlabReader::from_range(Some(slabs.clone()), range)?;
+		let mut buf = [0u8; 64];
+		reader.read_fixed_bytes(&mut buf)?;
+		assert_eq!(&buf[..], &data[..]);
+		assert!(reader.read_u8().is_err());
+		{
+			let slabs = slabs.rlock()?;
+			let guard = slabs.guard()?;
+			assert_eq!((**guard).get(6)?.get(), &data[16..32]);
+		}
+
+		// freeing the range returns all of its slabs
+		let mut slabs = slabs.wlock()?;
+		let guard = slabs.guard()?;
+		(**guard).free_range(range)?;
+		assert_eq!((**guard).free_count()?, 5);
+		assert!((**guard).free_range(range).is_err());
+		assert_eq!((**guard).free_count()?, 5);
+		assert_eq!((**guard).allocate_contiguous(5)?.start(), 5);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_hashtable_contiguous() -> Result<(), Error> {
+		let mut hashtable = hashtable!(
+			SlabSize(64),
+			SlabCount(40),
+			GlobalSlabAllocator(false),
+			MaxEntries(10),
+			ContiguousThresholdBytes(100)
+		)?;
+		let slabs = hashtable.slabs()?.unwrap();
+		// leave free slabs 0, 2 and 4 before the first run of 10 free slabs
+		let held: Vec<usize> = {
+			let mut slabs = slabs.clone();
+			let mut slabs = slabs.wlock()?;
+			let guard = slabs.guard()?;
+			let mut held = vec![];
+			for _ in 0..6 {
+				held.push((**guard).allocate()?.id());
+			}
+			for i in [0, 2, 4] {
+				(**guard).free(i)?;
+			}
+			held.into_iter().filter(|i| i % 2 == 1).collect()
+		};
+		assert_eq!(held, vec![1, 3, 5]);
+
+		let value = "x".repeat(600);
+		hashtable.insert(&1u32, &value)?;
+		{
+			let slabs = slabs.rlock()?;
+			let guard = slabs.guard()?;
+			// the value took 10 adjacent slabs starting after slab 5
+			assert_eq!((**guard).free_count()?, 40 - 3 - 10);
+			assert_eq!((**guard).high_water_mark()?, 16);
+		}
+		assert_eq!(hashtable.get(&1u32)?, Some(value.clone()));
+
+		// small values still use a single slab
+		hashtable.insert(&2u32, &"small".to_string())?;
+		assert_eq!(hashtable.get(&2u32)?, Some("small".to_string()));
+
+		// when no run is long enough the value is chained
+		{
+			let mut slabs = slabs.clone();
+			let mut slabs = slabs.wlock()?;
+			let guard = slabs.guard()?;
+			// free runs are now 2, 4, 16..19, 21..29 and 31..39
+			for _ in 0..26 {
+				(**guard).allocate()?;
+			}
+			for i in [2, 4].into_iter().chain(16..40) {
+				if i != 20 && i != 30 {
+					(**guard).free(i)?;
+				}
+			}
+			assert_eq!((**guard).free_count()?, 24);
+		}
+		let value2 = "y".repeat(600);
+		hashtable.insert(&3u32, &value2)?;
+		assert_eq!(hashtable.get(&3u32)?, Some(value2));
+
+		hashtable.remove(&1u32)?;
+		hashtable.remove(&2u32)?;
+		hashtable.remove(&3u32)?;
+		{
+			let slabs = slabs.rlock()?;
+			let guard = slabs.guard()?;
+			assert_eq!((**guard).free_count()?, 40 - 5);
+		}
+
+		let res = UtilBuilder::build_hashset::<u32>(vec![ContiguousThresholdBytes(100)]);
+		assert!(matches!(
+			res.err().unwrap().kind(),
+			ErrorKind::Configuration(_)
+		));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_hashtable_compact() -> Result<(), Error> {
+		let mut hashtable = hashtable!(
+			SlabSize(64),
+			SlabCount(20_000),
+			GlobalSlabAllocator(false),
+			MaxEntries(5_000)
+		)?;
+		let mut check = HashMap::new();
+
+		// churn with values that span multiple slabs
+		for i in 0..4_000u32 {
+			let value = format!("{}-{}", i, "x".repeat((i % 200) as usize));
+			hashtable.insert(&i, &value)?;
+			check.insert(i, value);
+			if i % 3 != 0 {
+				let rem = (i * 7) % (i + 1);
+				if hashtable.remove(&rem)?.is_some() {
+					check.remove(&rem);
+				}
+			}
+		}
+		for i in 0..4_000u32 {
+			if i % 5 != 0 && hashtable.remove(&i)?.is_some() {
+				check.remove(&i);
+			}
+		}
+		assert_eq!(hashtable.size(), check.len());
+
+		let slabs = hashtable.slabs()?.unwrap();
+		let high_water_mark = rlock!(slabs).high_water_mark()?;
+
+		// compact in steps and verify all entries are intact after each step
+		let mut total = 0;
+		loop {
+			let moves = hashtable.compact(100)?;
+			cbreak!(moves == 0);
+			assert!(moves <= 100);
+			total += moves;
+			for (k, v) in &check {
+				assert_eq!(hashtable.get(k)?.as_ref(), Some(v));
+			}
+		}
+		assert!(total > 0);
+		assert!(rlock!(slabs).high_water_mark()? < high_water_mark);
+
+		// iteration and further updates still work
+		assert_eq!(hashtable.iter().count(), check.len());
+		for (k, v) in hashtable.iter() {
+			assert_eq!(check.get(&k), Some(&v));
+		}
+		for (k, _) in check.clone() {
+			assert!(hashtable.remove(&k)?.is_some());
+		}
+		assert_eq!(hashtable.size(), 0);
+		assert_eq!(rlock!(slabs).high_water_mark()?, 0);
+
+		// not allowed with the global slab allocator
+		let mut hashtable = hashtable!()?;
+		hashtable.insert(&1, &2)?;
+		assert!(hashtable.compact(100).is_err());
+		Ok(())
+	}
+
 	#[test]
 	fn test_slab_allocator_macro() -> Result<(), bmw_err::Error> {
 		let mut slabs = slab_allocator!()?;
@@ -2467,6 +3908,153 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_slab_allocator_overflow() -> Result<(), Error> {
+		// slab_count * (slab_size + ptr_size) overflows
+		let count = usize::MAX / 64;
+		let res = slab_allocator!(SlabSize(128), SlabCount(count));
+		let text = res.err().unwrap().to_string();
+		assert!(text.contains(&count.to_string()));
+		assert!(text.contains("136"));
+
+		// the addition overflows
+		let res = slab_allocator!(SlabSize(usize::MAX), SlabCount(1));
+		let text = res.err().unwrap().to_string();
+		assert!(text.contains(&format!("{} + 8", usize::MAX)));
+
+		// an entry array that can't be represented is a configuration error
+		let hashtable = UtilBuilder::build_hashtable::<u32, u32>(vec![
+			MaxEntries(usize::MAX),
+			MaxLoadFactor(0.5),
+		]);
+		let e = hashtable.err().unwrap();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
+
+		// normal configurations are unaffected
+		let mut slabs = slab_allocator!(SlabSize(128), SlabCount(10))?;
+		assert_eq!(slabs.allocate()?.get().len(), 128);
+		let mut hashtable = hashtable!(MaxEntries(100), MaxLoadFactor(0.9))?;
+		hashtable.insert(&1, &2)?;
+		assert_eq!(hashtable.get(&1)?, Some(2));
+		Ok(())
+	}
+
+	// random bytes from a small alphabet so that needles and delimiters occur often
+	fn random_alphabet_bytes(max_len: usize, alphabet: &[u8]) -> Vec<u8> {
+		let len = random::<usize>() % (max_len + 1);
+		(0..len)
+			.map(|_| alphabet[random::<usize>() % alphabet.len()])
+			.collect()
+	}
+
+	#[test]
+	fn test_find_subsequence() -> Result<(), Error> {
+		assert_eq!(find_subsequence(b"hello world", b"hello"), Some(0));
+		assert_eq!(find_subsequence(b"hello world", b"world"), Some(6));
+		assert_eq!(find_subsequence(b"hello world", b"o w"), Some(4));
+		assert_eq!(find_subsequence(b"hello world", b"worlds"), None);
+		assert_eq!(find_subsequence(b"hello world", b"x"), None);
+		assert_eq!(find_subsequence(b"abc", b"abcd"), None);
+		assert_eq!(find_subsequence(b"aaab", b"aab"), Some(1));
+		// empty needles are found at the start
+		assert_eq!(find_subsequence(b"abc", b""), Some(0));
+		assert_eq!(find_subsequence(b"", b""), Some(0));
+		assert_eq!(find_subsequence(b"", b"a"), None);
+
+		let mut large = vec![b'x'; 100_000];
+		large.extend(b"\r\n\r\n");
+		assert_eq!(find_subsequence(&large, b"\r\n\r\n"), Some(100_000));
+
+		for _ in 0..10_000 {
+			let haystack = random_alphabet_bytes(64, b"abc");
+			let needle = random_alphabet_bytes(4, b"abc");
+			let expected = if needle.is_empty() {
+				Some(0)
+			} else {
+				haystack
+					.windows(needle.len())
+					.position(|w| w == &needle[..])
+			};
+			assert_eq!(find_subsequence(&haystack, &needle), expected);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_split_on() -> Result<(), Error> {
+		let split = |h: &'static [u8], d: &'static [u8]| split_on(h, d).collect::<Vec<&[u8]>>();
+		assert_eq!(split(b"a,b,c", b","), vec![&b"a"[..], b"b", b"c"]);
+		assert_eq!(split(b",a,", b","), vec![&b""[..], b"a", b""]);
+		assert_eq!(split(b"a,,b", b","), vec![&b"a"[..], b"", b"b"]);
+		assert_eq!(split(b"abc", b"x"), vec![&b"abc"[..]]);
+		assert_eq!(split(b"", b","), vec![&b""[..]]);
+		assert_eq!(split(b"abc", b""), vec![&b"abc"[..]]);
+		assert_eq!(split(b"a\r\nb\r\n", b"\r\n"), vec![&b"a"[..], b"b", b""]);
+		// overlapping occurrences are matched from the left
+		assert_eq!(split(b"aaa", b"aa"), vec![&b""[..], b"a"]);
+		assert_eq!(split(b"aaaa", b"aa"), vec![&b""[..], b"", b""]);
+		assert_eq!(split(b"xabababx", b"aba"), vec![&b"x"[..], b"babx"]);
+
+		// the subslices borrow from the haystack
+		let haystack = b"key=value".to_vec();
+		let parts: Vec<&[u8]> = split_on(&haystack, b"=").collect();
+		assert_eq!(parts[1].as_ptr(), haystack[4..].as_ptr());
+
+		for _ in 0..10_000 {
+			let haystack = String::from_utf8(random_alphabet_bytes(64, b"abc")).unwrap();
+			let delim = String::from_utf8(random_alphabet_bytes(3, b"abc")).unwrap();
+			let expected: Vec<&[u8]> = if delim.is_empty() {
+				vec![haystack.as_bytes()]
+			} else {
+				haystack
+					.split(delim.as_str())
+					.map(|s| s.as_bytes())
+					.collect()
+			};
+			let actual: Vec<&[u8]> = split_on(haystack.as_bytes(), delim.as_bytes()).collect();
+			assert_eq!(actual, expected);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_constant_time_eq() -> Result<(), Error> {
+		assert!(constant_time_eq(b"secret-token", b"secret-token"));
+		assert!(!constant_time_eq(b"secret-token", b"secret-tokeN"));
+		assert!(!constant_time_eq(b"secret-token", b"Secret-token"));
+		// unequal lengths, including a prefix
+		assert!(!constant_time_eq(b"secret", b"secret-token"));
+		assert!(!constant_time_eq(b"secret-token", b""));
+		assert!(constant_time_eq(b"", b""));
+
+		for _ in 0..10_000 {
+			let a = random_alphabet_bytes(8, &[0, 1, 0xFF]);
+			let b = random_alphabet_bytes(8, &[0, 1, 0xFF]);
+			assert_eq!(constant_time_eq(&a, &b), a == b);
+			assert!(constant_time_eq(&a, &a.clone()));
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_checked_arithmetic() -> Result<(), Error> {
+		assert_eq!(checked_mul_usize(6, 7)?, 42);
+		assert_eq!(checked_add_usize(6, 7)?, 13);
+		let e = checked_mul_usize(usize::MAX, 2).err().unwrap();
+		assert!(matches!(e.kind(), ErrorKind::Overflow(_)));
+		assert!(e.to_string().contains(&format!("{} * 2", usize::MAX)));
+		let e = checked_add_usize(1, usize::MAX).err().unwrap();
+		assert!(e.to_string().contains(&format!("1 + {}", usize::MAX)));
+
+		let x: u64 = 100;
+		assert_eq!(usize_from!(x)?, 100);
+		let y: i64 = -5;
+		let e = usize_from!(y).err().unwrap();
+		assert!(matches!(e.kind(), ErrorKind::Overflow(_)));
+		assert!(e.to_string().contains("y (-5)"));
+		Ok(())
+	}
+
 	#[test]
 	fn test_hashtable_macro() -> Result<(), bmw_err::Error> {
 		let mut hashtable = hashtable!()?;
@@ -3142,23 +4730,158 @@ mod test {
 		Ok(())
 	}
 
-	#[test]
-	fn test_serialization_slab_rw() -> Result<(), Error> {
-		let ser_out = SerAll {
-			a: rand::random(),
-			b: rand::random(),
-			c: rand::random(),
-			d: rand::random(),
-			e: rand::random(),
-			f: rand::random(),
-			g: rand::random(),
-			h: rand::random(),
-			i: rand::random(),
-			j: rand::random(),
-			k: rand::random(),
-		};
+	// writes data spanning several slabs with the writer and reads it back with the reader,
+	// which is built once the data has been written
+	fn check_slab_writer_reader(
+		mut slab_writer: SlabWriter,
+		slab_reader: impl FnOnce() -> Result<SlabReader, Error>,
+	) -> Result<(), Error> {
+		let value: Vec<String> = (0..20).map(|i| format!("value{}", i)).collect();
+		value.write(&mut slab_writer)?;
+		slab_writer.write_u128(123)?;
+		let mut slab_reader = slab_reader()?;
+		assert_eq!(Vec::<String>::read(&mut slab_reader)?, value);
+		assert_eq!(slab_reader.read_u128()?, 123);
+		Ok(())
+	}
 
-		ser_helper_slabs(ser_out)?;
+	#[test]
+	fn test_builder_slab_writer_reader() -> Result<(), Error> {
+		let mut slabs = slab_allocator(64, 100)?;
+		let mut allocate = || -> Result<usize, Error> {
+			let mut slabs = slabs.wlock()?;
+			let guard = slabs.guard()?;
+			let mut slab = (**guard).allocate()?;
+			// set the next pointer to max so that the writer allocates new slabs
+			slab.get_mut().fill(0xFF);
+			Ok(slab.id())
+		};
+		let slab_id1 = allocate()?;
+		let slab_id2 = allocate()?;
+		let slab_id3 = allocate()?;
+
+		// the builder and the constructors are interchangeable
+		check_slab_writer_reader(
+			UtilBuilder::build_slab_writer(Some(slabs.clone()), slab_id1, None)?,
+			|| UtilBuilder::build_slab_reader(Some(slabs.clone()), slab_id1, None),
+		)?;
+		check_slab_writer_reader(
+			UtilBuilder::build_slab_writer(Some(slabs.clone()), slab_id2, Some(2))?,
+			|| SlabReader::new(Some(slabs.clone()), slab_id2, Some(2)),
+		)?;
+		check_slab_writer_reader(
+			SlabWriter::new(Some(slabs.clone()), slab_id3, None)?,
+			|| UtilBuilder::build_slab_reader(Some(slabs.clone()), slab_id3, None),
+		)?;
+
+		// the global slab allocator is used if no allocator is specified
+		let slab_id = GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<usize, Error> {
+			let slabs = unsafe { f.get().as_mut().unwrap() };
+			if !slabs.is_init() {
+				slabs.init(SlabAllocatorConfig::default())?;
+			}
+			let mut slab = slabs.allocate()?;
+			slab.get_mut().fill(0xFF);
+			Ok(slab.id())
+		})?;
+		check_slab_writer_reader(UtilBuilder::build_slab_writer(None, slab_id, None)?, || {
+			UtilBuilder::build_slab_reader(None, slab_id, None)
+		})?;
+		Ok(())
+	}
+
+	#[test]
+	fn test_slab_serialization_byte_order() -> Result<(), Error> {
+		let mut slabs = slab_allocator(1024, 10)?;
+		let slab_id = {
+			let mut slabs = slabs.wlock()?;
+			let guard = slabs.guard()?;
+			let slab = (**guard).allocate()?;
+			slab.id()
+		};
+
+		// the bytes stored in the slabs are the big-endian bytes written by serialize
+		let value = (0x0102030405060708u64, ("key".to_string(), -2i32));
+		let mut slab_writer = SlabWriter::new(Some(slabs.clone()), slab_id, None)?;
+		value.write(&mut slab_writer)?;
+
+		let mut expected = vec![];
+		serialize(&mut expected, &value)?;
+		assert_eq!(&expected[0..8], &[1, 2, 3, 4, 5, 6, 7, 8]);
+		{
+			let slabs = slabs.rlock()?;
+			let guard = slabs.guard()?;
+			let slab = (**guard).get(slab_id)?;
+			assert_eq!(&slab.get()[0..expected.len()], &expected[..]);
+		}
+
+		let mut slab_reader = SlabReader::new(Some(slabs), slab_id, None)?;
+		assert_eq!(<(u64, (String, i32))>::read(&mut slab_reader)?, value);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_slab_reader_read_bytes_ref() -> Result<(), Error> {
+		// the global slab allocator is initialized by the SlabWriter
+		let mut slab_writer = SlabWriter::new(None, 0, None)?;
+		let slab_id = GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<usize, Error> {
+			let mut slab = unsafe { f.get().as_mut().unwrap().allocate()? };
+			// set the next pointer to max so that the writer allocates new slabs
+			slab.get_mut().fill(0xFF);
+			Ok(slab.id())
+		})?;
+		let data: Vec<u8> = (0..1_000).map(|i| (i % 256) as u8).collect();
+		slab_writer.seek(slab_id, 0);
+		slab_writer.write_fixed_bytes(&data)?;
+
+		// within the first slab, the slice is returned without copying
+		let mut slab_reader = SlabReader::new(None, slab_id, None)?;
+		assert_eq!(slab_reader.read_bytes_ref(10)?, &data[0..10]);
+		assert_eq!(slab_reader.read_bytes_ref(20)?, &data[10..30]);
+
+		// a range that spans two slabs is an error and the reader does not move
+		let e = slab_reader.read_bytes_ref(500).unwrap_err().kind();
+		assert!(matches!(e, ErrorKind::OperationNotSupported(_)));
+		let mut buf = [0u8; 500];
+		slab_reader.read_fixed_bytes(&mut buf)?;
+		assert_eq!(&buf[..], &data[30..530]);
+
+		// after reading to the end of a slab, the next read_bytes_ref starts in the next one
+		let mut slab_reader = SlabReader::new(None, slab_id, None)?;
+		let bytes_per_slab = slab_reader.bytes_per_slab;
+		slab_reader.skip_bytes(bytes_per_slab)?;
+		assert_eq!(
+			slab_reader.read_bytes_ref(10)?,
+			&data[bytes_per_slab..bytes_per_slab + 10]
+		);
+
+		// slab allocators other than the global one are not supported
+		let slabs = slab_allocator(1024, 10)?;
+		let mut slab_reader = SlabReader::new(Some(slabs), 0, None)?;
+		let e = slab_reader.read_bytes_ref(1).unwrap_err().kind();
+		assert!(matches!(e, ErrorKind::OperationNotSupported(_)));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_serialization_slab_rw() -> Result<(), Error> {
+		let ser_out = SerAll {
+			a: rand::random(),
+			b: rand::random(),
+			c: rand::random(),
+			d: rand::random(),
+			e: rand::random(),
+			f: rand::random(),
+			g: rand::random(),
+			h: rand::random(),
+			i: rand::random(),
+			j: rand::random(),
+			k: rand::random(),
+		};
+
+		ser_helper_slabs(ser_out)?;
 
 		let ser_err = SerErr { exp: 100, empty: 0 };
 
@@ -3634,6 +5357,91 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_slab_overflow_to_heap() -> Result<(), Error> {
+		let mut slabs = slab_allocator!(SlabSize(16), SlabCount(4), OverflowToHeap(true))?;
+		assert_eq!(slabs.id_limit()?, 8);
+		let mut ids = vec![];
+		for i in 0..8 {
+			let mut slab = slabs.allocate()?;
+			slab.get_mut()[0] = i;
+			assert_eq!(slab.get().len(), 16);
+			ids.push(slab.id());
+		}
+		// the arena and the overflow are both exhausted
+		assert!(slabs.allocate().is_err());
+		assert!(ids[4..].iter().all(|id| *id >= 4 && *id < 8));
+		for i in 0..8 {
+			assert_eq!(slabs.get(ids[i])?.get()[0], i as u8);
+		}
+		let stats = slabs.stats()?;
+		assert_eq!(stats.free_count, 0);
+		assert_eq!(stats.heap_overflow_slabs, 4);
+		assert_eq!(stats.heap_overflow_in_use, 4);
+
+		// heap slabs are not returned to the arena
+		slabs.free(ids[5])?;
+		assert!(slabs.free(ids[5]).is_err());
+		assert_eq!(slabs.free_count()?, 0);
+		assert_eq!(slabs.stats()?.heap_overflow_in_use, 3);
+		assert_eq!(slabs.stats()?.heap_overflow_slabs, 4);
+
+		// the cached heap slab is reused
+		let id = slabs.allocate()?.id();
+		assert_eq!(id, ids[5]);
+		slabs.free(id)?;
+
+		slabs.free(ids[7])?;
+		slabs.free(ids[0])?;
+		assert_eq!(slabs.free_count()?, 1);
+		assert_eq!(slabs.shrink_overflow()?, 2);
+		assert_eq!(slabs.stats()?.heap_overflow_slabs, 2);
+		assert!(slabs.get(ids[7]).is_err());
+		assert!(slabs.free(ids[7]).is_err());
+		assert_eq!(slabs.get(ids[6])?.get()[0], 6);
+
+		// without the option, the capacity is fixed
+		let mut slabs = slab_allocator!(SlabSize(16), SlabCount(4))?;
+		assert_eq!(slabs.id_limit()?, 4);
+		for _ in 0..4 {
+			slabs.allocate()?;
+		}
+		assert!(slabs.allocate().is_err());
+		assert_eq!(slabs.stats()?.heap_overflow_slabs, 0);
+		Ok(())
+	}
+
+	#[test]
+	fn test_hashtable_overflow_to_heap() -> Result<(), Error> {
+		global_slab_allocator!(SlabSize(64), SlabCount(20), OverflowToHeap(true))?;
+		let mut hashtable = hashtable!(MaxEntries(100))?;
+		for i in 0..30u64 {
+			hashtable.insert(&i, &(i * 10))?;
+		}
+		let stats = GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<SlabStats, Error> {
+			Ok(unsafe { f.get().as_ref().unwrap().stats()? })
+		})?;
+		assert_eq!(stats.free_count, 0);
+		assert_eq!(stats.heap_overflow_slabs, 10);
+		for i in 0..30u64 {
+			assert_eq!(hashtable.get(&i)?, Some(i * 10));
+		}
+
+		for i in 0..30u64 {
+			hashtable.remove(&i)?;
+		}
+		let released = GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<usize, Error> {
+			Ok(unsafe { f.get().as_mut().unwrap().shrink_overflow()? })
+		})?;
+		assert_eq!(released, 10);
+		let stats = GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<SlabStats, Error> {
+			Ok(unsafe { f.get().as_ref().unwrap().stats()? })
+		})?;
+		assert_eq!(stats.heap_overflow_slabs, 0);
+		assert_eq!(stats.free_count, 20);
+		Ok(())
+	}
+
 	#[test]
 	fn test_error_conditions() -> Result<(), Error> {
 		let mut slabs = UtilBuilder::build_slabs();
@@ -4101,52 +5909,41 @@ mod test {
 
 	#[test]
 	fn test_search_trie_error_conditions() -> Result<(), Error> {
-		assert!(UtilBuilder::build_search_trie(
-			vec![UtilBuilder::build_pattern(vec![
-				Regex("".to_string()),
-				IsCaseSensitive(false),
-				IsTerminationPattern(false),
-				IsMultiLine(false),
-				PatternId(0)
-			])?],
-			vec![TerminationLength(36), MaxWildCardLength(36)]
-		)
+		// regex errors are found when the pattern is built
+		assert!(UtilBuilder::build_pattern(vec![
+			Regex("".to_string()),
+			IsCaseSensitive(false),
+			IsTerminationPattern(false),
+			IsMultiLine(false),
+			PatternId(0)
+		])
 		.is_err());
 
-		assert!(UtilBuilder::build_search_trie(
-			vec![UtilBuilder::build_pattern(vec![
-				Regex("^".to_string()),
-				IsCaseSensitive(false),
-				IsTerminationPattern(false),
-				IsMultiLine(false),
-				PatternId(0)
-			])?],
-			vec![TerminationLength(100), MaxWildCardLength(100)]
-		)
+		assert!(UtilBuilder::build_pattern(vec![
+			Regex("^".to_string()),
+			IsCaseSensitive(false),
+			IsTerminationPattern(false),
+			IsMultiLine(false),
+			PatternId(0)
+		])
 		.is_err());
 
-		assert!(UtilBuilder::build_search_trie(
-			vec![UtilBuilder::build_pattern(vec![
-				Regex("x\\y".to_string()),
-				IsCaseSensitive(false),
-				IsTerminationPattern(false),
-				IsMultiLine(false),
-				PatternId(0)
-			])?],
-			vec![TerminationLength(100), MaxWildCardLength(100)]
-		)
+		assert!(UtilBuilder::build_pattern(vec![
+			Regex("x\\y".to_string()),
+			IsCaseSensitive(false),
+			IsTerminationPattern(false),
+			IsMultiLine(false),
+			PatternId(0)
+		])
 		.is_err());
 
-		assert!(UtilBuilder::build_search_trie(
-			vec![UtilBuilder::build_pattern(vec![
-				Regex("x\\".to_string()),
-				IsCaseSensitive(false),
-				IsTerminationPattern(false),
-				IsMultiLine(false),
-				PatternId(0)
-			])?],
-			vec![TerminationLength(100), MaxWildCardLength(100)]
-		)
+		assert!(UtilBuilder::build_pattern(vec![
+			Regex("x\\".to_string()),
+			IsCaseSensitive(false),
+			IsTerminationPattern(false),
+			IsMultiLine(false),
+			PatternId(0)
+		])
 		.is_err());
 
 		assert!(UtilBuilder::build_search_trie(
@@ -4212,6 +6009,287 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_search_trie_classes() -> Result<(), Error> {
+		let mut matches = [tmatch!()?; 10];
+		let mut search_trie = search_trie!(
+			vec![
+				pattern!(Regex("^/user/[0-9]*$".to_string()), PatternId(0))?,
+				pattern!(Regex("^/item/[0-9a-f][0-9a-f]/".to_string()), PatternId(1))?,
+				pattern!(Regex("id=[0-9]*;".to_string()), PatternId(2))?
+			],
+			MaxWildCardLength(10)
+		)?;
+
+		let count = search_trie.tmatch(b"/user/1234", &mut matches)?;
+		assert_eq!(count, 1);
+		assert_eq!(matches[0].id(), 0);
+		assert_eq!(matches[0].start(), 0);
+		assert_eq!(matches[0].end(), 10);
+
+		// no digits, a non-digit, not at the end or not at the start
+		assert_eq!(search_trie.tmatch(b"/user/", &mut matches)?, 0);
+		assert_eq!(search_trie.tmatch(b"/user/12a4", &mut matches)?, 0);
+		assert_eq!(search_trie.tmatch(b"/user/1234/", &mut matches)?, 0);
+		assert_eq!(search_trie.tmatch(b"x/user/1234", &mut matches)?, 0);
+
+		// the repetition is limited by MaxWildCardLength
+		assert_eq!(search_trie.tmatch(b"/user/123456789012", &mut matches)?, 0);
+
+		// classes are case insensitive unless IsCaseSensitive is set
+		let count = search_trie.tmatch(b"/item/aF/", &mut matches)?;
+		assert_eq!(count, 1);
+		assert_eq!(matches[0].id(), 1);
+		assert_eq!(search_trie.tmatch(b"/item/ag/", &mut matches)?, 0);
+		assert_eq!(search_trie.tmatch(b"/item/a/", &mut matches)?, 0);
+
+		let text = b"x id=77; id=; id=5;";
+		let count = search_trie.tmatch(text, &mut matches)?;
+		assert_eq!(count, 2);
+		assert_eq!(matches[0].id(), 2);
+		assert_eq!(&text[matches[0].start()..matches[0].end()], b"id=77;");
+		assert_eq!(matches[1].id(), 2);
+		assert_eq!(&text[matches[1].start()..matches[1].end()], b"id=5;");
+
+		// class edges branch with literal edges of other patterns
+		let mut search_trie = search_trie!(
+			vec![
+				pattern!(Regex("ab[c-e]x".to_string()), PatternId(0))?,
+				pattern!(Regex("abcy".to_string()), PatternId(1))?,
+				pattern!(Regex("a\\[b\\]\\*".to_string()), PatternId(2))?
+			],
+			MaxWildCardLength(10)
+		)?;
+		let count = search_trie.tmatch(b"abcx abdx abcy", &mut matches)?;
+		assert_eq!(count, 3);
+		assert_eq!((matches[0].id(), matches[0].start()), (0, 0));
+		assert_eq!((matches[1].id(), matches[1].start()), (0, 5));
+		assert_eq!((matches[2].id(), matches[2].start()), (1, 10));
+		let count = search_trie.tmatch(b"a[b]*", &mut matches)?;
+		assert_eq!(count, 1);
+		assert_eq!(matches[0].id(), 2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_search_trie_anchors() -> Result<(), Error> {
+		let mut matches = [tmatch!()?; 10];
+		let mut search_trie = search_trie!(
+			vec![
+				pattern!(Regex("abc$".to_string()), PatternId(0))?,
+				pattern!(Regex("abc".to_string()), PatternId(1))?,
+				pattern!(Regex("^x.$".to_string()), PatternId(2))?
+			],
+			MaxWildCardLength(10)
+		)?;
+
+		// both the anchored and unanchored pattern with the same regex match at the end
+		let count = search_trie.tmatch(b"abcabc", &mut matches)?;
+		assert_eq!(count, 3);
+		assert_eq!((matches[0].id(), matches[0].start()), (1, 0));
+		assert_eq!((matches[1].id(), matches[1].start()), (1, 3));
+		assert_eq!((matches[2].id(), matches[2].start()), (0, 3));
+
+		assert_eq!(search_trie.tmatch(b"abca", &mut matches)?, 1);
+		assert_eq!(matches[0].id(), 1);
+
+		// a pattern with both anchors must cover the whole text
+		assert_eq!(search_trie.tmatch(b"xy", &mut matches)?, 1);
+		assert_eq!(matches[0].id(), 2);
+		assert_eq!(search_trie.tmatch(b"xyz", &mut matches)?, 0);
+		assert_eq!(search_trie.tmatch(b"axy", &mut matches)?, 0);
+
+		// with the streaming matcher, $ is the end of the stream and not of a chunk
+		let mut search_trie = search_trie_box!(
+			vec![pattern!(Regex("abc$".to_string()), PatternId(0))?],
+			MaxWildCardLength(10)
+		)?;
+		let res = stream_matches(&mut search_trie, &[b"xxabc", b"abc"])?;
+		assert_eq!(res, vec![(5, 8, 0)]);
+		let res = stream_matches(&mut search_trie, &[b"xxabc", b"abc", b"x"])?;
+		assert_eq!(res, vec![]);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_search_trie_regex_errors() -> Result<(), Error> {
+		let err_text = |regex: &str| -> String {
+			match pattern!(Regex(regex.to_string()), PatternId(0)) {
+				Ok(_) => "".to_string(),
+				Err(e) => {
+					assert!(matches!(e.kind(), ErrorKind::IllegalArgument(_)));
+					e.to_string()
+				}
+			}
+		};
+
+		assert!(err_text("abc[^0-9]").contains("position 4"));
+		assert!(err_text("ab^c").contains("position 2"));
+		assert!(err_text("a$c").contains("position 1"));
+		assert!(err_text("*abc").contains("position 0"));
+		assert!(err_text("ab**").contains("position 3"));
+		assert!(err_text("abc]").contains("position 3"));
+		assert!(err_text("a[bc").contains("position 1"));
+		assert!(err_text("a[]").contains("position 1"));
+		assert!(err_text("a[z-a]").contains("position 2"));
+		assert!(err_text("a[\\n]").contains("position 3"));
+		assert!(err_text("^$").contains("not including"));
+
+		// these are all supported
+		for regex in [
+			"a-b",
+			"[-a]",
+			"[a-]",
+			"[\\]\\-]",
+			"1+1?",
+			"\\$[0-9]*\\.[0-9][0-9]",
+		] {
+			assert_eq!(err_text(regex), "");
+		}
+
+		Ok(())
+	}
+
+	fn stream_matches(
+		search_trie: &mut Box<dyn SearchTrie + Send + Sync>,
+		chunks: &[&[u8]],
+	) -> Result<Vec<(usize, usize, usize)>, Error> {
+		let mut ret = vec![];
+		let mut matcher = search_trie.streaming_matcher()?;
+		for chunk in chunks {
+			matcher.feed(chunk, |m| {
+				ret.push((m.start(), m.end(), m.id()));
+				true
+			})?;
+		}
+		matcher.finish(|m| {
+			ret.push((m.start(), m.end(), m.id()));
+			true
+		})?;
+		ret.sort();
+		Ok(ret)
+	}
+
+	#[test]
+	fn test_search_trie_streaming() -> Result<(), Error> {
+		let mut matches = [tmatch!()?; 100];
+		let mut search_trie = search_trie_box!(
+			vec![
+				pattern!(Regex("aab".to_string()), PatternId(0))?,
+				pattern!(Regex("aaab".to_string()), PatternId(1))?,
+				pattern!(Regex("x.*yz".to_string()), PatternId(2))?,
+				pattern!(Regex("^aa".to_string()), PatternId(3))?,
+				pattern!(
+					Regex("CaSe".to_string()),
+					PatternId(4),
+					IsCaseSensitive(true)
+				)?,
+				pattern!(Regex("end.".to_string()), PatternId(5))?
+			],
+			TerminationLength(1_000),
+			MaxWildCardLength(10)
+		)?;
+
+		let texts: Vec<&[u8]> = vec![
+			b"aaaabxaabyzcase CaSe aaabxxxxyz",
+			b"aaab aaaab CaSeCaSe xyzxyz xxxxxxxxxxxxxxxyz",
+			b"no matches here",
+		];
+		for text in texts {
+			let count = search_trie.tmatch(text, &mut matches)?;
+			let mut expected: Vec<(usize, usize, usize)> = matches[0..count]
+				.iter()
+				.map(|m| (m.start(), m.end(), m.id()))
+				.collect();
+			expected.sort();
+
+			// a single chunk and one byte at a time
+			assert_eq!(stream_matches(&mut search_trie, &[text])?, expected);
+			let bytes: Vec<&[u8]> = text.chunks(1).collect();
+			assert_eq!(stream_matches(&mut search_trie, &bytes)?, expected);
+
+			// every two and three way split
+			for i in 0..=text.len() {
+				let (a, b) = text.split_at(i);
+				assert_eq!(stream_matches(&mut search_trie, &[a, b])?, expected);
+				for j in i..=text.len() {
+					let chunks = [&text[0..i], &text[i..j], &text[j..]];
+					assert_eq!(stream_matches(&mut search_trie, &chunks)?, expected);
+				}
+			}
+		}
+
+		// termination patterns drop later matches in both dictionaries
+		let mut search_trie = search_trie_box!(
+			vec![
+				pattern!(Regex("abc".to_string()), PatternId(0))?,
+				pattern!(
+					Regex("def".to_string()),
+					PatternId(1),
+					IsTerminationPattern(true)
+				)?,
+				pattern!(
+					Regex("Xyz.*Xyz".to_string()),
+					PatternId(2),
+					IsCaseSensitive(true)
+				)?
+			],
+			MaxWildCardLength(20)
+		)?;
+		let text = b"abcXyzdeXyzfXyzabcdefabcXyzXyz";
+		let count = search_trie.tmatch(text, &mut matches)?;
+		let mut expected: Vec<(usize, usize, usize)> = matches[0..count]
+			.iter()
+			.map(|m| (m.start(), m.end(), m.id()))
+			.collect();
+		expected.sort();
+		assert_eq!(expected.len(), 5);
+		for i in 0..=text.len() {
+			let (a, b) = text.split_at(i);
+			assert_eq!(stream_matches(&mut search_trie, &[a, b])?, expected);
+		}
+
+		// unbounded wildcards can't be streamed
+		let mut search_trie =
+			search_trie_box!(vec![pattern!(Regex("a.*b".to_string()), PatternId(0))?],)?;
+		assert!(search_trie.streaming_matcher().is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_search_trie_streaming_stop() -> Result<(), Error> {
+		let mut search_trie = search_trie_box!(
+			vec![pattern!(Regex("ab".to_string()), PatternId(0))?],
+			MaxWildCardLength(10)
+		)?;
+
+		let mut found = vec![];
+		let mut matcher = search_trie.streaming_matcher()?;
+		assert!(matcher.feed(b"xxaba", |m| {
+			found.push(m.start());
+			true
+		})?);
+		// stop after the second match
+		assert!(!matcher.feed(b"bxabxxab", |m| {
+			found.push(m.start());
+			found.len() < 2
+		})?);
+		assert!(!matcher.feed(b"abab", |m| {
+			found.push(m.start());
+			true
+		})?);
+		assert!(!matcher.finish(|m| {
+			found.push(m.start());
+			true
+		})?);
+		assert_eq!(found, vec![2, 4]);
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_threadpool1() -> Result<(), Error> {
 		let mut tp = UtilBuilder::build_thread_pool(vec![MinSize(10), MaxSize(10)])?;
@@ -4495,6 +6573,110 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_thread_pool_schedule() -> Result<(), Error> {
+		let mut tp = UtilBuilder::build_thread_pool(vec![MinSize(2), MaxSize(4)])?;
+		tp.set_on_panic(move |_, _| -> Result<(), Error> { Ok(()) })?;
+
+		// not started yet
+		assert!(tp.schedule(10, || Ok(())).is_err());
+		tp.start()?;
+		assert!(tp.schedule_recurring(0, || Ok(())).is_err());
+
+		let fired = lock_box!(None)?;
+		let mut fired_clone = fired.clone();
+		let start = std::time::Instant::now();
+		tp.schedule(300, move || -> Result<(), Error> {
+			wlock!(fired_clone) = Some(start.elapsed());
+			Ok(())
+		})?;
+
+		// schedule later, cancel before it fires
+		let cancelled_ran = lock_box!(false)?;
+		let mut cancelled_ran_clone = cancelled_ran.clone();
+		let handle = tp.schedule(200, move || -> Result<(), Error> {
+			wlock!(cancelled_ran_clone) = true;
+			Ok(())
+		})?;
+		assert!(!handle.is_cancelled());
+		handle.cancel();
+		assert!(handle.is_cancelled());
+
+		let mut count = 0;
+		loop {
+			sleep(Duration::from_millis(10));
+			count += 1;
+			cbreak!(rlock!(fired).is_some() || count > 500);
+		}
+
+		let elapsed = rlock!(fired).unwrap();
+		info!("scheduled task fired after {:?}", elapsed)?;
+		assert!(elapsed >= Duration::from_millis(300));
+		assert!(elapsed < Duration::from_millis(1_300));
+
+		sleep(Duration::from_millis(100));
+		assert!(!rlock!(cancelled_ran));
+		let stats = tp.schedule_stats()?;
+		assert_eq!(stats.dispatched, 1);
+		assert_eq!(stats.pending, 0);
+
+		tp.stop()?;
+		Ok(())
+	}
+
+	#[test]
+	fn test_thread_pool_schedule_recurring() -> Result<(), Error> {
+		let mut tp = UtilBuilder::build_thread_pool(vec![MinSize(4), MaxSize(4)])?;
+		tp.set_on_panic(move |_, _| -> Result<(), Error> { Ok(()) })?;
+		tp.start()?;
+
+		let in_flight = Arc::new(std::sync::atomic::AtomicBool::new(false));
+		let in_flight_clone = in_flight.clone();
+		let overlap = lock_box!(false)?;
+		let mut overlap_clone = overlap.clone();
+		let runs = lock_box!(0)?;
+		let mut runs_clone = runs.clone();
+
+		// the body takes longer than the interval so some ticks must be skipped
+		let handle = tp.schedule_recurring(50, move || -> Result<(), Error> {
+			if in_flight_clone.swap(true, std::sync::atomic::Ordering::SeqCst) {
+				wlock!(overlap_clone) = true;
+			}
+			sleep(Duration::from_millis(175));
+			in_flight_clone.store(false, std::sync::atomic::Ordering::SeqCst);
+			wlock!(runs_clone) += 1;
+			Ok(())
+		})?;
+
+		sleep(Duration::from_millis(1_200));
+		handle.cancel();
+		sleep(Duration::from_millis(300));
+
+		let runs_at_cancel = rlock!(runs);
+		assert!(runs_at_cancel >= 2);
+		assert!(!rlock!(overlap));
+		let stats = tp.schedule_stats()?;
+		info!("runs={},stats={:?}", runs_at_cancel, stats)?;
+		assert!(stats.skipped > 0);
+
+		// canceled, so no more runs
+		sleep(Duration::from_millis(300));
+		assert_eq!(rlock!(runs), runs_at_cancel);
+
+		// the stopper cancels outstanding schedules
+		let handle = tp.schedule_recurring(50, || Ok(()))?;
+		let handle2 = tp.schedule(10_000, || Ok(()))?;
+		assert_eq!(tp.schedule_stats()?.pending, 2);
+		let mut stopper = tp.stopper()?;
+		stopper.stop()?;
+		assert!(handle.is_cancelled());
+		assert!(handle2.is_cancelled());
+		assert_eq!(tp.schedule_stats()?.pending, 0);
+		assert!(tp.schedule(10, || Ok(())).is_err());
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_no_on_panic_handler() -> Result<(), Error> {
 		let mut tp = UtilBuilder::build_thread_pool(vec![MinSize(1), MaxSize(1)])?;
@@ -4568,6 +6750,80 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_thread_pool_task_context() -> Result<(), Error> {
+		let mut tp = thread_pool!(MinSize(2))?;
+		tp.set_on_panic(move |_id, _e| -> Result<(), Error> { Ok(()) })?;
+		tp.start()?;
+		let mut inner = thread_pool!(MinSize(2))?;
+		inner.set_on_panic(move |_id, _e| -> Result<(), Error> { Ok(()) })?;
+		inner.start()?;
+		let executor = inner.executor()?;
+
+		let cid = || current_task_context().and_then(|ctx| ctx.correlation_id);
+		assert!(current_task_context().is_none());
+
+		let ctx = TaskContext {
+			correlation_id: Some(42),
+			deadline_millis: Some(1_000),
+			data: Some(Arc::new("payload".to_string())),
+		};
+		let (handle, nested) = with_task_context(ctx, || -> Result<_, Error> {
+			// the submitting thread sees its own context
+			assert_eq!(cid(), Some(42));
+			let handle = tp.execute(
+				async move {
+					let ctx = current_task_context().unwrap();
+					let data = ctx.data.unwrap();
+					assert_eq!(data.downcast_ref::<String>().unwrap(), "payload");
+					assert_eq!(ctx.deadline_millis, Some(1_000));
+					Ok(ctx.correlation_id)
+				},
+				0,
+			)?;
+			// a task submitted from a task of another pool gets the same context
+			let nested = tp.execute(
+				async move {
+					let rx = executor.execute(async move { Ok(cid()) }, 1)?;
+					match rx.recv()? {
+						PoolResult::Ok(cid) => Ok(cid),
+						_ => Err(err!(ErrKind::Test, "nested task failed")),
+					}
+				},
+				2,
+			)?;
+			Ok((handle, nested))
+		})?;
+		assert_eq!(block_on!(handle), PoolResult::Ok(Some(42)));
+		assert_eq!(block_on!(nested), PoolResult::Ok(Some(42)));
+
+		// the context is cleared on the submitting thread and the workers afterward
+		assert!(current_task_context().is_none());
+		for _ in 0..10 {
+			let handle = tp.execute(async move { Ok(cid()) }, 3)?;
+			assert_eq!(block_on!(handle), PoolResult::Ok(None));
+		}
+
+		// nested contexts restore the outer one
+		let outer = TaskContext {
+			correlation_id: Some(1),
+			..Default::default()
+		};
+		with_task_context(outer, || {
+			let inner = TaskContext {
+				correlation_id: Some(2),
+				..Default::default()
+			};
+			with_task_context(inner, || assert_eq!(cid(), Some(2)));
+			assert_eq!(cid(), Some(1));
+		});
+		assert!(current_task_context().is_none());
+
+		tp.stop()?;
+		inner.stop()?;
+		Ok(())
+	}
+
 	#[test]
 	fn test_thread_pool_macro_panic() -> Result<(), Error> {
 		let (tx, rx) = sync_channel(1);
@@ -4802,6 +7058,185 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_panic_info_capture() -> Result<(), Error> {
+		let (tx, rx) = sync_channel(1);
+		let mut tp = thread_pool!()?;
+		tp.set_on_panic_info(Box::new(move |id, info| -> Result<(), Error> {
+			tx.send((id, info.clone()))?;
+			Ok(())
+		}))?;
+		tp.set_on_panic(move |_id, _e| -> Result<(), Error> { Ok(()) })?;
+		tp.start()?;
+
+		let str_line = line!() + 4;
+		tp.executor()?.execute(
+			async move {
+				if true {
+					panic!("str payload");
+				}
+				Ok(())
+			},
+			1,
+		)?;
+		let (id, info) = rx.recv()?;
+		assert_eq!(id, 1);
+		assert_eq!(info.message(), "str payload");
+		assert!(info.file().unwrap().ends_with("test.rs"));
+		assert_eq!(info.line(), Some(str_line));
+
+		let string_line = line!() + 5;
+		tp.executor()?.execute(
+			async move {
+				let value = 7;
+				if true {
+					panic!("string payload {}", value);
+				}
+				Ok(())
+			},
+			2,
+		)?;
+		let (id, info) = rx.recv()?;
+		assert_eq!(id, 2);
+		assert_eq!(info.message(), "string payload 7");
+		assert!(info.file().unwrap().ends_with("test.rs"));
+		assert_eq!(info.line(), Some(string_line));
+
+		let any_line = line!() + 4;
+		tp.executor()?.execute(
+			async move {
+				if true {
+					std::panic::panic_any(1234u64);
+				}
+				Ok(())
+			},
+			3,
+		)?;
+		let (id, info) = rx.recv()?;
+		assert_eq!(id, 3);
+		assert_eq!(info.message(), "Box<dyn Any>");
+		assert!(info.file().unwrap().ends_with("test.rs"));
+		assert_eq!(info.line(), Some(any_line));
+
+		let display = info.to_string();
+		assert!(display.ends_with(&format!("test.rs:{}: Box<dyn Any>", any_line)));
+		assert!(display.starts_with("thread '"));
+
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &info)?;
+		let info2: PanicInfoCapture = deserialize(&mut &v[..])?;
+		assert_eq!(info, info2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_panic_info_capture_disabled() -> Result<(), Error> {
+		let e: Box<dyn Any + Send> = Box::new("no capture");
+		let info = PanicInfoCapture::from_payload(&*e, std::thread::current().id());
+		assert_eq!(info.message(), "no capture");
+		assert_eq!(info.file(), None);
+		assert_eq!(info.line(), None);
+		assert_eq!(info.to_string(), "thread '<unnamed>' panicked: no capture");
+		Ok(())
+	}
+
+	#[test]
+	fn test_thread_pool_shutdown_drain() -> Result<(), Error> {
+		let mut tp = thread_pool!(MinSize(2), MaxSize(4), SyncChannelSize(100))?;
+		tp.set_on_panic(move |_id, _e| -> Result<(), Error> { Ok(()) })?;
+		tp.start()?;
+		let executor = tp.executor()?;
+
+		let mut handles = vec![];
+		for i in 0..50 {
+			handles.push(tp.execute(
+				async move {
+					sleep(Duration::from_millis(1));
+					Ok(i)
+				},
+				i,
+			)?);
+		}
+		tp.shutdown(ThreadPoolShutdownMode::Drain)?;
+		assert_eq!(tp.size()?, 0);
+
+		for (i, handle) in handles.iter().enumerate() {
+			assert_eq!(handle.block_on(), PoolResult::Ok(i as u128));
+		}
+
+		let e = tp.execute(async move { Ok(0) }, 0).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::ShuttingDown(_)));
+		let e = executor.execute(async move { Ok(0) }, 0).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::ShuttingDown(_)));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_thread_pool_shutdown_discard_pending() -> Result<(), Error> {
+		let mut tp = thread_pool!(MinSize(1), MaxSize(1), SyncChannelSize(100))?;
+		tp.set_on_panic(move |_id, _e| -> Result<(), Error> { Ok(()) })?;
+		tp.start()?;
+
+		let (tx, rx) = sync_channel(1);
+		let in_flight = tp.execute(
+			async move {
+				tx.send(())?;
+				sleep(Duration::from_millis(100));
+				Ok(0)
+			},
+			0,
+		)?;
+		let mut handles = vec![];
+		for i in 1..50 {
+			handles.push(tp.execute(async move { Ok(i) }, i)?);
+		}
+		rx.recv()?;
+		tp.shutdown(ThreadPoolShutdownMode::DiscardPending)?;
+
+		assert_eq!(in_flight.block_on(), PoolResult::Ok(0));
+		for handle in handles {
+			match handle.block_on() {
+				PoolResult::Err(e) => assert!(matches!(e.kind(), ErrorKind::Cancelled(_))),
+				_ => panic!("expected a cancelled error"),
+			}
+		}
+		let e = tp.execute(async move { Ok(0) }, 0).unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::ShuttingDown(_)));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_thread_pool_shutdown_timeout() -> Result<(), Error> {
+		let mut tp = thread_pool!(MinSize(1), MaxSize(1))?;
+		tp.set_on_panic(move |_id, _e| -> Result<(), Error> { Ok(()) })?;
+		assert!(tp.shutdown(ThreadPoolShutdownMode::Drain).is_err());
+		tp.start()?;
+
+		let (tx, rx) = sync_channel(1);
+		let handle = tp.execute(
+			async move {
+				tx.send(())?;
+				sleep(Duration::from_millis(500));
+				Ok(1)
+			},
+			0,
+		)?;
+		rx.recv()?;
+
+		let start = std::time::Instant::now();
+		tp.shutdown_timeout(ThreadPoolShutdownMode::Drain, 50)?;
+		assert!(start.elapsed() < Duration::from_millis(400));
+		assert_eq!(tp.size()?, 1);
+
+		// the detached worker still completes its task
+		assert_eq!(handle.block_on(), PoolResult::Ok(1));
+
+		Ok(())
+	}
+
 	fn build_tp(min: usize, max: usize, sync: usize) -> Result<(), Error> {
 		let mut tp = thread_pool!(MinSize(min), MaxSize(max), SyncChannelSize(sync))?;
 		tp.set_on_panic(move |_id, _e| -> Result<(), Error> { Ok(()) })?;
@@ -66,6 +66,81 @@ mod test {
 		Ok(())
 	}
 
+	#[derive(Configurable, Debug)]
+	#[validate(check_sizes)]
+	struct SizedConfig {
+		size: usize,
+		threshold: usize,
+	}
+
+	impl Default for SizedConfig {
+		fn default() -> Self {
+			Self {
+				size: 100,
+				threshold: 10,
+			}
+		}
+	}
+
+	impl SizedConfig {
+		fn check_sizes(&self) -> Result<(), Error> {
+			if self.size < 2 * self.threshold {
+				let text = format!(
+					"size ({}) must be at least twice threshold ({})",
+					self.size, self.threshold
+				);
+				return Err(err!(ErrKind::Configuration, text));
+			}
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn test_derive_configurable_validate() -> Result<(), Error> {
+		let sized = config!(SizedConfig, SizedConfig_Options, vec![])?;
+		assert_eq!(sized.size, 100);
+		assert_eq!(sized.threshold, 10);
+
+		let sized = config!(SizedConfig, SizedConfig_Options, vec![Size(20)])?;
+		assert_eq!(sized.size, 20);
+
+		let sized = config!(
+			SizedConfig,
+			SizedConfig_Options,
+			vec![Size(1_000), Threshold(500)]
+		)?;
+		assert_eq!(sized.threshold, 500);
+
+		// fails with the default size of 100
+		let res = config!(SizedConfig, SizedConfig_Options, vec![Threshold(51)]);
+		assert!(matches!(
+			res.unwrap_err().kind(),
+			ErrorKind::Configuration(_)
+		));
+
+		let res = config!(SizedConfig, SizedConfig_Options, vec![Size(19)]);
+		assert!(res.is_err());
+
+		// duplicate errors are reported rather than calling validate
+		let res = config!(
+			SizedConfig,
+			SizedConfig_Options,
+			vec![Size(1_000), Size(1_000)]
+		);
+		assert_eq!(
+			res.unwrap_err().kind(),
+			ErrorKind::Configuration(
+				"config option (Size) was specified more than once".to_string()
+			)
+		);
+
+		// structs without the attribute accept anything
+		let my = config!(MyConfig2, MyConfig2_Options, vec![V1(0)])?;
+		assert!(my.validate().is_ok());
+
+		Ok(())
+	}
+
 	#[derive(Configurable)]
 	struct EvhConfig {
 		threads: u8,
@@ -236,6 +311,45 @@ mod test {
 		Ok(())
 	}
 
+	#[test]
+	fn test_derive_configurable_to_json() -> Result<(), Error> {
+		let config = config!(
+			EvhConfig,
+			EvhConfig_Options,
+			vec![
+				Port(8081),
+				Debug(true),
+				ServerName("a\"b\\c"),
+				TestVec(7u32),
+				TestVec(3u32),
+				Header(("x-test", "say \"hi\"\nbye\u{1}")),
+				Headers(("a", "b")),
+				Headers(("c", "d")),
+			]
+		)?;
+
+		let json = config.to_json();
+		info!("json={}", json)?;
+		let expected = r#"{"threads":1,"port":8081,"slab_count":100,"timeout":100,"blob":0,"#
+			.to_string()
+			+ r#""x32":7,"server_name":"a\"b\\c","debug":true,"test_vec":[7,3],"#
+			+ r#""test_vec2":[],"other_ports":[],"default_not_empty":[1,2,3],"#
+			+ r#""header":["x-test","say \"hi\"\nbye\u0001"],"headers":[["a","b"],["c","d"]]}"#;
+		assert_eq!(json, expected);
+
+		// every field appears as a key and no raw control characters remain
+		for name in EvhConfig::field_names() {
+			assert!(json.contains(&format!("\"{}\":", name)));
+		}
+		assert_eq!(EvhConfig::field_names().len(), 14);
+		assert!(!json.chars().any(|c| (c as u32) < 0x20));
+
+		assert_eq!(Empty::new().to_json(), "{}");
+		assert!(Empty::field_names().is_empty());
+
+		Ok(())
+	}
+
 	#[derive(Configurable, PartialEq, Debug)]
 	pub struct Empty {}
 
@@ -516,4 +630,330 @@ mod test {
 
 		Ok(())
 	}
+
+	#[derive(Configurable, PartialEq, Debug)]
+	struct GatedConfig {
+		threads: usize,
+		#[cfg(target_os = "linux")]
+		#[required]
+		epoll_size: usize,
+		#[cfg(not(target_os = "linux"))]
+		kqueue_name: String,
+		name: String,
+	}
+
+	impl Default for GatedConfig {
+		fn default() -> Self {
+			Self {
+				threads: 1,
+				#[cfg(target_os = "linux")]
+				epoll_size: 0,
+				#[cfg(not(target_os = "linux"))]
+				kqueue_name: "kq".to_string(),
+				name: "gated".to_string(),
+			}
+		}
+	}
+
+	#[test]
+	fn test_derive_configurable_cfg_fields() -> Result<(), Error> {
+		#[cfg(target_os = "linux")]
+		{
+			let config = config!(
+				GatedConfig,
+				GatedConfig_Options,
+				vec![Threads(4), EpollSize(128), Name("x")]
+			)?;
+			assert_eq!(config.threads, 4);
+			assert_eq!(config.epoll_size, 128);
+			assert_eq!(config.name, "x".to_string());
+			assert_eq!(
+				config.to_json(),
+				r#"{"threads":4,"epoll_size":128,"name":"x"}"#
+			);
+			assert_eq!(
+				GatedConfig::field_names(),
+				&["threads", "epoll_size", "name"]
+			);
+			assert_eq!(GatedConfig::required(), vec!["EpollSize".to_string()]);
+			assert!(config!(GatedConfig, GatedConfig_Options, vec![Threads(4)]).is_err());
+		}
+
+		#[cfg(not(target_os = "linux"))]
+		{
+			let config = config!(GatedConfig, GatedConfig_Options, vec![Threads(4)])?;
+			assert_eq!(config.threads, 4);
+			assert_eq!(config.kqueue_name, "kq".to_string());
+			assert_eq!(
+				GatedConfig::field_names(),
+				&["threads", "kqueue_name", "name"]
+			);
+			assert!(GatedConfig::required().is_empty());
+		}
+
+		Ok(())
+	}
+
+	#[derive(Configurable, PartialEq, Debug)]
+	struct HumanConfig {
+		#[human_duration]
+		max_age: u64,
+		#[required]
+		#[human_bytes]
+		max_size: usize,
+		#[human_duration]
+		timeouts: Vec<u64>,
+		threads: usize,
+	}
+
+	impl Default for HumanConfig {
+		fn default() -> Self {
+			Self {
+				max_age: 1_000,
+				max_size: 0,
+				timeouts: vec![],
+				threads: 1,
+			}
+		}
+	}
+
+	#[test]
+	fn test_derive_configurable_human() -> Result<(), Error> {
+		let config = config!(
+			HumanConfig,
+			HumanConfig_Options,
+			vec![
+				MaxAge("1.5h"),
+				MaxSize("64KiB"),
+				Timeouts("500ms"),
+				Timeouts("2s"),
+				Timeouts(7),
+				Threads(4),
+			]
+		)?;
+		assert_eq!(config.max_age, 5_400_000);
+		assert_eq!(config.max_size, 65_536);
+		assert_eq!(config.timeouts, vec![500, 2_000, 7]);
+		assert_eq!(config.threads, 4);
+		assert_eq!(
+			config.to_json(),
+			r#"{"max_age":5400000,"max_size":65536,"timeouts":[500,2000,7],"threads":4}"#
+		);
+
+		// numbers are milliseconds and bytes
+		let config = config!(
+			HumanConfig,
+			HumanConfig_Options,
+			vec![MaxAge(60_000), MaxSize(100)]
+		)?;
+		assert_eq!(config.max_age, 60_000);
+		assert_eq!(config.max_size, 100);
+
+		// options built outside the macro take the value types
+		let options = vec![
+			HumanConfig_Options::MaxSize(ByteSizeVal::parse("1mb")?),
+			HumanConfig_Options::MaxAge(DurationVal::from(10)),
+		];
+		let config = config!(HumanConfig, HumanConfig_Options, options)?;
+		assert_eq!(config.max_size, 1_000_000);
+		assert_eq!(config.max_age, 10);
+
+		// the error names the field and the bad string
+		let e = config!(
+			HumanConfig,
+			HumanConfig_Options,
+			vec![MaxSize(1), MaxAge("1 century")]
+		)
+		.unwrap_err();
+		assert!(matches!(e.kind(), ErrorKind::Configuration(_)));
+		assert!(e.to_string().contains("MaxAge"));
+		assert!(e.to_string().contains("'1 century'"));
+
+		let e = config!(
+			HumanConfig,
+			HumanConfig_Options,
+			vec![MaxSize("99999999tib")]
+		)
+		.unwrap_err();
+		assert!(e.to_string().contains("MaxSize"));
+		assert!(e.to_string().contains("'99999999tib'"));
+
+		// required and duplicate checks still apply
+		assert!(config!(HumanConfig, HumanConfig_Options, vec![MaxAge("1s")]).is_err());
+		assert!(config!(
+			HumanConfig,
+			HumanConfig_Options,
+			vec![MaxSize(1), MaxAge("1s"), MaxAge(1)]
+		)
+		.is_err());
+
+		Ok(())
+	}
+
+	#[derive(Configurable, PartialEq, Debug)]
+	struct VecAttrConfig {
+		#[unique]
+		ports: Vec<u16>,
+		#[unique_by_first]
+		headers: Vec<(String, String)>,
+		#[sorted]
+		names: Vec<String>,
+		#[unique]
+		#[sorted]
+		ids: Vec<u64>,
+		#[unique_by_first]
+		#[sorted]
+		params: Vec<(String, String)>,
+		values: Vec<u8>,
+	}
+
+	impl Default for VecAttrConfig {
+		fn default() -> Self {
+			Self {
+				ports: vec![80],
+				headers: vec![("Server".to_string(), "bmw".to_string())],
+				names: vec!["m".to_string()],
+				ids: vec![],
+				params: vec![],
+				values: vec![],
+			}
+		}
+	}
+
+	#[test]
+	fn test_derive_configurable_unique() -> Result<(), Error> {
+		let config = config!(
+			VecAttrConfig,
+			VecAttrConfig_Options,
+			vec![Ports(443), Ports(8080), Values(1), Values(1)]
+		)?;
+		assert_eq!(config.ports, vec![80, 443, 8080]);
+		// fields without the attribute keep duplicates
+		assert_eq!(config.values, vec![1, 1]);
+
+		let e = config!(
+			VecAttrConfig,
+			VecAttrConfig_Options,
+			vec![Ports(443), Ports(8080), Ports(443)]
+		)
+		.unwrap_err();
+		assert_eq!(
+			e.kind(),
+			ErrorKind::Configuration(
+				"config option (Ports) has a duplicate value: 443".to_string()
+			)
+		);
+
+		// the default value counts as well
+		let e = config!(VecAttrConfig, VecAttrConfig_Options, vec![Ports(80)]).unwrap_err();
+		assert!(e.to_string().contains("duplicate value: 80"));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_derive_configurable_unique_by_first() -> Result<(), Error> {
+		let config = config!(
+			VecAttrConfig,
+			VecAttrConfig_Options,
+			vec![
+				Headers(("A", "1")),
+				Headers(("B", "1")),
+				Headers(("A", "2")),
+			]
+		)?;
+		assert_eq!(
+			config.headers,
+			vec![
+				("Server".to_string(), "bmw".to_string()),
+				("A".to_string(), "2".to_string()),
+				("B".to_string(), "1".to_string()),
+			]
+		);
+
+		// a configured value replaces the default in place
+		let config = config!(
+			VecAttrConfig,
+			VecAttrConfig_Options,
+			vec![Headers(("A", "1")), Headers(("Server", "other"))]
+		)?;
+		assert_eq!(
+			config.headers,
+			vec![
+				("Server".to_string(), "other".to_string()),
+				("A".to_string(), "1".to_string()),
+			]
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_derive_configurable_sorted() -> Result<(), Error> {
+		let config = config!(
+			VecAttrConfig,
+			VecAttrConfig_Options,
+			vec![Names("z"), Names("a"), Names("m")]
+		)?;
+		assert_eq!(config.names, vec!["a", "m", "m", "z"]);
+		assert!(config.to_json().contains(r#""names":["a","m","m","z"]"#));
+
+		// the order of the options doesn't matter
+		let a = config!(
+			VecAttrConfig,
+			VecAttrConfig_Options,
+			vec![
+				Ids(3),
+				Ids(1),
+				Ids(2),
+				Params(("b", "1")),
+				Params(("a", "1"))
+			]
+		)?;
+		let b = config!(
+			VecAttrConfig,
+			VecAttrConfig_Options,
+			vec![
+				Params(("a", "1")),
+				Ids(2),
+				Params(("b", "1")),
+				Ids(3),
+				Ids(1)
+			]
+		)?;
+		assert_eq!(a, b);
+		assert_eq!(a.ids, vec![1, 2, 3]);
+		assert_eq!(
+			a.params,
+			vec![
+				("a".to_string(), "1".to_string()),
+				("b".to_string(), "1".to_string()),
+			]
+		);
+
+		// combined with unique and unique_by_first
+		let e = config!(
+			VecAttrConfig,
+			VecAttrConfig_Options,
+			vec![Ids(3), Ids(1), Ids(3)]
+		)
+		.unwrap_err();
+		assert!(e
+			.to_string()
+			.contains("config option (Ids) has a duplicate value: 3"));
+		let config = config!(
+			VecAttrConfig,
+			VecAttrConfig_Options,
+			vec![Params(("b", "1")), Params(("a", "1")), Params(("b", "2"))]
+		)?;
+		assert_eq!(
+			config.params,
+			vec![
+				("a".to_string(), "1".to_string()),
+				("b".to_string(), "2".to_string()),
+			]
+		);
+
+		Ok(())
+	}
 }
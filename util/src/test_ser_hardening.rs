@@ -0,0 +1,260 @@
+// Copyright (c) 2023-2024, The BitcoinMW Developers
+// Some code and concepts from:
+// * Grin: https://github.com/mimblewimble/grin
+// * Arti: https://gitlab.torproject.org/tpo/core/arti
+// * BitcoinMW: https://github.com/bitcoinmw/bitcoinmw
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(test)]
+mod test {
+	use crate as bmw_util;
+	use crate::{
+		lock_box, Array, Array2D, ArrayList, BitField, Hashset, Hashtable, Pattern,
+		SlabAllocatorConfig, SlabReader, SlabWriter, StringInterner, UtilBuilder,
+		GLOBAL_SLAB_ALLOCATOR,
+	};
+	use bmw_derive::Serializable;
+	use bmw_err::*;
+	use bmw_ser::hardening_tests::{hostile_inputs, read_target, Target};
+	use bmw_ser::*;
+
+	// the hostile inputs of the bmw_ser corpus are run against the SlabReader. The entries
+	// below cover the Serializable implementations of this crate and derived types.
+	#[derive(Clone, Copy, Debug)]
+	enum UtilTarget {
+		Ser(Target),
+		Shape,
+		Array,
+		Array2D,
+		ArrayList,
+		BitField,
+		Pattern,
+		Hashtable,
+		Hashset,
+		StringInterner,
+	}
+
+	#[derive(Serializable, Debug, PartialEq)]
+	enum Shape {
+		Point,
+		Circle(u32),
+		Rect { w: u32, h: u32 },
+	}
+
+	fn read_util_target<R: Reader>(target: UtilTarget, reader: &mut R) -> Result<(), Error> {
+		match target {
+			UtilTarget::Ser(target) => read_target(target, reader),
+			UtilTarget::Shape => Shape::read(reader).map(|_| ()),
+			UtilTarget::Array => Array::<u64>::read(reader).map(|_| ()),
+			UtilTarget::Array2D => Array2D::<u8>::read(reader).map(|_| ()),
+			UtilTarget::ArrayList => ArrayList::<u8>::read(reader).map(|_| ()),
+			UtilTarget::BitField => BitField::read(reader).map(|_| ()),
+			UtilTarget::Pattern => Pattern::read(reader).map(|_| ()),
+			UtilTarget::Hashtable => Box::<dyn Hashtable<u32, u32>>::read(reader).map(|_| ()),
+			UtilTarget::Hashset => Box::<dyn Hashset<u32>>::read(reader).map(|_| ()),
+			UtilTarget::StringInterner => StringInterner::read(reader).map(|_| ()),
+		}
+	}
+
+	fn usize_bytes(n: usize) -> Vec<u8> {
+		n.to_be_bytes().to_vec()
+	}
+
+	fn util_hostile_inputs() -> Vec<(&'static str, UtilTarget, Vec<u8>)> {
+		let mut ret = vec![];
+		for (name, target, bytes) in hostile_inputs() {
+			ret.push((name, UtilTarget::Ser(target), bytes));
+		}
+
+		ret.push((
+			"enum discriminant past the last variant",
+			UtilTarget::Shape,
+			vec![0, 3],
+		));
+
+		let mut v = usize_bytes(usize::MAX);
+		v.extend_from_slice(&7u64.to_be_bytes());
+		ret.push(("array length of usize::MAX", UtilTarget::Array, v));
+		ret.push(("array of length 0", UtilTarget::Array, usize_bytes(0)));
+
+		let mut v = usize_bytes(1 << 33);
+		v.extend(usize_bytes(1 << 33));
+		v.push(1);
+		ret.push(("array2d size overflowing usize", UtilTarget::Array2D, v));
+
+		let mut v = usize_bytes(1 << 20);
+		v.extend(usize_bytes(1 << 20));
+		v.push(1);
+		ret.push(("array2d shorter than its size", UtilTarget::Array2D, v));
+
+		ret.push((
+			"array list of length 0",
+			UtilTarget::ArrayList,
+			usize_bytes(0),
+		));
+
+		let mut v = u32::MAX.to_be_bytes().to_vec();
+		v.push(0);
+		ret.push(("bitfield length of u32::MAX", UtilTarget::BitField, v));
+
+		let mut v = 1u32.to_be_bytes().to_vec();
+		v.push(0x80);
+		ret.push(("bitfield bits set past its length", UtilTarget::BitField, v));
+
+		let mut v = usize_bytes(1);
+		v.push(b'a');
+		v.extend_from_slice(&[1, 9, 0]);
+		v.extend(usize_bytes(0));
+		ret.push((
+			"pattern bool byte other than 0 or 1",
+			UtilTarget::Pattern,
+			v,
+		));
+
+		let mut v = usize_bytes(usize::MAX);
+		v.extend_from_slice(&0.75f64.to_bits().to_be_bytes());
+		v.extend(usize_bytes(0));
+		ret.push((
+			"hashtable max entries of usize::MAX",
+			UtilTarget::Hashtable,
+			v,
+		));
+
+		let mut v = usize_bytes(100);
+		v.extend_from_slice(&f64::NAN.to_bits().to_be_bytes());
+		v.extend(usize_bytes(0));
+		ret.push(("hashset max load factor of NaN", UtilTarget::Hashset, v));
+
+		let mut v = usize_bytes(100);
+		v.extend(usize_bytes(usize::MAX));
+		v.extend(usize_bytes(usize::MAX));
+		v.extend(usize_bytes(0));
+		ret.push((
+			"interner slab size of usize::MAX",
+			UtilTarget::StringInterner,
+			v,
+		));
+
+		ret
+	}
+
+	// the offset in the first slab that `len` bytes are written at so that they end at the
+	// end of the last slab of the chain. Reading past the data then fails like it does at the
+	// end of a byte slice.
+	fn aligned_offset(len: usize, bytes_per_slab: usize) -> usize {
+		match len % bytes_per_slab {
+			0 if len == 0 => bytes_per_slab,
+			0 => 0,
+			rem => bytes_per_slab - rem,
+		}
+	}
+
+	// read `bytes` with a SlabReader over a new slab allocator
+	fn read_locked(target: UtilTarget, bytes: &[u8]) -> Result<(), Error> {
+		let config = SlabAllocatorConfig {
+			slab_size: 64,
+			slab_count: 1_000,
+			..Default::default()
+		};
+		let mut slabs = lock_box!(UtilBuilder::build_sync_slabs())?;
+		let slab_id = {
+			let mut slabs = slabs.wlock()?;
+			let guard = slabs.guard()?;
+			(**guard).init(config)?;
+			let mut slab = (**guard).allocate()?;
+			// the next pointer is max so that the writer allocates new slabs
+			slab.get_mut().fill(0xFF);
+			slab.id()
+		};
+		let mut reader = SlabReader::new(Some(slabs.clone()), slab_id, None)?;
+		let offset = aligned_offset(bytes.len(), reader.bytes_per_slab);
+		let mut writer = SlabWriter::new(Some(slabs.clone()), slab_id, None)?;
+		writer.seek(slab_id, offset);
+		writer.write_fixed_bytes(bytes)?;
+		reader.seek(slab_id, offset);
+		read_util_target(target, &mut reader)
+	}
+
+	// read `bytes` with a SlabReader over the thread local slab allocator, which also
+	// supports read_bytes_ref
+	fn read_global(target: UtilTarget, bytes: &[u8]) -> Result<(), Error> {
+		// the global slab allocator is initialized by the SlabWriter
+		let mut writer = SlabWriter::new(None, 0, None)?;
+		let slab_id = GLOBAL_SLAB_ALLOCATOR.with(|f| -> Result<usize, Error> {
+			let mut slab = unsafe { f.get().as_mut().unwrap().allocate()? };
+			slab.get_mut().fill(0xFF);
+			Ok(slab.id())
+		})?;
+		let mut reader = SlabReader::new(None, slab_id, None)?;
+		let offset = aligned_offset(bytes.len(), reader.bytes_per_slab);
+		writer.seek(slab_id, offset);
+		writer.write_fixed_bytes(bytes)?;
+		reader.seek(slab_id, offset);
+		read_util_target(target, &mut reader)
+	}
+
+	fn read_bin(target: UtilTarget, mut bytes: &[u8]) -> Result<(), Error> {
+		read_util_target(target, &mut BinReader::from_slice(&mut bytes))
+	}
+
+	#[test]
+	fn test_hostile_inputs_all_readers() -> Result<(), Error> {
+		let inputs = util_hostile_inputs();
+		assert!(inputs.len() >= hostile_inputs().len() + 12);
+		for (name, target, bytes) in inputs {
+			assert!(read_bin(target, &bytes).is_err(), "bin accepted: {}", name);
+			assert!(
+				read_locked(target, &bytes).is_err(),
+				"slab accepted: {}",
+				name
+			);
+			assert!(
+				read_global(target, &bytes).is_err(),
+				"global accepted: {}",
+				name
+			);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_hostile_inputs_valid_data() -> Result<(), Error> {
+		// the readers used for the corpus accept valid data, so each error above is caused by
+		// the hostile input
+		let mut v = vec![];
+		serialize(&mut v, &(7u64, "seven".to_string()))?;
+		let target = UtilTarget::Ser(Target::U64);
+		read_bin(target, &v[0..8])?;
+		read_locked(target, &v[0..8])?;
+		read_global(target, &v[0..8])?;
+		let target = UtilTarget::Ser(Target::Tuple);
+		let mut v = vec![];
+		serialize(&mut v, &(7u32, "seven".to_string()))?;
+		read_bin(target, &v)?;
+		read_locked(target, &v)?;
+		read_global(target, &v)?;
+
+		let mut v = vec![];
+		serialize(&mut v, &Shape::Rect { w: 1, h: 2 })?;
+		read_locked(UtilTarget::Shape, &v)?;
+		assert!(read_locked(UtilTarget::Shape, &v[0..v.len() - 1]).is_err());
+
+		let mut v = vec![];
+		let a = UtilBuilder::build_array2d(2, 3, &9u8)?;
+		serialize(&mut v, &a)?;
+		read_global(UtilTarget::Array2D, &v)?;
+		let a: Array2D<u8> = deserialize(&mut &v[..])?;
+		assert_eq!(a.as_flat(), &[9u8; 6]);
+		Ok(())
+	}
+}
@@ -18,11 +18,14 @@
 
 #[cfg(test)]
 mod test {
+	use bmw_deps::chrono::{DateTime, NaiveDate, Utc};
 	use bmw_deps::rand;
 	use bmw_derive::Serializable;
 	use bmw_err::*;
 	use bmw_ser::*;
 	use std::fmt::Debug;
+	use std::rc::Rc;
+	use std::sync::Arc;
 
 	#[derive(Serializable, PartialEq, Debug)]
 	struct OtherSer {
@@ -54,6 +57,153 @@ mod test {
 		z: Option<Vec<OtherSer>>,
 	}
 
+	#[derive(Serializable, PartialEq, Debug)]
+	enum Expr {
+		Leaf(u64),
+		Node(Box<Expr>, Box<Expr>),
+	}
+
+	#[derive(Serializable, PartialEq, Debug)]
+	enum Mixed {
+		Unit,
+		Tuple(u64, String),
+		Struct {
+			a: u64,
+			b: String,
+			writer: Option<Vec<u8>>,
+		},
+		Single(u8),
+		Map {
+			m: Vec<(u32, u32)>,
+		},
+	}
+
+	#[derive(Serializable, PartialEq, Debug)]
+	enum UnitOnly {
+		First,
+		Second,
+		Third,
+	}
+
+	#[derive(Serializable, PartialEq, Debug)]
+	struct Parent {
+		id: u32,
+		child: Option<Box<Child>>,
+	}
+
+	#[derive(Serializable, PartialEq, Debug)]
+	struct Child {
+		name: String,
+		parent: Option<Box<Parent>>,
+	}
+
+	#[derive(Serializable, PartialEq, Debug)]
+	struct Shared {
+		rc: Rc<OtherSer>,
+		arc: Arc<Vec<u8>>,
+	}
+
+	mod v1 {
+		use bmw_derive::Serializable;
+
+		#[derive(Serializable)]
+		#[ser(fingerprint)]
+		pub struct Handshake {
+			pub version: u32,
+			pub name: String,
+			pub peers: Vec<(u64, String)>,
+		}
+
+		#[derive(Serializable)]
+		#[ser(fingerprint)]
+		pub enum Message {
+			Ping,
+			Data(Vec<u8>, u64),
+			Close { reason: String },
+		}
+	}
+
+	// the same layout as v1 with different formatting, docs and visibility and a skipped field
+	mod v1_copy {
+		use bmw_derive::Serializable;
+
+		/// A handshake.
+		#[derive(Serializable, Debug, PartialEq)]
+		#[ser(fingerprint)]
+		pub struct Handshake {
+			/// The protocol version.
+			version: u32,
+			pub(crate) name: String,
+			#[ser(skip)]
+			pub local: Option<u64>,
+			pub peers: Vec<(u64, String)>,
+		}
+
+		#[derive(Serializable)]
+		#[ser(fingerprint)]
+		pub enum Message {
+			Ping,
+			Data(Vec<u8>, u64),
+			/// Closes the connection.
+			Close {
+				reason: String,
+			},
+		}
+
+		pub fn handshake(local: Option<u64>) -> Handshake {
+			Handshake {
+				version: 1,
+				name: "bmw".to_string(),
+				local,
+				peers: vec![(1, "peer".to_string())],
+			}
+		}
+	}
+
+	mod v2 {
+		use bmw_derive::Serializable;
+
+		#[derive(Serializable)]
+		#[ser(fingerprint)]
+		pub struct Handshake {
+			pub name: String,
+			pub version: u32,
+			pub peers: Vec<(u64, String)>,
+		}
+
+		#[derive(Serializable)]
+		#[ser(fingerprint)]
+		pub enum Message {
+			Data(Vec<u8>, u64),
+			Ping,
+			Close { reason: String },
+		}
+	}
+
+	mod v3 {
+		use bmw_derive::Serializable;
+
+		#[derive(Serializable)]
+		#[ser(fingerprint)]
+		pub struct Handshake {
+			pub version: u32,
+			pub user: String,
+			pub peers: Vec<(u64, String)>,
+		}
+
+		#[derive(Serializable)]
+		#[ser(fingerprint)]
+		pub enum Message {
+			Ping,
+			Data(Vec<u8>, u32),
+			Close { reason: String },
+		}
+	}
+
+	// the fingerprints of v1::Handshake and v1::Message
+	const HANDSHAKE_FINGERPRINT: u64 = 0x2b74f530ff87e6d9;
+	const MESSAGE_FINGERPRINT: u64 = 0xa22a0fbf2d01c253;
+
 	// helper function that serializes and deserializes a Serializable and tests them for
 	// equality
 	fn ser_helper<S: Serializable + Debug + PartialEq>(ser_out: S) -> Result<(), Error> {
@@ -140,4 +290,280 @@ mod test {
 		ser_helper(ser_out)?;
 		Ok(())
 	}
+	#[test]
+	fn test_derive_recursive() -> Result<(), Error> {
+		// a right leaning tree 1000 levels deep
+		let mut expr = Expr::Leaf(1000);
+		for i in 0..1000 {
+			expr = Expr::Node(Box::new(Expr::Leaf(i)), Box::new(expr));
+		}
+		ser_helper(expr)?;
+
+		// mutually recursive structs
+		let parent = Parent {
+			id: 1,
+			child: Some(Box::new(Child {
+				name: "child1".to_string(),
+				parent: Some(Box::new(Parent {
+					id: 2,
+					child: Some(Box::new(Child {
+						name: "child2".to_string(),
+						parent: None,
+					})),
+				})),
+			})),
+		};
+		ser_helper(parent)?;
+
+		let other = Rc::new(OtherSer {
+			a: 7,
+			b: "shared".to_string(),
+		});
+		let shared = Shared {
+			rc: other.clone(),
+			arc: Arc::new(vec![1, 2, 3]),
+		};
+		ser_helper(shared)?;
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_derive_enum_variants() -> Result<(), Error> {
+		ser_helper(Mixed::Unit)?;
+		ser_helper(Mixed::Tuple(7, "seven".to_string()))?;
+		ser_helper(Mixed::Struct {
+			a: 8,
+			b: "eight".to_string(),
+			writer: Some(vec![1, 2, 3]),
+		})?;
+		ser_helper(Mixed::Struct {
+			a: u64::MAX,
+			b: "".to_string(),
+			writer: None,
+		})?;
+		ser_helper(Mixed::Single(9))?;
+		ser_helper(Mixed::Map {
+			m: vec![(1, 2), (3, 4)],
+		})?;
+		ser_helper(vec![Mixed::Unit, Mixed::Single(1), Mixed::Unit])?;
+
+		// mixed enums use a u16 discriminant and fields are written in declaration order
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &Mixed::Unit)?;
+		assert_eq!(v, 0u16.to_be_bytes());
+		let mut v: Vec<u8> = vec![];
+		let s = Mixed::Struct {
+			a: 1,
+			b: "x".to_string(),
+			writer: None,
+		};
+		serialize(&mut v, &s)?;
+		assert_eq!(&v[0..2], &2u16.to_be_bytes());
+		assert_eq!(&v[2..10], &1u64.to_be_bytes());
+
+		// an out of range discriminant is an error
+		let v = 5u16.to_be_bytes();
+		let mixed: Result<Mixed, Error> = deserialize(&mut &v[..]);
+		assert!(matches!(
+			mixed.unwrap_err().kind(),
+			ErrorKind::CorruptedData(_)
+		));
+
+		// unit only enums use a single byte
+		ser_helper(UnitOnly::First)?;
+		ser_helper(UnitOnly::Second)?;
+		ser_helper(UnitOnly::Third)?;
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &UnitOnly::Third)?;
+		assert_eq!(v, vec![2u8]);
+		let v = [3u8];
+		let unit: Result<UnitOnly, Error> = deserialize(&mut &v[..]);
+		assert!(matches!(
+			unit.unwrap_err().kind(),
+			ErrorKind::CorruptedData(_)
+		));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_derive_max_depth() -> Result<(), Error> {
+		// build the serialized form of a tree that is deeper than the read limit by hand
+		// so that writing it doesn't need to recurse.
+		let mut v: Vec<u8> = vec![];
+		for _ in 0..10_000 {
+			v.extend_from_slice(&1u16.to_be_bytes());
+			v.extend_from_slice(&0u16.to_be_bytes());
+			v.extend_from_slice(&0u64.to_be_bytes());
+		}
+		v.extend_from_slice(&0u16.to_be_bytes());
+		v.extend_from_slice(&0u64.to_be_bytes());
+
+		let expr: Result<Expr, Error> = deserialize(&mut &v[..]);
+		let e = expr.unwrap_err().kind();
+		assert!(matches!(e, ErrorKind::CorruptedData(_)));
+
+		// the depth counter is reset after the error so shallow reads still work
+		let expr = Expr::Node(Box::new(Expr::Leaf(1)), Box::new(Expr::Leaf(2)));
+		ser_helper(expr)?;
+		Ok(())
+	}
+
+	#[derive(Serializable, PartialEq, Debug)]
+	struct Gated {
+		a: u32,
+		#[cfg(target_os = "linux")]
+		b: u64,
+		#[cfg(not(target_os = "linux"))]
+		c: String,
+		#[cfg_attr(test, allow(dead_code))]
+		d: String,
+	}
+
+	#[derive(Serializable, PartialEq, Debug)]
+	enum GatedEnum {
+		First(u8),
+		#[cfg(target_os = "linux")]
+		Linux(u32),
+		Named {
+			a: u8,
+			#[cfg(target_os = "linux")]
+			b: u16,
+		},
+	}
+
+	#[test]
+	fn test_derive_cfg_fields() -> Result<(), Error> {
+		let gated = Gated {
+			a: 1,
+			#[cfg(target_os = "linux")]
+			b: 2,
+			#[cfg(not(target_os = "linux"))]
+			c: "c".to_string(),
+			d: "d".to_string(),
+		};
+		ser_helper(gated)?;
+		ser_helper(GatedEnum::First(3))?;
+		ser_helper(GatedEnum::Named {
+			a: 4,
+			#[cfg(target_os = "linux")]
+			b: 5,
+		})?;
+
+		// gated variants are removed before the derive runs so the discriminants of the
+		// variants after them differ between platforms
+		let mut v: Vec<u8> = vec![];
+		serialize(
+			&mut v,
+			&GatedEnum::Named {
+				a: 4,
+				#[cfg(target_os = "linux")]
+				b: 5,
+			},
+		)?;
+		#[cfg(target_os = "linux")]
+		assert_eq!(&v[0..2], &2u16.to_be_bytes());
+		#[cfg(not(target_os = "linux"))]
+		assert_eq!(&v[0..2], &1u16.to_be_bytes());
+
+		#[cfg(target_os = "linux")]
+		{
+			ser_helper(GatedEnum::Linux(6))?;
+			let mut v: Vec<u8> = vec![];
+			let gated = Gated {
+				a: 1,
+				b: 2,
+				d: "".to_string(),
+			};
+			serialize(&mut v, &gated)?;
+			assert_eq!(&v[4..12], &2u64.to_be_bytes());
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_derive_fingerprint() -> Result<(), Error> {
+		// structurally identical types have the same fingerprint
+		assert_eq!(
+			v1::Handshake::SCHEMA_FINGERPRINT,
+			v1_copy::Handshake::SCHEMA_FINGERPRINT
+		);
+		assert_eq!(
+			v1::Message::SCHEMA_FINGERPRINT,
+			v1_copy::Message::SCHEMA_FINGERPRINT
+		);
+		assert_eq!(
+			v1::Handshake::schema_fingerprint(),
+			v1::Handshake::SCHEMA_FINGERPRINT
+		);
+
+		// reordering fields or variants, renaming a field or changing a type changes it
+		assert_ne!(
+			v1::Handshake::SCHEMA_FINGERPRINT,
+			v2::Handshake::SCHEMA_FINGERPRINT
+		);
+		assert_ne!(
+			v1::Message::SCHEMA_FINGERPRINT,
+			v2::Message::SCHEMA_FINGERPRINT
+		);
+		assert_ne!(
+			v1::Handshake::SCHEMA_FINGERPRINT,
+			v3::Handshake::SCHEMA_FINGERPRINT
+		);
+		assert_ne!(
+			v1::Message::SCHEMA_FINGERPRINT,
+			v3::Message::SCHEMA_FINGERPRINT
+		);
+		assert_ne!(
+			v1::Handshake::SCHEMA_FINGERPRINT,
+			v1::Message::SCHEMA_FINGERPRINT
+		);
+
+		// the value is computed from the schema only, so it is the same in every build
+		assert_eq!(v1::Handshake::SCHEMA_FINGERPRINT, HANDSHAKE_FINGERPRINT);
+		assert_eq!(v1::Message::SCHEMA_FINGERPRINT, MESSAGE_FINGERPRINT);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_derive_skip() -> Result<(), Error> {
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &v1_copy::handshake(Some(7)))?;
+		let mut v_none: Vec<u8> = vec![];
+		serialize(&mut v_none, &v1_copy::handshake(None))?;
+		assert_eq!(v, v_none);
+
+		// skipped fields are read as their default value
+		let handshake: v1_copy::Handshake = deserialize(&mut &v[..])?;
+		assert_eq!(handshake, v1_copy::handshake(None));
+		Ok(())
+	}
+
+	#[derive(Serializable, PartialEq, Debug)]
+	struct Snapshot {
+		taken: DateTime<Utc>,
+		day: NaiveDate,
+		history: Vec<DateTime<Utc>>,
+	}
+
+	#[test]
+	fn test_derive_chrono() -> Result<(), Error> {
+		let snapshot = Snapshot {
+			taken: DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap(),
+			day: NaiveDate::from_ymd_opt(1969, 7, 20).unwrap(),
+			history: vec![
+				DateTime::from_timestamp(-86_400, 0).unwrap(),
+				DateTime::from_timestamp(0, 0).unwrap(),
+			],
+		};
+		let mut v: Vec<u8> = vec![];
+		serialize(&mut v, &snapshot)?;
+		// 12 bytes per timestamp, 4 for the date and 8 for the length of the vec
+		assert_eq!(v.len(), 12 + 4 + 8 + 2 * 12);
+		let snapshot_in: Snapshot = deserialize(&mut &v[..])?;
+		assert_eq!(snapshot_in, snapshot);
+		Ok(())
+	}
 }
@@ -17,24 +17,79 @@
 
 use crate::constants::*;
 use crate::types::{
-	FutureWrapper, Lock, ThreadPoolConfig, ThreadPoolHandle, ThreadPoolImpl, ThreadPoolState,
+	FutureWrapper, Lock, OnPanicInfo, RunningGuard, ScheduledTask, Scheduler, SchedulerState,
+	TaskContextGuard, ThreadPoolConfig, ThreadPoolHandle, ThreadPoolImpl, ThreadPoolState,
+};
+use crate::{
+	LockBox, PanicInfoCapture, PoolResult, ScheduleHandle, ScheduleStats, TaskContext, ThreadPool,
+	ThreadPoolExecutor, ThreadPoolShutdownMode, ThreadPoolStopper, UtilBuilder,
 };
-use crate::{LockBox, PoolResult, ThreadPool, ThreadPoolExecutor, ThreadPoolStopper, UtilBuilder};
 use bmw_conf::ConfigOptionName as CN;
 use bmw_conf::{ConfigBuilder, ConfigOption};
 use bmw_deps::futures::executor::block_on;
 use bmw_err::{cbreak, err, Error};
 use bmw_log::*;
 use std::any::Any;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt::{Debug, Formatter};
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::mpsc::{sync_channel, Receiver};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread::{sleep, spawn};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 info!();
 
+thread_local! {
+	// the TaskContext of this thread. See with_task_context.
+	static TASK_CONTEXT: RefCell<Option<TaskContext>> = const { RefCell::new(None) };
+}
+
+/// Execute `f` with `ctx` as the [`crate::TaskContext`] of the current thread. Tasks submitted
+/// to a [`crate::ThreadPool`] from within `f` carry `ctx` with them. The previous context of the
+/// thread is restored when `f` returns or panics. See [`crate::TaskContext`] for an example.
+pub fn with_task_context<R, F>(ctx: TaskContext, f: F) -> R
+where
+	F: FnOnce() -> R,
+{
+	let _guard = install_task_context(Some(ctx));
+	f()
+}
+
+/// Returns a copy of the [`crate::TaskContext`] of the current thread or [`None`] if there is
+/// none. Inside a [`crate::ThreadPool`] task, this is the context of the thread that submitted
+/// the task.
+pub fn current_task_context() -> Option<TaskContext> {
+	TASK_CONTEXT.with(|context| context.borrow().clone())
+}
+
+fn install_task_context(ctx: Option<TaskContext>) -> TaskContextGuard {
+	let prev = TASK_CONTEXT.with(|context| context.replace(ctx));
+	TaskContextGuard { prev }
+}
+
+impl Drop for TaskContextGuard {
+	fn drop(&mut self) {
+		let prev = self.prev.take();
+		// the thread local may already be gone if the thread is exiting
+		let _ = TASK_CONTEXT.try_with(|context| context.replace(prev));
+	}
+}
+
+impl Debug for TaskContext {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+		f.debug_struct("TaskContext")
+			.field("correlation_id", &self.correlation_id)
+			.field("deadline_millis", &self.deadline_millis)
+			.field("data", &self.data.as_ref().map(|_| ".."))
+			.finish()
+	}
+}
+
 impl<T, E> PoolResult<T, E> {
 	pub fn is_err(&self) -> bool {
 		match self {
@@ -65,6 +120,173 @@ impl<T> ThreadPoolHandle<T> {
 	}
 }
 
+impl ScheduleHandle {
+	/// Returns the id of the scheduled task. This id is passed to the on_panic handler if the
+	/// task panics.
+	pub fn id(&self) -> u128 {
+		self.id
+	}
+
+	/// Cancel the scheduled task. A task which has not started executing yet will not run.
+	/// Recurring tasks are not run again, but an execution that is already in progress
+	/// completes normally.
+	pub fn cancel(&self) {
+		self.cancelled.store(true, SeqCst);
+	}
+
+	/// Returns true if [`crate::ScheduleHandle::cancel`] has been called or the thread pool
+	/// has been stopped.
+	pub fn is_cancelled(&self) -> bool {
+		self.cancelled.load(SeqCst)
+	}
+}
+
+impl PartialEq for ScheduledTask {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for ScheduledTask {}
+
+impl PartialOrd for ScheduledTask {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for ScheduledTask {
+	// reversed so that the BinaryHeap (a max-heap) pops the earliest deadline first
+	fn cmp(&self, other: &Self) -> Ordering {
+		other
+			.deadline
+			.cmp(&self.deadline)
+			.then_with(|| other.seq.cmp(&self.seq))
+	}
+}
+
+impl Drop for RunningGuard {
+	fn drop(&mut self) {
+		self.running.store(false, SeqCst);
+	}
+}
+
+impl Debug for Scheduler {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+		write!(f, "Scheduler")
+	}
+}
+
+impl Scheduler {
+	fn new() -> Self {
+		Self {
+			state: Mutex::new(SchedulerState {
+				heap: BinaryHeap::new(),
+				seq: 0,
+				stop: false,
+				stats: ScheduleStats::default(),
+			}),
+			cond: Condvar::new(),
+		}
+	}
+
+	fn push(&self, mut task: ScheduledTask) -> Result<(), Error> {
+		let mut state = self.state.lock()?;
+		if state.stop {
+			let fmt = "Thread pool has been stopped";
+			return Err(err!(ErrKind::IllegalState, fmt));
+		}
+		task.seq = state.seq;
+		state.seq = state.seq.wrapping_add(1);
+		state.heap.push(task);
+		self.cond.notify_one();
+		Ok(())
+	}
+
+	// cancel all outstanding schedules and end the timer thread
+	fn stop(&self) -> Result<(), Error> {
+		let mut state = self.state.lock()?;
+		state.stop = true;
+		for task in state.heap.drain() {
+			task.cancelled.store(true, SeqCst);
+		}
+		self.cond.notify_all();
+		Ok(())
+	}
+
+	fn stats(&self) -> Result<ScheduleStats, Error> {
+		let state = self.state.lock()?;
+		let mut stats = state.stats.clone();
+		stats.pending = state.heap.len();
+		Ok(stats)
+	}
+
+	fn run_timer(scheduler: Arc<Scheduler>) {
+		spawn(move || -> Result<(), Error> {
+			let poison = |_| err!(ErrKind::Poison, "scheduler lock poisoned");
+			loop {
+				let (mut task, dispatch) = {
+					let mut state = scheduler.state.lock()?;
+					loop {
+						if state.stop {
+							return Ok(());
+						}
+						let now = Instant::now();
+						let timeout = match state.heap.peek() {
+							Some(next) if next.deadline <= now => None,
+							Some(next) => Some(next.deadline - now),
+							None => {
+								state = scheduler.cond.wait(state)?;
+								continue;
+							}
+						};
+						if let Some(timeout) = timeout {
+							state = scheduler
+								.cond
+								.wait_timeout(state, timeout)
+								.map_err(poison)?
+								.0;
+							continue;
+						}
+						let task = match state.heap.pop() {
+							Some(task) => task,
+							None => continue,
+						};
+						if task.cancelled.load(SeqCst) {
+							continue;
+						}
+						// a recurring task which is still running skips this tick
+						if task.running.swap(true, SeqCst) {
+							state.stats.skipped += 1;
+							break (task, false);
+						}
+						state.stats.dispatched += 1;
+						break (task, true);
+					}
+				};
+
+				// dispatch without holding the lock since the worker queue may be full
+				if dispatch {
+					if let Err(e) = (task.dispatch)() {
+						warn!("could not dispatch scheduled task: {}", e)?;
+						continue;
+					}
+				}
+
+				if let Some(interval) = task.interval {
+					// skip ahead rather than firing a burst of ticks after a delay
+					let now = Instant::now();
+					task.deadline += interval;
+					if task.deadline < now {
+						task.deadline = now + interval;
+					}
+					let _ = scheduler.push(task);
+				}
+			}
+		});
+	}
+}
+
 unsafe impl<T, E> Send for PoolResult<T, E> {}
 unsafe impl<T, E> Sync for PoolResult<T, E> {}
 
@@ -107,6 +329,7 @@ where
 				cur_size: min_size,
 				config: config.clone(),
 				stop,
+				shutdown: None,
 			};
 			let state = UtilBuilder::build_lock_box(tps)?;
 
@@ -119,24 +342,103 @@ where
 				rx,
 				state,
 				on_panic: None,
+				on_panic_info: None,
+				scheduler: Arc::new(Scheduler::new()),
+				shutting_down: Arc::new(AtomicBool::new(false)),
 			};
 			Ok(ret)
 		}
 	}
 
+	fn schedule_task<F>(
+		&self,
+		delay: Duration,
+		interval: Option<Duration>,
+		task: F,
+	) -> Result<ScheduleHandle, Error>
+	where
+		F: FnMut() -> Result<T, Error> + Send + 'static,
+	{
+		let tx = match self.tx.as_ref() {
+			Some(tx) => tx.clone(),
+			None => {
+				let fmt = "Thread pool has not been initialized";
+				return Err(err!(ErrKind::IllegalState, fmt));
+			}
+		};
+
+		let id = bmw_deps::rand::random();
+		let cancelled = Arc::new(AtomicBool::new(false));
+		let running = Arc::new(AtomicBool::new(false));
+		let context = current_task_context();
+		let dispatch = Self::dispatcher(tx, id, task, context, cancelled.clone(), running.clone());
+		self.scheduler.push(ScheduledTask {
+			deadline: Instant::now() + delay,
+			seq: 0,
+			interval,
+			cancelled: cancelled.clone(),
+			running,
+			dispatch,
+		})?;
+		Ok(ScheduleHandle { id, cancelled })
+	}
+
+	// build the closure the timer thread calls to send one execution of a scheduled task to
+	// the worker queue. The running flag is cleared when the execution completes.
+	fn dispatcher<F>(
+		tx: SyncSender<FutureWrapper<T>>,
+		id: u128,
+		task: F,
+		context: Option<TaskContext>,
+		cancelled: Arc<AtomicBool>,
+		running: Arc<AtomicBool>,
+	) -> Box<dyn FnMut() -> Result<(), Error> + Send>
+	where
+		F: FnMut() -> Result<T, Error> + Send + 'static,
+	{
+		let task = Arc::new(Mutex::new(task));
+		Box::new(move || -> Result<(), Error> {
+			let task = task.clone();
+			let cancelled = cancelled.clone();
+			let guard = RunningGuard {
+				running: running.clone(),
+			};
+			let f = async move {
+				let _guard = guard;
+				if cancelled.load(SeqCst) {
+					return Err(err!(ErrKind::IllegalState, "scheduled task cancelled"));
+				}
+				let mut task = task.lock()?;
+				(task)()
+			};
+			// nobody waits on the result of a scheduled task
+			let (res_tx, _) = sync_channel(1);
+			tx.send(FutureWrapper {
+				f: Box::pin(f),
+				tx: res_tx,
+				id,
+				context: context.clone(),
+			})?;
+			Ok(())
+		})
+	}
+
 	fn run_thread<R: 'static>(
 		rx: Arc<Mutex<Receiver<FutureWrapper<R>>>>,
 		mut state: Box<dyn LockBox<ThreadPoolState>>,
 		mut on_panic: Option<Pin<Box<OnPanic>>>,
+		mut on_panic_info: Option<Box<dyn LockBox<OnPanicInfo>>>,
 	) -> Result<(), Error> {
 		spawn(move || -> Result<(), Error> {
 			loop {
 				let rx = rx.clone();
 				let mut state_clone = state.clone();
 				let on_panic_clone = on_panic.clone();
+				let on_panic_info_clone = on_panic_info.clone();
 				let mut id = UtilBuilder::build_lock(0)?;
 				let id_clone = id.clone();
 				let jh = spawn(move || -> Result<(), Error> {
+					PanicInfoCapture::enable();
 					loop {
 						let (next, do_run_thread) = {
 							let mut do_run_thread = false;
@@ -152,17 +454,26 @@ where
 								}
 								guard.waiting += 1;
 							}
-							let rx = rx.lock()?;
-							let ret = rx.recv()?;
+							let ret = Self::next_task(&*rx.lock()?, &*state_clone)?;
 							let mut state = state_clone.wlock()?;
 							let guard = &mut **state.guard()?;
 							guard.waiting = guard.waiting.saturating_sub(1);
-							if guard.waiting == 0 {
+							let ret = match ret {
+								Some(ret) => ret,
+								// shutting down and the queue is empty
+								None => return Ok(()),
+							};
+							if guard.waiting == 0 && guard.shutdown.is_none() {
 								if guard.cur_size < guard.config.max_size {
 									guard.cur_size += 1;
 									do_run_thread = true;
 								}
 							}
+							if guard.shutdown == Some(ThreadPoolShutdownMode::DiscardPending) {
+								let fmt = "thread pool shut down before the task started";
+								let _ = ret.tx.send(PoolResult::Err(err!(ErrKind::Cancelled, fmt)));
+								continue;
+							}
 							debug!("cur state = {:?}", guard)?;
 							(ret, do_run_thread)
 						};
@@ -173,6 +484,7 @@ where
 								rx.clone(),
 								state_clone.clone(),
 								on_panic_clone.clone(),
+								on_panic_info_clone.clone(),
 							)?;
 						}
 
@@ -181,7 +493,11 @@ where
 							let guard = id.guard()?;
 							(**guard) = next.id;
 						}
-						match block_on(next.f) {
+						let res = {
+							let _context = install_task_context(next.context);
+							block_on(next.f)
+						};
+						match res {
 							Ok(res) => {
 								let send_res = next.tx.send(PoolResult::Ok(res));
 								if send_res.is_err() {
@@ -200,6 +516,7 @@ where
 					}
 				});
 
+				let thread_id = jh.thread().id();
 				let res = jh.join();
 				if res.is_ok() {
 					let mut state = state.wlock()?;
@@ -209,6 +526,14 @@ where
 					cbreak!(true);
 				} else {
 					let e = res.unwrap_err();
+					if let Some(on_panic_info) = on_panic_info.as_mut() {
+						let info = PanicInfoCapture::from_payload(&*e, thread_id);
+						let id = **id_clone.rlock()?.guard()?;
+						let mut on_panic_info = on_panic_info.wlock_ignore_poison()?;
+						if let Err(e) = (on_panic_info.guard()?)(id, &info) {
+							warn!("on_panic_info handler generated error: {}", e)?;
+						}
+					}
 					if on_panic.is_some() {
 						let on_panic = on_panic.as_mut().unwrap();
 						debug!("found an onpanic")?;
@@ -226,6 +551,79 @@ where
 		});
 		Ok(())
 	}
+
+	// wait for the next task. None is returned once the thread pool is shutting down and the
+	// queue is empty. Idle workers poll so that they notice the shutdown even though
+	// executors may still hold a sender.
+	fn next_task<R>(
+		rx: &Receiver<FutureWrapper<R>>,
+		state: &dyn LockBox<ThreadPoolState>,
+	) -> Result<Option<FutureWrapper<R>>, Error> {
+		let poll = Duration::from_millis(THREAD_POOL_SHUTDOWN_POLL_MILLIS);
+		loop {
+			if (**state.rlock()?.guard()?).shutdown.is_some() {
+				return Ok(rx.try_recv().ok());
+			}
+			match rx.recv_timeout(poll) {
+				Ok(next) => return Ok(Some(next)),
+				Err(RecvTimeoutError::Timeout) => {}
+				Err(RecvTimeoutError::Disconnected) => {
+					let fmt = "thread pool channel disconnected";
+					return Err(err!(ErrKind::IllegalState, fmt));
+				}
+			}
+		}
+	}
+
+	fn shutdown_impl(
+		&mut self,
+		mode: ThreadPoolShutdownMode,
+		timeout: Option<Duration>,
+	) -> Result<(), Error> {
+		if self.tx.is_none() {
+			let fmt = "Thread pool has not been initialized";
+			return Err(err!(ErrKind::IllegalState, fmt));
+		}
+		self.shutting_down.store(true, SeqCst);
+		(**self.state.wlock()?.guard()?).shutdown = Some(mode);
+		self.tx = None;
+		self.scheduler.stop()?;
+
+		let start = Instant::now();
+		loop {
+			let cur_size = (**self.state.rlock()?.guard()?).cur_size;
+			cbreak!(cur_size == 0);
+			if let Some(timeout) = timeout {
+				if start.elapsed() >= timeout {
+					warn!(
+						"detaching {} thread pool workers at the shutdown deadline",
+						cur_size
+					)?;
+					break;
+				}
+			}
+			sleep(Duration::from_millis(1));
+		}
+		Ok(())
+	}
+}
+
+impl<T, OnPanic> Drop for ThreadPoolImpl<T, OnPanic>
+where
+	T: 'static + Send + Sync,
+	OnPanic: FnMut(u128, Box<dyn Any + Send>) -> Result<(), Error>
+		+ Send
+		+ 'static
+		+ Clone
+		+ Sync
+		+ Unpin,
+{
+	fn drop(&mut self) {
+		if self.tx.is_some() {
+			let timeout = Some(Duration::from_millis(THREAD_POOL_DROP_TIMEOUT_MILLIS));
+			let _ = self.shutdown_impl(ThreadPoolShutdownMode::Drain, timeout);
+		}
+	}
 }
 
 impl<T, OnPanic> ThreadPool<T, OnPanic> for ThreadPoolImpl<T, OnPanic>
@@ -242,6 +640,10 @@ where
 	where
 		F: Future<Output = Result<T, Error>> + Send + 'static,
 	{
+		if self.shutting_down.load(SeqCst) {
+			let fmt = "thread pool is shutting down";
+			return Err(err!(ErrKind::ShuttingDown, fmt));
+		}
 		if self.tx.is_none() {
 			let fmt = "Thread pool has not been initialized";
 			return Err(err!(ErrKind::IllegalState, fmt));
@@ -252,6 +654,7 @@ where
 			f: Box::pin(f),
 			tx,
 			id,
+			context: current_task_context(),
 		};
 		self.tx.as_ref().unwrap().send(fw)?;
 		Ok(ThreadPoolHandle::new(id, rx))
@@ -267,8 +670,15 @@ where
 		self.tx = Some(tx.clone());
 
 		for _ in 0..self.config.min_size {
-			Self::run_thread(rx.clone(), self.state.clone(), self.on_panic.clone())?;
+			let on_panic_info = self.on_panic_info.clone();
+			Self::run_thread(
+				rx.clone(),
+				self.state.clone(),
+				self.on_panic.clone(),
+				on_panic_info,
+			)?;
 		}
+		Scheduler::run_timer(self.scheduler.clone());
 
 		let mut count = 0;
 		loop {
@@ -288,7 +698,15 @@ where
 		let mut state = self.state.wlock()?;
 		(**state.guard()?).stop = true;
 		self.tx = None;
-		Ok(())
+		self.scheduler.stop()
+	}
+
+	fn shutdown(&mut self, mode: ThreadPoolShutdownMode) -> Result<(), Error> {
+		self.shutdown_impl(mode, None)
+	}
+
+	fn shutdown_timeout(&mut self, mode: ThreadPoolShutdownMode, millis: u64) -> Result<(), Error> {
+		self.shutdown_impl(mode, Some(Duration::from_millis(millis)))
 	}
 
 	fn size(&self) -> Result<usize, Error> {
@@ -296,15 +714,40 @@ where
 		Ok((**state.guard()?).cur_size)
 	}
 
+	fn schedule<F>(&self, delay_millis: u64, task: F) -> Result<ScheduleHandle, Error>
+	where
+		F: FnMut() -> Result<T, Error> + Send + 'static,
+	{
+		self.schedule_task(Duration::from_millis(delay_millis), None, task)
+	}
+
+	fn schedule_recurring<F>(&self, interval_millis: u64, task: F) -> Result<ScheduleHandle, Error>
+	where
+		F: FnMut() -> Result<T, Error> + Send + 'static,
+	{
+		if interval_millis == 0 {
+			let fmt = "interval_millis must be greater than 0";
+			return Err(err!(ErrKind::IllegalArgument, fmt));
+		}
+		let interval = Duration::from_millis(interval_millis);
+		self.schedule_task(interval, Some(interval), task)
+	}
+
+	fn schedule_stats(&self) -> Result<ScheduleStats, Error> {
+		self.scheduler.stats()
+	}
+
 	fn stopper(&self) -> Result<ThreadPoolStopper, Error> {
 		Ok(ThreadPoolStopper {
 			state: self.state.clone(),
+			scheduler: self.scheduler.clone(),
 		})
 	}
 
 	fn executor(&self) -> Result<ThreadPoolExecutor<T>, Error> {
 		Ok(ThreadPoolExecutor {
 			tx: self.tx.clone(),
+			shutting_down: self.shutting_down.clone(),
 		})
 	}
 
@@ -313,6 +756,11 @@ where
 		Ok(())
 	}
 
+	fn set_on_panic_info(&mut self, on_panic_info: OnPanicInfo) -> Result<(), Error> {
+		self.on_panic_info = Some(UtilBuilder::build_lock_box(on_panic_info)?);
+		Ok(())
+	}
+
 	#[cfg(test)]
 	fn set_on_panic_none(&mut self) -> Result<(), Error> {
 		self.on_panic = None;
@@ -328,6 +776,10 @@ where
 	where
 		F: Future<Output = Result<T, Error>> + Send + 'static,
 	{
+		if self.shutting_down.load(SeqCst) {
+			let fmt = "thread pool is shutting down";
+			return Err(err!(ErrKind::ShuttingDown, fmt));
+		}
 		if self.tx.is_none() {
 			let fmt = "Thread pool has not been initialized";
 			return Err(err!(ErrKind::IllegalState, fmt));
@@ -338,6 +790,7 @@ where
 			f: Box::pin(f),
 			tx,
 			id,
+			context: current_task_context(),
 		};
 		self.tx.as_ref().unwrap().send(fw)?;
 		Ok(rx)
@@ -351,9 +804,9 @@ impl ThreadPoolStopper {
 	/// The main purpose of this function is so that the state can be stored
 	/// in a struct, but caller must ensure that the threads stop.
 	/// This is not the case with [`crate::ThreadPool::stop`] and that function
-	/// should be used where possible.
+	/// should be used where possible. Any outstanding scheduled tasks are canceled.
 	pub fn stop(&mut self) -> Result<(), Error> {
 		(**self.state.wlock()?.guard()?).stop = true;
-		Ok(())
+		self.scheduler.stop()
 	}
 }
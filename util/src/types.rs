@@ -16,17 +16,22 @@
 // limitations under the License.
 
 use crate::constants::*;
+use bmw_deps::bitvec::order::Lsb0;
+use bmw_deps::bitvec::vec::BitVec;
 use bmw_deps::dyn_clone::{clone_trait_object, DynClone};
 use bmw_derive::Serializable;
 use bmw_err::*;
 use bmw_ser::Serializable;
 use std::any::Any;
+use std::collections::BinaryHeap;
 use std::fmt::Debug;
 use std::future::Future;
 use std::marker::PhantomData;
 use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::{Receiver, SyncSender};
-use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
 
 /// Arrays for use with other functions in this library. An array can be contructed with the macro
 /// [`crate::array!`].
@@ -34,6 +39,16 @@ pub struct Array<T> {
 	pub(crate) data: Vec<T>,
 }
 
+/// A fixed size two dimensional grid. The cells are stored in row major order in a single
+/// [`crate::Array`], so there are no per-row allocations. All accessors check the bounds and
+/// return an error instead of panicking. An Array2D can be constructed with the macro
+/// [`crate::array2d!`].
+pub struct Array2D<T> {
+	pub(crate) data: Array<T>,
+	pub(crate) rows: usize,
+	pub(crate) cols: usize,
+}
+
 /// ArrayList data structure. Arraylist implements List and Sortable list. A [`crate::ArrayList`]
 /// can be constructed with the macro [`crate::array_list`].
 #[derive(Clone)]
@@ -51,6 +66,15 @@ pub struct ArrayListIterator<'a, T> {
 	pub(crate) c: usize,
 }
 
+/// An iterator over the subslices of a byte slice that are separated by a delimiter. See
+/// [`crate::split_on`].
+pub struct SplitIter<'a> {
+	pub(crate) remaining: &'a [u8],
+	pub(crate) delim: &'a [u8],
+	pub(crate) skip: [usize; 256],
+	pub(crate) done: bool,
+}
+
 /// An iterator for the [`crate::Array`]. See [`crate::array`] for examples.
 pub struct ArrayIterator<'a, T> {
 	pub(crate) array_ref: &'a Array<T>,
@@ -86,6 +110,7 @@ where
 	pub(crate) linked_list_ref: &'a HashImpl<V>,
 	pub(crate) cur: usize,
 	pub(crate) direction: Direction,
+	pub(crate) remaining: usize,
 	pub(crate) _phantom_data: PhantomData<V>,
 	pub(crate) slab_reader: SlabReader,
 }
@@ -154,6 +179,41 @@ where
 	fn clear(&mut self) -> Result<(), Error>;
 	/// Returns an [`std::iter::Iterator`] to iterate through this hashtable.
 	fn iter<'a>(&'a self) -> HashtableIterator<'a, K, V>;
+	/// Returns an [`std::iter::Iterator`] over the keys of this hashtable in the same order as
+	/// [`crate::Hashtable::iter`]. The values are not deserialized.
+	fn keys<'a>(&'a self) -> HashsetIterator<'a, K>;
+	/// Returns an [`std::iter::Iterator`] over the values of this hashtable in the same order
+	/// as [`crate::Hashtable::iter`]. Each key is still read to find its value since keys are
+	/// stored without a length.
+	fn values<'a>(&'a self) -> HashtableValueIterator<'a, K, V>;
+	/// Returns an [`std::iter::Iterator`] over a snapshot of this hashtable that does not
+	/// borrow it. This is intended for long iterations (i.e. dumping stats) over a hashtable
+	/// that is shared through a [`crate::LockBox`]. Only the first slab id of each entry and
+	/// its generation (see [`crate::SlabAllocator::generation`]) are copied while the lock is
+	/// held, which takes 16 bytes per entry. Values are not copied. The lock may then be
+	/// released and other threads may modify the hashtable while the snapshot is iterated.
+	/// Each entry is read from the slabs when it is reached. The iteration is weakly
+	/// consistent:
+	/// * Entries that are present for the whole iteration are returned exactly once.
+	/// * Entries that are removed (or replaced by inserting the same key) after the snapshot
+	///   are skipped if they are removed before they are reached.
+	/// * Entries that are inserted after the snapshot are not returned.
+	/// * Entries whose first slab is moved by [`crate::Hashtable::compact`] are skipped.
+	///
+	/// The order is the order of the slots in the entry array, not the order of
+	/// [`crate::Hashtable::iter`]. The hashtable must have its own slab allocator
+	/// (SlabSize/SlabCount specified), as is the case for hashtables built with
+	/// [`crate::hashtable_sync`]. Otherwise an error of kind
+	/// [`bmw_err::ErrKind::IllegalState`] is returned.
+	fn snapshot_iter(&self) -> Result<HashtableSnapshotIterator<K, V>, Error>;
+	/// Returns an [`std::iter::Iterator`] over the entries of this hashtable sorted by key.
+	/// Unlike [`crate::Hashtable::iter`], the order only depends on the keys that are in the
+	/// hashtable and not on the order in which they were inserted, which makes it suitable for
+	/// a canonical encoding of the contents. All entries are read before the first one is
+	/// returned.
+	fn iter_ordered_by_key(&self) -> std::vec::IntoIter<(K, V)>
+	where
+		K: Ord;
 	/// Bring the entry to the front of the list for deletion purposes in a cache.
 	fn bring_to_front(&mut self, key: &K) -> Result<(), Error>;
 	/// Remove the oldest entry in the hashtable.
@@ -174,6 +234,13 @@ where
 	fn slabs(
 		&self,
 	) -> Result<Option<Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>>, Error>;
+	/// Move up to `max_moves` of the slabs used by this hashtable toward the low end of its
+	/// slab allocator and update the stored slab ids accordingly. See
+	/// [`crate::SlabAllocator::compact`]. Returns the number of slabs moved so that callers
+	/// may amortize compaction (i.e. in a housekeeping task) by calling this function until it
+	/// returns 0. The hashtable must have its own slab allocator (SlabSize/SlabCount
+	/// specified). Otherwise an error of kind [`bmw_err::ErrKind::IllegalState`] is returned.
+	fn compact(&mut self, max_moves: usize) -> Result<usize, Error>;
 }
 
 /// The hashset trait. See [`crate::hashset`] for working examples.
@@ -198,6 +265,16 @@ where
 	fn clear(&mut self) -> Result<(), Error>;
 	/// Returns an [`std::iter::Iterator`] to iterate through this hashset.
 	fn iter<'a>(&'a self) -> HashsetIterator<'a, K>;
+	/// Returns an [`std::iter::Iterator`] over the keys of this hashset. This is the same as
+	/// [`crate::Hashset::iter`].
+	fn keys<'a>(&'a self) -> HashsetIterator<'a, K>;
+	/// Recompute the bloom filter (see BloomBits in [`crate::hashset`]) from the keys that are
+	/// currently in this hashset. The filter's counters are decremented when keys are removed
+	/// so this is normally not needed, but a counter that was incremented by 255 or more keys
+	/// is never decremented again. Such counters only cause false positives (a lookup that
+	/// must check the slabs), never false negatives. Rebuilding resets them. Returns an error
+	/// of kind [`bmw_err::ErrKind::IllegalState`] if this hashset has no bloom filter.
+	fn rebuild_bloom(&mut self) -> Result<(), Error>;
 	fn slabs(
 		&self,
 	) -> Result<Option<Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>>, Error>;
@@ -210,20 +287,65 @@ where
 {
 	pub(crate) hashtable: &'a HashImpl<K>,
 	pub(crate) cur: usize,
+	pub(crate) remaining: usize,
 	pub(crate) _phantom_data: PhantomData<(K, V)>,
 }
 
-/// An iterator for the [`crate::Hashset`].
+/// An iterator over the values of a [`crate::Hashtable`]. See [`crate::Hashtable::values`].
+pub struct HashtableValueIterator<'a, K, V>
+where
+	K: Serializable + Clone,
+{
+	pub(crate) hashtable: &'a HashImpl<K>,
+	pub(crate) cur: usize,
+	pub(crate) remaining: usize,
+	pub(crate) _phantom_data: PhantomData<V>,
+	pub(crate) slab_reader: SlabReader,
+}
+
+/// An iterator for the [`crate::Hashset`] and the keys of a [`crate::Hashtable`] (see
+/// [`crate::Hashtable::keys`]).
 pub struct HashsetIterator<'a, K>
 where
 	K: Serializable + Clone,
 {
 	pub(crate) hashset: &'a HashImpl<K>,
 	pub(crate) cur: usize,
+	pub(crate) remaining: usize,
 	pub(crate) _phantom_data: PhantomData<K>,
 	pub(crate) slab_reader: SlabReader,
 }
 
+/// A weakly consistent iterator over a snapshot of a [`crate::Hashtable`]. See
+/// [`crate::Hashtable::snapshot_iter`]. The iterator does not borrow the hashtable, so it may be
+/// used after the lock that protects the hashtable has been released.
+pub struct HashtableSnapshotIterator<K, V> {
+	pub(crate) slabs: Box<dyn LockBox<Box<dyn SlabAllocator + Send + Sync>>>,
+	// the first slab id and its generation for each entry at the time of the snapshot
+	pub(crate) entries: Vec<(usize, u64)>,
+	pub(crate) cur: usize,
+	// the data of the entry being read. Reused for each entry.
+	pub(crate) buffer: Vec<u8>,
+	pub(crate) bytes_per_slab: usize,
+	pub(crate) slab_size: usize,
+	pub(crate) ptr_size: usize,
+	pub(crate) max_value: usize,
+	pub(crate) _phantom_data: PhantomData<(K, V)>,
+}
+
+/// A weakly consistent iterator over the shards of a [`crate::ShardedHashtable`]. See
+/// [`crate::ShardedHashtable::snapshot_iter`].
+pub struct ShardedHashtableSnapshotIterator<K, V>
+where
+	K: Serializable + Clone + 'static,
+	V: Serializable + 'static,
+{
+	pub(crate) shards: Vec<Box<dyn LockBox<Box<dyn Hashtable<K, V> + Send + Sync>>>>,
+	// the index of the next shard to take a snapshot of
+	pub(crate) next_shard: usize,
+	pub(crate) cur: Option<HashtableSnapshotIterator<K, V>>,
+}
+
 /// Internal struct used to build slab allocators. See [`crate::slab_allocator`].
 #[derive(Debug, Clone, Serializable)]
 pub struct SlabAllocatorConfig {
@@ -231,6 +353,34 @@ pub struct SlabAllocatorConfig {
 	pub slab_size: usize,
 	/// The number of slabs that this slab allocator can allocate
 	pub slab_count: usize,
+	/// If true, slabs are allocated from the heap once all `slab_count` slabs are in use. Up
+	/// to `slab_count` additional slabs may be allocated this way. See
+	/// [`crate::SlabAllocator::shrink_overflow`].
+	pub overflow_to_heap: bool,
+}
+
+/// Statistics about a [`crate::SlabAllocator`]. See [`crate::SlabAllocator::stats`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SlabStats {
+	/// The number of slabs in the fixed arena.
+	pub slab_count: usize,
+	/// The number of free slabs in the fixed arena.
+	pub free_count: usize,
+	/// The number of heap allocated overflow slabs held, whether in use or cached.
+	pub heap_overflow_slabs: usize,
+	/// The number of heap allocated overflow slabs that are in use.
+	pub heap_overflow_in_use: usize,
+}
+
+/// A run of adjacent slabs returned by [`crate::SlabAllocator::allocate_contiguous`]. The slabs
+/// can be written and read as one region of `count * slab_size` bytes using
+/// [`crate::SlabWriter::from_range`] and [`crate::SlabReader::from_range`], so no next pointers
+/// are stored and no pointers have to be followed when reading. The range is freed with
+/// [`crate::SlabAllocator::free_range`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabRange {
+	pub(crate) start: usize,
+	pub(crate) count: usize,
 }
 
 /// Struct that is used as a mutable reference to data in a slab. See [`crate::SlabAllocator`] for
@@ -256,6 +406,15 @@ pub trait SlabAllocator: DynClone + Debug {
 	fn allocate<'a>(&'a mut self) -> Result<SlabMut<'a>, Error>;
 	/// Free a slab. Return an error if the slab cannot be freed.
 	fn free(&mut self, id: usize) -> Result<(), Error>;
+	/// Allocate `n_slabs` adjacent slabs from the fixed arena. The lowest run of free slabs
+	/// that is long enough is used (first fit). If enough slabs are free but no run of
+	/// `n_slabs` adjacent slabs exists, [`bmw_err::ErrKind::Fragmentation`] is returned so that
+	/// the caller can fall back to allocating a chain of slabs or call
+	/// [`crate::SlabAllocator::compact`]. If fewer than `n_slabs` slabs are free,
+	/// [`bmw_err::ErrKind::CapacityExceeded`] is returned. Heap overflow slabs are never used.
+	fn allocate_contiguous(&mut self, n_slabs: usize) -> Result<SlabRange, Error>;
+	/// Free all slabs in a range returned by [`crate::SlabAllocator::allocate_contiguous`].
+	fn free_range(&mut self, range: SlabRange) -> Result<(), Error>;
 	/// Get an immutible reference to a slab.
 	fn get<'a>(&'a self, id: usize) -> Result<Slab<'a>, Error>;
 	/// Get a mutable reference to a slab.
@@ -269,6 +428,38 @@ pub trait SlabAllocator: DynClone + Debug {
 	/// Initializes the [`crate::SlabAllocator`] with the given `config`. See
 	/// [`crate::SlabAllocatorConfig`] for further details.
 	fn init(&mut self, config: SlabAllocatorConfig) -> Result<(), Error>;
+	/// Move allocated slabs toward the low end of the allocator. The highest allocated slab is
+	/// copied into the lowest free slab until no free slab is below an allocated one or
+	/// `max_moves` slabs have been moved, so compaction may be done incrementally. For each
+	/// slab moved, `relocate` is called with the old id and the new id so that the owner of the
+	/// slab can update any stored slab ids. Returns the number of slabs that were moved. Note
+	/// that all owners of slabs in this [`crate::SlabAllocator`] must be able to handle the
+	/// relocation, so this should not be called on a shared slab allocator.
+	fn compact(
+		&mut self,
+		max_moves: usize,
+		relocate: &mut dyn FnMut(usize, usize) -> Result<(), Error>,
+	) -> Result<usize, Error>;
+	/// Returns one more than the id of the highest allocated slab or 0 if no slabs are
+	/// allocated. Heap overflow slabs are not included.
+	fn high_water_mark(&self) -> Result<usize, Error>;
+	/// Returns one more than the highest slab id that this [`crate::SlabAllocator`] may
+	/// return. This is `slab_count` unless `overflow_to_heap` is set, in which case ids from
+	/// `slab_count` to `2 * slab_count - 1` refer to heap allocated overflow slabs. Data
+	/// structures that store slab ids must size them to hold this value.
+	fn id_limit(&self) -> Result<usize, Error>;
+	/// Returns the [`crate::SlabStats`] for this [`crate::SlabAllocator`].
+	fn stats(&self) -> Result<SlabStats, Error>;
+	/// Release the heap allocated overflow slabs that have been freed. Freed overflow slabs
+	/// are not returned to the fixed arena. They are kept for the next burst until this
+	/// function is called. Returns the number of slabs that were released.
+	fn shrink_overflow(&mut self) -> Result<usize, Error>;
+	/// Returns the generation of the slab with the specified `id`. The generation is
+	/// incremented each time the slab is freed (or moved by
+	/// [`crate::SlabAllocator::compact`]), so a slab id along with its generation identifies a
+	/// single allocation. This allows a reader that recorded a slab id earlier to detect that
+	/// the slab has since been freed and possibly reused.
+	fn generation(&self, id: usize) -> Result<u64, Error>;
 }
 
 /// A lock which can be used to pass data to and from threads. See [`crate::lock!`].
@@ -328,6 +519,34 @@ pub struct RwLockWriteGuardWrapper<'a, T> {
 	pub(crate) debug_err: bool,
 }
 
+/// How the lock ordering tracker handles the [`crate::LockBox`] acquisitions of this process.
+/// The tracker records an edge from each lock that a thread holds to each lock that it acquires
+/// while holding it. A cycle in these edges means that two threads may deadlock if they run at
+/// the same time, even if they did not on this run. The tracker only exists in builds with
+/// `debug_assertions`. Its initial mode is read from the `BMW_LOCK_ORDER` environment variable:
+/// "strict" selects [`crate::LockOrderMode::Strict`], any other value
+/// [`crate::LockOrderMode::Log`] and if it is not set, the tracker is off. See
+/// [`crate::set_lock_order_mode`] and [`crate::lock_order_report`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockOrderMode {
+	/// No acquisitions are recorded.
+	Off,
+	/// Acquisitions are recorded and cycles are logged as warnings.
+	Log,
+	/// Acquisitions are recorded and the thread that completes a cycle panics.
+	Strict,
+}
+
+/// The lock ordering observed by the tracker. See [`crate::lock_order_report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockOrderReport {
+	/// The (held, acquired) pairs of [`crate::LockBox::id`] values, in ascending order.
+	pub edges: Vec<(u128, u128)>,
+	/// The cycles that were detected. Each cycle starts and ends with the same id and each
+	/// consecutive pair of ids is an edge.
+	pub cycles: Vec<Vec<u128>>,
+}
+
 /// Utility to write to slabs using the [`bmw_ser::Writer`] trait.
 #[derive(Clone)]
 pub struct SlabWriter {
@@ -336,6 +555,7 @@ pub struct SlabWriter {
 	pub(crate) offset: usize,
 	pub(crate) slab_size: usize,
 	pub(crate) bytes_per_slab: usize,
+	pub(crate) range: Option<SlabRange>,
 }
 
 /// Utility to read from slabs using the [`bmw_ser::Reader`] trait.
@@ -347,6 +567,7 @@ pub struct SlabReader {
 	pub(crate) slab_size: usize,
 	pub(crate) bytes_per_slab: usize,
 	pub(crate) max_value: usize,
+	pub(crate) range: Option<SlabRange>,
 }
 
 /// The result returned by a call to [`crate::ThreadPoolHandle::block_on`]. It stores the result of
@@ -358,6 +579,17 @@ pub enum PoolResult<T, E> {
 	Panic,
 }
 
+/// How a [`crate::ThreadPool`] handles the tasks that are still queued when it is shut down.
+/// See [`crate::ThreadPool::shutdown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serializable)]
+pub enum ThreadPoolShutdownMode {
+	/// Execute all queued tasks before the worker threads exit.
+	Drain,
+	/// Only finish the tasks that are already running. The queued tasks are dropped and their
+	/// [`crate::PoolResult`] is an [`bmw_err::ErrKind::Cancelled`] error.
+	DiscardPending,
+}
+
 /// A thread pool. See [`crate::thread_pool`] for working examples.
 pub trait ThreadPool<T, OnPanic>
 where
@@ -371,6 +603,7 @@ where
 	/// Execute a task in the thread pool. This task will run to completion
 	/// on the first available thread in the pool. The return value [`crate::ThreadPoolHandle`]
 	/// which can be used to get the id of the task sent to the thread pool or to block on.
+	/// The [`crate::TaskContext`] of the calling thread is installed while the task runs.
 	fn execute<F>(&self, f: F, id: u128) -> Result<ThreadPoolHandle<T>, Error>
 	where
 		F: Future<Output = Result<T, Error>> + Send + 'static;
@@ -384,10 +617,42 @@ where
 	/// immediately. That is the responsibility of the user.
 	fn stop(&mut self) -> Result<(), Error>;
 
+	/// Shut down the thread pool and wait for all of its worker threads to exit. Once this is
+	/// called, [`crate::ThreadPool::execute`] and [`crate::ThreadPoolExecutor::execute`] return
+	/// an [`bmw_err::ErrKind::ShuttingDown`] error and any outstanding scheduled tasks are
+	/// canceled. The tasks that are still queued are handled according to `mode`. See
+	/// [`crate::ThreadPoolShutdownMode`].
+	fn shutdown(&mut self, mode: ThreadPoolShutdownMode) -> Result<(), Error>;
+
+	/// Same as [`crate::ThreadPool::shutdown`], but only wait up to `millis` milliseconds for
+	/// the worker threads to exit. Worker threads that are still running a task at the deadline
+	/// are detached and exit once they are done. The thread pool is also shut down this way
+	/// with [`crate::ThreadPoolShutdownMode::Drain`] and a short timeout when it is dropped.
+	fn shutdown_timeout(&mut self, mode: ThreadPoolShutdownMode, millis: u64) -> Result<(), Error>;
+
 	/// Returns the current size of the thread pool which will be between
 	/// the configured maximum and minimum size.
 	fn size(&self) -> Result<usize, Error>;
 
+	/// Schedule a task to be executed once, `delay_millis` milliseconds from now. When the
+	/// delay expires, the pool's timer thread dispatches the task onto the normal worker queue
+	/// so a long running task never blocks the timer. The returned [`crate::ScheduleHandle`]
+	/// may be used to cancel the task before it runs.
+	fn schedule<F>(&self, delay_millis: u64, task: F) -> Result<ScheduleHandle, Error>
+	where
+		F: FnMut() -> Result<T, Error> + Send + 'static;
+
+	/// Schedule a task to be executed every `interval_millis` milliseconds until the returned
+	/// [`crate::ScheduleHandle`] is canceled or the thread pool is stopped. If the previous
+	/// execution is still running when the next tick arrives, the tick is skipped and counted
+	/// in [`crate::ScheduleStats::skipped`]. `interval_millis` must be greater than 0.
+	fn schedule_recurring<F>(&self, interval_millis: u64, task: F) -> Result<ScheduleHandle, Error>
+	where
+		F: FnMut() -> Result<T, Error> + Send + 'static;
+
+	/// Returns the [`crate::ScheduleStats`] for the scheduled tasks of this thread pool.
+	fn schedule_stats(&self) -> Result<ScheduleStats, Error>;
+
 	/// Get the [`crate::ThreadPoolStopper`] for this thread pool.
 	fn stopper(&self) -> Result<ThreadPoolStopper, Error>;
 
@@ -399,10 +664,58 @@ where
 	/// Set an on panic handler for this thread pool
 	fn set_on_panic(&mut self, on_panic: OnPanic) -> Result<(), Error>;
 
+	/// Set a handler that is passed a [`crate::PanicInfoCapture`] with the message, thread
+	/// name and location of a panic in a task along with the id of the task. It is executed
+	/// before the handler set with [`crate::ThreadPool::set_on_panic`] if both are set. This
+	/// must be called before [`crate::ThreadPool::start`].
+	fn set_on_panic_info(&mut self, on_panic_info: OnPanicInfo) -> Result<(), Error>;
+
 	#[cfg(test)]
 	fn set_on_panic_none(&mut self) -> Result<(), Error>;
 }
 
+/// Context that follows a task onto the [`crate::ThreadPool`] worker that runs it. When a task
+/// is submitted with [`crate::ThreadPool::execute`], [`crate::ThreadPoolExecutor::execute`],
+/// [`crate::ThreadPool::schedule`] or [`crate::ThreadPool::schedule_recurring`], the context of
+/// the submitting thread is captured. The worker installs it while the task runs and restores
+/// its own context afterward, so nested submissions propagate it as well. The context of a
+/// thread is set with [`crate::with_task_context`] and read with
+/// [`crate::current_task_context`].
+///
+/// # Examples
+///
+///```
+/// use bmw_err::*;
+/// use bmw_util::*;
+///
+/// fn main() -> Result<(), Error> {
+///     let mut tp = thread_pool!()?;
+///     tp.set_on_panic(move |_id, _e| -> Result<(), Error> { Ok(()) })?;
+///     tp.start()?;
+///
+///     let ctx = TaskContext {
+///         correlation_id: Some(42),
+///         ..Default::default()
+///     };
+///     let handle = with_task_context(ctx, || {
+///         execute!(tp, { Ok(current_task_context().and_then(|ctx| ctx.correlation_id)) })
+///     })?;
+///     assert_eq!(block_on!(handle), PoolResult::Ok(Some(42)));
+///
+///     Ok(())
+/// }
+///```
+#[derive(Clone, Default)]
+pub struct TaskContext {
+	/// An id that ties the work done by the task (i.e. log lines) to the request that caused it.
+	pub correlation_id: Option<u64>,
+	/// The time, in milliseconds since the unix epoch, by which the task should complete.
+	pub deadline_millis: Option<u64>,
+	/// Any other value. It is shared rather than moved because the submitting thread keeps its
+	/// context.
+	pub data: Option<Arc<dyn Any + Send + Sync>>,
+}
+
 /// This handle is returned by [`crate::ThreadPool::execute`]. It can be used to retrieve the task
 /// id or to block on the task.
 #[derive(Debug)]
@@ -419,6 +732,7 @@ where
 	T: 'static + Send + Sync,
 {
 	pub(crate) tx: Option<SyncSender<FutureWrapper<T>>>,
+	pub(crate) shutting_down: Arc<AtomicBool>,
 }
 
 /// Struct that can be used to stop the thread pool. Note the limitations
@@ -426,9 +740,52 @@ where
 #[derive(Debug, Clone)]
 pub struct ThreadPoolStopper {
 	pub(crate) state: Box<dyn LockBox<ThreadPoolState>>,
+	pub(crate) scheduler: Arc<Scheduler>,
 }
 
-/// A pattern to match with the search trie
+/// This handle is returned by [`crate::ThreadPool::schedule`] and
+/// [`crate::ThreadPool::schedule_recurring`]. It can be used to cancel the scheduled task.
+#[derive(Debug, Clone)]
+pub struct ScheduleHandle {
+	pub(crate) id: u128,
+	pub(crate) cancelled: Arc<AtomicBool>,
+}
+
+/// Statistics about the scheduled tasks of a thread pool. See
+/// [`crate::ThreadPool::schedule_stats`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScheduleStats {
+	/// The number of scheduled task executions dispatched onto the worker queue.
+	pub dispatched: u64,
+	/// The number of recurring ticks skipped because the previous execution was still running.
+	pub skipped: u64,
+	/// The number of scheduled tasks currently waiting in the timer queue.
+	pub pending: usize,
+}
+
+/// A pattern to match with the search trie. The regex is a restricted syntax which is compiled
+/// directly into the search trie rather than a full regular expression. The following is
+/// supported:
+///
+/// * Literal bytes - any byte not listed below matches itself. This includes characters such as
+///   '+', '?', '(', ')', '|', '{' and '}' which have no special meaning here.
+/// * '.' - matches any single byte.
+/// * '[...]' - a character class which matches any single byte in the class. A class consists
+///   of bytes and ranges such as `[a-z0-9_]`. A '-' which is the first or last byte of the class
+///   is a literal. Within a class, `\\`, `\]`, `\[`, `\-` and `\^` match the escaped byte.
+/// * '*' - repeats the preceding byte, '.' or class. Note that the atom must occur at least once
+///   and that repetition is lazy: it ends at the first byte which can continue the pattern. The
+///   number of repetitions is limited by MaxWildCardLength.
+/// * '^' - as the first byte of the regex, the pattern only matches at the start of the text.
+/// * '$' - as the last byte of the regex, the pattern only matches at the end of the text. With
+///   [`crate::StreamingMatcher`], this is the end of the stream.
+/// * `\\`, `\.`, `\*`, `\[`, `\]`, `\^` and `\$` - match the escaped byte literally.
+///
+/// Negated classes (`[^...]`), a '*' which does not follow an atom, a '^' or '$' anywhere other
+/// than the start or end of the regex, an unmatched ']', an unterminated or empty class, a
+/// reversed range and unknown escapes are not supported. They are rejected when the pattern is
+/// built with a [`bmw_err::ErrKind::IllegalArgument`] error which includes the offending position.
+/// In case insensitive patterns, the regex is lower-cased before it is compiled.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Pattern {
 	pub(crate) regex: String,
@@ -456,6 +813,119 @@ pub trait SearchTrie: DynClone {
 	/// array supplied by the caller. The result is the number of
 	/// matches found or a [`bmw_err::Error`] if an error occurs.
 	fn tmatch(&mut self, text: &[u8], matches: &mut [Match]) -> Result<usize, Error>;
+
+	/// return a [`crate::StreamingMatcher`] which matches this [`crate::SearchTrie`] against
+	/// input that is supplied in chunks, such as data which is too large to fit in memory.
+	/// The matcher only retains as much of the input as the longest possible match. Because
+	/// of this, a [`bmw_err::ErrKind::Configuration`] error is returned if a pattern contains
+	/// a '.*' wildcard and MaxWildCardLength was not configured.
+	fn streaming_matcher(&mut self) -> Result<StreamingMatcher<'_>, Error>;
+}
+
+/// A matcher which is returned by [`crate::SearchTrie::streaming_matcher`]. Input is supplied
+/// with [`crate::StreamingMatcher::feed`] and matches spanning chunk boundaries are found.
+/// The termination patterns and TerminationLength are handled as in
+/// [`crate::SearchTrie::tmatch`], with positions relative to the start of the stream.
+pub struct StreamingMatcher<'a> {
+	pub(crate) trie: &'a mut SearchTrieImpl,
+	pub(crate) window: Vec<u8>,
+	pub(crate) offset: usize,
+	pub(crate) next_insensitive: usize,
+	pub(crate) next_sensitive: usize,
+	pub(crate) term_pos: usize,
+	pub(crate) is_insensitive_done: bool,
+	pub(crate) is_sensitive_done: bool,
+	pub(crate) is_stopped: bool,
+}
+
+/// A [`crate::Hashtable`] that is split into shards so that it can be shared by several
+/// threads without serializing all access through a single lock. Each shard is a synchronous
+/// [`crate::Hashtable`] behind its own [`crate::LockBox`] and each key is routed to a shard by
+/// its hash. Clones share the same shards, so a clone may be passed to each thread. See
+/// [`crate::UtilBuilder::build_sharded_hashtable`] and [`crate::sharded_hashtable`].
+#[derive(Clone)]
+pub struct ShardedHashtable<K, V>
+where
+	K: Serializable + Clone + 'static,
+	V: Serializable + 'static,
+{
+	pub(crate) shards: Vec<Box<dyn LockBox<Box<dyn Hashtable<K, V> + Send + Sync>>>>,
+}
+
+/// A fixed length set of bits backed by a bitvec. This is useful for compact flags (i.e.
+/// peer capabilities) and for persisting bit sets. BitFields are [`bmw_ser::Serializable`] as
+/// the length in bits (u32) followed by the packed bytes, least significant bit first. See
+/// [`crate::UtilBuilder::build_bitfield`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BitField {
+	pub(crate) bits: BitVec<u8, Lsb0>,
+}
+
+/// A fixed capacity binary min-heap (priority queue) built on preallocated [`crate::Array`]s.
+/// Push, pop, remove and update_key are O(log n) and peek is O(1). When the heap is full, push
+/// returns an error instead of growing, so no heap allocation is done after construction.
+/// Values pushed with [`crate::MinHeap::push_with_handle`] can be canceled with
+/// [`crate::MinHeap::remove`] or rescheduled with [`crate::MinHeap::update_key`]. See
+/// [`crate::UtilBuilder::build_min_heap`] and [`crate::min_heap!`].
+pub struct MinHeap<V> {
+	// the values in heap order
+	pub(crate) values: Array<Option<V>>,
+	// heap index -> slot
+	pub(crate) slots: Array<usize>,
+	// slot -> heap index (HEAP_NONE if the slot is free)
+	pub(crate) positions: Array<usize>,
+	pub(crate) generations: Array<u64>,
+	pub(crate) free: Array<usize>,
+	pub(crate) free_len: usize,
+	pub(crate) len: usize,
+}
+
+/// A handle to a value in a [`crate::MinHeap`]. Handles are returned by
+/// [`crate::MinHeap::push_with_handle`]. Once the value is popped or removed, the handle is no
+/// longer valid, even if its slot is reused by a later push.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HeapHandle {
+	pub(crate) slot: usize,
+	pub(crate) generation: u64,
+}
+
+/// The details of a panic that was caught by a [`crate::ThreadPool`] (or by another component
+/// such as the EventHandler in bmw_evh). The message is taken from the panic payload if it is a
+/// `&str` or a [`std::string::String`]. The thread name and location are recorded by a panic
+/// hook for threads that called [`crate::PanicInfoCapture::enable`]. The capture implements
+/// [`std::fmt::Display`] and [`bmw_ser::Serializable`] so that it can be logged or sent to
+/// another process. See [`crate::ThreadPool::set_on_panic_info`].
+#[derive(Debug, Clone, PartialEq, Serializable)]
+pub struct PanicInfoCapture {
+	pub(crate) message: String,
+	pub(crate) thread_name: Option<String>,
+	pub(crate) file: Option<String>,
+	pub(crate) line: Option<u32>,
+}
+
+/// A small, stable id for a string interned in a [`crate::StringInterner`]. Symbols are
+/// [`bmw_ser::Serializable`] so structs that contain them can be serialized, but a symbol can
+/// only be resolved by the interner that issued it (or one read back from that interner's
+/// serialized contents). See [`crate::StringInterner::intern`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(pub(crate) u32);
+
+/// A string interner which stores each distinct string once in its own slab allocator and
+/// hands out a [`crate::Symbol`] for it. This is useful when the same strings (i.e. peer
+/// addresses or header names) would otherwise be stored many times. The maximum number of
+/// symbols and the byte capacity are fixed when the interner is built and no heap allocation
+/// is done when interning after that. Strings can't be removed. See
+/// [`crate::UtilBuilder::build_string_interner`] and [`crate::string_interner`].
+pub struct StringInterner {
+	pub(crate) slabs: Box<dyn SlabAllocator + Send + Sync>,
+	pub(crate) index: Box<dyn Hashtable<u64, u32> + Send + Sync>,
+	pub(crate) entries: Array<InternEntry>,
+	pub(crate) len: usize,
+	pub(crate) bytes_used: usize,
+	pub(crate) slabs_allocated: usize,
+	pub(crate) max_symbols: usize,
+	pub(crate) slab_size: usize,
+	pub(crate) slab_count: usize,
 }
 
 clone_trait_object!(SlabAllocator);
@@ -484,6 +954,15 @@ pub(crate) enum Direction {
 	Backward,
 }
 
+// the location that stores a slab id within a hash based data structure
+#[derive(Clone, Copy)]
+pub(crate) enum SlabRef {
+	// the slab is the first slab of the entry at this index of the entry array
+	Entry(usize),
+	// the slab follows this slab in a chain
+	Next(usize),
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct SlabAllocatorImpl {
 	pub(crate) config: Option<SlabAllocatorConfig>,
@@ -492,12 +971,27 @@ pub(crate) struct SlabAllocatorImpl {
 	pub(crate) free_count: usize,
 	pub(crate) ptr_size: usize,
 	pub(crate) max_value: usize,
+	// heap allocated slabs used once the arena is exhausted if overflow_to_heap is set. The
+	// slab id is slab_count + the index. None means that the heap slab was released.
+	pub(crate) overflow: Vec<Option<HeapSlab>>,
+	// indices of the heap slabs in overflow that are freed but not yet released
+	pub(crate) overflow_free: Vec<usize>,
+	// the generation of each slab id (including heap overflow ids). Incremented on free.
+	pub(crate) generations: Vec<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct HeapSlab {
+	pub(crate) data: Vec<u8>,
+	pub(crate) allocated: bool,
 }
 
 pub(crate) struct FutureWrapper<T> {
 	pub(crate) f: Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'static>>,
 	pub(crate) tx: SyncSender<PoolResult<T, Error>>,
 	pub(crate) id: u128,
+	// the TaskContext of the submitting thread. Installed on the worker while f runs.
+	pub(crate) context: Option<TaskContext>,
 }
 
 pub(crate) struct ThreadPoolImpl<T, OnPanic>
@@ -515,18 +1009,75 @@ where
 	pub(crate) tx: Option<SyncSender<FutureWrapper<T>>>,
 	pub(crate) state: Box<dyn LockBox<ThreadPoolState>>,
 	pub(crate) on_panic: Option<Pin<Box<OnPanic>>>,
+	pub(crate) on_panic_info: Option<Box<dyn LockBox<OnPanicInfo>>>,
+	pub(crate) scheduler: Arc<Scheduler>,
+	pub(crate) shutting_down: Arc<AtomicBool>,
+}
+
+pub(crate) type OnPanicInfo =
+	Box<dyn FnMut(u128, &PanicInfoCapture) -> Result<(), Error> + Send + Sync>;
+
+// an entry in the timer queue. The dispatch closure sends the task to the worker queue and
+// the heap is ordered so that the earliest deadline is on top.
+pub(crate) struct ScheduledTask {
+	pub(crate) deadline: Instant,
+	pub(crate) seq: u64,
+	pub(crate) interval: Option<Duration>,
+	pub(crate) cancelled: Arc<AtomicBool>,
+	pub(crate) running: Arc<AtomicBool>,
+	pub(crate) dispatch: Box<dyn FnMut() -> Result<(), Error> + Send>,
+}
+
+pub(crate) struct SchedulerState {
+	pub(crate) heap: BinaryHeap<ScheduledTask>,
+	pub(crate) seq: u64,
+	pub(crate) stop: bool,
+	pub(crate) stats: ScheduleStats,
+}
+
+pub(crate) struct Scheduler {
+	pub(crate) state: Mutex<SchedulerState>,
+	pub(crate) cond: Condvar,
+}
+
+// clears the running flag of a scheduled task when its execution completes or panics
+pub(crate) struct RunningGuard {
+	pub(crate) running: Arc<AtomicBool>,
+}
+
+// restores the previous TaskContext of the thread when dropped
+pub(crate) struct TaskContextGuard {
+	pub(crate) prev: Option<TaskContext>,
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct Node {
 	pub(crate) next: [u32; 257],
+	pub(crate) classes: Vec<([u64; 4], u32)>,
+	pub(crate) multi_set: [u64; 4],
+	pub(crate) end_next: u32,
 	pub(crate) pattern_id: usize,
 	pub(crate) is_multi: bool,
 	pub(crate) is_term: bool,
 	pub(crate) is_start_only: bool,
+	pub(crate) is_end_only: bool,
 	pub(crate) is_multi_line: bool,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RegexAtom {
+	Byte(u8),
+	Wildcard,
+	Class([u64; 4]),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CompiledRegex {
+	pub(crate) atoms: Vec<(RegexAtom, bool)>,
+	pub(crate) is_start_only: bool,
+	pub(crate) is_end_only: bool,
+}
+
 #[derive(Clone)]
 pub(crate) struct Dictionary {
 	pub(crate) nodes: Vec<Node>,
@@ -539,6 +1090,7 @@ pub(crate) struct SearchTrieImpl {
 	pub(crate) dictionary_case_sensitive: Dictionary,
 	pub(crate) termination_length: usize,
 	pub(crate) max_wildcard_length: usize,
+	pub(crate) max_match_len: usize,
 	pub(crate) branch_stack: Box<dyn Stack<(usize, usize)> + Send + Sync>,
 }
 
@@ -572,6 +1124,24 @@ where
 	pub(crate) _phantom_data: PhantomData<K>,
 	pub(crate) debug_get_next_slot_error: bool,
 	pub(crate) debug_entry_array_len: bool,
+	// a counting bloom filter (one byte per bit) in front of the entry array of a hashset
+	pub(crate) bloom: Option<Array<u8>>,
+	pub(crate) bloom_hashes: usize,
+	// written to the hasher before each key. See DeterministicHashing.
+	pub(crate) hash_seed: u64,
+	// values larger than this are stored in adjacent slabs. See ContiguousThresholdBytes.
+	pub(crate) contiguous_threshold: Option<usize>,
+	#[cfg(test)]
+	pub(crate) debug_key_reads: std::cell::Cell<usize>,
+}
+
+// the location of an interned string in the interner's slabs and the next symbol with the
+// same hash
+#[derive(Clone, Default)]
+pub(crate) struct InternEntry {
+	pub(crate) offset: usize,
+	pub(crate) len: usize,
+	pub(crate) next: u32,
 }
 
 #[derive(Debug, Clone, Serializable)]
@@ -587,4 +1157,5 @@ pub(crate) struct ThreadPoolState {
 	pub(crate) cur_size: usize,
 	pub(crate) config: ThreadPoolConfig,
 	pub(crate) stop: bool,
+	pub(crate) shutdown: Option<ThreadPoolShutdownMode>,
 }